@@ -0,0 +1,163 @@
+use std::str;
+
+///Parsed credentials from an `Authorization` header.
+///
+///This is what `Context::authorization` returns. The `Basic` and `Bearer`
+///schemes are parsed, and anything else is kept as it is, for the handler
+///to interpret. Scheme names are case insensitive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Authorization {
+    ///A user name and a password, from the `Basic` scheme. The password is
+    ///everything after the first `:`, so it may contain `:` itself.
+    Basic {
+        ///The user name.
+        user: String,
+
+        ///The password.
+        pass: String,
+    },
+
+    ///A token from the `Bearer` scheme.
+    Bearer(String),
+
+    ///Credentials for any other scheme.
+    Other {
+        ///The name of the scheme.
+        scheme: String,
+
+        ///Everything after the scheme name, without the leading space.
+        params: String,
+    },
+}
+
+impl Authorization {
+    ///Parse the value of an `Authorization` header. `None` is returned if it
+    ///has no scheme, if a `Basic` value is not valid base64 or doesn't
+    ///contain a `:`, or if a `Bearer` value is empty.
+    ///
+    ///```
+    ///use rustful::context::Authorization;
+    ///
+    ///let credentials = Authorization::parse(b"Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==");
+    ///assert_eq!(credentials, Some(Authorization::Basic {
+    ///    user: "Aladdin".into(),
+    ///    pass: "open sesame".into(),
+    ///}));
+    ///
+    ///assert_eq!(Authorization::parse(b"Bearer abc.def"), Some(Authorization::Bearer("abc.def".into())));
+    ///assert_eq!(Authorization::parse(b"Basic not base64!"), None);
+    ///```
+    pub fn parse(value: &[u8]) -> Option<Authorization> {
+        let value = match str::from_utf8(value) {
+            Ok(value) => value.trim(),
+            Err(_) => return None
+        };
+
+        let (scheme, params) = match value.find(' ') {
+            Some(index) => (&value[..index], value[index + 1..].trim()),
+            None => (value, "")
+        };
+
+        if scheme.is_empty() {
+            return None;
+        }
+
+        if scheme.eq_ignore_ascii_case("Basic") {
+            let decoded = match decode_base64(params).and_then(|decoded| String::from_utf8(decoded).ok()) {
+                Some(decoded) => decoded,
+                None => return None
+            };
+
+            decoded.find(':').map(|index| Authorization::Basic {
+                user: decoded[..index].to_owned(),
+                pass: decoded[index + 1..].to_owned(),
+            })
+        } else if scheme.eq_ignore_ascii_case("Bearer") {
+            if params.is_empty() || params.contains(' ') {
+                None
+            } else {
+                Some(Authorization::Bearer(params.to_owned()))
+            }
+        } else {
+            Some(Authorization::Other {
+                scheme: scheme.to_owned(),
+                params: params.to_owned(),
+            })
+        }
+    }
+}
+
+//Decode standard base64, with optional padding.
+fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    let end = input.bytes().rposition(|byte| byte != b'=').map_or(0, |index| index + 1);
+    let input = &input[..end];
+    if input.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut output = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for byte in input.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None
+        };
+
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    Some(output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Authorization;
+
+    #[test]
+    fn basic() {
+        assert_eq!(Authorization::parse(b"Basic dXNlcjpwYTpzcw=="), Some(Authorization::Basic {
+            user: "user".into(),
+            pass: "pa:ss".into(),
+        }));
+        assert_eq!(Authorization::parse(b"basic dXNlcjo"), Some(Authorization::Basic {
+            user: "user".into(),
+            pass: "".into(),
+        }));
+    }
+
+    #[test]
+    fn bearer() {
+        assert_eq!(Authorization::parse(b"Bearer mF_9.B5f-4.1JqM"), Some(Authorization::Bearer("mF_9.B5f-4.1JqM".into())));
+        assert_eq!(Authorization::parse(b"Bearer"), None);
+    }
+
+    #[test]
+    fn other() {
+        assert_eq!(Authorization::parse(b"Digest username=\"user\", realm=\"x\""), Some(Authorization::Other {
+            scheme: "Digest".into(),
+            params: "username=\"user\", realm=\"x\"".into(),
+        }));
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(Authorization::parse(b"Basic dXNlcjpwYXNz!"), None);
+        assert_eq!(Authorization::parse(b"Basic dXNlcg=="), None);
+        assert_eq!(Authorization::parse(b"Basic d"), None);
+        assert_eq!(Authorization::parse(b"Basic /w=="), None);
+        assert_eq!(Authorization::parse(b""), None);
+    }
+}
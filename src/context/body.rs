@@ -7,22 +7,65 @@ use rustc_serialize::Decodable;
 
 #[cfg(feature = "multipart")]
 use multipart::server::{HttpRequest, Multipart};
+#[cfg(feature = "multipart")]
+use context::upload::{self, Upload, UploadSettings, UploadError};
 
-use std::io::{self, Read};
+use std::io::{self, Read, BufRead, Write};
+use std::net::Shutdown;
+use std::error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
+#[cfg(feature = "rustc_json_body")]
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use hyper::buffer::BufReader;
 use hyper::http::h1::HttpReader;
 use hyper::net::NetworkStream;
 
-use context::Parameters;
+use context::{Parameters, FormData};
 use header::Headers;
+#[cfg(feature = "encoding")]
+use utils::Charset;
+
+const BUFFER_SIZE: usize = 8 * 1024;
+const MAX_TRAILER_SIZE: u64 = 8 * 1024;
 
 ///A reader for a request body.
+///
+///It implements both `Read` and `BufRead`, so it's possible to read the body
+///line by line. It will never read beyond the end of the body, as it's
+///defined by `Content-Length` or chunked transfer encoding.
+///
+///```
+///use std::io::BufRead;
+///use rustful::{Context, Response};
+///
+///fn my_handler(context: Context, response: Response) {
+///    let mut count = 0;
+///    for line in context.body.lines() {
+///        match line {
+///            Ok(_) => count += 1,
+///            Err(_) => return
+///        }
+///    }
+///
+///    response.send(format!("got {} lines", count));
+///}
+///```
 pub struct BodyReader<'a, 'b: 'a> {
-    reader: HttpReader<&'a mut BufReader<&'b mut NetworkStream>>,
+    reader: BufferedHttpReader<'a, 'b>,
+    max_length: Option<u64>,
+    max_fields: Option<usize>,
 
     #[cfg(feature = "multipart")]
-    multipart_boundary: Option<String>
+    multipart_boundary: Option<String>,
+
+    #[cfg(feature = "encoding")]
+    request_charset: Option<Option<Charset>>,
+    #[cfg(feature = "encoding")]
+    fallback_charset: Option<Charset>
 }
 
 impl<'a, 'b> BodyReader<'a, 'b> {
@@ -30,15 +73,15 @@ impl<'a, 'b> BodyReader<'a, 'b> {
     #[cfg(feature = "multipart")]
     ///Internal and may change without warning.
     pub fn from_reader(reader: HttpReader<&'a mut BufReader<&'b mut NetworkStream>>, headers: &Headers) -> BodyReader<'a, 'b> {
-        use header::ContentType;
         use mime::{Mime, TopLevel, SubLevel, Attr, Value};
+        use utils::parse_content_type;
 
-        let boundary = match headers.get() {
-            Some(&ContentType(Mime(TopLevel::Multipart, SubLevel::FormData, ref attrs))) => {
-                attrs.iter()
-                    .find(|&&(ref attr, _)| attr == &Attr::Boundary)
-                    .and_then(|&(_, ref val)| if let Value::Ext(ref boundary) = *val {
-                        Some(boundary.clone())
+        let boundary = match parse_content_type(headers) {
+            Some(Mime(TopLevel::Multipart, SubLevel::FormData, attrs)) => {
+                attrs.into_iter()
+                    .find(|&(ref attr, _)| attr == &Attr::Boundary)
+                    .and_then(|(_, val)| if let Value::Ext(boundary) = val {
+                        Some(boundary)
                     } else {
                         None
                     })
@@ -47,22 +90,305 @@ impl<'a, 'b> BodyReader<'a, 'b> {
         };
 
         BodyReader {
-            reader: reader,
-            multipart_boundary: boundary
+            reader: BufferedHttpReader::new(reader),
+            max_length: None,
+            max_fields: None,
+            multipart_boundary: boundary,
+            #[cfg(feature = "encoding")]
+            request_charset: ::utils::request_charset(headers),
+            #[cfg(feature = "encoding")]
+            fallback_charset: None
         }
     }
 
     #[doc(hidden)]
     #[cfg(not(feature = "multipart"))]
     ///Internal and may change without warning.
-    pub fn from_reader(reader: HttpReader<&'a mut BufReader<&'b mut NetworkStream>>, _headers: &Headers) -> BodyReader<'a, 'b> {
+    #[cfg_attr(not(feature = "encoding"), allow(unused_variables))]
+    pub fn from_reader(reader: HttpReader<&'a mut BufReader<&'b mut NetworkStream>>, headers: &Headers) -> BodyReader<'a, 'b> {
         BodyReader {
-            reader: reader
+            reader: BufferedHttpReader::new(reader),
+            max_length: None,
+            max_fields: None,
+            #[cfg(feature = "encoding")]
+            request_charset: ::utils::request_charset(headers),
+            #[cfg(feature = "encoding")]
+            fallback_charset: None
+        }
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn read_counter(&self) -> Arc<AtomicUsize> {
+        self.reader.bytes_read.clone()
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn completion_flag(&self) -> Arc<AtomicBool> {
+        self.reader.complete.clone()
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.reader.deadline = deadline;
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn drain_flag(&self) -> Arc<AtomicBool> {
+        self.reader.drain.clone()
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn set_chunk_limits(&mut self, max_size: u64, max_extension_length: usize) {
+        self.reader.chunk_limits = Some((max_size, max_extension_length));
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn set_max_length(&mut self, max_length: Option<u64>) {
+        self.max_length = max_length;
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn set_max_fields(&mut self, max_fields: usize) {
+        self.max_fields = Some(max_fields);
+    }
+
+    //Count the fields before they are parsed and stored.
+    fn check_field_count(&self, body: &[u8]) -> io::Result<()> {
+        match self.max_fields {
+            Some(max_fields) if ::utils::count_pairs(body) > max_fields => {
+                Err(io::Error::new(io::ErrorKind::InvalidData, BodyError::TooManyFields))
+            },
+            _ => Ok(())
+        }
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn check_buffered_chunk(&mut self) -> io::Result<()> {
+        match (&mut self.reader.reader, self.reader.chunk_limits) {
+            (&mut HttpReader::ChunkedReader(ref mut reader, None), Some((max_size, max_extension_length))) => {
+                check_chunk_line(reader.get_buf(), max_size, max_extension_length).map(|_| ())
+            },
+            _ => Ok(())
         }
     }
 }
 
+#[cfg(feature = "encoding")]
 impl<'a, 'b> BodyReader<'a, 'b> {
+    ///Set the encoding of form values that are not valid UTF-8, when the
+    ///body is read using `read_query_body`. It's initially the one from
+    ///`Server::fallback_charset`, and it can be changed for a single route by
+    ///a context filter, like `filter::FallbackCharset`. A `charset` parameter
+    ///in the `Content-Type` header of the request takes precedence.
+    pub fn set_fallback_charset(&mut self, charset: Option<Charset>) {
+        self.fallback_charset = charset;
+    }
+
+    ///The encoding that is used for form values that are not valid UTF-8.
+    ///This is the one from the `charset` parameter of the request, if there
+    ///is one, and the fallback charset otherwise. `None` means that the
+    ///values are left as `NotUtf8`, which is also the case if the request
+    ///declares UTF-8 or an unsupported charset.
+    pub fn charset(&self) -> Option<Charset> {
+        match self.request_charset {
+            Some(declared) => declared,
+            None => self.fallback_charset
+        }
+    }
+}
+
+impl<'a, 'b> BodyReader<'a, 'b> {
+    ///Get the number of body bytes that has been received from the client so
+    ///far. This is the size of the body itself, after removing any chunked
+    ///transfer encoding, and it includes everything that has been buffered by
+    ///the reader. Anything that is left unread, or never arrived because the
+    ///client disconnected, is not included.
+    pub fn bytes_read(&self) -> u64 {
+        self.reader.bytes_read.load(Ordering::Relaxed) as u64
+    }
+
+    ///Get the trailers that were sent after a chunked body. They are parsed
+    ///when the end of the body is reached, so this is empty until then, as
+    ///well as for bodies that are not chunked. The trailer section may be at
+    ///most 8 KiB, and reading the end of the body fails if it's larger.
+    pub fn trailers(&self) -> &Headers {
+        &self.reader.trailers
+    }
+
+    ///Read and discard the rest of the body, including any trailers, to
+    ///make it possible to keep the connection alive.
+    ///
+    ///There is no limit besides `Server::max_body_length`, which only
+    ///applies to bodies with a `Content-Length`, so a chunked body may be
+    ///long. It's often cheaper to let the connection be closed, which
+    ///happens automatically if the body is left unread. See
+    ///`Server::auto_drain_limit` for draining small bodies automatically.
+    pub fn drain(&mut self) -> io::Result<()> {
+        self.reader.drain()
+    }
+
+    ///Read the rest of the body into `buf`, and return the number of bytes
+    ///that were read. The body is appended to what's already in `buf`, so
+    ///it's up to the caller to clear it when it's reused between requests.
+    ///
+    ///`BodyError::TooLarge` is returned if the body is longer than `max`
+    ///bytes, and `buf` is then truncated to its original length. The rest of
+    ///the body is left unread in that case.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///use rustful::StatusCode::PayloadTooLarge;
+    ///use rustful::context::body::BodyError;
+    ///
+    ///fn my_handler(mut context: Context, mut response: Response) {
+    ///    let mut buffer = Vec::with_capacity(4096);
+    ///    match context.body.read_into(&mut buffer, 1024) {
+    ///        Ok(length) => response.send(format!("got {} bytes", length)),
+    ///        Err(BodyError::TooLarge) => response.set_status(PayloadTooLarge),
+    ///        Err(_) => {}
+    ///    }
+    ///}
+    ///```
+    pub fn read_into(&mut self, buf: &mut Vec<u8>, max: usize) -> Result<usize, BodyError> {
+        let start = buf.len();
+        let result = self.by_ref().take(max as u64 + 1).read_to_end(buf);
+        match result {
+            Ok(length) if length > max => {
+                buf.truncate(start);
+                Err(BodyError::TooLarge)
+            },
+            Ok(length) => Ok(length),
+            Err(e) => {
+                buf.truncate(start);
+                Err(BodyError::Io(e))
+            }
+        }
+    }
+
+    ///Read the whole body into a shared buffer, and keep it, so it can be
+    ///used more than once. This is useful when the same body has to be
+    ///handled in more than one way, like when a signature is checked over
+    ///the raw bytes before the body is decoded.
+    ///
+    ///The body is only read the first time, and the following calls return
+    ///the same buffer. The body reader is rewound to the beginning of it
+    ///each time, and `read_json_body`, `read_form_data` and the other
+    ///methods that read the body as a whole, always start from the
+    ///beginning of the buffer, so they can be used any number of times
+    ///afterwards. The buffer only contains what hasn't been read before the
+    ///first call, which is the whole body unless something else has already
+    ///read from it.
+    ///
+    ///`BodyError::TooLarge` is returned if the body is longer than
+    ///`Server::max_body_length`, which is then also checked for chunked
+    ///bodies, and the body can't be read again in that case. There is no
+    ///limit if `max_body_length` is `None`.
+    ///
+    ///```
+    ///use rustful::{Context, Response, StatusCode};
+    ///
+    ///# fn is_signed(_context: &Context, _body: &[u8]) -> bool { true }
+    ///fn webhook(mut context: Context, mut response: Response) {
+    ///    let body = match context.body_bytes() {
+    ///        Ok(body) => body,
+    ///        Err(_) => return response.set_status(StatusCode::PayloadTooLarge)
+    ///    };
+    ///
+    ///    if !is_signed(&context, &body) {
+    ///        return response.set_status(StatusCode::Forbidden);
+    ///    }
+    ///
+    ///    match context.body.read_json_body() {
+    ///        Ok(event) => response.send(format!("got {}", event)),
+    ///        Err(_) => response.set_status(StatusCode::BadRequest)
+    ///    }
+    ///}
+    ///```
+    pub fn cached_bytes(&mut self) -> Result<Arc<[u8]>, BodyError> {
+        if let Some(ref cached) = self.reader.cached {
+            self.reader.cached_position = 0;
+            return Ok(cached.clone());
+        }
+
+        let max = self.max_length.map_or(::std::usize::MAX - 1, |max| ::std::cmp::min(max, ::std::usize::MAX as u64 - 1) as usize);
+        let mut body = vec![];
+        try!(self.read_into(&mut body, max));
+
+        let body: Arc<[u8]> = body.into();
+        self.reader.cached = Some(body.clone());
+        self.reader.cached_position = 0;
+        Ok(body)
+    }
+
+    ///Read the rest of the body into an internal buffer and return it,
+    ///without consuming it. Everything that is returned is still left to be
+    ///read, so this is useful for filters that need to look at the body
+    ///before it reaches the handler.
+    ///
+    ///`BodyError::TooLarge` is returned if the body is longer than `max`
+    ///bytes. What has been buffered so far is still left to be read in that
+    ///case, followed by the rest of the body.
+    pub fn peek(&mut self, max: usize) -> Result<&[u8], BodyError> {
+        self.reader.peek(max)
+    }
+
+    ///Copy the rest of the body to `writer`, while reporting the progress to
+    ///`progress`, and return the number of bytes that were copied.
+    ///
+    ///`progress` is called after each buffer of up to 8 KiB has been
+    ///written, with the number of body bytes that have been read so far and
+    ///the total length of the body, if it was sent with a `Content-Length`.
+    ///The count includes anything that was read before the copy started.
+    ///
+    ///```
+    ///use std::io;
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(mut context: Context, response: Response) {
+    ///    let result = context.body.copy_with_progress(&mut io::sink(), |read, total| {
+    ///        if let Some(total) = total {
+    ///            println!("received {} of {} bytes", read, total);
+    ///        } else {
+    ///            println!("received {} bytes", read);
+    ///        }
+    ///    });
+    ///
+    ///    match result {
+    ///        Ok(length) => response.send(format!("got {} bytes", length)),
+    ///        Err(e) => response.send(format!("failed to read the body: {}", e))
+    ///    }
+    ///}
+    ///```
+    pub fn copy_with_progress<W, F>(&mut self, writer: &mut W, mut progress: F) -> io::Result<u64> where
+        W: Write + ?Sized,
+        F: FnMut(u64, Option<u64>)
+    {
+        let total = self.reader.total_length();
+        let mut copied = 0;
+
+        loop {
+            let length = {
+                let buffer = try!(self.reader.fill_buf());
+                if buffer.is_empty() {
+                    return Ok(copied);
+                }
+                try!(writer.write_all(buffer));
+                buffer.len()
+            };
+            self.reader.consume(length);
+            copied += length as u64;
+            progress(self.reader.consumed(), total);
+        }
+    }
+
     ///Try to create a `multipart/form-data` reader from the request body.
     ///
     ///```
@@ -103,6 +429,7 @@ impl<'a, 'b> BodyReader<'a, 'b> {
     ///```
     #[cfg(feature = "multipart")]
     pub fn as_multipart<'r>(&'r mut self) -> Option<Multipart<MultipartRequest<'r, 'a, 'b>>> {
+        self.reader.rewind();
         let reader = &mut self.reader;
         self.multipart_boundary.as_ref().and_then(move |boundary|
             Multipart::from_request(MultipartRequest {
@@ -112,8 +439,59 @@ impl<'a, 'b> BodyReader<'a, 'b> {
         )
     }
 
+    ///Save a `multipart/form-data` request to disk.
+    ///
+    ///Each file is streamed to its own file in `settings.directory`, while
+    ///the text fields are collected in memory. The files are given unique
+    ///names, so the original filenames are only kept as information. The
+    ///size limits in `settings` are checked while the files are written, and
+    ///everything that has been saved so far will be removed if a limit is
+    ///exceeded or if anything else fails.
+    ///
+    ///```
+    ///# extern crate rustful;
+    ///use rustful::{Context, Response};
+    ///use rustful::StatusCode::{BadRequest, PayloadTooLarge};
+    ///use rustful::context::upload::{UploadSettings, UploadError};
+    ///
+    ///fn my_handler(mut context: Context, mut response: Response) {
+    ///    let settings = UploadSettings {
+    ///        part_limit: Some(1024 * 1024),
+    ///        total_limit: Some(4 * 1024 * 1024),
+    ///        ..UploadSettings::default()
+    ///    };
+    ///
+    ///    match context.body.save_multipart(&settings) {
+    ///        Ok(upload) => {
+    ///            let names: Vec<_> = upload.files.iter()
+    ///                .filter_map(|file| file.filename.as_ref())
+    ///                .map(|name| &**name)
+    ///                .collect();
+    ///            response.send(format!("saved {}", names.join(", ")));
+    ///        },
+    ///        Err(UploadError::PartTooLarge(_)) | Err(UploadError::TooLarge) => {
+    ///            response.set_status(PayloadTooLarge);
+    ///        },
+    ///        Err(_) => response.set_status(BadRequest)
+    ///    }
+    ///}
+    ///# fn main() {}
+    ///```
+    #[cfg(feature = "multipart")]
+    pub fn save_multipart(&mut self, settings: &UploadSettings) -> Result<Upload, UploadError> {
+        match self.as_multipart() {
+            Some(mut multipart) => upload::save(&mut multipart, settings),
+            None => Err(UploadError::NotMultipart)
+        }
+    }
+
     ///Read and parse the request body as a query string. The body will be
     ///decoded as UTF-8 and plain '+' characters will be replaced with spaces.
+    ///Malformed percent escape sequences will result in an `InvalidData`
+    ///error, and so will more fields than `Server::max_parameters`, where
+    ///the error wraps `BodyError::TooManyFields`. Values that are not valid
+    ///UTF-8 are decoded using `charset()`, when the `encoding` feature is
+    ///enabled.
     ///
     ///A simplified example of how to parse `a=number&b=number`:
     ///
@@ -133,9 +511,52 @@ impl<'a, 'b> BodyReader<'a, 'b> {
     ///```
     #[inline]
     pub fn read_query_body(&mut self) -> io::Result<Parameters> {
+        self.reader.rewind();
         let mut buf = Vec::new();
         try!(self.read_to_end(&mut buf));
-        Ok(::utils::parse_parameters(&buf))
+        try!(self.check_field_count(&buf));
+        let parameters = try!(::utils::parse_parameters(&buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+
+        #[cfg(feature = "encoding")]
+        let parameters = match self.charset() {
+            Some(charset) => charset.decode_parameters(parameters),
+            None => parameters
+        };
+
+        Ok(parameters)
+    }
+
+    ///Read and parse the request body as a query string, like
+    ///`read_query_body`, but keep every value of repeated fields. See
+    ///`FormData` for more details.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(mut context: Context, response: Response) {
+    ///    match context.body.read_form_data() {
+    ///        Ok(form) => {
+    ///            let tags = form.group_brackets().get_all("tags").len();
+    ///            response.send(format!("got {} tags", tags));
+    ///        },
+    ///        Err(_) => response.send("could not read the form")
+    ///    }
+    ///}
+    ///```
+    pub fn read_form_data(&mut self) -> io::Result<FormData> {
+        self.reader.rewind();
+        let mut buf = Vec::new();
+        try!(self.read_to_end(&mut buf));
+        try!(self.check_field_count(&buf));
+        let form = try!(FormData::parse(&buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+
+        #[cfg(feature = "encoding")]
+        let form = match self.charset() {
+            Some(charset) => charset.decode_form_data(form),
+            None => form
+        };
+
+        Ok(form)
     }
 
     ///Read the request body into a generic JSON structure. This structure can
@@ -159,6 +580,7 @@ impl<'a, 'b> BodyReader<'a, 'b> {
     ///```
     #[cfg(feature = "rustc_json_body")]
     pub fn read_json_body(&mut self) -> Result<json::Json, json::BuilderError> {
+        self.reader.rewind();
         json::Json::from_reader(self)
     }
 
@@ -189,6 +611,7 @@ impl<'a, 'b> BodyReader<'a, 'b> {
     ///```
     #[cfg(feature = "rustc_json_body")]
     pub fn decode_json_body<T: Decodable>(&mut self) -> json::DecodeResult<T> {
+        self.reader.rewind();
         let mut buf = String::new();
         try!(self.read_to_string(&mut buf).map_err(|e| {
             let parse_err = json::ParserError::IoError(e);
@@ -196,6 +619,145 @@ impl<'a, 'b> BodyReader<'a, 'b> {
         }));
         json::decode(&buf)
     }
+
+    ///Decode a newline delimited JSON body, where each line is a JSON value,
+    ///one record at the time. The target type must implement
+    ///`rustc_serialize::Decodable`.
+    ///
+    ///Each line is only read when the iterator reaches it, so the whole body
+    ///is never kept in memory. Blank lines, and trailing newlines, are
+    ///skipped. A line that can't be decoded gives an error, without stopping
+    ///the iteration, while an IO error, including going beyond a body size
+    ///limit, ends it.
+    ///
+    ///```
+    ///extern crate rustful;
+    ///extern crate rustc_serialize;
+    ///
+    ///use std::collections::BTreeMap;
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(mut context: Context, response: Response) {
+    ///    let mut records = 0;
+    ///    let mut errors = 0;
+    ///
+    ///    for record in context.body.decode_ndjson_body::<BTreeMap<String, f64>>() {
+    ///        match record {
+    ///            Ok(_) => records += 1,
+    ///            Err(_) => errors += 1
+    ///        }
+    ///    }
+    ///
+    ///    response.send(format!("{} records and {} errors", records, errors));
+    ///}
+    ///# fn main() {}
+    ///```
+    #[cfg(feature = "rustc_json_body")]
+    pub fn decode_ndjson_body<'r, T: Decodable>(&'r mut self) -> NdJson<'r, 'a, 'b, T> {
+        self.reader.rewind();
+        NdJson {
+            body: Some(self),
+            line: vec![],
+            _decoded: PhantomData,
+        }
+    }
+}
+
+///An iterator over the records in a newline delimited JSON body.
+///
+///It's created by `BodyReader::decode_ndjson_body`.
+#[cfg(feature = "rustc_json_body")]
+pub struct NdJson<'r, 'a: 'r, 'b: 'a, T> {
+    body: Option<&'r mut BodyReader<'a, 'b>>,
+    line: Vec<u8>,
+    _decoded: PhantomData<fn() -> T>,
+}
+
+#[cfg(feature = "rustc_json_body")]
+impl<'r, 'a, 'b, T: Decodable> Iterator for NdJson<'r, 'a, 'b, T> {
+    type Item = json::DecodeResult<T>;
+
+    fn next(&mut self) -> Option<json::DecodeResult<T>> {
+        loop {
+            self.line.clear();
+            let result = match self.body {
+                Some(ref mut body) => body.read_until(b'\n', &mut self.line),
+                None => return None
+            };
+
+            match result {
+                Ok(0) => {
+                    self.body = None;
+                    return None;
+                },
+                Ok(_) => {},
+                Err(e) => {
+                    self.body = None;
+                    return Some(Err(json::DecoderError::ParseError(json::ParserError::IoError(e))));
+                }
+            }
+
+            let line = match ::std::str::from_utf8(&self.line) {
+                Ok(line) => line.trim(),
+                Err(e) => {
+                    let e = io::Error::new(io::ErrorKind::InvalidData, e);
+                    return Some(Err(json::DecoderError::ParseError(json::ParserError::IoError(e))));
+                }
+            };
+
+            if !line.is_empty() {
+                return Some(json::decode(line));
+            }
+        }
+    }
+}
+
+///An error from `BodyReader::read_into` or `BodyReader::cached_bytes`.
+#[derive(Debug)]
+pub enum BodyError {
+    ///The body was longer than the limit.
+    TooLarge,
+
+    ///A form body had more fields than `Server::max_parameters`. This is
+    ///wrapped in an `InvalidData` error from the methods that return
+    ///`io::Result`.
+    TooManyFields,
+
+    ///The body could not be read.
+    Io(io::Error)
+}
+
+impl From<io::Error> for BodyError {
+    fn from(err: io::Error) -> BodyError {
+        BodyError::Io(err)
+    }
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BodyError::TooLarge => write!(f, "the body is too large"),
+            BodyError::TooManyFields => write!(f, "the form has too many fields"),
+            BodyError::Io(ref e) => write!(f, "io error: {}", e)
+        }
+    }
+}
+
+impl error::Error for BodyError {
+    fn description(&self) -> &str {
+        match *self {
+            BodyError::TooLarge => "the body is too large",
+            BodyError::TooManyFields => "the form has too many fields",
+            BodyError::Io(_) => "the body could not be read"
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            BodyError::Io(ref e) => Some(e),
+            _ => None
+        }
+    }
 }
 
 impl<'a, 'b> Read for BodyReader<'a, 'b> {
@@ -205,11 +767,314 @@ impl<'a, 'b> Read for BodyReader<'a, 'b> {
     }
 }
 
+impl<'a, 'b> BufRead for BodyReader<'a, 'b> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.reader.consume(amount)
+    }
+}
+
+//A lazily allocated buffer on top of the body reader. Reads will go straight
+//to the underlying reader when the buffer is empty.
+struct BufferedHttpReader<'a, 'b: 'a> {
+    reader: HttpReader<&'a mut BufReader<&'b mut NetworkStream>>,
+    buffer: Vec<u8>,
+    position: usize,
+    bytes_read: Arc<AtomicUsize>,
+    trailers: Headers,
+    trailers_read: bool,
+    //Set when the whole body, including any trailers, has been read from
+    //the connection, so the next request can be read after it.
+    complete: Arc<AtomicBool>,
+    //Set if the rest of the body should be drained when the reader is
+    //dropped.
+    drain: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+    //The largest chunk size and chunk extension that are accepted.
+    chunk_limits: Option<(u64, usize)>,
+    //The whole body, from `BodyReader::cached_bytes`, which is read from instead
+    //of the connection once it's set.
+    cached: Option<Arc<[u8]>>,
+    cached_position: usize
+}
+
+impl<'a, 'b> BufferedHttpReader<'a, 'b> {
+    fn new(reader: HttpReader<&'a mut BufReader<&'b mut NetworkStream>>) -> BufferedHttpReader<'a, 'b> {
+        let complete = match reader {
+            HttpReader::EmptyReader(_) | HttpReader::SizedReader(_, 0) => true,
+            _ => false
+        };
+
+        BufferedHttpReader {
+            reader: reader,
+            buffer: Vec::new(),
+            position: 0,
+            bytes_read: Arc::new(AtomicUsize::new(0)),
+            trailers: Headers::new(),
+            trailers_read: false,
+            complete: Arc::new(AtomicBool::new(complete)),
+            drain: Arc::new(AtomicBool::new(false)),
+            deadline: None,
+            chunk_limits: None,
+            cached: None,
+            cached_position: 0
+        }
+    }
+
+    //The length of a body with a `Content-Length`, including what has
+    //already been read.
+    fn total_length(&self) -> Option<u64> {
+        match self.reader {
+            HttpReader::SizedReader(_, remaining) => Some(self.bytes_read.load(Ordering::Relaxed) as u64 + remaining),
+            HttpReader::EmptyReader(_) => Some(0),
+            _ => None
+        }
+    }
+
+    //The number of body bytes that have been passed on from the buffer.
+    fn consumed(&self) -> u64 {
+        (self.bytes_read.load(Ordering::Relaxed) - (self.buffer.len() - self.position)) as u64
+    }
+
+    //Start from the beginning of the cached body, if there is one.
+    fn rewind(&mut self) {
+        self.cached_position = 0;
+    }
+
+    fn peek(&mut self, max: usize) -> Result<&[u8], BodyError> {
+        if let Some(ref cached) = self.cached {
+            let rest = &cached[self.cached_position..];
+            return if rest.len() > max { Err(BodyError::TooLarge) } else { Ok(rest) };
+        }
+
+        if self.position > 0 {
+            self.buffer.drain(..self.position);
+            self.position = 0;
+        }
+
+        let mut chunk = [0; BUFFER_SIZE];
+        while self.buffer.len() <= max {
+            let length = try!(self.read_body(&mut chunk));
+            if length == 0 {
+                return Ok(&self.buffer);
+            }
+            self.buffer.extend_from_slice(&chunk[..length]);
+        }
+
+        Err(BodyError::TooLarge)
+    }
+
+    fn drain(&mut self) -> io::Result<()> {
+        if let Some(ref cached) = self.cached {
+            self.cached_position = cached.len();
+        }
+
+        self.position = self.buffer.len();
+        let mut buffer = [0; BUFFER_SIZE];
+        while try!(self.read_body(&mut buffer)) > 0 {}
+        Ok(())
+    }
+
+    //Reads from the body and parses the trailers when the last chunk has
+    //been reached.
+    fn read_body(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "the request body took too long to arrive"));
+            }
+        }
+
+        //The read timeout is reported as `WouldBlock` on some platforms.
+        let length = match self.check_chunk().and_then(|_| self.reader.read(buf)) {
+            Ok(length) => length,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "the request body stopped arriving"));
+            },
+            Err(e) => return Err(e)
+        };
+        self.bytes_read.fetch_add(length, Ordering::Relaxed);
+
+        if length == 0 && !self.trailers_read {
+            if let HttpReader::ChunkedReader(ref mut reader, Some(0)) = self.reader {
+                self.trailers = try!(read_trailers(&mut **reader));
+                self.trailers_read = true;
+            }
+        }
+
+        let complete = match self.reader {
+            HttpReader::SizedReader(_, remaining) => remaining == 0,
+            HttpReader::ChunkedReader(..) => self.trailers_read,
+            HttpReader::EofReader(_) | HttpReader::EmptyReader(_) => length == 0
+        };
+        if complete {
+            self.complete.store(true, Ordering::Relaxed);
+        }
+
+        Ok(length)
+    }
+}
+
+impl<'a, 'b> Drop for BufferedHttpReader<'a, 'b> {
+    fn drop(&mut self) {
+        if self.drain.load(Ordering::Relaxed) && !self.complete.load(Ordering::Relaxed) {
+            if let Err(e) = self.drain() {
+                debug!(target: "rustful::server", "could not drain the request body: {}", e);
+
+                //What's left of the body would otherwise be read as the next
+                //request.
+                let _ = self.reader.get_mut().get_mut().close(Shutdown::Read);
+            }
+        }
+    }
+}
+
+impl<'a, 'b> BufferedHttpReader<'a, 'b> {
+    //Checks the next chunk size line, if one is about to be read, without
+    //consuming it. Hyper accepts any chunk size, and any amount of
+    //extensions, so they have to be checked before it gets to them.
+    fn check_chunk(&mut self) -> io::Result<()> {
+        let (max_size, max_extension_length) = match self.chunk_limits {
+            Some(limits) => limits,
+            None => return Ok(())
+        };
+
+        if let HttpReader::ChunkedReader(ref mut reader, None) = self.reader {
+            while !try!(check_chunk_line(reader.get_buf(), max_size, max_extension_length)) {
+                //Hyper will report the end of the stream.
+                if try!(reader.read_into_buf()) == 0 {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+//Checks the size and the extension length in the beginning of a chunk size
+//line. Returns `false` if more of the line is needed to tell, and leaves the
+//syntax to Hyper.
+fn check_chunk_line(line: &[u8], max_size: u64, max_extension_length: usize) -> io::Result<bool> {
+    let digits = line.iter().take_while(|b| (**b as char).is_digit(16)).count();
+    let significant = line[..digits].iter().skip_while(|&&b| b == b'0').count();
+
+    let size = line[..digits].iter().fold(0u64, |size, &b| {
+        size.saturating_mul(16).saturating_add((b as char).to_digit(16).unwrap_or(0) as u64)
+    });
+    if significant > 16 || digits > 32 || size > max_size {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "the chunk is too large"));
+    }
+
+    //The rest of the line, including any whitespace and the line break.
+    let rest = &line[digits..];
+    let (extension_length, complete) = match rest.iter().position(|&b| b == b'\n') {
+        Some(index) => (index.saturating_sub(1), true),
+        None => (rest.len().saturating_sub(1), false)
+    };
+    if extension_length > max_extension_length {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "the chunk extension is too long"));
+    }
+
+    Ok(complete)
+}
+
+//Reads the trailer section after the last chunk, including the final line
+//break.
+fn read_trailers<R: BufRead>(reader: R) -> io::Result<Headers> {
+    let mut reader = reader.take(MAX_TRAILER_SIZE);
+    let mut trailers = Headers::new();
+    let mut line = vec![];
+
+    loop {
+        line.clear();
+        try!(reader.read_until(b'\n', &mut line));
+        if !line.ends_with(b"\n") {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the trailer section is too large or incomplete"));
+        }
+
+        let end = line.iter().rposition(|&b| b != b'\r' && b != b'\n' && b != b' ' && b != b'\t').map_or(0, |i| i + 1);
+        let content = &line[..end];
+        if content.is_empty() {
+            return Ok(trailers);
+        }
+
+        let (name, value) = match content.iter().position(|&b| b == b':') {
+            Some(index) => (&content[..index], &content[index + 1..]),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid trailer"))
+        };
+
+        let name = match ::std::str::from_utf8(name) {
+            Ok(name) if !name.is_empty() && name.bytes().all(|b| b > b' ' && b < 0x7f) => name.to_owned(),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid trailer name"))
+        };
+
+        let start = value.iter().position(|&b| b != b' ' && b != b'\t').unwrap_or(value.len());
+        let mut values = trailers.get_raw(&name).map_or_else(Vec::new, |values| values.to_vec());
+        values.push(value[start..].to_vec());
+        trailers.set_raw(name, values);
+    }
+}
+
+impl<'a, 'b> Read for BufferedHttpReader<'a, 'b> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(ref cached) = self.cached {
+            let length = try!((&cached[self.cached_position..]).read(buf));
+            self.cached_position += length;
+            Ok(length)
+        } else if self.position < self.buffer.len() {
+            let length = try!((&self.buffer[self.position..]).read(buf));
+            self.consume(length);
+            Ok(length)
+        } else {
+            self.read_body(buf)
+        }
+    }
+}
+
+impl<'a, 'b> BufRead for BufferedHttpReader<'a, 'b> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if let Some(ref cached) = self.cached {
+            return Ok(&cached[self.cached_position..]);
+        }
+
+        if self.position >= self.buffer.len() {
+            let mut buffer = ::std::mem::replace(&mut self.buffer, vec![]);
+            buffer.resize(BUFFER_SIZE, 0);
+            self.position = 0;
+            match self.read_body(&mut buffer) {
+                Ok(length) => {
+                    buffer.truncate(length);
+                    self.buffer = buffer;
+                },
+                Err(e) => {
+                    buffer.clear();
+                    self.buffer = buffer;
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(&self.buffer[self.position..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        if let Some(ref cached) = self.cached {
+            self.cached_position = ::std::cmp::min(self.cached_position + amount, cached.len());
+            return;
+        }
+
+        self.position = ::std::cmp::min(self.position + amount, self.buffer.len());
+    }
+}
+
 ///A specialized request representation for the multipart interface.
 #[cfg(feature = "multipart")]
 pub struct MultipartRequest<'r, 'a: 'r, 'b: 'a> {
     boundary: &'r str,
-    reader: &'r mut HttpReader<&'a mut BufReader<&'b mut NetworkStream>>
+    reader: &'r mut BufferedHttpReader<'a, 'b>
 }
 
 #[cfg(feature = "multipart")]
@@ -231,4 +1096,277 @@ impl<'r, 'a, 'b> Read for MultipartRequest<'r, 'a, 'b> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         self.reader.read(buf)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, Read};
+
+    use hyper::buffer::BufReader;
+    use hyper::http::h1::HttpReader;
+    use hyper::net::NetworkStream;
+
+    use header::Headers;
+    use mock::MockStream;
+    use super::{BodyReader, check_chunk_line};
+
+    #[test]
+    fn read_sized_lines() {
+        let body = "{\"a\": 1}\n{\"b\": 2}\r\n{\"c\": 3}";
+        let mut stream = MockStream::new(format!("{}GET / HTTP/1.1\r\n\r\n", body));
+        let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+
+        {
+            let body = BodyReader::from_reader(HttpReader::SizedReader(&mut reader, body.len() as u64), &Headers::new());
+            let lines: Vec<_> = body.lines().map(|line| line.unwrap()).collect();
+            assert_eq!(lines, vec!["{\"a\": 1}", "{\"b\": 2}", "{\"c\": 3}"]);
+        }
+
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "GET / HTTP/1.1\r\n\r\n");
+    }
+
+    #[test]
+    fn body_deadline() {
+        use std::io;
+        use std::time::{Duration, Instant};
+
+        let mut stream = MockStream::new("hello");
+        let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+        let mut body = BodyReader::from_reader(HttpReader::SizedReader(&mut reader, 5), &Headers::new());
+
+        body.set_deadline(Some(Instant::now() + Duration::from_secs(60)));
+        let mut buffer = [0; 2];
+        body.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"he");
+
+        body.set_deadline(Some(Instant::now() - Duration::from_millis(1)));
+        let mut buffer = [0; 8];
+        assert_eq!(body.read(&mut buffer).unwrap_err().kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn chunk_limits() {
+        assert_eq!(check_chunk_line(b"ff\r\n", 255, 0).unwrap(), true);
+        assert_eq!(check_chunk_line(b"0000ff;a=b\r\n", 255, 4).unwrap(), true);
+        assert_eq!(check_chunk_line(b"ff", 255, 0).unwrap(), false);
+        assert_eq!(check_chunk_line(b"ff;a", 255, 4).unwrap(), false);
+        assert!(check_chunk_line(b"100\r\n", 255, 0).is_err());
+        assert!(check_chunk_line(b"fffffffffffffffffffff", 255, 0).is_err());
+        assert!(check_chunk_line(b"ff;a=bc\r\n", 255, 4).is_err());
+        assert!(check_chunk_line(b"ff;a=bcd", 255, 4).is_err());
+
+        let mut stream = MockStream::new("5\r\nhello\r\n1000\r\n");
+        let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+        let mut body = BodyReader::from_reader(HttpReader::ChunkedReader(&mut reader, None), &Headers::new());
+        body.set_chunk_limits(255, 16);
+        assert!(body.check_buffered_chunk().is_ok());
+
+        let mut buffer = vec![];
+        let error = body.read_to_end(&mut buffer).unwrap_err();
+        assert_eq!(error.to_string(), "the chunk is too large");
+        assert_eq!(buffer, b"hello");
+    }
+
+    #[test]
+    fn read_chunked_lines() {
+        let mut stream = MockStream::new("9\r\nline 1\nli\r\n5\r\nne 2\n\r\n0\r\n\r\nrest");
+        let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+
+        {
+            let mut body = BodyReader::from_reader(HttpReader::ChunkedReader(&mut reader, None), &Headers::new());
+            let mut line = String::new();
+            body.read_line(&mut line).unwrap();
+            assert_eq!(line, "line 1\n");
+
+            let mut rest = String::new();
+            body.read_to_string(&mut rest).unwrap();
+            assert_eq!(rest, "line 2\n");
+        }
+
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "rest");
+    }
+
+    #[test]
+    fn read_chunked_trailers() {
+        let mut stream = MockStream::new("5\r\nhello\r\n0\r\nChecksum: abc\r\nX-Note:\t a\r\nx-note: b\r\n\r\nrest");
+        let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+
+        {
+            let mut body = BodyReader::from_reader(HttpReader::ChunkedReader(&mut reader, None), &Headers::new());
+            assert_eq!(body.trailers().len(), 0);
+
+            let mut content = String::new();
+            body.read_to_string(&mut content).unwrap();
+            assert_eq!(content, "hello");
+            assert_eq!(body.trailers().get_raw("checksum"), Some(&[b"abc".to_vec()][..]));
+            assert_eq!(body.trailers().get_raw("X-Note"), Some(&[b"a".to_vec(), b"b".to_vec()][..]));
+            assert_eq!(body.bytes_read(), 5);
+        }
+
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "rest");
+    }
+
+    #[test]
+    fn reject_large_trailers() {
+        let trailers: String = (0..1000).map(|i| format!("X-Trailer-{}: value\r\n", i)).collect();
+        let mut stream = MockStream::new(format!("5\r\nhello\r\n0\r\n{}\r\n", trailers));
+        let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+
+        let mut body = BodyReader::from_reader(HttpReader::ChunkedReader(&mut reader, None), &Headers::new());
+        let mut content = String::new();
+        assert!(body.read_to_string(&mut content).is_err());
+    }
+
+    #[test]
+    fn count_chunked_bytes() {
+        let mut stream = MockStream::new("9\r\nline 1\nli\r\n5\r\nne 2\n\r\n0\r\n\r\n");
+        let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+
+        let mut body = BodyReader::from_reader(HttpReader::ChunkedReader(&mut reader, None), &Headers::new());
+        assert_eq!(body.bytes_read(), 0);
+
+        let mut line = String::new();
+        body.read_line(&mut line).unwrap();
+        assert_eq!(body.bytes_read(), 9);
+
+        body.read_to_string(&mut line).unwrap();
+        assert_eq!(body.bytes_read(), 14);
+    }
+
+    #[test]
+    #[cfg(feature = "rustc_json_body")]
+    fn decode_ndjson() {
+        use std::collections::BTreeMap;
+
+        let body = "{\"a\": 1}\n\n{\"b\": 2}\r\n{\"c\": \n{\"d\": 4}\n\n";
+        let mut stream = MockStream::new(body);
+        let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+
+        let mut body = BodyReader::from_reader(HttpReader::SizedReader(&mut reader, body.len() as u64), &Headers::new());
+        let records: Vec<_> = body.decode_ndjson_body::<BTreeMap<String, u32>>().collect();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0].as_ref().ok().and_then(|record| record.get("a")), Some(&1));
+        assert_eq!(records[1].as_ref().ok().and_then(|record| record.get("b")), Some(&2));
+        assert!(records[2].is_err());
+        assert_eq!(records[3].as_ref().ok().and_then(|record| record.get("d")), Some(&4));
+    }
+
+    #[test]
+    fn read_into_buffer() {
+        use super::BodyError;
+
+        let mut stream = MockStream::new("hello world");
+        let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+        let mut buffer = b"> ".to_vec();
+
+        {
+            let mut body = BodyReader::from_reader(HttpReader::SizedReader(&mut reader, 5), &Headers::new());
+            assert_eq!(body.read_into(&mut buffer, 5).unwrap(), 5);
+            assert_eq!(buffer, b"> hello");
+        }
+
+        let mut stream = MockStream::new("5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n");
+        let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+        buffer.clear();
+
+        {
+            let mut body = BodyReader::from_reader(HttpReader::ChunkedReader(&mut reader, None), &Headers::new());
+            assert_eq!(body.read_into(&mut buffer, 11).unwrap(), 11);
+            assert_eq!(buffer, b"hello world");
+        }
+
+        let mut stream = MockStream::new("5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n");
+        let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+
+        let mut body = BodyReader::from_reader(HttpReader::ChunkedReader(&mut reader, None), &Headers::new());
+        match body.read_into(&mut buffer, 10) {
+            Err(BodyError::TooLarge) => {},
+            other => panic!("unexpected result: {:?}", other)
+        }
+        assert_eq!(buffer, b"hello world");
+    }
+
+    #[test]
+    fn read_bytes_twice() {
+        use std::sync::Arc;
+        use super::BodyError;
+
+        let mut stream = MockStream::new("5\r\na=1&b\r\n4\r\n=2&c\r\n0\r\n\r\nrest");
+        let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+
+        {
+            let mut body = BodyReader::from_reader(HttpReader::ChunkedReader(&mut reader, None), &Headers::new());
+            body.set_max_length(Some(9));
+            let first = body.cached_bytes().unwrap();
+            assert_eq!(&*first, b"a=1&b=2&c");
+
+            let form = body.read_query_body().unwrap();
+            assert_eq!(form.get("b"), Some("2".into()));
+            assert_eq!(form.len(), 3);
+            assert_eq!(body.read_form_data().unwrap().len(), 3);
+
+            //Plain reads continue where the last read stopped.
+            assert_eq!(body.peek(9).unwrap(), b"");
+
+            let second = body.cached_bytes().unwrap();
+            assert!(Arc::ptr_eq(&first, &second));
+            let mut line = String::new();
+            body.read_line(&mut line).unwrap();
+            assert_eq!(line, "a=1&b=2&c");
+            assert_eq!(body.bytes_read(), 9);
+        }
+
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "rest");
+
+        let mut stream = MockStream::new("5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n");
+        let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+
+        let mut body = BodyReader::from_reader(HttpReader::ChunkedReader(&mut reader, None), &Headers::new());
+        body.set_max_length(Some(10));
+        match body.cached_bytes() {
+            Err(BodyError::TooLarge) => {},
+            other => panic!("unexpected result: {:?}", other)
+        }
+    }
+
+    #[test]
+    fn copy_with_progress() {
+        let body = vec![b'a'; 20000];
+        let mut stream = MockStream::new(body.clone());
+        let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+        let mut body_reader = BodyReader::from_reader(HttpReader::SizedReader(&mut reader, 20000), &Headers::new());
+
+        let mut first = [0; 100];
+        body_reader.read_exact(&mut first).unwrap();
+
+        let mut copy = first.to_vec();
+        let mut reports = vec![];
+        let length = body_reader.copy_with_progress(&mut copy, |read, total| reports.push((read, total))).unwrap();
+        assert_eq!(length, 19900);
+        assert_eq!(copy, body);
+
+        assert!(reports.len() > 1);
+        assert!(reports.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(reports.iter().all(|&(_, total)| total == Some(20000)));
+        assert_eq!(reports.last(), Some(&(20000, Some(20000))));
+    }
+
+    #[test]
+    fn read_invalid_lines() {
+        let body = b"valid\n\xff\xfe\n";
+        let mut stream = MockStream::new(&body[..]);
+        let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+
+        let body = BodyReader::from_reader(HttpReader::SizedReader(&mut reader, body.len() as u64), &Headers::new());
+        let mut lines = body.lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "valid");
+        assert!(lines.next().unwrap().is_err());
+    }
+}
@@ -0,0 +1,324 @@
+use std::cmp::min;
+use std::str;
+
+//Like `try!`, but for `Option`.
+macro_rules! try_opt {
+    ($e: expr) => (match $e {
+        Some(value) => value,
+        None => return None
+    })
+}
+
+///The directives of one or more `Cache-Control` headers, as defined in RFC
+///7234.
+///
+///The same type is used for both requests and responses, so some of the
+///fields only make sense in one of them. Directive names are case
+///insensitive, and arguments may be quoted. A numeric directive that
+///appears more than once gets the lowest of its values, since that's the
+///safest choice for a cache.
+///
+///```
+///use rustful::context::CacheControl;
+///
+///let cache_control = CacheControl::parse(&[b"public, max-age=3600, Must-Revalidate".to_vec()]).unwrap();
+///assert!(cache_control.public);
+///assert!(cache_control.must_revalidate);
+///assert_eq!(cache_control.max_age, Some(3600));
+///assert!(!cache_control.no_store);
+///```
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct CacheControl {
+    ///`no-cache`.
+    pub no_cache: bool,
+
+    ///The header names from a qualified `no-cache="..."`.
+    pub no_cache_headers: Vec<String>,
+
+    ///`no-store`.
+    pub no_store: bool,
+
+    ///`no-transform`.
+    pub no_transform: bool,
+
+    ///`must-revalidate`.
+    pub must_revalidate: bool,
+
+    ///`proxy-revalidate`.
+    pub proxy_revalidate: bool,
+
+    ///`public`.
+    pub public: bool,
+
+    ///`private`.
+    pub private: bool,
+
+    ///The header names from a qualified `private="..."`.
+    pub private_headers: Vec<String>,
+
+    ///`immutable`, from RFC 8246.
+    pub immutable: bool,
+
+    ///`only-if-cached`.
+    pub only_if_cached: bool,
+
+    ///`max-age`, in seconds.
+    pub max_age: Option<u64>,
+
+    ///`s-maxage`, in seconds.
+    pub s_maxage: Option<u64>,
+
+    ///`max-stale`, in seconds. A `max-stale` without an argument accepts
+    ///any age, and is represented by `u64::MAX`.
+    pub max_stale: Option<u64>,
+
+    ///`min-fresh`, in seconds.
+    pub min_fresh: Option<u64>,
+
+    ///Any other directives, with their names in lowercase and their
+    ///arguments, if any.
+    pub extensions: Vec<(String, Option<String>)>,
+}
+
+impl CacheControl {
+    ///Parse the values of one or more `Cache-Control` headers. `None` is
+    ///returned if any of them is malformed, or if a numeric directive has an
+    ///argument that isn't a number, since it's then impossible to tell what
+    ///was intended.
+    ///
+    ///```
+    ///use rustful::context::CacheControl;
+    ///
+    ///let cache_control = CacheControl::parse(&[b"private=\"Set-Cookie, X-Token\", max-age=\"60\"".to_vec()]).unwrap();
+    ///assert!(cache_control.private);
+    ///assert_eq!(cache_control.private_headers, vec!["Set-Cookie".to_owned(), "X-Token".to_owned()]);
+    ///assert_eq!(cache_control.max_age, Some(60));
+    ///
+    ///assert_eq!(CacheControl::parse(&[b"max-age=soon".to_vec()]), None);
+    ///```
+    pub fn parse(values: &[Vec<u8>]) -> Option<CacheControl> {
+        let mut cache_control = CacheControl::default();
+        for value in values {
+            let mut parser = Parser {
+                input: value,
+                position: 0
+            };
+            for (name, argument) in try_opt!(parser.directives()) {
+                try_opt!(cache_control.set(name, argument));
+            }
+        }
+        Some(cache_control)
+    }
+
+    fn set(&mut self, name: String, argument: Option<String>) -> Option<()> {
+        match &*name {
+            "no-cache" => {
+                self.no_cache = true;
+                self.no_cache_headers.extend(argument.as_ref().map(|a| field_names(a)).unwrap_or_default());
+            },
+            "private" => {
+                self.private = true;
+                self.private_headers.extend(argument.as_ref().map(|a| field_names(a)).unwrap_or_default());
+            },
+            "no-store" => self.no_store = true,
+            "no-transform" => self.no_transform = true,
+            "must-revalidate" => self.must_revalidate = true,
+            "proxy-revalidate" => self.proxy_revalidate = true,
+            "public" => self.public = true,
+            "immutable" => self.immutable = true,
+            "only-if-cached" => self.only_if_cached = true,
+            "max-age" => set_seconds(&mut self.max_age, try_opt!(argument.as_ref().and_then(|a| seconds(a)))),
+            "s-maxage" => set_seconds(&mut self.s_maxage, try_opt!(argument.as_ref().and_then(|a| seconds(a)))),
+            "min-fresh" => set_seconds(&mut self.min_fresh, try_opt!(argument.as_ref().and_then(|a| seconds(a)))),
+            "max-stale" => {
+                let max_stale = match argument {
+                    Some(ref argument) => try_opt!(seconds(argument)),
+                    None => ::std::u64::MAX
+                };
+                set_seconds(&mut self.max_stale, max_stale);
+            },
+            _ => self.extensions.push((name, argument))
+        }
+
+        Some(())
+    }
+}
+
+fn set_seconds(field: &mut Option<u64>, seconds: u64) {
+    *field = Some(field.map_or(seconds, |previous| min(previous, seconds)));
+}
+
+//Delta seconds, where values that are too large are capped, as RFC 7234
+//allows.
+fn seconds(argument: &str) -> Option<u64> {
+    if argument.is_empty() || !argument.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    Some(argument.parse().unwrap_or(::std::u64::MAX))
+}
+
+fn field_names(argument: &str) -> Vec<String> {
+    argument.split(',').map(str::trim).filter(|name| !name.is_empty()).map(|name| name.to_owned()).collect()
+}
+
+fn is_tchar(byte: u8) -> bool {
+    match byte {
+        b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => true,
+        b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => true,
+        _ => false
+    }
+}
+
+//Parses comma separated directives with optional arguments.
+struct Parser<'a> {
+    input: &'a [u8],
+    position: usize
+}
+
+impl<'a> Parser<'a> {
+    fn directives(&mut self) -> Option<Vec<(String, Option<String>)>> {
+        let mut directives = vec![];
+
+        loop {
+            self.skip_whitespace();
+
+            //Empty list elements are allowed, as in `public, , max-age=60`.
+            if self.eat(b',') {
+                continue;
+            }
+
+            if self.position == self.input.len() {
+                break;
+            }
+
+            let name = try_opt!(self.token()).to_ascii_lowercase();
+            self.skip_whitespace();
+            let argument = if self.eat(b'=') {
+                self.skip_whitespace();
+                Some(try_opt!(self.argument()))
+            } else {
+                None
+            };
+            directives.push((name, argument));
+
+            self.skip_whitespace();
+            if !self.eat(b',') && self.position < self.input.len() {
+                return None;
+            }
+        }
+
+        Some(directives)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.position < self.input.len() && (self.input[self.position] == b' ' || self.input[self.position] == b'\t') {
+            self.position += 1;
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        if self.input.get(self.position) == Some(&byte) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn token(&mut self) -> Option<&'a str> {
+        let start = self.position;
+        while self.position < self.input.len() && is_tchar(self.input[self.position]) {
+            self.position += 1;
+        }
+
+        if self.position > start {
+            str::from_utf8(&self.input[start..self.position]).ok()
+        } else {
+            None
+        }
+    }
+
+    fn argument(&mut self) -> Option<String> {
+        if !self.eat(b'"') {
+            return self.token().map(|token| token.to_owned());
+        }
+
+        let mut value = vec![];
+        loop {
+            match self.input.get(self.position).cloned() {
+                Some(b'"') => {
+                    self.position += 1;
+                    return String::from_utf8(value).ok();
+                },
+                Some(b'\\') => {
+                    value.push(*try_opt!(self.input.get(self.position + 1)));
+                    self.position += 2;
+                },
+                Some(byte) => {
+                    value.push(byte);
+                    self.position += 1;
+                },
+                None => return None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CacheControl;
+
+    fn parse(value: &str) -> Option<CacheControl> {
+        CacheControl::parse(&[value.as_bytes().to_vec()])
+    }
+
+    #[test]
+    fn max_age_and_no_cache() {
+        let cache_control = parse("max-age=0, no-cache").unwrap();
+        assert_eq!(cache_control, CacheControl {
+            max_age: Some(0),
+            no_cache: true,
+            ..CacheControl::default()
+        });
+    }
+
+    #[test]
+    fn private_and_no_store() {
+        let cache_control = parse("private, no-store").unwrap();
+        assert_eq!(cache_control, CacheControl {
+            private: true,
+            no_store: true,
+            ..CacheControl::default()
+        });
+    }
+
+    #[test]
+    fn arguments() {
+        let cache_control = parse("No-Cache=\"Set-Cookie,\\\"X-A\\\"\" ,s-maxage = 20,stale-while-revalidate=30, x-flag").unwrap();
+        assert!(cache_control.no_cache);
+        assert_eq!(cache_control.no_cache_headers, vec!["Set-Cookie".to_owned(), "\"X-A\"".to_owned()]);
+        assert_eq!(cache_control.s_maxage, Some(20));
+        assert_eq!(cache_control.extensions, vec![
+            ("stale-while-revalidate".to_owned(), Some("30".to_owned())),
+            ("x-flag".to_owned(), None),
+        ]);
+
+        let cache_control = CacheControl::parse(&[b"max-age=60, max-stale".to_vec(), b"max-age=10".to_vec()]).unwrap();
+        assert_eq!(cache_control.max_age, Some(10));
+        assert_eq!(cache_control.max_stale, Some(::std::u64::MAX));
+
+        assert_eq!(parse("max-age=99999999999999999999").unwrap().max_age, Some(::std::u64::MAX));
+        assert_eq!(parse(""), Some(CacheControl::default()));
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(parse("max-age"), None);
+        assert_eq!(parse("max-age=-1"), None);
+        assert_eq!(parse("max-age=1.5"), None);
+        assert_eq!(parse("public private"), None);
+        assert_eq!(parse("private=\"Set-Cookie"), None);
+        assert_eq!(parse("=60"), None);
+    }
+}
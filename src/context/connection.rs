@@ -0,0 +1,149 @@
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use anymap::Map;
+use anymap::any::Any;
+use hyper::server::request::Request;
+
+///Information about the connection of a request.
+///
+///The TLS fields are only populated when the server is running with
+///`Scheme::Https`, which requires the `ssl` feature, and they are `None` for
+///plain HTTP. The server doesn't offer any protocols using ALPN, since only
+///HTTP/1 is supported, so there is no information about it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    ///The negotiated TLS version, like `TLSv1.2`.
+    pub tls_version: Option<String>,
+
+    ///The name of the negotiated cipher, like `ECDHE-RSA-AES128-GCM-SHA256`.
+    pub cipher: Option<String>,
+
+    ///The host name that the client asked for, using SNI.
+    pub server_name: Option<String>,
+}
+
+impl ConnectionInfo {
+    #[doc(hidden)]
+    #[cfg(feature = "ssl")]
+    ///Internal and may change without warning.
+    pub fn from_request(request: &Request) -> ConnectionInfo {
+        use hyper::net::HttpStream;
+        use openssl::ssl::SslStream;
+
+        match request.ssl::<SslStream<HttpStream>>() {
+            Some(stream) => {
+                let ssl = stream.ssl();
+                ConnectionInfo {
+                    tls_version: Some(ssl.version().to_owned()),
+                    cipher: ssl.get_current_cipher().map(|cipher| cipher.name().to_owned()),
+                    server_name: ssl.get_servername(),
+                }
+            },
+            None => ConnectionInfo::default()
+        }
+    }
+
+    #[doc(hidden)]
+    #[cfg(not(feature = "ssl"))]
+    ///Internal and may change without warning.
+    pub fn from_request(_request: &Request) -> ConnectionInfo {
+        ConnectionInfo::default()
+    }
+
+    ///Check if the connection is encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.tls_version.is_some()
+    }
+}
+
+///State that is shared by every request on the same connection.
+///
+///A new, empty, storage is created when a connection is accepted, and it's
+///dropped when the connection is closed. Every request on a keep-alive
+///connection sees the same values, so it can be used for things like
+///counting requests or remembering a connection level authentication. This
+///is different from the filter storage, which is only shared by the filters
+///and the handler of a single request.
+///
+///The requests on a connection are handled one at a time, but the storage
+///is still shared, so a value that is inserted by one request will still be
+///there for the next, even if it's from a different client behind the same
+///proxy. Handlers should be careful with what they keep in there, and what
+///they trust. The values will live longer than the connection if the storage
+///is cloned and kept somewhere else.
+///
+///```
+///use rustful::{Context, Response};
+///
+///#[derive(Default)]
+///struct RequestCount(usize);
+///
+///fn count(context: Context, response: Response) {
+///    let count = context.connection_storage().with(|count: &mut RequestCount| {
+///        count.0 += 1;
+///        count.0
+///    });
+///    response.send(format!("request {} on this connection", count));
+///}
+///```
+#[derive(Clone)]
+pub struct ConnectionStorage(Arc<Mutex<Map<Any + Send>>>);
+
+impl ConnectionStorage {
+    ///Create an empty storage.
+    pub fn new() -> ConnectionStorage {
+        ConnectionStorage(Arc::new(Mutex::new(Map::new())))
+    }
+
+    ///Insert a value, returning the previous value of the same type, if any.
+    pub fn insert<T: Any + Send>(&self, value: T) -> Option<T> {
+        self.lock().insert(value)
+    }
+
+    ///Remove and return the value of type `T`, if there is one.
+    pub fn remove<T: Any + Send>(&self) -> Option<T> {
+        self.lock().remove()
+    }
+
+    ///Get a copy of the value of type `T`, if there is one.
+    pub fn get<T: Any + Send + Clone>(&self) -> Option<T> {
+        self.lock().get().cloned()
+    }
+
+    ///Check if there is a value of type `T`.
+    pub fn contains<T: Any + Send>(&self) -> bool {
+        self.lock().contains::<T>()
+    }
+
+    ///Modify the value of type `T`, which is inserted with its default value
+    ///first if there isn't one. The storage is locked while `f` runs, so it
+    ///can't be used from within `f`.
+    pub fn with<T, F, R>(&self, f: F) -> R where
+        T: Any + Send + Default,
+        F: FnOnce(&mut T) -> R
+    {
+        let mut map = self.lock();
+        if !map.contains::<T>() {
+            map.insert(T::default());
+        }
+        f(map.get_mut().expect("the value was just inserted"))
+    }
+
+    //A panic in `with` doesn't leave the map itself in a broken state.
+    fn lock<'a>(&'a self) -> MutexGuard<'a, Map<Any + Send>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for ConnectionStorage {
+    fn default() -> ConnectionStorage {
+        ConnectionStorage::new()
+    }
+}
+
+impl fmt::Debug for ConnectionStorage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ConnectionStorage")
+    }
+}
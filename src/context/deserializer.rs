@@ -0,0 +1,205 @@
+use std::collections::{HashMap, BTreeMap};
+
+use rustc_serialize::json::Json;
+
+use StatusCode;
+use mime::Mime;
+use context::FormData;
+use context::body::BodyReader;
+
+///A deserializer for request bodies of one or more content types.
+///
+///The body is deserialized into a `Json` value, which is then decoded into
+///the requested type by `Context::read_typed`. `Json` works as a common data
+///model for most formats, so a deserializer for something like MessagePack
+///or CBOR only has to convert its own values into it. Formats that depend on
+///a schema, like Protocol Buffers, have to use the schema to name the
+///fields.
+///
+///Return `Err(StatusCode::BadRequest)` if the body is malformed, or any
+///other status code that the request should be rejected with.
+///
+///```
+///extern crate rustful;
+///extern crate rustc_serialize;
+///
+///use rustc_serialize::json::Json;
+///use rustful::StatusCode;
+///use rustful::context::body::BodyReader;
+///use rustful::context::BodyDeserializers;
+///
+/////Plain text, where each line is an item in a list.
+///fn lines(body: &mut BodyReader) -> Result<Json, StatusCode> {
+///    use std::io::Read;
+///
+///    let mut text = String::new();
+///    try!(body.read_to_string(&mut text).map_err(|_| StatusCode::BadRequest));
+///    Ok(Json::Array(text.lines().map(|line| Json::String(line.to_owned())).collect()))
+///}
+///
+///# fn main() {
+///let mut deserializers = BodyDeserializers::new();
+///deserializers.insert("text/plain", lines);
+///# }
+///```
+pub trait BodyDeserializer: Send + Sync + 'static {
+    ///Read and deserialize the body.
+    fn deserialize(&self, body: &mut BodyReader) -> Result<Json, StatusCode>;
+}
+
+impl<F: Fn(&mut BodyReader) -> Result<Json, StatusCode> + Send + Sync + 'static> BodyDeserializer for F {
+    fn deserialize(&self, body: &mut BodyReader) -> Result<Json, StatusCode> {
+        self(body)
+    }
+}
+
+///Deserializes JSON bodies. It's registered for `application/json` by
+///default.
+#[derive(Clone, Copy, Debug)]
+pub struct JsonDeserializer;
+
+impl BodyDeserializer for JsonDeserializer {
+    fn deserialize(&self, body: &mut BodyReader) -> Result<Json, StatusCode> {
+        body.read_json_body().map_err(|_| StatusCode::BadRequest)
+    }
+}
+
+///Deserializes `application/x-www-form-urlencoded` bodies, where the fields
+///become strings and lists of strings, as in `extract::Form`. It's
+///registered for `application/x-www-form-urlencoded` by default.
+#[derive(Clone, Copy, Debug)]
+pub struct FormDeserializer;
+
+impl BodyDeserializer for FormDeserializer {
+    fn deserialize(&self, body: &mut BodyReader) -> Result<Json, StatusCode> {
+        body.read_form_data().map(|form| form_to_json(&form)).map_err(|_| StatusCode::BadRequest)
+    }
+}
+
+///The body deserializers that are used by `Context::read_typed`, keyed by
+///content type.
+///
+///The content types are matched without their parameters, and without
+///regard to case, so a deserializer for `application/json` is also used for
+///`Application/JSON; charset=utf-8`.
+pub struct BodyDeserializers {
+    deserializers: HashMap<String, Box<BodyDeserializer>>,
+}
+
+impl BodyDeserializers {
+    ///Create a registry with `JsonDeserializer` for `application/json` and
+    ///`FormDeserializer` for `application/x-www-form-urlencoded`.
+    pub fn new() -> BodyDeserializers {
+        let mut deserializers = BodyDeserializers::empty();
+        deserializers.insert("application/json", JsonDeserializer);
+        deserializers.insert("application/x-www-form-urlencoded", FormDeserializer);
+        deserializers
+    }
+
+    ///Create a registry without any deserializers.
+    pub fn empty() -> BodyDeserializers {
+        BodyDeserializers {
+            deserializers: HashMap::new(),
+        }
+    }
+
+    ///Register `deserializer` for `content_type`, like `application/msgpack`,
+    ///and replace any previous deserializer for it. Any parameters in
+    ///`content_type` are ignored.
+    pub fn insert<D: BodyDeserializer>(&mut self, content_type: &str, deserializer: D) {
+        self.deserializers.insert(essence(content_type), Box::new(deserializer));
+    }
+
+    ///Remove the deserializer for `content_type`, if any.
+    pub fn remove(&mut self, content_type: &str) -> Option<Box<BodyDeserializer>> {
+        self.deserializers.remove(&essence(content_type))
+    }
+
+    ///Get the deserializer for `content_type`, if there is one.
+    pub fn get(&self, content_type: &Mime) -> Option<&BodyDeserializer> {
+        let Mime(ref top, ref sub, _) = *content_type;
+        self.deserializers.get(&format!("{}/{}", top, sub).to_ascii_lowercase()).map(|deserializer| &**deserializer)
+    }
+}
+
+impl Default for BodyDeserializers {
+    fn default() -> BodyDeserializers {
+        BodyDeserializers::new()
+    }
+}
+
+fn essence(content_type: &str) -> String {
+    content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase()
+}
+
+#[doc(hidden)]
+///Internal and may change without warning.
+///
+///Turn form fields into a JSON object of strings and lists of strings. The
+///JSON decoder parses numbers from strings, and missing fields are decoded
+///as `null`.
+pub fn form_to_json(form: &FormData) -> Json {
+    let mut object = BTreeMap::new();
+    for &(ref name, ref value) in form.iter() {
+        let name = name.as_utf8_lossy();
+        let value = Json::String(value.as_utf8_lossy().into_owned());
+
+        if name.ends_with("[]") {
+            let name = name[..name.len() - 2].to_owned();
+            match *object.entry(name).or_insert_with(|| Json::Array(vec![])) {
+                Json::Array(ref mut values) => values.push(value),
+                ref mut single => {
+                    let first = ::std::mem::replace(single, Json::Null);
+                    *single = Json::Array(vec![first, value]);
+                }
+            }
+        } else {
+            match object.get_mut(&*name) {
+                Some(&mut Json::Array(ref mut values)) => {
+                    values.push(value);
+                    continue;
+                },
+                Some(single) => {
+                    let first = ::std::mem::replace(single, Json::Null);
+                    *single = Json::Array(vec![first, value]);
+                    continue;
+                },
+                None => {}
+            }
+            object.insert(name.into_owned(), value);
+        }
+    }
+
+    Json::Object(object)
+}
+
+#[cfg(test)]
+mod test {
+    use rustc_serialize::json::Json;
+
+    use StatusCode;
+    use mime::Mime;
+    use context::body::BodyReader;
+    use super::BodyDeserializers;
+
+    fn nothing(_body: &mut BodyReader) -> Result<Json, StatusCode> {
+        Ok(Json::Null)
+    }
+
+    #[test]
+    fn content_types() {
+        let mut deserializers = BodyDeserializers::new();
+        deserializers.insert("Application/MsgPack; x=y", nothing);
+
+        let msgpack: Mime = "application/msgpack".parse().unwrap();
+        let json: Mime = "Application/JSON; charset=utf-8".parse().unwrap();
+        let cbor: Mime = "application/cbor".parse().unwrap();
+        assert!(deserializers.get(&msgpack).is_some());
+        assert!(deserializers.get(&json).is_some());
+        assert!(deserializers.get(&cbor).is_none());
+
+        assert!(deserializers.remove("application/json").is_some());
+        assert!(deserializers.get(&json).is_none());
+        assert!(BodyDeserializers::empty().get(&msgpack).is_none());
+    }
+}
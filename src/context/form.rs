@@ -0,0 +1,176 @@
+use std::borrow::Cow;
+use std::iter::FromIterator;
+use std::slice;
+use std::str::FromStr;
+use std::vec;
+
+use context::MaybeUtf8Owned;
+use utils::{parse_urlencoded, PercentDecodeError};
+
+///Form fields or query parameters, where each name may have more than one
+///value.
+///
+///`Parameters` keeps one value for each name, so repeated fields, like
+///`tag=a&tag=b`, overwrite each other. `FormData` keeps every field, in the
+///order they were sent.
+///
+///Some clients, like PHP style forms, add `[]` to the names of fields that
+///are meant to be lists, as in `tags[]=a&tags[]=b`. The brackets are kept as
+///part of the name by default, but `group_brackets` removes them, to make
+///`tags[]` and `tags` the same field.
+///
+///```
+///use rustful::context::FormData;
+///
+///let form = FormData::parse(b"tag=a&tags[]=b&tag=c&page=2").unwrap();
+///assert_eq!(form.get_all("tag"), vec!["a", "c"]);
+///assert!(form.get_all("tags").is_empty());
+///
+///let form = form.group_brackets();
+///assert_eq!(form.get_all("tags"), vec!["b"]);
+///assert_eq!(form.get_parsed_all::<_, u8>("page"), vec![Ok(2)]);
+///```
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct FormData {
+    fields: Vec<(MaybeUtf8Owned, MaybeUtf8Owned)>
+}
+
+impl FormData {
+    ///Create an empty `FormData`.
+    pub fn new() -> FormData {
+        FormData::default()
+    }
+
+    ///Parse an `application/x-www-form-urlencoded` byte string, like a query
+    ///string or a form body. See `utils::parse_urlencoded` for the details.
+    pub fn parse(source: &[u8]) -> Result<FormData, PercentDecodeError> {
+        parse_urlencoded(source).collect::<Result<Vec<_>, _>>().map(|fields| FormData { fields: fields })
+    }
+
+    ///Remove a trailing `[]` from each name, so that `tags[]` and `tags` are
+    ///treated as the same field. This has to be asked for, since brackets are
+    ///otherwise a valid part of a name.
+    pub fn group_brackets(mut self) -> FormData {
+        for &mut (ref mut name, _) in &mut self.fields {
+            if name.as_bytes().ends_with(b"[]") {
+                let length = name.len() - 2;
+                let bytes: Vec<u8> = name.clone().into();
+                *name = MaybeUtf8Owned::from(bytes[..length].to_vec());
+            }
+        }
+        self
+    }
+
+    ///Get the first value of a field as a UTF-8 string. A lossy conversion
+    ///will be performed if it's not encoded as UTF-8.
+    pub fn get<'a, K: ?Sized + AsRef<[u8]>>(&'a self, key: &K) -> Option<Cow<'a, str>> {
+        self.get_raw(key).map(|value| value.as_utf8_lossy())
+    }
+
+    ///Get the first value of a field that may or may not be a UTF-8 string.
+    pub fn get_raw<'a, K: ?Sized + AsRef<[u8]>>(&'a self, key: &K) -> Option<&'a MaybeUtf8Owned> {
+        let key = key.as_ref();
+        self.fields.iter().find(|&&(ref name, _)| name.as_bytes() == key).map(|&(_, ref value)| value)
+    }
+
+    ///Get every value of a field, in the order they were sent.
+    pub fn get_all<'a, K: ?Sized + AsRef<[u8]>>(&'a self, key: &K) -> Vec<&'a MaybeUtf8Owned> {
+        let key = key.as_ref();
+        self.fields.iter().filter(|&&(ref name, _)| name.as_bytes() == key).map(|&(_, ref value)| value).collect()
+    }
+
+    ///Try to parse every value of a field as `T`. The values are converted
+    ///to UTF-8 first, like in `get`.
+    pub fn get_parsed_all<K: ?Sized + AsRef<[u8]>, T: FromStr>(&self, key: &K) -> Vec<Result<T, T::Err>> {
+        self.get_all(key).into_iter().map(|value| value.as_utf8_lossy().parse()).collect()
+    }
+
+    ///Returns true if there is at least one field with the given name.
+    pub fn contains_key<K: ?Sized + AsRef<[u8]>>(&self, key: &K) -> bool {
+        self.get_raw(key).is_some()
+    }
+
+    ///Add a field after the existing ones.
+    pub fn insert<K, V>(&mut self, key: K, value: V) where
+        K: Into<MaybeUtf8Owned>, V: Into<MaybeUtf8Owned>
+    {
+        self.fields.push((key.into(), value.into()));
+    }
+
+    ///Get the number of fields, including repeated names.
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    ///Returns true if there are no fields.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    ///Iterate over every field, in the order they were sent.
+    pub fn iter<'a>(&'a self) -> slice::Iter<'a, (MaybeUtf8Owned, MaybeUtf8Owned)> {
+        self.fields.iter()
+    }
+}
+
+impl IntoIterator for FormData {
+    type IntoIter = vec::IntoIter<(MaybeUtf8Owned, MaybeUtf8Owned)>;
+    type Item = (MaybeUtf8Owned, MaybeUtf8Owned);
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.fields.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a FormData {
+    type IntoIter = slice::Iter<'a, (MaybeUtf8Owned, MaybeUtf8Owned)>;
+    type Item = &'a (MaybeUtf8Owned, MaybeUtf8Owned);
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.fields.iter()
+    }
+}
+
+impl<K: Into<MaybeUtf8Owned>, V: Into<MaybeUtf8Owned>> FromIterator<(K, V)> for FormData {
+    fn from_iter<T: IntoIterator<Item=(K, V)>>(iterable: T) -> FormData {
+        FormData {
+            fields: iterable.into_iter().map(|(key, value)| (key.into(), value.into())).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use context::MaybeUtf8Owned;
+    use super::FormData;
+
+    #[test]
+    fn repeated_fields() {
+        let form = FormData::parse(b"tag=a&name=x&tag=b&tag=%FF&age=12&age=old").unwrap();
+        assert_eq!(form.len(), 6);
+        assert_eq!(form.get("tag").unwrap(), "a");
+        assert_eq!(form.get_all("tag"), vec![&MaybeUtf8Owned::from("a"), &"b".into(), &vec![255].into()]);
+        assert_eq!(form.get_all("name"), vec!["x"]);
+        assert!(form.get_all("missing").is_empty());
+
+        let ages = form.get_parsed_all::<_, u8>("age");
+        assert_eq!(ages.len(), 2);
+        assert_eq!(ages[0], Ok(12));
+        assert!(ages[1].is_err());
+
+        assert!(FormData::parse(b"a=1&b=%G0").is_err());
+    }
+
+    #[test]
+    fn bracket_names() {
+        let form = FormData::parse(b"tags[]=a&tags=b&tags%5B%5D=c&x[y]=d&[]=e").unwrap();
+        assert_eq!(form.get_all("tags[]"), vec!["a", "c"]);
+        assert_eq!(form.get_all("tags"), vec!["b"]);
+
+        let form = form.group_brackets();
+        assert_eq!(form.get_all("tags"), vec!["a", "b", "c"]);
+        assert!(form.get_all("tags[]").is_empty());
+        assert_eq!(form.get_all("x[y]"), vec!["d"]);
+        assert_eq!(form.get_all(""), vec!["e"]);
+    }
+}
@@ -0,0 +1,483 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str;
+
+use header::Headers;
+
+//Like `try!`, but for `Option`.
+macro_rules! try_opt {
+    ($e: expr) => (match $e {
+        Some(value) => value,
+        None => return None
+    })
+}
+
+///One element of a `Forwarded` header, as defined in RFC 7239.
+///
+///Each proxy that a request passes through may add an element, so the last
+///element comes from the proxy that is closest to the server. Like with
+///`X-Forwarded-For`, only the elements that were added by trusted proxies
+///can be relied on, since the client can send anything.
+///
+///An element can also be built, to be added to a request that is forwarded
+///by the server itself:
+///
+///```
+///use rustful::header::Headers;
+///use rustful::context::{ForwardedElement, Node};
+///
+///let mut headers = Headers::new();
+///headers.set_raw("Forwarded", vec![b"for=192.0.2.60".to_vec()]);
+///
+///ForwardedElement::new()
+///    .for_node(Node::from("2001:db8::17".parse::<std::net::IpAddr>().unwrap()))
+///    .proto("https")
+///    .append_to(&mut headers);
+///
+///assert_eq!(
+///    headers.get_raw("Forwarded"),
+///    Some(&[b"for=192.0.2.60".to_vec(), b"for=\"[2001:db8::17]\";proto=https".to_vec()][..])
+///);
+///```
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct ForwardedElement {
+    ///The client that made the request to the proxy, from the `for`
+    ///parameter.
+    pub for_node: Option<Node>,
+
+    ///The interface where the proxy received the request, from the `by`
+    ///parameter.
+    pub by: Option<Node>,
+
+    ///The original `Host` header, from the `host` parameter.
+    pub host: Option<String>,
+
+    ///The scheme of the original request, like `http` or `https`, from the
+    ///`proto` parameter.
+    pub proto: Option<String>,
+
+    ///Any other parameters, as name and value pairs.
+    pub extensions: Vec<(String, String)>,
+}
+
+impl ForwardedElement {
+    ///Create an empty element.
+    pub fn new() -> ForwardedElement {
+        ForwardedElement::default()
+    }
+
+    ///Set the `for` parameter.
+    pub fn for_node(mut self, node: Node) -> ForwardedElement {
+        self.for_node = Some(node);
+        self
+    }
+
+    ///Set the `by` parameter.
+    pub fn by(mut self, node: Node) -> ForwardedElement {
+        self.by = Some(node);
+        self
+    }
+
+    ///Set the `host` parameter.
+    pub fn host<S: Into<String>>(mut self, host: S) -> ForwardedElement {
+        self.host = Some(host.into());
+        self
+    }
+
+    ///Set the `proto` parameter.
+    pub fn proto<S: Into<String>>(mut self, proto: S) -> ForwardedElement {
+        self.proto = Some(proto.into());
+        self
+    }
+
+    ///Add this element after any existing `Forwarded` values in `headers`.
+    pub fn append_to(&self, headers: &mut Headers) {
+        let mut values = headers.get_raw("Forwarded").map_or_else(Vec::new, |values| values.to_vec());
+        values.push(self.to_string().into_bytes());
+        headers.set_raw("Forwarded", values);
+    }
+
+    ///Parse the values of one or more `Forwarded` headers, in order. `None`
+    ///is returned if any of them is malformed, since it's then impossible to
+    ///tell which element came from which proxy. Parameter names are case
+    ///insensitive, and values may be quoted.
+    ///
+    ///```
+    ///use rustful::context::{ForwardedElement, Node, NodeName, NodePort};
+    ///
+    ///let elements = ForwardedElement::parse(&[b"for=\"[2001:db8:cafe::17]:4711\", for=unknown;proto=http".to_vec()]).unwrap();
+    ///assert_eq!(elements.len(), 2);
+    ///assert_eq!(elements[0].for_node, Some(Node {
+    ///    name: NodeName::Ip("2001:db8:cafe::17".parse().unwrap()),
+    ///    port: Some(NodePort::Number(4711)),
+    ///}));
+    ///assert_eq!(elements[1].for_node.as_ref().map(|node| &node.name), Some(&NodeName::Unknown));
+    ///assert_eq!(elements[1].proto, Some("http".into()));
+    ///```
+    pub fn parse(values: &[Vec<u8>]) -> Option<Vec<ForwardedElement>> {
+        let mut elements = vec![];
+        for value in values {
+            let mut parser = Parser {
+                input: value,
+                position: 0
+            };
+            elements.extend(try_opt!(parser.elements()));
+        }
+        Some(elements)
+    }
+
+    fn set(&mut self, name: &str, value: String) -> Option<()> {
+        let name = name.to_ascii_lowercase();
+        let duplicate = match &*name {
+            "for" => self.for_node.is_some(),
+            "by" => self.by.is_some(),
+            "host" => self.host.is_some(),
+            "proto" => self.proto.is_some(),
+            _ => self.extensions.iter().any(|&(ref other, _)| other.eq_ignore_ascii_case(&name))
+        };
+
+        if duplicate {
+            return None;
+        }
+
+        match &*name {
+            "for" => self.for_node = Some(try_opt!(Node::parse(&value))),
+            "by" => self.by = Some(try_opt!(Node::parse(&value))),
+            "host" => self.host = Some(value),
+            "proto" => self.proto = Some(value),
+            _ => self.extensions.push((name, value))
+        }
+
+        Some(())
+    }
+}
+
+impl fmt::Display for ForwardedElement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut pairs = vec![];
+        if let Some(ref node) = self.for_node {
+            pairs.push(("for", node.to_string()));
+        }
+        if let Some(ref node) = self.by {
+            pairs.push(("by", node.to_string()));
+        }
+        if let Some(ref host) = self.host {
+            pairs.push(("host", host.clone()));
+        }
+        if let Some(ref proto) = self.proto {
+            pairs.push(("proto", proto.clone()));
+        }
+        for &(ref name, ref value) in &self.extensions {
+            pairs.push((name, value.clone()));
+        }
+
+        for (index, (name, value)) in pairs.into_iter().enumerate() {
+            if index > 0 {
+                try!(f.write_str(";"));
+            }
+
+            if !value.is_empty() && value.bytes().all(is_tchar) {
+                try!(write!(f, "{}={}", name, value));
+            } else {
+                try!(write!(f, "{}=\"{}\"", name, value.replace('\\', "\\\\").replace('"', "\\\"")));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+///A node in a `for` or `by` parameter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Node {
+    ///The address or identifier of the node.
+    pub name: NodeName,
+
+    ///The port of the node, if it's included.
+    pub port: Option<NodePort>,
+}
+
+impl Node {
+    ///Get the IP address of the node, if it's known.
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self.name {
+            NodeName::Ip(ip) => Some(ip),
+            _ => None
+        }
+    }
+
+    fn parse(value: &str) -> Option<Node> {
+        let (name, port) = if value.starts_with('[') {
+            let end = try_opt!(value.find(']'));
+            let ip: Ipv6Addr = try_opt!(value[1..end].parse().ok());
+            (NodeName::Ip(IpAddr::V6(ip)), &value[end + 1..])
+        } else {
+            let (name, port) = value.split_at(value.find(':').unwrap_or(value.len()));
+            let name = if name.eq_ignore_ascii_case("unknown") {
+                NodeName::Unknown
+            } else if is_obfuscated(name) {
+                NodeName::Obfuscated(name.to_owned())
+            } else {
+                let ip: Ipv4Addr = try_opt!(name.parse().ok());
+                NodeName::Ip(IpAddr::V4(ip))
+            };
+            (name, port)
+        };
+
+        let port = match port {
+            "" => None,
+            port if port.starts_with(':') => {
+                let port = &port[1..];
+                if is_obfuscated(port) {
+                    Some(NodePort::Obfuscated(port.to_owned()))
+                } else if !port.is_empty() && port.bytes().all(|byte| byte.is_ascii_digit()) {
+                    Some(NodePort::Number(try_opt!(port.parse().ok())))
+                } else {
+                    return None;
+                }
+            },
+            _ => return None
+        };
+
+        Some(Node {
+            name: name,
+            port: port
+        })
+    }
+}
+
+impl From<IpAddr> for Node {
+    fn from(ip: IpAddr) -> Node {
+        Node {
+            name: NodeName::Ip(ip),
+            port: None
+        }
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name {
+            NodeName::Ip(IpAddr::V6(ip)) => try!(write!(f, "[{}]", ip)),
+            NodeName::Ip(IpAddr::V4(ip)) => try!(write!(f, "{}", ip)),
+            NodeName::Unknown => try!(f.write_str("unknown")),
+            NodeName::Obfuscated(ref name) => try!(f.write_str(name))
+        }
+
+        match self.port {
+            Some(NodePort::Number(port)) => write!(f, ":{}", port),
+            Some(NodePort::Obfuscated(ref port)) => write!(f, ":{}", port),
+            None => Ok(())
+        }
+    }
+}
+
+///The address or identifier of a `Node`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NodeName {
+    ///An IPv4 or IPv6 address.
+    Ip(IpAddr),
+
+    ///The node is not known, or hidden, as in `for=unknown`.
+    Unknown,
+
+    ///An obfuscated identifier, starting with `_`.
+    Obfuscated(String),
+}
+
+///The port of a `Node`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NodePort {
+    ///A port number.
+    Number(u16),
+
+    ///An obfuscated identifier, starting with `_`.
+    Obfuscated(String),
+}
+
+fn is_obfuscated(value: &str) -> bool {
+    value.len() > 1 && value.starts_with('_') && value.bytes().all(|byte| match byte {
+        b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'.' | b'_' | b'-' => true,
+        _ => false
+    })
+}
+
+fn is_tchar(byte: u8) -> bool {
+    match byte {
+        b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => true,
+        b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => true,
+        _ => false
+    }
+}
+
+//Parses comma separated elements of semicolon separated pairs.
+struct Parser<'a> {
+    input: &'a [u8],
+    position: usize
+}
+
+impl<'a> Parser<'a> {
+    fn elements(&mut self) -> Option<Vec<ForwardedElement>> {
+        let mut elements = vec![];
+        let mut element = ForwardedElement::new();
+        let mut empty = true;
+
+        loop {
+            self.skip_whitespace();
+
+            //Empty list elements are allowed, as in `a=b, , c=d`.
+            if empty && self.eat(b',') {
+                continue;
+            }
+
+            if self.position == self.input.len() {
+                break;
+            }
+
+            let name = try_opt!(self.token());
+            if !self.eat(b'=') {
+                return None;
+            }
+            let value = try_opt!(self.value());
+            try_opt!(element.set(name, value));
+            empty = false;
+
+            self.skip_whitespace();
+            if self.eat(b';') {
+                continue;
+            } else if self.eat(b',') || self.position == self.input.len() {
+                elements.push(::std::mem::replace(&mut element, ForwardedElement::new()));
+                empty = true;
+            } else {
+                return None;
+            }
+        }
+
+        Some(elements)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.position < self.input.len() && (self.input[self.position] == b' ' || self.input[self.position] == b'\t') {
+            self.position += 1;
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        if self.input.get(self.position) == Some(&byte) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn token(&mut self) -> Option<&'a str> {
+        let start = self.position;
+        while self.position < self.input.len() && is_tchar(self.input[self.position]) {
+            self.position += 1;
+        }
+
+        if self.position > start {
+            str::from_utf8(&self.input[start..self.position]).ok()
+        } else {
+            None
+        }
+    }
+
+    fn value(&mut self) -> Option<String> {
+        if !self.eat(b'"') {
+            return self.token().map(|token| token.to_owned());
+        }
+
+        let mut value = vec![];
+        loop {
+            match self.input.get(self.position).cloned() {
+                Some(b'"') => {
+                    self.position += 1;
+                    return String::from_utf8(value).ok();
+                },
+                Some(b'\\') => {
+                    value.push(*try_opt!(self.input.get(self.position + 1)));
+                    self.position += 2;
+                },
+                Some(byte) => {
+                    value.push(byte);
+                    self.position += 1;
+                },
+                None => return None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+
+    use super::{ForwardedElement, Node, NodeName, NodePort};
+
+    fn parse(value: &str) -> Option<Vec<ForwardedElement>> {
+        ForwardedElement::parse(&[value.as_bytes().to_vec()])
+    }
+
+    #[test]
+    fn quoted_ipv6() {
+        let elements = parse("For=\"[2001:db8:cafe::17]:4711\";by=\"[::1]\"").unwrap();
+        assert_eq!(elements.len(), 1);
+        assert_eq!(elements[0].for_node, Some(Node {
+            name: NodeName::Ip("2001:db8:cafe::17".parse().unwrap()),
+            port: Some(NodePort::Number(4711)),
+        }));
+        assert_eq!(elements[0].by.as_ref().and_then(Node::ip), Some("::1".parse().unwrap()));
+
+        assert_eq!(parse("for=\"[2001:db8:cafe::17\""), None);
+        assert_eq!(parse("for=\"[2001:db8:cafe::17]4711\""), None);
+        assert_eq!(parse("for=[2001:db8:cafe::17]"), None);
+        assert_eq!(parse("for=192.0.2.43:80"), None);
+    }
+
+    #[test]
+    fn several_elements() {
+        let elements = parse("for=192.0.2.43, for=\"198.51.100.17:80\";proto=https;host=\"example.com\",,for=\"_hidden:_port\"").unwrap();
+        assert_eq!(elements.len(), 3);
+        assert_eq!(elements[0].for_node.as_ref().and_then(Node::ip), Some("192.0.2.43".parse().unwrap()));
+        assert_eq!(elements[1].for_node.as_ref().and_then(|node| node.port.clone()), Some(NodePort::Number(80)));
+        assert_eq!(elements[1].proto, Some("https".into()));
+        assert_eq!(elements[1].host, Some("example.com".into()));
+        assert_eq!(elements[2].for_node, Some(Node {
+            name: NodeName::Obfuscated("_hidden".into()),
+            port: Some(NodePort::Obfuscated("_port".into())),
+        }));
+
+        let elements = ForwardedElement::parse(&[b"for=unknown".to_vec(), b"for=10.0.0.1;secret=\"a \\\"b\\\"\"".to_vec()]).unwrap();
+        assert_eq!(elements.len(), 2);
+        assert_eq!(elements[1].extensions, vec![("secret".to_owned(), "a \"b\"".to_owned())]);
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(parse("for"), None);
+        assert_eq!(parse("for=1.2.3.4;for=5.6.7.8"), None);
+        assert_eq!(parse("for=1.2.3.4 proto=http"), None);
+        assert_eq!(parse("for=\"1.2.3.4"), None);
+        assert_eq!(parse("for=example.com"), None);
+        assert_eq!(parse(""), Some(vec![]));
+    }
+
+    #[test]
+    fn build() {
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        let element = ForwardedElement::new()
+            .for_node(Node {
+                name: NodeName::Ip(ip),
+                port: Some(NodePort::Number(8080)),
+            })
+            .by(Node::from("203.0.113.1".parse::<IpAddr>().unwrap()))
+            .host("example.com:8080")
+            .proto("https");
+
+        let formatted = element.to_string();
+        assert_eq!(formatted, "for=\"[2001:db8::1]:8080\";by=203.0.113.1;host=\"example.com:8080\";proto=https");
+        assert_eq!(parse(&formatted), Some(vec![element]));
+    }
+}
@@ -0,0 +1,182 @@
+use std::fmt;
+
+use header::{self, Headers, HeaderView, HeadersItems};
+
+///An iterator over the request headers, as returned by
+///`Context::headers_iter`.
+pub struct HeadersIter<'a> {
+    headers: &'a Headers,
+    items: HeadersItems<'a>,
+}
+
+impl<'a> HeadersIter<'a> {
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn new(headers: &'a Headers) -> HeadersIter<'a> {
+        HeadersIter {
+            headers: headers,
+            items: headers.iter(),
+        }
+    }
+}
+
+impl<'a> Iterator for HeadersIter<'a> {
+    type Item = HeaderEntry<'a>;
+
+    fn next(&mut self) -> Option<HeaderEntry<'a>> {
+        let headers = self.headers;
+        self.items.next().map(|view| HeaderEntry {
+            headers: headers,
+            view: view,
+        })
+    }
+}
+
+///A request header, with its raw values and, if it's a well-known header, a
+///typed representation.
+pub struct HeaderEntry<'a> {
+    headers: &'a Headers,
+    view: HeaderView<'a>,
+}
+
+impl<'a> HeaderEntry<'a> {
+    ///The name of the header, as it was received.
+    pub fn name(&self) -> &'a str {
+        self.view.name()
+    }
+
+    ///The raw values of the header, with one value for each time it appears
+    ///in the request.
+    pub fn raw(&self) -> &'a [Vec<u8>] {
+        self.headers.get_raw(self.view.name()).unwrap_or(&[])
+    }
+
+    ///Parse the header as `H`, if it has the name of `H`. `None` is returned
+    ///if it's another header, or if it's malformed. The parsed value is
+    ///cached in the headers, so it's only parsed once.
+    pub fn get<H: header::Header + header::HeaderFormat>(&self) -> Option<&'a H> {
+        if self.view.is::<H>() {
+            self.view.value::<H>()
+        } else {
+            None
+        }
+    }
+
+    ///Parse the header if it's one of the headers in `KnownHeader`. `None` is
+    ///returned for any other header, or if it's malformed.
+    pub fn known(&self) -> Option<KnownHeader<'a>> {
+        macro_rules! known {
+            ($($name: ident),*) => {
+                $(
+                    if self.view.is::<header::$name>() {
+                        return self.get::<header::$name>().map(KnownHeader::$name);
+                    }
+                )*
+            }
+        }
+
+        known!(
+            Accept, AcceptCharset, AcceptEncoding, AcceptLanguage, CacheControl,
+            Connection, ContentLength, ContentType, Cookie, Expect, Host, IfMatch,
+            IfModifiedSince, IfNoneMatch, IfUnmodifiedSince, Range, Referer,
+            TransferEncoding, UserAgent
+        );
+
+        None
+    }
+}
+
+impl<'a> fmt::Debug for HeaderEntry<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HeaderEntry")
+            .field("name", &self.name())
+            .field("raw", &self.raw())
+            .finish()
+    }
+}
+
+///A parsed, well-known, request header.
+#[derive(Clone, Copy, Debug)]
+pub enum KnownHeader<'a> {
+    ///`Accept`.
+    Accept(&'a header::Accept),
+    ///`Accept-Charset`.
+    AcceptCharset(&'a header::AcceptCharset),
+    ///`Accept-Encoding`.
+    AcceptEncoding(&'a header::AcceptEncoding),
+    ///`Accept-Language`.
+    AcceptLanguage(&'a header::AcceptLanguage),
+    ///`Cache-Control`.
+    CacheControl(&'a header::CacheControl),
+    ///`Connection`.
+    Connection(&'a header::Connection),
+    ///`Content-Length`.
+    ContentLength(&'a header::ContentLength),
+    ///`Content-Type`.
+    ContentType(&'a header::ContentType),
+    ///`Cookie`.
+    Cookie(&'a header::Cookie),
+    ///`Expect`.
+    Expect(&'a header::Expect),
+    ///`Host`.
+    Host(&'a header::Host),
+    ///`If-Match`.
+    IfMatch(&'a header::IfMatch),
+    ///`If-Modified-Since`.
+    IfModifiedSince(&'a header::IfModifiedSince),
+    ///`If-None-Match`.
+    IfNoneMatch(&'a header::IfNoneMatch),
+    ///`If-Unmodified-Since`.
+    IfUnmodifiedSince(&'a header::IfUnmodifiedSince),
+    ///`Range`.
+    Range(&'a header::Range),
+    ///`Referer`.
+    Referer(&'a header::Referer),
+    ///`Transfer-Encoding`.
+    TransferEncoding(&'a header::TransferEncoding),
+    ///`User-Agent`.
+    UserAgent(&'a header::UserAgent),
+}
+
+#[cfg(test)]
+mod test {
+    use header::{Headers, ContentLength};
+    use super::{HeadersIter, KnownHeader};
+
+    #[test]
+    fn known_and_custom_headers() {
+        let mut headers = Headers::new();
+        headers.set_raw("Content-Length", vec![b"42".to_vec()]);
+        headers.set_raw("Host", vec![b"example.com:8080".to_vec()]);
+        headers.set_raw("If-Modified-Since", vec![b"not a date".to_vec()]);
+        headers.set_raw("X-Custom", vec![b"a".to_vec(), b"b".to_vec()]);
+
+        let mut entries: Vec<_> = HeadersIter::new(&headers).collect();
+        entries.sort_by_key(|entry| entry.name().to_owned());
+        let names: Vec<_> = entries.iter().map(|entry| entry.name()).collect();
+        assert_eq!(names, ["Content-Length", "Host", "If-Modified-Since", "X-Custom"]);
+
+        match entries[0].known() {
+            Some(KnownHeader::ContentLength(&ContentLength(42))) => {},
+            other => panic!("unexpected content length: {:?}", other)
+        }
+        assert_eq!(entries[0].get::<ContentLength>(), Some(&ContentLength(42)));
+        assert_eq!(entries[0].raw(), &[b"42".to_vec()][..]);
+
+        match entries[1].known() {
+            Some(KnownHeader::Host(host)) => {
+                assert_eq!(host.hostname, "example.com");
+                assert_eq!(host.port, Some(8080));
+            },
+            other => panic!("unexpected host: {:?}", other)
+        }
+        assert_eq!(entries[1].get::<ContentLength>(), None);
+
+        //A malformed header doesn't stop the iteration.
+        assert!(entries[2].known().is_none());
+        assert_eq!(entries[2].raw(), &[b"not a date".to_vec()][..]);
+
+        assert!(entries[3].known().is_none());
+        assert_eq!(entries[3].raw(), &[b"a".to_vec(), b"b".to_vec()][..]);
+    }
+}
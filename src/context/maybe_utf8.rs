@@ -1,6 +1,8 @@
 use std::ops::{Deref, DerefMut, Drop};
 use std::borrow::{Cow, Borrow};
 use std::hash::{Hash, Hasher};
+use std::fmt;
+use std::io;
 
 use ::utils::BytesExt;
 
@@ -39,6 +41,26 @@ impl<S, V> MaybeUtf8<S, V> {
         }
     }
 
+    ///Copy the content into a new owned string, while keeping the variant.
+    ///This works for both owned strings and slices, and `into_owned` can be
+    ///used to convert a slice when it's no longer needed.
+    ///
+    ///```
+    ///use rustful::context::{MaybeUtf8Owned, MaybeUtf8Slice};
+    ///
+    ///let bytes = [b'a', 255];
+    ///let slice = MaybeUtf8Slice::NotUtf8(&bytes);
+    ///let owned: MaybeUtf8Owned = slice.clone_owned();
+    ///assert_eq!(owned, slice);
+    ///assert!(!owned.is_utf8());
+    ///```
+    pub fn clone_owned(&self) -> MaybeUtf8Owned where S: AsRef<str>, V: AsRef<[u8]> {
+        match *self {
+            MaybeUtf8::Utf8(ref s) => MaybeUtf8::Utf8(s.as_ref().to_owned()),
+            MaybeUtf8::NotUtf8(ref v) => MaybeUtf8::NotUtf8(v.as_ref().to_owned())
+        }
+    }
+
     ///Borrow the string if it's encoded as valid UTF-8.
     ///
     ///```
@@ -54,6 +76,45 @@ impl<S, V> MaybeUtf8<S, V> {
         }
     }
 
+    ///Borrow the string if it's encoded as valid UTF-8, or get the raw bytes
+    ///if it's not.
+    ///
+    ///```
+    ///use rustful::context::MaybeUtf8Owned;
+    ///
+    ///let valid = MaybeUtf8Owned::from("abc");
+    ///assert_eq!(valid.try_as_str(), Ok("abc"));
+    ///
+    ///let invalid = MaybeUtf8Owned::from(vec![97, 255]);
+    ///assert_eq!(invalid.try_as_str(), Err(&[97, 255][..]));
+    ///```
+    pub fn try_as_str(&self) -> Result<&str, &[u8]> where S: AsRef<str>, V: AsRef<[u8]> {
+        match *self {
+            MaybeUtf8::Utf8(ref s) => Ok(s.as_ref()),
+            MaybeUtf8::NotUtf8(ref v) => Err(v.as_ref())
+        }
+    }
+
+    ///Convert the string into a UTF-8 string if it's encoded as valid UTF-8,
+    ///or get the original bytes back if it's not. Unlike `Into<String>`, this
+    ///never makes a lossy conversion.
+    ///
+    ///```
+    ///use rustful::context::MaybeUtf8Owned;
+    ///
+    ///let valid = MaybeUtf8Owned::from("abc");
+    ///assert_eq!(valid.into_utf8(), Ok("abc".to_owned()));
+    ///
+    ///let invalid = MaybeUtf8Owned::from(vec![97, 255]);
+    ///assert_eq!(invalid.into_utf8(), Err(vec![97, 255]));
+    ///```
+    pub fn into_utf8(self) -> Result<S, V> {
+        match self {
+            MaybeUtf8::Utf8(s) => Ok(s),
+            MaybeUtf8::NotUtf8(v) => Err(v)
+        }
+    }
+
     ///Borrow the string if it's encoded as valid UTF-8, or make a lossy conversion.
     ///
     ///```
@@ -84,6 +145,146 @@ impl<S, V> MaybeUtf8<S, V> {
         }
     }
 
+    ///Find the position of the first occurrence of `needle`, in bytes. An
+    ///empty `needle` is found at position 0.
+    ///
+    ///```
+    ///use rustful::context::MaybeUtf8Owned;
+    ///
+    ///let string = MaybeUtf8Owned::from("a/b/c");
+    ///assert_eq!(string.find(b"/"), Some(1));
+    ///assert_eq!(string.find(b"d"), None);
+    ///
+    ///let invalid = MaybeUtf8Owned::from(vec![255, b'/', 254]);
+    ///assert_eq!(invalid.find(&[254]), Some(2));
+    ///```
+    pub fn find(&self, needle: &[u8]) -> Option<usize> where S: AsRef<[u8]>, V: AsRef<[u8]> {
+        let bytes = self.as_bytes();
+        if needle.is_empty() {
+            return Some(0);
+        }
+
+        bytes.windows(needle.len()).position(|window| window == needle)
+    }
+
+    ///Check if the string contains `needle`.
+    ///
+    ///```
+    ///use rustful::context::MaybeUtf8Owned;
+    ///
+    ///let string = MaybeUtf8Owned::from("../etc");
+    ///assert!(string.contains(b".."));
+    ///
+    ///let invalid = MaybeUtf8Owned::from(vec![255, b'.', b'.']);
+    ///assert!(invalid.contains(b".."));
+    ///assert!(!invalid.contains(b"/"));
+    ///```
+    pub fn contains(&self, needle: &[u8]) -> bool where S: AsRef<[u8]>, V: AsRef<[u8]> {
+        self.find(needle).is_some()
+    }
+
+    ///Replace every non-overlapping occurrence of `from` with `to`, from
+    ///left to right, and return the result as a new string. The replacement
+    ///is done byte by byte, and the result is UTF-8 encoded if it's valid
+    ///UTF-8, regardless of the input. Nothing is replaced if `from` is
+    ///empty.
+    ///
+    ///```
+    ///use rustful::context::MaybeUtf8Owned;
+    ///
+    ///let string = MaybeUtf8Owned::from("a b c");
+    ///assert_eq!(string.replace(b" ", b"%20"), "a%20b%20c");
+    ///
+    ///let invalid = MaybeUtf8Owned::from(vec![b'a', 255, b'b']);
+    ///let replaced = invalid.replace(&[255], "é".as_bytes());
+    ///assert_eq!(replaced.as_utf8(), Some("aéb"));
+    ///```
+    pub fn replace(&self, from: &[u8], to: &[u8]) -> MaybeUtf8Owned where S: AsRef<[u8]>, V: AsRef<[u8]> {
+        let bytes = self.as_bytes();
+        if from.is_empty() {
+            return bytes.to_vec().into();
+        }
+
+        let mut result = Vec::with_capacity(bytes.len());
+        let mut rest = bytes;
+
+        while rest.len() >= from.len() {
+            if rest.starts_with(from) {
+                result.extend_from_slice(to);
+                rest = &rest[from.len()..];
+            } else {
+                result.push(rest[0]);
+                rest = &rest[1..];
+            }
+        }
+        result.extend_from_slice(rest);
+
+        result.into()
+    }
+
+    ///Copy the string, with every ASCII letter converted to lower case.
+    ///Other bytes, including non-ASCII characters, are left as they are, so
+    ///the variant is kept. This is meant for protocol tokens, like header
+    ///names, where Unicode case rules don't apply.
+    ///
+    ///```
+    ///use rustful::context::{MaybeUtf8Owned, MaybeUtf8Slice};
+    ///
+    ///let string = MaybeUtf8Slice::from("Content-Type: ÅÄÖ");
+    ///assert_eq!(string.to_ascii_lowercase(), "content-type: ÅÄÖ");
+    ///
+    ///let invalid = MaybeUtf8Owned::from(vec![b'A', 255, b'b', 0xC4]);
+    ///let lower = invalid.to_ascii_lowercase();
+    ///assert_eq!(lower, &[b'a', 255, b'b', 0xC4][..]);
+    ///assert!(!lower.is_utf8());
+    ///```
+    pub fn to_ascii_lowercase(&self) -> MaybeUtf8Owned where S: AsRef<str>, V: AsRef<[u8]> {
+        match *self {
+            MaybeUtf8::Utf8(ref s) => MaybeUtf8::Utf8(s.as_ref().to_ascii_lowercase()),
+            MaybeUtf8::NotUtf8(ref v) => MaybeUtf8::NotUtf8(v.as_ref().to_ascii_lowercase())
+        }
+    }
+
+    ///Copy the string, with every ASCII letter converted to upper case. See
+    ///`to_ascii_lowercase` for more details.
+    ///
+    ///```
+    ///use rustful::context::{MaybeUtf8Owned, MaybeUtf8Slice};
+    ///
+    ///let string = MaybeUtf8Slice::from("gzip, brötli");
+    ///assert_eq!(string.to_ascii_uppercase(), "GZIP, BRöTLI");
+    ///
+    ///let invalid = MaybeUtf8Owned::from(vec![b'a', 255, b'B', 0xE4]);
+    ///let upper = invalid.to_ascii_uppercase();
+    ///assert_eq!(upper, &[b'A', 255, b'B', 0xE4][..]);
+    ///assert!(!upper.is_utf8());
+    ///```
+    pub fn to_ascii_uppercase(&self) -> MaybeUtf8Owned where S: AsRef<str>, V: AsRef<[u8]> {
+        match *self {
+            MaybeUtf8::Utf8(ref s) => MaybeUtf8::Utf8(s.as_ref().to_ascii_uppercase()),
+            MaybeUtf8::NotUtf8(ref v) => MaybeUtf8::NotUtf8(v.as_ref().to_ascii_uppercase())
+        }
+    }
+
+    ///Check if the string is equal to `other`, without regard to the case of
+    ///ASCII letters. Other bytes have to be exactly equal, as in
+    ///`to_ascii_lowercase`, and nothing is allocated.
+    ///
+    ///```
+    ///use rustful::context::{MaybeUtf8Owned, MaybeUtf8Slice};
+    ///
+    ///let name = MaybeUtf8Slice::from("Content-Type");
+    ///assert!(name.eq_ignore_ascii_case(b"content-type"));
+    ///assert!(name.eq_ignore_ascii_case(b"CONTENT-TYPE"));
+    ///assert!(!name.eq_ignore_ascii_case(b"content-length"));
+    ///
+    ///let invalid = MaybeUtf8Owned::from(vec![b'A', 255]);
+    ///assert!(invalid.eq_ignore_ascii_case(&[b'a', 255]));
+    ///```
+    pub fn eq_ignore_ascii_case(&self, other: &[u8]) -> bool where S: AsRef<[u8]>, V: AsRef<[u8]> {
+        self.as_ref().eq_ignore_ascii_case(other)
+    }
+
     ///Check if the string is valid UTF-8.
     ///
     ///```
@@ -103,7 +304,81 @@ impl<S, V> MaybeUtf8<S, V> {
     }
 }
 
+impl<'a> MaybeUtf8<&'a str, &'a [u8]> {
+    ///Copy the borrowed content into an owned string, with the same variant,
+    ///so it can be kept after what it borrows from is gone.
+    ///
+    ///```
+    ///use rustful::context::{MaybeUtf8Owned, MaybeUtf8Slice};
+    ///
+    ///let valid = MaybeUtf8Slice::Utf8("abc");
+    ///let owned: MaybeUtf8Owned = valid.clone().into_owned();
+    ///assert_eq!(owned.as_utf8(), Some("abc"));
+    ///assert_eq!(owned, valid);
+    ///
+    ///let bytes = vec![b'a', 255];
+    ///let invalid = MaybeUtf8Slice::NotUtf8(&bytes);
+    ///let owned = invalid.clone().into_owned();
+    ///assert_eq!(owned.as_utf8(), None);
+    ///assert_eq!(owned, invalid);
+    ///```
+    pub fn into_owned(self) -> MaybeUtf8Owned {
+        match self {
+            MaybeUtf8::Utf8(s) => MaybeUtf8::Utf8(s.to_owned()),
+            MaybeUtf8::NotUtf8(v) => MaybeUtf8::NotUtf8(v.to_owned())
+        }
+    }
+}
+
 impl MaybeUtf8<String, Vec<u8>> {
+    ///Create a UTF-8 string from a `String`. This is the same as
+    ///`MaybeUtf8Owned::from(string)`.
+    ///
+    ///```
+    ///use rustful::context::MaybeUtf8Owned;
+    ///
+    ///let string = MaybeUtf8Owned::from_utf8("abc".to_owned());
+    ///assert_eq!(string.as_utf8(), Some("abc"));
+    ///```
+    pub fn from_utf8(string: String) -> MaybeUtf8Owned {
+        MaybeUtf8::Utf8(string)
+    }
+
+    ///Create a string from a byte vector. The result will be UTF-8 encoded
+    ///if `bytes` is valid UTF-8. This is the same as
+    ///`MaybeUtf8Owned::from(bytes)`.
+    ///
+    ///```
+    ///use rustful::context::MaybeUtf8Owned;
+    ///
+    ///assert!(MaybeUtf8Owned::from_bytes(b"abc".to_vec()).is_utf8());
+    ///assert!(!MaybeUtf8Owned::from_bytes(vec![255]).is_utf8());
+    ///```
+    pub fn from_bytes(bytes: Vec<u8>) -> MaybeUtf8Owned {
+        match String::from_utf8(bytes) {
+            Ok(string) => MaybeUtf8::Utf8(string),
+            Err(e) => MaybeUtf8::NotUtf8(e.into_bytes())
+        }
+    }
+
+    ///Create a non-UTF-8 string from a byte vector, without validating it.
+    ///
+    ///The caller is expected to know that `bytes` is not valid UTF-8. This is
+    ///not a memory safety issue, but valid UTF-8 will still be treated as
+    ///non-UTF-8, so `as_utf8` will return `None` and `is_utf8` will return
+    ///`false` until the string is modified.
+    ///
+    ///```
+    ///use rustful::context::MaybeUtf8Owned;
+    ///
+    ///let string = MaybeUtf8Owned::from_bytes_unchecked(vec![255, 254]);
+    ///assert!(!string.is_utf8());
+    ///assert_eq!(&[255, 254][..], string);
+    ///```
+    pub fn from_bytes_unchecked(bytes: Vec<u8>) -> MaybeUtf8Owned {
+        MaybeUtf8::NotUtf8(bytes)
+    }
+
     ///Push a single `char` to the end of the string.
     ///
     ///```
@@ -174,6 +449,77 @@ impl MaybeUtf8<String, Vec<u8>> {
         }
     }
 
+    ///Join a number of strings, with `separator` between each of them. The
+    ///result will be UTF-8 encoded if it's valid UTF-8.
+    ///
+    ///```
+    ///use rustful::context::{MaybeUtf8Owned, MaybeUtf8Slice};
+    ///
+    ///let parts = vec![MaybeUtf8Slice::from("path"), MaybeUtf8Slice::from("to"), MaybeUtf8Slice::from("file")];
+    ///let path = MaybeUtf8Owned::join(b'/', parts);
+    ///assert_eq!("path/to/file", path);
+    ///assert!(path.is_utf8());
+    ///
+    ///let parts = vec![MaybeUtf8Owned::from("a"), MaybeUtf8Owned::from(vec![255])];
+    ///let joined = MaybeUtf8Owned::join(b' ', &parts);
+    ///assert_eq!(&b"a \xff"[..], joined);
+    ///assert!(!joined.is_utf8());
+    ///```
+    pub fn join<I, P>(separator: u8, parts: I) -> MaybeUtf8Owned where
+        I: IntoIterator<Item = P>,
+        P: AsRef<[u8]>
+    {
+        let mut bytes = vec![];
+
+        for (i, part) in parts.into_iter().enumerate() {
+            if i > 0 {
+                bytes.push(separator);
+            }
+            bytes.extend_from_slice(part.as_ref());
+        }
+
+        bytes.into()
+    }
+
+    ///Convert every ASCII letter to lower case, in place. Other bytes are
+    ///left as they are. See `to_ascii_lowercase` for more details.
+    ///
+    ///```
+    ///use rustful::context::MaybeUtf8Owned;
+    ///
+    ///let mut string = MaybeUtf8Owned::from("X-Forwarded-För");
+    ///string.make_ascii_lowercase();
+    ///assert_eq!(string, "x-forwarded-för");
+    ///
+    ///let mut invalid = MaybeUtf8Owned::from(vec![b'X', 0xF6, b'Z']);
+    ///invalid.make_ascii_lowercase();
+    ///assert_eq!(invalid, &[b'x', 0xF6, b'z'][..]);
+    ///```
+    pub fn make_ascii_lowercase(&mut self) {
+        match *self {
+            MaybeUtf8::Utf8(ref mut s) => s.make_ascii_lowercase(),
+            MaybeUtf8::NotUtf8(ref mut v) => v.make_ascii_lowercase()
+        }
+    }
+
+    ///Convert every ASCII letter to upper case, in place. Other bytes are
+    ///left as they are. See `to_ascii_uppercase` for more details.
+    ///
+    ///```
+    ///use rustful::context::MaybeUtf8Owned;
+    ///
+    ///let mut invalid = MaybeUtf8Owned::from(vec![b'x', 0xF6, b'z']);
+    ///invalid.make_ascii_uppercase();
+    ///assert_eq!(invalid, &[b'X', 0xF6, b'Z'][..]);
+    ///assert!(!invalid.is_utf8());
+    ///```
+    pub fn make_ascii_uppercase(&mut self) {
+        match *self {
+            MaybeUtf8::Utf8(ref mut s) => s.make_ascii_uppercase(),
+            MaybeUtf8::NotUtf8(ref mut v) => v.make_ascii_uppercase()
+        }
+    }
+
     ///Borrow this string as a mutable byte buffer. The string's UTF-8
     ///compatibility will be reevaluated when the buffer is dropped.
     pub fn as_buffer(&mut self) -> Buffer {
@@ -200,10 +546,47 @@ impl<'a, S: From<&'a str>, V> From<&'a str> for MaybeUtf8<S, V> {
 
 impl From<Vec<u8>> for MaybeUtf8<String, Vec<u8>> {
     fn from(bytes: Vec<u8>) -> MaybeUtf8<String, Vec<u8>> {
-        match String::from_utf8(bytes) {
-            Ok(string) => MaybeUtf8::Utf8(string),
-            Err(e) => MaybeUtf8::NotUtf8(e.into_bytes())
-        }
+        MaybeUtf8::from_bytes(bytes)
+    }
+}
+
+///Write formatted text to the end of the string.
+///
+///```
+///use std::fmt::Write;
+///use rustful::context::MaybeUtf8Owned;
+///
+///let mut string = MaybeUtf8Owned::new();
+///write!(string, "{} + {} = {}", 1, 2, 3).unwrap();
+///assert_eq!("1 + 2 = 3", string);
+///```
+impl fmt::Write for MaybeUtf8<String, Vec<u8>> {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        self.push_str(string);
+        Ok(())
+    }
+}
+
+///Write bytes to the end of the string. The string's UTF-8 compatibility may
+///change, and writing will never fail.
+///
+///```
+///use std::io::Write;
+///use rustful::context::MaybeUtf8Owned;
+///
+///let mut string = MaybeUtf8Owned::from("abc");
+///string.write_all(&[100, 255]).unwrap();
+///assert_eq!(&b"abcd\xff"[..], string);
+///assert!(!string.is_utf8());
+///```
+impl io::Write for MaybeUtf8<String, Vec<u8>> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.push_bytes(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
     }
 }
 
@@ -335,6 +718,93 @@ impl<S: AsRef<[u8]>, V: AsRef<[u8]>> Deref for MaybeUtf8<S, V> {
     }
 }
 
+///A key that is compared and hashed without regard to the case of ASCII
+///letters, like header names.
+///
+///It can wrap anything that can be seen as bytes, such as `MaybeUtf8Owned`
+///or `String`, so a `HashMap<CaseInsensitive<MaybeUtf8Owned>, T>` treats
+///`Content-Type` and `content-type` as the same key. The original case is
+///kept in the key, and nothing is allocated when comparing or hashing it.
+///
+///```
+///use std::collections::HashMap;
+///use rustful::context::{CaseInsensitive, MaybeUtf8Owned};
+///
+///let mut headers = HashMap::new();
+///headers.insert(CaseInsensitive(MaybeUtf8Owned::from("Content-Type")), "text/plain");
+///headers.insert(CaseInsensitive(MaybeUtf8Owned::from("content-type")), "text/html");
+///
+///assert_eq!(headers.len(), 1);
+///assert_eq!(headers.get(CaseInsensitive::from_bytes(b"CONTENT-TYPE")), Some(&"text/html"));
+///```
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(transparent)]
+pub struct CaseInsensitive<T: ?Sized>(pub T);
+
+impl CaseInsensitive<[u8]> {
+    ///Borrow `bytes` as a case insensitive key. This makes it possible to
+    ///look up values in a map without creating an owned key.
+    ///
+    ///```
+    ///use std::collections::hash_map::DefaultHasher;
+    ///use std::hash::{Hash, Hasher};
+    ///use rustful::context::{CaseInsensitive, MaybeUtf8Owned};
+    ///
+    ///fn hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    ///    let mut hasher = DefaultHasher::new();
+    ///    value.hash(&mut hasher);
+    ///    hasher.finish()
+    ///}
+    ///
+    ///let key = CaseInsensitive(MaybeUtf8Owned::from("Content-Type"));
+    ///assert!(key == *CaseInsensitive::from_bytes(b"content-type"));
+    ///assert_eq!(hash(&key), hash(CaseInsensitive::from_bytes(b"content-type")));
+    ///assert_eq!(hash(&key), hash(&CaseInsensitive("CONTENT-TYPE")));
+    ///```
+    pub fn from_bytes(bytes: &[u8]) -> &CaseInsensitive<[u8]> {
+        //`CaseInsensitive` is transparent, so it has the same layout as `[u8]`.
+        unsafe { &*(bytes as *const [u8] as *const CaseInsensitive<[u8]>) }
+    }
+}
+
+impl<T: AsRef<[u8]> + ?Sized, U: AsRef<[u8]> + ?Sized> PartialEq<CaseInsensitive<U>> for CaseInsensitive<T> {
+    fn eq(&self, other: &CaseInsensitive<U>) -> bool {
+        self.0.as_ref().eq_ignore_ascii_case(other.0.as_ref())
+    }
+}
+
+impl<T: AsRef<[u8]> + ?Sized> Eq for CaseInsensitive<T> {}
+
+impl<T: AsRef<[u8]> + ?Sized> Hash for CaseInsensitive<T> {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        let bytes = self.0.as_ref();
+        hasher.write_usize(bytes.len());
+        for &byte in bytes {
+            hasher.write_u8(byte.to_ascii_lowercase());
+        }
+    }
+}
+
+impl<T: AsRef<[u8]>> Borrow<CaseInsensitive<[u8]>> for CaseInsensitive<T> {
+    fn borrow(&self) -> &CaseInsensitive<[u8]> {
+        CaseInsensitive::from_bytes(self.0.as_ref())
+    }
+}
+
+impl<T> From<T> for CaseInsensitive<T> {
+    fn from(value: T) -> CaseInsensitive<T> {
+        CaseInsensitive(value)
+    }
+}
+
+impl<T: ?Sized> Deref for CaseInsensitive<T> {
+    type Target=T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
 ///A byte buffer for more efficient `MaybeUtf8` manipulation.
 ///
 ///The buffer is essentially a `&mut Vec<u8>` that will be checked for UTF-8
@@ -109,27 +109,76 @@
 //![log]: ../log/index.html
 //![body_reader]: body/struct.BodyReader.html
 
+use std::io;
 use std::net::SocketAddr;
 use std::fmt;
 use std::borrow::Cow;
+use std::hash::Hash;
+use std::time::{Duration, Instant, SystemTime};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+#[cfg(feature = "rustc_json_body")]
+use rustc_serialize::{Decodable, json};
 
 use HttpVersion;
 use Method;
-use header::Headers;
-use server::Global;
+#[cfg(feature = "rustc_json_body")]
+use StatusCode;
+use header::{Headers, Accept, EntityTag, HttpDate};
+use mime::Mime;
+use response::Response;
+use server::{Global, Lifecycle};
+use session::{Session, CookieKeys, SignedCookies, PrivateCookies};
 
-use self::body::BodyReader;
+use self::body::{BodyReader, BodyError};
 use self::hypermedia::Link;
 
 pub mod body;
 pub mod hypermedia;
+#[cfg(feature = "multipart")]
+pub mod upload;
 
 mod maybe_utf8;
-pub use self::maybe_utf8::{MaybeUtf8, MaybeUtf8Owned, MaybeUtf8Slice, Buffer};
+pub use self::maybe_utf8::{MaybeUtf8, MaybeUtf8Owned, MaybeUtf8Slice, Buffer, CaseInsensitive};
 
 mod parameters;
 pub use self::parameters::Parameters;
 
+mod form;
+pub use self::form::FormData;
+
+mod negotiation;
+pub use self::negotiation::Negotiated;
+#[doc(hidden)]
+pub use self::negotiation::VaryHeaders;
+
+mod connection;
+pub use self::connection::{ConnectionInfo, ConnectionStorage};
+
+mod authorization;
+pub use self::authorization::Authorization;
+
+mod headers;
+pub use self::headers::{HeadersIter, HeaderEntry, KnownHeader};
+
+mod forwarded;
+pub use self::forwarded::{ForwardedElement, Node, NodeName, NodePort};
+
+mod precondition;
+pub use self::precondition::PreconditionResult;
+
+mod cache_control;
+pub use self::cache_control::CacheControl;
+
+#[cfg(feature = "rustc_json_body")]
+mod deserializer;
+#[cfg(feature = "rustc_json_body")]
+pub use self::deserializer::{BodyDeserializer, BodyDeserializers, JsonDeserializer, FormDeserializer};
+#[cfg(feature = "rustc_json_body")]
+#[doc(hidden)]
+pub use self::deserializer::form_to_json;
+
 ///A container for handler input, like request data and utilities.
 pub struct Context<'a, 'b: 'a, 's> {
     ///Headers from the HTTP request.
@@ -138,7 +187,9 @@ pub struct Context<'a, 'b: 'a, 's> {
     ///The HTTP version used in the request.
     pub http_version: HttpVersion,
 
-    ///The client address
+    ///The client address. IPv4 clients that are connected to a dual-stack
+    ///IPv6 socket will have their IPv4-mapped addresses (`::ffff:a.b.c.d`)
+    ///converted to plain IPv4 addresses.
     pub address: SocketAddr,
 
     ///The HTTP method.
@@ -162,11 +213,876 @@ pub struct Context<'a, 'b: 'a, 's> {
     ///Globally accessible data.
     pub global: &'s Global,
 
+    ///The lifecycle state of the server.
+    pub lifecycle: &'s Lifecycle,
+
+    #[doc(hidden)]
+    ///Internal and may change without warning. Use `session()` instead.
+    pub session: Option<Session>,
+
+    #[doc(hidden)]
+    ///Internal and may change without warning. Use `signed_cookies()` or
+    ///`private_cookies()` instead.
+    pub cookie_keys: Option<&'s CookieKeys>,
+
+    #[cfg(feature = "rustc_json_body")]
+    #[doc(hidden)]
+    ///Internal and may change without warning. Use `read_typed()` instead.
+    pub body_deserializers: &'s BodyDeserializers,
+
+    #[doc(hidden)]
+    ///Internal and may change without warning. Use `connection_info()`
+    ///instead.
+    pub connection_info: ConnectionInfo,
+
+    #[doc(hidden)]
+    ///Internal and may change without warning. Use `connection_storage()`
+    ///instead.
+    pub connection_storage: ConnectionStorage,
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub vary: VaryHeaders,
+
+    #[doc(hidden)]
+    ///Internal and may change without warning. Use `deadline()` instead.
+    pub deadline: Option<Instant>,
+
+    #[doc(hidden)]
+    ///Internal and may change without warning. Use `is_secure()` instead.
+    pub secure: bool,
+
+    #[doc(hidden)]
+    ///Internal and may change without warning. Use `raw_target()` instead.
+    pub raw_target: String,
+
+    #[doc(hidden)]
+    ///Internal and may change without warning. Use `target_form()` instead.
+    pub target_form: TargetForm,
+
+    #[doc(hidden)]
+    ///Internal and may change without warning. Use `original_path()`
+    ///instead.
+    pub original_path: Option<UriPath>,
+
     ///A reader for the request body.
     pub body: BodyReader<'a, 'b>,
 }
 
-///A URI Path that can be a path or an asterisk (`*`).
+impl<'a, 'b, 's> Context<'a, 'b, 's> {
+    ///Get the number of request body bytes that has been received so far.
+    ///See `BodyReader::bytes_read` for more details.
+    pub fn bytes_read(&self) -> u64 {
+        self.body.bytes_read()
+    }
+
+    ///Read and discard the rest of the request body. See `BodyReader::drain`
+    ///for more details.
+    pub fn drain_body(&mut self) -> io::Result<()> {
+        self.body.drain()
+    }
+
+    ///Prepare `response` for being written while the request body is still
+    ///being read, to stream a response to a streaming request over the same
+    ///connection.
+    ///
+    ///The body and the response can always be used at the same time, since
+    ///they are separate fields and arguments, but the connection is normally
+    ///closed after a response that was started before the body had been
+    ///read. This keeps it alive instead, and drains whatever the handler
+    ///leaves of the body after it returns, so the next request can be read.
+    ///It has to be called before the head of the response is sent.
+    ///
+    ///```
+    ///use std::io::Read;
+    ///use rustful::{Context, Response};
+    ///
+    ///fn echo(mut context: Context, mut response: Response) {
+    ///    context.full_duplex(&mut response);
+    ///    let mut chunked = response.into_chunked();
+    ///    let mut buffer = [0; 4096];
+    ///
+    ///    loop {
+    ///        match context.body.read(&mut buffer) {
+    ///            Ok(0) | Err(_) => break,
+    ///            Ok(length) => {
+    ///                chunked.send(&buffer[..length]);
+    ///                if chunked.flush().is_err() {
+    ///                    break;
+    ///                }
+    ///            }
+    ///        }
+    ///    }
+    ///}
+    ///```
+    ///
+    ///HTTP/1.1 doesn't forbid this, but it doesn't promise that the other
+    ///side is prepared for it either, so there are some limitations:
+    ///
+    /// * Both halves belong to the connection, so they can't be sent to
+    ///   other threads. Interleave the reads and writes in the handler, as
+    ///   above, or pass the data on through channels.
+    /// * The status and the headers are sent first, so they can't depend on
+    ///   what's later in the body. The response should be chunked, and
+    ///   flushed after each part, since it's otherwise buffered.
+    /// * Many clients and proxies don't read the response until they have
+    ///   sent the whole request, and may stop sending if the response isn't
+    ///   read. The handler should keep reading as long as it writes, or both
+    ///   sides may end up waiting for each other.
+    /// * The rest of the body is drained without any limit other than
+    ///   `Server::max_body_length`, so the handler should read it to the end.
+    ///   The connection is closed if draining fails.
+    /// * `Server::body_read_timeout` and `Server::body_read_deadline` still
+    ///   apply to the body, so a long lived stream may need a longer
+    ///   deadline.
+    /// * HTTP/1.0 clients get an unchunked response that ends when the
+    ///   connection is closed, as described for `Response::into_chunked`.
+    pub fn full_duplex(&self, response: &mut Response) {
+        let drain = self.body.drain_flag();
+        drain.store(true, Ordering::Relaxed);
+        response.set_body_drain(drain);
+    }
+
+    ///Read the whole request body into a shared buffer, that can be used
+    ///more than once, and that the body readers will read from afterwards.
+    ///See `BodyReader::cached_bytes` for more details.
+    pub fn body_bytes(&mut self) -> Result<Arc<[u8]>, BodyError> {
+        self.body.cached_bytes()
+    }
+
+    ///Read the rest of the request body into `buf`, without allocating a new
+    ///buffer. See `BodyReader::read_into` for more details.
+    pub fn read_body_into(&mut self, buf: &mut Vec<u8>, max: usize) -> Result<usize, BodyError> {
+        self.body.read_into(buf, max)
+    }
+
+    ///Copy the rest of the request body to `writer`, while reporting the
+    ///progress. See `BodyReader::copy_with_progress` for more details.
+    pub fn copy_body_with_progress<W, F>(&mut self, writer: &mut W, progress: F) -> io::Result<u64> where
+        W: io::Write + ?Sized,
+        F: FnMut(u64, Option<u64>)
+    {
+        self.body.copy_with_progress(writer, progress)
+    }
+
+    ///Get the point in time when the request should be done, or `None` if
+    ///there is no deadline. It's set from `Server::request_timeout`, counted
+    ///from when the request was received.
+    ///
+    ///The deadline is advisory, so nothing happens when it has passed. It's
+    ///meant for handlers that can choose to skip optional work.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    ///Get the time that is left until the deadline, or `None` if there is no
+    ///deadline. This is zero if the deadline has passed.
+    ///
+    ///```
+    ///use std::time::Duration;
+    ///use rustful::{Context, Response};
+    ///
+    ///fn handler(context: Context, response: Response) {
+    ///    let short_on_time = context.time_remaining().map_or(false, |remaining| remaining < Duration::from_millis(50));
+    ///
+    ///    if short_on_time {
+    ///        response.send("the basic page");
+    ///    } else {
+    ///        response.send("the page, with recommendations");
+    ///    }
+    ///}
+    ///```
+    pub fn time_remaining(&self) -> Option<Duration> {
+        self.deadline.map(|deadline| {
+            let now = Instant::now();
+            if deadline > now {
+                deadline - now
+            } else {
+                Duration::from_secs(0)
+            }
+        })
+    }
+
+    ///Replace the deadline. This can be used to give some routes a shorter,
+    ///or longer, deadline from a context filter.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    ///Borrow the requested path, or get `None` if it's an asterisk (`*`) or
+    ///an authority. This doesn't allocate.
+    ///
+    ///The path is percent decoded, and normalized if the server is set to
+    ///normalize paths, so it may not be valid UTF-8. The path of an
+    ///absolute-form target, like `http://example.com/path`, is extracted,
+    ///so it looks the same as when the target is just `/path`. Use
+    ///`raw_target()` to see the undecoded target.
+    ///
+    ///The decoding is done in a single pass, so `%252F` becomes `%2F`. A
+    ///decoded `%2F` looks like any other `/` here, but it's not treated as a
+    ///separator when the request is routed. Requests with malformed percent
+    ///encoding, like `%ZZ`, are rejected with `400 Bad Request` before they
+    ///get this far.
+    pub fn path(&self) -> Option<MaybeUtf8Slice> {
+        self.uri_path.as_path()
+    }
+
+    ///Borrow the requested path as it was before a `Mount` removed its
+    ///prefix, or get `None` if it's an asterisk (`*`). This is the same as
+    ///`path()` if the request didn't pass through a `Mount`.
+    pub fn original_path<'c>(&'c self) -> Option<MaybeUtf8Slice<'c>> {
+        self.original_path.as_ref().unwrap_or(&self.uri_path).as_path()
+    }
+
+    ///Borrow the decoded fragment (after `#`), if any.
+    ///
+    ///Note that clients are not supposed to send the fragment, and browsers
+    ///don't, so this is usually `None`.
+    pub fn fragment(&self) -> Option<MaybeUtf8Slice> {
+        self.fragment.as_ref().map(|fragment| fragment.as_slice())
+    }
+
+    ///Borrow the request target from the request line, before any decoding
+    ///or normalization. It includes the query and, if it was sent, the
+    ///fragment.
+    ///
+    ///Absolute-form targets are parsed and written back by the URL parser,
+    ///so they may differ in insignificant ways from what was received, like
+    ///the case of the scheme and host.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///use rustful::context::TargetForm;
+    ///
+    ///fn handler(context: Context, response: Response) {
+    ///    match context.target_form() {
+    ///        TargetForm::Origin => response.send(format!("requested {}", context.raw_target())),
+    ///        TargetForm::Absolute => response.send(format!("proxied request for {}", context.raw_target())),
+    ///        TargetForm::Asterisk => response.send("asked about the whole server"),
+    ///        TargetForm::Authority => response.send(format!("asked for a tunnel to {}", context.raw_target())),
+    ///    }
+    ///}
+    ///```
+    pub fn raw_target(&self) -> &str {
+        &self.raw_target
+    }
+
+    ///Get the form of the request target.
+    pub fn target_form(&self) -> TargetForm {
+        self.target_form
+    }
+
+    ///Parse the query into a `FormData`, which keeps every value of repeated
+    ///query variables, in contrast to `query`. The query is parsed each time
+    ///this is called, and the values are not decoded from
+    ///`Server::fallback_charset`.
+    pub fn query_form_data(&self) -> FormData {
+        let target = self.raw_target.split('#').next().unwrap_or("");
+        match target.find('?') {
+            //The request would have been rejected if the query was malformed.
+            Some(index) => FormData::parse(target[index + 1..].as_bytes()).unwrap_or_default(),
+            None => FormData::new()
+        }
+    }
+
+    ///Borrow the value of a query variable, without allocating.
+    pub fn query_raw<K: ?Sized + Hash + Eq + AsRef<[u8]>>(&self, key: &K) -> Option<MaybeUtf8Slice> {
+        self.query.get_raw(key).map(|value| value.as_slice())
+    }
+
+    ///Set the value of a route variable, and return its previous value. The
+    ///variable is added if it's missing.
+    ///
+    ///This is meant for route filters, in `RouteFilters`, that validate or
+    ///canonicalize the variables before the handler reads them from
+    ///`variables`. Changing a variable doesn't route the request again, so
+    ///the same handler is called. The variables are still empty when the
+    ///global context filters run, since that's before the request is routed.
+    ///
+    ///```
+    ///use rustful::{Context, StatusCode};
+    ///use rustful::filter::{ContextFilter, ContextAction, FilterContext};
+    ///
+    ///struct LowercaseSlug;
+    ///
+    ///impl ContextFilter for LowercaseSlug {
+    ///    fn modify(&self, _ctx: FilterContext, context: &mut Context) -> ContextAction {
+    ///        let slug = context.variables.get("slug").map(|slug| slug.to_lowercase());
+    ///        match slug {
+    ///            Some(slug) => {
+    ///                context.set_variable("slug", slug);
+    ///                ContextAction::next()
+    ///            },
+    ///            None => ContextAction::abort(StatusCode::NotFound)
+    ///        }
+    ///    }
+    ///}
+    ///```
+    pub fn set_variable<K, V>(&mut self, name: K, value: V) -> Option<MaybeUtf8Owned> where
+        K: Into<MaybeUtf8Owned>, V: Into<MaybeUtf8Owned>
+    {
+        self.variables.insert(name, value)
+    }
+
+    ///Borrow the raw values of a header, without parsing or allocating. Use
+    ///`headers.get()` to get a parsed header. The name is case insensitive,
+    ///and there is one value for each time the header appears in the
+    ///request.
+    pub fn header_raw(&self, name: &str) -> Option<&[Vec<u8>]> {
+        self.headers.get_raw(name)
+    }
+
+    ///Check if a header is present, without parsing or allocating. The name
+    ///is case insensitive.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(context: Context, response: Response) {
+    ///    if context.has_header("Upgrade") {
+    ///        response.send("upgrades are not supported");
+    ///    } else {
+    ///        response.send("hello");
+    ///    }
+    ///}
+    ///```
+    pub fn has_header(&self, name: &str) -> bool {
+        self.headers.get_raw(name).is_some()
+    }
+
+    ///Iterate over all of the request headers, with their raw values and,
+    ///for well-known headers, their parsed values.
+    ///
+    ///Each header is only parsed when `known()` or `get()` is called on it,
+    ///and the result is cached in `headers`, like with `headers.get()`. A
+    ///malformed header is reported as `None` from those methods, and its raw
+    ///values are still available, so one bad header doesn't stop the
+    ///iteration. The order of the headers is unspecified.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///use rustful::context::KnownHeader;
+    ///
+    ///fn log_headers(context: Context, response: Response) {
+    ///    for header in context.headers_iter() {
+    ///        match header.known() {
+    ///            Some(KnownHeader::Host(host)) => println!("host: {}", host.hostname),
+    ///            Some(known) => println!("{:?}", known),
+    ///            None => println!("{}: {} values", header.name(), header.raw().len())
+    ///        }
+    ///    }
+    ///    response.send("logged");
+    ///}
+    ///```
+    pub fn headers_iter<'c>(&'c self) -> HeadersIter<'c> {
+        HeadersIter::new(&self.headers)
+    }
+
+    ///Check if the request is a conditional request for a cached copy, with
+    ///`If-None-Match` or `If-Modified-Since`. The result is also reported in
+    ///`CompletionInfo::conditional`, together with whether the response was
+    ///`304 Not Modified`.
+    pub fn is_conditional(&self) -> bool {
+        ::utils::is_conditional(&self.headers)
+    }
+
+    ///Evaluate the preconditions of the request against the current state of
+    ///the requested resource, for example to make sure that a `PUT` doesn't
+    ///overwrite someone else's changes.
+    ///
+    ///`If-Match` and `If-Unmodified-Since` are checked first, then
+    ///`If-None-Match` and `If-Modified-Since`, as described in RFC 7232.
+    ///`If-Match` uses strong comparison and `If-None-Match` uses weak
+    ///comparison, and the date headers are ignored when the corresponding
+    ///tag header is present. The resource is assumed to exist if it has an
+    ///`ETag` or a modification date, which is what the `*` wildcards are
+    ///matched against, so pass `None` for both if it doesn't exist.
+    ///Malformed headers are ignored.
+    ///
+    ///```
+    ///use rustful::{Context, Response, StatusCode};
+    ///use rustful::context::PreconditionResult;
+    ///use rustful::header::EntityTag;
+    ///
+    ///fn update(context: Context, mut response: Response) {
+    ///    let current = EntityTag::strong("v2".to_owned());
+    ///    match context.precondition_check(Some(&current), None) {
+    ///        PreconditionResult::Proceed => response.send("updated"),
+    ///        PreconditionResult::PreconditionFailed => response.set_status(StatusCode::PreconditionFailed),
+    ///        PreconditionResult::NotModified => response.set_status(StatusCode::NotModified)
+    ///    }
+    ///}
+    ///```
+    pub fn precondition_check(&self, current_etag: Option<&EntityTag>, last_modified: Option<HttpDate>) -> PreconditionResult {
+        precondition::check(&self.method, &self.headers, current_etag, last_modified.map(|date| date.0.to_timespec().sec))
+    }
+
+    ///Parse the `Authorization` header into credentials. `None` is returned
+    ///if the header is missing, appears more than once, or is malformed. See
+    ///`Authorization::parse` for the details.
+    ///
+    ///```
+    ///use rustful::{Context, Response, StatusCode};
+    ///use rustful::context::Authorization;
+    ///
+    ///fn my_handler(context: Context, mut response: Response) {
+    ///    match context.authorization() {
+    ///        Some(Authorization::Bearer(token)) => response.send(format!("token: {}", token)),
+    ///        _ => response.set_status(StatusCode::Unauthorized)
+    ///    }
+    ///}
+    ///```
+    pub fn authorization(&self) -> Option<Authorization> {
+        match self.headers.get_raw("Authorization") {
+            Some(values) if values.len() == 1 => Authorization::parse(&values[0]),
+            _ => None
+        }
+    }
+
+    ///Parse the `Forwarded` headers into their elements, in the order the
+    ///proxies added them. An empty list is returned if there is no
+    ///`Forwarded` header, or if any of them is malformed.
+    ///
+    ///The elements are reported as they are, so only those that were added
+    ///by trusted proxies should be relied on. `Server::trusted_proxies` and
+    ///`is_secure` only consider `X-Forwarded-Proto`.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(context: Context, response: Response) {
+    ///    let client = context.forwarded().last().and_then(|element| element.for_node.as_ref().and_then(|node| node.ip()));
+    ///    match client {
+    ///        Some(ip) => response.send(format!("forwarded for {}", ip)),
+    ///        None => response.send(format!("from {}", context.address.ip()))
+    ///    }
+    ///}
+    ///```
+    pub fn forwarded(&self) -> Vec<ForwardedElement> {
+        self.headers.get_raw("Forwarded").and_then(ForwardedElement::parse).unwrap_or_default()
+    }
+
+    ///Get the `Origin` header, which browsers send with cross-origin requests,
+    ///`POST` requests and WebSocket handshakes, among others. `None` is
+    ///returned if there is no `Origin` header, if it's repeated, or if it's
+    ///not valid UTF-8.
+    ///
+    ///The value is the scheme, host and port of the page that made the
+    ///request, like `https://example.com:8443`, or `null` for opaque origins,
+    ///like sandboxed frames and local files.
+    pub fn origin(&self) -> Option<&str> {
+        match self.headers.get_raw("Origin") {
+            Some(values) if values.len() == 1 => ::std::str::from_utf8(&values[0]).ok().map(str::trim),
+            _ => None
+        }
+    }
+
+    ///Check if the `Origin` header matches any of the origins in `allowed`,
+    ///like `["https://example.com", "https://admin.example.com:8443"]`.
+    ///
+    ///The scheme, host and port have to match exactly, except for the case
+    ///of the scheme and host, and the port may be left out if it's the
+    ///default port for the scheme. This means that `https://example.com`
+    ///matches `https://example.com:443`, but not `http://example.com`,
+    ///`https://sub.example.com` or `https://example.com.evil.net`. Missing,
+    ///malformed and `null` origins are never allowed, and neither are
+    ///malformed entries in `allowed`.
+    ///
+    ///This is meant for protecting WebSocket handshakes and requests that
+    ///change state, which browsers send with the cookies of the user, no
+    ///matter which site started them. Checking the origin makes sure the
+    ///request came from a trusted page. This is not the same as CORS, which
+    ///is about letting the browser share *responses* with pages on other
+    ///origins, using `Access-Control-Allow-Origin` and related headers. The
+    ///browser still sends requests that CORS doesn't allow, such as form
+    ///submissions and WebSocket handshakes, so CORS headers alone don't stop
+    ///them from reaching the handler.
+    ///
+    ///Clients that aren't browsers can send any origin, or none, so requests
+    ///without an `Origin` header need another form of protection, like the
+    ///`Csrf` filter, if they should be accepted.
+    ///
+    ///```
+    ///use rustful::{Context, Response, StatusCode};
+    ///
+    ///fn feed(context: Context, mut response: Response) {
+    ///    if !context.origin_allowed(&["https://example.com", "http://localhost:8080"]) {
+    ///        response.set_status(StatusCode::Forbidden);
+    ///        return;
+    ///    }
+    ///
+    ///    response.send("welcome");
+    ///}
+    ///```
+    pub fn origin_allowed(&self, allowed: &[&str]) -> bool {
+        let origin = match self.origin().and_then(parse_origin) {
+            Some(origin) => origin,
+            None => return false
+        };
+
+        allowed.iter().filter_map(|&allowed| parse_origin(allowed)).any(|allowed| allowed == origin)
+    }
+
+    ///Parse the `Cache-Control` headers of the request. `None` is returned if
+    ///there is no `Cache-Control` header, or if any of them is malformed.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(context: Context, response: Response) {
+    ///    let fresh = context.cache_control().map_or(false, |cache_control| cache_control.no_cache || cache_control.max_age == Some(0));
+    ///    if fresh {
+    ///        response.send("reloaded");
+    ///    } else {
+    ///        response.send("maybe from a cache");
+    ///    }
+    ///}
+    ///```
+    pub fn cache_control(&self) -> Option<CacheControl> {
+        self.headers.get_raw("Cache-Control").and_then(CacheControl::parse)
+    }
+
+    ///Parse the `If-Modified-Since` header as a `SystemTime`. All three HTTP
+    ///date formats are accepted, and `None` is returned if the header is
+    ///missing, malformed, or repeated. See `utils::parse_http_date` for how
+    ///it's parsed.
+    ///
+    ///HTTP dates only have whole seconds, so a modification time with a
+    ///fraction of a second should be rounded down before it's compared with
+    ///this, or it will look newer than a date that was generated from it.
+    ///
+    ///```
+    ///use std::fs;
+    ///use rustful::{Context, Response, StatusCode};
+    ///use rustful::utils::unix_seconds;
+    ///
+    ///fn my_handler(context: Context, mut response: Response) {
+    ///    let modified = fs::metadata("page.html").and_then(|m| m.modified()).ok();
+    ///
+    ///    if let (Some(modified), Some(since)) = (modified, context.if_modified_since()) {
+    ///        if unix_seconds(modified) <= unix_seconds(since) {
+    ///            response.set_status(StatusCode::NotModified);
+    ///            response.send("");
+    ///            return;
+    ///        }
+    ///    }
+    ///
+    ///    response.send("the page");
+    ///}
+    ///```
+    pub fn if_modified_since(&self) -> Option<SystemTime> {
+        ::utils::header_date(&self.headers, "If-Modified-Since")
+    }
+
+    ///Parse the `Content-Type` header, including its parameters. `None` is
+    ///returned if the header is missing or malformed. See
+    ///`utils::parse_media_type` for how it's parsed.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///use rustful::mime::{Mime, TopLevel, SubLevel};
+    ///
+    ///fn my_handler(context: Context, response: Response) {
+    ///    match context.content_type() {
+    ///        Some(Mime(TopLevel::Application, SubLevel::Json, _)) => response.send("json"),
+    ///        _ => response.send("something else")
+    ///    }
+    ///}
+    ///```
+    pub fn content_type(&self) -> Option<Mime> {
+        ::utils::parse_content_type(&self.headers)
+    }
+
+    ///Read the body and decode it as `T`, using the deserializer in
+    ///`Server::body_deserializers` for the `Content-Type` of the request.
+    ///There are deserializers for JSON and `application/x-www-form-urlencoded`
+    ///by default, and more can be registered for other formats, like
+    ///MessagePack or CBOR. See `BodyDeserializer` for how they work.
+    ///
+    ///The returned error is the status code that the request should be
+    ///rejected with. It's `415 Unsupported Media Type` if there's no
+    ///deserializer for the content type, or no content type at all, `422
+    ///Unprocessable Entity` if the body doesn't fit `T`, and whatever the
+    ///deserializer returned if it failed, which is usually `400 Bad Request`.
+    ///
+    ///```
+    ///use std::collections::BTreeMap;
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(mut context: Context, response: Response) {
+    ///    match context.read_typed::<BTreeMap<String, String>>() {
+    ///        Ok(user) => response.send(format!("hello, {}", user.get("name").map(|name| &**name).unwrap_or("stranger"))),
+    ///        Err(status) => {
+    ///            let mut response = response;
+    ///            response.set_status(status);
+    ///        }
+    ///    }
+    ///}
+    ///```
+    #[cfg(feature = "rustc_json_body")]
+    pub fn read_typed<T: Decodable>(&mut self) -> Result<T, StatusCode> {
+        let deserializers = self.body_deserializers;
+        let deserializer = match self.content_type().and_then(|content_type| deserializers.get(&content_type)) {
+            Some(deserializer) => deserializer,
+            None => return Err(StatusCode::UnsupportedMediaType)
+        };
+
+        let value = try!(deserializer.deserialize(&mut self.body));
+        Decodable::decode(&mut json::Decoder::new(value)).map_err(|_| StatusCode::UnprocessableEntity)
+    }
+
+    ///Get the trailers that were sent after a chunked request body. They are
+    ///only available after the body has been read to its end, and the
+    ///headers are not affected by them. See `BodyReader::trailers` for more
+    ///details.
+    ///
+    ///```
+    ///use std::io::Read;
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(mut context: Context, response: Response) {
+    ///    let mut body = vec![];
+    ///    if context.body.read_to_end(&mut body).is_err() {
+    ///        return;
+    ///    }
+    ///
+    ///    match context.trailers().get_raw("Checksum") {
+    ///        Some(_) => response.send(format!("got {} bytes and a checksum", body.len())),
+    ///        None => response.send(format!("got {} bytes", body.len()))
+    ///    }
+    ///}
+    ///```
+    pub fn trailers(&self) -> &Headers {
+        self.body.trailers()
+    }
+
+    ///Get the session of the request, or `None` if sessions are disabled.
+    ///See the [`session`](../session/index.html) module for more information.
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    ///Read and write signed cookies, or `None` if `Server::cookie_keys` is
+    ///not set. See `SignedCookies` for an example.
+    pub fn signed_cookies<'c>(&'c self) -> Option<SignedCookies<'c>> {
+        self.cookie_keys.map(|keys| keys.signed(&self.headers))
+    }
+
+    ///Read and write private, encrypted, cookies, or `None` if
+    ///`Server::cookie_keys` is not set. See `PrivateCookies` for an example.
+    pub fn private_cookies<'c>(&'c self) -> Option<PrivateCookies<'c>> {
+        self.cookie_keys.map(|keys| keys.private(&self.headers))
+    }
+
+    ///Get information about the connection of the request, like its TLS
+    ///parameters.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///use rustful::StatusCode::Forbidden;
+    ///
+    ///fn my_handler(context: Context, mut response: Response) {
+    ///    match context.connection_info().tls_version.as_ref().map(|v| &**v) {
+    ///        Some("TLSv1.2") => response.send("secret"),
+    ///        _ => response.set_status(Forbidden)
+    ///    }
+    ///}
+    ///```
+    pub fn connection_info(&self) -> &ConnectionInfo {
+        &self.connection_info
+    }
+
+    ///Get the state that is shared by every request on the same connection.
+    ///See `ConnectionStorage` for the details and an example.
+    pub fn connection_storage(&self) -> &ConnectionStorage {
+        &self.connection_storage
+    }
+
+    ///Check if the client used HTTPS. This is the case if the request was
+    ///received over TLS, or if it was forwarded by one of the
+    ///`Server::trusted_proxies` with `X-Forwarded-Proto: https`. See
+    ///`Server::trusted_proxies` for the details.
+    ///
+    ///Session cookies are marked as `Secure` when this is `true`, and the
+    ///`StrictTransportSecurity` filter only sends its header then.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///use rustful::StatusCode::Forbidden;
+    ///
+    ///fn my_handler(context: Context, mut response: Response) {
+    ///    if context.is_secure() {
+    ///        response.send("secret");
+    ///    } else {
+    ///        response.set_status(Forbidden);
+    ///    }
+    ///}
+    ///```
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    ///Choose the language from `available` that the client prefers the most,
+    ///according to the `Accept-Language` header.
+    ///
+    ///The languages are compared without regard to case, and a language range
+    ///will also match more or less specific tags, so `en` will match `en-US`
+    ///and the other way around, but an exact match is preferred when the
+    ///quality values are the same. Languages that are equally preferred are
+    ///chosen in the order they are offered. `None` is returned if nothing is
+    ///acceptable, or if the header is missing.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(context: Context, response: Response) {
+    ///    match context.preferred_language(&["en", "sv"]).as_ref().map(|l| &**l) {
+    ///        Some("sv") => response.send("hej"),
+    ///        _ => response.send("hello")
+    ///    }
+    ///}
+    ///```
+    pub fn preferred_language(&self, available: &[&str]) -> Option<String> {
+        self.vary.accept_language();
+        negotiation::preferred_language(&self.headers, available).map(|language| language.to_owned())
+    }
+
+    ///Choose the media type from `available` that the client prefers the
+    ///most, according to the `Accept` header.
+    ///
+    ///Media ranges, like `text/*`, are supported and the most specific range
+    ///decides the quality of a media type. Media types that are equally
+    ///preferred are chosen in the order they are offered, unless one has a
+    ///more precise match. Parameters are ignored. `None` is returned if
+    ///nothing is acceptable, or if the header is missing.
+    ///
+    ///```
+    ///#[macro_use]
+    ///extern crate rustful;
+    ///use rustful::{Context, Response};
+    ///use rustful::header::ContentType;
+    ///
+    ///fn my_handler(context: Context, mut response: Response) {
+    ///    let available = [content_type!(Application / Json), content_type!(Text / Plain)];
+    ///
+    ///    match context.preferred_content_type(&available) {
+    ///        Some(json) if *json == available[0] => {
+    ///            response.headers_mut().set(ContentType(json.clone()));
+    ///            response.send("{\"message\": \"hello\"}");
+    ///        },
+    ///        _ => {
+    ///            response.headers_mut().set(ContentType(available[1].clone()));
+    ///            response.send("hello");
+    ///        }
+    ///    }
+    ///}
+    ///# fn main() {}
+    ///```
+    pub fn preferred_content_type<'m>(&self, available: &'m [Mime]) -> Option<&'m Mime> {
+        self.vary.accept();
+        negotiation::preferred_media_type(&self.headers, available)
+    }
+
+    ///Choose the media type that the response should have, from the types
+    ///in `offers` that the handler can produce.
+    ///
+    ///This works like `preferred_content_type`, but the first offer is
+    ///chosen if there is no `Accept` header, since anything is acceptable
+    ///then. `Negotiated::NotAcceptable` is returned if none of the offers
+    ///are accepted, if `offers` is empty, or if the header is malformed.
+    ///`Accept` is recorded for `Vary`, as the response depends on it either
+    ///way, and `Negotiated::apply` sets `Content-Type`, or `406 Not
+    ///Acceptable`.
+    ///
+    ///```
+    ///#[macro_use]
+    ///extern crate rustful;
+    ///use rustful::{Context, Response};
+    ///use rustful::context::Negotiated;
+    ///
+    ///fn my_handler(context: Context, mut response: Response) {
+    ///    let offers = [content_type!(Application / Json), content_type!(Text / Html)];
+    ///    let negotiated = context.negotiate(&offers);
+    ///
+    ///    match negotiated {
+    ///        Negotiated::Accepted(0, _) => {
+    ///            negotiated.apply(&mut response);
+    ///            response.send("{\"message\": \"hello\"}");
+    ///        },
+    ///        Negotiated::Accepted(_, _) => {
+    ///            negotiated.apply(&mut response);
+    ///            response.send("<p>hello</p>");
+    ///        },
+    ///        Negotiated::NotAcceptable => {
+    ///            negotiated.apply(&mut response);
+    ///        }
+    ///    }
+    ///}
+    ///# fn main() {}
+    ///```
+    pub fn negotiate<'m>(&self, offers: &'m [Mime]) -> Negotiated<'m> {
+        let chosen = if self.headers.has::<Accept>() {
+            self.preferred_content_type(offers)
+        } else {
+            self.vary.accept();
+            offers.first()
+        };
+
+        chosen
+            .and_then(|chosen| offers.iter().position(|offer| ::std::ptr::eq(offer, chosen)).map(|index| Negotiated::Accepted(index, chosen)))
+            .unwrap_or(Negotiated::NotAcceptable)
+    }
+
+    ///Check if the client accepts the content coding `encoding`, such as
+    ///`gzip`, according to the `Accept-Encoding` header. A quality value of
+    ///0 excludes a coding, and `*` matches any coding that isn't listed.
+    ///Only `identity`, meaning no coding, is accepted if the header is
+    ///missing. Use `Compression::negotiate` to choose between codings.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(context: Context, response: Response) {
+    ///    if context.accepts_encoding("gzip") {
+    ///        response.send("gzip is supported");
+    ///    } else {
+    ///        response.send("gzip is not supported");
+    ///    }
+    ///}
+    ///```
+    pub fn accepts_encoding(&self, encoding: &str) -> bool {
+        self.vary.accept_encoding();
+        negotiation::accepts_encoding(&self.headers, encoding)
+    }
+
+    ///Check if the client accepts the media type `media_type`, according to
+    ///the `Accept` header. The most specific media range decides, just like
+    ///for `preferred_content_type`, and everything is accepted if the header
+    ///is missing.
+    pub fn accepts(&self, media_type: &Mime) -> bool {
+        self.vary.accept();
+        negotiation::accepts_media_type(&self.headers, media_type)
+    }
+
+    ///Check if the request was made by a script, as indicated by the header
+    ///`X-Requested-With: XMLHttpRequest`, which many JavaScript libraries
+    ///add. This is only a hint, since anyone can set the header, and the
+    ///standard `fetch` API doesn't add it.
+    pub fn is_ajax(&self) -> bool {
+        self.headers.get_raw("X-Requested-With").map_or(false, |values| {
+            values.iter().any(|value| value.eq_ignore_ascii_case(b"XMLHttpRequest"))
+        })
+    }
+
+    ///Choose a language from `available`, like `preferred_language`, but
+    ///fall back to the first one if none of them are acceptable. `None` is
+    ///only returned if `available` is empty.
+    pub fn preferred_language_or_first(&self, available: &[&str]) -> Option<String> {
+        self.preferred_language(available).or_else(|| available.first().map(|&language| language.to_owned()))
+    }
+}
+
+///A URI Path that can be a path, an asterisk (`*`) or an authority.
 ///
 ///The URI Path may be an invalid UTF-8 path and it is therefore represented as a
 ///percent decoded byte vector, but can easily be parsed as a string.
@@ -175,7 +1091,9 @@ pub enum UriPath {
     ///A path URI.
     Path(MaybeUtf8Owned),
     ///An asterisk (`*`) URI.
-    Asterisk
+    Asterisk,
+    ///An authority-form target, like `example.com:443`, which has no path.
+    Authority
 }
 
 impl UriPath {
@@ -183,7 +1101,7 @@ impl UriPath {
     pub fn as_path(&self) -> Option<MaybeUtf8Slice> {
         match *self {
             UriPath::Path(ref path) => Some(path.as_slice()),
-            UriPath::Asterisk => None
+            UriPath::Asterisk | UriPath::Authority => None
         }
     }
 
@@ -191,7 +1109,7 @@ impl UriPath {
     pub fn as_utf8_path(&self) -> Option<&str> {
         match *self {
             UriPath::Path(ref path) => path.as_utf8(),
-            UriPath::Asterisk => None
+            UriPath::Asterisk | UriPath::Authority => None
         }
     }
 
@@ -200,7 +1118,7 @@ impl UriPath {
     pub fn as_utf8_path_lossy(&self) -> Option<Cow<str>> {
         match *self {
             UriPath::Path(ref path) => Some(path.as_utf8_lossy()),
-            UriPath::Asterisk => None
+            UriPath::Asterisk | UriPath::Authority => None
         }
     }
 
@@ -208,19 +1126,99 @@ impl UriPath {
     pub fn is_path(&self) -> bool {
         match *self {
             UriPath::Path(_) => true,
-            UriPath::Asterisk => false
+            UriPath::Asterisk | UriPath::Authority => false
         }
     }
 
     ///Check if the URI is an asterisk (`*`).
     pub fn is_asterisk(&self) -> bool {
         match *self {
-            UriPath::Path(_) => false,
-            UriPath::Asterisk => true
+            UriPath::Asterisk => true,
+            UriPath::Path(_) | UriPath::Authority => false
         }
     }
 }
 
+///The form of a request target, as described in [RFC 7230, section
+///5.3](https://tools.ietf.org/html/rfc7230#section-5.3).
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TargetForm {
+    ///An absolute path with an optional query, like `/path?query`. This is
+    ///what most clients send.
+    Origin,
+
+    ///A complete URI, like `http://example.com/path?query`. This is sent to
+    ///proxies, but servers must also accept it. Its host replaces the `Host`
+    ///header.
+    Absolute,
+
+    ///An asterisk (`*`), for `OPTIONS` requests for the whole server. The
+    ///path is `UriPath::Asterisk` in this case, and the request is only
+    ///passed to the fallback handler, since there is no path to route.
+    Asterisk,
+
+    ///A host and a port, like `example.com:443`, for `CONNECT` requests to
+    ///a proxy. The authority is available from `raw_target()`, and it
+    ///replaces the `Host` header. The path is `UriPath::Authority`, and the
+    ///request is only passed to the fallback handler, as with `Asterisk`.
+    ///Authority-form targets without a port, or with any other method than
+    ///`CONNECT`, are rejected with `400 Bad Request`.
+    Authority
+}
+
+//Split an origin into its lowercase scheme and host, and its port, where
+//the default ports for HTTP and WebSockets are filled in. Anything with a
+//path, user info or other garbage is rejected, and so is `null`.
+fn parse_origin(origin: &str) -> Option<(String, String, Option<u16>)> {
+    let separator = match origin.find("://") {
+        Some(separator) => separator,
+        None => return None
+    };
+    let (scheme, authority) = (&origin[..separator], &origin[separator + 3..]);
+
+    let valid_scheme = scheme.bytes().next().map_or(false, |byte| byte.is_ascii_alphabetic())
+        && scheme.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'+' || byte == b'-' || byte == b'.');
+    if !valid_scheme || authority.is_empty() || authority.contains(|c| c == '/' || c == '?' || c == '#' || c == '@') {
+        return None;
+    }
+
+    //The port separator is the last `:` after any IPv6 address.
+    let host_end = if authority.starts_with('[') {
+        match authority.find(']') {
+            Some(end) => end + 1,
+            None => return None
+        }
+    } else {
+        authority.find(':').unwrap_or(authority.len())
+    };
+    let (host, port) = authority.split_at(host_end);
+
+    let port = if port.is_empty() {
+        None
+    } else if port.len() > 1 && port.starts_with(':') && port[1..].bytes().all(|byte| byte.is_ascii_digit()) {
+        match port[1..].parse() {
+            Ok(port) => Some(port),
+            Err(_) => return None
+        }
+    } else {
+        return None;
+    };
+
+    if host.is_empty() {
+        return None;
+    }
+
+    let scheme = scheme.to_ascii_lowercase();
+    let port = port.or_else(|| match &*scheme {
+        "http" | "ws" => Some(80),
+        "https" | "wss" => Some(443),
+        _ => None
+    });
+
+    Some((scheme, host.to_ascii_lowercase(), port))
+}
+
 impl fmt::Display for UriPath {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         self.as_utf8_path_lossy().unwrap_or_else(|| "*".into()).fmt(f)
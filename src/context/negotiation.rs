@@ -0,0 +1,437 @@
+//Helpers for content negotiation.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use StatusCode;
+use header::{Headers, Accept, ContentType};
+use mime::{Mime, TopLevel, SubLevel};
+use response::Response;
+
+//The headers that can be recorded by `VaryHeaders`, in the order they are
+//added to `Vary`.
+const VARY_NAMES: [&'static str; 3] = ["Accept", "Accept-Encoding", "Accept-Language"];
+
+#[doc(hidden)]
+///Internal and may change without warning.
+#[derive(Clone, Debug, Default)]
+pub struct VaryHeaders(Arc<AtomicUsize>);
+
+impl VaryHeaders {
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn new() -> VaryHeaders {
+        VaryHeaders::default()
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn accept(&self) {
+        self.record(0);
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn accept_encoding(&self) {
+        self.record(1);
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn accept_language(&self) {
+        self.record(2);
+    }
+
+    fn record(&self, index: usize) {
+        self.0.fetch_or(1 << index, Ordering::Relaxed);
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn apply(&self, headers: &mut Headers) {
+        let recorded = self.0.load(Ordering::Relaxed);
+        if recorded == 0 {
+            return;
+        }
+
+        let mut fields: Vec<String> = vec![];
+        if let Some(values) = headers.get_raw("vary") {
+            for value in values {
+                for field in String::from_utf8_lossy(value).split(',') {
+                    let field = field.trim();
+                    if field == "*" {
+                        return;
+                    }
+                    if !field.is_empty() && !fields.iter().any(|f| f.eq_ignore_ascii_case(field)) {
+                        fields.push(field.to_owned());
+                    }
+                }
+            }
+        }
+
+        for (index, name) in VARY_NAMES.iter().enumerate() {
+            if recorded & (1 << index) != 0 && !fields.iter().any(|f| f.eq_ignore_ascii_case(name)) {
+                fields.push((*name).to_owned());
+            }
+        }
+
+        headers.set_raw("Vary", vec![fields.join(", ").into_bytes()]);
+    }
+}
+
+///The result of `Context::negotiate`.
+///
+///```
+///#[macro_use]
+///extern crate rustful;
+///use rustful::{Context, Response};
+///
+///fn my_handler(context: Context, mut response: Response) {
+///    let offers = [content_type!(Application / Json), content_type!(Text / Html)];
+///
+///    match context.negotiate(&offers).apply(&mut response) {
+///        Some(0) => response.send("{\"message\": \"hello\"}"),
+///        Some(_) => response.send("<p>hello</p>"),
+///        None => response.send("only JSON and HTML are available")
+///    }
+///}
+///# fn main() {}
+///```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Negotiated<'m> {
+    ///The offer at the index was chosen.
+    Accepted(usize, &'m Mime),
+
+    ///None of the offers are acceptable, and the response should be `406
+    ///Not Acceptable`.
+    NotAcceptable
+}
+
+impl<'m> Negotiated<'m> {
+    ///Get the index of the chosen offer, if any.
+    pub fn index(&self) -> Option<usize> {
+        match *self {
+            Negotiated::Accepted(index, _) => Some(index),
+            Negotiated::NotAcceptable => None
+        }
+    }
+
+    ///Get the chosen media type, if any.
+    pub fn media_type(&self) -> Option<&'m Mime> {
+        match *self {
+            Negotiated::Accepted(_, media_type) => Some(media_type),
+            Negotiated::NotAcceptable => None
+        }
+    }
+
+    ///Set `Content-Type` to the chosen media type, or the status to `406 Not
+    ///Acceptable` if nothing was chosen, and return the index of the chosen
+    ///offer. `Vary: Accept` has already been recorded by `negotiate`.
+    pub fn apply(&self, response: &mut Response) -> Option<usize> {
+        match *self {
+            Negotiated::Accepted(index, media_type) => {
+                response.headers_mut().set(ContentType(media_type.clone()));
+                Some(index)
+            },
+            Negotiated::NotAcceptable => {
+                response.set_status(StatusCode::NotAcceptable);
+                None
+            }
+        }
+    }
+}
+
+//How well a language range matches a language tag.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LanguageMatch {
+    //`*`
+    Wildcard,
+    //`en-US` for `en`
+    Fallback,
+    //`en` for `en-US`
+    Prefix,
+    //`en-US` for `en-US`
+    Exact
+}
+
+//Choose the best language from `available`, based on the `Accept-Language`
+//header. Languages with equal quality are chosen in the order they are
+//offered, unless one has a more precise match.
+pub fn preferred_language<'a>(headers: &Headers, available: &[&'a str]) -> Option<&'a str> {
+    let ranges = match headers.get_raw("accept-language") {
+        Some(values) => parse_ranges(values),
+        None => return None
+    };
+
+    let mut best: Option<(&str, u16, LanguageMatch)> = None;
+
+    for &language in available {
+        //The most specific range decides the quality.
+        let matched = ranges.iter()
+            .filter_map(|&(ref range, quality)| match_language(range, language).map(|m| (range.len(), m, quality)))
+            .max_by_key(|&(length, m, _)| (m != LanguageMatch::Wildcard, length, m));
+
+        if let Some((_, m, quality)) = matched {
+            let better = match best {
+                Some((_, best_quality, best_match)) => (quality, m) > (best_quality, best_match),
+                None => quality > 0
+            };
+
+            if better {
+                best = Some((language, quality, m));
+            }
+        }
+    }
+
+    best.map(|(language, _, _)| language)
+}
+
+//Choose the best media type from `available`, based on the `Accept` header.
+//Media types with equal quality are chosen in the order they are offered,
+//unless one has a more precise match.
+pub fn preferred_media_type<'a>(headers: &Headers, available: &'a [Mime]) -> Option<&'a Mime> {
+    let accept = match headers.get::<Accept>() {
+        Some(accept) => accept,
+        None => return None
+    };
+
+    let mut best: Option<(&Mime, u16, u8)> = None;
+
+    for media_type in available {
+        //The most specific range decides the quality.
+        let matched = accept.iter()
+            .filter_map(|range| match_media_type(&range.item, media_type).map(|m| (m, range.quality.0)))
+            .max_by_key(|&(m, _)| m);
+
+        if let Some((m, quality)) = matched {
+            let better = match best {
+                Some((_, best_quality, best_match)) => (quality, m) > (best_quality, best_match),
+                None => quality > 0
+            };
+
+            if better {
+                best = Some((media_type, quality, m));
+            }
+        }
+    }
+
+    best.map(|(media_type, _, _)| media_type)
+}
+
+//Check if `encoding` is acceptable, according to the `Accept-Encoding`
+//header. An explicit entry for the coding takes precedence over `*`, and
+//`identity` is acceptable unless it's excluded. Nothing else is acceptable
+//if the header is missing.
+pub fn accepts_encoding(headers: &Headers, encoding: &str) -> bool {
+    let identity = encoding.eq_ignore_ascii_case("identity");
+    let ranges = match headers.get_raw("accept-encoding") {
+        Some(values) => parse_ranges(values),
+        None => return identity
+    };
+
+    let explicit = ranges.iter().find(|&&(ref range, _)| range.eq_ignore_ascii_case(encoding));
+    let wildcard = ranges.iter().find(|&&(ref range, _)| range == "*");
+
+    match (explicit, wildcard) {
+        (Some(&(_, quality)), _) | (None, Some(&(_, quality))) => quality > 0,
+        (None, None) => identity
+    }
+}
+
+//Check if `media_type` is acceptable, according to the `Accept` header. The
+//most specific range decides, and anything is acceptable if the header is
+//missing.
+pub fn accepts_media_type(headers: &Headers, media_type: &Mime) -> bool {
+    let accept = match headers.get::<Accept>() {
+        Some(accept) => accept,
+        None => return !headers.has::<Accept>()
+    };
+
+    accept.iter()
+        .filter_map(|range| match_media_type(&range.item, media_type).map(|m| (m, range.quality.0)))
+        .max_by_key(|&(m, _)| m)
+        .map_or(false, |(_, quality)| quality > 0)
+}
+
+//How well a media range matches a media type, where a higher number is a
+//more precise match. Parameters are ignored.
+fn match_media_type(range: &Mime, media_type: &Mime) -> Option<u8> {
+    let (&Mime(ref top, ref sub, _), &Mime(ref media_top, ref media_sub, _)) = (range, media_type);
+
+    match (top, sub) {
+        (&TopLevel::Star, _) => Some(0),
+        (top, &SubLevel::Star) if top == media_top => Some(1),
+        (top, sub) if top == media_top && sub == media_sub => Some(2),
+        _ => None
+    }
+}
+
+fn match_language(range: &str, language: &str) -> Option<LanguageMatch> {
+    if range == "*" {
+        Some(LanguageMatch::Wildcard)
+    } else if range.eq_ignore_ascii_case(language) {
+        Some(LanguageMatch::Exact)
+    } else if is_subtag_prefix(range, language) {
+        Some(LanguageMatch::Prefix)
+    } else if is_subtag_prefix(language, range) {
+        Some(LanguageMatch::Fallback)
+    } else {
+        None
+    }
+}
+
+//Check if `prefix` is `tag`, minus one or more subtags.
+fn is_subtag_prefix(prefix: &str, tag: &str) -> bool {
+    tag.len() > prefix.len()
+        && tag.as_bytes()[prefix.len()] == b'-'
+        && tag[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+//Parse a list of `range;q=value` pairs. Invalid entries are skipped.
+fn parse_ranges(values: &[Vec<u8>]) -> Vec<(String, u16)> {
+    let mut ranges = vec![];
+
+    for value in values {
+        let value = String::from_utf8_lossy(value);
+        for entry in value.split(',') {
+            let mut parts = entry.split(';').map(str::trim);
+            let range = match parts.next() {
+                Some("") | None => continue,
+                Some(range) => range
+            };
+
+            let mut quality = Some(1000);
+            for parameter in parts {
+                if parameter.starts_with("q=") || parameter.starts_with("Q=") {
+                    quality = parse_quality(&parameter[2..]);
+                }
+            }
+
+            if let Some(quality) = quality {
+                ranges.push((range.to_owned(), quality));
+            }
+        }
+    }
+
+    ranges
+}
+
+fn parse_quality(quality: &str) -> Option<u16> {
+    match quality.parse::<f32>() {
+        Ok(quality) if (0.0..=1.0).contains(&quality) => Some((quality * 1000.0).round() as u16),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use header::Headers;
+    use super::{preferred_language, preferred_media_type, accepts_encoding, accepts_media_type, VaryHeaders};
+
+    fn accept(value: &str) -> Headers {
+        let mut headers = Headers::new();
+        headers.set_raw("Accept-Language", vec![value.as_bytes().to_vec()]);
+        headers
+    }
+
+    #[test]
+    fn quality() {
+        let headers = accept("fr;q=0.5, de, en;q=0.8");
+        assert_eq!(preferred_language(&headers, &["en", "fr"]), Some("en"));
+        assert_eq!(preferred_language(&headers, &["fr", "de"]), Some("de"));
+        assert_eq!(preferred_language(&headers, &["sv"]), None);
+    }
+
+    #[test]
+    fn ranges() {
+        let headers = accept("en-US, sv;q=0.5");
+        assert_eq!(preferred_language(&headers, &["sv", "en"]), Some("en"));
+        assert_eq!(preferred_language(&headers, &["en", "en-us"]), Some("en-us"));
+        assert_eq!(preferred_language(&headers, &["sv-FI", "fr"]), Some("sv-FI"));
+        assert_eq!(preferred_language(&headers, &["eng"]), None);
+    }
+
+    #[test]
+    fn wildcard() {
+        let headers = accept("de;q=0.9, *;q=0.5, fr;q=0");
+        assert_eq!(preferred_language(&headers, &["fr", "en", "de"]), Some("de"));
+        assert_eq!(preferred_language(&headers, &["fr", "en"]), Some("en"));
+        assert_eq!(preferred_language(&headers, &["fr"]), None);
+    }
+
+    #[test]
+    fn missing_header() {
+        assert_eq!(preferred_language(&Headers::new(), &["en"]), None);
+        assert_eq!(preferred_media_type(&Headers::new(), &[content_type!(Text / Html)]), None);
+    }
+
+    #[test]
+    fn media_types() {
+        let mut headers = Headers::new();
+        headers.set_raw("Accept", vec![b"text/html, application/xhtml+xml, application/*;q=0.9, */*;q=0.8".to_vec()]);
+
+        let html = content_type!(Text / Html);
+        let json = content_type!(Application / Json);
+        let png = content_type!(Image / Png);
+
+        assert_eq!(preferred_media_type(&headers, &[json.clone(), html.clone()]), Some(&html));
+        assert_eq!(preferred_media_type(&headers, &[png.clone(), json.clone()]), Some(&json));
+        assert_eq!(preferred_media_type(&headers, &[png.clone()]), Some(&png));
+
+        headers.set_raw("Accept", vec![b"application/json, */*;q=0".to_vec()]);
+        assert_eq!(preferred_media_type(&headers, &[html.clone(), json.clone()]), Some(&json));
+        assert_eq!(preferred_media_type(&headers, &[html, png]), None);
+    }
+
+    #[test]
+    fn encodings() {
+        let mut headers = Headers::new();
+        assert!(!accepts_encoding(&headers, "gzip"));
+        assert!(accepts_encoding(&headers, "identity"));
+
+        headers.set_raw("Accept-Encoding", vec![b"GZIP, br;q=0.5, deflate;q=0".to_vec()]);
+        assert!(accepts_encoding(&headers, "gzip"));
+        assert!(accepts_encoding(&headers, "br"));
+        assert!(!accepts_encoding(&headers, "deflate"));
+        assert!(!accepts_encoding(&headers, "compress"));
+        assert!(accepts_encoding(&headers, "identity"));
+
+        headers.set_raw("Accept-Encoding", vec![b"br, *;q=0".to_vec()]);
+        assert!(!accepts_encoding(&headers, "gzip"));
+        assert!(!accepts_encoding(&headers, "identity"));
+
+        headers.set_raw("Accept-Encoding", vec![b"*".to_vec()]);
+        assert!(accepts_encoding(&headers, "gzip"));
+    }
+
+    #[test]
+    fn acceptable_media_types() {
+        let mut headers = Headers::new();
+        assert!(accepts_media_type(&headers, &content_type!(Image / Png)));
+
+        headers.set_raw("Accept", vec![b"text/*, text/plain;q=0, application/json;q=0.1".to_vec()]);
+        assert!(accepts_media_type(&headers, &content_type!(Text / Html)));
+        assert!(!accepts_media_type(&headers, &content_type!(Text / Plain)));
+        assert!(accepts_media_type(&headers, &content_type!(Application / Json)));
+        assert!(!accepts_media_type(&headers, &content_type!(Image / Png)));
+    }
+
+    #[test]
+    fn merge_vary() {
+        let vary = VaryHeaders::new();
+        let mut headers = Headers::new();
+        vary.apply(&mut headers);
+        assert!(headers.get_raw("Vary").is_none());
+
+        vary.accept_language();
+        vary.accept();
+        vary.accept();
+        headers.set_raw("Vary", vec![b"Origin, accept".to_vec(), b"origin".to_vec()]);
+        vary.apply(&mut headers);
+        assert_eq!(headers.get_raw("Vary"), Some(&[b"Origin, accept, Accept-Language".to_vec()][..]));
+
+        headers.set_raw("Vary", vec![b"*".to_vec()]);
+        vary.apply(&mut headers);
+        assert_eq!(headers.get_raw("Vary"), Some(&[b"*".to_vec()][..]));
+    }
+}
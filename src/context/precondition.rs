@@ -0,0 +1,154 @@
+//Evaluation of conditional request headers.
+
+use Method;
+use header::{Headers, EntityTag, IfMatch, IfNoneMatch};
+use utils::{header_date, unix_seconds};
+
+///The outcome of `Context::precondition_check`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PreconditionResult {
+    ///The preconditions are fulfilled, or there are none, so the request
+    ///should be handled as usual.
+    Proceed,
+
+    ///The preconditions are not fulfilled, so the request should not be
+    ///applied, and the response should be `412 Precondition Failed`.
+    PreconditionFailed,
+
+    ///The client already has the current representation, so the response
+    ///should be `304 Not Modified`. This is only the result for `GET` and
+    ///`HEAD` requests.
+    NotModified,
+}
+
+//Evaluate the preconditions in the order from RFC 7232, section 6. The
+//resource exists if it has at least one validator. The modification time is
+//in whole seconds since the epoch, which is the precision of HTTP dates.
+pub fn check(method: &Method, headers: &Headers, etag: Option<&EntityTag>, last_modified: Option<i64>) -> PreconditionResult {
+    let exists = etag.is_some() || last_modified.is_some();
+    let safe = *method == Method::Get || *method == Method::Head;
+
+    if let Some(if_match) = headers.get::<IfMatch>() {
+        let matches = match *if_match {
+            IfMatch::Any => exists,
+            IfMatch::Items(ref tags) => etag.map_or(false, |etag| tags.iter().any(|tag| tag.strong_eq(etag)))
+        };
+
+        if !matches {
+            return PreconditionResult::PreconditionFailed;
+        }
+    } else if let (Some(since), Some(modified)) = (header_date(headers, "If-Unmodified-Since"), last_modified) {
+        if modified > unix_seconds(since) {
+            return PreconditionResult::PreconditionFailed;
+        }
+    }
+
+    let fresh = if let Some(if_none_match) = headers.get::<IfNoneMatch>() {
+        match *if_none_match {
+            IfNoneMatch::Any => exists,
+            IfNoneMatch::Items(ref tags) => etag.map_or(false, |etag| tags.iter().any(|tag| tag.weak_eq(etag)))
+        }
+    } else if !safe {
+        false
+    } else if let (Some(since), Some(modified)) = (header_date(headers, "If-Modified-Since"), last_modified) {
+        modified <= unix_seconds(since)
+    } else {
+        false
+    };
+
+    match (fresh, safe) {
+        (true, true) => PreconditionResult::NotModified,
+        (true, false) => PreconditionResult::PreconditionFailed,
+        (false, _) => PreconditionResult::Proceed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use Method;
+    use header::{Headers, EntityTag};
+    use utils::{parse_http_date, unix_seconds};
+    use super::{check, PreconditionResult};
+
+    fn headers(fields: &[(&str, &str)]) -> Headers {
+        let mut headers = Headers::new();
+        for &(name, value) in fields {
+            headers.set_raw(name.to_owned(), vec![value.as_bytes().to_vec()]);
+        }
+        headers
+    }
+
+    fn date(value: &str) -> i64 {
+        unix_seconds(parse_http_date(value).unwrap())
+    }
+
+    #[test]
+    fn if_match() {
+        let etag = EntityTag::strong("v2".to_owned());
+        let weak = EntityTag::weak("v2".to_owned());
+
+        let matching = headers(&[("If-Match", "\"v1\", \"v2\"")]);
+        assert_eq!(check(&Method::Put, &matching, Some(&etag), None), PreconditionResult::Proceed);
+        assert_eq!(check(&Method::Put, &matching, Some(&weak), None), PreconditionResult::PreconditionFailed);
+
+        let mismatching = headers(&[("If-Match", "\"v1\"")]);
+        assert_eq!(check(&Method::Put, &mismatching, Some(&etag), None), PreconditionResult::PreconditionFailed);
+        assert_eq!(check(&Method::Delete, &mismatching, None, None), PreconditionResult::PreconditionFailed);
+    }
+
+    #[test]
+    fn wildcards() {
+        let etag = EntityTag::strong("v2".to_owned());
+
+        let any = headers(&[("If-Match", "*")]);
+        assert_eq!(check(&Method::Put, &any, Some(&etag), None), PreconditionResult::Proceed);
+        assert_eq!(check(&Method::Put, &any, None, None), PreconditionResult::PreconditionFailed);
+
+        let none = headers(&[("If-None-Match", "*")]);
+        assert_eq!(check(&Method::Put, &none, None, None), PreconditionResult::Proceed);
+        assert_eq!(check(&Method::Put, &none, Some(&etag), None), PreconditionResult::PreconditionFailed);
+        assert_eq!(check(&Method::Get, &none, Some(&etag), None), PreconditionResult::NotModified);
+    }
+
+    #[test]
+    fn dates() {
+        let modified = Some(date("Sun, 06 Nov 1994 08:49:37 GMT"));
+
+        let before = headers(&[("If-Unmodified-Since", "Sat, 05 Nov 1994 08:49:37 GMT")]);
+        assert_eq!(check(&Method::Put, &before, None, modified), PreconditionResult::PreconditionFailed);
+
+        let after = headers(&[("If-Unmodified-Since", "Sun, 06 Nov 1994 08:49:37 GMT")]);
+        assert_eq!(check(&Method::Put, &after, None, modified), PreconditionResult::Proceed);
+
+        let since = headers(&[("If-Modified-Since", "Sun, 06 Nov 1994 08:49:37 GMT")]);
+        assert_eq!(check(&Method::Get, &since, None, modified), PreconditionResult::NotModified);
+        assert_eq!(check(&Method::Put, &since, None, modified), PreconditionResult::Proceed);
+
+        let obsolete = headers(&[("If-Modified-Since", "Sunday, 06-Nov-94 08:49:37 GMT")]);
+        assert_eq!(check(&Method::Get, &obsolete, None, modified), PreconditionResult::NotModified);
+
+        let asctime = headers(&[("If-Modified-Since", "Sun Nov  6 08:49:36 1994")]);
+        assert_eq!(check(&Method::Get, &asctime, None, modified), PreconditionResult::Proceed);
+
+        let malformed = headers(&[("If-Unmodified-Since", "yesterday")]);
+        assert_eq!(check(&Method::Put, &malformed, None, modified), PreconditionResult::Proceed);
+    }
+
+    #[test]
+    fn if_match_overrides_date() {
+        let etag = EntityTag::strong("v2".to_owned());
+        let modified = Some(date("Sun, 06 Nov 1994 08:49:37 GMT"));
+
+        let fields = headers(&[
+            ("If-Match", "\"v2\""),
+            ("If-Unmodified-Since", "Sat, 05 Nov 1994 08:49:37 GMT")
+        ]);
+        assert_eq!(check(&Method::Put, &fields, Some(&etag), modified), PreconditionResult::Proceed);
+
+        let fields = headers(&[
+            ("If-None-Match", "W/\"v2\""),
+            ("If-Modified-Since", "Sat, 05 Nov 1994 08:49:37 GMT")
+        ]);
+        assert_eq!(check(&Method::Get, &fields, Some(&etag), modified), PreconditionResult::NotModified);
+    }
+}
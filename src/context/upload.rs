@@ -0,0 +1,333 @@
+//!Saving `multipart/form-data` uploads to disk.
+//!
+//!See [`BodyReader::save_multipart`][save_multipart] for more information.
+//!
+//![save_multipart]: ../body/struct.BodyReader.html#method.save_multipart
+
+use std::io::{self, Read, Write, BufWriter};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::env;
+use std::error;
+use std::fmt;
+
+use multipart::server::{Multipart, MultipartField, MultipartData};
+
+use time;
+
+use mime::Mime;
+use context::Parameters;
+
+///Settings for saving uploaded files.
+#[derive(Clone, Debug)]
+pub struct UploadSettings {
+    ///The directory where the files will be saved. Default is the system's
+    ///temporary directory.
+    pub directory: PathBuf,
+
+    ///The maximal size of each file. Default is `None`, meaning no limit.
+    pub part_limit: Option<u64>,
+
+    ///The maximal total size of all files and fields. Default is `None`,
+    ///meaning no limit.
+    pub total_limit: Option<u64>
+}
+
+impl UploadSettings {
+    ///Create the default settings. This is the same as `UploadSettings::default()`.
+    pub fn new() -> UploadSettings {
+        UploadSettings::default()
+    }
+}
+
+impl Default for UploadSettings {
+    fn default() -> UploadSettings {
+        UploadSettings {
+            directory: env::temp_dir(),
+            part_limit: None,
+            total_limit: None
+        }
+    }
+}
+
+///The saved content of a `multipart/form-data` request.
+#[derive(Debug)]
+pub struct Upload {
+    ///The text fields.
+    pub fields: Parameters,
+
+    ///The saved files.
+    pub files: Vec<UploadedFile>
+}
+
+///A file that has been saved to disk.
+///
+///The file will not be removed automatically, so it's up to the handler to
+///move or remove it when it's done.
+#[derive(Debug)]
+pub struct UploadedFile {
+    ///The name of the form field.
+    pub field: String,
+
+    ///Where the file was saved.
+    pub path: PathBuf,
+
+    ///The original filename, if provided by the client. It should not be
+    ///trusted, and should not be used as a path without validating it.
+    pub filename: Option<String>,
+
+    ///The content type, as provided by the client.
+    pub content_type: Mime,
+
+    ///The size of the file, in bytes.
+    pub size: u64
+}
+
+///An error that occurred while saving an upload.
+#[derive(Debug)]
+pub enum UploadError {
+    ///The request is not a `multipart/form-data` request.
+    NotMultipart,
+
+    ///A file was larger than `part_limit`. The name of the field is included.
+    PartTooLarge(String),
+
+    ///The content of the request was larger than `total_limit`.
+    TooLarge,
+
+    ///The request could not be read, or a file could not be written.
+    Io(io::Error)
+}
+
+impl From<io::Error> for UploadError {
+    fn from(err: io::Error) -> UploadError {
+        UploadError::Io(err)
+    }
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UploadError::NotMultipart => write!(f, "not a multipart request"),
+            UploadError::PartTooLarge(ref field) => write!(f, "the file in '{}' is too large", field),
+            UploadError::TooLarge => write!(f, "the upload is too large"),
+            UploadError::Io(ref e) => write!(f, "io error: {}", e)
+        }
+    }
+}
+
+impl error::Error for UploadError {
+    fn description(&self) -> &str {
+        match *self {
+            UploadError::NotMultipart => "not a multipart request",
+            UploadError::PartTooLarge(_) => "a file is too large",
+            UploadError::TooLarge => "the upload is too large",
+            UploadError::Io(ref e) => e.description()
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            UploadError::Io(ref e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+#[doc(hidden)]
+///Internal and may change without warning.
+pub fn save<B: Read>(multipart: &mut Multipart<B>, settings: &UploadSettings) -> Result<Upload, UploadError> {
+    let mut upload = Upload {
+        fields: Parameters::new(),
+        files: vec![]
+    };
+
+    match save_entries(multipart, settings, &mut upload) {
+        Ok(()) => Ok(upload),
+        Err(e) => {
+            for file in &upload.files {
+                let _ = fs::remove_file(&file.path);
+            }
+            Err(e)
+        }
+    }
+}
+
+fn save_entries<B: Read>(multipart: &mut Multipart<B>, settings: &UploadSettings, upload: &mut Upload) -> Result<(), UploadError> {
+    let mut total = 0;
+
+    while let Some(MultipartField { name, data }) = try!(multipart.read_entry()) {
+        match data {
+            MultipartData::Text(text) => {
+                total += text.len() as u64;
+                if settings.total_limit.map_or(false, |limit| total > limit) {
+                    return Err(UploadError::TooLarge);
+                }
+                upload.fields.insert(name, text.to_owned());
+            },
+            MultipartData::File(mut file) => {
+                let (path, output) = try!(create_file(&settings.directory));
+                upload.files.push(UploadedFile {
+                    field: name.clone(),
+                    path: path,
+                    filename: file.filename().map(|filename| filename.to_owned()),
+                    content_type: file.content_type().clone(),
+                    size: 0
+                });
+
+                let remaining = settings.total_limit.map(|limit| limit.saturating_sub(total));
+                let limit = match (settings.part_limit, remaining) {
+                    (Some(part), Some(remaining)) => Some(::std::cmp::min(part, remaining)),
+                    (part, remaining) => part.or(remaining)
+                };
+
+                let size = try!(copy_limited(&mut file, output, limit));
+                if limit.map_or(false, |limit| size > limit) {
+                    return if settings.part_limit.map_or(false, |part| size > part) {
+                        Err(UploadError::PartTooLarge(name))
+                    } else {
+                        Err(UploadError::TooLarge)
+                    };
+                }
+
+                total += size;
+                if let Some(uploaded) = upload.files.last_mut() {
+                    uploaded.size = size;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+//Copies at most one byte more than `limit`, to detect overflows.
+fn copy_limited<R: Read>(input: &mut R, output: File, limit: Option<u64>) -> io::Result<u64> {
+    let mut output = BufWriter::new(output);
+    let size = match limit {
+        Some(limit) => try!(io::copy(&mut input.by_ref().take(limit + 1), &mut output)),
+        None => try!(io::copy(input, &mut output))
+    };
+    try!(output.flush());
+    Ok(size)
+}
+
+static FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn create_file(directory: &Path) -> io::Result<(PathBuf, File)> {
+    try!(fs::create_dir_all(directory));
+
+    loop {
+        let name = format!(
+            "rustful-upload-{}-{}",
+            time::precise_time_ns(),
+            FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = directory.join(name);
+
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{self, File};
+    use std::io::Read;
+    use std::env;
+
+    use hyper::buffer::BufReader;
+    use hyper::http::h1::HttpReader;
+    use hyper::net::NetworkStream;
+
+    use header::{Headers, ContentType};
+    use mock::MockStream;
+    use context::body::BodyReader;
+    use super::{Upload, UploadSettings, UploadError};
+
+    const BODY: &'static str = "--boundary\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        holiday\r\n\
+        --boundary\r\n\
+        Content-Disposition: form-data; name=\"first\"; filename=\"a.txt\"\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        first file\r\n\
+        --boundary\r\n\
+        Content-Disposition: form-data; name=\"second\"; filename=\"b.txt\"\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        second file\r\n\
+        --boundary--\r\n";
+
+    fn save(directory: &str, settings: UploadSettings) -> Result<Upload, UploadError> {
+        let mut stream = MockStream::new(BODY);
+        let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+        let mut headers = Headers::new();
+        headers.set(ContentType(content_type!(Multipart / FormData; Boundary = "boundary")));
+
+        let mut body = BodyReader::from_reader(HttpReader::SizedReader(&mut reader, BODY.len() as u64), &headers);
+        body.save_multipart(&UploadSettings {
+            directory: env::temp_dir().join(directory),
+            ..settings
+        })
+    }
+
+    fn count_files(directory: &str) -> usize {
+        fs::read_dir(env::temp_dir().join(directory)).map(|entries| entries.count()).unwrap_or(0)
+    }
+
+    #[test]
+    fn save_files() {
+        let upload = save("rustful-test-save-files", UploadSettings::default()).unwrap();
+        assert_eq!(upload.fields.get("title").as_ref().map(|t| &**t), Some("holiday"));
+        assert_eq!(upload.files.len(), 2);
+
+        let file = &upload.files[1];
+        assert_eq!(file.field, "second");
+        assert_eq!(file.filename.as_ref().map(|f| &**f), Some("b.txt"));
+        assert_eq!(file.content_type, content_type!(Text / Plain));
+        assert_eq!(file.size, 11);
+
+        let mut content = String::new();
+        File::open(&file.path).unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "second file");
+
+        for file in &upload.files {
+            fs::remove_file(&file.path).unwrap();
+        }
+    }
+
+    #[test]
+    fn part_too_large() {
+        let result = save("rustful-test-part-too-large", UploadSettings {
+            part_limit: Some(10),
+            ..UploadSettings::default()
+        });
+
+        match result {
+            Err(UploadError::PartTooLarge(ref field)) => assert_eq!(field, "second"),
+            other => panic!("unexpected result: {:?}", other)
+        }
+        assert_eq!(count_files("rustful-test-part-too-large"), 0);
+    }
+
+    #[test]
+    fn total_too_large() {
+        let result = save("rustful-test-total-too-large", UploadSettings {
+            total_limit: Some(20),
+            ..UploadSettings::default()
+        });
+
+        match result {
+            Err(UploadError::TooLarge) => {},
+            other => panic!("unexpected result: {:?}", other)
+        }
+        assert_eq!(count_files("rustful-test-total-too-large"), 0);
+    }
+}
@@ -0,0 +1,261 @@
+//!Handlers that get their arguments from the request.
+//!
+//!A function that takes one or more `FromRequest` arguments, and returns a
+//!`Responder`, can be turned into a handler with `Extracting`. Each argument
+//!is extracted from the request before the function is called, and the
+//!request is rejected with a `4xx` status code if any of them can't be
+//!extracted, so the function only has to deal with valid input.
+//!
+//!```
+//!#[macro_use]
+//!extern crate rustful;
+//!
+//!use std::collections::BTreeMap;
+//!use rustful::{Server, TreeRouter, StatusCode};
+//!use rustful::extract::{Extracting, Path, Json};
+//!
+//!fn create(Path(group): Path<u64>, Json(user): Json<BTreeMap<String, String>>) -> (StatusCode, String) {
+//!    let name = user.get("name").map(|name| &**name).unwrap_or("someone");
+//!    (StatusCode::Created, format!("added {} to group {}", name, group))
+//!}
+//!
+//!# fn main() {
+//!let router = insert_routes! {
+//!    TreeRouter::new() => {
+//!        "/groups/:id/users" => Post: Extracting::new(create)
+//!    }
+//!};
+//!
+//!let server = Server::new(router);
+//!# }
+//!```
+//!
+//!Functions with up to four arguments are supported. The body can only be
+//!read once, so there should only be one body extractor, like `Json`, `Form`
+//!or `Typed`, and it should be last.
+
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+#[cfg(feature = "rustc_json_body")]
+use rustc_serialize::Decodable;
+#[cfg(feature = "rustc_json_body")]
+use rustc_serialize::json::{self, DecoderError};
+
+#[cfg(feature = "rustc_json_body")]
+use mime::{Mime, TopLevel, SubLevel};
+
+use context::Context;
+#[cfg(feature = "rustc_json_body")]
+use context::{FormData, form_to_json};
+use handler::Handler;
+use response::{Response, Responder};
+use StatusCode;
+
+#[cfg(feature = "rustc_json_body")]
+pub use response::Json;
+
+///A value that can be extracted from a request.
+///
+///```
+///use rustful::{Context, StatusCode};
+///use rustful::extract::FromRequest;
+///
+///struct ApiKey(String);
+///
+///impl FromRequest for ApiKey {
+///    fn from_request(context: &mut Context) -> Result<ApiKey, StatusCode> {
+///        match context.headers.get_raw("X-Api-Key") {
+///            Some(values) => Ok(ApiKey(String::from_utf8_lossy(&values[0]).into_owned())),
+///            None => Err(StatusCode::Unauthorized)
+///        }
+///    }
+///}
+///```
+pub trait FromRequest: Sized {
+    ///Extract the value from `context`, or return the status code that the
+    ///request should be rejected with.
+    fn from_request(context: &mut Context) -> Result<Self, StatusCode>;
+}
+
+///The route variable, parsed as `T`.
+///
+///The route has to have exactly one variable, such as `/users/:id`. Requests
+///are rejected with `404 Not Found` if it can't be parsed, since the path
+///doesn't point to anything, and with `500 Internal Server Error` if the
+///route has no variable, or more than one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Path<T>(pub T);
+
+impl<T: FromStr> FromRequest for Path<T> {
+    fn from_request(context: &mut Context) -> Result<Path<T>, StatusCode> {
+        if context.variables.len() != 1 {
+            error!("a Path<T> argument requires a route with exactly one variable, but the route has {}", context.variables.len());
+            return Err(StatusCode::InternalServerError);
+        }
+
+        let value = context.variables.values().next().and_then(|value| value.as_utf8());
+        match value.map(str::parse) {
+            Some(Ok(value)) => Ok(Path(value)),
+            _ => Err(StatusCode::NotFound)
+        }
+    }
+}
+
+///The query, decoded as `T`.
+///
+///Each variable is decoded as a string, which can also be parsed as a
+///number, and the fields that may be missing should be `Option`s. Variables
+///that are repeated, or that end with `[]`, become lists, so `tag=a&tag=b`
+///and `tags[]=a` can be decoded into `Vec`s. Requests that can't be decoded
+///are rejected with `400 Bad Request`.
+///
+///```
+///use std::collections::HashMap;
+///use rustful::extract::{Extracting, Query};
+///
+///fn search(Query(query): Query<HashMap<String, String>>) -> String {
+///    format!("searching for {}", query.get("q").map(|q| &**q).unwrap_or(""))
+///}
+///
+///let handler = Extracting::new(search);
+///```
+#[cfg(feature = "rustc_json_body")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Query<T>(pub T);
+
+#[cfg(feature = "rustc_json_body")]
+impl<T: Decodable> FromRequest for Query<T> {
+    fn from_request(context: &mut Context) -> Result<Query<T>, StatusCode> {
+        decode_form(&context.query_form_data()).map(Query)
+    }
+}
+
+///An `application/x-www-form-urlencoded` request body, decoded as `T`.
+///
+///The fields are decoded in the same way as in `Query`. Requests with
+///another content type are rejected with `415 Unsupported Media Type`, and
+///requests that can't be decoded are rejected with `400 Bad Request`.
+#[cfg(feature = "rustc_json_body")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Form<T>(pub T);
+
+#[cfg(feature = "rustc_json_body")]
+impl<T: Decodable> FromRequest for Form<T> {
+    fn from_request(context: &mut Context) -> Result<Form<T>, StatusCode> {
+        match context.content_type() {
+            Some(Mime(TopLevel::Application, SubLevel::WwwFormUrlEncoded, _)) => {},
+            _ => return Err(StatusCode::UnsupportedMediaType)
+        }
+
+        match context.body.read_form_data() {
+            Ok(form) => decode_form(&form).map(Form),
+            Err(_) => Err(StatusCode::BadRequest)
+        }
+    }
+}
+
+///A JSON request body, decoded as `T`.
+///
+///Requests with another content type are rejected with `415 Unsupported
+///Media Type`, requests with malformed JSON are rejected with `400 Bad
+///Request`, and requests with JSON that doesn't fit `T` are rejected with
+///`422 Unprocessable Entity`.
+#[cfg(feature = "rustc_json_body")]
+impl<T: Decodable> FromRequest for Json<T> {
+    fn from_request(context: &mut Context) -> Result<Json<T>, StatusCode> {
+        match context.content_type() {
+            Some(Mime(TopLevel::Application, SubLevel::Json, _)) => {},
+            _ => return Err(StatusCode::UnsupportedMediaType)
+        }
+
+        match context.body.decode_json_body() {
+            Ok(value) => Ok(Json(value)),
+            Err(DecoderError::ParseError(_)) => Err(StatusCode::BadRequest),
+            Err(_) => Err(StatusCode::UnprocessableEntity)
+        }
+    }
+}
+
+///A request body, decoded as `T` by the deserializer for its content type,
+///as in `Context::read_typed`.
+///
+///This makes it possible to accept the same input in more than one format,
+///like JSON and MessagePack, with the same function. Requests are rejected
+///with the status code from `Context::read_typed` if the body can't be
+///decoded.
+#[cfg(feature = "rustc_json_body")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Typed<T>(pub T);
+
+#[cfg(feature = "rustc_json_body")]
+impl<T: Decodable> FromRequest for Typed<T> {
+    fn from_request(context: &mut Context) -> Result<Typed<T>, StatusCode> {
+        context.read_typed().map(Typed)
+    }
+}
+
+#[cfg(feature = "rustc_json_body")]
+fn decode_form<T: Decodable>(form: &FormData) -> Result<T, StatusCode> {
+    let mut decoder = json::Decoder::new(form_to_json(form));
+    Decodable::decode(&mut decoder).map_err(|_| StatusCode::BadRequest)
+}
+
+///A function that can be called with values that are extracted from a
+///request. It's implemented for functions and closures with up to four
+///`FromRequest` arguments, that return a `Responder`.
+pub trait ExtractFn<A>: Send + Sync + 'static {
+    ///Extract the arguments from `context` and call the function, or reject
+    ///the request if an argument can't be extracted.
+    fn call(&self, context: Context, response: Response);
+}
+
+macro_rules! impl_extract_fn {
+    ($($ty: ident: $arg: ident),+) => {
+        impl<F, R, $($ty),+> ExtractFn<($($ty,)+)> for F where
+            F: Fn($($ty),+) -> R + Send + Sync + 'static,
+            R: Responder,
+            $($ty: FromRequest),+
+        {
+            fn call(&self, mut context: Context, response: Response) {
+                $(
+                    let $arg = match $ty::from_request(&mut context) {
+                        Ok(value) => value,
+                        Err(status) => return status.respond(response)
+                    };
+                )+
+
+                self($($arg),+).respond(response);
+            }
+        }
+    }
+}
+
+impl_extract_fn!(A: a);
+impl_extract_fn!(A: a, B: b);
+impl_extract_fn!(A: a, B: b, C: c);
+impl_extract_fn!(A: a, B: b, C: c, D: d);
+
+///A handler that extracts the arguments of a function from the request.
+///
+///See the [module level documentation](index.html) for an example.
+pub struct Extracting<F, A> {
+    handler: F,
+    arguments: PhantomData<fn(A)>,
+}
+
+impl<F: ExtractFn<A>, A> Extracting<F, A> {
+    ///Create a handler that calls `handler` with extracted arguments.
+    pub fn new(handler: F) -> Extracting<F, A> {
+        Extracting {
+            handler: handler,
+            arguments: PhantomData,
+        }
+    }
+}
+
+impl<F: ExtractFn<A>, A: 'static> Handler for Extracting<F, A> {
+    fn handle_request(&self, context: Context, response: Response) {
+        self.handler.call(context, response);
+    }
+}
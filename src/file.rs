@@ -1,8 +1,24 @@
 //!File related utilities.
 
-use std::path::{Path, Component};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::fs;
+use std::hash::Hasher;
+use std::io;
+use std::path::{Path, PathBuf, Component};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use mime::{Mime, TopLevel, SubLevel};
+use mime::{Mime, TopLevel, SubLevel, Attr, Value};
+
+use StatusCode;
+use context::Context;
+use handler::Handler;
+use header::{CacheControl, CacheDirective, ContentType, ETag, EntityTag, IfNoneMatch, Location};
+use response::{Response, Error as ResponseError};
+use utils::{html_escape, format_http_date, unix_seconds, contains_escapes};
 
 include!(concat!(env!("OUT_DIR"), "/mime.rs"));
 
@@ -28,6 +44,51 @@ pub fn ext_to_mime(ext: &str) -> Option<Mime> {
     })
 }
 
+///Create a `Content-Disposition` value that makes browsers save the response
+///as a file called `filename`, instead of showing it.
+///
+///Quotes, backslashes and control characters can't be safely included in
+///the plain `filename` parameter, so they are replaced with `_`, just like
+///any non-ASCII characters. The full name is then included as an RFC 5987
+///encoded `filename*` parameter, which is preferred by browsers that
+///understand it. Control characters are removed from it.
+///
+///```
+///use rustful::file::content_disposition;
+///
+///assert_eq!(content_disposition("report.pdf"), "attachment; filename=\"report.pdf\"");
+///assert_eq!(
+///    content_disposition("résumé.pdf"),
+///    "attachment; filename=\"r_sum_.pdf\"; filename*=UTF-8''r%C3%A9sum%C3%A9.pdf"
+///);
+///```
+pub fn content_disposition(filename: &str) -> String {
+    let fallback: String = filename.chars().map(|c| match c {
+        '"' | '\\' => '_',
+        c if c.is_control() || !c.is_ascii() => '_',
+        c => c
+    }).collect();
+
+    if fallback == filename {
+        return format!("attachment; filename=\"{}\"", fallback);
+    }
+
+    let mut encoded = String::new();
+    for c in filename.chars().filter(|c| !c.is_control()) {
+        match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '!' | '#' | '$' | '&' | '+' | '-' | '.' | '^' | '_' | '`' | '|' | '~' => encoded.push(c),
+            c => {
+                let mut buffer = [0; 4];
+                for byte in c.encode_utf8(&mut buffer).bytes() {
+                    encoded.push_str(&format!("%{:02X}", byte));
+                }
+            }
+        }
+    }
+
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, encoded)
+}
+
 enum MaybeKnown<T> {
     Known(T),
     Unknown(&'static str)
@@ -86,3 +147,814 @@ pub fn check_path<P: ?Sized + AsRef<Path>>(path: &P) -> Result<(), Component> {
 
     Ok(())
 }
+
+///A file that is kept in memory, for `EmbeddedFiles`.
+#[derive(Clone, Debug)]
+pub struct EmbeddedFile {
+    content: Cow<'static, [u8]>,
+    content_type: Mime,
+    last_modified: Option<SystemTime>,
+    etag: EntityTag,
+}
+
+impl EmbeddedFile {
+    ///Create an embedded file from its content and media type. The ETag is
+    ///a hash of the content.
+    pub fn new<C: Into<Cow<'static, [u8]>>>(content: C, content_type: Mime) -> EmbeddedFile {
+        let content = content.into();
+        let mut hasher = DefaultHasher::new();
+        hasher.write(&content);
+
+        EmbeddedFile {
+            content: content,
+            content_type: content_type,
+            last_modified: None,
+            etag: EntityTag::strong(format!("{:016x}", hasher.finish())),
+        }
+    }
+
+    ///Set the time when the file was last modified. It will be rounded down
+    ///to whole seconds.
+    pub fn last_modified(mut self, time: SystemTime) -> EmbeddedFile {
+        self.last_modified = Some(time);
+        self
+    }
+
+    ///Borrow the content of the file.
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+
+    ///Get the ETag of the file.
+    pub fn etag(&self) -> &EntityTag {
+        &self.etag
+    }
+
+    //Set the validators and check if the client's cached copy is fresh.
+    fn set_validators(&self, context: &Context, response: &mut Response) -> bool {
+        set_validators(context, response, &self.etag, self.last_modified)
+    }
+}
+
+//Set `ETag` and `Last-Modified` and check if the client's cached copy is
+//still fresh. `If-Modified-Since` is ignored if `If-None-Match` is present.
+fn set_validators(context: &Context, response: &mut Response, etag: &EntityTag, last_modified: Option<SystemTime>) -> bool {
+    response.headers_mut().set(ETag(etag.clone()));
+    if let Some(modified) = last_modified {
+        response.set_last_modified(modified);
+    }
+
+    if let Some(if_none_match) = context.headers.get::<IfNoneMatch>() {
+        return match *if_none_match {
+            IfNoneMatch::Any => true,
+            IfNoneMatch::Items(ref tags) => tags.iter().any(|tag| tag.weak_eq(etag))
+        };
+    }
+
+    match (context.if_modified_since(), last_modified) {
+        (Some(since), Some(modified)) => unix_seconds(modified) <= unix_seconds(since),
+        _ => false
+    }
+}
+
+///A handler that serves files from memory, for self-contained binaries.
+///
+///The files are looked up using a route variable, which is `path` by
+///default, so the handler should be inserted with a variable sequence, like
+///`"assets/*path"`. Files that are not found results in `404 Not Found`.
+///
+///Each response has an `ETag` and, if known, a `Last-Modified` header, and
+///conditional requests with `If-None-Match` or `If-Modified-Since` are
+///answered with `304 Not Modified` when the client already has the file.
+///Add the `Compression` filter to the server to have the files compressed.
+///
+///```
+///#[macro_use] extern crate rustful;
+///use rustful::{Router, TreeRouter};
+///use rustful::file::{EmbeddedFiles, EmbeddedFile};
+///use rustful::Method::Get;
+///
+///# fn main() {
+///let mut files = EmbeddedFiles::new();
+///files.insert("css/style.css", EmbeddedFile::new(
+///    &b"body { color: black; }"[..],
+///    content_type!(Text / Css; Charset = Utf8)
+///));
+///
+///let mut router = TreeRouter::new();
+///router.insert(Get, "/assets/*path", files);
+///# }
+///```
+#[derive(Clone, Debug)]
+pub struct EmbeddedFiles {
+    ///The route variable that holds the path of the file. Default is
+    ///`"path"`.
+    pub variable: Cow<'static, str>,
+
+    files: HashMap<String, EmbeddedFile>,
+}
+
+impl EmbeddedFiles {
+    ///Create an empty collection of files.
+    pub fn new() -> EmbeddedFiles {
+        EmbeddedFiles {
+            variable: "path".into(),
+            files: HashMap::new(),
+        }
+    }
+
+    ///Add a file, possibly replacing a previous one with the same path.
+    ///Leading slashes in the path are ignored.
+    pub fn insert<P: Into<String>>(&mut self, path: P, file: EmbeddedFile) {
+        let path = path.into();
+        let path = path.trim_start_matches('/').to_owned();
+        self.files.insert(path, file);
+    }
+
+    ///Get a file from its path.
+    pub fn get(&self, path: &str) -> Option<&EmbeddedFile> {
+        self.files.get(path.trim_start_matches('/'))
+    }
+}
+
+impl Default for EmbeddedFiles {
+    fn default() -> EmbeddedFiles {
+        EmbeddedFiles::new()
+    }
+}
+
+impl Handler for EmbeddedFiles {
+    fn handle_request(&self, context: Context, mut response: Response) {
+        let file = context.variables.get(&*self.variable).and_then(|path| self.get(&path));
+        let file = match file {
+            Some(file) => file,
+            None => {
+                response.set_status(StatusCode::NotFound);
+                return;
+            }
+        };
+
+        if file.set_validators(&context, &mut response) {
+            response.set_status(StatusCode::NotModified);
+            return;
+        }
+
+        response.headers_mut().set(ContentType(file.content_type.clone()));
+        response.send(&file.content[..]);
+    }
+}
+
+///A handler that serves the same file for every request, like `favicon.ico`
+///or `robots.txt`.
+///
+///The request path is ignored, so the handler is inserted at the route of
+///the file. The file is either read from the file system for each request,
+///or kept in memory as an `EmbeddedFile`, for self-contained binaries. It's
+///served with an `ETag` and a `Last-Modified` header, and conditional
+///requests are answered with `304 Not Modified`, just like for
+///`EmbeddedFiles`. The ETag of a file from the file system is made from its
+///size and modification time. A missing file results in `404 Not Found`.
+///
+///```
+///#[macro_use] extern crate rustful;
+///use std::time::Duration;
+///use rustful::{Router, TreeRouter};
+///use rustful::file::{SingleFile, EmbeddedFile};
+///use rustful::Method::Get;
+///
+///# fn main() {
+///let robots = SingleFile::embedded(EmbeddedFile::new(
+///    &b"User-agent: *\nDisallow: /admin/\n"[..],
+///    content_type!(Text / Plain; Charset = Utf8)
+///));
+///
+///let mut router = TreeRouter::new();
+///router.insert(Get, "/robots.txt", robots);
+///router.insert(Get, "/favicon.ico", SingleFile::new("static/favicon.ico").max_age(Duration::from_secs(86400)));
+///# }
+///```
+#[derive(Clone, Debug)]
+pub struct SingleFile {
+    source: FileSource,
+    max_age: Option<Duration>,
+    immutable: bool,
+}
+
+#[derive(Clone, Debug)]
+enum FileSource {
+    Path(PathBuf, Mime),
+    Embedded(EmbeddedFile),
+}
+
+impl SingleFile {
+    ///Serve the file at `path`. The media type is guessed from the file
+    ///extension, using `ext_to_mime`, and it's
+    ///`application/octet-stream` if it's unknown.
+    pub fn new<P: Into<PathBuf>>(path: P) -> SingleFile {
+        let path = path.into();
+        let mime = path
+            .extension()
+            .and_then(|ext| ext_to_mime(&ext.to_string_lossy()))
+            .unwrap_or_else(|| Mime(TopLevel::Application, SubLevel::Ext("octet-stream".into()), vec![]));
+
+        SingleFile {
+            source: FileSource::Path(path, mime),
+            max_age: None,
+            immutable: false,
+        }
+    }
+
+    ///Serve a file from memory.
+    pub fn embedded(file: EmbeddedFile) -> SingleFile {
+        SingleFile {
+            source: FileSource::Embedded(file),
+            max_age: None,
+            immutable: false,
+        }
+    }
+
+    ///Set the media type, instead of guessing it from the file extension.
+    ///This has no effect on embedded files, since they already have one.
+    pub fn content_type(mut self, content_type: Mime) -> SingleFile {
+        if let FileSource::Path(_, ref mut mime) = self.source {
+            *mime = content_type;
+        }
+        self
+    }
+
+    ///Let clients cache the file for `max_age`, using `Cache-Control:
+    ///public, max-age=...`. No `Cache-Control` header is sent by default.
+    pub fn max_age(mut self, max_age: Duration) -> SingleFile {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    ///Tell clients that the file will never change while it's fresh, by
+    ///adding `immutable` from RFC 8246 to `Cache-Control`. This is meant for
+    ///files with a hash of their content in their names, and it makes
+    ///supporting clients skip even the conditional requests that they
+    ///would otherwise send when the user reloads the page. Conditional
+    ///requests that are sent anyway are still answered with `304 Not
+    ///Modified`.
+    ///
+    ///It only has an effect together with `max_age`, since `immutable` is
+    ///limited to the freshness lifetime of the response. A `CacheHandler` in
+    ///front of the file ignores it, and keeps using `max-age` as the upper
+    ///limit for how long the response is stored.
+    ///
+    ///```
+    ///use std::time::Duration;
+    ///use rustful::file::SingleFile;
+    ///
+    ///let app = SingleFile::new("static/app.3f9a1c.js")
+    ///    .max_age(Duration::from_secs(31536000))
+    ///    .immutable(true);
+    ///```
+    pub fn immutable(mut self, immutable: bool) -> SingleFile {
+        self.immutable = immutable;
+        self
+    }
+}
+
+impl Handler for SingleFile {
+    fn handle_request(&self, context: Context, mut response: Response) {
+        if let Some(max_age) = self.max_age {
+            let mut directives = vec![
+                CacheDirective::Public,
+                CacheDirective::MaxAge(max_age.as_secs() as u32)
+            ];
+            if self.immutable {
+                directives.push(CacheDirective::Extension("immutable".into(), None));
+            }
+            response.headers_mut().set(CacheControl(directives));
+        }
+
+        match self.source {
+            FileSource::Embedded(ref file) => {
+                if file.set_validators(&context, &mut response) {
+                    response.set_status(StatusCode::NotModified);
+                    return;
+                }
+
+                response.headers_mut().set(ContentType(file.content_type.clone()));
+                response.send(&file.content[..]);
+            },
+            FileSource::Path(ref path, ref mime) => {
+                let metadata = match fs::metadata(path) {
+                    Ok(metadata) => metadata,
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                        response.set_status(StatusCode::NotFound);
+                        return;
+                    },
+                    Err(e) => {
+                        error!("failed to read the metadata of '{}': {}", path.display(), e);
+                        response.set_status(StatusCode::InternalServerError);
+                        return;
+                    }
+                };
+
+                let modified = metadata.modified().ok();
+                let seconds = modified
+                    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                    .map(|duration| duration.as_secs())
+                    .unwrap_or(0);
+                let etag = EntityTag::strong(format!("{:x}-{:x}", metadata.len(), seconds));
+
+                if set_validators(&context, &mut response, &etag, modified) {
+                    response.set_status(StatusCode::NotModified);
+                    return;
+                }
+
+                let result = response.send_file_with_mime(path, |_| Some(mime.clone()))
+                    .or_else(|e| e.send_not_found("the file was not found"))
+                    .or_else(|e| e.ignore_send_error());
+
+                if let Err((e, mut response)) = result {
+                    error!("failed to open '{}': {}", path.display(), e);
+                    response.set_status(StatusCode::InternalServerError);
+                }
+            }
+        }
+    }
+}
+
+///A handler that serves files from a directory in the file system.
+///
+///The files are looked up using a route variable, which is `path` by
+///default, so the handler should be inserted with a variable sequence, like
+///`"assets/*path"`. Paths that try to escape the root directory, as checked
+///by `check_path`, are rejected with `403 Forbidden`, and files that are not
+///found results in `404 Not Found`. Paths that are still percent encoded
+///after being decoded, like `%2e%2e` from a double encoded `%252e%252e`, are
+///also rejected with `403 Forbidden`, as checked by
+///`utils::contains_escapes`, since they may be decoded again further on.
+///
+///A request for a directory is answered with its index file, if there is
+///one. Requests for directories without a trailing slash are redirected to
+///the same path with a slash, so relative links work as expected. What
+///happens if there is no index file depends on `listing`:
+///
+///```
+///use rustful::{Router, TreeRouter};
+///use rustful::file::StaticFiles;
+///use rustful::Method::Get;
+///
+///let mut files = StaticFiles::new("public");
+///files.listing = true;
+///
+///let mut router = TreeRouter::new();
+///router.insert(Get, "/files", files.clone());
+///router.insert(Get, "/files/*path", files);
+///```
+///
+///The `Cache-Control` header is set for each file according to `cache`:
+///
+///```
+///use rustful::file::{StaticFiles, CachePolicy, CacheRules};
+///
+///let mut files = StaticFiles::new("public");
+///files.cache = CachePolicy::Rules(CacheRules::new()
+///    .prefix("assets", "public, max-age=31536000, immutable")
+///    .extension("html", "no-cache"));
+///```
+///
+///Files can be layered from more than one directory, such as a theme that
+///overrides some of the files in a default theme. The roots are tried in
+///order, and the first one that has the requested path wins, so a file in
+///an earlier root shadows the files with the same path in the later ones.
+///The same rules for paths and caching are used for all of them:
+///
+///```
+///use rustful::file::StaticFiles;
+///
+///let files = StaticFiles::new("themes/custom").fallback("themes/default");
+///```
+#[derive(Clone, Debug)]
+pub struct StaticFiles {
+    ///The directory that the files are served from.
+    pub root: PathBuf,
+
+    ///Directories that are tried in order, after `root`, if the path is not
+    ///found in any of the earlier directories. Index files are looked for in
+    ///the same order, while a directory listing only shows the entries in
+    ///the first directory where the requested directory is found. Default
+    ///is no fallbacks.
+    pub fallbacks: Vec<PathBuf>,
+
+    ///The route variable that holds the path of the file. Default is
+    ///`"path"`.
+    pub variable: Cow<'static, str>,
+
+    ///The name of the file that is served for directories, or `None` to not
+    ///look for one. Default is `"index.html"`.
+    pub index: Option<Cow<'static, str>>,
+
+    ///Generate a listing of the entries in directories without an index
+    ///file, instead of responding with `404 Not Found`. The listing is
+    ///streamed as HTML, or as JSON if the client prefers `application/json`.
+    ///Each entry has a name, a type, a size and a modification time, and
+    ///entries with names that are not valid UTF-8 are left out.
+    ///
+    ///This reveals the names of all of the files in the directories, so it's
+    ///`false` by default.
+    pub listing: bool,
+
+    ///How `Cache-Control` is set for the files. It's not set for directory
+    ///listings or redirects. Default is `CachePolicy::Off`.
+    pub cache: CachePolicy,
+}
+
+impl StaticFiles {
+    ///Serve the files in `root`, with the default settings.
+    pub fn new<P: Into<PathBuf>>(root: P) -> StaticFiles {
+        StaticFiles {
+            root: root.into(),
+            fallbacks: vec![],
+            variable: "path".into(),
+            index: Some("index.html".into()),
+            listing: false,
+            cache: CachePolicy::Off,
+        }
+    }
+
+    ///Add a directory to try if a file is not found in the directories
+    ///before it.
+    pub fn fallback<P: Into<PathBuf>>(mut self, root: P) -> StaticFiles {
+        self.fallbacks.push(root.into());
+        self
+    }
+
+    //All of the roots, in the order they are tried.
+    fn roots<'a>(&'a self) -> Box<Iterator<Item=&'a PathBuf> + 'a> {
+        Box::new(Some(&self.root).into_iter().chain(&self.fallbacks))
+    }
+
+    //Send a file and its `Cache-Control` header. The path is relative to
+    //`root`.
+    fn send_file(&self, root: &Path, relative: &Path, mut response: Response) {
+        if let Some(value) = self.cache.cache_control(relative) {
+            response.headers_mut().set_raw("Cache-Control", vec![value.into_bytes()]);
+        }
+        send_file(root.join(relative), response);
+    }
+}
+
+///Decides the `Cache-Control` header for the files from `StaticFiles`.
+///
+///The path that is given to the policy is the path of the file that is
+///served, relative to the root directory. This is the index file when a
+///directory is requested, so the index files in `docs/` are seen as
+///`docs/index.html`.
+#[derive(Clone)]
+pub enum CachePolicy {
+    ///Don't set `Cache-Control`.
+    Off,
+
+    ///Use a list of rules.
+    Rules(CacheRules),
+
+    ///Call a function that returns the value of `Cache-Control`, or `None` to
+    ///not set it.
+    ///
+    ///```
+    ///use std::path::Path;
+    ///use rustful::file::{StaticFiles, CachePolicy};
+    ///
+    ///let mut files = StaticFiles::new("public");
+    ///files.cache = CachePolicy::function(|path: &Path| {
+    ///    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+    ///    if name.contains(".min.") {
+    ///        Some("public, max-age=86400".into())
+    ///    } else {
+    ///        None
+    ///    }
+    ///});
+    ///```
+    Function(Arc<Fn(&Path) -> Option<String> + Send + Sync>),
+}
+
+impl CachePolicy {
+    ///Use the function `f` as the policy.
+    pub fn function<F: Fn(&Path) -> Option<String> + Send + Sync + 'static>(f: F) -> CachePolicy {
+        CachePolicy::Function(Arc::new(f))
+    }
+
+    ///Get the value of `Cache-Control` for a file, if any.
+    pub fn cache_control(&self, path: &Path) -> Option<String> {
+        match *self {
+            CachePolicy::Off => None,
+            CachePolicy::Rules(ref rules) => rules.cache_control(path).map(|value| value.to_owned()),
+            CachePolicy::Function(ref f) => f(path),
+        }
+    }
+}
+
+impl fmt::Debug for CachePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CachePolicy::Off => f.write_str("Off"),
+            CachePolicy::Rules(ref rules) => f.debug_tuple("Rules").field(rules).finish(),
+            CachePolicy::Function(_) => f.write_str("Function(..)"),
+        }
+    }
+}
+
+///A list of `Cache-Control` rules for file extensions and paths.
+///
+///The rules are checked in the order they were added, and the first one
+///that matches the file decides the value. Add the most specific rules
+///first, like a rule for a directory of hashed assets before a rule for the
+///`js` extension. `Cache-Control` is not set if none of the rules match,
+///unless there is a fallback, which is only used when nothing else matches.
+///
+///```
+///use std::path::Path;
+///use rustful::file::CacheRules;
+///
+///let rules = CacheRules::new()
+///    .prefix("assets", "public, max-age=31536000, immutable")
+///    .extension("html", "no-cache")
+///    .fallback("public, max-age=3600");
+///
+///assert_eq!(rules.cache_control(Path::new("assets/app.html")), Some("public, max-age=31536000, immutable"));
+///assert_eq!(rules.cache_control(Path::new("about/index.html")), Some("no-cache"));
+///assert_eq!(rules.cache_control(Path::new("robots.txt")), Some("public, max-age=3600"));
+///```
+#[derive(Clone, Debug, Default)]
+pub struct CacheRules {
+    rules: Vec<(CacheMatch, String)>,
+    fallback: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+enum CacheMatch {
+    Extension(String),
+    Prefix(PathBuf),
+}
+
+impl CacheRules {
+    ///Create an empty list of rules.
+    pub fn new() -> CacheRules {
+        CacheRules::default()
+    }
+
+    ///Add a rule for files with the extension `extension`, without the
+    ///leading `.`. Extensions are compared without regard to case.
+    pub fn extension<E: Into<String>, V: Into<String>>(mut self, extension: E, value: V) -> CacheRules {
+        self.rules.push((CacheMatch::Extension(extension.into().to_lowercase()), value.into()));
+        self
+    }
+
+    ///Add a rule for files in the path `prefix`. The paths are compared
+    ///component by component, so `assets` matches `assets/app.js`, but not
+    ///`assets-old/app.js`.
+    pub fn prefix<P: Into<PathBuf>, V: Into<String>>(mut self, prefix: P, value: V) -> CacheRules {
+        self.rules.push((CacheMatch::Prefix(prefix.into()), value.into()));
+        self
+    }
+
+    ///Add a rule for files in the path `prefix` that never change, like
+    ///files with a hash of their content in their names. They get
+    ///`public, max-age=..., immutable`, as described for
+    ///`SingleFile::immutable`.
+    ///
+    ///```
+    ///use std::path::Path;
+    ///use std::time::Duration;
+    ///use rustful::file::CacheRules;
+    ///
+    ///let rules = CacheRules::new().immutable("assets", Duration::from_secs(31536000));
+    ///assert_eq!(rules.cache_control(Path::new("assets/app.3f9a1c.js")), Some("public, max-age=31536000, immutable"));
+    ///```
+    pub fn immutable<P: Into<PathBuf>>(self, prefix: P, max_age: Duration) -> CacheRules {
+        self.prefix(prefix, format!("public, max-age={}, immutable", max_age.as_secs()))
+    }
+
+    ///Set the value for files that don't match any of the rules.
+    pub fn fallback<V: Into<String>>(mut self, value: V) -> CacheRules {
+        self.fallback = Some(value.into());
+        self
+    }
+
+    ///Get the value of `Cache-Control` for a file, if any of the rules
+    ///match it.
+    pub fn cache_control(&self, path: &Path) -> Option<&str> {
+        let extension = path.extension().and_then(|extension| extension.to_str()).map(|extension| extension.to_lowercase());
+
+        self.rules.iter()
+            .find(|&&(ref rule, _)| match *rule {
+                CacheMatch::Extension(ref rule) => extension.as_ref() == Some(rule),
+                CacheMatch::Prefix(ref rule) => path.starts_with(rule),
+            })
+            .map(|&(_, ref value)| &**value)
+            .or_else(|| self.fallback.as_ref().map(|value| &**value))
+    }
+}
+
+impl Handler for StaticFiles {
+    fn handle_request(&self, context: Context, mut response: Response) {
+        let relative = context.variables.get(&*self.variable).map(|path| path.into_owned()).unwrap_or_default();
+        if check_path(&relative).is_err() || contains_escapes(relative.as_bytes()) {
+            response.set_status(StatusCode::Forbidden);
+            return;
+        }
+
+        let mut found = None;
+        for root in self.roots() {
+            let path = root.join(&relative);
+            match fs::metadata(&path) {
+                Ok(metadata) => {
+                    found = Some((root, path, metadata));
+                    break;
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+                Err(e) => {
+                    error!("failed to read the metadata of '{}': {}", path.display(), e);
+                    response.set_status(StatusCode::InternalServerError);
+                    return;
+                }
+            }
+        }
+
+        let (root, path, metadata) = match found {
+            Some(found) => found,
+            None => {
+                response.set_status(StatusCode::NotFound);
+                return;
+            }
+        };
+
+        if !metadata.is_dir() {
+            self.send_file(root, Path::new(&relative), response);
+            return;
+        }
+
+        let target = context.raw_target();
+        let (target_path, target_rest) = target.split_at(target.find(|c| c == '?' || c == '#').unwrap_or(target.len()));
+        if !target_path.ends_with('/') {
+            response.set_status(StatusCode::MovedPermanently);
+            response.headers_mut().set(Location(format!("{}/{}", target_path, target_rest)));
+            return;
+        }
+
+        if let Some(ref index) = self.index {
+            let index = Path::new(&relative).join(&**index);
+            if let Some(root) = self.roots().find(|root| root.join(&index).is_file()) {
+                self.send_file(root, &index, response);
+                return;
+            }
+        }
+
+        if self.listing {
+            send_listing(&path, !relative.is_empty(), &context, response);
+        } else {
+            response.set_status(StatusCode::NotFound);
+        }
+    }
+}
+
+fn send_file(path: PathBuf, response: Response) {
+    let result = response.send_file(&path)
+        .or_else(|e| e.send_not_found("the file was not found"))
+        .or_else(|e| e.ignore_send_error());
+
+    if let Err((e, mut response)) = result {
+        error!("failed to open '{}': {}", path.display(), e);
+        response.set_status(StatusCode::InternalServerError);
+    }
+}
+
+const LISTING_CHUNK_SIZE: usize = 8 * 1024;
+
+//An entry in a directory listing.
+struct ListingEntry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+fn send_listing(path: &Path, has_parent: bool, context: &Context, mut response: Response) {
+    let mut entries = match read_listing(path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("failed to list '{}': {}", path.display(), e);
+            response.set_status(StatusCode::InternalServerError);
+            return;
+        }
+    };
+    entries.sort_by(|a, b| (!a.is_dir, &a.name).cmp(&(!b.is_dir, &b.name)));
+
+    let html = Mime(TopLevel::Text, SubLevel::Html, vec![(Attr::Charset, Value::Utf8)]);
+    let json = Mime(TopLevel::Application, SubLevel::Json, vec![(Attr::Charset, Value::Utf8)]);
+    let as_json = context.has_header("Accept") && context.preferred_content_type(&[html.clone(), json.clone()]) == Some(&json);
+
+    response.headers_mut().set(ContentType(if as_json { json } else { html }));
+    let mut response = response.into_chunked();
+
+    //The entries are collected into chunks of a reasonable size.
+    let mut buffer = String::new();
+    let result = {
+        let mut send = |part: String| {
+            buffer.push_str(&part);
+            if buffer.len() >= LISTING_CHUNK_SIZE {
+                try!(response.try_send(&buffer[..]));
+                buffer.clear();
+            }
+            Ok(())
+        };
+
+        if as_json {
+            write_json_listing(&entries, &mut send)
+        } else {
+            write_html_listing(&entries, has_parent, &mut send)
+        }
+    };
+    let result = result.and_then(|_| response.try_send(buffer).map(|_| ()));
+
+    if let Err(e) = result {
+        error!("failed to send the listing of '{}': {}", path.display(), e);
+    }
+}
+
+fn read_listing(path: &Path) -> io::Result<Vec<ListingEntry>> {
+    let mut entries = vec![];
+    for entry in try!(fs::read_dir(path)) {
+        let entry = try!(entry);
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue
+        };
+        let metadata = try!(entry.metadata());
+        entries.push(ListingEntry {
+            name: name,
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        });
+    }
+    Ok(entries)
+}
+
+fn write_html_listing<F: FnMut(String) -> Result<(), ResponseError>>(entries: &[ListingEntry], has_parent: bool, mut send: F) -> Result<(), ResponseError> {
+    try!(send("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Index</title></head><body>\n<table>\n".into()));
+    if has_parent {
+        try!(send("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n".into()));
+    }
+
+    for entry in entries {
+        let slash = if entry.is_dir { "/" } else { "" };
+        let size = if entry.is_dir { String::new() } else { entry.size.to_string() };
+        let modified = entry.modified.map(format_http_date).unwrap_or_default();
+        try!(send(format!(
+            "<tr><td><a href=\"{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            html_escape(&percent_encode_name(&entry.name)), slash, html_escape(&entry.name), slash, size, modified
+        )));
+    }
+
+    send("</table>\n</body></html>\n".into())
+}
+
+fn write_json_listing<F: FnMut(String) -> Result<(), ResponseError>>(entries: &[ListingEntry], mut send: F) -> Result<(), ResponseError> {
+    try!(send("[".into()));
+    for (i, entry) in entries.iter().enumerate() {
+        let modified = entry.modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or("null".to_owned(), |duration| duration.as_secs().to_string());
+        try!(send(format!(
+            "{}{{\"name\":\"{}\",\"type\":\"{}\",\"size\":{},\"modified\":{}}}",
+            if i == 0 { "" } else { "," },
+            escape_json(&entry.name),
+            if entry.is_dir { "directory" } else { "file" },
+            if entry.is_dir { "null".to_owned() } else { entry.size.to_string() },
+            modified
+        )));
+    }
+    send("]".into())
+}
+
+//Percent encode everything except unreserved characters, so the name is a
+//single relative path segment.
+fn percent_encode_name(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for &byte in name.as_bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            byte => encoded.push_str(&format!("%{:02X}", byte))
+        }
+    }
+    encoded
+}
+
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c)
+        }
+    }
+    escaped
+}
@@ -0,0 +1,53 @@
+//!Fallback encodings for individual routes.
+//!
+//!`Server::fallback_charset` applies to every request, while the
+//!`FallbackCharset` filter can be added to the routes that receive legacy
+//!forms, using `RouteFilters`:
+//!
+//!```
+//!use rustful::{Router, TreeRouter, Context, Response};
+//!use rustful::filter::{RouteFilters, FallbackCharset};
+//!use rustful::utils::Charset;
+//!use rustful::Method::Post;
+//!
+//!fn submit(mut context: Context, response: Response) {
+//!    match context.body.read_query_body() {
+//!        Ok(form) => response.send(format!("hello, {}", form.get("name").unwrap_or("stranger".into()))),
+//!        Err(_) => response.send("invalid form")
+//!    }
+//!}
+//!
+//!let submit = RouteFilters::new(submit as fn(Context, Response))
+//!    .context_filter(FallbackCharset(Charset::from_label("Shift_JIS").unwrap()));
+//!
+//!let mut router = TreeRouter::new();
+//!router.insert(Post, "/legacy/submit", Box::new(submit) as Box<rustful::Handler>);
+//!```
+//!
+//!The query is decoded when the filter runs, and the form body is decoded
+//!when it's read. A `charset` parameter in the `Content-Type` of the body
+//!still takes precedence. Values that were already decoded using
+//!`Server::fallback_charset` are not decoded again.
+
+use std::mem;
+
+use context::Context;
+use filter::{FilterContext, ContextFilter, ContextAction};
+use utils::Charset;
+
+///A context filter that sets the encoding of query and form values that are
+///not valid UTF-8.
+///
+///See the [module level documentation](index.html) for more information.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FallbackCharset(pub Charset);
+
+impl ContextFilter for FallbackCharset {
+    ///Decode the query and set the fallback charset of the body.
+    fn modify(&self, _ctx: FilterContext, context: &mut Context) -> ContextAction {
+        let query = mem::replace(&mut context.query, Default::default());
+        context.query = self.0.decode_parameters(query);
+        context.body.set_fallback_charset(Some(self.0));
+        ContextAction::next()
+    }
+}
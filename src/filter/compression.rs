@@ -0,0 +1,524 @@
+//!Response body compression.
+//!
+//!The `Compression` filter will compress response bodies, using the best
+//!content coding that both the client and the server supports. Gzip and
+//!deflate are available through the `gzip` feature, and Brotli is available
+//!through the `brotli` feature. Codings that are not compiled in will simply
+//!never be chosen.
+//!
+//!The filter has to be able to see the request headers, so it has to be
+//!added as both a context filter and a response filter:
+//!
+//!```no_run
+//!# use rustful::{Server, Context, Response};
+//!use rustful::filter::Compression;
+//!
+//!# fn handler(_context: Context, _response: Response) {}
+//!let compression = Compression::new();
+//!
+//!let server_result = Server {
+//!    context_filters: vec![Box::new(compression.clone())],
+//!    response_filters: vec![Box::new(compression)],
+//!    ..Server::new(handler)
+//!}.run();
+//!```
+//!
+//!The body is compressed after all of the response filters have processed
+//!it, using `ResponseFilter::wrap_body`. Raw responses are never compressed.
+//!
+//!The handler decides over the automatic compression. A response will be
+//!left as it is if the handler has set a `Content-Encoding` header, since the
+//!body is then already encoded, or if it has set `Cache-Control:
+//!no-transform`, which forbids any transformation of the body. This is
+//!checked before the content type and the negotiated coding.
+//!
+//!Ranges are only served for the uncompressed representation, since a
+//!`Content-Range` would otherwise be ambiguous. Partial responses, meaning
+//!`206 Partial Content` and `416 Range Not Satisfiable`, or anything with a
+//!`Content-Range` header, are never compressed, and `Accept-Ranges` is removed
+//!from compressed responses. The ranged responses from
+//!`Response::send_seekable` are raw, so they are not compressed either way.
+//!
+//!Responses to `HEAD` requests describe the uncompressed representation. The
+//!length of the compressed body can't be known without compressing it, so
+//!they are sent without `Content-Encoding`, and with the `Content-Length` of
+//!the uncompressed body, if the handler set one. This is the same as for the
+//!heads from `Response::send_file`, which doesn't read the file. `Vary:
+//!Accept-Encoding` is still added, since the `GET` response may be
+//!compressed.
+
+use std::io::Write;
+
+#[cfg(feature = "gzip")]
+use flate2;
+#[cfg(feature = "brotli")]
+use brotli;
+
+use {StatusCode, Method};
+use header::{Headers, AcceptEncoding, AcceptRanges, ContentEncoding, ContentRange, ContentType, Encoding, QualityItem};
+use mime::{Mime, TopLevel, SubLevel};
+use context::{Context, CacheControl};
+use response::Data;
+use filter::{FilterContext, ContextFilter, ContextAction, ResponseFilter, ResponseAction, RawResponse};
+
+///A content coding for compressed response bodies.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ContentCoding {
+    ///Brotli (`br`). Requires the `brotli` feature.
+    Brotli,
+
+    ///Gzip (`gzip`). Requires the `gzip` feature.
+    Gzip,
+
+    ///Zlib wrapped deflate (`deflate`). Requires the `gzip` feature.
+    Deflate
+}
+
+impl ContentCoding {
+    ///Check if support for this coding has been compiled in.
+    pub fn is_available(&self) -> bool {
+        match *self {
+            ContentCoding::Brotli => cfg!(feature = "brotli"),
+            ContentCoding::Gzip | ContentCoding::Deflate => cfg!(feature = "gzip")
+        }
+    }
+
+    ///The name of the coding, as it appears in `Accept-Encoding` and
+    ///`Content-Encoding`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ContentCoding::Brotli => "br",
+            ContentCoding::Gzip => "gzip",
+            ContentCoding::Deflate => "deflate"
+        }
+    }
+
+    fn encoding(&self) -> Encoding {
+        match *self {
+            ContentCoding::Brotli => Encoding::EncodingExt("br".into()),
+            ContentCoding::Gzip => Encoding::Gzip,
+            ContentCoding::Deflate => Encoding::Deflate
+        }
+    }
+
+    fn wrap(&self, writer: Box<Write>) -> Box<Write> {
+        match *self {
+            #[cfg(feature = "brotli")]
+            ContentCoding::Brotli => Box::new(brotli::CompressorWriter::new(writer, 4096, 5, 22)),
+            #[cfg(feature = "gzip")]
+            ContentCoding::Gzip => Box::new(flate2::write::GzEncoder::new(writer, flate2::Compression::default())),
+            #[cfg(feature = "gzip")]
+            ContentCoding::Deflate => Box::new(flate2::write::ZlibEncoder::new(writer, flate2::Compression::default())),
+            #[allow(unreachable_patterns)]
+            _ => writer
+        }
+    }
+}
+
+///A filter that compresses response bodies.
+///
+///See the [module level documentation](index.html) for more information.
+#[derive(Clone, Debug)]
+pub struct Compression {
+    ///The content codings that may be used, in order of preference. This
+    ///order is used when the client doesn't prefer one over an other. Codings
+    ///that are not compiled in will be skipped. The default is Brotli, gzip
+    ///and then deflate.
+    pub codings: Vec<ContentCoding>
+}
+
+impl Compression {
+    ///Create a compression filter with the default settings.
+    pub fn new() -> Compression {
+        Compression::default()
+    }
+
+    ///Choose the coding with the highest quality value in an
+    ///`Accept-Encoding` header, or `None` if no available coding is accepted.
+    ///
+    ///```
+    ///use rustful::header::{qitem, Encoding, QualityItem, Quality};
+    ///use rustful::filter::{Compression, ContentCoding};
+    ///
+    ///let accept = vec![qitem(Encoding::Deflate), QualityItem::new(Encoding::Gzip, Quality(500))];
+    ///let compression = Compression::new();
+    ///# if ContentCoding::Deflate.is_available() {
+    ///assert_eq!(compression.negotiate(&accept), Some(ContentCoding::Deflate));
+    ///# }
+    ///```
+    pub fn negotiate(&self, accept: &[QualityItem<Encoding>]) -> Option<ContentCoding> {
+        let wildcard = Encoding::EncodingExt("*".into());
+        let wildcard = accept.iter().find(|q| q.item == wildcard).map(|q| q.quality.0);
+        let mut best: Option<(ContentCoding, u16)> = None;
+
+        for &coding in self.codings.iter().filter(|c| c.is_available()) {
+            let encoding = coding.encoding();
+            let quality = accept.iter()
+                .find(|q| q.item == encoding)
+                .map(|q| q.quality.0)
+                .or(wildcard)
+                .unwrap_or(0);
+
+            let better = match best {
+                Some((_, best_quality)) => quality > best_quality,
+                None => quality > 0
+            };
+
+            if better {
+                best = Some((coding, quality));
+            }
+        }
+
+        best.map(|(coding, _)| coding)
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression {
+            codings: vec![ContentCoding::Brotli, ContentCoding::Gzip, ContentCoding::Deflate]
+        }
+    }
+}
+
+//The coding that was negotiated for the request.
+struct Negotiated(Option<ContentCoding>);
+
+//The coding that is used for the response.
+struct Active(ContentCoding);
+
+impl ContextFilter for Compression {
+    ///Choose a content coding for the response. `HEAD` requests never get
+    ///one.
+    fn modify(&self, ctx: FilterContext, context: &mut Context) -> ContextAction {
+        let coding = if context.method == Method::Head {
+            None
+        } else {
+            context.headers.get::<AcceptEncoding>().and_then(|accept| self.negotiate(accept))
+        };
+        ctx.storage.insert(Negotiated(coding));
+        ContextAction::next()
+    }
+}
+
+impl ResponseFilter for Compression {
+    fn begin(&self, _ctx: FilterContext, status: StatusCode, _headers: &mut Headers) -> (StatusCode, ResponseAction) {
+        (status, ResponseAction::next::<Data>(None))
+    }
+
+    fn write<'a>(&'a self, _ctx: FilterContext, content: Option<Data<'a>>) -> ResponseAction {
+        ResponseAction::next(content)
+    }
+
+    fn end(&self, _ctx: FilterContext) -> ResponseAction {
+        ResponseAction::next::<Data>(None)
+    }
+
+    ///Set `Content-Encoding` and `Vary` if the response can be compressed.
+    fn finalize(&self, ctx: FilterContext, status: &mut StatusCode, headers: &mut Headers) {
+        //Raw bodies can't be compressed.
+        if ctx.storage.contains::<RawResponse>() {
+            return;
+        }
+
+        let coding = match ctx.storage.get() {
+            Some(&Negotiated(coding)) => coding,
+            None => return
+        };
+
+        if !is_compressible(*status, headers) {
+            return;
+        }
+
+        add_vary(headers);
+
+        if let Some(coding) = coding {
+            headers.set(ContentEncoding(vec![coding.encoding()]));
+            headers.remove_raw("content-length");
+            headers.remove::<AcceptRanges>();
+            ctx.storage.insert(Active(coding));
+        }
+    }
+
+    ///Compress the body, if a coding was chosen.
+    fn wrap_body(&self, ctx: FilterContext, writer: Box<Write>) -> Box<Write> {
+        match ctx.storage.get() {
+            Some(&Active(coding)) => coding.wrap(writer),
+            None => writer
+        }
+    }
+}
+
+fn is_compressible(status: StatusCode, headers: &Headers) -> bool {
+    match status {
+        StatusCode::NoContent | StatusCode::NotModified => return false,
+        StatusCode::PartialContent | StatusCode::RangeNotSatisfiable => return false,
+        status if status.is_informational() => return false,
+        _ => {}
+    }
+
+    if headers.has::<ContentEncoding>() || headers.has::<ContentRange>() {
+        return false;
+    }
+
+    //A malformed header may have been meant to forbid transformations.
+    if let Some(values) = headers.get_raw("Cache-Control") {
+        if CacheControl::parse(values).map_or(true, |cache_control| cache_control.no_transform) {
+            return false;
+        }
+    }
+
+    match headers.get() {
+        Some(&ContentType(Mime(TopLevel::Image, SubLevel::Ext(ref sub), _))) => sub == "svg+xml",
+        Some(&ContentType(Mime(TopLevel::Image, _, _))) |
+        Some(&ContentType(Mime(TopLevel::Audio, _, _))) |
+        Some(&ContentType(Mime(TopLevel::Video, _, _))) => false,
+        _ => true
+    }
+}
+
+fn add_vary(headers: &mut Headers) {
+    let mut values = headers.get_raw("vary").map(|values| values.to_owned()).unwrap_or_default();
+    let present = values.iter().any(|value| {
+        String::from_utf8_lossy(value).split(',').any(|field| {
+            let field = field.trim();
+            field == "*" || field.eq_ignore_ascii_case("accept-encoding")
+        })
+    });
+
+    if !present {
+        values.push(b"Accept-Encoding".to_vec());
+        headers.set_raw("Vary", values);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(any(feature = "gzip", feature = "brotli"))]
+    use std::io::Read;
+
+    use header::{qitem, Encoding, QualityItem, Quality, ContentEncoding, ContentType, CacheControl, CacheDirective};
+    use header::{AcceptRanges, RangeUnit, ContentRange, ContentRangeSpec};
+    use filter::ResponseFilter;
+    use response::{Response, ResponseBuffer, BufferHandle};
+    use StatusCode;
+    use super::{Compression, ContentCoding, Negotiated};
+
+    fn compressed_response<F: FnOnce(Response)>(coding: Option<ContentCoding>, respond: F) -> BufferHandle {
+        let filters: Vec<Box<ResponseFilter>> = vec![Box::new(Compression::new())];
+        let mut buffer = ResponseBuffer::new();
+        let (mut response, output) = buffer.filtered(&filters);
+        response.filter_storage_mut().insert(Negotiated(coding));
+        respond(response);
+        output
+    }
+
+    #[cfg(any(feature = "gzip", feature = "brotli"))]
+    fn decompress(coding: ContentCoding, body: &[u8]) -> Vec<u8> {
+        let mut result = vec![];
+        match coding {
+            #[cfg(feature = "brotli")]
+            ContentCoding::Brotli => ::brotli::Decompressor::new(body, 4096).read_to_end(&mut result).unwrap(),
+            #[cfg(feature = "gzip")]
+            ContentCoding::Gzip => ::flate2::read::GzDecoder::new(body).read_to_end(&mut result).unwrap(),
+            #[cfg(feature = "gzip")]
+            ContentCoding::Deflate => ::flate2::read::ZlibDecoder::new(body).read_to_end(&mut result).unwrap(),
+            #[allow(unreachable_patterns)]
+            _ => unreachable!()
+        };
+        result
+    }
+
+    #[test]
+    #[cfg(any(feature = "gzip", feature = "brotli"))]
+    fn compress_sized() {
+        let codings = [ContentCoding::Brotli, ContentCoding::Gzip, ContentCoding::Deflate];
+        for &coding in codings.iter().filter(|c| c.is_available()) {
+            let output = compressed_response(Some(coding), |response| response.send("hello, hello, hello"));
+            let headers = output.headers().unwrap();
+            let body = output.body().unwrap();
+
+            assert_eq!(headers.get(), Some(&ContentEncoding(vec![coding.encoding()])));
+            assert_eq!(headers.get(), Some(&::header::ContentLength(body.len() as u64)));
+            assert_eq!(headers.get_raw("vary"), Some(&[b"Accept-Encoding".to_vec()][..]));
+            assert_eq!(decompress(coding, &body), b"hello, hello, hello");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn flush_chunked() {
+        //Reads as much as possible from an incomplete stream.
+        fn partial_decompress(body: &[u8]) -> Vec<u8> {
+            let mut decoder = ::flate2::read::GzDecoder::new(body);
+            let mut result = vec![];
+            let mut buffer = [0; 64];
+            while let Ok(length) = decoder.read(&mut buffer) {
+                if length == 0 {
+                    break;
+                }
+                result.extend_from_slice(&buffer[..length]);
+            }
+            result
+        }
+
+        let filters: Vec<Box<ResponseFilter>> = vec![Box::new(Compression::new())];
+        let mut buffer = ResponseBuffer::new();
+        let (mut response, output) = buffer.filtered(&filters);
+        response.filter_storage_mut().insert(Negotiated(Some(ContentCoding::Gzip)));
+
+        let mut chunked = response.into_chunked();
+        chunked.send("hello");
+        assert_eq!(partial_decompress(&output.body().unwrap()), b"");
+        chunked.flush().unwrap();
+        assert_eq!(partial_decompress(&output.body().unwrap()), b"hello");
+        chunked.send(", world");
+        chunked.end().unwrap();
+
+        assert_eq!(decompress(ContentCoding::Gzip, &output.body().unwrap()), b"hello, world");
+    }
+
+    #[test]
+    #[cfg(any(feature = "gzip", feature = "brotli"))]
+    fn compress_chunked() {
+        let codings = [ContentCoding::Brotli, ContentCoding::Gzip, ContentCoding::Deflate];
+        for &coding in codings.iter().filter(|c| c.is_available()) {
+            let output = compressed_response(Some(coding), |response| {
+                let mut chunked = response.into_chunked();
+                chunked.send("hello, ");
+                chunked.send("world");
+            });
+
+            assert_eq!(output.headers().unwrap().get(), Some(&ContentEncoding(vec![coding.encoding()])));
+            assert_eq!(decompress(coding, &output.body().unwrap()), b"hello, world");
+        }
+    }
+
+    #[test]
+    fn not_accepted() {
+        let output = compressed_response(None, |response| response.send("hello"));
+        let headers = output.headers().unwrap();
+
+        assert!(!headers.has::<ContentEncoding>());
+        assert_eq!(headers.get_raw("vary"), Some(&[b"Accept-Encoding".to_vec()][..]));
+        assert_eq!(output.body(), Some(b"hello".to_vec()));
+    }
+
+    #[test]
+    fn not_compressible() {
+        let output = compressed_response(Some(ContentCoding::Gzip), |mut response| {
+            response.headers_mut().set(ContentType(content_type!(Image / Png)));
+            response.send("not really a png");
+        });
+        assert!(!output.headers().unwrap().has::<ContentEncoding>());
+        assert_eq!(output.body(), Some(b"not really a png".to_vec()));
+
+        let output = compressed_response(Some(ContentCoding::Gzip), |mut response| {
+            response.set_status(StatusCode::NoContent);
+            response.send("");
+        });
+        assert!(!output.headers().unwrap().has::<ContentEncoding>());
+        assert!(output.headers().unwrap().get_raw("vary").is_none());
+    }
+
+    #[test]
+    fn not_raw() {
+        let output = compressed_response(Some(ContentCoding::Gzip), |response| {
+            let mut raw = unsafe { response.into_raw(5) };
+            raw.send("hello");
+        });
+        assert!(!output.headers().unwrap().has::<ContentEncoding>());
+        assert!(output.headers().unwrap().get_raw("vary").is_none());
+        assert_eq!(output.body(), Some(b"hello".to_vec()));
+    }
+
+    #[test]
+    fn partial_content() {
+        let output = compressed_response(Some(ContentCoding::Gzip), |mut response| {
+            response.set_status(StatusCode::PartialContent);
+            response.headers_mut().set(ContentRange(ContentRangeSpec::Bytes {
+                range: Some((0, 4)),
+                instance_length: Some(11)
+            }));
+            response.send("hello");
+        });
+        let headers = output.headers().unwrap();
+        assert!(!headers.has::<ContentEncoding>());
+        assert_eq!(headers.get(), Some(&::header::ContentLength(5)));
+        assert_eq!(output.body(), Some(b"hello".to_vec()));
+
+        let output = compressed_response(Some(ContentCoding::Gzip), |mut response| {
+            response.headers_mut().set(AcceptRanges(vec![RangeUnit::Bytes]));
+            response.send("hello world");
+        });
+        let headers = output.headers().unwrap();
+        if ContentCoding::Gzip.is_available() {
+            assert!(headers.has::<ContentEncoding>());
+            assert!(!headers.has::<AcceptRanges>());
+        }
+    }
+
+    #[test]
+    fn handler_decides() {
+        let output = compressed_response(Some(ContentCoding::Gzip), |mut response| {
+            response.headers_mut().set(CacheControl(vec![CacheDirective::Public, CacheDirective::NoTransform]));
+            response.send("hello");
+        });
+        assert!(!output.headers().unwrap().has::<ContentEncoding>());
+        assert!(output.headers().unwrap().get_raw("vary").is_none());
+        assert_eq!(output.body(), Some(b"hello".to_vec()));
+
+        let output = compressed_response(Some(ContentCoding::Gzip), |mut response| {
+            response.headers_mut().set_raw("Cache-Control", vec![b"private=\"Set-Cookie, X-Token\", No-Transform".to_vec()]);
+            response.send("hello");
+        });
+        assert!(!output.headers().unwrap().has::<ContentEncoding>());
+
+        let output = compressed_response(Some(ContentCoding::Gzip), |mut response| {
+            response.headers_mut().set(ContentEncoding(vec![Encoding::EncodingExt("br".into())]));
+            response.send("already compressed");
+        });
+        assert_eq!(output.headers().unwrap().get(), Some(&ContentEncoding(vec![Encoding::EncodingExt("br".into())])));
+        assert_eq!(output.body(), Some(b"already compressed".to_vec()));
+    }
+
+    #[test]
+    fn negotiate_quality() {
+        let compression = Compression::new();
+        let accept = vec![
+            QualityItem::new(Encoding::Gzip, Quality(500)),
+            QualityItem::new(Encoding::EncodingExt("br".into()), Quality(800)),
+            qitem(Encoding::Deflate)
+        ];
+
+        let expected = if ContentCoding::Deflate.is_available() {
+            Some(ContentCoding::Deflate)
+        } else if ContentCoding::Brotli.is_available() {
+            Some(ContentCoding::Brotli)
+        } else {
+            None
+        };
+        assert_eq!(compression.negotiate(&accept), expected);
+    }
+
+    #[test]
+    fn negotiate_preference() {
+        let compression = Compression::new();
+        let accept = vec![qitem(Encoding::Gzip), qitem(Encoding::EncodingExt("br".into()))];
+
+        let expected = compression.codings.iter().cloned().find(|c| c.is_available() && *c != ContentCoding::Deflate);
+        assert_eq!(compression.negotiate(&accept), expected);
+    }
+
+    #[test]
+    fn negotiate_rejected() {
+        let compression = Compression::new();
+        let accept = vec![
+            QualityItem::new(Encoding::Gzip, Quality(0)),
+            QualityItem::new(Encoding::EncodingExt("*".into()), Quality(0)),
+            qitem(Encoding::Identity)
+        ];
+        assert_eq!(compression.negotiate(&accept), None);
+        assert_eq!(compression.negotiate(&[]), None);
+    }
+}
@@ -0,0 +1,258 @@
+//!Cross-site request forgery protection.
+//!
+//!The `Csrf` filter uses the double submit cookie pattern. Each client gets
+//!a random token in a signed cookie, and every `POST`, `PUT`, `PATCH` and
+//!`DELETE` request has to submit the same token again, either in the
+//!`X-CSRF-Token` header, for AJAX requests, or in the `csrf_token` field of
+//!an `application/x-www-form-urlencoded` form. Another site can make the
+//!browser send the cookie, but it can't read it, so it can't submit the
+//!token. The cookie is signed with the keys in `Server::cookie_keys`, so a
+//!token can't be planted by someone who controls a neighboring subdomain
+//!either. Requests without a matching token are rejected with `403
+//!Forbidden`, before they reach any handler.
+//!
+//!The token of the current request is stored as a `CsrfToken` in the filter
+//!storage, to be included in forms and pages. A new token is issued if the
+//!client doesn't have a valid one, so the filter has to be added as both a
+//!context filter and a response filter:
+//!
+//!```no_run
+//!# use rustful::{Server, Context, Response};
+//!use rustful::filter::{Csrf, CsrfToken};
+//!use rustful::session::CookieKeys;
+//!
+//!fn form(_context: Context, response: Response) {
+//!    let token = response.filter_storage().get::<CsrfToken>().map(|t| t.0.clone()).unwrap_or_default();
+//!    response.send(format!(
+//!        "<form method=\"post\"><input type=\"hidden\" name=\"csrf_token\" value=\"{}\"></form>",
+//!        token
+//!    ));
+//!}
+//!
+//!let csrf = Csrf::new().exempt("/webhooks/");
+//!
+//!let server_result = Server {
+//!    cookie_keys: Some(CookieKeys::new("a long and random secret key")),
+//!    context_filters: vec![Box::new(csrf.clone())],
+//!    response_filters: vec![Box::new(csrf)],
+//!    ..Server::new(form)
+//!}.run();
+//!```
+//!
+//!Requests are rejected with `500 Internal Server Error` if
+//!`Server::cookie_keys` is not set, since there is no way to protect them.
+//!The form field is only looked for if the header is missing, and the body
+//!is left unread for the handler.
+
+use std::io;
+
+use StatusCode;
+use Method;
+use header::{Headers, SetCookie, CookiePair};
+use mime::{Mime, TopLevel, SubLevel};
+use context::Context;
+use context::body::BodyError;
+use response::Data;
+use router::Route;
+use filter::{FilterContext, ContextFilter, ContextAction, ResponseFilter, ResponseAction};
+use utils::{constant_time_eq, random_bytes};
+
+const TOKEN_SIZE: usize = 32;
+
+///A filter that protects against cross-site request forgery.
+///
+///See the [module level documentation](index.html) for more information.
+#[derive(Clone, Debug)]
+pub struct Csrf {
+    ///The name of the cookie that holds the token. The default is
+    ///`rustful_csrf`.
+    pub cookie_name: String,
+
+    ///The header that AJAX requests submit the token in. The default is
+    ///`X-CSRF-Token`.
+    pub header_name: String,
+
+    ///The form field that forms submit the token in. The default is
+    ///`csrf_token`.
+    pub field_name: String,
+
+    ///The largest form body that is searched for the token. Larger forms are
+    ///rejected with `413 Payload Too Large` if they don't have the header.
+    ///The default is 64 KiB.
+    pub max_form_length: usize,
+
+    ///Paths that are not checked, such as webhooks that are called by other
+    ///servers. A request path is exempted if its first segments are the
+    ///same as all of the segments of any of them, so `/webhooks/` exempts
+    ///`/webhooks/github`, but not `/webhooks-admin`. It's compared to the
+    ///decoded and normalized path, which is the same path as the router
+    ///sees. Paths with encoded slashes, like `%2F`, are never exempted,
+    ///since they are split differently when they are routed.
+    pub exempt: Vec<String>,
+}
+
+impl Csrf {
+    ///Create a filter with the default settings.
+    pub fn new() -> Csrf {
+        Csrf::default()
+    }
+
+    ///Let requests for paths that start with the segments of `prefix`
+    ///through unchecked.
+    pub fn exempt<S: Into<String>>(mut self, prefix: S) -> Csrf {
+        self.exempt.push(prefix.into());
+        self
+    }
+
+    fn is_exempt(&self, context: &Context) -> bool {
+        let target = context.raw_target();
+        let raw_path = &target[..target.find(|c| c == '?' || c == '#').unwrap_or(target.len())];
+        if raw_path.to_ascii_lowercase().contains("%2f") {
+            return false;
+        }
+
+        let path = match context.path() {
+            Some(path) => path,
+            None => return false
+        };
+
+        self.exempt.iter().any(|prefix| {
+            let mut segments = path.as_bytes().segments();
+            prefix.segments().all(|prefix| segments.next() == Some(prefix))
+        })
+    }
+
+    //The token that was submitted with the request, if any.
+    fn submitted_token(&self, context: &mut Context) -> Result<Option<String>, StatusCode> {
+        if let Some(values) = context.header_raw(&self.header_name) {
+            let value = values.first().and_then(|value| ::std::str::from_utf8(value).ok());
+            return Ok(value.map(|value| value.trim().to_owned()));
+        }
+
+        match context.content_type() {
+            Some(Mime(TopLevel::Application, SubLevel::WwwFormUrlEncoded, _)) => {},
+            _ => return Ok(None)
+        }
+
+        let body = match context.body.peek(self.max_form_length) {
+            Ok(body) => body,
+            Err(BodyError::TooLarge) => return Err(StatusCode::PayloadTooLarge),
+            Err(_) => return Err(StatusCode::BadRequest)
+        };
+
+        match ::utils::parse_parameters(body) {
+            Ok(parameters) => Ok(parameters.get(&self.field_name).map(|value| value.into_owned())),
+            Err(_) => Err(StatusCode::BadRequest)
+        }
+    }
+}
+
+impl Default for Csrf {
+    fn default() -> Csrf {
+        Csrf {
+            cookie_name: "rustful_csrf".to_owned(),
+            header_name: "X-CSRF-Token".to_owned(),
+            field_name: "csrf_token".to_owned(),
+            max_form_length: 64 * 1024,
+            exempt: vec![],
+        }
+    }
+}
+
+///The CSRF token of the current request, as stored by `Csrf`. It should be
+///submitted with any form or AJAX request that changes something.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CsrfToken(pub String);
+
+//A newly issued token cookie.
+struct IssuedCookie(CookiePair);
+
+impl ContextFilter for Csrf {
+    ///Issue a token and check the submitted one, if necessary.
+    fn modify(&self, ctx: FilterContext, context: &mut Context) -> ContextAction {
+        let (token, issued) = match context.signed_cookies() {
+            Some(cookies) => match cookies.get(&self.cookie_name) {
+                Some(token) => (token, false),
+                None => {
+                    let token = match new_token() {
+                        Ok(token) => token,
+                        Err(e) => {
+                            error!("could not generate a CSRF token: {}", e);
+                            return ContextAction::abort(StatusCode::InternalServerError);
+                        }
+                    };
+                    let mut cookie = CookiePair::new(self.cookie_name.clone(), token.clone());
+                    cookie.path = Some("/".to_owned());
+                    cookie.httponly = true;
+                    cookie.secure = context.is_secure();
+
+                    let mut headers = Headers::new();
+                    cookies.set(&mut headers, cookie);
+                    if let Some(cookie) = headers.get::<SetCookie>().and_then(|cookies| cookies.0.first()) {
+                        ctx.storage.insert(IssuedCookie(cookie.clone()));
+                    }
+                    (token, true)
+                }
+            },
+            None => {
+                error!("the CSRF filter requires Server::cookie_keys to be set");
+                return ContextAction::abort(StatusCode::InternalServerError);
+            }
+        };
+        ctx.storage.insert(CsrfToken(token.clone()));
+
+        let unsafe_method = match context.method {
+            Method::Post | Method::Put | Method::Patch | Method::Delete => true,
+            _ => false
+        };
+
+        if !unsafe_method || self.is_exempt(context) {
+            return ContextAction::next();
+        }
+
+        //A new token can't have been submitted.
+        if issued {
+            return ContextAction::abort(StatusCode::Forbidden);
+        }
+
+        match self.submitted_token(context) {
+            Ok(Some(ref submitted)) if constant_time_eq(token.as_bytes(), submitted.as_bytes()) => ContextAction::next(),
+            Ok(_) => ContextAction::abort(StatusCode::Forbidden),
+            Err(status) => ContextAction::abort(status)
+        }
+    }
+}
+
+impl ResponseFilter for Csrf {
+    fn begin<'s>(&'s self, _ctx: FilterContext, status: StatusCode, _headers: &mut Headers) -> (StatusCode, ResponseAction<'s>) {
+        (status, ResponseAction::next::<Data>(None))
+    }
+
+    fn write<'a>(&'a self, _ctx: FilterContext, content: Option<Data<'a>>) -> ResponseAction<'a> {
+        ResponseAction::next(content)
+    }
+
+    fn end<'s>(&'s self, _ctx: FilterContext) -> ResponseAction<'s> {
+        ResponseAction::next::<Data>(None)
+    }
+
+    ///Set the token cookie if a new token was issued.
+    fn finalize(&self, ctx: FilterContext, _status: &mut StatusCode, headers: &mut Headers) {
+        if let Some(&IssuedCookie(ref cookie)) = ctx.storage.get::<IssuedCookie>() {
+            if let Some(&mut SetCookie(ref mut cookies)) = headers.get_mut::<SetCookie>() {
+                if !cookies.iter().any(|existing| existing.name == cookie.name) {
+                    cookies.push(cookie.clone());
+                }
+                return;
+            }
+
+            headers.set(SetCookie(vec![cookie.clone()]));
+        }
+    }
+}
+
+fn new_token() -> io::Result<String> {
+    let mut token = [0; TOKEN_SIZE];
+    try!(random_bytes(&mut token));
+    Ok(token.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
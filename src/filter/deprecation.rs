@@ -0,0 +1,164 @@
+//!Deprecation of API endpoints.
+//!
+//!The `Deprecation` filter marks the responses of a route as deprecated,
+//!with `Deprecation: true`, an optional `Sunset` header, from RFC 8594, with
+//!the date when the route is expected to stop working, and an optional
+//!`Link` to documentation about what to use instead. This lets clients
+//!detect that they are using something that's going away, without any
+//!changes to the handler.
+//!
+//!It's meant to be added to the routes that are deprecated, using
+//!`RouteFilters`:
+//!
+//!```
+//!use std::time::{Duration, UNIX_EPOCH};
+//!use rustful::{Router, TreeRouter, Context, Response};
+//!use rustful::filter::{Deprecation, RouteFilters};
+//!use rustful::Method::Get;
+//!
+//!fn old_users(_context: Context, response: Response) {
+//!    response.send("[]");
+//!}
+//!
+//!let sunset = UNIX_EPOCH + Duration::from_secs(1893456000);
+//!let deprecation = Deprecation::new(Some(sunset), Some("https://example.com/docs/v2")).unwrap();
+//!
+//!let old_users = RouteFilters::new(old_users as fn(Context, Response))
+//!    .response_filter(deprecation);
+//!
+//!let mut router = TreeRouter::new();
+//!router.insert(Get, "/v1/users", Box::new(old_users) as Box<rustful::Handler>);
+//!```
+//!
+//!A single response can also be marked with `Response::mark_deprecated`. A
+//!response that already has a `Deprecation` header is left as it is.
+
+use std::io;
+use std::time::SystemTime;
+
+use StatusCode;
+use header::Headers;
+use response::{Data, Error};
+use filter::{FilterContext, ResponseFilter, ResponseAction};
+use utils::format_http_date;
+
+///A filter that marks responses as deprecated.
+///
+///See the [module level documentation](index.html) for more information.
+#[derive(Clone, Debug)]
+pub struct Deprecation {
+    sunset: Option<SystemTime>,
+    link: Option<String>,
+}
+
+impl Deprecation {
+    ///Create a filter that sets `Sunset` to `sunset`, if any, and links to
+    ///`link`, if any. An error is returned if `link` isn't a valid URI
+    ///reference, as described for `Response::mark_deprecated`.
+    pub fn new(sunset: Option<SystemTime>, link: Option<&str>) -> Result<Deprecation, Error> {
+        if let Some(link) = link {
+            try!(check_link(link));
+        }
+
+        Ok(Deprecation {
+            sunset: sunset,
+            link: link.map(|link| link.to_owned()),
+        })
+    }
+}
+
+impl ResponseFilter for Deprecation {
+    fn begin(&self, _ctx: FilterContext, status: StatusCode, _headers: &mut Headers) -> (StatusCode, ResponseAction) {
+        (status, ResponseAction::next::<Data>(None))
+    }
+
+    fn write<'a>(&'a self, _ctx: FilterContext, content: Option<Data<'a>>) -> ResponseAction {
+        ResponseAction::next(content)
+    }
+
+    fn end(&self, _ctx: FilterContext) -> ResponseAction {
+        ResponseAction::next::<Data>(None)
+    }
+
+    ///Set the deprecation headers, unless the response already has them.
+    fn finalize(&self, _ctx: FilterContext, _status: &mut StatusCode, headers: &mut Headers) {
+        if headers.get_raw("Deprecation").is_none() {
+            set_headers(headers, self.sunset, self.link.as_ref().map(|link| &**link));
+        }
+    }
+}
+
+#[doc(hidden)]
+///Internal and may change without warning.
+///
+///The link has to be checked first.
+pub fn set_headers(headers: &mut Headers, sunset: Option<SystemTime>, link: Option<&str>) {
+    headers.set_raw("Deprecation", vec![b"true".to_vec()]);
+
+    if let Some(sunset) = sunset {
+        headers.set_raw("Sunset", vec![format_http_date(sunset).into_bytes()]);
+    }
+
+    if let Some(link) = link {
+        let mut links = headers.get_raw("Link").map_or_else(Vec::new, |links| links.to_vec());
+        links.push(format!("<{}>; rel=\"deprecation\"", link).into_bytes());
+        headers.set_raw("Link", links);
+    }
+}
+
+#[doc(hidden)]
+///Internal and may change without warning.
+///
+///Check that `link` is a URI reference from RFC 3986, or it could break the
+///`Link` header.
+pub fn check_link(link: &str) -> Result<(), Error> {
+    let bytes = link.as_bytes();
+    let valid = !bytes.is_empty() && bytes.iter().enumerate().all(|(i, &byte)| match byte {
+        b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => true,
+        b'-' | b'.' | b'_' | b'~' | b':' | b'/' | b'?' | b'#' | b'[' | b']' | b'@' => true,
+        b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'=' => true,
+        b'%' => bytes.len() > i + 2 && bytes[i + 1].is_ascii_hexdigit() && bytes[i + 2].is_ascii_hexdigit(),
+        _ => false
+    });
+
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput, "the link is not a valid URI reference")))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+    use filter::ResponseFilter;
+    use response::ResponseBuffer;
+    use super::{Deprecation, check_link};
+
+    #[test]
+    fn route_filter() {
+        let sunset = UNIX_EPOCH + Duration::from_secs(1893456000);
+        let filters: Vec<Box<ResponseFilter>> = vec![Box::new(Deprecation::new(Some(sunset), None).unwrap())];
+
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = buffer.filtered(&filters);
+        response.send("old");
+
+        let headers = output.headers().unwrap();
+        assert_eq!(headers.get_raw("Deprecation"), Some(&[b"true".to_vec()][..]));
+        assert_eq!(headers.get_raw("Sunset"), Some(&[b"Tue, 01 Jan 2030 00:00:00 GMT".to_vec()][..]));
+        assert_eq!(headers.get_raw("Link"), None);
+    }
+
+    #[test]
+    fn links() {
+        assert!(check_link("https://example.com/docs/v2?from=v1#users").is_ok());
+        assert!(check_link("/docs/caf%C3%A9").is_ok());
+        assert!(check_link("").is_err());
+        assert!(check_link("/docs/v2>; rel=\"next\"").is_err());
+        assert!(check_link("/docs/v2\r\nSet-Cookie: a=b").is_err());
+        assert!(check_link("/docs/caf\u{e9}").is_err());
+        assert!(check_link("/docs/%C").is_err());
+        assert!(Deprecation::new(None, Some("/docs v2")).is_err());
+    }
+}
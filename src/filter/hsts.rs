@@ -0,0 +1,153 @@
+//!HTTP Strict Transport Security.
+//!
+//!The `StrictTransportSecurity` filter adds a `Strict-Transport-Security`
+//!header to responses, which tells browsers to only use HTTPS for the host
+//!from now on. The header is only sent in responses to secure requests, as
+//!reported by `Context::is_secure`, since browsers ignore it over plain HTTP.
+//!This includes requests that were forwarded by a trusted proxy with
+//!`X-Forwarded-Proto: https`, so the filter works behind a TLS terminating
+//!proxy, as long as it's listed in `Server::trusted_proxies`.
+//!
+//!The filter has to be able to see the request, so it has to be added as
+//!both a context filter and a response filter:
+//!
+//!```no_run
+//!# use rustful::{Server, Context, Response};
+//!use rustful::filter::StrictTransportSecurity;
+//!
+//!# fn handler(_context: Context, _response: Response) {}
+//!let hsts = StrictTransportSecurity::new();
+//!
+//!let server_result = Server {
+//!    trusted_proxies: vec!["127.0.0.1".parse().unwrap()],
+//!    context_filters: vec![Box::new(hsts.clone())],
+//!    response_filters: vec![Box::new(hsts)],
+//!    ..Server::new(handler)
+//!}.run();
+//!```
+//!
+//!A header that has been set by the handler is left as it is.
+
+use std::time::Duration;
+
+use StatusCode;
+use header::Headers;
+use context::Context;
+use response::Data;
+use filter::{FilterContext, ContextFilter, ContextAction, ResponseFilter, ResponseAction};
+
+///A filter that adds `Strict-Transport-Security` to secure responses.
+///
+///See the [module level documentation](index.html) for more information.
+#[derive(Clone, Debug)]
+pub struct StrictTransportSecurity {
+    ///How long the browser should remember to only use HTTPS. The default is
+    ///one year.
+    pub max_age: Duration,
+
+    ///Apply the policy to all subdomains as well, using
+    ///`includeSubDomains`. The default is `false`.
+    pub include_subdomains: bool,
+
+    ///Add the `preload` directive, to allow the host to be included in the
+    ///browsers' preload lists. The default is `false`.
+    pub preload: bool
+}
+
+impl StrictTransportSecurity {
+    ///Create a filter with the default settings.
+    pub fn new() -> StrictTransportSecurity {
+        StrictTransportSecurity::default()
+    }
+
+    ///The value of the header.
+    ///
+    ///```
+    ///use std::time::Duration;
+    ///use rustful::filter::StrictTransportSecurity;
+    ///
+    ///let hsts = StrictTransportSecurity {
+    ///    max_age: Duration::from_secs(600),
+    ///    include_subdomains: true,
+    ///    ..StrictTransportSecurity::new()
+    ///};
+    ///assert_eq!(hsts.header_value(), "max-age=600; includeSubDomains");
+    ///```
+    pub fn header_value(&self) -> String {
+        let mut value = format!("max-age={}", self.max_age.as_secs());
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if self.preload {
+            value.push_str("; preload");
+        }
+        value
+    }
+}
+
+impl Default for StrictTransportSecurity {
+    fn default() -> StrictTransportSecurity {
+        StrictTransportSecurity {
+            max_age: Duration::from_secs(365 * 24 * 60 * 60),
+            include_subdomains: false,
+            preload: false
+        }
+    }
+}
+
+//Marks a secure request.
+struct Secure;
+
+impl ContextFilter for StrictTransportSecurity {
+    ///Check if the request is secure.
+    fn modify(&self, ctx: FilterContext, context: &mut Context) -> ContextAction {
+        if context.is_secure() {
+            ctx.storage.insert(Secure);
+        }
+        ContextAction::next()
+    }
+}
+
+impl ResponseFilter for StrictTransportSecurity {
+    fn begin(&self, _ctx: FilterContext, status: StatusCode, _headers: &mut Headers) -> (StatusCode, ResponseAction) {
+        (status, ResponseAction::next::<Data>(None))
+    }
+
+    fn write<'a>(&'a self, _ctx: FilterContext, content: Option<Data<'a>>) -> ResponseAction {
+        ResponseAction::next(content)
+    }
+
+    fn end(&self, _ctx: FilterContext) -> ResponseAction {
+        ResponseAction::next::<Data>(None)
+    }
+
+    ///Set `Strict-Transport-Security` if the request is secure.
+    fn finalize(&self, ctx: FilterContext, _status: &mut StatusCode, headers: &mut Headers) {
+        if ctx.storage.get::<Secure>().is_some() && headers.get_raw("Strict-Transport-Security").is_none() {
+            headers.set_raw("Strict-Transport-Security", vec![self.header_value().into_bytes()]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use filter::ResponseFilter;
+    use response::ResponseBuffer;
+    use super::{StrictTransportSecurity, Secure};
+
+    #[test]
+    fn only_secure() {
+        let filters: Vec<Box<ResponseFilter>> = vec![Box::new(StrictTransportSecurity::new())];
+
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = buffer.filtered(&filters);
+        response.send("plain");
+        assert_eq!(output.headers().unwrap().get_raw("Strict-Transport-Security"), None);
+
+        let mut buffer = ResponseBuffer::new();
+        let (mut response, output) = buffer.filtered(&filters);
+        response.filter_storage_mut().insert(Secure);
+        response.send("secure");
+        assert_eq!(output.headers().unwrap().get_raw("Strict-Transport-Security"), Some(&[b"max-age=31536000".to_vec()][..]));
+    }
+}
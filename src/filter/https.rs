@@ -0,0 +1,150 @@
+//!Redirection from HTTP to HTTPS.
+//!
+//!The `RequireHttps` filter responds to insecure requests with a redirect to
+//!the same URL, but with the `https` scheme, before they reach any handler.
+//!Whether a request is secure is decided by `Context::is_secure`, so
+//!requests that were forwarded by a trusted proxy with `X-Forwarded-Proto:
+//!https` are let through, as long as the proxy is listed in
+//!`Server::trusted_proxies`.
+//!
+//!The filter has to set the `Location` header, so it has to be added as both
+//!a context filter and a response filter. It works well together with
+//!`StrictTransportSecurity`, which tells the browsers to skip the redirect
+//!next time:
+//!
+//!```no_run
+//!# use rustful::{Server, Context, Response};
+//!use rustful::filter::{RequireHttps, StrictTransportSecurity};
+//!
+//!# fn handler(_context: Context, _response: Response) {}
+//!let https = RequireHttps::new().exempt("/.well-known/acme-challenge/");
+//!let hsts = StrictTransportSecurity::new();
+//!
+//!let server_result = Server {
+//!    context_filters: vec![Box::new(https.clone()), Box::new(hsts.clone())],
+//!    response_filters: vec![Box::new(https), Box::new(hsts)],
+//!    ..Server::new(handler)
+//!}.run();
+//!```
+//!
+//!Requests without a `Host` header are rejected with `400 Bad Request`,
+//!since there is nowhere to redirect them.
+
+use StatusCode;
+use header::{Headers, Host, Location};
+use context::{Context, TargetForm};
+use response::Data;
+use filter::{FilterContext, ContextFilter, ContextAction, ResponseFilter, ResponseAction};
+
+///A filter that redirects insecure requests to HTTPS.
+///
+///See the [module level documentation](index.html) for more information.
+#[derive(Clone, Debug)]
+pub struct RequireHttps {
+    ///The status of the redirect. Use `308 Permanent Redirect` to make the
+    ///clients keep the method and the body. The default is `301 Moved
+    ///Permanently`.
+    pub status: StatusCode,
+
+    ///The port to redirect to, or `None` to use the default HTTPS port. The
+    ///port of the original request is never kept. The default is `None`.
+    pub port: Option<u16>,
+
+    ///Paths that are let through without being redirected, such as ACME
+    ///challenges. A request path is exempted if it starts with any of them.
+    ///It's compared to the path as it was received, before it's decoded.
+    pub exempt: Vec<String>,
+}
+
+impl RequireHttps {
+    ///Create a filter with the default settings.
+    pub fn new() -> RequireHttps {
+        RequireHttps::default()
+    }
+
+    ///Let requests for paths that start with `prefix` through.
+    pub fn exempt<S: Into<String>>(mut self, prefix: S) -> RequireHttps {
+        self.exempt.push(prefix.into());
+        self
+    }
+
+    fn is_exempt(&self, target: &str) -> bool {
+        let (path, _) = target.split_at(target.find(|c| c == '?' || c == '#').unwrap_or(target.len()));
+        self.exempt.iter().any(|prefix| path.starts_with(&**prefix))
+    }
+}
+
+impl Default for RequireHttps {
+    fn default() -> RequireHttps {
+        RequireHttps {
+            status: StatusCode::MovedPermanently,
+            port: None,
+            exempt: vec![],
+        }
+    }
+}
+
+//The location of a redirect.
+struct Redirect(String);
+
+impl ContextFilter for RequireHttps {
+    ///Redirect the request if it's insecure.
+    fn modify(&self, ctx: FilterContext, context: &mut Context) -> ContextAction {
+        if context.is_secure() || context.target_form() == TargetForm::Asterisk || context.target_form() == TargetForm::Authority {
+            return ContextAction::next();
+        }
+
+        let target = origin_target(context);
+        if self.is_exempt(&target) {
+            return ContextAction::next();
+        }
+
+        let location = match context.headers.get::<Host>() {
+            Some(host) => match self.port {
+                Some(port) if port != 443 => format!("https://{}:{}{}", host.hostname, port, target),
+                _ => format!("https://{}{}", host.hostname, target)
+            },
+            None => return ContextAction::abort(StatusCode::BadRequest)
+        };
+
+        ctx.storage.insert(Redirect(location));
+        ContextAction::abort(self.status)
+    }
+}
+
+//The path and query of the request target, without the scheme and the
+//authority of an absolute target.
+fn origin_target(context: &Context) -> String {
+    let target = context.raw_target();
+    let target = match context.target_form() {
+        TargetForm::Absolute => {
+            let authority = target.find("://").map_or(0, |index| index + 3);
+            &target[target[authority..].find('/').map_or(target.len(), |index| authority + index)..]
+        },
+        _ => target
+    };
+
+    let target = target.split('#').next().unwrap_or(target);
+    if target.is_empty() { "/".to_owned() } else { target.to_owned() }
+}
+
+impl ResponseFilter for RequireHttps {
+    fn begin<'s>(&'s self, _ctx: FilterContext, status: StatusCode, _headers: &mut Headers) -> (StatusCode, ResponseAction<'s>) {
+        (status, ResponseAction::next::<Data>(None))
+    }
+
+    fn write<'a>(&'a self, _ctx: FilterContext, content: Option<Data<'a>>) -> ResponseAction<'a> {
+        ResponseAction::next(content)
+    }
+
+    fn end<'s>(&'s self, _ctx: FilterContext) -> ResponseAction<'s> {
+        ResponseAction::next::<Data>(None)
+    }
+
+    ///Set `Location` if the request was redirected.
+    fn finalize(&self, ctx: FilterContext, _status: &mut StatusCode, headers: &mut Headers) {
+        if let Some(&Redirect(ref location)) = ctx.storage.get::<Redirect>() {
+            headers.set(Location(location.clone()));
+        }
+    }
+}
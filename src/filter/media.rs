@@ -0,0 +1,122 @@
+//!Media type requirements for routes.
+//!
+//!The `MediaTypes` filter checks the media type of the request body, and
+//!what the client accepts in return, before the handler runs. It's meant to
+//!be added to the routes that need it, using `RouteFilters`:
+//!
+//!```
+//!#[macro_use]
+//!extern crate rustful;
+//!use rustful::{Router, TreeRouter, Context, Response};
+//!use rustful::filter::{RouteFilters, MediaTypes, Negotiated};
+//!use rustful::header::ContentType;
+//!use rustful::Method::Post;
+//!
+//!fn create(_context: Context, mut response: Response) {
+//!    let media_type = response.filter_storage().get::<Negotiated>().map(|n| n.0.clone());
+//!    if let Some(media_type) = media_type {
+//!        response.headers_mut().set(ContentType(media_type));
+//!    }
+//!    response.send("{\"created\": true}");
+//!}
+//!
+//!# fn main() {
+//!let create = RouteFilters::new(create as fn(Context, Response))
+//!    .context_filter(MediaTypes::new()
+//!        .consumes(content_type!(Application / Json))
+//!        .produces(content_type!(Application / Json)));
+//!
+//!let mut router = TreeRouter::new();
+//!router.insert(Post, "/items", Box::new(create) as Box<rustful::Handler>);
+//!# }
+//!```
+//!
+//!A request with a body of any other type is rejected with `415 Unsupported
+//!Media Type`, and a request that doesn't accept any of the produced types is
+//!rejected with `406 Not Acceptable`. The handler is not called in either
+//!case.
+
+use StatusCode;
+use header::{ContentLength, TransferEncoding};
+use mime::{Mime, TopLevel, SubLevel};
+use context::Context;
+use filter::{FilterContext, ContextFilter, ContextAction};
+
+///A context filter that checks the request and response media types.
+///
+///See the [module level documentation](index.html) for more information.
+#[derive(Clone, Debug, Default)]
+pub struct MediaTypes {
+    consumes: Vec<Mime>,
+    produces: Vec<Mime>
+}
+
+impl MediaTypes {
+    ///Create a filter that accepts everything.
+    pub fn new() -> MediaTypes {
+        MediaTypes::default()
+    }
+
+    ///Accept request bodies of this media type. Ranges, like `text/*`, can
+    ///be used, and the parameters are ignored. Any media type is accepted if
+    ///none are added. Requests without a body are always accepted.
+    pub fn consumes(mut self, media_type: Mime) -> MediaTypes {
+        self.consumes.push(media_type);
+        self
+    }
+
+    ///Add a media type that the route can respond with. The one that the
+    ///client prefers, according to the `Accept` header, is put in the filter
+    ///storage as `Negotiated`, and the first one is chosen if the client
+    ///accepts anything. Nothing is negotiated if none are added.
+    pub fn produces(mut self, media_type: Mime) -> MediaTypes {
+        self.produces.push(media_type);
+        self
+    }
+
+    fn accepts(&self, media_type: &Mime) -> bool {
+        self.consumes.iter().any(|&Mime(ref top, ref sub, _)| {
+            (*top == TopLevel::Star || *top == media_type.0) && (*sub == SubLevel::Star || *sub == media_type.1)
+        })
+    }
+}
+
+///The media type that was chosen for the response by `MediaTypes`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Negotiated(pub Mime);
+
+impl ContextFilter for MediaTypes {
+    ///Check the media type of the body and negotiate the response type.
+    fn modify(&self, ctx: FilterContext, context: &mut Context) -> ContextAction {
+        if !self.consumes.is_empty() && has_body(context) {
+            match context.content_type() {
+                Some(ref media_type) if self.accepts(media_type) => {},
+                _ => return ContextAction::abort(StatusCode::UnsupportedMediaType)
+            }
+        }
+
+        if !self.produces.is_empty() {
+            let negotiated = if context.has_header("Accept") {
+                context.preferred_content_type(&self.produces)
+            } else {
+                self.produces.first()
+            };
+
+            match negotiated {
+                Some(media_type) => {
+                    ctx.storage.insert(Negotiated(media_type.clone()));
+                },
+                None => return ContextAction::abort(StatusCode::NotAcceptable)
+            }
+        }
+
+        ContextAction::next()
+    }
+}
+
+fn has_body(context: &Context) -> bool {
+    match context.headers.get::<ContentLength>() {
+        Some(&ContentLength(length)) => length > 0,
+        None => context.headers.has::<TransferEncoding>()
+    }
+}
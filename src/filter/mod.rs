@@ -0,0 +1,197 @@
+//!Request and context filters.
+
+use std::io::Write;
+
+use anymap::AnyMap;
+
+use StatusCode;
+use header::Headers;
+
+use context::Context;
+
+use response::Data;
+use server::Global;
+
+pub use self::compression::{Compression, ContentCoding};
+pub use self::route::RouteFilters;
+pub use self::csrf::{Csrf, CsrfToken};
+pub use self::deprecation::Deprecation;
+pub use self::hsts::StrictTransportSecurity;
+pub use self::https::RequireHttps;
+pub use self::media::{MediaTypes, Negotiated};
+pub use self::status::{StatusHooks, StatusHook, StatusMatch};
+pub use self::transform::{Transforms, Transform, BodyTransform, Replace};
+#[cfg(feature = "encoding")]
+pub use self::charset::FallbackCharset;
+
+pub mod compression;
+pub mod route;
+pub mod csrf;
+pub mod deprecation;
+pub mod hsts;
+pub mod https;
+pub mod media;
+pub mod status;
+pub mod transform;
+#[cfg(feature = "encoding")]
+pub mod charset;
+
+///Contextual tools for filters.
+pub struct FilterContext<'a> {
+    ///Shared storage for filters. It is local to the current request and
+    ///accessible from the handler and all of the filters. It can be used to
+    ///send data between these units.
+    pub storage: &'a mut AnyMap,
+
+    ///Globally accessible data.
+    pub global: &'a Global,
+}
+
+///A trait for context filters.
+///
+///They are able to modify and react to a `Context` before it's sent to the handler.
+///
+///The `Context` is borrowed, so filters that only inspect the request, like
+///logging or authentication filters, can do so without allocating. The
+///accessors differ in how much work they do:
+///
+/// * `method`, `http_version` and `address` are plain fields.
+/// * `path()`, `query_raw()` and `header_raw()` borrow the raw data, and
+///`has_header()` only checks if a header is present.
+///Strings are borrowed as `MaybeUtf8Slice`, since they may not be UTF-8.
+/// * `query.get()` borrows the value if it's valid UTF-8, and only allocates
+///for a lossy conversion.
+/// * `headers.get()` parses the header the first time it's accessed, and the
+///parsed value is cached in `headers`, so later accesses only borrow it.
+/// * `preferred_language()` and similar negotiation methods parse the header
+///every time and return owned values, when applicable.
+///
+///The route variables are not available yet, since the request has not been
+///routed.
+///
+///```
+///use rustful::{Context, StatusCode};
+///use rustful::filter::{ContextAction, ContextFilter, FilterContext};
+///
+///struct RequireToken;
+///
+///impl ContextFilter for RequireToken {
+///    fn modify(&self, _ctx: FilterContext, context: &mut Context) -> ContextAction {
+///        let is_public = context.path().map_or(false, |path| path.as_bytes().starts_with(b"/public/"));
+///        let has_token = context.header_raw("X-Token").is_some() || context.query_raw("token").is_some();
+///
+///        if is_public || has_token {
+///            ContextAction::next()
+///        } else {
+///            ContextAction::abort(StatusCode::Forbidden)
+///        }
+///    }
+///}
+///```
+pub trait ContextFilter: Send + Sync {
+    ///Try to modify the handler `Context`.
+    fn modify(&self, context: FilterContext, request_context: &mut Context) -> ContextAction;
+}
+
+///The result from a context filter.
+#[derive(Clone)]
+pub enum ContextAction {
+    ///Continue to the next filter in the stack.
+    Next,
+
+    ///Abort and set HTTP status.
+    Abort(StatusCode)
+}
+
+impl<'a> ContextAction {
+    ///Continue to the next filter in the stack.
+    pub fn next() -> ContextAction {
+        ContextAction::Next
+    }
+
+    ///Abort and set HTTP status.
+    pub fn abort(status: StatusCode) -> ContextAction {
+        ContextAction::Abort(status)
+    }
+}
+
+
+///A trait for response filters.
+///
+///They are able to modify headers and data before it gets written in the response.
+pub trait ResponseFilter: Send + Sync {
+    ///Set or modify headers before they are sent to the client and maybe initiate the body.
+    fn begin(&self, context: FilterContext, status: StatusCode, headers: &mut Headers) ->
+        (StatusCode, ResponseAction);
+
+    ///Handle content before writing it to the body.
+    fn write<'a>(&'a self, context: FilterContext, content: Option<Data<'a>>) -> ResponseAction;
+
+    ///End of body writing. Last chance to add content.
+    fn end(&self, context: FilterContext) -> ResponseAction;
+
+    ///Make final changes to the status code and headers, right before they
+    ///are sent to the client. This is called for every response, after
+    ///`begin` has been called for all of the filters in the stack, so the
+    ///status and headers are in their final state. The filters are called in
+    ///stack order.
+    ///
+    ///It's also called for raw responses, but their bodies are never wrapped
+    ///and `begin` is never called for them. The filter storage contains a
+    ///`RawResponse` marker in that case, so filters that change the body can
+    ///leave those responses alone.
+    ///
+    ///Does nothing by default.
+    #[allow(unused_variables)]
+    fn finalize(&self, context: FilterContext, status: &mut StatusCode, headers: &mut Headers) {}
+
+    ///Wrap the writer for the response body. This makes it possible to
+    ///transform the body as a stream, after every filter has written to it.
+    ///The wrappers are applied in stack order, so the wrapper from the first
+    ///filter will receive the data first.
+    ///
+    ///Chunked framing, or the size of a sized response, is taken care of
+    ///after the data has passed through the wrappers, so they can write any
+    ///bytes they like. Any pending data has to be written when the wrapper is
+    ///dropped. Raw responses are not wrapped.
+    ///
+    ///Leaves the writer as it is by default.
+    #[allow(unused_variables)]
+    fn wrap_body(&self, context: FilterContext, writer: Box<Write>) -> Box<Write> {
+        writer
+    }
+}
+
+///Put in the filter storage before the headers of a raw response are
+///finalized. See `ResponseFilter::finalize`.
+pub struct RawResponse;
+
+///The result from a response filter.
+#[derive(Clone)]
+pub enum ResponseAction<'a> {
+    ///Continue to the next filter and maybe write data.
+    Next(Option<Data<'a>>),
+
+    ///Do not continue to the next filter.
+    SilentAbort,
+
+    ///Abort with an error.
+    Abort(String)
+}
+
+impl<'a> ResponseAction<'a> {
+    ///Continue to the next filter and maybe write data.
+    pub fn next<T: Into<Data<'a>>>(data: Option<T>) -> ResponseAction<'a> {
+        ResponseAction::Next(data.map(|d| d.into()))
+    }
+
+    ///Do not continue to the next filter.
+    pub fn silent_abort() -> ResponseAction<'a> {
+        ResponseAction::SilentAbort
+    }
+
+    ///Abort with an error.
+    pub fn abort(message: String) -> ResponseAction<'a> {
+        ResponseAction::Abort(message)
+    }
+}
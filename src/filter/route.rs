@@ -0,0 +1,149 @@
+//!Filters for individual routes.
+//!
+//!The filters in `Server` apply to every request, but some routes may need
+//!more, like authentication, while others need less. `RouteFilters` wraps a
+//!handler with its own filter stacks, so it can be inserted into a router as
+//!a single route, or as a fallback handler for a group of routes.
+//!
+//!The filters are applied in this order:
+//!
+//! 1. The global context filters, from `Server::context_filters`. They run
+//!before the request is routed, so they can't be skipped by a route.
+//! 2. The context filters of the route.
+//! 3. The handler.
+//! 4. The response filters of the route. They see the output of the handler
+//!first, like an inner layer.
+//! 5. The global response filters, from `Server::response_filters`, except
+//!for those that the route has chosen to skip.
+//!
+//!Aborting in a route context filter works like aborting in a global one, so
+//!the handler is skipped, but all of the response filters are still applied.
+//!
+//!`RouteFilters` can be nested, for example to add filters to a group of
+//!routes that already have their own. The response filters of the inner
+//!`RouteFilters` are applied first, and no global filter that any of them
+//!skips is applied.
+
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use context::Context;
+use handler::Handler;
+use response::Response;
+use filter::{FilterContext, ContextFilter, ContextAction, ResponseFilter};
+
+///A handler with its own context and response filters.
+///
+///See the [module level documentation](index.html) for the order of the
+///filters.
+///
+///```
+///use rustful::{Router, TreeRouter, Context, Response, StatusCode};
+///use rustful::filter::{ContextFilter, ContextAction, FilterContext, RouteFilters};
+///use rustful::Method::Get;
+///
+///struct RequireToken;
+///
+///impl ContextFilter for RequireToken {
+///    fn modify(&self, _ctx: FilterContext, context: &mut Context) -> ContextAction {
+///        if context.header_raw("X-Token").is_some() {
+///            ContextAction::next()
+///        } else {
+///            ContextAction::abort(StatusCode::Forbidden)
+///        }
+///    }
+///}
+///
+///fn public(_context: Context, response: Response) {
+///    response.send("hello");
+///}
+///
+///fn private(_context: Context, response: Response) {
+///    response.send("secret");
+///}
+///
+///let private = RouteFilters::new(private as fn(Context, Response))
+///    .context_filter(RequireToken)
+///    //The first global response filter is skipped for this route.
+///    .skip_global_response_filter(0);
+///
+///let mut router = TreeRouter::new();
+///router.insert(Get, "/public", Box::new(public) as Box<rustful::Handler>);
+///router.insert(Get, "/private", Box::new(private) as Box<rustful::Handler>);
+///```
+pub struct RouteFilters<H> {
+    handler: H,
+    context_filters: Vec<Box<ContextFilter>>,
+    response_filters: Arc<RouteResponseFilters>,
+}
+
+impl<H: Handler> RouteFilters<H> {
+    ///Wrap a handler without adding any filters.
+    pub fn new(handler: H) -> RouteFilters<H> {
+        RouteFilters {
+            handler: handler,
+            context_filters: vec![],
+            response_filters: Arc::new(RouteResponseFilters {
+                filters: vec![],
+                skip_global: vec![],
+            }),
+        }
+    }
+
+    ///Add a context filter to the end of the route's context filter stack.
+    pub fn context_filter<F: ContextFilter + 'static>(mut self, filter: F) -> RouteFilters<H> {
+        self.context_filters.push(Box::new(filter));
+        self
+    }
+
+    ///Add a response filter to the end of the route's response filter stack.
+    pub fn response_filter<F: ResponseFilter + 'static>(mut self, filter: F) -> RouteFilters<H> {
+        self.response_filters_mut().filters.push(Box::new(filter));
+        self
+    }
+
+    ///Skip a global response filter for this route. The filter is
+    ///identified by its index in `Server::response_filters`.
+    pub fn skip_global_response_filter(mut self, index: usize) -> RouteFilters<H> {
+        self.response_filters_mut().skip_global.push(index);
+        self
+    }
+
+    fn response_filters_mut(&mut self) -> &mut RouteResponseFilters {
+        Arc::get_mut(&mut self.response_filters).expect("route filters can't be changed while a request is handled")
+    }
+}
+
+impl<H: Handler> Handler for RouteFilters<H> {
+    fn handle_request(&self, mut context: Context, mut response: Response) {
+        response.push_route_filters(self.response_filters.clone());
+
+        for filter in &self.context_filters {
+            let action = {
+                let filter_context = FilterContext {
+                    storage: response.filter_storage_mut(),
+                    global: context.global,
+                };
+                filter.modify(filter_context, &mut context)
+            };
+
+            if let ContextAction::Abort(status) = action {
+                response.set_status(status);
+                return;
+            }
+        }
+
+        self.handler.handle_request(context, response);
+    }
+
+    fn description(&self) -> Option<Cow<'static, str>> {
+        self.handler.description()
+    }
+}
+
+#[doc(hidden)]
+///Internal and may change without warning.
+pub struct RouteResponseFilters {
+    pub filters: Vec<Box<ResponseFilter>>,
+    pub skip_global: Vec<usize>,
+}
@@ -0,0 +1,315 @@
+//!Post-processing of responses with certain status codes.
+//!
+//!The `StatusHooks` filter runs hooks for responses with a specific status
+//!code, like `503 Service Unavailable`, or a whole class of them, like every
+//!server error. A hook can add or change headers, and it can choose a
+//!`Transform` for the body, which makes it possible to do things like adding
+//!`Retry-After` to every 503 response, or injecting a snippet into every
+//!successful HTML page, without involving the handlers:
+//!
+//!```
+//!use rustful::{Server, Context, Response, StatusCode, StatusClass};
+//!use rustful::header::{Headers, ContentType};
+//!use rustful::mime::{Mime, TopLevel, SubLevel};
+//!use rustful::filter::{StatusHooks, StatusHook, Transform, Replace};
+//!
+//!//Injects an analytics script into HTML pages.
+//!struct Analytics;
+//!
+//!impl StatusHook for Analytics {
+//!    fn headers(&self, _status: StatusCode, _headers: &mut Headers) {}
+//!
+//!    fn transform(&self, _status: StatusCode, headers: &Headers) -> Option<Box<Transform>> {
+//!        match headers.get() {
+//!            Some(&ContentType(Mime(TopLevel::Text, SubLevel::Html, _))) => {
+//!                Some(Box::new(Replace::new("</body>", "<script src=\"/analytics.js\"></script></body>")))
+//!            },
+//!            _ => None
+//!        }
+//!    }
+//!}
+//!
+//!fn retry_later(_status: StatusCode, headers: &mut Headers) {
+//!    if headers.get_raw("Retry-After").is_none() {
+//!        headers.set_raw("Retry-After", vec![b"120".to_vec()]);
+//!    }
+//!}
+//!
+//!# fn handler(_context: Context, _response: Response) {}
+//!let hooks = StatusHooks::new()
+//!    .on(StatusCode::ServiceUnavailable, retry_later)
+//!    .on(StatusClass::Success, Analytics);
+//!
+//!let server = Server {
+//!    response_filters: vec![Box::new(hooks)],
+//!    ..Server::new(handler)
+//!};
+//!```
+//!
+//!The hooks apply to every response that passes through the response
+//!filters, no matter where it comes from. This includes responses from the
+//!handlers, requests that are rejected by context filters, and errors from
+//!the server itself, like the `503 Service Unavailable` responses from
+//!`Server::large_body_limit`. Raw responses bypass most of the response
+//!filter steps, but they are still finalized, so the hooks can change their
+//!headers. Their bodies are never transformed.
+//!
+//!The order of things is as follows:
+//!
+//! 1. The default headers, as well as `Server::content_type` and
+//!`Server::server`, are set before the handler is called, and the handler
+//!can then change the status and the headers as it likes.
+//! 2. The response filters run `begin`, in stack order, and may change the
+//!status and the headers further.
+//! 3. The hooks run when the filter makes its final changes, in
+//!`ResponseFilter::finalize`, so they see the final status, and the headers
+//!from the handler and the filters. They can therefore override both the
+//!default headers and the headers from the handler, which is why hooks like
+//!`retry_later`, above, should check if the header is already set. The
+//!`finalize` step of the filters that come after `StatusHooks` in the stack
+//!still runs after the hooks. The hooks themselves run in the order they
+//!were added, and every hook that matches the status is used.
+//! 4. The body transformations run in `ResponseFilter::wrap_body`, after all
+//!of the response filters have written to the body. They have to run before
+//!compression, so `StatusHooks` has to be placed before `Compression` in the
+//!stack, just like `Transforms`. The body isn't transformed if it already
+//!has a `Content-Encoding`, or if the status doesn't allow a body, and
+//!`Content-Length` is removed from transformed responses.
+
+use std::io::Write;
+
+use {StatusCode, StatusClass};
+use header::{Headers, ContentEncoding};
+use response::Data;
+use filter::{FilterContext, ResponseFilter, ResponseAction};
+use filter::transform::{self, Transform};
+
+///A hook for responses with a matching status.
+///
+///It's implemented for functions and closures with the same signature as
+///`headers`, for hooks that only change the headers.
+pub trait StatusHook: Send + Sync {
+    ///Add or change the headers of the response.
+    fn headers(&self, status: StatusCode, headers: &mut Headers);
+
+    ///Choose a transformation of the body. This is called after `headers`,
+    ///and for every hook that matches the status.
+    ///
+    ///Leaves the body as it is by default.
+    #[allow(unused_variables)]
+    fn transform(&self, status: StatusCode, headers: &Headers) -> Option<Box<Transform>> {
+        None
+    }
+}
+
+impl<F: Fn(StatusCode, &mut Headers) + Send + Sync> StatusHook for F {
+    fn headers(&self, status: StatusCode, headers: &mut Headers) {
+        self(status, headers)
+    }
+}
+
+///The statuses that a hook applies to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatusMatch {
+    ///A single status code.
+    Code(StatusCode),
+
+    ///A whole class of status codes, like `StatusClass::ServerError` for
+    ///5xx.
+    Class(StatusClass),
+}
+
+impl StatusMatch {
+    ///Check if `status` matches.
+    ///
+    ///```
+    ///use rustful::{StatusCode, StatusClass};
+    ///use rustful::filter::StatusMatch;
+    ///
+    ///assert!(StatusMatch::from(StatusClass::ServerError).matches(StatusCode::BadGateway));
+    ///assert!(!StatusMatch::from(StatusCode::ServiceUnavailable).matches(StatusCode::BadGateway));
+    ///```
+    pub fn matches(&self, status: StatusCode) -> bool {
+        match *self {
+            StatusMatch::Code(code) => code == status,
+            StatusMatch::Class(class) => class == status.class()
+        }
+    }
+}
+
+impl From<StatusCode> for StatusMatch {
+    fn from(status: StatusCode) -> StatusMatch {
+        StatusMatch::Code(status)
+    }
+}
+
+impl From<StatusClass> for StatusMatch {
+    fn from(class: StatusClass) -> StatusMatch {
+        StatusMatch::Class(class)
+    }
+}
+
+///A response filter that runs hooks for responses with certain statuses.
+///
+///See the [module level documentation](index.html) for more information.
+#[derive(Default)]
+pub struct StatusHooks {
+    hooks: Vec<(StatusMatch, Box<StatusHook>)>,
+}
+
+impl StatusHooks {
+    ///Create a filter without any hooks.
+    pub fn new() -> StatusHooks {
+        StatusHooks::default()
+    }
+
+    ///Add a hook for a status code, or a class of status codes.
+    pub fn on<M: Into<StatusMatch>, H: StatusHook + 'static>(mut self, status: M, hook: H) -> StatusHooks {
+        self.hooks.push((status.into(), Box::new(hook)));
+        self
+    }
+}
+
+//The transformations from the hooks, after the headers have been checked.
+struct Active(Vec<Box<Transform>>);
+
+impl ResponseFilter for StatusHooks {
+    fn begin<'s>(&'s self, _ctx: FilterContext, status: StatusCode, _headers: &mut Headers) -> (StatusCode, ResponseAction<'s>) {
+        (status, ResponseAction::next::<Data>(None))
+    }
+
+    fn write<'a>(&'a self, _ctx: FilterContext, content: Option<Data<'a>>) -> ResponseAction<'a> {
+        ResponseAction::next(content)
+    }
+
+    fn end<'s>(&'s self, _ctx: FilterContext) -> ResponseAction<'s> {
+        ResponseAction::next::<Data>(None)
+    }
+
+    ///Run the hooks that match the final status.
+    fn finalize(&self, ctx: FilterContext, status: &mut StatusCode, headers: &mut Headers) {
+        let mut transforms = vec![];
+        for &(ref status_match, ref hook) in &self.hooks {
+            if status_match.matches(*status) {
+                hook.headers(*status, headers);
+                transforms.extend(hook.transform(*status, headers));
+            }
+        }
+
+        match *status {
+            _ if transforms.is_empty() => return,
+            StatusCode::NoContent | StatusCode::NotModified => return,
+            _ if headers.has::<ContentEncoding>() => return,
+            _ => {}
+        }
+
+        headers.remove_raw("content-length");
+
+        //The transformations from more than one `StatusHooks` are applied
+        //together, by the first one.
+        match ctx.storage.get_mut::<Active>() {
+            Some(&mut Active(ref mut active)) => return active.extend(transforms),
+            None => {}
+        }
+        ctx.storage.insert(Active(transforms));
+    }
+
+    ///Transform the body, if any of the hooks chose to.
+    fn wrap_body(&self, ctx: FilterContext, writer: Box<Write>) -> Box<Write> {
+        match ctx.storage.remove::<Active>() {
+            Some(Active(mut transforms)) => if transforms.len() == 1 {
+                transform::wrap_writer(writer, transforms.remove(0))
+            } else {
+                transform::wrap_writer(writer, Box::new(Chain(transforms)))
+            },
+            None => writer
+        }
+    }
+}
+
+//Transformations that are applied one after the other.
+struct Chain(Vec<Box<Transform>>);
+
+impl Transform for Chain {
+    fn transform(&mut self, input: &[u8], output: &mut Vec<u8>) {
+        let mut data = input.to_vec();
+        for transform in &mut self.0 {
+            let mut next = vec![];
+            transform.transform(&data, &mut next);
+            data = next;
+        }
+        output.extend_from_slice(&data);
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) {
+        let mut data = vec![];
+        for transform in &mut self.0 {
+            let mut next = vec![];
+            transform.transform(&data, &mut next);
+            transform.finish(&mut next);
+            data = next;
+        }
+        output.extend_from_slice(&data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {StatusCode, StatusClass};
+    use header::{Headers, ContentLength};
+    use filter::{ResponseFilter, Transform, Replace};
+    use response::ResponseBuffer;
+    use super::{StatusHooks, StatusHook};
+
+    fn retry_later(_status: StatusCode, headers: &mut Headers) {
+        headers.set_raw("Retry-After", vec![b"120".to_vec()]);
+    }
+
+    struct Replacer(&'static str, &'static str);
+
+    impl StatusHook for Replacer {
+        fn headers(&self, _status: StatusCode, _headers: &mut Headers) {}
+
+        fn transform(&self, _status: StatusCode, _headers: &Headers) -> Option<Box<Transform>> {
+            Some(Box::new(Replace::new(self.0, self.1)))
+        }
+    }
+
+    #[test]
+    fn match_statuses() {
+        let filters: Vec<Box<ResponseFilter>> = vec![Box::new(
+            StatusHooks::new()
+                .on(StatusCode::ServiceUnavailable, retry_later)
+                .on(StatusClass::ServerError, Replacer("error", "problem"))
+                .on(StatusClass::ServerError, Replacer("prob", "troub"))
+        )];
+
+        let mut buffer = ResponseBuffer::new();
+        let (mut response, output) = buffer.filtered(&filters);
+        response.set_status(StatusCode::ServiceUnavailable);
+        response.send("an error");
+
+        let headers = output.headers().unwrap();
+        assert_eq!(headers.get_raw("Retry-After"), Some(&[b"120".to_vec()][..]));
+        assert_eq!(headers.get(), Some(&ContentLength(11)));
+        assert_eq!(output.body(), Some(b"an troublem".to_vec()));
+
+        let mut buffer = ResponseBuffer::new();
+        let (mut response, output) = buffer.filtered(&filters);
+        response.set_status(StatusCode::BadGateway);
+        let mut chunked = response.into_chunked();
+        chunked.send("an err");
+        chunked.send("or");
+        chunked.end().unwrap();
+
+        assert_eq!(output.headers().unwrap().get_raw("Retry-After"), None);
+        assert_eq!(output.body(), Some(b"an troublem".to_vec()));
+
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = buffer.filtered(&filters);
+        response.send("an error");
+
+        assert_eq!(output.headers().unwrap().get_raw("Retry-After"), None);
+        assert_eq!(output.body(), Some(b"an error".to_vec()));
+    }
+}
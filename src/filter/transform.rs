@@ -0,0 +1,296 @@
+//!Streaming transformation of response bodies.
+//!
+//!The `Transforms` filter lets a handler modify its own response body as it
+//!passes through the filters, one chunk at a time, without buffering the
+//!whole body. This is useful for things like injecting a CSP nonce into an
+//!HTML page that is streamed from a file or a template. The handler chooses
+//!a `Transform` for the response by putting it in the filter storage, as a
+//!`BodyTransform`:
+//!
+//!```
+//!use rustful::{Context, Response};
+//!use rustful::filter::{BodyTransform, Replace};
+//!
+//!fn page(_context: Context, mut response: Response) {
+//!    let nonce = "r4nd0m";
+//!    response.headers_mut().set_raw(
+//!        "Content-Security-Policy",
+//!        vec![format!("script-src 'nonce-{}'", nonce).into_bytes()]
+//!    );
+//!    response.filter_storage_mut().insert(BodyTransform::new(Replace::new("{{nonce}}", nonce)));
+//!
+//!    let mut chunked = response.into_chunked();
+//!    chunked.send("<script nonce=\"{{no");
+//!    chunked.send("nce}}\">run();</script>");
+//!}
+//!```
+//!
+//!The transformation runs in `ResponseFilter::wrap_body`, so it sees the
+//!body after all of the response filters have processed it. It has to run
+//!before compression, so `Transforms` has to be placed before `Compression`
+//!in the response filter stack, since the first filter's wrapper receives
+//!the data first. A body that already has a `Content-Encoding`, when the
+//!filter makes its final changes to the headers, is left as it is. The
+//!`Content-Length` header is removed from transformed responses, since the
+//!length may change, and the length of a sized response is then counted
+//!after the transformation.
+
+use std::io::{self, Write};
+
+use StatusCode;
+use header::{Headers, ContentEncoding};
+use response::Data;
+use filter::{FilterContext, ResponseFilter, ResponseAction};
+
+///A transformation of a response body.
+///
+///It receives the body one chunk at a time, and appends the transformed
+///bytes to `output`. Anything that can't be decided yet, like the
+///beginning of a pattern at the end of a chunk, can be held back until the
+///next chunk, or until `finish` is called at the end of the body.
+pub trait Transform: Send {
+    ///Transform a chunk of the body.
+    fn transform(&mut self, input: &[u8], output: &mut Vec<u8>);
+
+    ///Write anything that has been held back, at the end of the body.
+    ///
+    ///Does nothing by default.
+    #[allow(unused_variables)]
+    fn finish(&mut self, output: &mut Vec<u8>) {}
+}
+
+///The transformation of the current response, to be picked up by
+///`Transforms`.
+pub struct BodyTransform(pub Box<Transform>);
+
+impl BodyTransform {
+    ///Use `transform` for the current response.
+    pub fn new<T: Transform + 'static>(transform: T) -> BodyTransform {
+        BodyTransform(Box::new(transform))
+    }
+}
+
+///Replaces every occurrence of a pattern with a replacement.
+///
+///Patterns that are split between chunks are found too. The smallest
+///possible part of the end of each chunk is held back, which is never more
+///than the length of the pattern, minus one byte.
+///
+///```
+///use rustful::filter::{Transform, Replace};
+///
+///let mut replace = Replace::new("{{name}}", "world");
+///let mut output = vec![];
+///replace.transform(b"hello, {{na", &mut output);
+///replace.transform(b"me}}!", &mut output);
+///replace.finish(&mut output);
+///assert_eq!(output, b"hello, world!");
+///```
+#[derive(Clone, Debug)]
+pub struct Replace {
+    pattern: Vec<u8>,
+    replacement: Vec<u8>,
+    pending: Vec<u8>
+}
+
+impl Replace {
+    ///Replace `pattern` with `replacement`. The body is left as it is if
+    ///`pattern` is empty.
+    pub fn new<P: Into<Vec<u8>>, R: Into<Vec<u8>>>(pattern: P, replacement: R) -> Replace {
+        Replace {
+            pattern: pattern.into(),
+            replacement: replacement.into(),
+            pending: vec![]
+        }
+    }
+}
+
+impl Transform for Replace {
+    fn transform(&mut self, input: &[u8], output: &mut Vec<u8>) {
+        if self.pattern.is_empty() {
+            output.extend_from_slice(input);
+            return;
+        }
+
+        self.pending.extend_from_slice(input);
+
+        let mut start = 0;
+        while let Some(index) = find(&self.pending[start..], &self.pattern) {
+            output.extend_from_slice(&self.pending[start..start + index]);
+            output.extend_from_slice(&self.replacement);
+            start += index + self.pattern.len();
+        }
+
+        let end = self.pending.len() - partial_match(&self.pending[start..], &self.pattern);
+        output.extend_from_slice(&self.pending[start..end]);
+        self.pending.drain(..end);
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) {
+        output.extend_from_slice(&self.pending);
+        self.pending.clear();
+    }
+}
+
+//The position of the first occurrence of `pattern` in `haystack`.
+fn find(haystack: &[u8], pattern: &[u8]) -> Option<usize> {
+    haystack.windows(pattern.len()).position(|window| window == pattern)
+}
+
+//The length of the longest end of `haystack` that is the beginning of
+//`pattern`, but not the whole pattern.
+fn partial_match(haystack: &[u8], pattern: &[u8]) -> usize {
+    let longest = ::std::cmp::min(haystack.len(), pattern.len() - 1);
+    (1..longest + 1).rev()
+        .find(|&length| haystack[haystack.len() - length..] == pattern[..length])
+        .unwrap_or(0)
+}
+
+///A response filter that applies the `BodyTransform` of each response.
+///
+///See the [module level documentation](index.html) for more information.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Transforms;
+
+impl Transforms {
+    ///Create a transformation filter.
+    pub fn new() -> Transforms {
+        Transforms
+    }
+}
+
+//The transformation, after the headers have been checked.
+struct Active(Box<Transform>);
+
+impl ResponseFilter for Transforms {
+    fn begin<'s>(&'s self, _ctx: FilterContext, status: StatusCode, _headers: &mut Headers) -> (StatusCode, ResponseAction<'s>) {
+        (status, ResponseAction::next::<Data>(None))
+    }
+
+    fn write<'a>(&'a self, _ctx: FilterContext, content: Option<Data<'a>>) -> ResponseAction<'a> {
+        ResponseAction::next(content)
+    }
+
+    fn end<'s>(&'s self, _ctx: FilterContext) -> ResponseAction<'s> {
+        ResponseAction::next::<Data>(None)
+    }
+
+    ///Remove `Content-Length` if the body will be transformed.
+    fn finalize(&self, ctx: FilterContext, status: &mut StatusCode, headers: &mut Headers) {
+        let transform = match ctx.storage.remove::<BodyTransform>() {
+            Some(BodyTransform(transform)) => transform,
+            None => return
+        };
+
+        match *status {
+            StatusCode::NoContent | StatusCode::NotModified => return,
+            _ if headers.has::<ContentEncoding>() => return,
+            _ => {}
+        }
+
+        headers.remove_raw("content-length");
+        ctx.storage.insert(Active(transform));
+    }
+
+    ///Transform the body, if a transformation was chosen.
+    fn wrap_body(&self, ctx: FilterContext, writer: Box<Write>) -> Box<Write> {
+        match ctx.storage.remove::<Active>() {
+            Some(Active(transform)) => wrap_writer(writer, transform),
+            None => writer
+        }
+    }
+}
+
+#[doc(hidden)]
+///Internal and may change without warning.
+///
+///Pass everything that's written to `writer` through `transform`.
+pub fn wrap_writer(writer: Box<Write>, transform: Box<Transform>) -> Box<Write> {
+    Box::new(TransformWriter {
+        writer: writer,
+        transform: transform,
+        buffer: vec![]
+    })
+}
+
+struct TransformWriter {
+    writer: Box<Write>,
+    transform: Box<Transform>,
+    buffer: Vec<u8>
+}
+
+impl Write for TransformWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.clear();
+        self.transform.transform(buf, &mut self.buffer);
+        try!(self.writer.write_all(&self.buffer));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Drop for TransformWriter {
+    fn drop(&mut self) {
+        self.buffer.clear();
+        self.transform.finish(&mut self.buffer);
+        if let Err(e) = self.writer.write_all(&self.buffer) {
+            debug!(target: "rustful::filter", "could not write the end of a transformed body: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use header::ContentLength;
+    use filter::ResponseFilter;
+    use response::ResponseBuffer;
+    use super::{Transform, Transforms, BodyTransform, Replace};
+
+    fn replace_all(pattern: &str, replacement: &str, chunks: &[&str]) -> String {
+        let mut replace = Replace::new(pattern, replacement);
+        let mut output = vec![];
+        for chunk in chunks {
+            replace.transform(chunk.as_bytes(), &mut output);
+        }
+        replace.finish(&mut output);
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn replace_across_chunks() {
+        assert_eq!(replace_all("abc", "X", &["abcab", "cxa", "b", "c", "ab"]), "XXxXab");
+        assert_eq!(replace_all("aab", "X", &["a", "a", "a", "b"]), "aX");
+        assert_eq!(replace_all("abc", "X", &["ab", "d"]), "abd");
+        assert_eq!(replace_all("", "X", &["ab", "c"]), "abc");
+    }
+
+    #[test]
+    fn transform_chunked() {
+        let filters: Vec<Box<ResponseFilter>> = vec![Box::new(Transforms)];
+        let mut buffer = ResponseBuffer::new();
+        let (mut response, output) = buffer.filtered(&filters);
+        response.filter_storage_mut().insert(BodyTransform::new(Replace::new("{{nonce}}", "r4nd0m")));
+
+        let mut chunked = response.into_chunked();
+        chunked.send("<script nonce=\"{{no");
+        chunked.send("nce}}\"></script>{{");
+        chunked.end().unwrap();
+
+        assert_eq!(output.body(), Some(b"<script nonce=\"r4nd0m\"></script>{{".to_vec()));
+    }
+
+    #[test]
+    fn transform_sized() {
+        let filters: Vec<Box<ResponseFilter>> = vec![Box::new(Transforms)];
+        let mut buffer = ResponseBuffer::new();
+        let (mut response, output) = buffer.filtered(&filters);
+        response.filter_storage_mut().insert(BodyTransform::new(Replace::new("{{name}}", "world")));
+        response.send("hello, {{name}}");
+
+        assert_eq!(output.body(), Some(b"hello, world".to_vec()));
+        assert_eq!(output.headers().unwrap().get(), Some(&ContentLength(12)));
+    }
+}
@@ -1,9 +1,28 @@
 //!Request handlers.
 use std::borrow::Cow;
+use std::collections::HashMap;
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::hash::{Hash, Hasher};
+use std::mem;
+#[cfg(feature = "async_bridge")]
+use std::future::Future;
+#[cfg(feature = "async_bridge")]
+use std::task::{Context as TaskContext, Poll, Wake, Waker};
+#[cfg(feature = "async_bridge")]
+use std::thread::{self, Thread};
 
-use context::Context;
-use response::Response;
-use std::sync::Arc;
+use rand::{self, Rng};
+
+use context::{Context, UriPath, CacheControl};
+use context::body::BodyError;
+use filter::{FilterContext, ResponseFilter, ResponseAction};
+use filter::route::RouteResponseFilters;
+use header::{Cookie, Headers};
+use response::{Data, Response, Responder};
+use std::sync::{Arc, Mutex, Condvar};
+use std::time::{Duration, Instant};
+use Method;
+use StatusCode;
 
 ///A trait for request handlers.
 pub trait Handler: Send + Sync + 'static {
@@ -34,3 +53,1176 @@ impl Handler for Box<Handler> {
         (**self).handle_request(context, response);
     }
 }
+
+///A handler that returns its response as a `Responder`, instead of writing
+///it to a `Response`.
+///
+///See [`Responder`](../response/trait.Responder.html) for an example.
+#[derive(Clone, Copy, Debug)]
+pub struct Responding<F>(pub F);
+
+impl<F: Fn(Context) -> R + Send + Sync + 'static, R: Responder> Handler for Responding<F> {
+    fn handle_request(&self, context: Context, response: Response) {
+        (self.0)(context).respond(response);
+    }
+}
+
+///A handler that splits the traffic between weighted variants, for A/B
+///testing and similar experiments.
+///
+///Each request is dispatched to one of the variants, where the chance of
+///being selected is proportional to the weight of the variant. The selection
+///is random by default, but it can be made sticky, using a cookie or a
+///header, to let the same client see the same variant every time. Requests
+///without the cookie or header are still dispatched randomly.
+///
+///The index of the selected variant is stored as a `Variant` in the filter
+///storage of the response, where response filters and the variant itself
+///can find it, for example for logging.
+///
+///```
+///use rustful::{Router, TreeRouter, Context, Response};
+///use rustful::handler::SplitHandler;
+///use rustful::Method::Get;
+///
+///fn current(_context: Context, response: Response) {
+///    response.send("the current page");
+///}
+///
+///fn redesign(_context: Context, response: Response) {
+///    response.send("the redesigned page");
+///}
+///
+///let mut split = SplitHandler::new(vec![
+///    (9, current as fn(Context, Response)),
+///    (1, redesign as fn(Context, Response)),
+///]);
+///split.stick_to_cookie("user_id");
+///
+///let mut router = TreeRouter::new();
+///router.insert(Get, "/", split);
+///```
+pub struct SplitHandler<H> {
+    variants: Vec<(u32, H)>,
+    total_weight: u64,
+    sticky: Option<Sticky>,
+}
+
+enum Sticky {
+    Cookie(Cow<'static, str>),
+    Header(Cow<'static, str>),
+}
+
+impl<H: Handler> SplitHandler<H> {
+    ///Create a `SplitHandler` from a list of weights and handlers.
+    ///
+    ///# Panics
+    ///
+    ///This will panic if the total weight is 0.
+    pub fn new(variants: Vec<(u32, H)>) -> SplitHandler<H> {
+        let total_weight = variants.iter().map(|&(weight, _)| weight as u64).sum();
+        assert!(total_weight > 0, "the total weight of a SplitHandler must be greater than 0");
+
+        SplitHandler {
+            variants: variants,
+            total_weight: total_weight,
+            sticky: None,
+        }
+    }
+
+    ///Select the same variant for all requests with the same value for the
+    ///cookie `name`.
+    pub fn stick_to_cookie<N: Into<Cow<'static, str>>>(&mut self, name: N) {
+        self.sticky = Some(Sticky::Cookie(name.into()));
+    }
+
+    ///Select the same variant for all requests with the same value for the
+    ///header `name`.
+    pub fn stick_to_header<N: Into<Cow<'static, str>>>(&mut self, name: N) {
+        self.sticky = Some(Sticky::Header(name.into()));
+    }
+
+    fn select(&self, context: &Context) -> usize {
+        let key = match self.sticky {
+            Some(Sticky::Cookie(ref name)) => context.headers.get::<Cookie>().and_then(|cookies| {
+                cookies.iter().find(|cookie| cookie.name == **name).map(|cookie| sticky_point(cookie.value.as_bytes(), self.total_weight))
+            }),
+            Some(Sticky::Header(ref name)) => context.headers.get_raw(name).map(|values| {
+                sticky_point(&values.concat(), self.total_weight)
+            }),
+            None => None
+        };
+        let mut point = key.unwrap_or_else(|| rand::thread_rng().gen_range(0, self.total_weight));
+
+        for (index, &(weight, _)) in self.variants.iter().enumerate() {
+            if point < weight as u64 {
+                return index;
+            }
+            point -= weight as u64;
+        }
+
+        unreachable!("the selected point is always less than the total weight")
+    }
+}
+
+impl<H: Handler> Handler for SplitHandler<H> {
+    fn handle_request(&self, context: Context, mut response: Response) {
+        let index = self.select(&context);
+        response.filter_storage_mut().insert(Variant(index));
+        self.variants[index].1.handle_request(context, response);
+    }
+
+    fn description(&self) -> Option<Cow<'static, str>> {
+        let descriptions: Vec<_> = self.variants.iter().filter_map(|&(_, ref handler)| handler.description()).collect();
+        if descriptions.is_empty() {
+            None
+        } else {
+            Some(descriptions.join(" | ").into())
+        }
+    }
+}
+
+///The index of the variant that was selected by a `SplitHandler`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Variant(pub usize);
+
+//Map a sticky key to a point within the total weight.
+fn sticky_point(key: &[u8], total_weight: u64) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish() % total_weight
+}
+
+///A handler that is mounted at a path prefix, such as a self-contained
+///sub-application.
+///
+///The prefix is removed from the path before the inner handler sees it, so
+///a request for `/metrics/cpu` looks like a request for `/cpu` to a handler
+///that is mounted at `/metrics`. Requests for the prefix itself, with or
+///without a trailing slash, are seen as requests for `/`. The full path is
+///still available from `Context::original_path`.
+///
+///It's usually added to a router with `Router::mount_handler`, which routes
+///the prefix and everything below it to the handler:
+///
+///```
+///use rustful::{Router, TreeRouter, Handler, Context, Response};
+///use rustful::Method::Get;
+///
+///fn home(_context: Context, response: Response) {
+///    response.send("home");
+///}
+///
+///fn metrics(context: Context, response: Response) {
+///    response.send(format!("metrics for {}", context.uri_path.as_utf8_path().unwrap_or("")));
+///}
+///
+///let mut router = TreeRouter::new();
+///router.insert(Get, "/", Box::new(home as fn(Context, Response)) as Box<Handler>);
+///router.mount_handler("/metrics", metrics as fn(Context, Response));
+///```
+///
+///The prefix is matched segment by segment, and it can't contain variables.
+pub struct Mount<H> {
+    prefix: Vec<Vec<u8>>,
+    handler: Arc<H>,
+}
+
+impl<H: Handler> Mount<H> {
+    ///Mount `handler` at `prefix`.
+    pub fn new(prefix: &str, handler: H) -> Mount<H> {
+        Mount {
+            prefix: prefix.split('/').filter(|segment| !segment.is_empty()).map(|segment| segment.as_bytes().to_owned()).collect(),
+            handler: Arc::new(handler),
+        }
+    }
+
+    ///Get the prefix, in its normalized form, such as `/metrics`.
+    pub fn prefix(&self) -> String {
+        let mut prefix = String::new();
+        for segment in &self.prefix {
+            prefix.push('/');
+            prefix.push_str(&String::from_utf8_lossy(segment));
+        }
+
+        if prefix.is_empty() {
+            prefix.push('/');
+        }
+
+        prefix
+    }
+
+    //Remove the prefix from `path`, or return `None` if it doesn't match.
+    fn strip(&self, path: &[u8]) -> Option<Vec<u8>> {
+        let mut rest = path;
+        for segment in &self.prefix {
+            let start = rest.iter().position(|&byte| byte != b'/').unwrap_or(rest.len());
+            rest = &rest[start..];
+            if !rest.starts_with(segment) || rest.get(segment.len()).map_or(false, |&byte| byte != b'/') {
+                return None;
+            }
+            rest = &rest[segment.len()..];
+        }
+
+        if rest.is_empty() {
+            Some(b"/".to_vec())
+        } else {
+            Some(rest.to_owned())
+        }
+    }
+}
+
+impl<H> Clone for Mount<H> {
+    fn clone(&self) -> Mount<H> {
+        Mount {
+            prefix: self.prefix.clone(),
+            handler: self.handler.clone(),
+        }
+    }
+}
+
+impl<H: Handler> Handler for Mount<H> {
+    fn handle_request(&self, mut context: Context, response: Response) {
+        let stripped = context.uri_path.as_path().and_then(|path| self.strip(path.as_bytes()));
+        if let Some(path) = stripped {
+            let original = mem::replace(&mut context.uri_path, UriPath::Path(path.into()));
+            if context.original_path.is_none() {
+                context.original_path = Some(original);
+            }
+        }
+
+        self.handler.handle_request(context, response);
+    }
+
+    fn description(&self) -> Option<Cow<'static, str>> {
+        self.handler.description()
+    }
+}
+
+impl<H: Handler> From<Mount<H>> for Box<Handler> {
+    fn from(mount: Mount<H>) -> Box<Handler> {
+        Box::new(mount)
+    }
+}
+
+///A handler that lets identical requests, that are handled at the same time,
+///share a single response.
+///
+///This protects expensive resources against a burst of identical requests,
+///sometimes called a thundering herd, where each request would otherwise
+///compute the same thing. The first request is handled as usual, while its
+///response is captured. Any identical request that arrives before it's done
+///will wait for it, and receive a copy of the captured status, headers and
+///body, instead of being handled separately.
+///
+///Only `GET` and `HEAD` requests are collapsed, and they are considered
+///identical if they have the same method, request target (path and query)
+///and the same values for the headers that are selected with `vary_on`.
+///Anything that the response depends on, like a `Cookie` or `Accept`
+///header, has to be included to avoid sending the wrong response to a
+///client. Everything else is passed directly to the inner handler.
+///
+///The response is captured before any global response filters, which are
+///applied separately to each copy. A response is only shared if it was
+///completely sent before the handler returned, and if the body is no larger
+///than `max_body_size`, which is 1 MiB by default. Waiting requests are
+///handled separately, as if they were never collapsed, if the response
+///could not be shared. Raw responses can't be captured.
+///
+///Note that the waiting requests are blocking their worker threads.
+///
+///```
+///use rustful::{Router, TreeRouter, Context, Response};
+///use rustful::handler::CollapsingHandler;
+///use rustful::Method::Get;
+///
+///fn report(_context: Context, response: Response) {
+///    response.send("an expensive report");
+///}
+///
+///let mut report = CollapsingHandler::new(report as fn(Context, Response));
+///report.vary_on("Accept-Language");
+///
+///let mut router = TreeRouter::new();
+///router.insert(Get, "/report", report);
+///```
+pub struct CollapsingHandler<H> {
+    handler: H,
+    vary: Vec<Cow<'static, str>>,
+    max_body_size: usize,
+    filters: Arc<RouteResponseFilters>,
+    in_flight: Mutex<HashMap<Vec<Vec<u8>>, Arc<Flight>>>,
+}
+
+impl<H: Handler> CollapsingHandler<H> {
+    ///Wrap a handler, with the default settings.
+    pub fn new(handler: H) -> CollapsingHandler<H> {
+        CollapsingHandler {
+            handler: handler,
+            vary: vec![],
+            max_body_size: 1024 * 1024,
+            filters: capture_filters(),
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    ///Only collapse requests with the same values for the header `name`.
+    pub fn vary_on<N: Into<Cow<'static, str>>>(&mut self, name: N) {
+        self.vary.push(name.into());
+    }
+
+    ///Set the largest response body, in bytes, that will be shared between
+    ///requests. Larger bodies are not captured at all.
+    pub fn max_body_size(&mut self, size: usize) {
+        self.max_body_size = size;
+    }
+
+    fn lead(&self, key: Vec<Vec<u8>>, flight: Arc<Flight>, context: Context, mut response: Response) {
+        let capture = start_capture(&mut response, &self.filters, self.max_body_size);
+
+        //Makes sure that the waiting requests are released, even if the
+        //handler panics.
+        let _landing = Landing {
+            in_flight: &self.in_flight,
+            key: key,
+            flight: flight,
+            capture: capture,
+        };
+
+        self.handler.handle_request(context, response);
+    }
+}
+
+impl<H: Handler> Handler for CollapsingHandler<H> {
+    fn handle_request(&self, context: Context, response: Response) {
+        match context.method {
+            Method::Get | Method::Head => {},
+            _ => return self.handler.handle_request(context, response)
+        }
+
+        let key = request_key(&context, &self.vary);
+
+        let (flight, is_leader) = {
+            let mut in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+            match in_flight.entry(key.clone()) {
+                Entry::Occupied(e) => (e.get().clone(), false),
+                Entry::Vacant(e) => {
+                    let flight = Arc::new(Flight {
+                        result: Mutex::new(None),
+                        landed: Condvar::new(),
+                    });
+                    e.insert(flight.clone());
+                    (flight, true)
+                }
+            }
+        };
+
+        if is_leader {
+            self.lead(key, flight, context, response);
+        } else if let Some(captured) = flight.wait() {
+            captured.replay(response);
+        } else {
+            self.handler.handle_request(context, response);
+        }
+    }
+
+    fn description(&self) -> Option<Cow<'static, str>> {
+        self.handler.description()
+    }
+}
+
+///A handler that keeps the responses from another handler in memory, for a
+///limited time.
+///
+///`GET` and `HEAD` requests are looked up in the cache, where they are
+///identified by their method, request target (path and query) and the
+///values of the headers that are selected with `vary_on`, like with
+///`CollapsingHandler`. A cached response is sent again, with an `Age`
+///header, without calling the inner handler. Anything else is passed
+///directly to the inner handler.
+///
+///The cache is shared between all clients, so requests with a `Cookie`
+///header are never looked up or stored, and requests with an
+///`Authorization` header are never looked up. A response to an
+///`Authorization` request is only stored if its `Cache-Control` header has
+///`public` or `s-maxage`. The response may also select more request headers
+///with its own `Vary` header, and a stored response is only sent again if
+///the new request has the same values for them. A response with `Vary: *`
+///is never stored.
+///
+///A response from the inner handler is stored if it was completely sent
+///before the handler returned, if its body is no larger than
+///`max_body_size`, and if it's accepted by the cache policy. The default
+///policy accepts the status codes that are cacheable by default, like
+///`200 OK` and `404 Not Found`, as long as there is no `Set-Cookie` header.
+///A `Cache-Control` header with `no-store`, `no-cache` or `private`, or a
+///malformed one, will always prevent the response from being stored, and
+///`max-age` or `s-maxage` will shorten the time to live, if they are lower.
+///Raw responses can't be stored.
+///
+///The cache is limited by both the number of entries and their total size,
+///where the least recently used entries are removed first. Expired entries
+///are removed when they are found. The responses are stored before any
+///global response filters, which are applied separately each time a stored
+///response is sent.
+///
+///```
+///use std::time::Duration;
+///use rustful::{Router, TreeRouter, Context, Response};
+///use rustful::handler::CacheHandler;
+///use rustful::Method::Get;
+///
+///fn front_page(_context: Context, response: Response) {
+///    response.send("the front page");
+///}
+///
+///let mut front_page = CacheHandler::new(front_page as fn(Context, Response), Duration::from_secs(10));
+///front_page.vary_on("Accept-Language");
+///front_page.max_entries(100);
+///
+///let mut router = TreeRouter::new();
+///router.insert(Get, "/", front_page);
+///```
+pub struct CacheHandler<H> {
+    handler: H,
+    ttl: Duration,
+    vary: Vec<Cow<'static, str>>,
+    max_body_size: usize,
+    max_entries: usize,
+    max_bytes: usize,
+    policy: Box<Fn(StatusCode, &Headers) -> bool + Send + Sync>,
+    filters: Arc<RouteResponseFilters>,
+    entries: Mutex<CacheEntries>,
+}
+
+impl<H: Handler> CacheHandler<H> {
+    ///Wrap a handler, and store its responses for, at most, `ttl`.
+    pub fn new(handler: H, ttl: Duration) -> CacheHandler<H> {
+        CacheHandler {
+            handler: handler,
+            ttl: ttl,
+            vary: vec![],
+            max_body_size: 1024 * 1024,
+            max_entries: 1000,
+            max_bytes: 16 * 1024 * 1024,
+            policy: Box::new(default_cache_policy),
+            filters: capture_filters(),
+            entries: Mutex::new(CacheEntries {
+                map: HashMap::new(),
+                bytes: 0,
+                clock: 0,
+            }),
+        }
+    }
+
+    ///Store separate responses for different values of the header `name`.
+    pub fn vary_on<N: Into<Cow<'static, str>>>(&mut self, name: N) {
+        self.vary.push(name.into());
+    }
+
+    ///Set the largest response body, in bytes, that will be stored. The
+    ///default is 1 MiB.
+    pub fn max_body_size(&mut self, size: usize) {
+        self.max_body_size = size;
+    }
+
+    ///Set the largest number of stored responses. The default is 1000.
+    pub fn max_entries(&mut self, entries: usize) {
+        self.max_entries = entries;
+    }
+
+    ///Set the largest total size, in bytes, of the stored headers and
+    ///bodies. The default is 16 MiB.
+    pub fn max_bytes(&mut self, bytes: usize) {
+        self.max_bytes = bytes;
+    }
+
+    ///Replace the cache policy, which decides if a response can be stored,
+    ///based on its status and headers. `Cache-Control` is checked before the
+    ///policy, and it can't be overridden.
+    pub fn policy<F: Fn(StatusCode, &Headers) -> bool + Send + Sync + 'static>(&mut self, policy: F) {
+        self.policy = Box::new(policy);
+    }
+
+    //Find the time to live for a response, or `None` if it can't be stored.
+    fn time_to_live(&self, captured: &Captured, authorized: bool) -> Option<Duration> {
+        let mut headers = Headers::new();
+        for &(ref name, ref value) in &captured.headers {
+            headers.set_raw(name.clone(), value.clone());
+        }
+
+        let mut ttl = self.ttl;
+
+        let cache_control = match headers.get_raw("Cache-Control").map(CacheControl::parse) {
+            Some(Some(cache_control)) => Some(cache_control),
+            //A malformed header may have been meant to prevent caching.
+            Some(None) => return None,
+            None => None
+        };
+
+        //Responses to authorized requests are private, unless they are
+        //explicitly marked as shareable.
+        if authorized && !cache_control.as_ref().map_or(false, |c| c.public || c.s_maxage.is_some()) {
+            return None;
+        }
+
+        if let Some(cache_control) = cache_control {
+            if cache_control.no_store || cache_control.no_cache || cache_control.private {
+                return None;
+            }
+
+            for &seconds in cache_control.max_age.iter().chain(&cache_control.s_maxage) {
+                ttl = ::std::cmp::min(ttl, Duration::from_secs(seconds));
+            }
+        }
+
+        if (self.policy)(captured.status, &headers) {
+            Some(ttl)
+        } else {
+            None
+        }
+    }
+
+    fn lookup(&self, key: &[Vec<u8>], request_headers: &Headers) -> Option<(Arc<Captured>, Duration)> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+
+        let (expired, matching) = match entries.map.get(key) {
+            Some(entry) => (
+                entry.expires <= now,
+                entry.variant.iter().all(|&(ref name, ref value)| header_value(request_headers, name) == *value)
+            ),
+            None => return None
+        };
+
+        if expired {
+            entries.remove(key);
+            return None;
+        }
+
+        if !matching {
+            return None;
+        }
+
+        entries.clock += 1;
+        let clock = entries.clock;
+        entries.map.get_mut(key).map(|entry| {
+            entry.last_used = clock;
+            (entry.response.clone(), now - entry.stored)
+        })
+    }
+
+    fn store(&self, key: Vec<Vec<u8>>, variant: Vec<(String, Vec<u8>)>, captured: Captured, ttl: Duration) {
+        let size = captured.size();
+        if size > self.max_bytes || self.max_entries == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.remove(&key);
+
+        while entries.map.len() >= self.max_entries || entries.bytes + size > self.max_bytes {
+            let oldest = entries.map.iter().min_by_key(|&(_, entry)| entry.last_used).map(|(key, _)| key.clone());
+            match oldest {
+                Some(oldest) => entries.remove(&oldest),
+                None => break
+            }
+        }
+
+        let now = Instant::now();
+        entries.clock += 1;
+        entries.bytes += size;
+        let clock = entries.clock;
+        entries.map.insert(key, CacheEntry {
+            response: Arc::new(captured),
+            variant: variant,
+            size: size,
+            stored: now,
+            expires: now + ttl,
+            last_used: clock,
+        });
+    }
+}
+
+impl<H: Handler> Handler for CacheHandler<H> {
+    fn handle_request(&self, context: Context, mut response: Response) {
+        match context.method {
+            Method::Get | Method::Head => {},
+            _ => return self.handler.handle_request(context, response)
+        }
+
+        //The response may be personal.
+        if context.headers.get_raw("Cookie").is_some() {
+            return self.handler.handle_request(context, response);
+        }
+
+        let key = request_key(&context, &self.vary);
+        let authorized = context.headers.get_raw("Authorization").is_some();
+
+        if !authorized {
+            if let Some((cached, age)) = self.lookup(&key, &context.headers) {
+                response.headers_mut().set_raw("Age", vec![age.as_secs().to_string().into_bytes()]);
+                cached.replay(response);
+                return;
+            }
+        }
+
+        //The response may vary on any of them.
+        let request_headers = context.headers.clone();
+
+        let capture = start_capture(&mut response, &self.filters, self.max_body_size);
+        self.handler.handle_request(context, response);
+
+        let captured = capture.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(captured) = captured {
+            if let Some(ttl) = self.time_to_live(&captured, authorized) {
+                if let Some(variant) = response_variant(&captured, &request_headers) {
+                    self.store(key, variant, captured, ttl);
+                }
+            }
+        }
+    }
+
+    fn description(&self) -> Option<Cow<'static, str>> {
+        self.handler.description()
+    }
+}
+
+//Heuristically cacheable, according to RFC 7231, and without cookies.
+fn default_cache_policy(status: StatusCode, headers: &Headers) -> bool {
+    let cacheable = match status {
+        StatusCode::Ok |
+        StatusCode::NonAuthoritativeInformation |
+        StatusCode::NoContent |
+        StatusCode::MultipleChoices |
+        StatusCode::MovedPermanently |
+        StatusCode::NotFound |
+        StatusCode::MethodNotAllowed |
+        StatusCode::Gone |
+        StatusCode::UriTooLong |
+        StatusCode::NotImplemented => true,
+        _ => false
+    };
+
+    cacheable && headers.get_raw("Set-Cookie").is_none()
+}
+
+///A handler that responds with `504 Gateway Timeout` if the inner handler is
+///too slow to respond.
+///
+///This is useful for handlers that wait for something else, like a slow
+///upstream server. The inner handler can't be interrupted, since it runs on
+///the worker thread, so the timeout is enforced when it starts its response.
+///A response that is started after the deadline is replaced with an empty
+///`504 Gateway Timeout`, and anything the handler writes to it is discarded.
+///A response that was started in time is sent as it is, even if the handler
+///is still writing its body when the deadline passes, since the status and
+///headers can't be taken back once they are sent.
+///
+///The deadline is also given to the inner handler, through
+///`Context::deadline` and `Context::time_remaining`, so it can give up on
+///its work in time. It's the earliest of the timeout and the deadline from
+///`Server::request_timeout`.
+///
+///It can wrap any handler, including a `Responding` handler.
+///
+///```
+///use std::time::Duration;
+///use rustful::{Router, TreeRouter, Context, Response};
+///use rustful::handler::{Responding, TimeoutHandler};
+///use rustful::Method::Get;
+///
+///fn fetch(context: Context) -> String {
+///    //Use the remaining time as the timeout for the upstream request.
+///    let _timeout = context.time_remaining();
+///    "the upstream response".to_owned()
+///}
+///
+///let fetch = TimeoutHandler::new(Responding(fetch as fn(Context) -> String), Duration::from_secs(5));
+///
+///let mut router = TreeRouter::new();
+///router.insert(Get, "/upstream", fetch);
+///```
+pub struct TimeoutHandler<H> {
+    handler: H,
+    timeout: Duration,
+    filters: Arc<RouteResponseFilters>,
+}
+
+impl<H: Handler> TimeoutHandler<H> {
+    ///Wrap a handler, and give it `timeout` to start its response.
+    pub fn new(handler: H, timeout: Duration) -> TimeoutHandler<H> {
+        TimeoutHandler {
+            handler: handler,
+            timeout: timeout,
+            filters: Arc::new(RouteResponseFilters {
+                filters: vec![Box::new(TimeoutFilter)],
+                skip_global: vec![],
+            }),
+        }
+    }
+}
+
+impl<H: Handler> Handler for TimeoutHandler<H> {
+    fn handle_request(&self, mut context: Context, mut response: Response) {
+        let deadline = Instant::now() + self.timeout;
+        context.deadline = Some(context.deadline.map_or(deadline, |current| ::std::cmp::min(current, deadline)));
+
+        response.push_route_filters(self.filters.clone());
+        response.filter_storage_mut().insert(ResponseDeadline {
+            deadline: deadline,
+            expired: false,
+        });
+
+        self.handler.handle_request(context, response);
+    }
+
+    fn description(&self) -> Option<Cow<'static, str>> {
+        self.handler.description()
+    }
+}
+
+struct ResponseDeadline {
+    deadline: Instant,
+    expired: bool,
+}
+
+//Replaces responses that are started after the `ResponseDeadline`.
+struct TimeoutFilter;
+
+impl TimeoutFilter {
+    fn is_expired(context: &FilterContext) -> bool {
+        context.storage.get::<ResponseDeadline>().map_or(false, |deadline| deadline.expired)
+    }
+}
+
+impl ResponseFilter for TimeoutFilter {
+    fn begin<'s>(&'s self, context: FilterContext, status: StatusCode, headers: &mut Headers) -> (StatusCode, ResponseAction<'s>) {
+        let expired = match context.storage.get_mut::<ResponseDeadline>() {
+            Some(deadline) => {
+                deadline.expired = Instant::now() >= deadline.deadline;
+                deadline.expired
+            },
+            None => false
+        };
+
+        if !expired {
+            return (status, ResponseAction::next(None::<Data>));
+        }
+
+        for name in &["Content-Length", "Content-Type", "Content-Encoding", "Content-Disposition", "ETag", "Last-Modified", "Location"] {
+            headers.remove_raw(name);
+        }
+
+        (StatusCode::GatewayTimeout, ResponseAction::next(None::<Data>))
+    }
+
+    fn write<'a>(&'a self, context: FilterContext, content: Option<Data<'a>>) -> ResponseAction<'a> {
+        if TimeoutFilter::is_expired(&context) {
+            ResponseAction::next(None::<Data>)
+        } else {
+            ResponseAction::Next(content)
+        }
+    }
+
+    fn end<'s>(&'s self, _context: FilterContext) -> ResponseAction<'s> {
+        ResponseAction::next(None::<Data>)
+    }
+}
+
+///A handler that verifies the body of a request against a trailer, before
+///the inner handler is called.
+///
+///This is meant for streaming uploads, where the client sends the body
+///with chunked transfer encoding and a checksum, or a signature, in a
+///trailer after it, like `X-Checksum`. The trailer can't be read until the
+///whole body has been received, so the body is read into memory first,
+///with `BodyReader::cached_bytes`, and given to the verification function
+///together with the trailer value. The inner handler can then read the
+///body again, as described for `cached_bytes`, but it can't stream it
+///from the client. `Server::max_body_length` limits how much may be read.
+///
+///The request is rejected, without calling the inner handler, with:
+///
+/// * `400 Bad Request` if the trailer is missing, or appears more than
+///   once, which includes bodies that are not chunked.
+/// * `422 Unprocessable Entity`, or the status from `rejection`, if the
+///   verification function returns `false`.
+/// * `413 Payload Too Large` if the body is longer than
+///   `Server::max_body_length`, or `400 Bad Request` if it can't be read.
+///
+///```
+///use rustful::{Router, TreeRouter, Context, Response};
+///use rustful::handler::IntegrityHandler;
+///use rustful::Method::Put;
+///
+///fn checksum(body: &[u8]) -> String {
+///    format!("{:08x}", body.iter().fold(0u32, |sum, &byte| sum.wrapping_mul(31).wrapping_add(byte as u32)))
+///}
+///
+///fn store(mut context: Context, response: Response) {
+///    let body = context.body_bytes().unwrap();
+///    response.send(format!("stored {} bytes", body.len()));
+///}
+///
+///let store = IntegrityHandler::new(store as fn(Context, Response), "X-Checksum", |body: &[u8], trailer: &[u8]| {
+///    checksum(body).as_bytes() == trailer
+///});
+///
+///let mut router = TreeRouter::new();
+///router.insert(Put, "/uploads/:name", store);
+///```
+pub struct IntegrityHandler<H> {
+    handler: H,
+    trailer: String,
+    verify: Box<Fn(&[u8], &[u8]) -> bool + Send + Sync>,
+    rejection: StatusCode,
+}
+
+impl<H: Handler> IntegrityHandler<H> {
+    ///Wrap a handler, and verify each request body against the value of
+    ///the `trailer` trailer, using `verify`.
+    pub fn new<S, F>(handler: H, trailer: S, verify: F) -> IntegrityHandler<H> where
+        S: Into<String>,
+        F: Fn(&[u8], &[u8]) -> bool + Send + Sync + 'static
+    {
+        IntegrityHandler {
+            handler: handler,
+            trailer: trailer.into(),
+            verify: Box::new(verify),
+            rejection: StatusCode::UnprocessableEntity,
+        }
+    }
+
+    ///Set the status code for bodies that fail the verification. The
+    ///default is `422 Unprocessable Entity`.
+    pub fn rejection(mut self, status: StatusCode) -> IntegrityHandler<H> {
+        self.rejection = status;
+        self
+    }
+}
+
+impl<H: Handler> Handler for IntegrityHandler<H> {
+    fn handle_request(&self, mut context: Context, mut response: Response) {
+        let body = match context.body.cached_bytes() {
+            Ok(body) => body,
+            Err(BodyError::TooLarge) => return response.set_status(StatusCode::PayloadTooLarge),
+            Err(_) => return response.set_status(StatusCode::BadRequest)
+        };
+
+        let verified = match context.body.trailers().get_raw(&self.trailer) {
+            Some(values) if values.len() == 1 => (self.verify)(&body, &values[0]),
+            _ => return response.set_status(StatusCode::BadRequest)
+        };
+
+        if verified {
+            self.handler.handle_request(context, response);
+        } else {
+            response.set_status(self.rejection);
+        }
+    }
+
+    fn description(&self) -> Option<Cow<'static, str>> {
+        self.handler.description()
+    }
+}
+
+///Run a future to completion on the current thread, and return its output.
+///This makes it possible to call async code, like an async database
+///client, from a handler. It requires the `async_bridge` feature.
+///
+///Handlers run on the worker threads of the server, and `block_on` blocks
+///the worker thread until the future is done. The thread is parked while
+///the future is waiting, and unparked when it's woken, so it doesn't spin,
+///but each waiting request still holds on to a worker, and
+///`Server::threads` limits how many requests can wait at the same time.
+///
+///The future is polled on the worker thread, without any runtime around
+///it. Futures that depend on a particular runtime, like the I/O types from
+///Tokio, should be spawned onto one shared runtime, which is created once,
+///before the server is started, and can be kept in `Server::global`. The
+///handler can then `block_on` the join handle, or a channel, since they
+///can be awaited from any thread. Calling `block_on` from within a runtime
+///thread would block that runtime, so it's only meant for handlers.
+///
+///```edition2018
+///use rustful::{Context, Response};
+///use rustful::handler::block_on;
+///
+///async fn find_user(id: u32) -> Option<String> {
+///    //Something like `db.query(...).await`.
+///    if id == 1 { Some("Ada".to_owned()) } else { None }
+///}
+///
+///fn show_user(context: Context, mut response: Response) {
+///    let id = context.variables.parse("id").unwrap_or(0);
+///    match block_on(find_user(id)) {
+///        Some(name) => response.send(name),
+///        None => response.set_status(rustful::StatusCode::NotFound)
+///    }
+///}
+///```
+#[cfg(feature = "async_bridge")]
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut task_context = TaskContext::from_waker(&waker);
+
+    loop {
+        match future.as_mut().poll(&mut task_context) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park()
+        }
+    }
+}
+
+//Unparks the thread that is blocked in `block_on`.
+#[cfg(feature = "async_bridge")]
+struct ThreadWaker(Thread);
+
+#[cfg(feature = "async_bridge")]
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+struct CacheEntries {
+    map: HashMap<Vec<Vec<u8>>, CacheEntry>,
+    bytes: usize,
+    //Counts lookups and insertions, to find the least recently used entry.
+    clock: u64,
+}
+
+impl CacheEntries {
+    fn remove(&mut self, key: &[Vec<u8>]) {
+        if let Some(entry) = self.map.remove(key) {
+            self.bytes -= entry.size;
+        }
+    }
+}
+
+struct CacheEntry {
+    response: Arc<Captured>,
+    //The request headers from the response's `Vary` header, and their
+    //values.
+    variant: Vec<(String, Vec<u8>)>,
+    size: usize,
+    stored: Instant,
+    expires: Instant,
+    last_used: u64,
+}
+
+//A request that is being handled, and that others may wait for.
+struct Flight {
+    //`Some(None)` if the response could not be shared.
+    result: Mutex<Option<Option<Arc<Captured>>>>,
+    landed: Condvar,
+}
+
+impl Flight {
+    fn wait(&self) -> Option<Arc<Captured>> {
+        let mut result = self.result.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if let Some(ref captured) = *result {
+                return captured.clone();
+            }
+            result = self.landed.wait(result).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+//Publishes the result of a flight when the leading request is done.
+struct Landing<'a> {
+    in_flight: &'a Mutex<HashMap<Vec<Vec<u8>>, Arc<Flight>>>,
+    key: Vec<Vec<u8>>,
+    flight: Arc<Flight>,
+    capture: Arc<Mutex<Capture>>,
+}
+
+impl<'a> Drop for Landing<'a> {
+    fn drop(&mut self) {
+        self.in_flight.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.key);
+
+        let captured = self.capture.lock().unwrap_or_else(|e| e.into_inner()).take().map(Arc::new);
+
+        *self.flight.result.lock().unwrap_or_else(|e| e.into_inner()) = Some(captured);
+        self.flight.landed.notify_all();
+    }
+}
+
+//Identifies identical requests.
+fn request_key(context: &Context, vary: &[Cow<'static, str>]) -> Vec<Vec<u8>> {
+    let mut key = vec![context.method.to_string().into_bytes(), context.raw_target().as_bytes().to_owned()];
+
+    for name in vary {
+        key.push(header_value(&context.headers, name));
+    }
+
+    key
+}
+
+//The values of a request header, joined into one.
+fn header_value(headers: &Headers, name: &str) -> Vec<u8> {
+    let mut value = vec![];
+    if let Some(values) = headers.get_raw(name) {
+        for (index, part) in values.iter().enumerate() {
+            if index > 0 {
+                value.extend_from_slice(b", ");
+            }
+            value.extend_from_slice(part);
+        }
+    }
+    value
+}
+
+//The request headers that a response varies on, with their values from
+//the request, or `None` if it varies on something other than the request
+//headers.
+fn response_variant(captured: &Captured, request_headers: &Headers) -> Option<Vec<(String, Vec<u8>)>> {
+    let mut variant = vec![];
+    for &(ref name, ref values) in &captured.headers {
+        if !name.eq_ignore_ascii_case("Vary") {
+            continue;
+        }
+
+        for value in values {
+            for field in String::from_utf8_lossy(value).split(',') {
+                match field.trim() {
+                    "*" => return None,
+                    "" => {},
+                    field => variant.push((field.to_owned(), header_value(request_headers, field)))
+                }
+            }
+        }
+    }
+
+    Some(variant)
+}
+
+//A complete response, as it was sent by a handler.
+struct Captured {
+    status: StatusCode,
+    headers: Vec<(String, Vec<Vec<u8>>)>,
+    body: Vec<u8>,
+}
+
+impl Captured {
+    fn replay(&self, mut response: Response) {
+        response.set_status(self.status);
+        for &(ref name, ref value) in &self.headers {
+            response.headers_mut().set_raw(name.clone(), value.clone());
+        }
+        response.send(&self.body[..]);
+    }
+
+    fn size(&self) -> usize {
+        self.headers.iter().map(|&(ref name, ref values)| name.len() + values.iter().map(|value| value.len()).sum::<usize>()).sum::<usize>() + self.body.len()
+    }
+}
+
+struct Capture {
+    status: StatusCode,
+    headers: Vec<(String, Vec<Vec<u8>>)>,
+    body: Vec<u8>,
+    max_body_size: usize,
+    state: CaptureState,
+}
+
+impl Capture {
+    //Take the response, if it was completely captured.
+    fn take(&mut self) -> Option<Captured> {
+        if let CaptureState::Complete = self.state {
+            Some(Captured {
+                status: self.status,
+                headers: ::std::mem::replace(&mut self.headers, vec![]),
+                body: ::std::mem::replace(&mut self.body, vec![]),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+enum CaptureState {
+    Pending,
+    Complete,
+    TooLarge,
+}
+
+struct CaptureSlot(Arc<Mutex<Capture>>);
+
+fn capture_filters() -> Arc<RouteResponseFilters> {
+    Arc::new(RouteResponseFilters {
+        filters: vec![Box::new(CaptureFilter)],
+        skip_global: vec![],
+    })
+}
+
+//Make `response` copy itself into the returned `Capture` as it's sent.
+fn start_capture(response: &mut Response, filters: &Arc<RouteResponseFilters>, max_body_size: usize) -> Arc<Mutex<Capture>> {
+    let capture = Arc::new(Mutex::new(Capture {
+        status: StatusCode::Ok,
+        headers: vec![],
+        body: vec![],
+        max_body_size: max_body_size,
+        state: CaptureState::Pending,
+    }));
+
+    response.push_route_filters(filters.clone());
+    response.filter_storage_mut().insert(CaptureSlot(capture.clone()));
+
+    capture
+}
+
+//Copies the response into the `CaptureSlot` as it passes.
+struct CaptureFilter;
+
+impl CaptureFilter {
+    fn capture<F: FnOnce(&mut Capture)>(context: FilterContext, f: F) {
+        if let Some(&CaptureSlot(ref capture)) = context.storage.get::<CaptureSlot>() {
+            f(&mut capture.lock().unwrap_or_else(|e| e.into_inner()));
+        }
+    }
+}
+
+impl ResponseFilter for CaptureFilter {
+    fn begin(&self, context: FilterContext, status: StatusCode, headers: &mut Headers) -> (StatusCode, ResponseAction) {
+        CaptureFilter::capture(context, |capture| {
+            capture.status = status;
+            capture.headers = headers.iter().map(|header| header.name().to_owned()).filter_map(|name| {
+                ::utils::raw_header_values(headers, &name).map(|value| (name, value))
+            }).collect();
+        });
+        (status, ResponseAction::next(None::<Data>))
+    }
+
+    fn write<'a>(&'a self, context: FilterContext, content: Option<Data<'a>>) -> ResponseAction {
+        if let Some(ref content) = content {
+            CaptureFilter::capture(context, |capture| {
+                if let CaptureState::Pending = capture.state {
+                    if capture.body.len() + content.as_bytes().len() > capture.max_body_size {
+                        capture.state = CaptureState::TooLarge;
+                        capture.body = vec![];
+                    } else {
+                        capture.body.extend_from_slice(content.as_bytes());
+                    }
+                }
+            });
+        }
+        ResponseAction::Next(content)
+    }
+
+    fn end(&self, context: FilterContext) -> ResponseAction {
+        CaptureFilter::capture(context, |capture| {
+            if let CaptureState::Pending = capture.state {
+                capture.state = CaptureState::Complete;
+            }
+        });
+        ResponseAction::next(None::<Data>)
+    }
+}
@@ -75,16 +75,41 @@ extern crate rustc_serialize;
 #[cfg(feature = "multipart")]
 extern crate multipart;
 
+#[cfg(feature = "gzip")]
+extern crate flate2;
+
+#[cfg(feature = "brotli")]
+extern crate brotli;
+
+#[cfg(feature = "ssl")]
+extern crate openssl;
+
+#[cfg(feature = "sendfile")]
+extern crate libc;
+
+#[cfg(feature = "encoding")]
+extern crate encoding_rs;
+
 extern crate url;
 extern crate time;
 extern crate hyper;
 extern crate anymap;
 extern crate phf;
 extern crate num_cpus;
+extern crate net2;
+extern crate rand;
+extern crate hmac;
+extern crate sha2;
+extern crate subtle;
+extern crate chacha20poly1305;
+
+#[macro_use]
+extern crate log;
 
 pub use hyper::mime;
 pub use hyper::method::Method;
 pub use hyper::status::StatusCode;
+pub use hyper::status::StatusClass;
 pub use hyper::header;
 pub use hyper::Result as HttpResult;
 pub use hyper::Error as HttpError;
@@ -98,7 +123,6 @@ pub use self::handler::Handler;
 pub use self::router::Router;
 pub use self::router::TreeRouter;
 
-mod utils;
 #[macro_use]
 #[doc(hidden)]
 pub mod macros;
@@ -106,7 +130,19 @@ pub mod macros;
 pub mod server;
 pub mod router;
 pub mod handler;
+pub mod extract;
 pub mod context;
 pub mod response;
 pub mod filter;
+pub mod session;
 pub mod file;
+pub mod utils;
+
+#[cfg(feature = "testing")]
+pub mod testing;
+
+#[cfg(all(feature = "sendfile", target_os = "linux"))]
+mod sendfile;
+
+#[cfg(test)]
+mod mock;
@@ -0,0 +1,52 @@
+//!Mock types for testing.
+
+use std::io::{self, Read, Write, Cursor};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use hyper::net::NetworkStream;
+
+///A network stream that reads from and writes to memory.
+pub struct MockStream {
+    pub input: Cursor<Vec<u8>>,
+    pub output: Vec<u8>
+}
+
+impl MockStream {
+    pub fn new<I: Into<Vec<u8>>>(input: I) -> MockStream {
+        MockStream {
+            input: Cursor::new(input.into()),
+            output: vec![]
+        }
+    }
+}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl NetworkStream for MockStream {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        Ok("127.0.0.1:1337".parse().unwrap())
+    }
+
+    fn set_read_timeout(&self, _dur: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _dur: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
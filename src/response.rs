@@ -32,32 +32,64 @@
 //![raw]: struct.Raw.html
 
 use std;
-use std::io::{self, Write};
+use std::io::{self, Read, Write, Seek, SeekFrom, BufWriter};
 use std::error;
 use std::borrow::Cow;
 use std::convert::From;
 use std::str::{from_utf8, Utf8Error};
 use std::string::{FromUtf8Error};
-use std::fs::File;
-use std::path::Path;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::env;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Receiver;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::time::{Duration, Instant, SystemTime};
+#[cfg(all(feature = "sendfile", target_os = "linux"))]
+use std::os::unix::io::RawFd;
+#[cfg(feature = "rustc_json_body")]
+use std::collections::BTreeMap;
+
+#[cfg(feature = "rustc_json_body")]
+use rustc_serialize::json::{self, ToJson};
+#[cfg(feature = "rustc_json_body")]
+use rustc_serialize::Encodable;
 
 use hyper;
 
 use anymap::AnyMap;
+use rand::{self, Rng};
 
 use StatusCode;
+use HttpVersion;
 
 use header::{
     Headers,
     ContentType,
     Connection,
-    ConnectionOption
+    ConnectionOption,
+    TransferEncoding,
+    Encoding,
+    AcceptRanges,
+    RangeUnit,
+    ContentRange,
+    ContentRangeSpec
 };
-use filter::{FilterContext, ResponseFilter};
+#[cfg(feature = "rustc_json_body")]
+use header::Location;
+use filter::{FilterContext, ResponseFilter, RawResponse};
 use filter::ResponseAction as Action;
-use mime::{Mime, TopLevel, SubLevel};
-use server::Global;
-use utils::BytesExt;
+use filter::route::RouteResponseFilters;
+use mime::{Mime, TopLevel, SubLevel, Attr, Value};
+use server::{Global, CompletionInfo};
+use session::Session;
+use utils::{BytesExt, parse_range, ByteRange};
+use context::{Context, VaryHeaders};
+use Method;
 
 ///The result of a response action.
 #[derive(Debug)]
@@ -139,6 +171,13 @@ impl<'a, 'b> FileError<'a, 'b> {
             _ => Ok(())
         }
     }
+
+    fn from_send(error: Error) -> FileError<'a, 'b> {
+        match error {
+            Error::Io(e) => FileError::Send(e),
+            Error::Filter(e) => FileError::Send(io::Error::new(io::ErrorKind::Other, e))
+        }
+    }
 }
 
 impl<'a, 'b> Into<io::Error> for FileError<'a, 'b> {
@@ -249,6 +288,214 @@ impl<'a> Into<Data<'a>> for &'a str {
     }
 }
 
+///An RFC 7807 problem description, for sending errors from APIs as
+///`application/problem+json`. Requires the `rustc_json_body` feature.
+///
+///```
+///extern crate rustc_serialize;
+///# extern crate rustful;
+///use rustc_serialize::json::ToJson;
+///use rustful::StatusCode;
+///use rustful::response::Problem;
+///
+///# fn main() {
+///let problem = Problem::new(StatusCode::Forbidden, "Not enough credit")
+///    .detail("The price is 50, but the balance is only 30.")
+///    .problem_type("https://example.com/problems/out-of-credit")
+///    .extension("balance", 30);
+///
+///assert_eq!(
+///    problem.to_json().to_string(),
+///    r#"{"balance":30,"detail":"The price is 50, but the balance is only 30.","status":403,"title":"Not enough credit","type":"https://example.com/problems/out-of-credit"}"#
+///);
+///# }
+///```
+#[cfg(feature = "rustc_json_body")]
+#[derive(Clone, Debug)]
+pub struct Problem {
+    ///A URI that identifies the type of problem. It's sent as `type`, and
+    ///`about:blank` is used if it's `None`.
+    pub problem_type: Option<String>,
+    ///A short summary of the type of problem.
+    pub title: String,
+    ///The status code of the response.
+    pub status: StatusCode,
+    ///An explanation of this particular occurrence of the problem.
+    pub detail: Option<String>,
+    ///A URI that identifies this particular occurrence of the problem.
+    pub instance: Option<String>,
+    ///Any additional members. They can't replace the standard members.
+    pub extensions: BTreeMap<String, json::Json>
+}
+
+#[cfg(feature = "rustc_json_body")]
+impl Problem {
+    ///Create a problem description from a status code and a title.
+    pub fn new<T: Into<String>>(status: StatusCode, title: T) -> Problem {
+        Problem {
+            problem_type: None,
+            title: title.into(),
+            status: status,
+            detail: None,
+            instance: None,
+            extensions: BTreeMap::new()
+        }
+    }
+
+    ///Set the detailed explanation.
+    pub fn detail<D: Into<String>>(mut self, detail: D) -> Problem {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    ///Set the problem type URI.
+    pub fn problem_type<T: Into<String>>(mut self, problem_type: T) -> Problem {
+        self.problem_type = Some(problem_type.into());
+        self
+    }
+
+    ///Set the URI of this occurrence.
+    pub fn instance<I: Into<String>>(mut self, instance: I) -> Problem {
+        self.instance = Some(instance.into());
+        self
+    }
+
+    ///Add an extension member, possibly replacing a previous one with the
+    ///same name.
+    pub fn extension<K: Into<String>, V: ToJson>(mut self, name: K, value: V) -> Problem {
+        self.extensions.insert(name.into(), value.to_json());
+        self
+    }
+}
+
+#[cfg(feature = "rustc_json_body")]
+impl ToJson for Problem {
+    fn to_json(&self) -> json::Json {
+        let mut object = self.extensions.clone();
+        object.insert("type".into(), self.problem_type.as_ref().map_or("about:blank", |t| &**t).to_json());
+        object.insert("title".into(), self.title.to_json());
+        object.insert("status".into(), json::Json::U64(self.status.to_u16() as u64));
+        if let Some(ref detail) = self.detail {
+            object.insert("detail".into(), detail.to_json());
+        }
+        if let Some(ref instance) = self.instance {
+            object.insert("instance".into(), instance.to_json());
+        }
+        json::Json::Object(object)
+    }
+}
+
+///A value that can be sent as a response, so it can be returned from a
+///handler.
+///
+///A handler can be written as a function that returns a `Responder`,
+///instead of writing to a `Response`, and be turned into a `Handler` with
+///`handler::Responding`. The `Response` is still there underneath, so the
+///two styles can be mixed freely, and custom types can implement this trait
+///to send themselves in any way they want.
+///
+///```
+///use rustful::{Router, TreeRouter, Context, StatusCode};
+///use rustful::handler::Responding;
+///use rustful::Method::Get;
+///
+///fn show_user(context: Context) -> Result<String, (StatusCode, &'static str)> {
+///    match context.variables.get("id") {
+///        Some(id) => Ok(format!("user {}", id)),
+///        None => Err((StatusCode::BadRequest, "missing user ID"))
+///    }
+///}
+///
+///let mut router = TreeRouter::new();
+///router.insert(Get, "/users/:id", Responding(show_user));
+///```
+pub trait Responder {
+    ///Send `self` using `response`.
+    fn respond(self, response: Response);
+}
+
+impl<'a> Responder for &'a str {
+    ///Send the string as `text/plain; charset=utf-8`.
+    fn respond(self, mut response: Response) {
+        response.headers_mut().set(ContentType(Mime(TopLevel::Text, SubLevel::Plain, vec![(Attr::Charset, Value::Utf8)])));
+        response.send(self);
+    }
+}
+
+impl Responder for String {
+    ///Send the string as `text/plain; charset=utf-8`.
+    fn respond(self, mut response: Response) {
+        response.headers_mut().set(ContentType(Mime(TopLevel::Text, SubLevel::Plain, vec![(Attr::Charset, Value::Utf8)])));
+        response.send(self);
+    }
+}
+
+impl Responder for StatusCode {
+    ///Send the status code with an empty body.
+    fn respond(self, mut response: Response) {
+        response.set_status(self);
+    }
+}
+
+impl<R: Responder> Responder for (StatusCode, R) {
+    ///Set the status code and send the second value.
+    fn respond(self, mut response: Response) {
+        response.set_status(self.0);
+        self.1.respond(response);
+    }
+}
+
+impl<T: Responder, E: Responder> Responder for Result<T, E> {
+    ///Send either the success or the error value.
+    fn respond(self, response: Response) {
+        match self {
+            Ok(value) => value.respond(response),
+            Err(error) => error.respond(response)
+        }
+    }
+}
+
+///A value that is sent as `application/json`. Requires the
+///`rustc_json_body` feature.
+///
+///The value is encoded when it's sent, and the response is `500 Internal
+///Server Error` if that fails.
+///
+///```
+///use std::collections::BTreeMap;
+///use rustful::Context;
+///use rustful::response::Json;
+///
+///fn add(context: Context) -> Json<BTreeMap<&'static str, i64>> {
+///    let a = context.query.parse("a").unwrap_or(0);
+///    let b = context.query.parse("b").unwrap_or(0);
+///
+///    let mut result = BTreeMap::new();
+///    result.insert("sum", a + b);
+///    Json(result)
+///}
+///```
+#[cfg(feature = "rustc_json_body")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Json<T>(pub T);
+
+#[cfg(feature = "rustc_json_body")]
+impl<T: Encodable> Responder for Json<T> {
+    ///Encode the value and send it as `application/json; charset=utf-8`.
+    fn respond(self, mut response: Response) {
+        match json::encode(&self.0) {
+            Ok(body) => {
+                response.headers_mut().set(ContentType(Mime(TopLevel::Application, SubLevel::Json, vec![(Attr::Charset, Value::Utf8)])));
+                response.send(body);
+            },
+            Err(e) => {
+                error!("failed to encode a JSON response: {}", e);
+                response.set_status(StatusCode::InternalServerError);
+            }
+        }
+    }
+}
+
 
 ///An interface for sending data to the client.
 ///
@@ -257,10 +504,74 @@ impl<'a> Into<Data<'a>> for &'a str {
 ///its size is known.
 pub struct Response<'a, 'b> {
     writer: Option<hyper::server::response::Response<'a>>,
-    filters: &'b [Box<ResponseFilter>],
+    filters: FilterChain<'b>,
     global: &'b Global,
     filter_storage: Option<AnyMap>,
-    force_close: bool
+    force_close: bool,
+    progress: Progress,
+    session: Option<Session>,
+    request_body: Option<Arc<AtomicBool>>,
+    body_drain: Option<Arc<AtomicBool>>,
+    vary: Option<VaryHeaders>,
+    head_request: bool,
+    stream_buffer_size: usize,
+    auto_content_length: usize,
+    #[cfg(all(feature = "sendfile", target_os = "linux"))]
+    socket: Option<RawFd>,
+    reason: ReasonPhrase
+}
+
+impl<'a> Response<'a, 'a> {
+    ///Create a `Response` that writes into an in-memory buffer, instead of a
+    ///network stream. The returned `BufferHandle` can be used to inspect the
+    ///status, headers and body after the response has been sent.
+    ///
+    ///The response borrows its output stream and headers from `buffer`,
+    ///which can be used again when the response, or whatever it turns into,
+    ///is dropped. Each response gets its own output.
+    ///
+    ///This is mainly useful for testing handlers.
+    ///
+    ///```
+    ///use rustful::{Response, StatusCode};
+    ///use rustful::response::ResponseBuffer;
+    ///
+    ///let mut buffer = ResponseBuffer::new();
+    ///let (mut response, output) = Response::buffered(&mut buffer);
+    ///response.set_status(StatusCode::NotFound);
+    ///response.send("nothing here");
+    ///
+    ///assert_eq!(output.status(), Some(StatusCode::NotFound));
+    ///assert_eq!(output.body(), Some(b"nothing here".to_vec()));
+    ///```
+    pub fn buffered(buffer: &'a mut ResponseBuffer) -> (Response<'a, 'a>, BufferHandle) {
+        let handle = BufferHandle(Arc::new(Mutex::new(Vec::new())));
+        let ResponseBuffer { ref mut output, ref mut headers, ref global } = *buffer;
+        *output = StatusLine::new(BufferWriter(handle.0.clone()));
+        *headers = Headers::new();
+        let reason = output.reason_phrase();
+
+        let response = Response {
+            writer: Some(hyper::server::response::Response::new(output, headers)),
+            filters: FilterChain::new(&[]),
+            global: global,
+            filter_storage: Some(AnyMap::new()),
+            force_close: false,
+            progress: Progress::default(),
+            session: None,
+            request_body: None,
+            body_drain: None,
+            vary: None,
+            head_request: false,
+            stream_buffer_size: DEFAULT_STREAM_BUFFER_SIZE,
+            auto_content_length: 0,
+            #[cfg(all(feature = "sendfile", target_os = "linux"))]
+            socket: None,
+            reason: reason
+        };
+
+        (response, handle)
+    }
 }
 
 impl<'a, 'b> Response<'a, 'b> {
@@ -268,29 +579,225 @@ impl<'a, 'b> Response<'a, 'b> {
     ///Internal and may change without warning.
     pub fn new(
         response: hyper::server::response::Response<'a>,
+        reason: ReasonPhrase,
         filters: &'b [Box<ResponseFilter>],
         global: &'b Global,
         force_close: bool
     ) -> Response<'a, 'b> {
         Response {
             writer: Some(response),
-            filters: filters,
+            filters: FilterChain::new(filters),
             global: global,
             filter_storage: Some(AnyMap::new()),
-            force_close: force_close
+            force_close: force_close,
+            progress: Progress::default(),
+            session: None,
+            request_body: None,
+            body_drain: None,
+            vary: None,
+            head_request: false,
+            stream_buffer_size: DEFAULT_STREAM_BUFFER_SIZE,
+            auto_content_length: 0,
+            #[cfg(all(feature = "sendfile", target_os = "linux"))]
+            socket: None,
+            reason: reason
         }
     }
 
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn progress(&self) -> Progress {
+        self.progress.clone()
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn set_session(&mut self, session: Session) {
+        self.session = Some(session);
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn set_request_body(&mut self, complete: Arc<AtomicBool>) {
+        self.request_body = Some(complete);
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn set_body_drain(&mut self, drain: Arc<AtomicBool>) {
+        self.body_drain = Some(drain);
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn set_vary_headers(&mut self, vary: VaryHeaders) {
+        self.vary = Some(vary);
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn set_head_request(&mut self, head_request: bool) {
+        self.head_request = head_request;
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    #[cfg(all(feature = "sendfile", target_os = "linux"))]
+    pub fn set_socket(&mut self, socket: RawFd) {
+        self.socket = Some(socket);
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn push_route_filters(&mut self, filters: Arc<RouteResponseFilters>) {
+        self.filters.route.push(filters);
+    }
+
+    ///Get the size of the buffer that is used when the body is copied from a
+    ///reader, as in `send_reader` and `send_file`.
+    pub fn stream_buffer_size(&self) -> usize {
+        self.stream_buffer_size
+    }
+
+    ///Set the size of the buffer that is used when the body is copied from a
+    ///reader, as in `send_reader` and `send_file`. The default is taken from
+    ///`Server::stream_buffer_size`, which is 8 KiB by default.
+    ///
+    ///A larger buffer means fewer reads and writes, which can improve the
+    ///throughput for large files, but each response in progress will hold on
+    ///to the whole buffer. It also decides the largest chunk in a chunked
+    ///`send_reader` response, so a smaller buffer lets the client see the
+    ///data sooner, if the reader is slow. A size of 0 is treated as 1.
+    pub fn set_stream_buffer_size(&mut self, size: usize) {
+        self.stream_buffer_size = size;
+    }
+
+    ///Get the largest body that a `Chunked` response will hold back and send
+    ///with a `Content-Length`, in bytes.
+    pub fn auto_content_length(&self) -> usize {
+        self.auto_content_length
+    }
+
+    ///Set the largest body that a `Chunked` response will hold back and send
+    ///with a `Content-Length`, in bytes. The default is taken from
+    ///`Server::auto_content_length`, which is 4 KiB by default, and 0 turns
+    ///it off.
+    ///
+    ///Sending a `Content-Length` lets the connection be kept alive without
+    ///the overhead of chunked transfer encoding, and for HTTP/1.0 clients,
+    ///which would otherwise have the connection closed after the body. The
+    ///status code, the headers and the body are buffered until the response
+    ///ends, and are then sent together with the length of the body. This
+    ///applies to `into_chunked`, and everything that's built on it, like
+    ///`send_reader` without a length.
+    ///
+    ///The response falls back to streaming, as usual, as soon as the body
+    ///grows larger than `limit`, or when it's flushed. Flushing counts as
+    ///asking for the body to be sent right away, so `send_channel`, and
+    ///anything else that flushes after each write, is never held back.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn list(_context: Context, mut response: Response) {
+    ///    response.set_auto_content_length(16 * 1024);
+    ///
+    ///    let mut chunked = response.into_chunked();
+    ///    for i in 0..10 {
+    ///        chunked.send(format!("{}\n", i));
+    ///    }
+    ///
+    ///    //The response is sent with `Content-Length: 20`.
+    ///}
+    ///```
+    pub fn set_auto_content_length(&mut self, limit: usize) {
+        self.auto_content_length = limit;
+    }
+
+    ///Register a callback that is called when this request has been
+    ///handled, after the response has been sent and flushed to the client.
+    ///It's useful for cleanup or metrics that shouldn't delay the response.
+    ///
+    ///The callbacks are called in the order they were registered, before
+    ///any `Server::completion_observers`, and they receive the same
+    ///`CompletionInfo`, with the final status and the number of bytes that
+    ///were written. They are called even if the client disconnected, or if
+    ///the handler panicked, and `CompletionInfo::interrupted` tells if the
+    ///response was cut short. The request headers are only included if
+    ///there are completion observers, so anything else that's needed from
+    ///the request has to be captured by the callback.
+    ///
+    ///```
+    ///# #[macro_use] extern crate log;
+    ///# extern crate rustful;
+    ///use rustful::{Context, Response};
+    ///use rustful::server::CompletionInfo;
+    ///
+    ///fn my_handler(context: Context, mut response: Response) {
+    ///    let path = context.uri_path.clone();
+    ///    response.on_complete(move |info: &CompletionInfo| {
+    ///        info!("sent {} bytes for {}", info.bytes_written, path);
+    ///    });
+    ///    response.send("hello");
+    ///}
+    ///# fn main() {}
+    ///```
+    pub fn on_complete<F: FnOnce(&CompletionInfo) + 'static>(&mut self, callback: F) {
+        self.progress.add_callback(Box::new(callback));
+    }
+
     ///Get the current status code.
     pub fn status(&self) -> StatusCode {
         self.writer.as_ref().expect("status accessed after drop").status()
     }
 
-    ///Change the status code. `Ok (200)` is the default.
+    ///Change the status code. `Ok (200)` is the default. The canonical
+    ///reason phrase is used, if there is one, and any custom phrase from
+    ///`set_status_with_reason` is removed.
     pub fn set_status(&mut self, status: StatusCode) {
         if let Some(ref mut writer) = self.writer {
             *writer.status_mut() = status;
         }
+
+        *self.reason.0.borrow_mut() = None;
+    }
+
+    ///Change the status code to any number from 100 to 599, and send it
+    ///with a custom reason phrase, like `419 Session Expired`. Use
+    ///`set_status` to go back to the canonical phrase.
+    ///
+    ///The phrase may contain spaces and tabs, but no other control
+    ///characters, and an error is returned if it does, or if the code is out
+    ///of range. The status can still be changed by the response filters, and
+    ///the custom phrase is only used if the final code is the same. Clients
+    ///are not supposed to care about the phrase, so it shouldn't carry any
+    ///important information.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(_context: Context, mut response: Response) {
+    ///    response.set_status_with_reason(419, "Session Expired").unwrap();
+    ///    response.send("please log in again");
+    ///}
+    ///```
+    pub fn set_status_with_reason(&mut self, code: u16, reason: &str) -> Result<(), Error> {
+        if code < 100 || code > 599 {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput, "the status code is not between 100 and 599")));
+        }
+
+        if reason.chars().any(|c| c != '\t' && (c.is_control() || c == '\u{7f}')) {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput, "the reason phrase contains a control character")));
+        }
+
+        if let Some(ref mut writer) = self.writer {
+            *writer.status_mut() = StatusCode::from_u16(code);
+        }
+
+        //Hyper always writes the canonical phrase, so the `StatusLine`
+        //below it rewrites it on its way out.
+        *self.reason.0.borrow_mut() = Some((code, reason.as_bytes().to_vec()));
+        Ok(())
     }
 
     ///Get a reference to the headers.
@@ -303,6 +810,252 @@ impl<'a, 'b> Response<'a, 'b> {
         self.writer.as_mut().expect("headers mutably accessed after drop").headers_mut()
     }
 
+    ///Set a raw header to `value`, and replace any values it already has.
+    ///Use `add_header` to keep them.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(_context: Context, mut response: Response) {
+    ///    response.set_header("Cache-Control", "no-store");
+    ///    response.send("hello");
+    ///}
+    ///```
+    pub fn set_header<K: Into<Cow<'static, str>>, V: Into<Vec<u8>>>(&mut self, name: K, value: V) {
+        self.headers_mut().set_raw(name.into(), vec![value.into()]);
+    }
+
+    ///Add `value` to a raw header, after any values it already has. Each
+    ///value is sent as a separate header line, and they are never joined
+    ///into one comma separated line.
+    ///
+    ///This is the way to add to headers that may appear more than once, like
+    ///`Set-Cookie`, `WWW-Authenticate`, `Link`, `Vary` and `Warning`.
+    ///`Set-Cookie` must always be sent as one line per cookie, since cookies
+    ///may contain commas themselves, so the values of a typed `SetCookie`
+    ///header are kept as separate lines when a value is added to it, as
+    ///well. Most other headers are either single values, like
+    ///`Content-Type`, that should be replaced with `set_header`, or comma
+    ///separated lists, where separate lines have the same meaning.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(_context: Context, mut response: Response) {
+    ///    response.add_header("Set-Cookie", "theme=dark; Path=/");
+    ///    response.add_header("Set-Cookie", "lang=sv; Path=/");
+    ///    response.send("hello");
+    ///}
+    ///```
+    pub fn add_header<K: Into<Cow<'static, str>>, V: Into<Vec<u8>>>(&mut self, name: K, value: V) {
+        let name = name.into();
+        let mut values = ::utils::raw_header_values(self.headers(), &name).unwrap_or_default();
+        values.push(value.into());
+        self.headers_mut().set_raw(name, values);
+    }
+
+    ///Set multiple raw headers at once, from name and value pairs.
+    ///
+    ///A header that is already in the response is replaced by the first value
+    ///with the same name, and any further values with that name are appended
+    ///to it. Names are compared without regard to case. The values can be
+    ///anything that can be converted into bytes, such as `&str`, `String` or
+    ///`Vec<u8>`.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(_context: Context, mut response: Response) {
+    ///    response.set_headers(vec![
+    ///        ("Access-Control-Allow-Origin", "*"),
+    ///        ("Access-Control-Allow-Methods", "GET, POST"),
+    ///        ("Vary", "Origin"),
+    ///        ("Vary", "Accept-Encoding"),
+    ///    ]);
+    ///    response.send("hello");
+    ///}
+    ///```
+    pub fn set_headers<I, K, V>(&mut self, headers: I) where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<Cow<'static, str>>,
+        V: Into<Vec<u8>>
+    {
+        let response_headers = self.headers_mut();
+        let mut set = HashSet::new();
+
+        for (name, value) in headers {
+            let name = name.into();
+            let mut values = if set.insert(name.to_ascii_lowercase()) {
+                vec![]
+            } else {
+                ::utils::raw_header_values(response_headers, &name).unwrap_or_default()
+            };
+
+            values.push(value.into());
+            response_headers.set_raw(name, values);
+        }
+    }
+
+    ///Remove all headers, including the default `Date`, `Server` and
+    ///`Content-Type` headers, and replace them with `headers`, as with
+    ///`set_headers`.
+    pub fn replace_headers<I, K, V>(&mut self, headers: I) where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<Cow<'static, str>>,
+        V: Into<Vec<u8>>
+    {
+        self.headers_mut().clear();
+        self.set_headers(headers);
+    }
+
+    ///Set the `Last-Modified` header to `time`, formatted as an HTTP date.
+    ///The time is rounded down to whole seconds, and times outside the years
+    ///1 to 9999 are clamped, as described for `utils::format_http_date`.
+    ///
+    ///`StaticFiles` and the conditional request checks compare it with
+    ///`If-Modified-Since`, which can be read with
+    ///`Context::if_modified_since`.
+    ///
+    ///```
+    ///use std::time::{Duration, UNIX_EPOCH};
+    ///use rustful::{Context, Response, StatusCode};
+    ///
+    ///fn my_handler(context: Context, mut response: Response) {
+    ///    let modified = UNIX_EPOCH + Duration::from_secs(1500000000);
+    ///    response.set_last_modified(modified);
+    ///
+    ///    if context.if_modified_since().map_or(false, |since| modified <= since) {
+    ///        response.set_status(StatusCode::NotModified);
+    ///        response.send("");
+    ///    } else {
+    ///        response.send("the content");
+    ///    }
+    ///}
+    ///```
+    pub fn set_last_modified(&mut self, time: SystemTime) {
+        self.headers_mut().set_raw("Last-Modified", vec![::utils::format_http_date(time).into_bytes()]);
+    }
+
+    ///Send the response as `206 Partial Content`, covering the bytes from
+    ///`start` to `end`, inclusive, of a representation that is `total` bytes
+    ///long, or of unknown length if `total` is `None`. This sets
+    ///`Content-Range` to `bytes start-end/total`, or `bytes start-end/*`, and
+    ///the body should be exactly those `end - start + 1` bytes.
+    ///
+    ///An error is returned, and nothing is changed, if `start` is after
+    ///`end`, or if `end` is not before `total`, since that's what makes the
+    ///last byte position off by one. Use `send_range` or `send_file` to let
+    ///the `Range` header be handled automatically.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn first_kilobyte(_context: Context, mut response: Response) {
+    ///    let content = vec![0; 4096];
+    ///    response.set_content_range(0, 1023, Some(4096)).unwrap();
+    ///    response.send(&content[..1024]);
+    ///}
+    ///```
+    pub fn set_content_range(&mut self, start: u64, end: u64, total: Option<u64>) -> Result<(), Error> {
+        if start > end {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput, "the range starts after its end")));
+        }
+
+        if total.map_or(false, |total| end >= total) {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput, "the range ends after the last byte")));
+        }
+
+        self.set_status(StatusCode::PartialContent);
+        self.headers_mut().set(ContentRange(ContentRangeSpec::Bytes {
+            range: Some((start, end)),
+            instance_length: total
+        }));
+        Ok(())
+    }
+
+    ///Mark the response as deprecated, with `Deprecation: true`. `sunset`
+    ///is the time when the resource is expected to stop working, and is
+    ///sent as an HTTP date in a `Sunset` header. `link` is a URI reference
+    ///to documentation about the deprecation, such as a migration guide, and
+    ///is added to the `Link` headers with `rel="deprecation"`.
+    ///
+    ///An error is returned, and nothing is changed, if `link` isn't a valid
+    ///URI reference, including if it contains anything that has to be
+    ///percent encoded first. Use the `Deprecation` filter to mark every
+    ///response from a route.
+    ///
+    ///```
+    ///use std::time::{Duration, UNIX_EPOCH};
+    ///use rustful::{Context, Response};
+    ///
+    ///fn old_users(_context: Context, mut response: Response) {
+    ///    let sunset = UNIX_EPOCH + Duration::from_secs(1893456000);
+    ///    response.mark_deprecated(Some(sunset), Some("https://example.com/docs/v2")).unwrap();
+    ///    response.send("[]");
+    ///}
+    ///```
+    pub fn mark_deprecated(&mut self, sunset: Option<SystemTime>, link: Option<&str>) -> Result<(), Error> {
+        if let Some(link) = link {
+            try!(::filter::deprecation::check_link(link));
+        }
+
+        ::filter::deprecation::set_headers(self.headers_mut(), sunset, link);
+        Ok(())
+    }
+
+    ///Send a `103 Early Hints` interim response with a `Link` header for
+    ///each of `links`, to let the client start loading resources while the
+    ///final response is prepared. Each link is a `Link` header value, like
+    ///`</style.css>; rel=preload; as=style`, with a URI reference in angle
+    ///brackets, optionally followed by parameters.
+    ///
+    ///The interim response is sent and flushed right away, before any of the
+    ///headers of this response, which are not affected. It can be sent more
+    ///than once, and the final response still has to be sent afterwards, as
+    ///usual. It doesn't matter for `keep-alive`, since that's decided by the
+    ///final response.
+    ///
+    ///Nothing is sent to HTTP/1.0 clients, since they don't expect interim
+    ///responses. An error is returned, and nothing is sent, if a link
+    ///doesn't start with a valid URI reference in angle brackets, or if it
+    ///contains a line break or other control characters. The interim
+    ///response bypasses the response filters, and it's not counted as a part
+    ///of the body.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn page(_context: Context, mut response: Response) {
+    ///    let _ = response.send_early_hints(&["</style.css>; rel=preload; as=style"]);
+    ///    //...render the page...
+    ///    response.send("<link rel=\"stylesheet\" href=\"/style.css\">");
+    ///}
+    ///```
+    pub fn send_early_hints(&mut self, links: &[&str]) -> Result<(), Error> {
+        for link in links {
+            try!(check_hint_link(link));
+        }
+
+        let writer = self.writer.take().expect("response used after drop");
+        if writer.version == HttpVersion::Http09 || writer.version == HttpVersion::Http10 {
+            self.writer = Some(writer);
+            return Ok(());
+        }
+
+        let (version, mut body, status, headers) = writer.deconstruct();
+
+        let result = write!(body, "{} 103 Early Hints\r\n", version).and_then(|_| {
+            for link in links {
+                try!(write!(body, "Link: {}\r\n", link));
+            }
+            try!(body.write_all(b"\r\n"));
+            body.flush()
+        });
+
+        self.writer = Some(hyper::server::response::Response::<hyper::net::Fresh>::construct(version, body, status, headers));
+        result.map_err(Error::Io)
+    }
+
     ///Get a reference to the filter storage.
     pub fn filter_storage(&self) -> &AnyMap {
         self.filter_storage.as_ref().expect("filter storage accessed after drop")
@@ -355,23 +1108,19 @@ impl<'a, 'b> Response<'a, 'b> {
         let mut filter_storage = self.filter_storage.take().expect("response used after drop");
 
         if self.filters.is_empty() {
-            if self.force_close {
-                writer.headers_mut().set(Connection(vec![ConnectionOption::Close]));
-            }
-            writer.send(content.into().as_bytes()).map_err(|e| e.into())
+            self.prepare_headers(writer.headers_mut());
+            send_body(writer, content.into().as_bytes(), &self.progress).map_err(|e| e.into())
         } else {
             let mut buffer = vec![];
 
             let (status, write_queue) = try!(filter_headers(
-                self.filters,
+                &self.filters,
                 writer.status(),
                 writer.headers_mut(),
                 self.global,
                 &mut filter_storage
             ));
-            if self.force_close {
-                writer.headers_mut().set(Connection(vec![ConnectionOption::Close]));
-            }
+            self.prepare_headers(writer.headers_mut());
             *writer.status_mut() = status;
             for action in write_queue {
                 match action {
@@ -382,14 +1131,14 @@ impl<'a, 'b> Response<'a, 'b> {
                 }
             }
 
-            let filter_result = filter_content(self.filters, content, self.global, &mut filter_storage);
+            let filter_result = filter_content(&self.filters, content, self.global, &mut filter_storage);
             match filter_result {
                 Action::Next(Some(content)) => buffer.push_bytes(content.as_bytes()),
                 Action::Abort(e) => return Err(Error::Filter(e)),
                 _ => {}
             }
 
-            let write_queue = try!(filter_end(self.filters, self.global, &mut filter_storage));
+            let write_queue = try!(filter_end(&self.filters, self.global, &mut filter_storage));
             for action in write_queue {
                 match action {
                     Action::Next(Some(content)) => buffer.push_bytes(content.as_bytes()),
@@ -398,19 +1147,56 @@ impl<'a, 'b> Response<'a, 'b> {
                     Action::SilentAbort => break
                 }
             }
-            
-            writer.send(&buffer).map_err(|e| e.into())
+
+            let mut wrapper = BodyWrapper::new(&self.filters, self.global, &mut filter_storage);
+            let mut body = try!(wrapper.write(&buffer));
+            body.extend(wrapper.finish());
+
+            send_body(writer, &body, &self.progress).map_err(|e| e.into())
         }
     }
 
-    ///Send a static file to the client.
-    ///
-    ///A MIME type is automatically applied to the response, based on the file
-    ///extension, and `application/octet-stream` is used as a fallback if the
+    //Last minute changes to the headers, before they are written.
+    fn prepare_headers(&self, headers: &mut Headers) {
+        //The next request can't be found if the current body is unread,
+        //unless it's small enough to be drained when the handler is done.
+        let body_unread = self.request_body.as_ref().map_or(false, |complete| !complete.load(Ordering::Relaxed));
+        let draining = self.body_drain.as_ref().map_or(false, |drain| drain.load(Ordering::Relaxed));
+        let closing = headers.get::<Connection>().map_or(false, |&Connection(ref options)| options.contains(&ConnectionOption::Close));
+
+        if self.force_close || closing || (body_unread && !draining) {
+            headers.set(Connection(vec![ConnectionOption::Close]));
+            if let Some(ref drain) = self.body_drain {
+                drain.store(false, Ordering::Relaxed);
+            }
+        }
+
+        if let Some(ref session) = self.session {
+            session.commit(headers);
+        }
+
+        if let Some(ref vary) = self.vary {
+            vary.apply(headers);
+        }
+    }
+
+    ///Send a static file to the client.
+    ///
+    ///A MIME type is automatically applied to the response, based on the file
+    ///extension, and `application/octet-stream` is used as a fallback if the
     ///extension is unknown. Use `send_file_with_mime` to override the MIME
     ///guessing. See also [`ext_to_mime`](../file/fn.ext_to_mime.html) for more
     ///information.
     ///
+    ///Only the head is sent in response to a `HEAD` request, with the length
+    ///of the file as `Content-Length`, and the file is not read.
+    ///
+    ///The file is sent with `sendfile(2)` if the `sendfile` feature is
+    ///enabled, the platform is Linux and the connection is plain HTTP, so it
+    ///doesn't have to be copied through userspace. It's copied through a
+    ///buffer in every other case, including HTTPS connections, since they
+    ///have to be encrypted. The same goes for `send_download`.
+    ///
     ///An error is returned upon failure and the response may be recovered
     ///from there if the file could not be opened.
     ///
@@ -521,7 +1307,53 @@ impl<'a, 'b> Response<'a, 'b> {
             .and_then(|ext| to_mime(&ext.to_string_lossy()))
             .unwrap_or_else(|| Mime(TopLevel::Application, SubLevel::Ext("octet-stream".into()), vec![]));
 
-        let mut file = match File::open(path) {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => return Err(FileError::Open(e, self))
+        };
+        let metadata = match file.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) => return Err(FileError::Open(e, self))
+        };
+
+        self.headers_mut().set(ContentType(mime));
+
+        if self.head_request {
+            return self.send_head(metadata.len()).map_err(FileError::Send);
+        }
+
+        self.send_file_region(file, 0, metadata.len()).map_err(FileError::from_send)
+    }
+
+    ///Send a file as a download, that browsers will save as `filename`.
+    ///
+    ///The `Content-Disposition` header is set to `attachment`, with the
+    ///filename encoded as by
+    ///[`content_disposition`](../file/fn.content_disposition.html), so any
+    ///name can be used. The MIME type is guessed from the extension of
+    ///`path`, like in `send_file`, and the file is sent with
+    ///`send_seekable`, so `Range` requests are supported, for resumed
+    ///downloads.
+    ///
+    ///An error is returned upon failure and the response may be recovered
+    ///from there if the file could not be opened.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn download_report(context: Context, response: Response) {
+    ///    let _ = response.send_download("reports/2016.csv", "Årsrapport 2016.csv", &context)
+    ///        .or_else(|e| e.send_not_found("the report was not found"));
+    ///}
+    ///```
+    pub fn send_download<P: AsRef<Path>>(mut self, path: P, filename: &str, request: &Context) -> Result<(), FileError<'a, 'b>> {
+        let path: &Path = path.as_ref();
+        let mime = path
+            .extension()
+            .and_then(|ext| ::file::ext_to_mime(&ext.to_string_lossy()))
+            .unwrap_or_else(|| Mime(TopLevel::Application, SubLevel::Ext("octet-stream".into()), vec![]));
+
+        let file = match File::open(path) {
             Ok(file) => file,
             Err(e) => return Err(FileError::Open(e, self))
         };
@@ -531,37 +1363,555 @@ impl<'a, 'b> Response<'a, 'b> {
         };
 
         self.headers_mut().set(ContentType(mime));
+        self.headers_mut().set_raw("Content-Disposition", vec![::file::content_disposition(filename).into_bytes()]);
+
+        self.send_range(file, metadata.len(), request, |response, file, first, length| {
+            response.send_file_region(file, first, length)
+        }).map_err(FileError::from_send)
+    }
+
+    ///Send the content of a seekable reader to the client, with support for
+    ///`Range` requests.
+    ///
+    ///The requested range is found using
+    ///[`parse_range`](../utils/fn.parse_range.html), and only that part of
+    ///`reader` is sent, with the status `206 Partial Content`. The whole
+    ///content is sent if the request is not a `GET` request, or if it asks
+    ///for the whole content, and `416 Range Not Satisfiable` is sent if
+    ///the range is outside of the content. `total_length` is the length of
+    ///the whole content, and the reader is expected to be at its beginning.
+    ///
+    ///Only the head is sent in response to a `HEAD` request, with
+    ///`Content-Length` set to `total_length`. The reader is not used, and
+    ///the `Range` header is ignored.
+    ///
+    ///The body is sent as in `send_reader`, with a fixed length, and errors
+    ///are handled in the same way. The status is set to
+    ///`500 Internal Server Error` if seeking fails.
+    ///
+    ///```
+    ///use std::io::Cursor;
+    ///use rustful::{Context, Response};
+    ///
+    ///fn send_data(context: Context, response: Response) {
+    ///    let data = Cursor::new(vec![0; 1000]);
+    ///    response.send_seekable(data, 1000, &context).unwrap_or(());
+    ///}
+    ///```
+    pub fn send_seekable<R: Read + Seek>(self, reader: R, total_length: u64, request: &Context) -> Result<(), Error> {
+        self.send_range(reader, total_length, request, |mut response, mut reader, first, length| {
+            if first > 0 {
+                if let Err(e) = reader.seek(SeekFrom::Start(first)) {
+                    response.seek_failed();
+                    return Err(Error::Io(e));
+                }
+            }
+
+            response.send_reader(reader, Some(length))
+        })
+    }
+
+    //Find the requested range of the content and send it using `send`, which
+    //gets the response, the content, and the offset and length of the range.
+    fn send_range<B, F>(mut self, content: B, total_length: u64, request: &Context, send: F) -> Result<(), Error> where
+        F: FnOnce(Response<'a, 'b>, B, u64, u64) -> Result<(), Error>
+    {
+        self.headers_mut().set(AcceptRanges(vec![RangeUnit::Bytes]));
+
+        if request.method == Method::Head {
+            return self.send_head(total_length).map_err(Error::Io);
+        }
+
+        let range = if request.method == Method::Get {
+            parse_range(&request.headers, total_length)
+        } else {
+            ByteRange::Full
+        };
+
+        match range {
+            ByteRange::Partial(0, last) if last + 1 == total_length => send(self, content, 0, total_length),
+            ByteRange::Full => send(self, content, 0, total_length),
+            ByteRange::Partial(first, last) => {
+                self.set_status(StatusCode::PartialContent);
+                self.headers_mut().set(ContentRange(ContentRangeSpec::Bytes {
+                    range: Some((first, last)),
+                    instance_length: Some(total_length)
+                }));
+                send(self, content, first, last - first + 1)
+            },
+            ByteRange::Unsatisfiable => {
+                self.set_status(StatusCode::RangeNotSatisfiable);
+                self.headers_mut().set(ContentRange(ContentRangeSpec::Bytes {
+                    range: None,
+                    instance_length: Some(total_length)
+                }));
+                self.try_send(&[][..])
+            }
+        }
+    }
+
+    //Send `length` bytes of `file`, starting at `offset`. The file is sent
+    //directly from the page cache, with `sendfile(2)`, if the connection is
+    //a plain TCP socket, and copied through a buffer otherwise.
+    fn send_file_region(mut self, mut file: File, offset: u64, length: u64) -> Result<(), Error> {
+        #[cfg(all(feature = "sendfile", target_os = "linux"))]
+        {
+            if let Some(socket) = self.socket {
+                let buffer_size = self.stream_buffer_size;
+                let mut writer = unsafe { self.into_raw(length) };
+                try!(writer.send_file(socket, &mut file, offset, buffer_size));
+                return writer.end().map_err(Error::Io);
+            }
+        }
+
+        if offset > 0 {
+            if let Err(e) = file.seek(SeekFrom::Start(offset)) {
+                self.seek_failed();
+                return Err(Error::Io(e));
+            }
+        }
+
+        self.send_reader(file, Some(length))
+    }
+
+    //The range can't be sent if seeking fails.
+    fn seek_failed(&mut self) {
+        self.set_status(StatusCode::InternalServerError);
+        self.headers_mut().remove::<ContentRange>();
+    }
+
+    ///Send the content of a reader to the client, in bounded chunks.
+    ///
+    ///The `Content-Length` header is set if `length` is `Some(...)`, and the
+    ///body bypasses any response filters, just like with `send_file`.
+    ///Chunked transfer encoding is used otherwise, and the body is filtered
+    ///as usual.
+    ///
+    ///The response can't be completed if the reader fails, if it ends before
+    ///`length` bytes has been read, or if the client disconnects, so the
+    ///client will see a truncated body. The error is returned in those cases.
+    ///Any content beyond `length` is ignored.
+    ///
+    ///```
+    ///# #[macro_use] extern crate log;
+    ///# extern crate rustful;
+    ///use std::process::{Command, Stdio};
+    ///use rustful::{Context, Response};
+    ///
+    ///fn show_uptime(_context: Context, response: Response) {
+    ///    let mut child = Command::new("uptime").stdout(Stdio::piped()).spawn().unwrap();
+    ///    let output = child.stdout.take().unwrap();
+    ///
+    ///    if let Err(e) = response.send_reader(output, None) {
+    ///        error!("could not send the output of `uptime`: {}", e);
+    ///    }
+    ///    child.wait().unwrap();
+    ///}
+    ///# fn main() {}
+    ///```
+    pub fn send_reader<R: Read>(self, mut reader: R, length: Option<u64>) -> Result<(), Error> {
+        let mut buffer = vec![0; ::std::cmp::max(self.stream_buffer_size, 1)];
+
+        match length {
+            Some(length) => {
+                let mut writer = unsafe { self.into_raw(length) };
+                let mut remaining = length;
+
+                while remaining > 0 {
+                    let limit = ::std::cmp::min(remaining, buffer.len() as u64) as usize;
+                    let read = match reader.read(&mut buffer[..limit]) {
+                        Ok(0) => return Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "the reader ended before the expected length"))),
+                        Ok(read) => read,
+                        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(Error::Io(e))
+                    };
+                    try!(writer.write_all(&buffer[..read]));
+                    remaining -= read as u64;
+                }
+
+                writer.end().map_err(Error::Io)
+            },
+            None => {
+                let mut writer = self.into_chunked();
+
+                loop {
+                    let read = match reader.read(&mut buffer) {
+                        Ok(0) => break,
+                        Ok(read) => read,
+                        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => {
+                            writer.abort();
+                            return Err(Error::Io(e));
+                        }
+                    };
+
+                    if let Err(e) = writer.try_send(&buffer[..read]) {
+                        writer.abort();
+                        return Err(e);
+                    }
+                }
+
+                writer.end()
+            }
+        }
+    }
+
+    ///Send the chunks that are received from `receiver`, using chunked
+    ///transfer encoding, until every sender has been dropped.
+    ///
+    ///This is meant for bodies that are produced by other threads, such as
+    ///live updates. Each chunk is filtered as usual and flushed to the client
+    ///right away. The response is aborted if a chunk can't be written, which
+    ///happens when the client has disconnected, and the error is returned.
+    ///The receiver is dropped before returning, so the senders will
+    ///get an error when they try to send more. A disconnect is only noticed
+    ///when the next chunk is written, since a half-closed connection looks
+    ///the same as an idle one until then.
+    ///
+    ///```
+    ///use std::sync::mpsc::channel;
+    ///use std::thread;
+    ///use rustful::{Context, Response};
+    ///
+    ///fn live(_context: Context, response: Response) {
+    ///    let (sender, receiver) = channel();
+    ///
+    ///    thread::spawn(move || {
+    ///        for i in 0..10 {
+    ///            if sender.send(format!("update {}\n", i).into_bytes()).is_err() {
+    ///                //The client has disconnected.
+    ///                break;
+    ///            }
+    ///        }
+    ///    });
+    ///
+    ///    response.send_channel(receiver).unwrap_or(());
+    ///}
+    ///```
+    pub fn send_channel(self, receiver: Receiver<Vec<u8>>) -> Result<(), Error> {
+        let mut writer = self.into_chunked();
+
+        for chunk in receiver.iter() {
+            let result = writer.try_send(chunk).and_then(|_| writer.flush().map_err(Error::Io));
+            if let Err(e) = result {
+                drop(receiver);
+                writer.abort();
+                return Err(e);
+            }
+        }
+
+        writer.end()
+    }
+
+    ///Send a body that is written by `write_body`, using chunked transfer
+    ///encoding.
+    ///
+    ///The body is filtered as usual, and what has been written is flushed to
+    ///the client after any write that comes at least 100 milliseconds after
+    ///the previous flush. Call `flush` on the writer to send everything right
+    ///away, for example before waiting for more content.
+    ///
+    ///The response is aborted, leaving the body truncated, if `write_body`
+    ///returns an error, and that error is returned. Errors from writing to
+    ///the client are passed to `write_body` as IO errors.
+    ///
+    ///```
+    ///# #[macro_use] extern crate log;
+    ///# extern crate rustful;
+    ///use std::io::Write;
+    ///use std::thread;
+    ///use std::time::Duration;
+    ///use rustful::{Context, Response};
+    ///
+    ///fn count_down(_context: Context, response: Response) {
+    ///    let result = response.send_with(|body| {
+    ///        for i in (1..4).rev() {
+    ///            try!(writeln!(body, "{}...", i));
+    ///            try!(body.flush());
+    ///            thread::sleep(Duration::from_millis(500));
+    ///        }
+    ///        writeln!(body, "done!")
+    ///    });
+    ///
+    ///    if let Err(e) = result {
+    ///        error!("the count down was interrupted: {}", e);
+    ///    }
+    ///}
+    ///# fn main() {}
+    ///```
+    pub fn send_with<F: FnOnce(&mut Write) -> io::Result<()>>(self, write_body: F) -> Result<(), Error> {
+        let mut writer = PeriodicFlush {
+            writer: self.into_chunked(),
+            interval: Duration::from_millis(100),
+            last_flush: None
+        };
+
+        match write_body(&mut writer) {
+            Ok(()) => writer.writer.end(),
+            Err(e) => {
+                writer.writer.abort();
+                Err(Error::Io(e))
+            }
+        }
+    }
+
+    ///Write the body into a buffer, and send it with a `Content-Length` when
+    ///it's done, instead of using chunked transfer encoding.
+    ///
+    ///This is for clients that don't handle chunked bodies well, when the
+    ///length isn't known beforehand. The body is kept in memory until it's
+    ///larger than `spill_threshold` bytes, and it's moved to a temporary file
+    ///after that. Nothing is sent until the `BufferedWriter` is dropped, or
+    ///`end` is called, so the client has to wait for the whole body, and a
+    ///large body will use disk space until it has been sent.
+    ///
+    ///The temporary file is created in the system's temporary directory, and
+    ///it's removed when the writer is done, even if the body couldn't be
+    ///sent. Platforms that allow open files to be removed, like Linux and
+    ///macOS, get it removed from the directory right away, so it's not left
+    ///behind if the process is killed.
+    ///
+    ///The body bypasses any response filters, like with `send_reader` and a
+    ///known length. The response is sent as `500 Internal Server Error`,
+    ///without a body, if the temporary file couldn't be written.
+    ///
+    ///```
+    ///use std::io::Write;
+    ///use rustful::{Context, Response};
+    ///
+    ///fn report(_context: Context, response: Response) {
+    ///    let mut body = response.buffered_body(64 * 1024);
+    ///    for i in 0..1000 {
+    ///        if writeln!(body, "line {}", i).is_err() {
+    ///            break;
+    ///        }
+    ///    }
+    ///}
+    ///```
+    pub fn buffered_body(self, spill_threshold: usize) -> BufferedWriter<'a, 'b> {
+        BufferedWriter {
+            response: Some(self),
+            memory: vec![],
+            file: None,
+            length: 0,
+            spill_threshold: spill_threshold,
+            error: None,
+        }
+    }
+
+    ///Apply the status and the headers of a response from an upstream
+    ///server, such as when acting as a gateway, and return the response,
+    ///ready for the body to be sent with `send` or `send_reader`.
+    ///
+    ///The headers are copied to this response, replacing any headers with
+    ///the same names, except for hop-by-hop headers, which only concern a
+    ///single connection. These are removed, as required by RFC 7230:
+    ///
+    /// * `Connection`, and any header that it lists,
+    /// * `Keep-Alive`,
+    /// * `Proxy-Authenticate` and `Proxy-Authorization`,
+    /// * `TE` and `Trailer`,
+    /// * `Transfer-Encoding`,
+    /// * `Upgrade`.
+    ///
+    ///`Content-Length` is not copied either, since it's set from the body
+    ///that is actually sent. Other headers that describe the body, like
+    ///`Content-Encoding`, are copied as they are, so the body should be sent
+    ///without being decoded.
+    ///
+    ///```
+    ///use rustful::{Context, Response, StatusCode};
+    ///use rustful::header::{Headers, ContentType};
+    ///
+    ///fn gateway(_context: Context, response: Response) {
+    ///    //This would usually come from a client request.
+    ///    let mut headers = Headers::new();
+    ///    headers.set(ContentType::plaintext());
+    ///    headers.set_raw("Connection", vec![b"close".to_vec()]);
+    ///
+    ///    response.from_parts(StatusCode::Ok, &headers).send("hello");
+    ///}
+    ///```
+    pub fn from_parts(mut self, status: StatusCode, headers: &Headers) -> Response<'a, 'b> {
+        let connection_headers: Vec<String> = headers.get::<Connection>().map_or_else(Vec::new, |&Connection(ref options)| {
+            options.iter().filter_map(|option| match *option {
+                ConnectionOption::ConnectionHeader(ref name) => Some(name.to_string()),
+                _ => None
+            }).collect()
+        });
+
+        for header in headers.iter() {
+            let name = header.name();
+            let hop_by_hop = HOP_BY_HOP_HEADERS.iter().any(|hop_by_hop| hop_by_hop.eq_ignore_ascii_case(name))
+                || connection_headers.iter().any(|hop_by_hop| hop_by_hop.eq_ignore_ascii_case(name));
+
+            if !hop_by_hop && !name.eq_ignore_ascii_case("Content-Length") {
+                if let Some(values) = ::utils::raw_header_values(headers, name) {
+                    self.headers_mut().set_raw(name.to_owned(), values);
+                }
+            }
+        }
+
+        self.set_status(status);
+        self
+    }
+
+    ///Forward a response from an upstream server to the client, without
+    ///buffering the body.
+    ///
+    ///The status and the headers are applied as with `from_parts`, which
+    ///removes any hop-by-hop headers.
+    ///
+    ///`body` is expected to be decoded from any transfer encoding, which is
+    ///already the case for the body of a Hyper client response. It's sent as
+    ///with `send_reader`, with the length from the upstream `Content-Length`
+    ///header, or using chunked transfer encoding if it's unknown.
+    ///
+    ///```
+    ///# #[macro_use] extern crate log;
+    ///# extern crate rustful;
+    ///use std::io::Cursor;
+    ///use rustful::{Context, Response, StatusCode};
+    ///use rustful::header::{Headers, ContentLength};
+    ///
+    ///fn gateway(_context: Context, response: Response) {
+    ///    //This would usually come from a client request.
+    ///    let mut headers = Headers::new();
+    ///    headers.set(ContentLength(5));
+    ///    let body = Cursor::new("hello");
+    ///
+    ///    if let Err(e) = response.proxy(StatusCode::Ok, &headers, body) {
+    ///        error!("could not forward the upstream response: {}", e);
+    ///    }
+    ///}
+    ///# fn main() {}
+    ///```
+    pub fn proxy<R: Read>(self, status: StatusCode, headers: &Headers, body: R) -> Result<(), Error> {
+        let length = headers.get::<::header::ContentLength>().map(|length| length.0);
+        self.from_parts(status, headers).send_reader(body, length)
+    }
+
+    ///Send an RFC 7807 problem description with the status `status`, the
+    ///summary `title` and the explanation `detail`. Use `send_problem` to
+    ///include more members. Requires the `rustc_json_body` feature.
+    ///
+    ///```
+    ///use rustful::{Context, Response, StatusCode};
+    ///
+    ///fn show_user(context: Context, response: Response) {
+    ///    match context.variables.get("id") {
+    ///        Some(id) => response.send(format!("user {}", id)),
+    ///        None => response.problem(StatusCode::BadRequest, "Missing user ID", "The user ID is required.")
+    ///    }
+    ///}
+    ///```
+    #[cfg(feature = "rustc_json_body")]
+    pub fn problem<T: Into<String>, D: Into<String>>(self, status: StatusCode, title: T, detail: D) {
+        self.send_problem(Problem::new(status, title).detail(detail));
+    }
+
+    ///Send an RFC 7807 problem description as `application/problem+json`,
+    ///with its status code. Requires the `rustc_json_body` feature.
+    #[cfg(feature = "rustc_json_body")]
+    pub fn send_problem(mut self, problem: Problem) {
+        self.set_status(problem.status);
+        self.headers_mut().set(ContentType(Mime(TopLevel::Application, SubLevel::Ext("problem+json".into()), vec![])));
+        self.send(problem.to_json().to_string());
+    }
 
-        let mut writer = unsafe { self.into_raw(metadata.len()) };
+    ///Send `201 Created`, with the URL of the new resource in `Location`, and
+    ///the resource itself encoded as `application/json`. Requires the
+    ///`rustc_json_body` feature.
+    ///
+    ///The response is `500 Internal Server Error` if `location` is empty, or
+    ///if the resource can't be encoded, since there is nothing correct to
+    ///send in those cases.
+    ///
+    ///```
+    ///use std::collections::BTreeMap;
+    ///use rustful::{Context, Response};
+    ///
+    ///fn create_user(context: Context, response: Response) {
+    ///    let name = context.query.get("name").map(|name| name.into_owned()).unwrap_or_default();
+    ///
+    ///    let mut user = BTreeMap::new();
+    ///    user.insert("id", "42".to_owned());
+    ///    user.insert("name", name);
+    ///    response.created_json("/users/42", &user);
+    ///}
+    ///```
+    #[cfg(feature = "rustc_json_body")]
+    pub fn created_json<T: Encodable>(mut self, location: &str, body: &T) {
+        if location.is_empty() {
+            error!("tried to send 201 Created without a location");
+            self.set_status(StatusCode::InternalServerError);
+            return;
+        }
 
-        io::copy(&mut file, &mut writer).map_err(FileError::Send).map(|_| ())
+        match json::encode(body) {
+            Ok(body) => {
+                self.set_status(StatusCode::Created);
+                self.headers_mut().set(Location(location.to_owned()));
+                self.headers_mut().set(ContentType(Mime(TopLevel::Application, SubLevel::Json, vec![(Attr::Charset, Value::Utf8)])));
+                self.send(body);
+            },
+            Err(e) => {
+                error!("failed to encode a JSON response: {}", e);
+                self.set_status(StatusCode::InternalServerError);
+            }
+        }
     }
 
     ///Write the status code and headers to the client and turn the `Response`
     ///into a `Chunked` response.
+    ///
+    ///HTTP/1.0 clients don't understand chunked bodies, so they get the body
+    ///as it is, with `Connection: close`, and the end of the body is marked
+    ///by closing the connection. The connection can't be kept alive in that
+    ///case, so prefer sending a body with a known size to those clients.
+    ///
+    ///The status code and headers are held back if `auto_content_length` is
+    ///set, until it's clear if the body is small enough to be sent with a
+    ///`Content-Length`. See `set_auto_content_length` for more details.
     pub fn into_chunked(mut self) -> Chunked<'a, 'b> {
         let mut writer = self.writer.take().expect("response used after drop");
+        let mut wrapper = None;
 
         //Make sure it's chunked
         writer.headers_mut().remove::<::header::ContentLength>();
         writer.headers_mut().remove_raw("content-length");
 
+        let filters = self.filters.clone();
         let writer = filter_headers(
-            self.filters,
+            &filters,
             writer.status(),
             writer.headers_mut(),
             self.global,
             self.filter_storage_mut()
         ).and_then(|(status, write_queue)|{
-            if self.force_close {
-                writer.headers_mut().set(Connection(vec![ConnectionOption::Close]));
-            }
+            self.prepare_headers(writer.headers_mut());
             *writer.status_mut() = status;
-            let mut writer = try!(writer.start());
+
+            let close_delimited = writer.version == HttpVersion::Http10 && has_body(status);
+            let mut writer = if self.auto_content_length > 0 && has_body(status) {
+                StreamWriter::Pending(PendingWriter {
+                    writer: Some(writer),
+                    buffer: vec![],
+                    limit: self.auto_content_length,
+                    close_delimited: close_delimited,
+                    progress: self.progress.clone()
+                })
+            } else {
+                try!(start_stream(writer, close_delimited, &self.progress))
+            };
+
+            if !filters.is_empty() {
+                wrapper = Some(BodyWrapper::new(&filters, self.global, self.filter_storage_mut()));
+            }
 
             for action in write_queue {
                 match action {
-                    Action::Next(Some(content)) => try!(writer.write_all(content.as_bytes())),
+                    Action::Next(Some(content)) => try!(write_wrapped(&mut wrapper, &mut self.progress.count(&mut writer), content.as_bytes())),
                     Action::Next(None) => {},
                     Action::Abort(e) => return Err(Error::Filter(e)),
                     Action::SilentAbort => break
@@ -573,30 +1923,122 @@ impl<'a, 'b> Response<'a, 'b> {
 
         Chunked {
             writer: Some(writer),
-            filters: self.filters,
+            filters: filters,
             global: self.global,
-            filter_storage: self.filter_storage.take().expect("response used after drop")
+            filter_storage: self.filter_storage.take().expect("response used after drop"),
+            wrapper: wrapper,
+            progress: self.progress.clone()
+        }
+    }
+
+    ///Write the status code and headers to the client and turn the `Response`
+    ///into a `MultipartStream`, for sending a `multipart/mixed` body, one part
+    ///at the time.
+    ///
+    ///The content type is set to `multipart/mixed`, with a random boundary,
+    ///and the body is sent using chunked transfer encoding, as with
+    ///`into_chunked`.
+    ///
+    ///```
+    ///#[macro_use]
+    ///extern crate rustful;
+    ///
+    ///use std::fs::File;
+    ///use rustful::{Context, Response};
+    ///use rustful::header::{Headers, ContentType};
+    ///
+    ///fn download_all(_context: Context, response: Response) {
+    ///    let mut multipart = response.into_multipart();
+    ///
+    ///    for name in &["report.csv", "summary.csv"] {
+    ///        let file = match File::open(name) {
+    ///            Ok(file) => file,
+    ///            Err(_) => return multipart.abort()
+    ///        };
+    ///
+    ///        let mut headers = Headers::new();
+    ///        headers.set(ContentType(content_type!(Text / "csv")));
+    ///        headers.set_raw("Content-Disposition", vec![format!("attachment; filename=\"{}\"", name).into_bytes()]);
+    ///
+    ///        if multipart.send_part(&headers, file).is_err() {
+    ///            //The client has disconnected, or the file couldn't be read.
+    ///            return;
+    ///        }
+    ///    }
+    ///}
+    ///# fn main() {}
+    ///```
+    pub fn into_multipart(mut self) -> MultipartStream<'a, 'b> {
+        let mut rng = rand::thread_rng();
+        let boundary = format!("{:016x}{:016x}", rng.gen::<u64>(), rng.gen::<u64>());
+        self.headers_mut().set(ContentType(Mime(
+            TopLevel::Multipart,
+            SubLevel::Ext("mixed".to_owned()),
+            vec![(Attr::Boundary, Value::Ext(boundary.clone()))]
+        )));
+
+        let buffer_size = ::std::cmp::max(self.stream_buffer_size, 1);
+        MultipartStream {
+            chunked: Some(self.into_chunked()),
+            boundary: boundary,
+            buffer: vec![0; buffer_size],
         }
     }
 
     ///Write the status code and headers to the client and turn the `Response`
     ///into a `Raw` response. Any eventual response filters are bypassed to
-    ///make sure that the data is not modified.
+    ///make sure that the data is not modified, except for
+    ///`ResponseFilter::finalize`, which may still change the status and the
+    ///headers. The filter storage will contain a `RawResponse` marker when
+    ///it's called.
     ///
     ///__Unsafety__: The content length is set beforehand, which makes it
-    ///possible to send responses that are too short.
+    ///possible to send responses that are too short or too long. The `Raw`
+    ///response keeps track of the length, and anything beyond it is cut off,
+    ///with an error from the write. A body that ends too early can't be
+    ///completed, so the connection is closed after it, like for
+    ///`Chunked::abort`, and `Raw::end` returns an error. The client will
+    ///still see a truncated body in both cases.
     pub unsafe fn into_raw(mut self, content_length: u64) -> Raw<'a> {
         let mut writer = self.writer.take().expect("response used after drop");
+        self.prepare_raw_head(&mut writer, content_length);
 
-        if self.force_close {
-            writer.headers_mut().set(Connection(vec![ConnectionOption::Close]));
+        Raw {
+            writer: Some(writer.start()),
+            remaining: content_length,
+            progress: self.progress.clone()
         }
-        writer.headers_mut().remove_raw("content-length");
-        writer.headers_mut().set(::header::ContentLength(content_length));
+    }
 
-        Raw {
-            writer: Some(writer.start())
+    //Send only the head, for a `HEAD` request, with the length of the body
+    //that would have been sent. The head is the same as from `into_raw`, so
+    //it matches the response to a `GET` request.
+    fn send_head(mut self, content_length: u64) -> io::Result<()> {
+        let mut writer = self.writer.take().expect("response used after drop");
+        self.prepare_raw_head(&mut writer, content_length);
+
+        //Hyper would expect the body to follow, so the head is written here
+        //instead.
+        let (version, mut body, status, headers) = writer.deconstruct();
+        try!(write!(body, "{} {}\r\n{}\r\n", version, status, headers));
+        body.flush()
+    }
+
+    //Finalize the status and the headers of a response that bypasses the
+    //other response filter steps, and set its length.
+    fn prepare_raw_head(&mut self, writer: &mut hyper::server::response::Response<'a>, content_length: u64) {
+        let mut status = writer.status();
+        {
+            let filter_storage = self.filter_storage.as_mut().expect("response used after drop");
+            filter_storage.insert(RawResponse);
+            finalize_headers(&self.filters, &mut status, writer.headers_mut(), self.global, filter_storage);
         }
+        *writer.status_mut() = status;
+
+        self.prepare_headers(writer.headers_mut());
+        writer.headers_mut().remove_raw("content-length");
+        writer.headers_mut().set(::header::ContentLength(content_length));
+        self.progress.set_head(writer.status(), writer.headers());
     }
 }
 
@@ -605,6 +2047,10 @@ impl<'a, 'b> Drop for Response<'a, 'b> {
     ///Writes status code and headers and closes the connection.
     fn drop(&mut self) {
         if self.writer.is_some() {
+            if ::std::thread::panicking() {
+                self.set_status(StatusCode::InternalServerError);
+                self.force_close = true;
+            }
             self.send_sized(&[][..]);
         }
     }
@@ -615,12 +2061,16 @@ impl<'a, 'b> Drop for Response<'a, 'b> {
 ///
 
 ///This is useful for when the size of the data is unknown, but it comes with
-///an overhead for each time `send` or `try_send` is called (simply put).
+///an overhead for each time `send` or `try_send` is called (simply put). The
+///data may be buffered before it's sent to the client, so use `flush` to make
+///sure that it's sent right away.
 pub struct Chunked<'a, 'b> {
-    writer: Option<Result<hyper::server::response::Response<'a, hyper::net::Streaming>, Error>>,
-    filters: &'b [Box<ResponseFilter>],
+    writer: Option<Result<StreamWriter<'a>, Error>>,
+    filters: FilterChain<'b>,
     global: &'b Global,
-    filter_storage: AnyMap
+    filter_storage: AnyMap,
+    wrapper: Option<BodyWrapper>,
+    progress: Progress
 }
 
 impl<'a, 'b> Chunked<'a, 'b> {
@@ -635,6 +2085,17 @@ impl<'a, 'b> Chunked<'a, 'b> {
         &mut self.filter_storage
     }
 
+    ///Get the number of body bytes that has been written to the connection
+    ///so far.
+    ///
+    ///This is counted after the response filters, including any compression,
+    ///so it's the size of the body as it's sent to the client, without the
+    ///chunk framing. Bytes that are buffered in the filters or failed to be
+    ///written, because the client disconnected, are not included.
+    pub fn bytes_written(&self) -> u64 {
+        self.progress.bytes_written()
+    }
+
     ///Send a chunk of data to the client, ignoring any eventual errors. Use
     ///`try_send` to get error information.
     ///
@@ -689,12 +2150,12 @@ impl<'a, 'b> Chunked<'a, 'b> {
             } else { unreachable!(); }
         };
 
-        let filter_result = filter_content(self.filters, content, self.global, &mut self.filter_storage);
+        let filter_result = filter_content(&self.filters, content, self.global, &mut self.filter_storage);
 
         let write_result = match filter_result {
             Action::Next(Some(ref s)) => {
                 let buf = s.as_bytes();
-                match writer.write_all(buf) {
+                match write_wrapped(&mut self.wrapper, &mut self.progress.count(writer), buf) {
                     Ok(()) => Some(Ok(buf.len())),
                     Err(e) => Some(Err(e))
                 }
@@ -713,6 +2174,42 @@ impl<'a, 'b> Chunked<'a, 'b> {
         }
     }
 
+    ///Send all buffered data to the client right away.
+    ///
+    ///Response filters, such as compression, may hold on to some of the data
+    ///before passing it on, and the connection is buffered in blocks of 8 kB.
+    ///Everything is sent when the response ends, but interactive streams,
+    ///like server-sent events, has to be flushed after each message to make
+    ///it reach the client in time.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn count(_context: Context, response: Response) {
+    ///    let mut chunked = response.into_chunked();
+    ///
+    ///    for i in 0..10 {
+    ///        chunked.send(format!("{}\n", i));
+    ///        if chunked.flush().is_err() {
+    ///            break;
+    ///        }
+    ///        std::thread::sleep(std::time::Duration::from_secs(1));
+    ///    }
+    ///}
+    ///```
+    pub fn flush(&mut self) -> io::Result<()> {
+        let output = match self.wrapper {
+            Some(ref mut wrapper) => try!(wrapper.flush()),
+            None => vec![]
+        };
+        let progress = self.progress.clone();
+        let mut writer = try!(response_to_io_result(self.borrow_writer()));
+        if !output.is_empty() {
+            try!(progress.count(&mut writer).write_all(&output));
+        }
+        writer.flush()
+    }
+
     ///Finish writing the response and collect eventual errors.
     ///
     ///This is optional and will happen silently when the writer drops out of
@@ -723,22 +2220,84 @@ impl<'a, 'b> Chunked<'a, 'b> {
 
     fn finish(&mut self) -> Result<(), Error> {
         let mut writer = try!(self.writer.take().expect("can only finish once"));
-        let write_queue = try!(filter_end(self.filters, self.global, &mut self.filter_storage));
+        let write_queue = try!(filter_end(&self.filters, self.global, &mut self.filter_storage));
 
         for action in write_queue {
             try!{
                 match action {
-                    Action::Next(Some(content)) => writer.write_all(content.as_bytes()),
+                    Action::Next(Some(content)) => write_wrapped(&mut self.wrapper, &mut self.progress.count(&mut writer), content.as_bytes()),
                     Action::Abort(e) => return Err(Error::Filter(e)),
                     _ => Ok(())
                 }
             }
         }
 
-        writer.end().map_err(Error::Io)
+        if let Some(mut wrapper) = self.wrapper.take() {
+            try!(self.progress.count(&mut writer).write_all(&wrapper.finish()));
+        }
+
+        self.progress.check(writer.end()).map_err(Error::Io)
+    }
+
+    ///Stop sending the body without ending it, to tell the client that the
+    ///response is incomplete.
+    ///
+    ///The status and the headers have already been sent at this point, so
+    ///there is no way to report the error as such. What's left is to leave
+    ///out the last chunk, which marks the end of a complete body, and close
+    ///the connection after what has been sent so far. The client will then
+    ///see that the body was cut off, instead of mistaking it for a complete
+    ///but shorter body, which is important for data that has to be
+    ///complete, like file downloads or exports. The connection can't be
+    ///reused for more requests afterwards. Anything that's buffered in the
+    ///response filters is discarded.
+    ///
+    ///The same thing happens when the body of `send_reader` or `send_with`
+    ///fails. `CompletionInfo::interrupted` is set for aborted responses.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///# fn next_row(_: u32) -> Result<Option<String>, ()> { Ok(None) }
+    ///fn export(_context: Context, response: Response) {
+    ///    let mut chunked = response.into_chunked();
+    ///
+    ///    for i in 0.. {
+    ///        match next_row(i) {
+    ///            Ok(Some(row)) => chunked.send(row),
+    ///            Ok(None) => break,
+    ///            Err(_) => return chunked.abort()
+    ///        }
+    ///    }
+    ///}
+    ///```
+    pub fn abort(mut self) {
+        self.progress.0.interrupted.set(true);
+
+        //A held back response has to be sent as a stream to be cut off.
+        if let Some(Ok(ref mut writer)) = self.writer {
+            let _ = writer.start_streaming();
+        }
+
+        match self.writer.take() {
+            Some(Ok(StreamWriter::Chunked(writer))) => {
+                //The writer would otherwise write the last chunk when dropped.
+                let (_, mut body, _, headers) = writer.deconstruct();
+                let _ = body.flush();
+
+                //Hyper checks the response headers to see if the connection
+                //can be kept alive, even after they have been sent.
+                headers.set(Connection(vec![ConnectionOption::Close]));
+            },
+            //The connection is already going to be closed.
+            Some(Ok(StreamWriter::Close(mut body))) => {
+                let _ = body.flush();
+            },
+            _ => {}
+        }
     }
 
-    fn borrow_writer(&mut self) -> Result<&mut hyper::server::response::Response<'a, hyper::net::Streaming>, Error> {
+    fn borrow_writer(&mut self) -> Result<&mut StreamWriter<'a>, Error> {
         match self.writer {
             Some(Ok(ref mut writer)) => Ok(writer),
             None => Err(Error::Io(io::Error::new(io::ErrorKind::BrokenPipe, "write after close"))),
@@ -759,8 +2318,7 @@ impl<'a, 'b> Write for Chunked<'a, 'b> {
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        let mut writer = try!(response_to_io_result(self.borrow_writer()));
-        writer.flush()
+        Chunked::flush(self)
     }
 }
 
@@ -774,6 +2332,409 @@ impl<'a, 'b> Drop for Chunked<'a, 'b> {
     }
 }
 
+///A streaming `multipart/mixed` response body, where each part has its own
+///headers and is copied from a reader.
+///
+///The parts are sent as soon as they are added, so the memory use stays
+///the same regardless of how many parts there are, or how large they are.
+///Each part is copied through a buffer of `Response::stream_buffer_size`
+///bytes, and flushed to the client when it's complete. This makes it
+///possible to send batch downloads, or other bundles of files, without
+///reading them into memory first.
+///
+///The response is aborted if a part can't be written, which happens when
+///the client has disconnected, or if its reader fails, and every part after
+///that is rejected right away. The body is not terminated in that case, so
+///the client can see that it's incomplete. It's otherwise ended with the
+///closing boundary when `end` is called, or when the `MultipartStream` is
+///dropped.
+///
+///See `Response::into_multipart` for an example.
+pub struct MultipartStream<'a, 'b> {
+    chunked: Option<Chunked<'a, 'b>>,
+    boundary: String,
+    buffer: Vec<u8>,
+}
+
+impl<'a, 'b> MultipartStream<'a, 'b> {
+    ///The boundary that separates the parts.
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    ///Send a part with `headers` and the content of `body`, and return the
+    ///number of bytes that were read from `body`. The response is aborted if
+    ///the part can't be sent.
+    pub fn send_part<R: Read>(&mut self, headers: &Headers, body: R) -> Result<u64, Error> {
+        let result = self.write_part(headers, body);
+        if result.is_err() {
+            if let Some(chunked) = self.chunked.take() {
+                chunked.abort();
+            }
+        }
+        result
+    }
+
+    fn write_part<R: Read>(&mut self, headers: &Headers, mut body: R) -> Result<u64, Error> {
+        let MultipartStream { ref mut chunked, ref boundary, ref mut buffer } = *self;
+        let chunked = match *chunked {
+            Some(ref mut chunked) => chunked,
+            None => return Err(Error::Io(io::Error::new(io::ErrorKind::BrokenPipe, "write after close")))
+        };
+
+        try!(chunked.try_send(format!("--{}\r\n{}\r\n", boundary, headers)));
+
+        let mut length = 0;
+        loop {
+            let read = match body.read(buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(Error::Io(e))
+            };
+            try!(chunked.try_send(&buffer[..read]));
+            length += read as u64;
+        }
+
+        try!(chunked.try_send("\r\n"));
+        try!(chunked.flush());
+        Ok(length)
+    }
+
+    ///Send the closing boundary and finish writing the response.
+    pub fn end(mut self) -> Result<(), Error> {
+        self.finish()
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        match self.chunked.take() {
+            Some(mut chunked) => {
+                try!(chunked.try_send(format!("--{}--\r\n", self.boundary)));
+                chunked.end()
+            },
+            None => Ok(())
+        }
+    }
+
+    ///Stop sending the body without the closing boundary, to tell the client
+    ///that the response is incomplete. See `Chunked::abort` for more
+    ///details.
+    pub fn abort(mut self) {
+        if let Some(chunked) = self.chunked.take() {
+            chunked.abort();
+        }
+    }
+}
+
+#[allow(unused_must_use)]
+impl<'a, 'b> Drop for MultipartStream<'a, 'b> {
+    ///Sends the closing boundary and finishes writing.
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+///A response body that is buffered in memory, or in a temporary file, and
+///sent with a `Content-Length` when it's complete.
+///
+///The body is sent when `end` is called, or when the writer is dropped. See
+///`Response::buffered_body` for more details.
+pub struct BufferedWriter<'a, 'b> {
+    response: Option<Response<'a, 'b>>,
+    memory: Vec<u8>,
+    //The file is closed before the path is removed.
+    file: Option<(BufWriter<File>, TempPath)>,
+    length: u64,
+    spill_threshold: usize,
+    //The first write that failed. The body is incomplete after it.
+    error: Option<io::Error>,
+}
+
+impl<'a, 'b> BufferedWriter<'a, 'b> {
+    ///Get the number of bytes that have been written to the buffer so far.
+    pub fn bytes_buffered(&self) -> u64 {
+        self.length
+    }
+
+    ///Check if the body has grown larger than the threshold, and has been
+    ///moved to a temporary file.
+    pub fn is_spilled(&self) -> bool {
+        self.file.is_some()
+    }
+
+    ///Add a piece of data to the body, ignoring any eventual errors. Use
+    ///`try_send` to get error information.
+    #[allow(unused_must_use)]
+    pub fn send<'d, Content: Into<Data<'d>>>(&mut self, content: Content) {
+        self.try_send(content);
+    }
+
+    ///Add a piece of data to the body. This is the same as `send`, but
+    ///errors are not ignored. An error means that the temporary file
+    ///couldn't be written, and the response will be sent as `500 Internal
+    ///Server Error`.
+    pub fn try_send<'d, Content: Into<Data<'d>>>(&mut self, content: Content) -> io::Result<()> {
+        self.write_all(content.into().as_bytes())
+    }
+
+    ///Send the buffered body and finish the response, and collect eventual
+    ///errors.
+    ///
+    ///This is optional and will happen silently when the writer drops out of
+    ///scope.
+    pub fn end(mut self) -> Result<(), Error> {
+        self.finish()
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        let mut response = match self.response.take() {
+            Some(response) => response,
+            None => return Ok(())
+        };
+
+        let file = match self.file.take() {
+            Some((writer, path)) => match rewind_spill_file(writer) {
+                Ok(file) => Some((file, path)),
+                Err(e) => {
+                    self.error = Some(e);
+                    None
+                }
+            },
+            None => None
+        };
+
+        if let Some(e) = self.error.take() {
+            response.set_status(StatusCode::InternalServerError);
+            let _ = response.try_send(&[][..]);
+            return Err(Error::Io(e));
+        }
+
+        if response.head_request {
+            return response.send_head(self.length).map_err(Error::Io);
+        }
+
+        match file {
+            Some((file, _path)) => response.send_reader(file, Some(self.length)),
+            None => {
+                let mut writer = unsafe { response.into_raw(self.length) };
+                try!(writer.write_all(&self.memory));
+                writer.end().map_err(Error::Io)
+            }
+        }
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        let (file, path) = try!(create_spill_file());
+        let mut writer = BufWriter::new(file);
+        try!(writer.write_all(&self.memory));
+        self.memory = vec![];
+        self.file = Some((writer, path));
+        Ok(())
+    }
+}
+
+impl<'a, 'b> Write for BufferedWriter<'a, 'b> {
+    fn write(&mut self, content: &[u8]) -> io::Result<usize> {
+        if self.error.is_some() {
+            return Err(io::Error::new(io::ErrorKind::Other, "the body could not be buffered"));
+        }
+
+        let mut result = Ok(());
+        if self.file.is_none() && self.memory.len() + content.len() > self.spill_threshold {
+            result = self.spill();
+        }
+
+        let result = result.and_then(|_| match self.file {
+            Some((ref mut file, _)) => file.write(content),
+            None => {
+                self.memory.extend_from_slice(content);
+                Ok(content.len())
+            }
+        });
+
+        match result {
+            Ok(written) => {
+                self.length += written as u64;
+                Ok(written)
+            },
+            Err(e) => {
+                let error = io::Error::new(e.kind(), e.to_string());
+                self.error = Some(e);
+                Err(error)
+            }
+        }
+    }
+
+    //Nothing is sent before the body is complete.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a, 'b> Drop for BufferedWriter<'a, 'b> {
+    ///Sends the buffered body.
+    fn drop(&mut self) {
+        //The response will be sent as a 500 error when it's dropped.
+        if !::std::thread::panicking() {
+            let _ = self.finish();
+        }
+    }
+}
+
+//A temporary file that is removed when this is dropped, unless it was
+//already removed when it was created.
+struct TempPath(Option<PathBuf>);
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.0 {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+fn create_spill_file() -> io::Result<(File, TempPath)> {
+    let directory = env::temp_dir();
+    let mut rng = rand::thread_rng();
+
+    loop {
+        let name = format!("rustful-response-{}-{:016x}", process::id(), rng.gen::<u64>());
+        let path = directory.join(name);
+
+        match OpenOptions::new().read(true).write(true).create_new(true).open(&path) {
+            Ok(file) => {
+                //The file stays open, and can still be used, on platforms
+                //that allow it to be removed.
+                let path = match fs::remove_file(&path) {
+                    Ok(()) => None,
+                    Err(_) => Some(path)
+                };
+                return Ok((file, TempPath(path)));
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e)
+        }
+    }
+}
+
+fn rewind_spill_file(writer: BufWriter<File>) -> io::Result<File> {
+    let mut file = try!(writer.into_inner().map_err(|e| e.into_error()));
+    try!(file.seek(SeekFrom::Start(0)));
+    Ok(file)
+}
+
+//The body writer of a `Chunked` response. HTTP/1.0 clients get the body as
+//it is, and the end is marked by closing the connection. Small bodies may be
+//held back, to be sent with a `Content-Length`.
+enum StreamWriter<'a> {
+    Chunked(hyper::server::response::Response<'a, hyper::net::Streaming>),
+    Close(hyper::http::h1::HttpWriter<&'a mut (Write + 'a)>),
+    Pending(PendingWriter<'a>),
+}
+
+impl<'a> StreamWriter<'a> {
+    fn end(self) -> io::Result<()> {
+        match self {
+            StreamWriter::Chunked(writer) => writer.end(),
+            StreamWriter::Close(mut writer) => writer.flush(),
+            StreamWriter::Pending(writer) => writer.end()
+        }
+    }
+
+    //Send the head and what has been held back, and continue as a stream.
+    fn start_streaming(&mut self) -> io::Result<()> {
+        let writer = match *self {
+            StreamWriter::Pending(ref mut pending) => try!(pending.start_streaming()),
+            _ => return Ok(())
+        };
+        *self = writer;
+        Ok(())
+    }
+}
+
+impl<'a> Write for StreamWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            StreamWriter::Chunked(ref mut writer) => return writer.write(buf),
+            StreamWriter::Close(ref mut writer) => return writer.write(buf),
+            StreamWriter::Pending(ref mut writer) => if writer.buffer.len() + buf.len() <= writer.limit {
+                writer.buffer.extend_from_slice(buf);
+                return Ok(buf.len());
+            }
+        }
+
+        try!(self.start_streaming());
+        self.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.start_streaming());
+        match *self {
+            StreamWriter::Chunked(ref mut writer) => writer.flush(),
+            StreamWriter::Close(ref mut writer) => writer.flush(),
+            StreamWriter::Pending(_) => Ok(())
+        }
+    }
+}
+
+//A response where the head and the body are held back until the body is
+//complete, or grows larger than `limit`. The body has already been counted
+//by the progress when it's buffered.
+struct PendingWriter<'a> {
+    writer: Option<hyper::server::response::Response<'a>>,
+    buffer: Vec<u8>,
+    limit: usize,
+    close_delimited: bool,
+    progress: Progress
+}
+
+impl<'a> PendingWriter<'a> {
+    fn start_streaming(&mut self) -> io::Result<StreamWriter<'a>> {
+        let writer = try!(self.writer.take().ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "write after close")));
+        let mut writer = try!(start_stream(writer, self.close_delimited, &self.progress));
+        try!(writer.write_all(&self.buffer));
+        self.buffer = vec![];
+        Ok(writer)
+    }
+
+    fn end(mut self) -> io::Result<()> {
+        let mut writer = try!(self.writer.take().ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "write after close")));
+        writer.headers_mut().remove::<::header::TransferEncoding>();
+        writer.headers_mut().set(::header::ContentLength(self.buffer.len() as u64));
+        self.progress.set_head(writer.status(), writer.headers());
+        let mut writer = try!(writer.start());
+        try!(writer.write_all(&self.buffer));
+        writer.end()
+    }
+}
+
+//Write the head of a chunked or close delimited response.
+fn start_stream<'a>(mut writer: hyper::server::response::Response<'a>, close_delimited: bool, progress: &Progress) -> io::Result<StreamWriter<'a>> {
+    if close_delimited {
+        writer.headers_mut().remove::<::header::TransferEncoding>();
+        writer.headers_mut().set(Connection(vec![ConnectionOption::Close]));
+    }
+
+    progress.set_head(writer.status(), writer.headers());
+    if close_delimited {
+        //Hyper would add chunked encoding, so the head is written
+        //here instead.
+        let (version, mut body, status, headers) = writer.deconstruct();
+        try!(write!(body, "{} {}\r\n{}\r\n", version, status, headers));
+        Ok(StreamWriter::Close(body))
+    } else {
+        writer.start().map(StreamWriter::Chunked)
+    }
+}
+
+//Hyper never sends a body with these.
+fn has_body(status: StatusCode) -> bool {
+    match status {
+        StatusCode::NoContent | StatusCode::NotModified => false,
+        status => status.class() != ::hyper::status::StatusClass::Informational
+    }
+}
+
 ///A streaming fixed-size response.
 ///
 ///Everything is written directly to the network stream, without being
@@ -782,10 +2743,20 @@ impl<'a, 'b> Drop for Chunked<'a, 'b> {
 ///__Unsafety__: The content length is set beforehand, which makes it possible
 ///to send responses that are too short.
 pub struct Raw<'a> {
-    writer: Option<Result<hyper::server::response::Response<'a, hyper::net::Streaming>, io::Error>>
+    writer: Option<Result<hyper::server::response::Response<'a, hyper::net::Streaming>, io::Error>>,
+    //What's left of the announced content length.
+    remaining: u64,
+    progress: Progress
 }
 
 impl<'a> Raw<'a> {
+    ///Get the number of body bytes that has been written to the connection
+    ///so far. Bytes that failed to be written, because the client
+    ///disconnected, are not included.
+    pub fn bytes_written(&self) -> u64 {
+        self.progress.bytes_written()
+    }
+
     ///Send a piece of data to the client, ignoring any eventual errors. Use
     ///`try_send` to get error information.
     ///
@@ -836,17 +2807,87 @@ impl<'a> Raw<'a> {
         self.write_all(content.into().as_bytes())
     }
 
+    ///Send all buffered data to the client right away. The connection is
+    ///buffered in blocks of 8 kB, which are otherwise sent when they are
+    ///full, or when the response ends.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let writer = try!(self.borrow_writer());
+        writer.flush()
+    }
+
     ///Finish writing the response and collect eventual errors.
     ///
     ///This is optional and will happen silently when the writer drops out of
-    ///scope.
+    ///scope. An error is returned if less than the announced content length
+    ///has been written, and the connection is closed.
     pub fn end(mut self) -> io::Result<()> {
+        if let (true, Some(Ok(_))) = (self.remaining > 0, self.writer.as_ref()) {
+            return Err(self.end_early());
+        }
+
         let writer = match self.writer.take() {
             Some(Ok(writer)) => writer,
             None => return Ok(()), //It has already ended
             Some(Err(e)) => return Err(e)
         };
-        writer.end()
+        self.progress.check(writer.end())
+    }
+
+    //Send the rest of the body from `file`, starting at `offset`, with
+    //`sendfile(2)`. The head, and anything else that has been written, is
+    //flushed first. It falls back to a buffered copy if the file can't be
+    //sent that way, like for some special file systems.
+    #[cfg(all(feature = "sendfile", target_os = "linux"))]
+    fn send_file(&mut self, socket: RawFd, file: &mut File, offset: u64, buffer_size: usize) -> io::Result<()> {
+        try!(self.flush());
+
+        let mut sent = 0;
+        while self.remaining > 0 {
+            match ::sendfile::send(socket, file, offset + sent, self.remaining) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the file ended before the expected length")),
+                Ok(length) => {
+                    sent += length as u64;
+                    self.remaining -= length as u64;
+                    self.progress.add_written(length as u64);
+                },
+                Err(ref e) if sent == 0 && ::sendfile::is_unsupported(e) => {
+                    try!(file.seek(SeekFrom::Start(offset)));
+                    let remaining = self.remaining;
+                    return copy_buffered(&mut file.take(remaining), self, buffer_size);
+                },
+                Err(e) => return self.progress.check(Err(e))
+            }
+        }
+
+        Ok(())
+    }
+
+    //Close the connection after a body that is shorter than announced.
+    fn end_early(&mut self) -> io::Error {
+        error!("a response body ended {} bytes before its Content-Length, and the connection will be closed", self.remaining);
+        self.progress.0.interrupted.set(true);
+
+        if let Some(Ok(writer)) = self.writer.take() {
+            //The writer would otherwise end the response when dropped, as if
+            //it was complete.
+            let (_, mut body, _, headers) = writer.deconstruct();
+            let _ = body.flush();
+
+            //Hyper checks the response headers to see if the connection
+            //can be kept alive, even after they have been sent.
+            headers.set(Connection(vec![ConnectionOption::Close]));
+        }
+
+        io::Error::new(io::ErrorKind::UnexpectedEof, "the body is shorter than Content-Length")
+    }
+
+    //The part of `content` that fits within the content length.
+    fn limit<'c>(&mut self, content: &'c [u8]) -> &'c [u8] {
+        let length = ::std::cmp::min(content.len() as u64, self.remaining) as usize;
+        if length < content.len() {
+            error!("a response body is longer than its Content-Length, and {} bytes were cut off", content.len() - length);
+        }
+        &content[..length]
     }
 
     fn borrow_writer(&mut self) -> io::Result<&mut hyper::server::response::Response<'a, hyper::net::Streaming>> {
@@ -862,44 +2903,607 @@ impl<'a> Raw<'a> {
 
 impl<'a> Write for Raw<'a> {
     fn write(&mut self, content: &[u8]) -> io::Result<usize> {
-        let mut writer = try!(self.borrow_writer());
-        writer.write(content)
+        let limited = self.limit(content);
+        if limited.is_empty() && !content.is_empty() {
+            return Err(too_long());
+        }
+
+        let progress = self.progress.clone();
+        let written = {
+            let writer = try!(self.borrow_writer());
+            try!(progress.count(writer).write(limited))
+        };
+        self.remaining -= written as u64;
+        Ok(written)
     }
 
     fn write_all(&mut self, content: &[u8]) -> io::Result<()> {
-        let mut writer = try!(self.borrow_writer());
-        writer.write_all(content)
+        let limited = self.limit(content);
+
+        let progress = self.progress.clone();
+        {
+            let writer = try!(self.borrow_writer());
+            try!(progress.count(writer).write_all(limited));
+        }
+        self.remaining -= limited.len() as u64;
+
+        if limited.len() < content.len() {
+            Err(too_long())
+        } else {
+            Ok(())
+        }
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        let mut writer = try!(self.borrow_writer());
-        writer.flush()
+        Raw::flush(self)
     }
 }
 
-fn response_to_io_result<T>(res:  Result<T, Error>) -> io::Result<T> {
-    match res {
-        Ok(v) => Ok(v),
-        Err(Error::Io(e)) => Err(e),
-        Err(e) => Err(io::Error::new(io::ErrorKind::Other, e))
+impl<'a> Drop for Raw<'a> {
+    fn drop(&mut self) {
+        if let (true, Some(Ok(_))) = (self.remaining > 0, self.writer.as_ref()) {
+            self.end_early();
+        }
     }
 }
 
-fn filter_headers<'a>(
-    filters: &'a [Box<ResponseFilter>],
-    status: StatusCode,
-    headers: &mut Headers,
-    global: &Global,
-    filter_storage: &mut AnyMap
-) -> Result<(StatusCode, Vec<Action<'a>>), Error> {
-    let mut write_queue = Vec::new();
-    let mut header_result = (status, Action::Next(None));
+fn too_long() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "the body is longer than Content-Length")
+}
 
-    for filter in filters {
-        header_result = match header_result {
-            (_, Action::SilentAbort) | (_, Action::Abort(_)) => break,
-            (status, r) => {
-                write_queue.push(r);
+#[doc(hidden)]
+///Internal and may change without warning.
+///
+///The status and the number of body bytes that have been sent, and the
+///headers if they are recorded.
+#[derive(Clone, Default)]
+pub struct Progress(Rc<ProgressState>);
+
+#[derive(Default)]
+struct ProgressState {
+    status: Cell<Option<StatusCode>>,
+    headers: RefCell<Option<Headers>>,
+    record_headers: Cell<bool>,
+    bytes_written: Cell<u64>,
+    interrupted: Cell<bool>,
+    timed_out: Cell<bool>,
+    callbacks: RefCell<Vec<Box<FnOnce(&CompletionInfo)>>>,
+    captured_body: RefCell<Option<Vec<u8>>>,
+    capture_limit: Cell<usize>,
+    capture_truncated: Cell<bool>
+}
+
+impl Progress {
+    pub fn status(&self) -> Option<StatusCode> {
+        self.0.status.get()
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.0.bytes_written.get()
+    }
+
+    pub fn record_headers(&self) {
+        self.0.record_headers.set(true);
+    }
+
+    pub fn take_headers(&self) -> Option<Headers> {
+        self.0.headers.borrow_mut().take()
+    }
+
+    pub fn capture_body(&self, limit: usize) {
+        self.0.capture_limit.set(limit);
+        *self.0.captured_body.borrow_mut() = Some(vec![]);
+    }
+
+    pub fn take_captured_body(&self) -> Option<(Vec<u8>, bool)> {
+        self.0.captured_body.borrow_mut().take().map(|body| (body, self.0.capture_truncated.get()))
+    }
+
+    //Copy what fits of the written content. The buffer grows with the body,
+    //so only the bytes that are actually sent are kept.
+    fn capture(&self, content: &[u8]) {
+        if let Some(ref mut captured) = *self.0.captured_body.borrow_mut() {
+            let room = self.0.capture_limit.get().saturating_sub(captured.len());
+            if content.len() > room {
+                self.0.capture_truncated.set(true);
+            }
+            captured.extend_from_slice(&content[..::std::cmp::min(room, content.len())]);
+        }
+    }
+
+    pub fn interrupted(&self) -> bool {
+        self.0.interrupted.get()
+    }
+
+    pub fn timed_out(&self) -> bool {
+        self.0.timed_out.get()
+    }
+
+    pub fn has_callbacks(&self) -> bool {
+        !self.0.callbacks.borrow().is_empty()
+    }
+
+    pub fn take_callbacks(&self) -> Vec<Box<FnOnce(&CompletionInfo)>> {
+        ::std::mem::replace(&mut *self.0.callbacks.borrow_mut(), vec![])
+    }
+
+    fn add_callback(&self, callback: Box<FnOnce(&CompletionInfo)>) {
+        self.record_headers();
+        self.0.callbacks.borrow_mut().push(callback);
+    }
+
+    fn check<T>(&self, result: io::Result<T>) -> io::Result<T> {
+        if let Err(ref e) = result {
+            self.0.interrupted.set(true);
+            if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock {
+                self.0.timed_out.set(true);
+            }
+        }
+        result
+    }
+
+    #[cfg(all(feature = "sendfile", target_os = "linux"))]
+    fn add_written(&self, length: u64) {
+        self.0.bytes_written.set(self.0.bytes_written.get() + length);
+
+        //The content never passes through this process.
+        if length > 0 && self.0.captured_body.borrow().is_some() {
+            self.0.capture_truncated.set(true);
+        }
+    }
+
+    fn set_head(&self, status: StatusCode, headers: &Headers) {
+        self.0.status.set(Some(status));
+        if self.0.record_headers.get() {
+            *self.0.headers.borrow_mut() = Some(headers.clone());
+        }
+    }
+
+    fn count<'w, W: Write>(&self, writer: &'w mut W) -> CountingWriter<'w, W> {
+        CountingWriter {
+            writer: writer,
+            progress: self.clone()
+        }
+    }
+}
+
+#[doc(hidden)]
+///Internal and may change without warning.
+///
+///A custom reason phrase for a status code, shared between a response and
+///the `StatusLine` it writes through.
+#[derive(Clone)]
+pub struct ReasonPhrase(Rc<RefCell<Option<(u16, Vec<u8>)>>>);
+
+#[doc(hidden)]
+///Internal and may change without warning.
+///
+///Replaces the reason phrase in the status line of the final response, if
+///its code is the one that the phrase belongs to. Interim responses, like
+///those from `send_early_hints`, are passed through. Everything after the
+///final status line is passed through as well.
+pub struct StatusLine<W> {
+    writer: W,
+    phrase: ReasonPhrase,
+    state: LineState
+}
+
+enum LineState {
+    //Collecting a status line.
+    Status(Vec<u8>),
+
+    //Passing an interim response through, with the number of bytes of
+    //`\r\n\r\n` that have been seen so far. The end of the status line
+    //counts.
+    Interim(usize),
+
+    Done
+}
+
+impl<W: Write> StatusLine<W> {
+    ///Wrap a writer. No phrase is replaced until one is set through the
+    ///`ReasonPhrase`.
+    pub fn new(writer: W) -> StatusLine<W> {
+        StatusLine {
+            writer: writer,
+            phrase: ReasonPhrase(Rc::new(RefCell::new(None))),
+            state: LineState::Status(vec![])
+        }
+    }
+
+    ///Get the phrase that is written in the status line.
+    pub fn reason_phrase(&self) -> ReasonPhrase {
+        self.phrase.clone()
+    }
+
+    fn write_status(&mut self, line: &[u8]) -> io::Result<()> {
+        let mut parts = line.splitn(3, |&b| b == b' ');
+        let version = parts.next().unwrap_or(&[]);
+        let code = parts.next().and_then(|code| from_utf8(code).ok()).and_then(|code| code.parse::<u16>().ok());
+
+        match (code, &*self.phrase.0.borrow()) {
+            (Some(code), &Some((expected, ref phrase))) if code == expected => {
+                self.state = LineState::Done;
+                try!(self.writer.write_all(version));
+                try!(write!(self.writer, " {} ", code));
+                try!(self.writer.write_all(phrase));
+                return self.writer.write_all(b"\r\n");
+            },
+            (Some(code), _) if code >= 100 && code < 200 => self.state = LineState::Interim(2),
+            _ => self.state = LineState::Done
+        }
+
+        self.writer.write_all(line).and_then(|_| self.writer.write_all(b"\r\n"))
+    }
+}
+
+impl<W: Write> Write for StatusLine<W> {
+    fn write(&mut self, content: &[u8]) -> io::Result<usize> {
+        let mut rest = content;
+
+        while !rest.is_empty() {
+            match self.state {
+                LineState::Status(ref mut line) => match rest.iter().position(|&b| b == b'\n') {
+                    Some(end) => {
+                        line.extend_from_slice(&rest[..end + 1]);
+                        rest = &rest[end + 1..];
+                    },
+                    None => {
+                        line.extend_from_slice(rest);
+                        return Ok(content.len());
+                    }
+                },
+                LineState::Interim(ref mut seen) => {
+                    let mut end = rest.len();
+                    for (i, &b) in rest.iter().enumerate() {
+                        *seen = match (*seen, b) {
+                            (0, b'\r') | (2, b'\r') => *seen + 1,
+                            (1, b'\n') => 2,
+                            (3, b'\n') => 4,
+                            (_, b'\r') => 1,
+                            _ => 0
+                        };
+
+                        if *seen == 4 {
+                            end = i + 1;
+                            break;
+                        }
+                    }
+
+                    try!(self.writer.write_all(&rest[..end]));
+                    rest = &rest[end..];
+                },
+                LineState::Done => {
+                    try!(self.writer.write_all(rest));
+                    return Ok(content.len());
+                }
+            }
+
+            //Either the line or the interim response is complete.
+            let line = match self.state {
+                LineState::Status(ref mut line) => Some(::std::mem::replace(line, vec![])),
+                LineState::Interim(4) => None,
+                _ => continue
+            };
+
+            match line {
+                Some(line) => {
+                    let line = if line.ends_with(b"\r\n") {
+                        &line[..line.len() - 2]
+                    } else {
+                        &line[..line.len() - 1]
+                    };
+                    try!(self.write_status(line));
+                },
+                None => self.state = LineState::Status(vec![])
+            }
+        }
+
+        Ok(content.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+//Counts what's successfully written to the inner writer.
+//Headers that are removed by `Response::proxy`, in addition to those that
+//are listed in the `Connection` header.
+const HOP_BY_HOP_HEADERS: &'static [&'static str] = &[
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade"
+];
+
+//Flushes after a write if it has been at least `interval` since the last
+//time, or if nothing has been flushed yet.
+struct PeriodicFlush<W: Write> {
+    writer: W,
+    interval: Duration,
+    last_flush: Option<Instant>
+}
+
+impl<W: Write> Write for PeriodicFlush<W> {
+    fn write(&mut self, content: &[u8]) -> io::Result<usize> {
+        let written = try!(self.writer.write(content));
+
+        if self.last_flush.map_or(true, |last_flush| last_flush.elapsed() >= self.interval) {
+            try!(self.flush());
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = Some(Instant::now());
+        self.writer.flush()
+    }
+}
+
+struct CountingWriter<'w, W: 'w> {
+    writer: &'w mut W,
+    progress: Progress
+}
+
+impl<'w, W: Write> Write for CountingWriter<'w, W> {
+    fn write(&mut self, content: &[u8]) -> io::Result<usize> {
+        let length = try!(self.progress.check(self.writer.write(content)));
+        let state = &self.progress.0;
+        state.bytes_written.set(state.bytes_written.get() + length as u64);
+        self.progress.capture(&content[..length]);
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.progress.check(self.writer.flush())
+    }
+}
+
+///A handle to the output of a buffered response.
+///
+///It's created together with a `Response`, using `Response::buffered`, and
+///can be used to inspect what was written after the response is done, even
+///if the `ResponseBuffer` is gone.
+#[derive(Clone)]
+pub struct BufferHandle(Arc<Mutex<Vec<u8>>>);
+
+impl BufferHandle {
+    ///Get a copy of everything that has been written so far, including the
+    ///status line and the headers.
+    pub fn bytes(&self) -> Vec<u8> {
+        self.0.lock().expect("response buffer poisoned").clone()
+    }
+
+    ///Get the status code, if the headers have been written.
+    pub fn status(&self) -> Option<StatusCode> {
+        self.parts().map(|(status, _, _)| status)
+    }
+
+    ///Get the headers, if they have been written.
+    pub fn headers(&self) -> Option<Headers> {
+        self.parts().map(|(_, headers, _)| headers)
+    }
+
+    ///Get the body, if the headers have been written. A chunked body will be
+    ///decoded, so only the actual content is returned.
+    pub fn body(&self) -> Option<Vec<u8>> {
+        self.parts().map(|(_, _, body)| body)
+    }
+
+    fn parts(&self) -> Option<(StatusCode, Headers, Vec<u8>)> {
+        let bytes = self.bytes();
+        let head_end = match bytes.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(index) => index,
+            None => return None
+        };
+
+        let head = match from_utf8(&bytes[..head_end]) {
+            Ok(head) => head,
+            Err(_) => return None
+        };
+        let mut lines = head.split("\r\n");
+
+        let status = match lines.next().and_then(|line| line.split(' ').nth(1)).and_then(|code| code.parse().ok()) {
+            Some(code) => StatusCode::from_u16(code),
+            None => return None
+        };
+
+        let mut raw_headers: Vec<(String, Vec<Vec<u8>>)> = vec![];
+        for line in lines {
+            if let Some(index) = line.find(':') {
+                let name = &line[..index];
+                let value = line[index + 1..].trim().as_bytes().to_owned();
+                match raw_headers.iter_mut().position(|&mut (ref n, _)| n.eq_ignore_ascii_case(name)) {
+                    Some(i) => raw_headers[i].1.push(value),
+                    None => raw_headers.push((name.to_owned(), vec![value]))
+                }
+            }
+        }
+
+        let mut headers = Headers::new();
+        for (name, values) in raw_headers {
+            headers.set_raw(name, values);
+        }
+
+        let body = &bytes[head_end + 4..];
+        let chunked = headers.get::<TransferEncoding>().map_or(false, |encodings| encodings.contains(&Encoding::Chunked));
+        let body = if chunked {
+            decode_chunked(body)
+        } else {
+            body.to_owned()
+        };
+
+        Some((status, headers, body))
+    }
+}
+
+fn decode_chunked(mut source: &[u8]) -> Vec<u8> {
+    let mut body = vec![];
+
+    while let Some(line_end) = source.windows(2).position(|w| w == b"\r\n") {
+        let size = from_utf8(&source[..line_end]).ok()
+            .and_then(|line| usize::from_str_radix(line.split(';').next().unwrap_or("").trim(), 16).ok());
+        let size = match size {
+            Some(0) | None => break,
+            Some(size) => size
+        };
+
+        source = &source[line_end + 2..];
+        let size = std::cmp::min(size, source.len());
+        body.extend_from_slice(&source[..size]);
+        source = &source[std::cmp::min(size + 2, source.len())..];
+    }
+
+    body
+}
+
+///The output stream, headers and global data that a buffered response
+///borrows. See `Response::buffered`.
+pub struct ResponseBuffer {
+    output: StatusLine<BufferWriter>,
+    headers: Headers,
+    global: Global
+}
+
+impl ResponseBuffer {
+    ///Create an empty buffer.
+    pub fn new() -> ResponseBuffer {
+        ResponseBuffer::default()
+    }
+}
+
+#[cfg(test)]
+impl ResponseBuffer {
+    //A buffered response with `filters` as its response filters.
+    pub fn filtered<'a>(&'a mut self, filters: &'a [Box<ResponseFilter>]) -> (Response<'a, 'a>, BufferHandle) {
+        let (mut response, output) = Response::buffered(self);
+        response.filters.global = filters;
+        (response, output)
+    }
+}
+
+impl Default for ResponseBuffer {
+    fn default() -> ResponseBuffer {
+        ResponseBuffer {
+            output: StatusLine::new(BufferWriter(Arc::new(Mutex::new(Vec::new())))),
+            headers: Headers::new(),
+            global: Global::default()
+        }
+    }
+}
+
+struct BufferWriter(Arc<Mutex<Vec<u8>>>);
+
+impl Write for BufferWriter {
+    fn write(&mut self, content: &[u8]) -> io::Result<usize> {
+        self.0.lock().expect("response buffer poisoned").extend_from_slice(content);
+        Ok(content.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+//Check that `link` is a `Link` header value, with a URI reference in angle
+//brackets, followed by parameters without control characters.
+fn check_hint_link(link: &str) -> Result<(), Error> {
+    let valid = link.starts_with('<') && match link.find('>') {
+        Some(end) => {
+            let parameters = link[end + 1..].trim_start_matches(|c| c == ' ' || c == '\t');
+            ::filter::deprecation::check_link(&link[1..end]).is_ok()
+                && (parameters.is_empty() || parameters.starts_with(';'))
+                && parameters.bytes().all(|byte| byte == b'\t' || (byte >= 0x20 && byte != 0x7f))
+        },
+        None => false
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput, "the link is not a valid Link header value")))
+    }
+}
+
+fn response_to_io_result<T>(res:  Result<T, Error>) -> io::Result<T> {
+    match res {
+        Ok(v) => Ok(v),
+        Err(Error::Io(e)) => Err(e),
+        Err(e) => Err(io::Error::new(io::ErrorKind::Other, e))
+    }
+}
+
+const DEFAULT_STREAM_BUFFER_SIZE: usize = 8 * 1024;
+
+//Like `io::copy`, but with a custom buffer size.
+#[cfg(all(feature = "sendfile", target_os = "linux"))]
+fn copy_buffered<R: Read, W: Write>(reader: &mut R, writer: &mut W, buffer_size: usize) -> io::Result<()> {
+    let mut buffer = vec![0; ::std::cmp::max(buffer_size, 1)];
+
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(read) => read,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e)
+        };
+        try!(writer.write_all(&buffer[..read]));
+    }
+}
+
+//The response filters of the route, if any, followed by the global ones.
+#[derive(Clone)]
+struct FilterChain<'b> {
+    global: &'b [Box<ResponseFilter>],
+    //The innermost route filters are pushed last.
+    route: Vec<Arc<RouteResponseFilters>>
+}
+
+impl<'b> FilterChain<'b> {
+    fn new(global: &'b [Box<ResponseFilter>]) -> FilterChain<'b> {
+        FilterChain {
+            global: global,
+            route: vec![]
+        }
+    }
+
+    fn iter<'s>(&'s self) -> impl DoubleEndedIterator<Item = &'s Box<ResponseFilter>> + Clone + 's {
+        let route = &self.route;
+
+        let global = self.global.iter()
+            .enumerate()
+            .filter(move |&(index, _)| !route.iter().any(|route| route.skip_global.contains(&index)))
+            .map(|(_, filter)| filter);
+
+        route.iter().rev().flat_map(|route| route.filters.iter()).chain(global)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+}
+
+fn filter_headers<'a>(
+    filters: &'a FilterChain,
+    status: StatusCode,
+    headers: &mut Headers,
+    global: &Global,
+    filter_storage: &mut AnyMap
+) -> Result<(StatusCode, Vec<Action<'a>>), Error> {
+    let mut write_queue = Vec::new();
+    let mut header_result = (status, Action::Next(None));
+
+    for filter in filters.iter() {
+        header_result = match header_result {
+            (_, Action::SilentAbort) | (_, Action::Abort(_)) => break,
+            (status, r) => {
+                write_queue.push(r);
 
                 let filter_res = {
                     let filter_context = FilterContext {
@@ -941,17 +3545,117 @@ fn filter_headers<'a>(
 
     match header_result {
         (_, Action::Abort(e)) => Err(Error::Filter(e)),
-        (status, action) => {
+        (mut status, action) => {
             write_queue.push(action);
+            finalize_headers(filters, &mut status, headers, global, filter_storage);
             Ok((status, write_queue))
         }
     }
 }
 
-fn filter_content<'a, 'd: 'a, Content: Into<Data<'d>>>(filters: &'a [Box<ResponseFilter>], content: Content, global: &Global, filter_storage: &mut AnyMap) -> Action<'a> {
+fn finalize_headers(
+    filters: &FilterChain,
+    status: &mut StatusCode,
+    headers: &mut Headers,
+    global: &Global,
+    filter_storage: &mut AnyMap
+) {
+    for filter in filters.iter() {
+        let filter_context = FilterContext {
+            storage: filter_storage,
+            global: global,
+        };
+        filter.finalize(filter_context, status, headers);
+    }
+}
+
+fn send_body(mut writer: hyper::server::response::Response, body: &[u8], progress: &Progress) -> io::Result<()> {
+    writer.headers_mut().set(::header::ContentLength(body.len() as u64));
+    progress.set_head(writer.status(), writer.headers());
+    let mut writer = try!(writer.start());
+    try!(progress.count(&mut writer).write_all(body));
+    progress.check(writer.end())
+}
+
+fn write_wrapped<W: Write>(wrapper: &mut Option<BodyWrapper>, writer: &mut W, content: &[u8]) -> io::Result<()> {
+    match *wrapper {
+        Some(ref mut wrapper) => {
+            let output = try!(wrapper.write(content));
+            if output.is_empty() {
+                Ok(())
+            } else {
+                writer.write_all(&output)
+            }
+        },
+        None => writer.write_all(content)
+    }
+}
+
+//The body writers from the response filters, stacked on top of each other.
+struct BodyWrapper {
+    writer: Option<Box<Write>>,
+    output: Rc<RefCell<Vec<u8>>>
+}
+
+impl BodyWrapper {
+    fn new(filters: &FilterChain, global: &Global, filter_storage: &mut AnyMap) -> BodyWrapper {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut writer: Box<Write> = Box::new(WrapperOutput(output.clone()));
+
+        for filter in filters.iter().rev() {
+            let filter_context = FilterContext {
+                storage: filter_storage,
+                global: global,
+            };
+            writer = filter.wrap_body(filter_context, writer);
+        }
+
+        BodyWrapper {
+            writer: Some(writer),
+            output: output
+        }
+    }
+
+    //Write through the wrappers and collect what comes out on the other side.
+    fn write(&mut self, content: &[u8]) -> io::Result<Vec<u8>> {
+        if let Some(ref mut writer) = self.writer {
+            try!(writer.write_all(content));
+        }
+        Ok(::std::mem::replace(&mut *self.output.borrow_mut(), Vec::new()))
+    }
+
+    //Flush the wrappers and collect what comes out on the other side.
+    fn flush(&mut self) -> io::Result<Vec<u8>> {
+        if let Some(ref mut writer) = self.writer {
+            try!(writer.flush());
+        }
+        Ok(::std::mem::replace(&mut *self.output.borrow_mut(), Vec::new()))
+    }
+
+    //Drop the wrappers and collect their final output.
+    fn finish(&mut self) -> Vec<u8> {
+        self.writer.take();
+        ::std::mem::replace(&mut *self.output.borrow_mut(), Vec::new())
+    }
+}
+
+struct WrapperOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for WrapperOutput {
+    fn write(&mut self, content: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(content);
+        Ok(content.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn filter_content<'a, 'd: 'a, Content: Into<Data<'d>>>(filters: &'a FilterChain, content: Content, global: &Global, filter_storage: &mut AnyMap) -> Action<'a> {
     let mut filter_result = Action::next(Some(content));
 
-    for filter in filters {
+    for filter in filters.iter() {
         filter_result = match filter_result {
             Action::Next(content) => {
                 let filter_context = FilterContext {
@@ -967,8 +3671,8 @@ fn filter_content<'a, 'd: 'a, Content: Into<Data<'d>>>(filters: &'a [Box<Respons
     filter_result
 }
 
-fn filter_end<'a>(filters: &'a [Box<ResponseFilter>], global: &Global, filter_storage: &mut AnyMap) -> Result<Vec<Action<'a>>, Error> {
-    let otuputs: Vec<_> = filters.into_iter()
+fn filter_end<'a>(filters: &'a FilterChain, global: &Global, filter_storage: &mut AnyMap) -> Result<Vec<Action<'a>>, Error> {
+    let otuputs: Vec<_> = filters.iter()
         .rev()
         .map(|filter| {
             let filter_context = FilterContext {
@@ -984,7 +3688,7 @@ fn filter_end<'a>(filters: &'a [Box<ResponseFilter>], global: &Global, filter_st
 
     let mut write_queue = vec![];
 
-    for (filter, action) in filters.into_iter().zip(otuputs.into_iter().chain(::std::iter::repeat(None))) {
+    for (filter, action) in filters.iter().zip(otuputs.into_iter().chain(::std::iter::repeat(None))) {
         let mut error = None;
 
         write_queue = write_queue.into_iter().filter_map(|action| match action {
@@ -1013,3 +3717,707 @@ fn filter_end<'a>(filters: &'a [Box<ResponseFilter>], global: &Global, filter_st
 
     Ok(write_queue)
 }
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read, Write, Seek, SeekFrom};
+    use header::{Headers, ContentLength, ContentType, TransferEncoding, Encoding};
+    use filter::{FilterContext, ResponseFilter, ResponseAction as Action};
+    use StatusCode;
+    use super::{Response, ResponseBuffer, Data};
+
+    #[test]
+    fn buffered_sized() {
+        let mut buffer = ResponseBuffer::new();
+        let (mut response, output) = Response::buffered(&mut buffer);
+        response.set_status(StatusCode::Created);
+        response.headers_mut().set(ContentType(content_type!(Text / Plain)));
+        response.send("hello");
+
+        let headers = output.headers().unwrap();
+        assert_eq!(output.status(), Some(StatusCode::Created));
+        assert_eq!(headers.get(), Some(&ContentLength(5)));
+        assert_eq!(headers.get(), Some(&ContentType(content_type!(Text / Plain))));
+        assert_eq!(output.body(), Some(b"hello".to_vec()));
+    }
+
+    #[test]
+    fn buffered_chunked() {
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        {
+            let mut chunked = response.into_chunked();
+            chunked.write_all(b"hello, ").unwrap();
+            assert_eq!(output.status(), Some(StatusCode::Ok));
+            chunked.write_all(b"world").unwrap();
+        }
+
+        assert_eq!(output.body(), Some(b"hello, world".to_vec()));
+    }
+
+    #[test]
+    fn mark_deprecated() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let mut buffer = ResponseBuffer::new();
+        let (mut response, output) = Response::buffered(&mut buffer);
+        response.headers_mut().set_raw("Link", vec![b"</v2/users>; rel=\"successor-version\"".to_vec()]);
+        assert!(response.mark_deprecated(None, Some("/docs/v2 migration")).is_err());
+
+        let sunset = UNIX_EPOCH + Duration::from_secs(1893456000);
+        response.mark_deprecated(Some(sunset), Some("https://example.com/docs/v2")).unwrap();
+        response.send("[]");
+
+        let headers = output.headers().unwrap();
+        assert_eq!(headers.get_raw("Deprecation"), Some(&[b"true".to_vec()][..]));
+        assert_eq!(headers.get_raw("Sunset"), Some(&[b"Tue, 01 Jan 2030 00:00:00 GMT".to_vec()][..]));
+        assert_eq!(headers.get_raw("Link"), Some(&[
+            b"</v2/users>; rel=\"successor-version\"".to_vec(),
+            b"<https://example.com/docs/v2>; rel=\"deprecation\"".to_vec()
+        ][..]));
+    }
+
+    #[test]
+    fn buffered_nothing_sent() {
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        assert_eq!(output.status(), None);
+        drop(response);
+
+        assert_eq!(output.status(), Some(StatusCode::Ok));
+        assert_eq!(output.body(), Some(vec![]));
+    }
+
+    struct Upper(Box<Write>);
+
+    impl Write for Upper {
+        fn write(&mut self, content: &[u8]) -> io::Result<usize> {
+            self.0.write_all(&content.to_ascii_uppercase()).map(|_| content.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    impl Drop for Upper {
+        fn drop(&mut self) {
+            self.0.write_all(b"!").unwrap();
+        }
+    }
+
+    struct Finalizer;
+
+    impl ResponseFilter for Finalizer {
+        fn begin(&self, _ctx: FilterContext, status: StatusCode, headers: &mut Headers) -> (StatusCode, Action) {
+            headers.set(ContentType(content_type!(Text / Html)));
+            (status, Action::next(Some("a")))
+        }
+
+        fn write<'a>(&'a self, _ctx: FilterContext, content: Option<Data<'a>>) -> Action {
+            Action::next(content)
+        }
+
+        fn end(&self, _ctx: FilterContext) -> Action {
+            Action::next(Some("z"))
+        }
+
+        fn finalize(&self, _ctx: FilterContext, status: &mut StatusCode, headers: &mut Headers) {
+            if headers.has::<ContentType>() {
+                *status = StatusCode::Accepted;
+            }
+        }
+
+        fn wrap_body(&self, _ctx: FilterContext, writer: Box<Write>) -> Box<Write> {
+            Box::new(Upper(writer))
+        }
+    }
+
+    #[test]
+    fn filter_finalize_and_wrap_sized() {
+        let filters: Vec<Box<ResponseFilter>> = vec![Box::new(Finalizer)];
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = buffer.filtered(&filters);
+        response.send("hello");
+
+        assert_eq!(output.status(), Some(StatusCode::Accepted));
+        assert_eq!(output.headers().unwrap().get(), Some(&ContentLength(8)));
+        assert_eq!(output.body(), Some(b"AHELLOZ!".to_vec()));
+    }
+
+    #[test]
+    fn filter_finalize_and_wrap_chunked() {
+        let filters: Vec<Box<ResponseFilter>> = vec![Box::new(Finalizer)];
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = buffer.filtered(&filters);
+        {
+            let mut chunked = response.into_chunked();
+            chunked.send("hello, ");
+            chunked.send("world");
+        }
+
+        assert_eq!(output.status(), Some(StatusCode::Accepted));
+        assert_eq!(output.body(), Some(b"AHELLO, WORLDZ!".to_vec()));
+    }
+
+    #[test]
+    fn count_written_bytes() {
+        let filters: Vec<Box<ResponseFilter>> = vec![Box::new(Finalizer)];
+        let mut buffer = ResponseBuffer::new();
+        let (response, _output) = buffer.filtered(&filters);
+        let progress = response.progress();
+        {
+            let mut chunked = response.into_chunked();
+            assert_eq!(chunked.bytes_written(), 1);
+            chunked.send("hello");
+            assert_eq!(chunked.bytes_written(), 6);
+        }
+        assert_eq!(progress.status(), Some(StatusCode::Accepted));
+        assert_eq!(progress.bytes_written(), 8);
+
+        let mut buffer = ResponseBuffer::new();
+        let (response, _output) = Response::buffered(&mut buffer);
+        let mut raw = unsafe { response.into_raw(5) };
+        raw.send("hello");
+        assert_eq!(raw.bytes_written(), 5);
+    }
+
+    #[test]
+    fn filter_finalize_raw() {
+        let filters: Vec<Box<ResponseFilter>> = vec![Box::new(Finalizer)];
+        let mut buffer = ResponseBuffer::new();
+        let (mut response, output) = buffer.filtered(&filters);
+        response.headers_mut().set(ContentType(content_type!(Text / Plain)));
+        {
+            let mut raw = unsafe { response.into_raw(5) };
+            raw.send("hello");
+        }
+
+        assert_eq!(output.status(), Some(StatusCode::Accepted));
+        assert_eq!(output.body(), Some(b"hello".to_vec()));
+    }
+
+    #[test]
+    fn send_from_reader() {
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        response.send_reader(io::Cursor::new(b"hello, world"), Some(5)).unwrap();
+        assert_eq!(output.headers().unwrap().get(), Some(&ContentLength(5)));
+        assert_eq!(output.body(), Some(b"hello".to_vec()));
+
+        let content: Vec<u8> = (0..20000).map(|i| i as u8).collect();
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        response.send_reader(&content[..], None).unwrap();
+        assert_eq!(output.headers().unwrap().get::<ContentLength>(), None);
+        assert_eq!(output.body(), Some(content));
+
+        let mut buffer = ResponseBuffer::new();
+        let (response, _output) = Response::buffered(&mut buffer);
+        assert!(response.send_reader(&b"hello"[..], Some(10)).is_err());
+    }
+
+    #[test]
+    fn stream_buffer_size() {
+        let mut buffer = ResponseBuffer::new();
+        let (mut response, output) = Response::buffered(&mut buffer);
+        response.set_stream_buffer_size(4);
+        response.send_reader(&b"hello, world"[..], None).unwrap();
+        assert!(output.bytes().ends_with(b"4\r\nhell\r\n4\r\no, w\r\n4\r\norld\r\n0\r\n\r\n"));
+
+        let mut buffer = ResponseBuffer::new();
+        let (mut response, output) = Response::buffered(&mut buffer);
+        response.set_stream_buffer_size(0);
+        response.send_reader(io::Cursor::new(b"hello"), Some(5)).unwrap();
+        assert_eq!(output.body(), Some(b"hello".to_vec()));
+    }
+
+    #[test]
+    fn auto_content_length() {
+        let mut buffer = ResponseBuffer::new();
+        let (mut response, output) = Response::buffered(&mut buffer);
+        response.set_auto_content_length(8);
+        response.send_reader(&b"hello"[..], None).unwrap();
+        assert_eq!(output.headers().unwrap().get_raw("Content-Length"), Some(&[b"5".to_vec()][..]));
+        assert_eq!(output.body(), Some(b"hello".to_vec()));
+
+        let mut buffer = ResponseBuffer::new();
+        let (mut response, output) = Response::buffered(&mut buffer);
+        response.set_auto_content_length(8);
+        let mut chunked = response.into_chunked();
+        chunked.send("hello");
+        chunked.abort();
+        assert!(output.bytes().ends_with(b"Transfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n"));
+    }
+
+    #[test]
+    fn custom_reason() {
+        let mut buffer = ResponseBuffer::new();
+        let (mut response, output) = Response::buffered(&mut buffer);
+        assert!(response.set_status_with_reason(99, "Too Low").is_err());
+        assert!(response.set_status_with_reason(600, "Too High").is_err());
+        assert!(response.set_status_with_reason(419, "Session\r\nExpired").is_err());
+        response.set_status_with_reason(419, "Session\tExpired").unwrap();
+        response.send_early_hints(&["</a.css>; rel=preload"]).unwrap();
+        response.set_status_with_reason(419, "Session Expired").unwrap();
+        response.send("HTTP/1.1 419 body");
+        let bytes = String::from_utf8(output.bytes()).unwrap();
+        assert!(bytes.starts_with("HTTP/1.1 103 Early Hints\r\nLink: </a.css>; rel=preload\r\n\r\nHTTP/1.1 419 Session Expired\r\n"), "{}", bytes);
+        assert!(bytes.ends_with("\r\n\r\nHTTP/1.1 419 body"), "{}", bytes);
+
+        let mut buffer = ResponseBuffer::new();
+        let (mut response, output) = Response::buffered(&mut buffer);
+        response.set_status_with_reason(404, "Gone Fishing").unwrap();
+        response.set_status(StatusCode::NotFound);
+        response.send("");
+        assert!(output.bytes().starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn send_from_channel() {
+        use std::sync::mpsc::channel;
+        use std::thread;
+
+        let (sender, receiver) = channel();
+        let producer = thread::spawn(move || {
+            for chunk in &["one", "two", "three"] {
+                sender.send(chunk.as_bytes().to_vec()).unwrap();
+            }
+        });
+
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        response.send_channel(receiver).unwrap();
+        producer.join().unwrap();
+
+        assert_eq!(output.headers().unwrap().get::<ContentLength>(), None);
+        assert_eq!(output.body(), Some(b"onetwothree".to_vec()));
+        assert!(output.bytes().ends_with(b"3\r\none\r\n3\r\ntwo\r\n5\r\nthree\r\n0\r\n\r\n"));
+    }
+
+    struct Broken;
+
+    impl Read for Broken {
+        fn read(&mut self, _buffer: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::Other, "broken"))
+        }
+    }
+
+    #[test]
+    fn abort_broken_reader() {
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        assert!(response.send_reader(Broken, None).is_err());
+        assert_eq!(output.status(), Some(StatusCode::Ok));
+        assert!(!output.bytes().ends_with(b"0\r\n\r\n"));
+    }
+
+    #[test]
+    fn multipart_stream() {
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        let boundary = {
+            let mut multipart = response.into_multipart();
+
+            let mut headers = Headers::new();
+            headers.set(ContentType(content_type!(Text / Plain)));
+            assert_eq!(multipart.send_part(&headers, io::Cursor::new("first part")).unwrap(), 10);
+
+            let mut headers = Headers::new();
+            headers.set_raw("Content-Disposition", vec![b"attachment; filename=\"b.csv\"".to_vec()]);
+            assert_eq!(multipart.send_part(&headers, io::Cursor::new("a,b\r\n1,2")).unwrap(), 8);
+
+            let boundary = multipart.boundary().to_owned();
+            multipart.end().unwrap();
+            boundary
+        };
+
+        let headers = output.headers().unwrap();
+        let content_type = headers.get_raw("Content-Type").and_then(|values| values.first()).unwrap();
+        assert_eq!(*content_type, format!("multipart/mixed; boundary={}", boundary).into_bytes());
+
+        let body = String::from_utf8(output.body().unwrap()).unwrap();
+        let delimiter = format!("--{}", boundary);
+        let sections: Vec<_> = body.split(&*delimiter).collect();
+        assert_eq!(sections.len(), 4, "{:?}", body);
+        assert_eq!(sections[0], "");
+        assert_eq!(sections[3], "--\r\n");
+
+        let parts: Vec<_> = sections[1..3].iter().map(|section| {
+            assert!(section.starts_with("\r\n") && section.ends_with("\r\n"), "{:?}", section);
+            let section = &section[2..section.len() - 2];
+            let split = section.find("\r\n\r\n").unwrap();
+            (&section[..split], &section[split + 4..])
+        }).collect();
+
+        assert_eq!(parts[0], ("Content-Type: text/plain", "first part"));
+        assert_eq!(parts[1], ("Content-Disposition: attachment; filename=\"b.csv\"", "a,b\r\n1,2"));
+    }
+
+    #[test]
+    fn abort_multipart_stream() {
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        {
+            let mut multipart = response.into_multipart();
+            assert!(multipart.send_part(&Headers::new(), io::Cursor::new("complete")).is_ok());
+            assert!(multipart.send_part(&Headers::new(), Broken).is_err());
+            assert!(multipart.send_part(&Headers::new(), io::Cursor::new("ignored")).is_err());
+        }
+
+        let body = output.bytes();
+        assert!(!body.ends_with(b"0\r\n\r\n"));
+        assert!(!String::from_utf8_lossy(&body).contains("ignored"));
+    }
+
+    #[test]
+    fn send_with_closure() {
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        response.send_with(|body| {
+            try!(body.write_all(b"hello, "));
+            body.write_all(b"world")
+        }).unwrap();
+        assert_eq!(output.status(), Some(StatusCode::Ok));
+        assert_eq!(output.body(), Some(b"hello, world".to_vec()));
+
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        let result = response.send_with(|body| {
+            try!(body.write_all(b"hello"));
+            Err(io::Error::new(io::ErrorKind::Other, "broken"))
+        });
+        assert!(result.is_err());
+        assert!(!output.bytes().ends_with(b"0\r\n\r\n"));
+    }
+
+    #[test]
+    fn proxy_response() {
+        let mut upstream = Headers::new();
+        upstream.set_raw("Connection", vec![b"keep-alive, X-Secret".to_vec()]);
+        upstream.set_raw("Keep-Alive", vec![b"timeout=5".to_vec()]);
+        upstream.set_raw("X-Secret", vec![b"42".to_vec()]);
+        upstream.set_raw("X-Forwarded", vec![b"a".to_vec(), b"b".to_vec()]);
+        upstream.set(ContentType(content_type!(Application / Json)));
+        upstream.set(ContentLength(5));
+
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        response.proxy(StatusCode::Created, &upstream, io::Cursor::new(b"hello, world")).unwrap();
+
+        let headers = output.headers().unwrap();
+        assert_eq!(output.status(), Some(StatusCode::Created));
+        assert_eq!(headers.get(), Some(&ContentType(content_type!(Application / Json))));
+        assert_eq!(headers.get_raw("X-Forwarded"), Some(&[b"a".to_vec(), b"b".to_vec()][..]));
+        assert!(headers.get_raw("Keep-Alive").is_none());
+        assert!(headers.get_raw("X-Secret").is_none());
+        assert!(headers.get_raw("Connection").is_none());
+        assert_eq!(output.body(), Some(b"hello".to_vec()));
+
+        let mut upstream = Headers::new();
+        upstream.set(TransferEncoding(vec![Encoding::Chunked]));
+
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        response.proxy(StatusCode::Ok, &upstream, &b"hello, world"[..]).unwrap();
+        assert_eq!(output.headers().unwrap().get::<ContentLength>(), None);
+        assert_eq!(output.body(), Some(b"hello, world".to_vec()));
+    }
+
+    #[test]
+    fn response_from_parts() {
+        let mut upstream = Headers::new();
+        upstream.set_raw("Connection", vec![b"X-Hop".to_vec()]);
+        upstream.set_raw("X-Hop", vec![b"1".to_vec()]);
+        upstream.set_raw("Upgrade", vec![b"websocket".to_vec()]);
+        upstream.set_raw("Content-Encoding", vec![b"gzip".to_vec()]);
+        upstream.set_raw("ETag", vec![b"\"abc\"".to_vec()]);
+        upstream.set(ContentLength(100));
+
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        let mut response: Response = response;
+        response.headers_mut().set_raw("ETag", vec![b"\"old\"".to_vec()]);
+        response.from_parts(StatusCode::NotFound, &upstream).send(&b"missing"[..]);
+
+        let headers = output.headers().unwrap();
+        assert_eq!(output.status(), Some(StatusCode::NotFound));
+        assert_eq!(headers.get_raw("Content-Encoding"), Some(&[b"gzip".to_vec()][..]));
+        assert_eq!(headers.get_raw("ETag"), Some(&[b"\"abc\"".to_vec()][..]));
+        assert_eq!(headers.get::<ContentLength>(), Some(&ContentLength(7)));
+        assert!(headers.get_raw("X-Hop").is_none());
+        assert!(headers.get_raw("Upgrade").is_none());
+        assert!(headers.get_raw("Connection").is_none());
+        assert_eq!(output.body(), Some(b"missing".to_vec()));
+    }
+
+    #[test]
+    fn set_many_headers() {
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        let mut response: Response = response;
+        response.headers_mut().set_raw("X-Old", vec![b"old".to_vec()]);
+        response.headers_mut().set_raw("X-Kept", vec![b"kept".to_vec()]);
+        response.set_headers(vec![
+            ("x-old", b"new".to_vec()),
+            ("X-Multi", b"a".to_vec()),
+            ("X-MULTI", b"b".to_vec())
+        ]);
+        response.send("");
+
+        let headers = output.headers().unwrap();
+        assert_eq!(headers.get_raw("X-Old"), Some(&[b"new".to_vec()][..]));
+        assert_eq!(headers.get_raw("X-Kept"), Some(&[b"kept".to_vec()][..]));
+        assert_eq!(headers.get_raw("X-Multi"), Some(&[b"a".to_vec(), b"b".to_vec()][..]));
+
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        let mut response: Response = response;
+        response.headers_mut().set_raw("X-Old", vec![b"old".to_vec()]);
+        response.replace_headers(Some(("X-New", "new")));
+        response.send("");
+
+        let headers = output.headers().unwrap();
+        assert!(headers.get_raw("X-Old").is_none());
+        assert_eq!(headers.get_raw("X-New"), Some(&[b"new".to_vec()][..]));
+    }
+
+    #[test]
+    fn content_range() {
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        let mut response: Response = response;
+        response.set_content_range(2, 4, Some(10)).unwrap();
+        response.send("cde");
+
+        assert_eq!(output.status(), Some(StatusCode::PartialContent));
+        assert_eq!(output.headers().unwrap().get_raw("Content-Range"), Some(&[b"bytes 2-4/10".to_vec()][..]));
+
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        let mut response: Response = response;
+        response.set_content_range(0, 0, None).unwrap();
+        response.send("a");
+
+        assert_eq!(output.status(), Some(StatusCode::PartialContent));
+        assert_eq!(output.headers().unwrap().get_raw("Content-Range"), Some(&[b"bytes 0-0/*".to_vec()][..]));
+    }
+
+    #[test]
+    fn invalid_content_range() {
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        let mut response: Response = response;
+        assert!(response.set_content_range(5, 4, None).is_err());
+        assert!(response.set_content_range(0, 10, Some(10)).is_err());
+        assert!(response.set_content_range(0, 0, Some(0)).is_err());
+        response.send("");
+
+        assert_eq!(output.status(), Some(StatusCode::Ok));
+        assert!(output.headers().unwrap().get_raw("Content-Range").is_none());
+    }
+
+    #[test]
+    fn repeated_headers() {
+        use header::{SetCookie, CookiePair};
+
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        let mut response: Response = response;
+        response.add_header("Set-Cookie", "a=1; Path=/");
+        response.add_header("Set-Cookie", "b=2, 3");
+        response.add_header("WWW-Authenticate", "Basic realm=\"x\"");
+        response.add_header("WWW-Authenticate", "Bearer");
+        response.set_header("X-Single", "old");
+        response.set_header("X-Single", "new");
+        response.send("");
+
+        let head = String::from_utf8(output.bytes()).unwrap();
+        assert!(head.contains("\r\nSet-Cookie: a=1; Path=/\r\nSet-Cookie: b=2, 3\r\n"), "{}", head);
+        assert!(head.contains("\r\nWWW-Authenticate: Basic realm=\"x\"\r\nWWW-Authenticate: Bearer\r\n"), "{}", head);
+        assert!(head.contains("\r\nX-Single: new\r\n"), "{}", head);
+        assert!(!head.contains("old"), "{}", head);
+
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        let mut response: Response = response;
+        response.headers_mut().set(SetCookie(vec![CookiePair::new("a".into(), "1".into()), CookiePair::new("b".into(), "2".into())]));
+        response.add_header("Set-Cookie", "c=3");
+        response.send("");
+
+        let head = String::from_utf8(output.bytes()).unwrap();
+        assert!(head.contains("\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\nSet-Cookie: c=3\r\n"), "{}", head);
+    }
+
+    #[test]
+    fn responders() {
+        use super::Responder;
+
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        "hello".respond(response);
+        assert_eq!(output.status(), Some(StatusCode::Ok));
+        assert_eq!(output.headers().unwrap().get_raw("content-type"), Some(&[b"text/plain; charset=utf-8".to_vec()][..]));
+        assert_eq!(output.body(), Some(b"hello".to_vec()));
+
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        StatusCode::NoContent.respond(response);
+        assert_eq!(output.status(), Some(StatusCode::NoContent));
+
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        let result: Result<String, (StatusCode, String)> = Err((StatusCode::Conflict, "taken".to_owned()));
+        result.respond(response);
+        assert_eq!(output.status(), Some(StatusCode::Conflict));
+        assert_eq!(output.body(), Some(b"taken".to_vec()));
+    }
+
+    #[test]
+    #[cfg(feature = "rustc_json_body")]
+    fn json_responder() {
+        use std::collections::BTreeMap;
+        use super::{Responder, Json};
+
+        let mut values = BTreeMap::new();
+        values.insert("a".to_owned(), 1);
+
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        Json(values).respond(response);
+        assert_eq!(output.headers().unwrap().get_raw("content-type"), Some(&[b"application/json; charset=utf-8".to_vec()][..]));
+        assert_eq!(output.body(), Some(b"{\"a\":1}".to_vec()));
+    }
+
+    #[test]
+    #[cfg(feature = "rustc_json_body")]
+    fn created_json() {
+        use std::collections::BTreeMap;
+
+        let mut user = BTreeMap::new();
+        user.insert("id".to_owned(), 5);
+
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        response.created_json("/users/5", &user);
+        assert_eq!(output.status(), Some(StatusCode::Created));
+        assert_eq!(output.headers().unwrap().get_raw("location"), Some(&[b"/users/5".to_vec()][..]));
+        assert_eq!(output.headers().unwrap().get_raw("content-type"), Some(&[b"application/json; charset=utf-8".to_vec()][..]));
+        assert_eq!(output.body(), Some(b"{\"id\":5}".to_vec()));
+
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        response.created_json("", &user);
+        assert_eq!(output.status(), Some(StatusCode::InternalServerError));
+        assert_eq!(output.headers().unwrap().get_raw("location"), None);
+    }
+
+    #[test]
+    #[cfg(feature = "rustc_json_body")]
+    fn send_problems() {
+        use rustc_serialize::json::Json;
+        use super::Problem;
+
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        response.problem(StatusCode::NotFound, "No such user", "There is no user with the ID 5.");
+
+        assert_eq!(output.status(), Some(StatusCode::NotFound));
+        assert_eq!(output.headers().unwrap().get_raw("content-type"), Some(&[b"application/problem+json".to_vec()][..]));
+        let body = Json::from_str(&String::from_utf8(output.body().unwrap()).unwrap()).unwrap();
+        assert_eq!(body.find("type").and_then(|t| t.as_string()), Some("about:blank"));
+        assert_eq!(body.find("title").and_then(|t| t.as_string()), Some("No such user"));
+        assert_eq!(body.find("status").and_then(|s| s.as_u64()), Some(404));
+        assert_eq!(body.find("detail").and_then(|d| d.as_string()), Some("There is no user with the ID 5."));
+        assert!(body.find("instance").is_none());
+
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        response.send_problem(Problem::new(StatusCode::Conflict, "Taken").extension("status", 200).extension("name", "alice".to_owned()));
+
+        let body = Json::from_str(&String::from_utf8(output.body().unwrap()).unwrap()).unwrap();
+        assert_eq!(body.find("status").and_then(|s| s.as_u64()), Some(409));
+        assert_eq!(body.find("name").and_then(|n| n.as_string()), Some("alice"));
+    }
+
+    #[test]
+    fn buffered_body_in_memory() {
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        let mut body = response.buffered_body(16);
+        body.send("hello, ");
+        body.write_all(b"world").unwrap();
+        assert!(!body.is_spilled());
+        assert_eq!(body.bytes_buffered(), 12);
+        assert_eq!(output.status(), None);
+        body.end().unwrap();
+
+        let headers = output.headers().unwrap();
+        assert_eq!(headers.get(), Some(&ContentLength(12)));
+        assert!(!headers.has::<TransferEncoding>());
+        assert_eq!(output.body(), Some(b"hello, world".to_vec()));
+    }
+
+    #[test]
+    fn buffered_body_spilled() {
+        use std::{env, fs, process};
+
+        let prefix = format!("rustful-response-{}-", process::id());
+        let temp_files = || fs::read_dir(env::temp_dir()).unwrap()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+            .count();
+
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        let mut expected = vec![];
+        {
+            let mut body = response.buffered_body(16);
+            for i in 0..1000 {
+                let line = format!("line {}\n", i);
+                body.send(&*line);
+                expected.extend_from_slice(line.as_bytes());
+            }
+            assert!(body.is_spilled());
+            assert_eq!(body.bytes_buffered(), expected.len() as u64);
+            assert_eq!(output.status(), None);
+
+            #[cfg(unix)]
+            assert_eq!(temp_files(), 0);
+        }
+        assert_eq!(temp_files(), 0);
+
+        let headers = output.headers().unwrap();
+        assert_eq!(headers.get(), Some(&ContentLength(expected.len() as u64)));
+        assert!(!headers.has::<TransferEncoding>());
+        assert_eq!(output.body(), Some(expected));
+    }
+
+    #[test]
+    fn raw_too_long() {
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        let mut raw = unsafe { response.into_raw(5) };
+        raw.write_all(b"hel").unwrap();
+
+        let error = raw.write_all(b"lo, world").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(raw.write(b"!").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(raw.bytes_written(), 5);
+        raw.end().unwrap();
+
+        assert_eq!(output.body(), Some(b"hello".to_vec()));
+    }
+
+    #[test]
+    fn raw_too_short() {
+        let mut buffer = ResponseBuffer::new();
+        let (response, output) = Response::buffered(&mut buffer);
+        let mut raw = unsafe { response.into_raw(10) };
+        raw.write_all(b"hello").unwrap();
+
+        let error = raw.end().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(output.body(), Some(b"hello".to_vec()));
+    }
+}
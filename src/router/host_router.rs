@@ -0,0 +1,260 @@
+use std::collections::hash_map::{HashMap, Entry};
+
+use router::{Router, Endpoint, InsertState, RouteState, RouteMeta, RouteInfo};
+use context::hypermedia::Link;
+use Method;
+
+///A router that selects an item from the requested host name, for virtual
+///hosting.
+///
+///Hosts are either exact host names, like `example.com`, or have a single
+///leading wildcard, like `*.example.com`. A wildcard host matches any
+///subdomain, but not the domain itself, and the most specific one is
+///chosen if more than one matches. Exact host names are always preferred
+///over wildcards. Host names are compared without regard to case, and the
+///port is ignored.
+///
+///Requests for hosts that doesn't match anything are passed to the default
+///router, which is where routes that are inserted directly into the
+///`HostRouter` ends up. A `404 Not Found` response will be sent if there is
+///no default router, unless the server has a fallback handler.
+///
+///```
+///use rustful::{Router, TreeRouter, Context, Response};
+///use rustful::router::HostRouter;
+///use rustful::Method::Get;
+///
+///fn blog(_context: Context, response: Response) {
+///    response.send("the blog");
+///}
+///
+///fn user_page(context: Context, response: Response) {
+///    response.send(format!("a user page for {:?}", context.headers.get::<rustful::header::Host>()));
+///}
+///
+///let mut blog_router = TreeRouter::new();
+///blog_router.insert(Get, "/", blog as fn(Context, Response));
+///
+///let mut user_router = TreeRouter::new();
+///user_router.insert(Get, "/", user_page as fn(Context, Response));
+///
+///let mut router = HostRouter::new();
+///router.insert_host("blog.example.com", blog_router);
+///router.insert_host("*.users.example.com", user_router);
+///```
+#[derive(Clone)]
+pub struct HostRouter<T> {
+    exact: HashMap<String, T>,
+    //Sorted with the longest suffix first.
+    wildcards: Vec<(String, T)>,
+    default: Option<T>,
+}
+
+impl<T: Router> HostRouter<T> {
+    ///Create an empty `HostRouter`.
+    pub fn new() -> HostRouter<T> {
+        HostRouter::default()
+    }
+
+    ///Insert a router for a host name pattern. It will be merged with any
+    ///previously inserted router for the same pattern.
+    pub fn insert_host(&mut self, host: &str, router: T) {
+        let host = normalize_host(host);
+
+        if host.starts_with("*.") {
+            let suffix = host[1..].to_owned();
+            match self.wildcards.iter().position(|&(ref existing, _)| *existing == suffix) {
+                Some(index) => self.wildcards[index].1.merge(router),
+                None => {
+                    let index = self.wildcards.iter().position(|&(ref existing, _)| existing.len() < suffix.len()).unwrap_or(self.wildcards.len());
+                    self.wildcards.insert(index, (suffix, router));
+                }
+            }
+        } else {
+            match self.exact.entry(host) {
+                Entry::Occupied(mut e) => e.get_mut().merge(router),
+                Entry::Vacant(e) => {
+                    e.insert(router);
+                }
+            }
+        }
+    }
+
+    ///Set the router that will be used when no host matches, replacing any
+    ///previous default router.
+    pub fn set_default(&mut self, router: T) {
+        self.default = Some(router);
+    }
+}
+
+impl<T> HostRouter<T> {
+    fn select(&self, host: Option<&str>) -> Option<&T> {
+        let host = host.map(normalize_host);
+
+        let matched = host.and_then(|host| {
+            self.exact.get(&host).or_else(|| {
+                self.wildcards.iter()
+                    .find(|&&(ref suffix, _)| host.len() > suffix.len() && host.ends_with(&**suffix))
+                    .map(|&(_, ref router)| router)
+            })
+        });
+
+        matched.or(self.default.as_ref())
+    }
+}
+
+impl<T: Router> Router for HostRouter<T> {
+    type Handler = T::Handler;
+
+    fn find<'a>(&'a self, method: &Method, route: &mut RouteState) -> Endpoint<'a, Self::Handler> {
+        match self.select(route.host()) {
+            Some(router) => router.find(method, route),
+            None => Endpoint::from(None)
+        }
+    }
+
+    fn hyperlinks<'a>(&'a self, base: Link<'a>) -> Vec<Link<'a>> {
+        self.default.hyperlinks(base)
+    }
+
+    fn routes<'a>(&'a self) -> Vec<RouteInfo<'a>> {
+        let mut routes = self.default.routes();
+        routes.extend(self.exact.values().flat_map(|router| router.routes()));
+        routes.extend(self.wildcards.iter().flat_map(|&(_, ref router)| router.routes()));
+        routes
+    }
+
+    fn build<'a, R: Into<InsertState<'a, I>>, I: Iterator<Item = &'a [u8]>>(method: Method, route: R, item: Self::Handler) -> HostRouter<T> {
+        let mut router = HostRouter::default();
+        router.insert(method, route, item);
+        router
+    }
+
+    fn insert<'a, R: Into<InsertState<'a, I>>, I: Iterator<Item = &'a [u8]>>(&mut self, method: Method, route: R, item: Self::Handler) {
+        Router::insert(&mut self.default, method, route, item);
+    }
+
+    fn set_meta<'a, R: Into<InsertState<'a, I>>, I: Iterator<Item = &'a [u8]>>(&mut self, method: Method, route: R, meta: RouteMeta) {
+        self.default.set_meta(method, route, meta);
+    }
+
+    fn insert_router<'a, R: Into<InsertState<'a, I>>, I: Clone + Iterator<Item = &'a [u8]>>(&mut self, route: R, router: HostRouter<T>) {
+        let route = route.into();
+        let HostRouter { exact, wildcards, default } = router;
+
+        self.default.insert_router(route.clone(), default);
+
+        for (host, mut item) in exact {
+            match self.exact.entry(host) {
+                Entry::Occupied(mut e) => e.get_mut().insert_router(route.clone(), item),
+                Entry::Vacant(e) => {
+                    item.prefix(route.clone());
+                    e.insert(item);
+                }
+            }
+        }
+
+        for (suffix, mut item) in wildcards {
+            match self.wildcards.iter().position(|&(ref existing, _)| *existing == suffix) {
+                Some(index) => self.wildcards[index].1.insert_router(route.clone(), item),
+                None => {
+                    item.prefix(route.clone());
+                    let index = self.wildcards.iter().position(|&(ref existing, _)| existing.len() < suffix.len()).unwrap_or(self.wildcards.len());
+                    self.wildcards.insert(index, (suffix, item));
+                }
+            }
+        }
+    }
+
+    fn prefix<'a, R: Into<InsertState<'a, I>>, I: Clone + Iterator<Item = &'a [u8]>>(&mut self, route: R) {
+        let route = route.into();
+
+        self.default.prefix(route.clone());
+        for item in self.exact.values_mut() {
+            item.prefix(route.clone());
+        }
+        for &mut (_, ref mut item) in &mut self.wildcards {
+            item.prefix(route.clone());
+        }
+    }
+}
+
+impl<T> Default for HostRouter<T> {
+    fn default() -> HostRouter<T> {
+        HostRouter {
+            exact: HashMap::new(),
+            wildcards: vec![],
+            default: None,
+        }
+    }
+}
+
+//Lower case and without any trailing dot.
+fn normalize_host(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod test {
+    use router::{Router, HostRouter, RouteState};
+    use Method::Get;
+
+    fn find(router: &HostRouter<u8>, host: Option<&str>) -> Option<u8> {
+        //`u8` isn't a handler, so the hosts are looked up directly.
+        let mut route = RouteState::from("/");
+        route.set_host(host);
+        router.select(route.host()).cloned()
+    }
+
+    #[test]
+    fn select_hosts() {
+        let mut router = HostRouter::default();
+        router.exact.insert("example.com".to_owned(), 1);
+        router.exact.insert("www.example.com".to_owned(), 2);
+        router.wildcards.push((".users.example.com".to_owned(), 4));
+        router.wildcards.push((".example.com".to_owned(), 3));
+
+        assert_eq!(find(&router, Some("example.com")), Some(1));
+        assert_eq!(find(&router, Some("WWW.Example.com.")), Some(2));
+        assert_eq!(find(&router, Some("blog.example.com")), Some(3));
+        assert_eq!(find(&router, Some("a.b.example.com")), Some(3));
+        assert_eq!(find(&router, Some("alice.users.example.com")), Some(4));
+        assert_eq!(find(&router, Some("example.org")), None);
+        assert_eq!(find(&router, Some("badexample.com")), None);
+        assert_eq!(find(&router, None), None);
+
+        router.default = Some(0);
+        assert_eq!(find(&router, Some("example.org")), Some(0));
+        assert_eq!(find(&router, None), Some(0));
+    }
+
+    #[test]
+    fn insert_hosts() {
+        use Context;
+        use Response;
+        use TreeRouter;
+
+        fn handler(_: Context, _: Response) {}
+
+        let mut tree = TreeRouter::new();
+        tree.insert(Get, "/a", handler as fn(Context, Response));
+
+        let mut router = HostRouter::new();
+        router.insert_host("*.example.com", tree.clone());
+        router.insert_host("*.users.example.com", tree.clone());
+        router.insert_host("*.a.example.com", tree.clone());
+        router.insert_host("Example.com", tree);
+
+        let suffixes: Vec<_> = router.wildcards.iter().map(|&(ref suffix, _)| &**suffix).collect();
+        assert_eq!(suffixes, vec![".users.example.com", ".a.example.com", ".example.com"]);
+        assert!(router.exact.contains_key("example.com"));
+
+        let mut route = RouteState::from("/a");
+        route.set_host(Some("x.a.example.com"));
+        assert!(router.find(&Get, &mut route).handler.is_some());
+
+        let mut route = RouteState::from("/a");
+        route.set_host(Some("example.org"));
+        assert!(router.find(&Get, &mut route).handler.is_none());
+    }
+}
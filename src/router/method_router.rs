@@ -1,6 +1,6 @@
 use std::collections::hash_map::{HashMap, Entry};
 
-use router::{Router, Endpoint, InsertState, RouteState};
+use router::{Router, Endpoint, InsertState, RouteState, RouteMeta, RouteInfo};
 use context::hypermedia::Link;
 use Method;
 
@@ -33,6 +33,15 @@ impl<T: Router> Router for MethodRouter<T> {
         }).collect()
     }
 
+    fn routes<'a>(&'a self) -> Vec<RouteInfo<'a>> {
+        self.items.iter().flat_map(|(method, item)| {
+            item.routes().into_iter().map(move |mut route| {
+                route.method = Some(method.clone());
+                route
+            })
+        }).collect()
+    }
+
     fn build<'a, R: Into<InsertState<'a, I>>, I: Iterator<Item = &'a [u8]>>(method: Method, route: R, item: Self::Handler) -> MethodRouter<T> {
         let mut router = MethodRouter::default();
         router.insert(method, route, item);
@@ -40,7 +49,15 @@ impl<T: Router> Router for MethodRouter<T> {
     }
 
     fn insert<'a, R: Into<InsertState<'a, I>>, I: Iterator<Item = &'a [u8]>>(&mut self, method: Method, route: R, item: Self::Handler) {
-        self.items.insert(method.clone(), T::build(method, route, item));
+        if self.items.insert(method.clone(), T::build(method.clone(), route, item)).is_some() {
+            warn!(target: "rustful::router", "a {} handler was replaced by a new one", method);
+        }
+    }
+
+    fn set_meta<'a, R: Into<InsertState<'a, I>>, I: Iterator<Item = &'a [u8]>>(&mut self, method: Method, route: R, meta: RouteMeta) {
+        if let Some(item) = self.items.get_mut(&method) {
+            item.set_meta(method, route, meta);
+        }
     }
 
     fn insert_router<'a, R: Into<InsertState<'a, I>>, I: Clone + Iterator<Item = &'a [u8]>>(&mut self, route: R, router: MethodRouter<T>) {
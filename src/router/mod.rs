@@ -178,17 +178,23 @@ use std::ops::Deref;
 use std::marker::PhantomData;
 use hyper::method::Method;
 
-use handler::Handler;
-use context::MaybeUtf8Owned;
-use context::hypermedia::Link;
+use handler::{Handler, Mount};
+use context::Context;
+use response::Response;
+use context::{MaybeUtf8Owned, MaybeUtf8Slice, Parameters};
+use context::hypermedia::{Link, LinkSegment, SegmentType};
 
 pub use self::tree_router::TreeRouter;
 pub use self::method_router::MethodRouter;
 pub use self::variables::Variables;
+pub use self::host_router::HostRouter;
+pub use self::query_router::QueryRouter;
 
 mod tree_router;
 mod method_router;
 mod variables;
+mod host_router;
+mod query_router;
 
 ///API endpoint data.
 pub struct Endpoint<'a, T: 'a> {
@@ -211,6 +217,132 @@ impl<'a, T> From<Option<&'a T>> for Endpoint<'a, T> {
     }
 }
 
+///Descriptive metadata for a route.
+///
+///It can be attached to a route using `Router::insert_with_meta` or
+///`Router::set_meta`, and is listed together with the route by
+///`Router::routes`. This makes it possible to generate documentation or to
+///list all endpoints, without having to maintain a separate registry.
+///
+///```
+///use rustful::router::RouteMeta;
+///
+///let meta = RouteMeta::new("Show a user").tag("users").attribute("auth", "token");
+///assert_eq!(meta.summary, Some("Show a user".to_owned()));
+///assert_eq!(meta.tags, vec!["users".to_owned()]);
+///```
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct RouteMeta {
+    ///A short summary of what the route does.
+    pub summary: Option<String>,
+    ///Tags for grouping routes.
+    pub tags: Vec<String>,
+    ///Any other attributes, such as authentication requirements.
+    pub attributes: HashMap<String, String>
+}
+
+impl RouteMeta {
+    ///Create metadata with a summary.
+    pub fn new<S: Into<String>>(summary: S) -> RouteMeta {
+        RouteMeta {
+            summary: Some(summary.into()),
+            ..RouteMeta::default()
+        }
+    }
+
+    ///Add a tag.
+    pub fn tag<S: Into<String>>(mut self, tag: S) -> RouteMeta {
+        self.tags.push(tag.into());
+        self
+    }
+
+    ///Add an attribute, possibly replacing a previous one with the same name.
+    pub fn attribute<K: Into<String>, V: Into<String>>(mut self, name: K, value: V) -> RouteMeta {
+        self.attributes.insert(name.into(), value.into());
+        self
+    }
+}
+
+///A registered route, as listed by `Router::routes`.
+#[derive(Clone, Debug)]
+pub struct RouteInfo<'a> {
+    ///The HTTP method of the route, if the router cares about it.
+    pub method: Option<Method>,
+    ///The path of the route. The labels of variable segments may be empty.
+    pub path: Vec<LinkSegment<'a>>,
+    ///The names of the route variables, in the order they appear in the path.
+    pub variables: Vec<MaybeUtf8Slice<'a>>,
+    ///Metadata attached to the route, if any.
+    pub meta: Option<&'a RouteMeta>
+}
+
+impl<'a> RouteInfo<'a> {
+    ///Create a `RouteInfo` for the root route.
+    pub fn new(meta: Option<&'a RouteMeta>) -> RouteInfo<'a> {
+        RouteInfo {
+            method: None,
+            path: vec![],
+            variables: vec![],
+            meta: meta
+        }
+    }
+
+    ///Format the path as a route pattern, such as `/users/:id`.
+    pub fn pattern(&self) -> String {
+        let mut variables = self.variables.iter();
+        let mut pattern = String::new();
+
+        for segment in &self.path {
+            pattern.push('/');
+            let label = match segment.ty {
+                SegmentType::Static => &segment.label,
+                SegmentType::VariableSegment => {
+                    pattern.push(':');
+                    variables.next().unwrap_or(&segment.label)
+                },
+                SegmentType::VariableSequence => {
+                    pattern.push('*');
+                    variables.next().unwrap_or(&segment.label)
+                }
+            };
+            pattern.push_str(&label.as_utf8_lossy());
+        }
+
+        if pattern.is_empty() {
+            pattern.push('/');
+        }
+
+        pattern
+    }
+}
+
+///The result of `Router::match_debug`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MatchOutcome {
+    ///A handler was found.
+    Matched {
+        ///The pattern of the matching route, like `/users/:id`, if it could
+        ///be identified among the listed routes.
+        pattern: Option<String>,
+        ///The captured route variables.
+        variables: HashMap<MaybeUtf8Owned, MaybeUtf8Owned>
+    },
+
+    ///The path matched, but not for this method. The methods that would
+    ///have matched are included, as listed by `Router::allowed_methods`.
+    MethodMismatch(Vec<Method>),
+
+    ///Nothing matched the path.
+    NoPath
+}
+
+//A handler that is never called, for matching patterns in `match_debug`.
+struct Probe;
+
+impl Handler for Probe {
+    fn handle_request(&self, _context: Context, _response: Response) {}
+}
+
 ///A common trait for routers.
 ///
 ///A router must to implement this trait to be usable in a Rustful server. This
@@ -227,6 +359,60 @@ pub trait Router: Send + Sync + 'static {
     ///both `method` and `route`, depending on its implementation.
     fn insert<'a, R: Into<InsertState<'a, I>>, I: Iterator<Item = &'a [u8]>>(&mut self, method: Method, route: R, item: Self::Handler);
 
+    ///Attach metadata to an already inserted route. The router may choose to
+    ///ignore the metadata, depending on its implementation, and routes that
+    ///don't exist are ignored.
+    ///
+    ///Ignores the metadata by default.
+    #[allow(unused_variables)]
+    fn set_meta<'a, R: Into<InsertState<'a, I>>, I: Iterator<Item = &'a [u8]>>(&mut self, method: Method, route: R, meta: RouteMeta) {}
+
+    ///Insert a new route, with attached metadata. This is the same as calling
+    ///`insert` and then `set_meta`.
+    ///
+    ///```
+    ///use rustful::{Router, TreeRouter, Context, Response};
+    ///use rustful::Method::Get;
+    ///use rustful::router::RouteMeta;
+    ///
+    ///fn show_user(_context: Context, _response: Response) {}
+    ///
+    ///let mut router = TreeRouter::new();
+    ///router.insert_with_meta(Get, "/users/:id", show_user as fn(Context, Response), RouteMeta::new("Show a user"));
+    ///
+    ///let routes = router.routes();
+    ///assert_eq!(routes[0].pattern(), "/users/:id");
+    ///assert_eq!(routes[0].method, Some(Get));
+    ///assert_eq!(routes[0].meta, Some(&RouteMeta::new("Show a user")));
+    ///```
+    fn insert_with_meta<'a, R: Into<InsertState<'a, I>>, I: Clone + Iterator<Item = &'a [u8]>>(&mut self, method: Method, route: R, item: Self::Handler, meta: RouteMeta) {
+        let route = route.into();
+        self.insert(method.clone(), route.clone(), item);
+        self.set_meta(method, route, meta);
+    }
+
+    ///Insert the same handler for several methods at once. This is the same
+    ///as calling `insert` for each of the methods, with a clone of the
+    ///handler.
+    ///
+    ///```
+    ///use rustful::{Router, TreeRouter, Context, Response};
+    ///use rustful::Method::{Get, Head, Post};
+    ///
+    ///fn search(_context: Context, _response: Response) {}
+    ///
+    ///let mut router = TreeRouter::new();
+    ///router.route(&[Get, Head, Post], "/search", search as fn(Context, Response));
+    ///
+    ///assert_eq!(router.allowed_methods("/search"), vec![Get, Head, Post]);
+    ///```
+    fn route<'a, R: Into<InsertState<'a, I>>, I: Clone + Iterator<Item = &'a [u8]>>(&mut self, methods: &[Method], route: R, item: Self::Handler) where Self::Handler: Clone {
+        let route = route.into();
+        for method in methods {
+            self.insert(method.clone(), route.clone(), item.clone());
+        }
+    }
+
     ///Insert an other router at a path. The content of the other router will
     ///be merged with this one and conflicting content will be overwritten.
     fn insert_router<'a, R: Into<InsertState<'a, I>>, I: Clone + Iterator<Item = &'a [u8]>>(&mut self, route: R, router: Self);
@@ -245,6 +431,132 @@ pub trait Router: Send + Sync + 'static {
     ///List all of the hyperlinks into this router, based on the provided base
     ///link. It's up to the router implementation to decide how deep to go.
     fn hyperlinks<'a>(&'a self, base: Link<'a>) -> Vec<Link<'a>>;
+
+    ///List all of the routes in this router, relative to the router itself,
+    ///together with any attached metadata.
+    ///
+    ///Lists nothing by default.
+    fn routes<'a>(&'a self) -> Vec<RouteInfo<'a>> {
+        vec![]
+    }
+
+    ///List the methods that have a handler for `path`, without dispatching
+    ///a request. The path is matched the same way as a request path would
+    ///be, including variable and wildcard segments, and the result is empty
+    ///if nothing matches. The methods are sorted by name.
+    ///
+    ///Only the methods that are mentioned in `routes` are checked, so a
+    ///handler that accepts any method is not included. The host and the
+    ///query are not known, so routers that depend on them will act as if
+    ///they are missing.
+    ///
+    ///```
+    ///use rustful::{Router, TreeRouter, Context, Response};
+    ///use rustful::Method::{Get, Delete};
+    ///
+    ///fn handler(_context: Context, _response: Response) {}
+    ///
+    ///let mut router = TreeRouter::new();
+    ///router.insert(Get, "/users/:id", handler as fn(Context, Response));
+    ///router.insert(Delete, "/users/:id", handler as fn(Context, Response));
+    ///
+    ///assert_eq!(router.allowed_methods("/users/42"), vec![Delete, Get]);
+    ///assert_eq!(router.allowed_methods("/posts"), vec![]);
+    ///```
+    fn allowed_methods(&self, path: &str) -> Vec<Method> {
+        let mut methods: Vec<Method> = self.routes().into_iter().filter_map(|route| route.method).collect();
+        methods.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+        methods.dedup();
+        methods.retain(|method| self.find(method, &mut path.into()).handler.is_some());
+        methods
+    }
+
+    ///Find out how a request for `method` and `path` would be routed,
+    ///without calling any handler. The handler and the variables are found
+    ///by `find`, just like for a real request, so the outcome is the same as
+    ///what the server would do, except that the host and the query are not
+    ///known. Routers that depend on them will act as if they are missing.
+    ///
+    ///The pattern of the matching route is found by comparing the captured
+    ///variables with those of each route in `routes` that matches the path,
+    ///so it's `None` if the router doesn't list its routes, or if more than
+    ///one route would capture the same variables.
+    ///
+    ///```
+    ///use rustful::{Router, TreeRouter, Context, Response};
+    ///use rustful::router::MatchOutcome;
+    ///use rustful::Method::{Get, Post};
+    ///
+    ///fn handler(_context: Context, _response: Response) {}
+    ///
+    ///let mut router = TreeRouter::new();
+    ///router.insert(Get, "/users/:id", handler as fn(Context, Response));
+    ///
+    ///match router.match_debug(&Get, "/users/42") {
+    ///    MatchOutcome::Matched { pattern, variables } => {
+    ///        assert_eq!(pattern, Some("/users/:id".to_owned()));
+    ///        assert_eq!(variables.get("id".as_bytes()).unwrap(), "42");
+    ///    },
+    ///    other => panic!("unexpected outcome: {:?}", other)
+    ///}
+    ///
+    ///assert_eq!(router.match_debug(&Post, "/users/42"), MatchOutcome::MethodMismatch(vec![Get]));
+    ///assert_eq!(router.match_debug(&Get, "/posts/42"), MatchOutcome::NoPath);
+    ///```
+    fn match_debug(&self, method: &Method, path: &str) -> MatchOutcome {
+        let endpoint = self.find(method, &mut path.into());
+        if endpoint.handler.is_none() {
+            let allowed = self.allowed_methods(path);
+            return if allowed.is_empty() {
+                MatchOutcome::NoPath
+            } else {
+                MatchOutcome::MethodMismatch(allowed)
+            };
+        }
+
+        let mut patterns = self.routes().into_iter().filter(|route| route.method.as_ref().map_or(true, |m| m == method)).filter_map(|route| {
+            let pattern = route.pattern();
+            let mut probe = TreeRouter::<MethodRouter<Variables<Probe>>>::new();
+            probe.insert(method.clone(), &*pattern, Probe);
+            let found = probe.find(method, &mut path.into());
+            if found.handler.is_some() && found.variables == endpoint.variables {
+                Some(pattern)
+            } else {
+                None
+            }
+        });
+
+        let pattern = match (patterns.next(), patterns.next()) {
+            (Some(pattern), None) => Some(pattern),
+            _ => None
+        };
+
+        MatchOutcome::Matched {
+            pattern: pattern,
+            variables: endpoint.variables
+        }
+    }
+
+    ///Mount a handler at a path prefix. Requests for the prefix, and any
+    ///path below it, are dispatched to `handler`, with the prefix removed
+    ///from `Context::uri_path`. See [`Mount`][mount] for the details.
+    ///
+    ///The handler is inserted for the `GET`, `HEAD`, `POST`, `PUT`, `PATCH`,
+    ///`DELETE` and `OPTIONS` methods, at both `prefix` and `prefix/*`. The
+    ///router has to store handlers that can be created from a `Mount`, like
+    ///`Box<Handler>` or `Mount` itself.
+    ///
+    ///[mount]: ../handler/struct.Mount.html
+    fn mount_handler<H: Handler>(&mut self, prefix: &str, handler: H) where Self: Sized, Self::Handler: From<Mount<H>> {
+        let mount = Mount::new(prefix, handler);
+        let prefix = mount.prefix();
+        let below = format!("{}/*", prefix.trim_end_matches('/'));
+
+        for method in &[Method::Get, Method::Head, Method::Post, Method::Put, Method::Patch, Method::Delete, Method::Options] {
+            self.insert(method.clone(), &*prefix, mount.clone().into());
+            self.insert(method.clone(), &*below, mount.clone().into());
+        }
+    }
 }
 
 impl<H: Handler> Router for H {
@@ -272,6 +584,10 @@ impl<H: Handler> Router for H {
         base.handler = Some(self);
         vec![base]
     }
+
+    fn routes<'a>(&'a self) -> Vec<RouteInfo<'a>> {
+        vec![RouteInfo::new(None)]
+    }
 }
 
 impl<T: Router> Router for Option<T> {
@@ -288,6 +604,12 @@ impl<T: Router> Router for Option<T> {
         }
     }
 
+    fn set_meta<'a, R: Into<InsertState<'a, I>>, I: Iterator<Item = &'a [u8]>>(&mut self, method: Method, route: R, meta: RouteMeta) {
+        if let Some(ref mut router) = *self {
+            router.set_meta(method, route, meta);
+        }
+    }
+
     fn insert_router<'a, R: Into<InsertState<'a, I>>, I: Clone + Iterator<Item = &'a [u8]>>(&mut self, route: R, router: Option<T>) {
         if let Some(mut other) = router {
             match *self {
@@ -319,6 +641,14 @@ impl<T: Router> Router for Option<T> {
             vec![]
         }
     }
+
+    fn routes<'a>(&'a self) -> Vec<RouteInfo<'a>> {
+        if let Some(ref router) = *self {
+            router.routes()
+        } else {
+            vec![]
+        }
+    }
 }
 
 ///A segmented route.
@@ -476,14 +806,65 @@ impl<'a, R: Route<'a> + ?Sized> From<&'a R> for InsertState<'a, R::Segments> {
 }
 
 ///A state object for routing.
+#[derive(Clone)]
 pub struct RouteState<'a> {
     route: Vec<&'a [u8]>,
     variables: Vec<Option<usize>>,
+    sequences: Vec<bool>,
     index: usize,
     var_index: usize,
+    host: Option<&'a str>,
+    query: Option<&'a Parameters>,
+    record_pattern: bool,
+    pattern: Option<String>,
 }
 
 impl<'a> RouteState<'a> {
+    ///Create a state from a path that is already split into segments. The
+    ///segments are used as they are, so they may contain `/`. This is how
+    ///the server routes percent encoded slashes, like in `/files/a%2Fb`,
+    ///where `a/b` is a single segment.
+    ///
+    ///```
+    ///use rustful::router::RouteState;
+    ///
+    ///let route = RouteState::from_segments(vec![&b"files"[..], &b"a/b"[..]]);
+    ///assert_eq!(route.get(), Some(&b"files"[..]));
+    ///```
+    pub fn from_segments(route: Vec<&'a [u8]>) -> RouteState<'a> {
+        RouteState {
+            variables: vec![None; route.len()],
+            sequences: vec![false; route.len()],
+            route: route,
+            index: 0,
+            var_index: 0,
+            host: None,
+            query: None,
+            record_pattern: false,
+            pattern: None,
+        }
+    }
+
+    ///Get the requested host name, without the port, if it's known.
+    pub fn host(&self) -> Option<&'a str> {
+        self.host
+    }
+
+    ///Set the requested host name. It should not include the port.
+    pub fn set_host(&mut self, host: Option<&'a str>) {
+        self.host = host;
+    }
+
+    ///Get the query parameters, if they are known.
+    pub fn query(&self) -> Option<&'a Parameters> {
+        self.query
+    }
+
+    ///Set the query parameters.
+    pub fn set_query(&mut self, query: Option<&'a Parameters>) {
+        self.query = query;
+    }
+
     ///Get the current path segment.
     pub fn get(&self) -> Option<&'a [u8]> {
         self.route.get(self.index).cloned()
@@ -497,6 +878,18 @@ impl<'a> RouteState<'a> {
 
     ///Include this path segment as a variable.
     pub fn keep(&mut self) {
+        self.mark_sequence(false);
+        let v_i = self.var_index;
+        self.variables.get_mut(self.index).map(|v| *v = Some(v_i));
+        self.index += 1;
+        self.var_index += 1;
+    }
+
+    ///Include this path segment as the last part of a variable sequence. It
+    ///works like `keep`, but the variable is recorded as a sequence in
+    ///`pattern`.
+    pub fn keep_sequence(&mut self) {
+        self.mark_sequence(true);
         let v_i = self.var_index;
         self.variables.get_mut(self.index).map(|v| *v = Some(v_i));
         self.index += 1;
@@ -506,11 +899,16 @@ impl<'a> RouteState<'a> {
     ///Extend a previously saved variable value with this path segment, or
     ///save it as a new variable.
     pub fn fuse(&mut self) {
+        self.mark_sequence(true);
         let v_i = self.var_index;
         self.variables.get_mut(self.index).map(|v| *v = Some(v_i));
         self.index += 1;
     }
 
+    fn mark_sequence(&mut self, sequence: bool) {
+        self.sequences.get_mut(self.index).map(|s| *s = sequence);
+    }
+
     ///Assign names to the saved variables and return them.
     pub fn variables(&self, names: &[MaybeUtf8Owned]) -> HashMap<MaybeUtf8Owned, MaybeUtf8Owned> {
         let values = self.route.iter().zip(self.variables.iter()).filter_map(|(v, keep)| {
@@ -529,6 +927,54 @@ impl<'a> RouteState<'a> {
         var_map
     }
 
+    ///Ask the router to record the pattern of the matched route, to be found
+    ///in `pattern` after the search. It's not recorded by default, since it
+    ///takes an extra allocation.
+    pub fn set_record_pattern(&mut self, record: bool) {
+        self.record_pattern = record;
+    }
+
+    ///Record the pattern of the matched route, using the names of the saved
+    ///variables, if it was asked for. Each variable, including a variable
+    ///is written as `:name`, and each variable sequence as `*name`, so the
+    ///pattern of a route stays the same whatever path it matches.
+    pub fn record_pattern(&mut self, names: &[MaybeUtf8Owned]) {
+        if !self.record_pattern {
+            return;
+        }
+
+        let mut pattern = String::new();
+        let mut previous = None;
+        let segments = self.route.iter().zip(self.variables.iter()).zip(self.sequences.iter());
+        for ((segment, &variable), &sequence) in segments {
+            match variable {
+                Some(index) if previous == Some(index) => {},
+                Some(index) => {
+                    pattern.push_str(if sequence { "/*" } else { "/:" });
+                    if let Some(name) = names.get(index) {
+                        pattern.push_str(&name.as_utf8_lossy());
+                    }
+                },
+                None => {
+                    pattern.push('/');
+                    pattern.push_str(&String::from_utf8_lossy(segment));
+                }
+            }
+            previous = variable;
+        }
+
+        if pattern.is_empty() {
+            pattern.push('/');
+        }
+
+        self.pattern = Some(pattern);
+    }
+
+    ///Get the pattern of the matched route, if it was recorded.
+    pub fn pattern(&self) -> Option<&str> {
+        self.pattern.as_ref().map(|pattern| &**pattern)
+    }
+
     ///Get a snapshot of a part of the current state.
     pub fn snapshot(&self) -> (usize, usize) {
         (self.index, self.var_index)
@@ -549,13 +995,7 @@ impl<'a> RouteState<'a> {
 
 impl<'a, R: Route<'a> + ?Sized> From<&'a R> for RouteState<'a> {
     fn from(route: &'a R) -> RouteState<'a> {
-        let route: Vec<_> = route.segments().collect();
-        RouteState {
-            variables: vec![None; route.len()],
-            route: route,
-            index: 0,
-            var_index: 0,
-        }
+        RouteState::from_segments(route.segments().collect())
     }
 }
 
@@ -0,0 +1,227 @@
+use router::{Router, Endpoint, InsertState, RouteState, RouteMeta, RouteInfo};
+use context::hypermedia::Link;
+use Method;
+
+///A router that selects an item from the query parameters, as well as the
+///path.
+///
+///Each router is inserted with a set of query constraints, like
+///`"format=csv"` or `"format=csv&verbose"`, where a parameter without a value
+///only has to be present. All of the constraints have to be satisfied for the
+///router to be tried, and the values are compared with the decoded query
+///values, without any decoding of the constraints themselves.
+///
+///The query matching happens after the path matching, in the sense that a
+///router with satisfied constraints is only chosen if it also has a handler
+///for the path. Otherwise, the next router is tried. The constrained routers
+///are tried in the order they were inserted, and the routes that are
+///inserted directly into the `QueryRouter` are used as the least
+///constrained fallback for everything. Requests without any query don't need
+///to check more than the constraints, so the path-only routes are not
+///affected much.
+///
+///```
+///use rustful::{Router, TreeRouter, Context, Response};
+///use rustful::router::QueryRouter;
+///use rustful::Method::Get;
+///
+///fn report(_context: Context, response: Response) {
+///    response.send("<p>the report</p>");
+///}
+///
+///fn csv_report(_context: Context, response: Response) {
+///    response.send("the,report");
+///}
+///
+///let mut csv_router = TreeRouter::new();
+///csv_router.insert(Get, "/report", csv_report as fn(Context, Response));
+///
+///let mut router = QueryRouter::new();
+///router.insert(Get, "/report", report as fn(Context, Response));
+///router.insert_query("format=csv", csv_router);
+///```
+#[derive(Clone)]
+pub struct QueryRouter<T> {
+    //In insertion order.
+    constrained: Vec<(Vec<(String, Option<String>)>, T)>,
+    default: Option<T>,
+}
+
+impl<T: Router> QueryRouter<T> {
+    ///Create an empty `QueryRouter`.
+    pub fn new() -> QueryRouter<T> {
+        QueryRouter::default()
+    }
+
+    ///Insert a router for a set of query constraints. It will be merged with
+    ///any previously inserted router for the same constraints, regardless of
+    ///their order.
+    pub fn insert_query(&mut self, constraints: &str, router: T) {
+        let constraints = parse_constraints(constraints);
+
+        match self.constrained.iter().position(|&(ref existing, _)| *existing == constraints) {
+            Some(index) => self.constrained[index].1.merge(router),
+            None => self.constrained.push((constraints, router))
+        }
+    }
+}
+
+impl<T> QueryRouter<T> {
+    //The constrained routers that the query satisfies, in insertion order.
+    fn candidates<'s, 'q>(&'s self, route: &RouteState<'q>) -> Vec<&'s T> {
+        let query = match route.query() {
+            Some(query) => query,
+            None => return vec![]
+        };
+
+        self.constrained.iter().filter(|&&(ref constraints, _)| {
+            constraints.iter().all(|&(ref name, ref value)| match (query.get_raw(name), value) {
+                (Some(_), &None) => true,
+                (Some(actual), &Some(ref value)) => actual == value,
+                (None, _) => false
+            })
+        }).map(|&(_, ref router)| router).collect()
+    }
+}
+
+impl<T: Router> Router for QueryRouter<T> {
+    type Handler = T::Handler;
+
+    fn find<'a>(&'a self, method: &Method, route: &mut RouteState) -> Endpoint<'a, Self::Handler> {
+        for router in self.candidates(route) {
+            let mut attempt = route.clone();
+            let endpoint = router.find(method, &mut attempt);
+            if endpoint.handler.is_some() {
+                *route = attempt;
+                return endpoint;
+            }
+        }
+
+        self.default.find(method, route)
+    }
+
+    fn hyperlinks<'a>(&'a self, base: Link<'a>) -> Vec<Link<'a>> {
+        self.default.hyperlinks(base)
+    }
+
+    fn routes<'a>(&'a self) -> Vec<RouteInfo<'a>> {
+        let mut routes = self.default.routes();
+        routes.extend(self.constrained.iter().flat_map(|&(_, ref router)| router.routes()));
+        routes
+    }
+
+    fn build<'a, R: Into<InsertState<'a, I>>, I: Iterator<Item = &'a [u8]>>(method: Method, route: R, item: Self::Handler) -> QueryRouter<T> {
+        let mut router = QueryRouter::default();
+        router.insert(method, route, item);
+        router
+    }
+
+    fn insert<'a, R: Into<InsertState<'a, I>>, I: Iterator<Item = &'a [u8]>>(&mut self, method: Method, route: R, item: Self::Handler) {
+        Router::insert(&mut self.default, method, route, item);
+    }
+
+    fn set_meta<'a, R: Into<InsertState<'a, I>>, I: Iterator<Item = &'a [u8]>>(&mut self, method: Method, route: R, meta: RouteMeta) {
+        self.default.set_meta(method, route, meta);
+    }
+
+    fn insert_router<'a, R: Into<InsertState<'a, I>>, I: Clone + Iterator<Item = &'a [u8]>>(&mut self, route: R, router: QueryRouter<T>) {
+        let route = route.into();
+        let QueryRouter { constrained, default } = router;
+
+        self.default.insert_router(route.clone(), default);
+
+        for (constraints, mut item) in constrained {
+            match self.constrained.iter().position(|&(ref existing, _)| *existing == constraints) {
+                Some(index) => self.constrained[index].1.insert_router(route.clone(), item),
+                None => {
+                    item.prefix(route.clone());
+                    self.constrained.push((constraints, item));
+                }
+            }
+        }
+    }
+
+    fn prefix<'a, R: Into<InsertState<'a, I>>, I: Clone + Iterator<Item = &'a [u8]>>(&mut self, route: R) {
+        let route = route.into();
+
+        self.default.prefix(route.clone());
+        for &mut (_, ref mut item) in &mut self.constrained {
+            item.prefix(route.clone());
+        }
+    }
+}
+
+impl<T> Default for QueryRouter<T> {
+    fn default() -> QueryRouter<T> {
+        QueryRouter {
+            constrained: vec![],
+            default: None,
+        }
+    }
+}
+
+//Sorted by name, to make the order irrelevant.
+fn parse_constraints(constraints: &str) -> Vec<(String, Option<String>)> {
+    let mut constraints: Vec<_> = constraints.split('&').filter(|part| !part.is_empty()).map(|part| {
+        match part.find('=') {
+            Some(index) => (part[..index].to_owned(), Some(part[index + 1..].to_owned())),
+            None => (part.to_owned(), None)
+        }
+    }).collect();
+    constraints.sort();
+    constraints.dedup();
+    constraints
+}
+
+#[cfg(test)]
+mod test {
+    use router::{Router, QueryRouter, RouteState};
+    use context::Parameters;
+    use Context;
+    use Response;
+    use TreeRouter;
+    use Method::Get;
+    use super::parse_constraints;
+
+    fn handler(_: Context, _: Response) {}
+
+    fn find(router: &QueryRouter<TreeRouter<::router::MethodRouter<::router::Variables<fn(Context, Response)>>>>, path: &str, query: &[(&str, &str)]) -> Option<String> {
+        let mut parameters = Parameters::new();
+        for &(name, value) in query {
+            parameters.insert(name, value);
+        }
+
+        let mut route = RouteState::from(path);
+        route.set_query(Some(&parameters));
+        router.find(&Get, &mut route).variables.get("v".as_bytes()).map(|v| v.as_utf8_lossy().into_owned())
+    }
+
+    #[test]
+    fn constraints() {
+        assert_eq!(parse_constraints("b&a=1&&b"), vec![("a".to_owned(), Some("1".to_owned())), ("b".to_owned(), None)]);
+        assert_eq!(parse_constraints("a=&b=c=d"), vec![("a".to_owned(), Some("".to_owned())), ("b".to_owned(), Some("c=d".to_owned()))]);
+    }
+
+    #[test]
+    fn select_by_query() {
+        let mut router = QueryRouter::new();
+        router.insert(Get, "/report/:v", handler as fn(Context, Response));
+
+        let mut csv = TreeRouter::new();
+        csv.insert(Get, "/csv/:v", handler as fn(Context, Response));
+        router.insert_query("format=csv", csv);
+
+        let mut verbose_csv = TreeRouter::new();
+        verbose_csv.insert(Get, "/verbose/:v", handler as fn(Context, Response));
+        router.insert_query("verbose&format=csv", verbose_csv);
+
+        assert_eq!(find(&router, "/report/a", &[]), Some("a".to_owned()));
+        assert_eq!(find(&router, "/csv/a", &[]), None);
+        assert_eq!(find(&router, "/csv/a", &[("format", "csv")]), Some("a".to_owned()));
+        assert_eq!(find(&router, "/csv/a", &[("format", "json")]), None);
+        assert_eq!(find(&router, "/report/a", &[("format", "csv")]), Some("a".to_owned()));
+        assert_eq!(find(&router, "/verbose/a", &[("format", "csv")]), None);
+        assert_eq!(find(&router, "/verbose/a", &[("format", "csv"), ("verbose", "")]), Some("a".to_owned()));
+        assert_eq!(find(&router, "/csv/a", &[("format", "csv"), ("verbose", "1")]), Some("a".to_owned()));
+    }
+}
@@ -4,7 +4,7 @@ use std::iter::{Iterator, IntoIterator, FromIterator};
 use std::ops::Deref;
 use hyper::method::Method;
 
-use router::{Router, Route, Endpoint, MethodRouter, InsertState, RouteState, Variables};
+use router::{Router, Route, Endpoint, MethodRouter, InsertState, RouteState, Variables, RouteMeta, RouteInfo};
 use context::{MaybeUtf8Owned, MaybeUtf8Slice};
 use context::hypermedia::{Link, LinkSegment, SegmentType};
 use handler::Handler;
@@ -25,6 +25,11 @@ enum Branch {
 ///requested path must always be exhausted before sub-routers are searched, so
 ///there is no point in storing other path based routers in a `TreeRouter`.
 ///
+///The static children of each node are stored in a hash map, so looking up
+///a static segment takes the same time regardless of how many siblings it
+///has. Static segments are tried first, followed by variables and variable
+///sequences, so large route tables don't slow down routing.
+///
 ///The `TreeRouter` has support for shallow hyperlinks to children, siblings,
 ///cousins, and so forth. The use of variable sequences complicates this
 ///process and may cause confusing results in certain situations. The
@@ -76,6 +81,15 @@ impl<T: Router + Default> TreeRouter<T> {
         }
     }
 
+    //Like `find_or_insert_router`, but without inserting anything.
+    fn find_router_mut<'a>(&'a mut self, key: &[u8]) -> Option<&'a mut TreeRouter<T>> {
+        match key.get(0) {
+            Some(&b'*') => self.wildcard_route.as_mut().map(|router| &mut **router),
+            Some(&b':') => self.variable_route.as_mut().map(|router| &mut **router),
+            _ => self.static_routes.get_mut(key)
+        }
+    }
+
     //Mergers this TreeRouter with an other TreeRouter.
     fn merge_router<'a, I: Iterator<Item = &'a [u8]> + Clone>(&mut self, state: InsertState<'a, I>, router: TreeRouter<T>) {
         self.item.insert_router(state.clone(), router.item);
@@ -207,7 +221,7 @@ impl<T: Router + Default> Router for TreeRouter<T> {
                             stack.push((current, Wildcard, s));
                             route.go_to(snapshot);
 
-                            route.keep();
+                            route.keep_sequence();
                             let snapshot = route.snapshot();
                             stack.push((next, Wildcard, snapshot));
                             stack.push((next, Variable, snapshot));
@@ -254,6 +268,42 @@ impl<T: Router + Default> Router for TreeRouter<T> {
         links
     }
 
+    fn routes<'a>(&'a self) -> Vec<RouteInfo<'a>> {
+        let mut routes = self.item.routes();
+
+        for (segment, router) in &self.static_routes {
+            routes.extend(router.routes().into_iter().map(|mut route| {
+                route.path.insert(0, LinkSegment {
+                    label: segment.as_slice(),
+                    ty: SegmentType::Static
+                });
+                route
+            }));
+        }
+
+        if let Some(ref router) = self.variable_route {
+            routes.extend(router.routes().into_iter().map(|mut route| {
+                route.path.insert(0, LinkSegment {
+                    label: MaybeUtf8Slice::new(),
+                    ty: SegmentType::VariableSegment
+                });
+                route
+            }));
+        }
+
+        if let Some(ref router) = self.wildcard_route {
+            routes.extend(router.routes().into_iter().map(|mut route| {
+                route.path.insert(0, LinkSegment {
+                    label: MaybeUtf8Slice::new(),
+                    ty: SegmentType::VariableSequence
+                });
+                route
+            }));
+        }
+
+        routes
+    }
+
     fn build<'a, R: Into<InsertState<'a, I>>, I: Iterator<Item = &'a [u8]>>(method: Method, route: R, item: Self::Handler) -> TreeRouter<T> {
         let mut router = TreeRouter::default();
         router.insert(method, route, item);
@@ -269,6 +319,18 @@ impl<T: Router + Default> Router for TreeRouter<T> {
         endpoint.item.insert(method, route, item);
     }
 
+    fn set_meta<'a, R: Into<InsertState<'a, I>>, I: Iterator<Item = &'a [u8]>>(&mut self, method: Method, route: R, meta: RouteMeta) {
+        let mut route = route.into();
+        let endpoint = (&mut route).fold(Some(self), |endpoint, segment| {
+            endpoint.and_then(|endpoint| endpoint.find_router_mut(segment))
+        });
+
+        //Routes that don't exist are left out.
+        if let Some(endpoint) = endpoint {
+            endpoint.item.set_meta(method, route, meta);
+        }
+    }
+
     fn insert_router<'a, R: Into<InsertState<'a, I>>, I: Clone + Iterator<Item = &'a [u8]>>(&mut self, route: R, router: TreeRouter<T>) {
         let mut route = route.into();
         let mut endpoint = (&mut route).fold(self, |endpoint, segment| {
@@ -792,6 +854,155 @@ mod test {
         check!(router1(&Get, b"a/path/to") => Some("test 2"), {"a" => "a"});
         check!(router1(&Get, b"a/path") => None, [["to"], ["test1"]]);
     }
+
+    #[test]
+    fn allowed_methods() {
+        let routes: Vec<(Method, &str, TestHandler)> = vec![
+            (Get, "", "test 1".into()),
+            (Get, "path/:a", "test 2".into()),
+            (Post, "path/:a", "test 3".into()),
+            (Delete, "path/to", "test 4".into()),
+            (Put, "files/*path", "test 5".into()),
+            (Method::Extension("PURGE".into()), "files/*path", "test 6".into())
+        ];
+
+        let router = routes.into_iter().collect::<TreeRouter<_>>();
+
+        assert_eq!(router.allowed_methods("/"), vec![Get]);
+        assert_eq!(router.allowed_methods("/path/anything"), vec![Get, Post]);
+        //The variable route is used for the other methods.
+        assert_eq!(router.allowed_methods("/path/to"), vec![Delete, Get, Post]);
+        assert_eq!(router.allowed_methods("/files/a/b/c"), vec![Method::Extension("PURGE".into()), Put]);
+        assert_eq!(router.allowed_methods("/missing"), vec![]);
+        assert_eq!(router.allowed_methods("/path/anything/more"), vec![]);
+    }
+
+    #[test]
+    fn route_many_methods() {
+        let mut router = TreeRouter::new();
+        router.route(&[Get, Head, Post], "path/:a", TestHandler::from("test 1"));
+        router.route(&[], "path/to", TestHandler::from("test 2"));
+
+        check!(router(&Get, b"path/to") => Some("test 1"), {"a" => "to"});
+        check!(router(&Head, b"path/to") => Some("test 1"), {"a" => "to"});
+        check!(router(&Post, b"path/to") => Some("test 1"), {"a" => "to"});
+        check!(router(&Put, b"path/to") => None);
+        assert_eq!(router.allowed_methods("/path/to"), vec![Get, Head, Post]);
+    }
+
+    #[test]
+    fn match_debug() {
+        use std::collections::HashMap;
+        use router::MatchOutcome;
+
+        let mut router = TreeRouter::new();
+        router.insert(Get, "users/:id", TestHandler::from("test 1"));
+        router.insert(Get, "users/new", TestHandler::from("test 2"));
+        router.insert(Delete, "users/:id", TestHandler::from("test 3"));
+        router.insert(Get, "files/*path", TestHandler::from("test 4"));
+
+        let outcome = router.match_debug(&Get, "/users/new");
+        assert_eq!(outcome, MatchOutcome::Matched {
+            pattern: Some("/users/new".to_owned()),
+            variables: HashMap::new()
+        });
+
+        match router.match_debug(&Delete, "/users/new") {
+            MatchOutcome::Matched { pattern, variables } => {
+                assert_eq!(pattern, Some("/users/:id".to_owned()));
+                assert_eq!(variables.get("id".as_bytes()).unwrap(), "new");
+            },
+            other => panic!("unexpected outcome: {:?}", other)
+        }
+
+        match router.match_debug(&Get, "/files/a/b") {
+            MatchOutcome::Matched { pattern, variables } => {
+                assert_eq!(pattern, Some("/files/*path".to_owned()));
+                assert_eq!(variables.get("path".as_bytes()).unwrap(), "a/b");
+            },
+            other => panic!("unexpected outcome: {:?}", other)
+        }
+
+        assert_eq!(router.match_debug(&Post, "/users/1"), MatchOutcome::MethodMismatch(vec![Delete, Get]));
+        assert_eq!(router.match_debug(&Get, "/posts"), MatchOutcome::NoPath);
+    }
+
+    #[test]
+    fn record_pattern() {
+        use router::RouteState;
+
+        let mut router = TreeRouter::new();
+        router.insert(Get, "", TestHandler::from("test 1"));
+        router.insert(Get, "users/:id/posts", TestHandler::from("test 2"));
+        router.insert(Get, "files/*path", TestHandler::from("test 3"));
+
+        let find_pattern = |path: &str| {
+            let mut route = RouteState::from(path);
+            route.set_record_pattern(true);
+            router.find(&Get, &mut route);
+            route.pattern().map(|pattern| pattern.to_owned())
+        };
+
+        assert_eq!(find_pattern("/"), Some("/".to_owned()));
+        assert_eq!(find_pattern("/users/1/posts"), Some("/users/:id/posts".to_owned()));
+        assert_eq!(find_pattern("/files/a"), Some("/files/*path".to_owned()));
+        assert_eq!(find_pattern("/files/a/b/c"), Some("/files/*path".to_owned()));
+        assert_eq!(find_pattern("/missing"), None);
+
+        let mut route = RouteState::from("/users/1/posts");
+        router.find(&Get, &mut route);
+        assert_eq!(route.pattern(), None);
+    }
+
+    #[test]
+    fn many_static_siblings() {
+        let mut router = TreeRouter::new();
+        for i in 0..1000 {
+            router.insert(Get, &*format!("items/item{}", i), TestHandler::from("static"));
+        }
+        router.insert(Get, "items/:id", TestHandler::from("variable"));
+        router.insert(Get, "items/item500/*rest", TestHandler::from("wildcard"));
+        router.insert(Get, "items/:id/details", TestHandler::from("details"));
+
+        //Static segments still take priority over variables and wildcards.
+        check!(router(&Get, b"items/item0") => Some("static"));
+        check!(router(&Get, b"items/item999") => Some("static"));
+        check!(router(&Get, b"items/item1000") => Some("variable"), {"id" => "item1000"});
+        check!(router(&Get, b"items/item500/a/b") => Some("wildcard"), {"rest" => "a/b"});
+        check!(router(&Get, b"items/item500/details") => Some("wildcard"), {"rest" => "details"});
+        check!(router(&Get, b"items/item501/details") => Some("details"), {"id" => "item501"});
+        check!(router(&Get, b"items/item501/more") => None);
+    }
+
+    #[test]
+    fn list_routes() {
+        use router::RouteMeta;
+
+        let routes: Vec<(Method, &str, TestHandler)> = vec![
+            (Get, "", "test 1".into()),
+            (Get, "path/:a/*b", "test 2".into()),
+            (Post, "path/:a/*b", "test 3".into())
+        ];
+
+        let mut router = routes.into_iter().collect::<TreeRouter<_>>();
+        router.set_meta(Post, "path/:a/*b", RouteMeta::new("test 3").tag("tests"));
+        router.set_meta(Get, "missing/route", RouteMeta::new("missing"));
+        assert_eq!(router.static_routes.len(), 1);
+        router.insert_with_meta(Put, "path/to", "test 4".into(), RouteMeta::new("test 4"));
+        router.prefix(":c");
+
+        let mut routes: Vec<_> = router.routes().into_iter().map(|route| {
+            (route.pattern(), route.method.unwrap(), route.meta.and_then(|meta| meta.summary.clone()))
+        }).collect();
+        routes.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.as_ref().cmp(b.1.as_ref())));
+
+        assert_eq!(routes, vec![
+            ("/:c".to_owned(), Get, None),
+            ("/:c/path/:a/*b".to_owned(), Get, None),
+            ("/:c/path/:a/*b".to_owned(), Post, Some("test 3".to_owned())),
+            ("/:c/path/to".to_owned(), Put, Some("test 4".to_owned()))
+        ]);
+    }
     
     #[bench]
     #[cfg(feature = "benchmark")]
@@ -865,4 +1076,24 @@ mod test {
             counter = (counter + 1) % paths.len()
         });
     }
+
+    #[bench]
+    #[cfg(feature = "benchmark")]
+    fn large_table_speed(b: &mut Bencher) {
+        let mut routes: Vec<(_, _, TestHandler)> = vec![];
+        for i in 0..1000 {
+            routes.push((Get, format!("api/resource{}/:id", i), "test 1".into()));
+        }
+        routes.push((Get, "api/:resource/:id".to_owned(), "test 2".into()));
+
+        let paths: Vec<_> = (0..1000).map(|i| format!("api/resource{}/42", i * 7 % 1010)).collect();
+
+        let router = routes.iter().map(|&(ref method, ref path, ref handler)| (method.clone(), &**path, handler.clone())).collect::<TreeRouter<_>>();
+        let mut counter = 0;
+
+        b.iter(|| {
+            router.find(&Get, &mut (paths[counter].as_str().into()));
+            counter = (counter + 1) % paths.len()
+        });
+    }
 }
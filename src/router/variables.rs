@@ -1,4 +1,4 @@
-use router::{Router, Endpoint, InsertState, RouteState};
+use router::{Router, Endpoint, InsertState, RouteState, RouteMeta, RouteInfo};
 use context::MaybeUtf8Owned;
 use context::hypermedia::Link;
 use {Method, Handler};
@@ -14,12 +14,14 @@ use {Method, Handler};
 pub struct Variables<H: Handler> {
     handler: H,
     variables: Vec<MaybeUtf8Owned>,
+    meta: Option<RouteMeta>,
 }
 
 impl<H: Handler> Router for Variables<H> {
     type Handler = H;
 
     fn find<'a>(&'a self, _method: &Method, route: &mut RouteState) -> Endpoint<'a, H> {
+        route.record_pattern(&self.variables);
         Endpoint {
             handler: Some(&self.handler),
             variables: route.variables(&self.variables),
@@ -32,10 +34,17 @@ impl<H: Handler> Router for Variables<H> {
         vec![base]
     }
 
+    fn routes<'a>(&'a self) -> Vec<RouteInfo<'a>> {
+        let mut route = RouteInfo::new(self.meta.as_ref());
+        route.variables = self.variables.iter().map(|v| v.as_slice()).collect();
+        vec![route]
+    }
+
     fn build<'a, R: Into<InsertState<'a, I>>, I: Iterator<Item = &'a [u8]>>(_method: Method, route: R, item: Self::Handler) -> Variables<H> {
         Variables {
             handler: item,
             variables: route.into().variables(),
+            meta: None,
         }
     }
 
@@ -44,6 +53,10 @@ impl<H: Handler> Router for Variables<H> {
         self.variables = route.into().variables();
     }
 
+    fn set_meta<'a, R: Into<InsertState<'a, I>>, I: Iterator<Item = &'a [u8]>>(&mut self, _method: Method, _route: R, meta: RouteMeta) {
+        self.meta = Some(meta);
+    }
+
     fn insert_router<'a, R: Into<InsertState<'a, I>>, I: Clone + Iterator<Item = &'a [u8]>>(&mut self, route: R, mut router: Variables<H>) {
         router.prefix(route);
         *self = router;
@@ -61,6 +74,7 @@ impl<H: Handler + Default> Default for Variables<H> {
         Variables {
             handler: H::default(),
             variables: vec![],
+            meta: None,
         }
     }
 }
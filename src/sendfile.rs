@@ -0,0 +1,44 @@
+//Zero-copy transfers from files to sockets, using `sendfile(2)`.
+
+use std::cmp::min;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use libc;
+
+//The largest number of bytes that Linux transfers in one call.
+const MAX_COUNT: u64 = 0x7fff_f000;
+
+//Send up to `length` bytes from `file`, starting at `offset`, to `socket`.
+//The number of bytes that were sent is returned, which is 0 at the end of
+//the file.
+pub fn send(socket: RawFd, file: &File, offset: u64, length: u64) -> io::Result<usize> {
+    if offset > libc::off_t::max_value() as u64 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "the file offset is too large for sendfile"));
+    }
+
+    let mut offset = offset as libc::off_t;
+    let count = min(length, MAX_COUNT) as usize;
+
+    loop {
+        let sent = unsafe { libc::sendfile(socket, file.as_raw_fd(), &mut offset, count) };
+        if sent >= 0 {
+            return Ok(sent as usize);
+        }
+
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+//Check if `sendfile` can't be used for this file or socket at all, as
+//opposed to failing because of the connection.
+pub fn is_unsupported(error: &io::Error) -> bool {
+    match error.raw_os_error() {
+        Some(libc::EINVAL) | Some(libc::ENOSYS) | Some(libc::EOPNOTSUPP) => true,
+        _ => error.kind() == io::ErrorKind::InvalidInput
+    }
+}
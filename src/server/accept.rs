@@ -0,0 +1,214 @@
+//A listener that backs off when accepting connections fails.
+
+use std::cmp::min;
+use std::io;
+use std::net::SocketAddr;
+use std::thread;
+use std::time::Duration;
+
+use hyper::net::NetworkListener;
+#[cfg(all(unix, feature = "sendfile"))]
+use libc::EBADF;
+
+use HttpResult;
+use HttpError;
+use server::{AcceptBackoff, Lifecycle};
+
+//How an accept error should be handled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Failure {
+    //Only the connection failed, like when the client disconnected or the
+    //TLS handshake failed, so the next one can be accepted right away.
+    Connection,
+
+    //The server has run out of something, like file descriptors, and may
+    //recover after a while.
+    Transient,
+
+    //The listener itself is broken, and won't accept anything again.
+    Fatal,
+}
+
+//The "bad file descriptor" error code, which is the same on all Unix
+//platforms. It comes from libc when it's available.
+#[cfg(not(all(unix, feature = "sendfile")))]
+const EBADF: i32 = 9;
+
+fn classify(error: &HttpError) -> Failure {
+    match *error {
+        HttpError::Io(ref e) => match e.kind() {
+            io::ErrorKind::ConnectionAborted |
+            io::ErrorKind::ConnectionReset |
+            io::ErrorKind::Interrupted => Failure::Connection,
+            io::ErrorKind::InvalidInput => Failure::Fatal,
+            _ if cfg!(unix) && e.raw_os_error() == Some(EBADF) => Failure::Fatal,
+            _ => Failure::Transient
+        },
+        _ => Failure::Connection
+    }
+}
+
+//Waits before trying again when accepting connections fails because of a
+//transient error, instead of retrying in a tight loop. The delay is reset
+//when a connection is accepted. A broken listener is reported to the
+//lifecycle.
+#[derive(Clone)]
+pub struct BackoffListener<L> {
+    listener: L,
+    backoff: AcceptBackoff,
+    lifecycle: Lifecycle,
+    delay: Option<Duration>,
+}
+
+impl<L: NetworkListener> BackoffListener<L> {
+    pub fn new(listener: L, backoff: AcceptBackoff, lifecycle: Lifecycle) -> BackoffListener<L> {
+        BackoffListener {
+            listener: listener,
+            backoff: backoff,
+            lifecycle: lifecycle,
+            delay: None,
+        }
+    }
+
+    //Accept the next connection, or return the error that broke the
+    //listener. Connection errors are passed on to Hyper, since it will try
+    //again anyway.
+    fn try_accept(&mut self) -> Result<HttpResult<L::Stream>, HttpError> {
+        loop {
+            match self.listener.accept() {
+                Ok(stream) => {
+                    self.delay = None;
+                    return Ok(Ok(stream));
+                },
+                Err(e) => match classify(&e) {
+                    Failure::Connection => return Ok(Err(e)),
+                    Failure::Transient => {
+                        let delay = self.next_delay();
+                        let millis = delay.as_secs() * 1000 + (delay.subsec_nanos() / 1_000_000) as u64;
+                        warn!(target: "rustful::server", "could not accept a connection, trying again in {} ms: {}", millis, e);
+                        thread::sleep(delay);
+                    },
+                    Failure::Fatal => return Err(e)
+                }
+            }
+        }
+    }
+
+    fn next_delay(&mut self) -> Duration {
+        let delay = match self.delay {
+            Some(delay) => delay.checked_mul(2).unwrap_or(self.backoff.max),
+            None => self.backoff.initial
+        };
+        let delay = min(delay, self.backoff.max);
+        self.delay = Some(delay);
+        delay
+    }
+}
+
+impl<L: NetworkListener> NetworkListener for BackoffListener<L> {
+    type Stream = L::Stream;
+
+    fn accept(&mut self) -> HttpResult<L::Stream> {
+        match self.try_accept() {
+            Ok(result) => result,
+            Err(e) => {
+                if self.lifecycle.failure().is_none() {
+                    error!(target: "rustful::server", "the listener failed, and the server will shut down: {}", e);
+                }
+                self.lifecycle.fail(e.to_string());
+
+                //Hyper will call again right away, and the error won't go
+                //away, so it's returned at the slowest pace.
+                thread::sleep(self.backoff.max);
+                Err(e)
+            }
+        }
+    }
+
+    fn local_addr(&mut self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::net::TcpStream;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use hyper::net::{NetworkListener, HttpListener};
+
+    use HttpResult;
+    use HttpError;
+    use server::{AcceptBackoff, Lifecycle, LifecycleState};
+    use super::BackoffListener;
+
+    //Fails with the injected errors before accepting real connections.
+    #[derive(Clone)]
+    struct Failing {
+        errors: Arc<Mutex<Vec<io::Error>>>,
+        listener: HttpListener,
+    }
+
+    impl NetworkListener for Failing {
+        type Stream = <HttpListener as NetworkListener>::Stream;
+
+        fn accept(&mut self) -> HttpResult<Self::Stream> {
+            match self.errors.lock().unwrap().pop() {
+                Some(e) => Err(HttpError::Io(e)),
+                None => self.listener.accept()
+            }
+        }
+
+        fn local_addr(&mut self) -> io::Result<::std::net::SocketAddr> {
+            self.listener.local_addr()
+        }
+    }
+
+    fn listener(errors: Vec<io::Error>) -> BackoffListener<Failing> {
+        let failing = Failing {
+            errors: Arc::new(Mutex::new(errors)),
+            listener: HttpListener::new("127.0.0.1:0").unwrap(),
+        };
+
+        BackoffListener::new(failing, AcceptBackoff {
+            initial: Duration::from_millis(20),
+            max: Duration::from_millis(30),
+        }, Lifecycle::new())
+    }
+
+    #[test]
+    fn transient_errors() {
+        //EMFILE, too many open files, on Unix.
+        let errors = (0..3).map(|_| io::Error::from_raw_os_error(24)).collect();
+        let mut listener = listener(errors);
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+
+        let started = Instant::now();
+        assert!(listener.try_accept().unwrap().is_ok());
+        assert!(started.elapsed() >= Duration::from_millis(20 + 30 + 30));
+        assert_eq!(listener.delay, None);
+    }
+
+    #[test]
+    fn connection_errors() {
+        let mut listener = listener(vec![io::Error::new(io::ErrorKind::ConnectionAborted, "aborted")]);
+
+        let started = Instant::now();
+        assert!(listener.try_accept().unwrap().is_err());
+        assert!(started.elapsed() < Duration::from_millis(20));
+    }
+
+    #[test]
+    fn fatal_errors() {
+        let mut broken = listener(vec![io::Error::new(io::ErrorKind::InvalidInput, "not listening")]);
+        assert!(broken.try_accept().is_err());
+
+        let mut broken = listener(vec![io::Error::new(io::ErrorKind::InvalidInput, "not listening")]);
+        let lifecycle = broken.lifecycle.clone();
+        assert!(broken.accept().is_err());
+        assert_eq!(lifecycle.failure(), Some("not listening".to_owned()));
+        assert_eq!(lifecycle.state(), LifecycleState::Stopped);
+    }
+}
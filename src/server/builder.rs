@@ -0,0 +1,461 @@
+#[cfg(feature = "ssl")]
+use std::path::PathBuf;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use hyper::mime::Mime;
+
+use filter::{ContextFilter, ResponseFilter};
+use header::{Header, HeaderFormat};
+use router::Router;
+use session::{SessionConfig, CookieKeys};
+#[cfg(feature = "encoding")]
+use utils::Charset;
+#[cfg(feature = "rustc_json_body")]
+use context::BodyDeserializer;
+use Method;
+
+use server::{Server, Host, Global, KeepAlive, AcceptBackoff, PathNormalization, LargeBodyLimit, UnexpectedBody, CompletionObserver, PathRewrite, ContinuePolicy, Metrics, Lifecycle, ConfigError};
+#[cfg(feature = "ssl")]
+use server::Scheme;
+
+///A chainable alternative to the `Server` struct.
+///
+///Each setter corresponds to a field in `Server`, where the details and the
+///default values are documented. Filters and observers are added one at a
+///time, to the end of their stacks. The combination of settings is checked
+///by `build`, which returns a `Server` if it's valid.
+///
+///```no_run
+///use rustful::{Server, Context, Response};
+///use rustful::server::KeepAlive;
+///use std::time::Duration;
+///
+///fn hello(_context: Context, response: Response) {
+///    response.send("hello");
+///}
+///
+///let server = Server::builder(hello as fn(Context, Response))
+///    .host(8080.into())
+///    .threads(8)
+///    .keep_alive(KeepAlive {
+///        timeout: Duration::from_secs(5),
+///        free_threads: 2,
+///    })
+///    .max_body_length(1024 * 1024)
+///    .build()
+///    .expect("invalid server configuration");
+///
+///server.run().expect("could not start the server");
+///```
+pub struct ServerBuilder<R: Router> {
+    server: Server<R>,
+    #[cfg(feature = "ssl")]
+    tls_cert: Option<PathBuf>,
+    #[cfg(feature = "ssl")]
+    tls_key: Option<PathBuf>,
+}
+
+impl<R: Router> ServerBuilder<R> {
+    ///Start with the default settings and `handlers`.
+    pub fn new(handlers: R) -> ServerBuilder<R> {
+        ServerBuilder {
+            server: Server::new(handlers),
+            #[cfg(feature = "ssl")]
+            tls_cert: None,
+            #[cfg(feature = "ssl")]
+            tls_key: None,
+        }
+    }
+
+    ///Set the fallback handler.
+    pub fn fallback_handler(mut self, handler: R::Handler) -> ServerBuilder<R> {
+        self.server.fallback_handler = Some(handler);
+        self
+    }
+
+    ///Set the host address and port.
+    pub fn host(mut self, host: Host) -> ServerBuilder<R> {
+        self.server.host = host;
+        self
+    }
+
+    ///Listen for HTTPS connections, using the certificate and key files at
+    ///the given paths.
+    #[cfg(feature = "ssl")]
+    pub fn tls<C: Into<PathBuf>, K: Into<PathBuf>>(self, cert: C, key: K) -> ServerBuilder<R> {
+        self.tls_cert(cert).tls_key(key)
+    }
+
+    ///Set the path to the TLS certificate. HTTPS is used if both a
+    ///certificate and a key is set.
+    #[cfg(feature = "ssl")]
+    pub fn tls_cert<P: Into<PathBuf>>(mut self, path: P) -> ServerBuilder<R> {
+        self.tls_cert = Some(path.into());
+        self
+    }
+
+    ///Set the path to the TLS key. HTTPS is used if both a certificate and a
+    ///key is set.
+    #[cfg(feature = "ssl")]
+    pub fn tls_key<P: Into<PathBuf>>(mut self, path: P) -> ServerBuilder<R> {
+        self.tls_key = Some(path.into());
+        self
+    }
+
+    ///Set or clear the `IPV6_V6ONLY` option.
+    pub fn ipv6_only(mut self, ipv6_only: Option<bool>) -> ServerBuilder<R> {
+        self.server.ipv6_only = ipv6_only;
+        self
+    }
+
+    ///Enable or disable `TCP_NODELAY`.
+    pub fn tcp_nodelay(mut self, nodelay: bool) -> ServerBuilder<R> {
+        self.server.tcp_nodelay = nodelay;
+        self
+    }
+
+    ///Set `SO_LINGER`.
+    pub fn linger(mut self, linger: Duration) -> ServerBuilder<R> {
+        self.server.linger = Some(linger);
+        self
+    }
+
+    ///Set the name prefix of the worker threads.
+    pub fn thread_name_prefix<S: Into<String>>(mut self, prefix: S) -> ServerBuilder<R> {
+        self.server.thread_name_prefix = prefix.into();
+        self
+    }
+
+    ///Set the number of threads in the thread pool.
+    pub fn threads(mut self, threads: usize) -> ServerBuilder<R> {
+        self.server.threads = Some(threads);
+        self
+    }
+
+    ///Allow `keep-alive` connections.
+    pub fn keep_alive(mut self, keep_alive: KeepAlive) -> ServerBuilder<R> {
+        self.server.keep_alive = Some(keep_alive);
+        self
+    }
+
+    ///Set how long to wait before trying again, when accepting a connection
+    ///fails.
+    pub fn accept_backoff(mut self, backoff: AcceptBackoff) -> ServerBuilder<R> {
+        self.server.accept_backoff = backoff;
+        self
+    }
+
+    ///Set how request paths should be normalized.
+    pub fn path_normalization(mut self, normalization: PathNormalization) -> ServerBuilder<R> {
+        self.server.path_normalization = normalization;
+        self
+    }
+
+    ///Set the time after which a request is reported as slow.
+    pub fn slow_request_threshold(mut self, threshold: Duration) -> ServerBuilder<R> {
+        self.server.slow_request_threshold = Some(threshold);
+        self
+    }
+
+    ///Set the advisory deadline for each request.
+    pub fn request_timeout(mut self, timeout: Duration) -> ServerBuilder<R> {
+        self.server.request_timeout = Some(timeout);
+        self
+    }
+
+    ///Set the largest accepted request body, in bytes.
+    pub fn max_body_length(mut self, length: u64) -> ServerBuilder<R> {
+        self.server.max_body_length = Some(length);
+        self
+    }
+
+    ///Limit how many requests with large bodies can be handled at once.
+    pub fn large_body_limit(mut self, limit: LargeBodyLimit) -> ServerBuilder<R> {
+        self.server.large_body_limit = Some(limit);
+        self
+    }
+
+    ///Set the largest unread request body that is drained automatically.
+    pub fn auto_drain_limit(mut self, limit: u64) -> ServerBuilder<R> {
+        self.server.auto_drain_limit = Some(limit);
+        self
+    }
+
+    ///Set what to do with request bodies for `method`, replacing any earlier
+    ///choice for the same method.
+    pub fn unexpected_body(mut self, method: Method, policy: UnexpectedBody) -> ServerBuilder<R> {
+        self.server.unexpected_bodies.retain(|&(ref m, _)| *m != method);
+        self.server.unexpected_bodies.push((method, policy));
+        self
+    }
+
+    ///Set the hook that decides if requests with `Expect: 100-continue` may
+    ///send their bodies.
+    pub fn continue_policy<P: ContinuePolicy + 'static>(mut self, policy: P) -> ServerBuilder<R> {
+        self.server.continue_policy = Some(Box::new(policy));
+        self
+    }
+
+    ///Set the largest accepted chunk of a chunked request body, in bytes.
+    pub fn max_chunk_size(mut self, size: u64) -> ServerBuilder<R> {
+        self.server.max_chunk_size = size;
+        self
+    }
+
+    ///Set the longest accepted chunk extension, in bytes.
+    pub fn max_chunk_extension_length(mut self, length: usize) -> ServerBuilder<R> {
+        self.server.max_chunk_extension_length = length;
+        self
+    }
+
+    ///Set the longest time to wait for more of a request body.
+    pub fn body_read_timeout(mut self, timeout: Duration) -> ServerBuilder<R> {
+        self.server.body_read_timeout = Some(timeout);
+        self
+    }
+
+    ///Set the longest time it may take to receive a whole request body.
+    pub fn body_read_deadline(mut self, deadline: Duration) -> ServerBuilder<R> {
+        self.server.body_read_deadline = Some(deadline);
+        self
+    }
+
+    ///Set the longest time a write to a slow client may be blocked.
+    pub fn write_timeout(mut self, timeout: Duration) -> ServerBuilder<R> {
+        self.server.write_timeout = Some(timeout);
+        self
+    }
+
+    ///Set the largest number of header fields in a request.
+    pub fn max_headers(mut self, max_headers: usize) -> ServerBuilder<R> {
+        self.server.max_headers = max_headers;
+        self
+    }
+
+    ///Set the largest number of parsed query parameters and form fields.
+    pub fn max_parameters(mut self, max_parameters: usize) -> ServerBuilder<R> {
+        self.server.max_parameters = max_parameters;
+        self
+    }
+
+    ///Set the longest accepted request target, in bytes.
+    pub fn max_target_length(mut self, length: usize) -> ServerBuilder<R> {
+        self.server.max_target_length = length;
+        self
+    }
+
+    ///Set the size of the buffer for streamed response bodies.
+    pub fn stream_buffer_size(mut self, size: usize) -> ServerBuilder<R> {
+        self.server.stream_buffer_size = size;
+        self
+    }
+
+    ///Set the largest chunked response body that is sent with a
+    ///`Content-Length` instead.
+    pub fn auto_content_length(mut self, limit: usize) -> ServerBuilder<R> {
+        self.server.auto_content_length = limit;
+        self
+    }
+
+    ///Set the content of the server header.
+    pub fn server_name<S: Into<String>>(mut self, name: S) -> ServerBuilder<R> {
+        self.server.server = name.into();
+        self
+    }
+
+    ///Set the default media type.
+    pub fn content_type(mut self, content_type: Mime) -> ServerBuilder<R> {
+        self.server.content_type = content_type;
+        self
+    }
+
+    ///Add a header to the default response headers.
+    pub fn default_header<H: Header + HeaderFormat>(mut self, header: H) -> ServerBuilder<R> {
+        self.server.default_headers.set(header);
+        self
+    }
+
+    ///Enable or disable the automatic `Vary` header.
+    pub fn auto_vary(mut self, auto_vary: bool) -> ServerBuilder<R> {
+        self.server.auto_vary = auto_vary;
+        self
+    }
+
+    ///Set the globally accessible data.
+    pub fn global<G: Into<Global>>(mut self, global: G) -> ServerBuilder<R> {
+        self.server.global = global.into();
+        self
+    }
+
+    ///Add a context filter to the end of the stack.
+    pub fn context_filter<F: ContextFilter + 'static>(mut self, filter: F) -> ServerBuilder<R> {
+        self.server.context_filters.push(Box::new(filter));
+        self
+    }
+
+    ///Add a response filter to the end of the stack.
+    pub fn response_filter<F: ResponseFilter + 'static>(mut self, filter: F) -> ServerBuilder<R> {
+        self.server.response_filters.push(Box::new(filter));
+        self
+    }
+
+    ///Add a completion observer.
+    pub fn completion_observer<O: CompletionObserver + 'static>(mut self, observer: O) -> ServerBuilder<R> {
+        self.server.completion_observers.push(Box::new(observer));
+        self
+    }
+
+    ///Set the hook that rewrites request paths before routing.
+    pub fn path_rewrite<P: PathRewrite + 'static>(mut self, rewrite: P) -> ServerBuilder<R> {
+        self.server.path_rewrite = Some(Box::new(rewrite));
+        self
+    }
+
+    ///Set the sink for request metrics.
+    pub fn metrics<M: Metrics + 'static>(mut self, metrics: M) -> ServerBuilder<R> {
+        self.server.metrics = Some(Box::new(metrics));
+        self
+    }
+
+    ///Add a header to the list of redacted headers.
+    pub fn redact_header<S: Into<String>>(mut self, name: S) -> ServerBuilder<R> {
+        self.server.redacted_headers.push(name.into());
+        self
+    }
+
+    ///Set how many bytes of each response body to capture for the completion
+    ///observers.
+    pub fn capture_response_body(mut self, limit: usize) -> ServerBuilder<R> {
+        self.server.capture_response_body = Some(limit);
+        self
+    }
+
+    ///Use an existing lifecycle handle.
+    pub fn lifecycle(mut self, lifecycle: Lifecycle) -> ServerBuilder<R> {
+        self.server.lifecycle = lifecycle;
+        self
+    }
+
+    ///Set the hook that is called before the server starts accepting
+    ///connections.
+    pub fn on_start<F: Fn() + Send + Sync + 'static>(mut self, hook: F) -> ServerBuilder<R> {
+        self.server.on_start = Some(Box::new(hook));
+        self
+    }
+
+    ///Set the hook that is called when the server has drained its requests
+    ///after shutdown has begun.
+    pub fn on_shutdown<F: Fn() + Send + Sync + 'static>(mut self, hook: F) -> ServerBuilder<R> {
+        self.server.on_shutdown = Some(Box::new(hook));
+        self
+    }
+
+    ///Add an address to the list of trusted proxies.
+    pub fn trusted_proxy(mut self, address: IpAddr) -> ServerBuilder<R> {
+        self.server.trusted_proxies.push(address);
+        self
+    }
+
+    ///Set the encoding of query and form values that are not UTF-8.
+    #[cfg(feature = "encoding")]
+    pub fn fallback_charset(mut self, charset: Charset) -> ServerBuilder<R> {
+        self.server.fallback_charset = Some(charset);
+        self
+    }
+
+    ///Register a body deserializer for `content_type`, to be used by
+    ///`Context::read_typed`.
+    #[cfg(feature = "rustc_json_body")]
+    pub fn body_deserializer<D: BodyDeserializer>(mut self, content_type: &str, deserializer: D) -> ServerBuilder<R> {
+        self.server.body_deserializers.insert(content_type, deserializer);
+        self
+    }
+
+    ///Set the keys for signed and private cookies.
+    pub fn cookie_keys(mut self, keys: CookieKeys) -> ServerBuilder<R> {
+        self.server.cookie_keys = Some(keys);
+        self
+    }
+
+    ///Enable sessions.
+    pub fn sessions(mut self, sessions: SessionConfig) -> ServerBuilder<R> {
+        self.server.sessions = Some(sessions);
+        self
+    }
+
+    ///Check the settings and produce a `Server`.
+    pub fn build(self) -> Result<Server<R>, ConfigError> {
+        #[cfg_attr(not(feature = "ssl"), allow(unused_mut))]
+        let mut server = self.server;
+
+        #[cfg(feature = "ssl")]
+        match (self.tls_cert, self.tls_key) {
+            (Some(cert), Some(key)) => server.scheme = Scheme::Https {
+                cert: cert,
+                key: key
+            },
+            (Some(_), None) => return Err(ConfigError::MissingTlsKey),
+            (None, Some(_)) => return Err(ConfigError::MissingTlsCert),
+            (None, None) => {}
+        }
+
+        if server.threads == Some(0) {
+            return Err(ConfigError::NoThreads);
+        }
+
+        if server.sessions.as_ref().map_or(false, |sessions| sessions.key.is_empty()) {
+            return Err(ConfigError::MissingSessionKey);
+        }
+
+        if server.cookie_keys.as_ref().map_or(false, |keys| keys.is_missing_key()) {
+            return Err(ConfigError::MissingCookieKey);
+        }
+
+        Ok(server)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use Context;
+    use Response;
+    use Server;
+    use session::{SessionConfig, CookieKeys};
+    use server::ConfigError;
+
+    fn hello(_context: Context, response: Response) {
+        response.send("hello");
+    }
+
+    #[test]
+    fn validate() {
+        let server = Server::builder(hello as fn(Context, Response))
+            .host(8080.into())
+            .threads(2)
+            .max_body_length(10)
+            .build()
+            .unwrap();
+        assert_eq!(server.threads, Some(2));
+        assert_eq!(server.max_body_length, Some(10));
+
+        let error = Server::builder(hello as fn(Context, Response)).threads(0).build().err();
+        assert_eq!(error, Some(ConfigError::NoThreads));
+
+        let error = Server::builder(hello as fn(Context, Response)).sessions(SessionConfig::default()).build().err();
+        assert_eq!(error, Some(ConfigError::MissingSessionKey));
+
+        let error = Server::builder(hello as fn(Context, Response)).cookie_keys(CookieKeys::new("")).build().err();
+        assert_eq!(error, Some(ConfigError::MissingCookieKey));
+    }
+
+    #[test]
+    #[cfg(feature = "ssl")]
+    fn validate_tls() {
+        let error = Server::builder(hello as fn(Context, Response)).tls_cert("cert.pem").build().err();
+        assert_eq!(error, Some(ConfigError::MissingTlsKey));
+
+        let error = Server::builder(hello as fn(Context, Response)).tls_key("key.pem").build().err();
+        assert_eq!(error, Some(ConfigError::MissingTlsCert));
+    }
+}
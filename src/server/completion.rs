@@ -0,0 +1,145 @@
+use std::net::SocketAddr;
+
+use Method;
+use StatusCode;
+use header::Headers;
+use context::UriPath;
+
+//The replacement value for redacted headers.
+const REDACTED: &'static str = "[REDACTED]";
+
+///Information about a request that has been handled.
+#[derive(Clone, Debug)]
+pub struct CompletionInfo {
+    ///The HTTP method.
+    pub method: Method,
+
+    ///The requested path, or `None` if it couldn't be parsed.
+    pub uri_path: Option<UriPath>,
+
+    ///The request target, as it was received. It's not decoded, so it's
+    ///available even if `uri_path` couldn't be parsed, as for a target with
+    ///malformed percent encoding.
+    pub raw_target: String,
+
+    ///The client address.
+    pub address: SocketAddr,
+
+    ///The final status code.
+    pub status: StatusCode,
+
+    ///The number of request body bytes that were received, after removing
+    ///any chunked transfer encoding.
+    pub bytes_read: u64,
+
+    ///The number of response body bytes that were written to the connection,
+    ///after all response filters and compression, and without any chunk
+    ///framing. This is what was actually sent to the client, so anything that
+    ///couldn't be written because of a disconnect is not included.
+    pub bytes_written: u64,
+
+    ///`true` if the response could not be completely sent, because of an
+    ///error while writing it, like a client disconnect, or because it was
+    ///deliberately cut off after a failure.
+    pub interrupted: bool,
+
+    ///`true` if the request had an `If-None-Match` or `If-Modified-Since`
+    ///header, as in `Context::is_conditional`.
+    pub conditional: bool,
+
+    ///`true` if the request was conditional and the final status is `304 Not
+    ///Modified`, meaning that the client's cached copy was used. This is
+    ///found from the status, so it doesn't matter how the response was
+    ///produced.
+    pub not_modified: bool,
+
+    ///A copy of the request headers, where the values of the headers in
+    ///`Server::redacted_headers` are replaced with `[REDACTED]`.
+    pub request_headers: Headers,
+
+    ///A copy of the response headers, as they were sent, or an empty set if
+    ///they were never sent. The headers in `Server::redacted_headers` are
+    ///redacted here as well.
+    pub response_headers: Headers,
+
+    ///The beginning of the response body, up to the limit in
+    ///`Server::capture_response_body`, or `None` if the body is not captured.
+    ///These are the same bytes as those that are counted in `bytes_written`,
+    ///so they come after the response filters and compression. Nothing is
+    ///redacted from it.
+    pub response_body: Option<Vec<u8>>,
+
+    ///`true` if `response_body` is only the beginning of the body, because
+    ///the body was longer than the limit. Files that are sent with
+    ///`sendfile` never pass through the server's buffers, so they can't be
+    ///captured, and are reported as truncated.
+    pub response_body_truncated: bool,
+}
+
+///A trait for observers that are notified when a request has been handled.
+///
+///The observers are called after the response has been sent, or failed to be
+///sent, so they can't affect it in any way.
+///
+///```no_run
+///# #[macro_use] extern crate log;
+///# extern crate rustful;
+///use rustful::{Server, Context, Response};
+///use rustful::server::CompletionInfo;
+///
+///# fn handler(_context: Context, _response: Response) {}
+///# fn main() {
+///let server_result = Server {
+///    completion_observers: vec![Box::new(|info: &CompletionInfo| {
+///        info!("{} bytes in, {} bytes out", info.bytes_read, info.bytes_written);
+///    })],
+///    ..Server::new(handler)
+///}.run();
+///# }
+///```
+pub trait CompletionObserver: Send + Sync + 'static {
+    ///Observe a handled request.
+    fn on_complete(&self, info: &CompletionInfo);
+}
+
+impl<F: Fn(&CompletionInfo) + Send + Sync + 'static> CompletionObserver for F {
+    fn on_complete(&self, info: &CompletionInfo) {
+        self(info);
+    }
+}
+
+#[doc(hidden)]
+///Internal and may change without warning.
+///
+///Replace the values of the named headers with `[REDACTED]`. The names are
+///case insensitive.
+pub fn redact(mut headers: Headers, names: &[String]) -> Headers {
+    for name in names {
+        if headers.get_raw(name).is_some() {
+            headers.set_raw(name.clone(), vec![REDACTED.as_bytes().to_vec()]);
+        }
+    }
+    headers
+}
+
+#[cfg(test)]
+mod test {
+    use header::Headers;
+    use super::redact;
+
+    #[test]
+    fn redact_headers() {
+        let mut headers = Headers::new();
+        headers.set_raw("Authorization", vec![b"Basic c2VjcmV0".to_vec()]);
+        headers.set_raw("Set-Cookie", vec![b"a=1".to_vec(), b"b=2".to_vec()]);
+        headers.set_raw("Accept", vec![b"text/html".to_vec()]);
+
+        let names = vec!["authorization".to_owned(), "set-cookie".to_owned(), "cookie".to_owned()];
+        let headers = redact(headers, &names);
+
+        assert_eq!(headers.get_raw("Authorization"), Some(&[b"[REDACTED]".to_vec()][..]));
+        assert_eq!(headers.get_raw("Set-Cookie"), Some(&[b"[REDACTED]".to_vec()][..]));
+        assert_eq!(headers.get_raw("Accept"), Some(&[b"text/html".to_vec()][..]));
+        assert_eq!(headers.get_raw("Cookie"), None);
+    }
+}
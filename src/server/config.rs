@@ -1,4 +1,4 @@
-use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6, Ipv4Addr};
+use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6, Ipv4Addr, Ipv6Addr};
 use std::str::FromStr;
 use std::any::TypeId;
 use std::mem::swap;
@@ -45,6 +45,13 @@ impl Host {
         Host(SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port)))
     }
 
+    ///Create a `Host` with the address `[::]:port`. This will accept IPv4
+    ///connections as well, if `ipv6_only` is disabled in the server
+    ///configuration, or by default on platforms that allows it.
+    pub fn any_v6(port: u16) -> Host {
+        Host(SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0), port, 0, 0)))
+    }
+
     ///Change the port of the host address.
     pub fn port(&mut self, port: u16) {
         self.0 = match self.0 {
@@ -92,6 +99,12 @@ impl From<(Ipv4Addr, u16)> for Host {
     }
 }
 
+impl From<(Ipv6Addr, u16)> for Host {
+    fn from((ip, port): (Ipv6Addr, u16)) -> Host {
+        Host(SocketAddr::V6(SocketAddrV6::new(ip, port, 0, 0)))
+    }
+}
+
 impl FromStr for Host {
     type Err = <SocketAddr as FromStr>::Err;
 
@@ -245,7 +258,11 @@ enum GlobalState {
 
 ///Settings for `keep-alive` connections to the server.
 pub struct KeepAlive {
-    ///How long a `keep-alive` connection may idle before it's forced close.
+    ///How long a connection may idle before it's forced close. This applies
+    ///to the time before the first request, as well as between `keep-alive`
+    ///requests. The connection is closed by the operating system, using a
+    ///read timeout on the socket, so it's closed even while its thread is
+    ///blocked waiting for it. Requests that are in progress are not affected.
     pub timeout: Duration,
 
     ///The number of threads in the thread pool that should be kept free from
@@ -253,3 +270,145 @@ pub struct KeepAlive {
     ///threads goes below this.
     pub free_threads: usize,
 }
+
+///How long to wait before trying again, when accepting a connection fails.
+///
+///Accepting fails for the whole server when it runs out of resources, like
+///file descriptors, and retrying right away would only spin the CPU. Each
+///accept thread waits for `initial` after the first failure, and then twice
+///as long after each following failure, up to `max`. The delay is reset when
+///a connection is accepted. Each failure is logged as a warning.
+///
+///Errors that only affect a single connection, like a client that
+///disconnects or fails the TLS handshake, are not delayed. An error that
+///means the listener itself is broken is logged as an error and stored in
+///`Lifecycle::failure`, and the server begins shutting down. The threads
+///keep trying to accept connections, waiting `max` between each attempt,
+///until the process exits.
+///
+///```
+///use std::time::Duration;
+///use rustful::server::AcceptBackoff;
+///
+///let backoff = AcceptBackoff {
+///    initial: Duration::from_millis(50),
+///    ..AcceptBackoff::default()
+///};
+///```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AcceptBackoff {
+    ///The delay after the first failure. Default is 10 ms.
+    pub initial: Duration,
+
+    ///The longest delay. Default is 1 second.
+    pub max: Duration,
+}
+
+impl Default for AcceptBackoff {
+    fn default() -> AcceptBackoff {
+        AcceptBackoff {
+            initial: Duration::from_millis(10),
+            max: Duration::from_secs(1),
+        }
+    }
+}
+
+///A limit on how many requests with large bodies can be handled at the same
+///time.
+///
+///Large bodies take a lot of memory or disk space while they are handled,
+///so many of them at once can exhaust the resources of the server, even if
+///each of them is below `Server::max_body_length`. Requests that would go
+///above the limit are rejected with `503 Service Unavailable`, before they
+///reach any handler, and their connections are closed.
+///
+///```
+///use rustful::server::LargeBodyLimit;
+///
+/////At most 4 uploads of more than 1 MiB, or multipart forms, at once.
+///let limit = LargeBodyLimit {
+///    threshold: 1024 * 1024,
+///    max_concurrent: 4,
+///    multipart: true,
+///};
+///```
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LargeBodyLimit {
+    ///Bodies with a `Content-Length` above this, in bytes, are counted as
+    ///large. Chunked bodies have no announced length, so they are always
+    ///counted.
+    pub threshold: u64,
+
+    ///The largest number of requests with large bodies that can be handled
+    ///at the same time.
+    pub max_concurrent: usize,
+
+    ///Count every `multipart/form-data` body as large, regardless of its
+    ///length.
+    pub multipart: bool,
+}
+
+///How request paths should be normalized before they are routed.
+///
+///Normalization collapses repeated slashes and resolves `.` and `..`
+///segments, without going above the root. It's applied to the path as it was
+///received, before any percent-escapes are decoded, so encoded characters,
+///such as `%2F` or `%2E`, will not be treated as separators or dot segments.
+///They will still be decoded before the path is routed, but an encoded slash
+///stays a part of its path segment when it's routed, so `/files/a%2Fb` will
+///match `/files/:name` with `a/b` as `name`.
+///
+///```
+///use rustful::server::PathNormalization;
+///
+/////`/users//42` and `/users/./42` will be routed as `/users/42`.
+///let normalization = PathNormalization::Normalize;
+///```
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PathNormalization {
+    ///Route paths exactly as they were received.
+    Off,
+
+    ///Route the normalized path.
+    Normalize,
+
+    ///Respond with `301 Moved Permanently` and the normalized path in the
+    ///`Location` header, if the path isn't already normalized.
+    Redirect
+}
+
+impl Default for PathNormalization {
+    fn default() -> PathNormalization {
+        PathNormalization::Off
+    }
+}
+
+///What to do with a request body, when it's sent with a method where it has
+///no defined meaning, like `GET`.
+///
+///Such bodies are usually not expected by the handlers, so they are often
+///left unread. The connection would then have to be closed, since the next
+///request can't be found before the body has been read.
+///
+///```
+///use rustful::server::UnexpectedBody;
+///use rustful::Method;
+///
+/////Refuse `GET` requests with bodies.
+///let policy = (Method::Get, UnexpectedBody::Reject);
+///```
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UnexpectedBody {
+    ///Treat it like any other body.
+    Allow,
+
+    ///Let the handler read it, if it wants to, and drain whatever is left
+    ///when it's done, to keep the connection open. This is done regardless
+    ///of `Server::auto_drain_limit`, but `Server::max_body_length` is still
+    ///checked.
+    Drain,
+
+    ///Reject the request with `400 Bad Request`, before it reaches any
+    ///handler.
+    Reject
+}
@@ -0,0 +1,247 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+use hyper;
+
+use StatusCode;
+use utils::PercentDecodeError;
+
+///An error that made a request impossible to handle.
+///
+///The server responds to these errors without involving any handler, using
+///the status from `status()`, and closes the connection afterwards, since
+///the rest of the request can't be trusted. Errors without a status, like
+///IO errors, mean that the client can't be reached, so the connection should
+///just be closed.
+///
+///Some requests are too broken to even reach rustful, like those with an
+///invalid request line or malformed headers. Hyper closes those connections
+///right away, but its errors can still be converted, using `From`, to see
+///how they are classified.
+#[derive(Debug)]
+pub enum RequestError {
+    ///The request line, or the request target in it, could not be parsed.
+    BadRequestLine,
+
+    ///A header is malformed or in conflict with another header. The name of
+    ///the header is included, if known.
+    BadHeader(Option<String>),
+
+    ///The body is sent with a transfer coding that is not supported. The
+    ///coding is included.
+    UnsupportedTransferEncoding(String),
+
+    ///The request head, or the announced body, is larger than allowed.
+    TooLarge,
+
+    ///The request has a body, but its method doesn't allow it, according to
+    ///`Server::unexpected_bodies`.
+    UnexpectedBody,
+
+    ///The first chunk of a chunked body is larger than
+    ///`Server::max_chunk_size`, or its extension is longer than
+    ///`Server::max_chunk_extension_length`.
+    InvalidChunk,
+
+    ///The request has more header fields than allowed.
+    TooManyHeaders,
+
+    ///The request target is longer than `Server::max_target_length`.
+    UriTooLong,
+
+    ///The query has more parameters than `Server::max_parameters`.
+    TooManyParameters,
+
+    ///The request has a large body, and too many of those are already being
+    ///handled, according to `Server::large_body_limit`.
+    TooManyLargeBodies,
+
+    ///The path, query or fragment of the request target has a malformed
+    ///percent escape sequence, like `%ZZ` or a truncated `%A`.
+    BadPercentEncoding(PercentDecodeError),
+
+    ///The request could not be read.
+    Io(io::Error)
+}
+
+impl RequestError {
+    ///Get the status code of the response to this error, or `None` if the
+    ///connection should be closed without a response.
+    ///
+    ///```
+    ///use rustful::StatusCode;
+    ///use rustful::server::RequestError;
+    ///
+    ///assert_eq!(RequestError::BadRequestLine.status(), Some(StatusCode::BadRequest));
+    ///assert_eq!(RequestError::TooLarge.status(), Some(StatusCode::PayloadTooLarge));
+    ///```
+    pub fn status(&self) -> Option<StatusCode> {
+        match *self {
+            RequestError::BadRequestLine => Some(StatusCode::BadRequest),
+            RequestError::BadHeader(_) => Some(StatusCode::BadRequest),
+            RequestError::UnsupportedTransferEncoding(_) => Some(StatusCode::NotImplemented),
+            RequestError::UnexpectedBody => Some(StatusCode::BadRequest),
+            RequestError::InvalidChunk => Some(StatusCode::BadRequest),
+            RequestError::TooLarge => Some(StatusCode::PayloadTooLarge),
+            RequestError::TooManyHeaders => Some(StatusCode::RequestHeaderFieldsTooLarge),
+            RequestError::UriTooLong => Some(StatusCode::UriTooLong),
+            RequestError::TooManyParameters => Some(StatusCode::BadRequest),
+            RequestError::TooManyLargeBodies => Some(StatusCode::ServiceUnavailable),
+            RequestError::BadPercentEncoding(_) => Some(StatusCode::BadRequest),
+            RequestError::Io(_) => None
+        }
+    }
+}
+
+///An invalid combination of settings, found by `ServerBuilder::build`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+    ///A TLS certificate was set without a key.
+    MissingTlsKey,
+
+    ///A TLS key was set without a certificate.
+    MissingTlsCert,
+
+    ///The thread pool was set to have no threads.
+    NoThreads,
+
+    ///Sessions are enabled without a signing key.
+    MissingSessionKey,
+
+    ///Cookie keys are set, but the current key is empty.
+    MissingCookieKey,
+}
+
+impl ConfigError {
+    fn message(&self) -> &'static str {
+        match *self {
+            ConfigError::MissingTlsKey => "a TLS certificate is set without a key",
+            ConfigError::MissingTlsCert => "a TLS key is set without a certificate",
+            ConfigError::NoThreads => "the thread pool has no threads",
+            ConfigError::MissingSessionKey => "sessions are enabled without a signing key",
+            ConfigError::MissingCookieKey => "the current cookie key is empty",
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl error::Error for ConfigError {
+    fn description(&self) -> &str {
+        self.message()
+    }
+}
+
+impl From<io::Error> for RequestError {
+    fn from(err: io::Error) -> RequestError {
+        RequestError::Io(err)
+    }
+}
+
+impl From<PercentDecodeError> for RequestError {
+    fn from(err: PercentDecodeError) -> RequestError {
+        RequestError::BadPercentEncoding(err)
+    }
+}
+
+impl From<hyper::Error> for RequestError {
+    fn from(err: hyper::Error) -> RequestError {
+        match err {
+            hyper::Error::Method | hyper::Error::Uri(_) | hyper::Error::Version => RequestError::BadRequestLine,
+            hyper::Error::Header | hyper::Error::Utf8(_) => RequestError::BadHeader(None),
+            hyper::Error::TooLarge => RequestError::TooLarge,
+            hyper::Error::Io(e) => RequestError::Io(e),
+            other => RequestError::Io(io::Error::new(io::ErrorKind::Other, other.to_string()))
+        }
+    }
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RequestError::BadRequestLine => write!(f, "invalid request line"),
+            RequestError::BadHeader(Some(ref name)) => write!(f, "invalid header: {}", name),
+            RequestError::BadHeader(None) => write!(f, "invalid header"),
+            RequestError::UnsupportedTransferEncoding(ref coding) => write!(f, "unsupported transfer coding: {}", coding),
+            RequestError::UnexpectedBody => write!(f, "the request method does not allow a body"),
+            RequestError::InvalidChunk => write!(f, "a chunk of the body is too large"),
+            RequestError::TooLarge => write!(f, "the request is too large"),
+            RequestError::TooManyHeaders => write!(f, "the request has too many header fields"),
+            RequestError::UriTooLong => write!(f, "the request target is too long"),
+            RequestError::TooManyParameters => write!(f, "the query has too many parameters"),
+            RequestError::TooManyLargeBodies => write!(f, "too many large request bodies are being handled"),
+            RequestError::BadPercentEncoding(ref e) => write!(f, "invalid request target: {}", e),
+            RequestError::Io(ref e) => write!(f, "io error: {}", e)
+        }
+    }
+}
+
+impl error::Error for RequestError {
+    fn description(&self) -> &str {
+        match *self {
+            RequestError::BadRequestLine => "invalid request line",
+            RequestError::BadHeader(_) => "invalid header",
+            RequestError::UnsupportedTransferEncoding(_) => "unsupported transfer coding",
+            RequestError::UnexpectedBody => "the request method does not allow a body",
+            RequestError::InvalidChunk => "a chunk of the body is too large",
+            RequestError::TooLarge => "the request is too large",
+            RequestError::TooManyHeaders => "the request has too many header fields",
+            RequestError::UriTooLong => "the request target is too long",
+            RequestError::TooManyParameters => "the query has too many parameters",
+            RequestError::TooManyLargeBodies => "too many large request bodies are being handled",
+            RequestError::BadPercentEncoding(_) => "invalid percent encoding in the request target",
+            RequestError::Io(ref e) => e.description()
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            RequestError::Io(ref e) => Some(e),
+            RequestError::BadPercentEncoding(ref e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use hyper;
+
+    use StatusCode;
+    use utils::PercentDecodeError;
+    use super::RequestError;
+
+    #[test]
+    fn classify_hyper_errors() {
+        let error: RequestError = hyper::Error::Method.into();
+        assert_eq!(error.status(), Some(StatusCode::BadRequest));
+
+        let error: RequestError = hyper::Error::Header.into();
+        assert_eq!(error.status(), Some(StatusCode::BadRequest));
+
+        let error: RequestError = hyper::Error::TooLarge.into();
+        assert_eq!(error.status(), Some(StatusCode::PayloadTooLarge));
+
+        let error: RequestError = hyper::Error::Io(io::Error::new(io::ErrorKind::ConnectionReset, "reset")).into();
+        assert_eq!(error.status(), None);
+        assert_eq!(error.to_string(), "io error: reset");
+    }
+
+    #[test]
+    fn bad_percent_encoding() {
+        let error: RequestError = PercentDecodeError::InvalidEscape(4).into();
+        assert_eq!(error.status(), Some(StatusCode::BadRequest));
+        assert_eq!(error.to_string(), "invalid request target: invalid percent escape sequence at position 4");
+
+        let error: RequestError = PercentDecodeError::Truncated(4).into();
+        assert_eq!(error.status(), Some(StatusCode::BadRequest));
+        assert_eq!(error.to_string(), "invalid request target: truncated percent escape sequence at position 4");
+    }
+}
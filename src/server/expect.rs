@@ -0,0 +1,62 @@
+use StatusCode;
+use Method;
+use header::Headers;
+
+///A hook that decides if a request with `Expect: 100-continue` may send its
+///body.
+///
+///Clients that send `Expect: 100-continue` wait for the server to accept
+///the request before they send the body, which makes it possible to reject
+///large uploads, or unauthorized requests, without receiving the body
+///first. The hook is called with the method, the request target, as it was
+///received, and the headers, after they have been parsed, but before the
+///request is routed and before any part of the body is read.
+///
+///Return `StatusCode::Continue` to let the client send the body, and to
+///handle the request as usual. Any other status code rejects the request,
+///without calling the context filters or the handler. The rejection is sent
+///as a status line without any headers or body, since the request has not
+///been read yet, and the connection is closed afterwards.
+///
+///`Server::max_body_length` is checked before the hook, so requests with a
+///`Content-Length` above it are rejected with `413 Payload Too Large`
+///without calling it. The same goes for requests with invalid framing
+///headers, which are rejected with `400 Bad Request`. Requests without
+///`Expect: 100-continue` never see the hook, so it doesn't replace the
+///usual checks in the filters or the handlers.
+///
+///```
+///use rustful::{Server, Context, Response, StatusCode, Method};
+///use rustful::header::{Headers, ContentLength};
+///
+///fn check_upload(method: &Method, target: &str, headers: &Headers) -> StatusCode {
+///    if *method != Method::Put || !target.starts_with("/uploads/") {
+///        return StatusCode::Continue;
+///    }
+///
+///    if !headers.has::<rustful::header::Authorization<String>>() {
+///        return StatusCode::Unauthorized;
+///    }
+///
+///    match headers.get::<ContentLength>() {
+///        Some(&ContentLength(length)) if length <= 10 * 1024 * 1024 => StatusCode::Continue,
+///        _ => StatusCode::PayloadTooLarge
+///    }
+///}
+///
+///# fn upload(_context: Context, _response: Response) {}
+///let server = Server {
+///    continue_policy: Some(Box::new(check_upload)),
+///    ..Server::new(upload as fn(Context, Response))
+///};
+///```
+pub trait ContinuePolicy: Send + Sync + 'static {
+    ///Decide if the client may send the body of the request.
+    fn check(&self, method: &Method, target: &str, headers: &Headers) -> StatusCode;
+}
+
+impl<F: Fn(&Method, &str, &Headers) -> StatusCode + Send + Sync + 'static> ContinuePolicy for F {
+    fn check(&self, method: &Method, target: &str, headers: &Headers) -> StatusCode {
+        self(method, target, headers)
+    }
+}
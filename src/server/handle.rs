@@ -0,0 +1,81 @@
+use std::sync::{Arc, RwLock};
+
+use router::Router;
+
+///A handle for replacing the router of a running server.
+///
+///The handle is created together with the `ServerInstance`, and can be
+///cloned and kept after the server has started. `reload` replaces the router
+///atomically, so each request is routed either by the old router or by the
+///new one, and never by a mix of both. Requests that are already being
+///handled finish with the old router, which is dropped after the last of
+///them.
+///
+///Only the router is replaced. Everything else, like the filters, the
+///fallback handler and the other settings, is kept. Socket level
+///configuration, like the host address, ports and TLS certificates, can't be
+///reloaded at all, and requires a separate restart of the server.
+///
+///```no_run
+///use rustful::{Server, Router, TreeRouter, Context, Response};
+///use rustful::Method::Get;
+///
+///fn hello(_context: Context, response: Response) {
+///    response.send("hello");
+///}
+///
+///fn goodbye(_context: Context, response: Response) {
+///    response.send("goodbye");
+///}
+///
+///let mut router = TreeRouter::new();
+///router.insert(Get, "/", hello as fn(Context, Response));
+///
+///let (server, scheme) = Server::new(router).build();
+///let handle = server.server_handle();
+///let _listening = server.run(scheme).unwrap();
+///
+///let mut router = TreeRouter::new();
+///router.insert(Get, "/", goodbye as fn(Context, Response));
+///handle.reload(router);
+///```
+pub struct ServerHandle<R> {
+    handlers: Arc<RwLock<Arc<R>>>,
+}
+
+impl<R: Router> ServerHandle<R> {
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn new(handlers: R) -> ServerHandle<R> {
+        ServerHandle {
+            handlers: Arc::new(RwLock::new(Arc::new(handlers))),
+        }
+    }
+
+    ///Replace the router. New requests are routed by `handlers`, while the
+    ///requests that are already being handled finish with the old router.
+    pub fn reload(&self, handlers: R) {
+        let old = {
+            let mut current = self.handlers.write().unwrap_or_else(|e| e.into_inner());
+            ::std::mem::replace(&mut *current, Arc::new(handlers))
+        };
+
+        //The old router is dropped outside the lock, if this was the last
+        //reference to it.
+        drop(old);
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn current(&self) -> Arc<R> {
+        self.handlers.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+impl<R> Clone for ServerHandle<R> {
+    fn clone(&self) -> ServerHandle<R> {
+        ServerHandle {
+            handlers: self.handlers.clone(),
+        }
+    }
+}
@@ -1,7 +1,13 @@
 use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::net::{SocketAddr, SocketAddrV4, Ipv4Addr, IpAddr, TcpStream, Shutdown};
+use std::io::{self, Read, Write, BufWriter};
+use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use std::cell::RefCell;
+#[cfg(all(feature = "sendfile", target_os = "linux"))]
+use std::os::unix::io::AsRawFd;
+use std::panic::{self, AssertUnwindSafe};
 #[cfg(feature = "ssl")]
 use std::path::PathBuf;
 
@@ -9,15 +15,17 @@ use time;
 
 use num_cpus;
 
-use url::percent_encoding::percent_decode;
+use net2::{TcpBuilder, TcpStreamExt};
+
 use url::Url;
 
 use hyper;
 use hyper::server::Handler as HyperHandler;
-use hyper::header::{Date, ContentType};
-use hyper::mime::Mime;
+use hyper::buffer::BufReader;
+use hyper::header::{Date, ContentType, Location, ContentLength, TransferEncoding, Encoding, Connection, ConnectionOption, Expect};
+use hyper::mime::{Mime, TopLevel, SubLevel};
 use hyper::uri::RequestUri;
-use hyper::net::HttpListener;
+use hyper::net::{HttpListener, HttpStream, NetworkStream};
 #[cfg(feature = "ssl")]
 use hyper::net::{Openssl, HttpsListener};
 
@@ -26,19 +34,35 @@ pub use hyper::server::Listening;
 use anymap::AnyMap;
 
 use StatusCode;
+use Method;
 
-use context::{self, Context, UriPath, MaybeUtf8Owned, Parameters};
+use context::{self, Context, UriPath, MaybeUtf8Owned, Parameters, ConnectionInfo, ConnectionStorage, TargetForm, VaryHeaders};
+#[cfg(feature = "rustc_json_body")]
+use context::BodyDeserializers;
 use filter::{FilterContext, ContextFilter, ContextAction, ResponseFilter};
-use router::{Router, Endpoint};
+use router::{Router, Endpoint, RouteState, Route};
 use handler::Handler;
-use response::Response;
-use header::HttpDate;
-use server::{Scheme, Global, KeepAlive};
+use response::{Response, StatusLine};
+use session::{Sessions, CookieKeys};
+use header::{Headers, HttpDate};
+use server::{Scheme, Global, KeepAlive, AcceptBackoff, PathNormalization, LargeBodyLimit, UnexpectedBody, CompletionInfo, CompletionObserver, PathRewrite, ContinuePolicy, Metrics, MetricLabels, Lifecycle, RequestError, ServerHandle};
+use server::completion;
+use server::accept::BackoffListener;
+use server::stream::SharedStream;
 
 use HttpResult;
+use HttpVersion;
 use Server;
 
-use utils;
+use utils::{self, percent_decode, PercentDecodeError};
+#[cfg(feature = "encoding")]
+use utils::Charset;
+
+thread_local!(static WORKER_NAME: RefCell<Option<String>> = RefCell::new(None));
+
+//The storage of the connection that is handled by the current thread. Hyper
+//handles each connection on a single thread, from start to end.
+thread_local!(static CONNECTION_STORAGE: RefCell<Option<ConnectionStorage>> = RefCell::new(None));
 
 ///A runnable instance of a server.
 ///
@@ -60,20 +84,63 @@ use utils;
 ///}.build();
 ///```
 pub struct ServerInstance<R: Router> {
-    handlers: R,
+    handlers: ServerHandle<R>,
     fallback_handler: Option<R::Handler>,
 
     host: SocketAddr,
+    ipv6_only: Option<bool>,
+    tcp_nodelay: bool,
+    linger: Option<Duration>,
 
     server: String,
     content_type: Mime,
+    //Kept as raw values, since `Headers` is not `Sync`.
+    default_headers: Vec<(String, Vec<Vec<u8>>)>,
+    auto_vary: bool,
 
     threads: usize,
+    thread_name_prefix: String,
+    workers: AtomicUsize,
     keep_alive: Option<KeepAlive>,
+    accept_backoff: AcceptBackoff,
     threads_in_use: AtomicUsize,
+    path_normalization: PathNormalization,
+    slow_request_threshold: Option<Duration>,
+    request_timeout: Option<Duration>,
+    max_body_length: Option<u64>,
+    large_body_limit: Option<LargeBodyLimit>,
+    large_bodies: AtomicUsize,
+    auto_drain_limit: Option<u64>,
+    unexpected_bodies: Vec<(Method, UnexpectedBody)>,
+    continue_policy: Option<Box<ContinuePolicy>>,
+    max_chunk_size: u64,
+    max_chunk_extension_length: usize,
+    body_read_timeout: Option<Duration>,
+    body_read_deadline: Option<Duration>,
+    write_timeout: Option<Duration>,
+    max_headers: usize,
+    max_parameters: usize,
+    max_target_length: usize,
+    stream_buffer_size: usize,
+    auto_content_length: usize,
+    redacted_headers: Vec<String>,
+    capture_response_body: Option<usize>,
+    trusted_proxies: Vec<IpAddr>,
+    #[cfg(feature = "encoding")]
+    fallback_charset: Option<Charset>,
 
     context_filters: Vec<Box<ContextFilter>>,
     response_filters: Vec<Box<ResponseFilter>>,
+    completion_observers: Vec<Box<CompletionObserver>>,
+    path_rewrite: Option<Box<PathRewrite>>,
+    metrics: Option<Box<Metrics>>,
+    lifecycle: Lifecycle,
+    on_start: Option<Box<Fn() + Send + Sync>>,
+    on_shutdown: Option<Box<Fn() + Send + Sync>>,
+    sessions: Option<Arc<Sessions>>,
+    cookie_keys: Option<CookieKeys>,
+    #[cfg(feature = "rustc_json_body")]
+    body_deserializers: BodyDeserializers,
 
     global: Global,
 }
@@ -83,42 +150,225 @@ impl<R: Router> ServerInstance<R> {
     ///the same as `Server{...}.build()`.
     pub fn new(config: Server<R>) -> (ServerInstance<R>, Scheme) {
         (ServerInstance {
-            handlers: config.handlers,
+            handlers: ServerHandle::new(config.handlers),
             fallback_handler: config.fallback_handler,
             host: config.host.into(),
+            ipv6_only: config.ipv6_only,
+            tcp_nodelay: config.tcp_nodelay,
+            linger: config.linger,
             server: config.server,
             content_type: config.content_type,
+            default_headers: raw_default_headers(&config.default_headers),
+            auto_vary: config.auto_vary,
             threads: config.threads.unwrap_or_else(|| (num_cpus::get() * 5) / 4),
+            thread_name_prefix: config.thread_name_prefix,
+            workers: AtomicUsize::new(0),
             keep_alive: config.keep_alive,
+            accept_backoff: config.accept_backoff,
             threads_in_use: AtomicUsize::new(0),
+            path_normalization: config.path_normalization,
+            slow_request_threshold: config.slow_request_threshold,
+            request_timeout: config.request_timeout,
+            max_body_length: config.max_body_length,
+            large_body_limit: config.large_body_limit,
+            large_bodies: AtomicUsize::new(0),
+            auto_drain_limit: config.auto_drain_limit,
+            unexpected_bodies: config.unexpected_bodies,
+            continue_policy: config.continue_policy,
+            max_chunk_size: config.max_chunk_size,
+            max_chunk_extension_length: config.max_chunk_extension_length,
+            body_read_timeout: config.body_read_timeout,
+            body_read_deadline: config.body_read_deadline,
+            write_timeout: config.write_timeout,
+            max_headers: config.max_headers,
+            max_parameters: config.max_parameters,
+            max_target_length: config.max_target_length,
+            stream_buffer_size: config.stream_buffer_size,
+            auto_content_length: config.auto_content_length,
+            redacted_headers: config.redacted_headers,
+            capture_response_body: config.capture_response_body,
+            trusted_proxies: config.trusted_proxies,
+            #[cfg(feature = "encoding")]
+            fallback_charset: config.fallback_charset,
             context_filters: config.context_filters,
             response_filters: config.response_filters,
+            completion_observers: config.completion_observers,
+            path_rewrite: config.path_rewrite,
+            metrics: config.metrics,
+            lifecycle: config.lifecycle,
+            on_start: config.on_start,
+            on_shutdown: config.on_shutdown,
+            sessions: config.sessions.map(Sessions::new),
+            cookie_keys: config.cookie_keys,
+            #[cfg(feature = "rustc_json_body")]
+            body_deserializers: config.body_deserializers,
             global: config.global,
         },
         config.scheme)
     }
 
+    ///Get a handle for replacing the router while the server is running.
+    ///See `ServerHandle` for more details.
+    pub fn server_handle(&self) -> ServerHandle<R> {
+        self.handlers.clone()
+    }
+
     ///Start the server.
     #[cfg(feature = "ssl")]
-    pub fn run(self, scheme: Scheme) -> HttpResult<Listening> {
+    pub fn run(mut self, scheme: Scheme) -> HttpResult<Listening> {
         let host = self.host;
         let threads = self.threads;
         let mut server = match scheme {
-            Scheme::Http => try!(HyperServer::http(host)),
-            Scheme::Https {cert, key} => try!(HyperServer::https(host, cert, key)),
+            Scheme::Http => try!(HyperServer::http(host, self.ipv6_only, self.accept_backoff, self.lifecycle.clone())),
+            Scheme::Https {cert, key} => try!(HyperServer::https(host, self.ipv6_only, self.accept_backoff, self.lifecycle.clone(), cert, key)),
         };
         server.keep_alive(self.keep_alive.as_ref().map(|k| k.timeout));
-        server.run(self, threads)
+        self.run_lifecycle_hooks();
+        server.run(self, threads).map(log_listening)
     }
 
     ///Start the server.
     #[cfg(not(feature = "ssl"))]
-    pub fn run(self, _scheme: Scheme) -> HttpResult<Listening> {
+    pub fn run(mut self, _scheme: Scheme) -> HttpResult<Listening> {
         let host = self.host;
         let threads = self.threads;
-        let mut server = try!(HyperServer::http(host));
+        let mut server = try!(HyperServer::http(host, self.ipv6_only, self.accept_backoff, self.lifecycle.clone()));
         server.keep_alive(self.keep_alive.as_ref().map(|k| k.timeout));
-        server.run(self, threads)
+        self.run_lifecycle_hooks();
+        server.run(self, threads).map(log_listening)
+    }
+
+    //Call the start hook and register the shutdown hook, right before the
+    //accept loop starts.
+    fn run_lifecycle_hooks(&mut self) {
+        if let Some(hook) = self.on_start.take() {
+            hook();
+        }
+
+        if let Some(hook) = self.on_shutdown.take() {
+            self.lifecycle.on_stopped(hook);
+        }
+    }
+
+    ///Serve the requests of a single connection over any stream, until the
+    ///connection is closed, and give the stream back.
+    ///
+    ///This works like the connection loop of a running server, but the
+    ///connection can come from anywhere. It can be used for transports that
+    ///the server doesn't listen on, like Unix sockets, and for testing, by
+    ///serving connections from memory. The stream is read from and written
+    ///to as it is, so any timeouts must be set on it beforehand. The requests
+    ///appear to come from `peer_addr`.
+    ///
+    ///```
+    ///use std::io::{self, Read, Write, Cursor};
+    ///use rustful::{Server, Context, Response};
+    ///
+    ///struct Connection {
+    ///    input: Cursor<Vec<u8>>,
+    ///    output: Vec<u8>,
+    ///}
+    ///
+    ///impl Read for Connection {
+    ///    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    ///        self.input.read(buf)
+    ///    }
+    ///}
+    ///
+    ///impl Write for Connection {
+    ///    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    ///        self.output.write(buf)
+    ///    }
+    ///
+    ///    fn flush(&mut self) -> io::Result<()> {
+    ///        Ok(())
+    ///    }
+    ///}
+    ///
+    ///fn hello(_context: Context, response: Response) {
+    ///    response.send("hello");
+    ///}
+    ///
+    ///let (server, _) = Server::new(hello as fn(Context, Response)).build();
+    ///let connection = Connection {
+    ///    input: Cursor::new(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n".to_vec()),
+    ///    output: vec![],
+    ///};
+    ///
+    ///let connection = server.serve_connection(connection, "127.0.0.1:1337".parse().unwrap());
+    ///assert!(connection.output.ends_with(b"\r\n\r\nhello"));
+    ///```
+    pub fn serve_connection<S: Read + Write + Send + 'static>(&self, stream: S, peer_addr: SocketAddr) -> S {
+        let stream = SharedStream::new(stream, peer_addr);
+
+        self.on_connection_start();
+        {
+            let mut reader_stream = stream.clone();
+            let mut reader = BufReader::new(&mut reader_stream as &mut NetworkStream);
+            let mut writer = BufWriter::new(stream.clone());
+
+            while self.serve_request(&mut reader, &mut writer, peer_addr) {}
+
+            if let Err(e) = writer.flush() {
+                debug!(target: "rustful::server", "could not flush the connection to {}: {}", peer_addr, e);
+            }
+        }
+        self.on_connection_end();
+
+        stream.into_inner().expect("the connection is still in use")
+    }
+
+    //Read and respond to the next request of a connection, and find out if
+    //the connection should be kept alive afterwards.
+    fn serve_request<W: Write>(&self, reader: &mut BufReader<&mut NetworkStream>, writer: &mut W, peer_addr: SocketAddr) -> bool {
+        let request = match hyper::server::request::Request::new(reader, peer_addr) {
+            Ok(request) => request,
+            Err(e) => {
+                debug!(target: "rustful::server", "stopped reading requests from {}: {}", peer_addr, e);
+                return false;
+            }
+        };
+
+        if request.version == HttpVersion::Http11 && request.headers.get() == Some(&Expect::Continue) {
+            let status = self.check_continue((&request.method, &request.uri, &request.headers));
+            if let Err(e) = write!(writer, "{} {}\r\n\r\n", HttpVersion::Http11, status).and_then(|_| writer.flush()) {
+                debug!(target: "rustful::server", "could not write 100-continue to {}: {}", peer_addr, e);
+                return false;
+            }
+
+            if status != StatusCode::Continue {
+                return false;
+            }
+        }
+
+        let version = request.version;
+        let keep_alive = self.keep_alive.is_some() && hyper::http::should_keep_alive(version, &request.headers);
+        let mut headers = Headers::new();
+        if !keep_alive {
+            headers.set(Connection(vec![ConnectionOption::Close]));
+        }
+
+        {
+            let mut response = hyper::server::response::Response::new(writer, &mut headers);
+            response.version = version;
+            self.handle(request, response);
+        }
+
+        //The response may also have asked for the connection to be closed.
+        keep_alive && hyper::http::should_keep_alive(version, &headers)
+    }
+
+    //Get the name of the current worker thread, or give it one if this is
+    //the first time it's needed.
+    fn worker_name(&self) -> String {
+        WORKER_NAME.with(|name| {
+            let mut name = name.borrow_mut();
+            if name.is_none() {
+                let number = self.workers.fetch_add(1, Ordering::Relaxed) + 1;
+                *name = Some(format!("{}-{}", self.thread_name_prefix, number));
+            }
+            name.clone().unwrap_or_default()
+        })
     }
 
     fn modify_context(&self, filter_storage: &mut AnyMap, context: &mut Context) -> ContextAction {
@@ -140,53 +390,124 @@ impl<R: Router> ServerInstance<R> {
         result
     }
 
-}
+    //Reserve one of the slots in `large_body_limit` if the request has a
+    //large body.
+    fn reserve_large_body<'s>(&'s self, headers: &Headers) -> Result<Option<LargeBodySlot<'s>>, RequestError> {
+        let limit = match self.large_body_limit {
+            Some(ref limit) => limit,
+            None => return Ok(None)
+        };
 
-struct ParsedUri {
-    host: Option<(String, Option<u16>)>,
-    uri_path: UriPath,
-    query: Parameters,
-    fragment: Option<MaybeUtf8Owned>
-}
+        let is_large = match headers.get::<ContentLength>() {
+            Some(&ContentLength(length)) => length > limit.threshold,
+            None => headers.has::<TransferEncoding>()
+        };
+        let is_multipart = limit.multipart && match utils::parse_content_type(headers) {
+            Some(Mime(TopLevel::Multipart, SubLevel::FormData, _)) => true,
+            _ => false
+        };
+
+        if !is_large && !is_multipart {
+            return Ok(None);
+        }
+
+        if self.large_bodies.fetch_add(1, Ordering::SeqCst) >= limit.max_concurrent {
+            self.large_bodies.fetch_sub(1, Ordering::SeqCst);
+            return Err(RequestError::TooManyLargeBodies);
+        }
+
+        Ok(Some(LargeBodySlot(&self.large_bodies)))
+    }
+
+    //Find out what to do with the body of the request, if it has one.
+    fn body_policy(&self, method: &Method, headers: &Headers) -> UnexpectedBody {
+        let has_body = headers.has::<TransferEncoding>() || headers.get::<ContentLength>().map_or(false, |length| length.0 > 0);
+        if !has_body {
+            return UnexpectedBody::Allow;
+        }
+
+        self.unexpected_bodies.iter()
+            .find(|&&(ref m, _)| m == method)
+            .map_or(UnexpectedBody::Allow, |&(_, policy)| policy)
+    }
+
+    //Handle the request and return what's needed for the completion report
+    //and the metrics. The response has been sent when this returns.
+    fn respond(&self, request: hyper::server::request::Request, mut response: Response, started: Instant) -> (Option<UriPath>, Option<Arc<AtomicUsize>>, Option<String>) {
+        let connection_info = ConnectionInfo::from_request(&request);
 
-impl<R: Router> HyperHandler for ServerInstance<R> {
-    fn handle(&self, request: hyper::server::request::Request, writer: hyper::server::response::Response) {
         let (
             request_addr,
             request_method,
             mut request_headers,
-            request_uri,
+            mut request_uri,
             request_version,
             request_reader
         ) = request.deconstruct();
 
-        let force_close = if let Some(ref keep_alive) = self.keep_alive {
-            self.threads_in_use.load(Ordering::SeqCst) + keep_alive.free_threads > self.threads
-        } else {
-            false
-        };
+        let raw_target = request_uri.to_string();
+        let target_checked = check_target_length(&raw_target, self.max_target_length);
 
-        let mut response = Response::new(writer, &self.response_filters, &self.global, force_close);
-        response.headers_mut().set(Date(HttpDate(time::now_utc())));
-        response.headers_mut().set(ContentType(self.content_type.clone()));
-        response.headers_mut().set(hyper::header::Server(self.server.clone()));
+        if self.path_normalization != PathNormalization::Off && target_checked.is_ok() {
+            let location = match request_uri {
+                RequestUri::AbsolutePath(ref mut path) => normalize_path(path).map(|normalized| {
+                    *path = normalized;
+                    path.clone()
+                }),
+                RequestUri::AbsoluteUri(ref mut url) => normalize_path(url.path()).map(|normalized| {
+                    url.set_path(&normalized);
+                    url.as_str().to_owned()
+                }),
+                _ => None
+            };
+
+            if let (PathNormalization::Redirect, Some(location)) = (self.path_normalization, location) {
+                response.set_status(StatusCode::MovedPermanently);
+                response.headers_mut().set(Location(location));
+                return (None, None, None);
+            }
+        }
+
+        //The slot is released when the request has been handled.
+        let mut _large_body = None;
+        let body_policy = self.body_policy(&request_method, &request_headers);
+        let checked = target_checked
+            .and_then(|_| check_header_count(&request_headers, self.max_headers))
+            .and_then(|_| check_parameter_count(&raw_target, self.max_parameters))
+            .and_then(|_| check_framing(&request_headers, self.max_body_length))
+            .and_then(|_| if body_policy == UnexpectedBody::Reject {
+                Err(RequestError::UnexpectedBody)
+            } else {
+                Ok(())
+            })
+            .and_then(|_| self.reserve_large_body(&request_headers).map(|slot| _large_body = slot));
 
-        let path_components = match request_uri {
-            RequestUri::AbsoluteUri(url) => Some(parse_url(&url)),
-            RequestUri::AbsolutePath(path) => Some(parse_path(&path)),
+        let path_components = checked.and_then(|_| match request_uri {
+            RequestUri::AbsoluteUri(url) => parse_url(&url).map(|uri| (uri, TargetForm::Absolute)).map_err(RequestError::from),
+            RequestUri::AbsolutePath(path) => parse_path(&path).map(|uri| (uri, TargetForm::Origin)).map_err(RequestError::from),
             RequestUri::Star => {
-                Some(ParsedUri {
+                Ok((ParsedUri {
                     host: None,
                     uri_path: UriPath::Asterisk,
+                    segments: None,
                     query: Parameters::new(),
                     fragment: None
-                })
+                }, TargetForm::Asterisk))
             },
-            _ => None
-        };
+            RequestUri::Authority(ref authority) if request_method == Method::Connect => {
+                parse_authority(authority).map(|host| (ParsedUri {
+                    host: Some(host),
+                    uri_path: UriPath::Authority,
+                    segments: None,
+                    query: Parameters::new(),
+                    fragment: None
+                }, TargetForm::Authority)).ok_or(RequestError::BadRequestLine)
+            },
+            RequestUri::Authority(_) => Err(RequestError::BadRequestLine)
+        });
 
         match path_components {
-            Some(ParsedUri{ host, uri_path, query, fragment }) => {
+            Ok((ParsedUri{ host, uri_path, segments, query, fragment }, target_form)) => {
                 if let Some((name, port)) = host {
                     request_headers.set(::header::Host {
                         hostname: name,
@@ -194,35 +515,132 @@ impl<R: Router> HyperHandler for ServerInstance<R> {
                     });
                 }
 
-                let body = context::body::BodyReader::from_reader(request_reader, &request_headers);
+                let mut body = context::body::BodyReader::from_reader(request_reader, &request_headers);
+                body.set_deadline(self.body_read_deadline.map(|deadline| started + deadline));
+                body.set_max_length(self.max_body_length);
+                body.set_max_fields(self.max_parameters);
+                body.set_chunk_limits(self.max_chunk_size, self.max_chunk_extension_length);
+
+                //The first chunk size is often sent together with the head,
+                //so it can be checked before the handler gets the request.
+                if body.check_buffered_chunk().is_err() {
+                    send_request_error(RequestError::InvalidChunk, &raw_target, response);
+                    return (None, None, None);
+                }
+
+                #[cfg(feature = "encoding")]
+                let query = match self.fallback_charset {
+                    Some(charset) => {
+                        body.set_fallback_charset(Some(charset));
+                        charset.decode_parameters(query)
+                    },
+                    None => query
+                };
+
+                let read_counter = body.read_counter();
+                response.set_request_body(body.completion_flag());
+
+                let drainable = body_policy == UnexpectedBody::Drain || match (request_headers.get::<ContentLength>(), self.auto_drain_limit) {
+                    (Some(&ContentLength(length)), Some(limit)) => length <= limit,
+                    _ => false
+                };
+                if drainable {
+                    let drain = body.drain_flag();
+                    drain.store(true, Ordering::Relaxed);
+                    response.set_body_drain(drain);
+                }
+                response.set_head_request(request_method == Method::Head);
+
+                let vary = VaryHeaders::new();
+                if self.auto_vary {
+                    response.set_vary_headers(vary.clone());
+                }
+
+                let address = unmap_address(request_addr);
+                let secure = connection_info.is_encrypted() || (self.trusted_proxies.contains(&address.ip()) && forwarded_https(&request_headers));
+
+                let session = self.sessions.as_ref().map(|sessions| Sessions::load(sessions, &request_headers, secure));
+                if let Some(ref session) = session {
+                    response.set_session(session.clone());
+                }
 
                 let mut context = Context {
                     headers: request_headers,
                     http_version: request_version,
                     method: request_method,
-                    address: request_addr,
+                    address: address,
                     uri_path: uri_path,
                     hyperlinks: vec![],
                     variables: Parameters::new(),
                     query: query.into(),
                     fragment: fragment,
                     global: &self.global,
+                    lifecycle: &self.lifecycle,
+                    session: session,
+                    cookie_keys: self.cookie_keys.as_ref(),
+                    #[cfg(feature = "rustc_json_body")]
+                    body_deserializers: &self.body_deserializers,
+                    connection_info: connection_info,
+                    vary: vary,
+                    connection_storage: CONNECTION_STORAGE.with(|storage| storage.borrow_mut().get_or_insert_with(ConnectionStorage::new).clone()),
+                    deadline: self.request_timeout.map(|timeout| started + timeout),
+                    secure: secure,
+                    raw_target: raw_target,
+                    target_form: target_form,
+                    original_path: None,
                     body: body
                 };
 
+                //Completion callbacks may be registered by the handler, so
+                //the path is always kept.
+                let reported_path = Some(context.uri_path.clone());
+
                 let mut filter_storage = AnyMap::new();
 
+                if let Some(ref rewrite) = self.path_rewrite {
+                    if let UriPath::Path(ref mut path) = context.uri_path {
+                        let original = ::std::mem::replace(path, MaybeUtf8Owned::new());
+                        *path = rewrite.rewrite(original, &mut filter_storage);
+
+                        if !path.as_bytes().starts_with(b"/") {
+                            *response.filter_storage_mut() = filter_storage;
+                            response.set_status(StatusCode::BadRequest);
+                            return (reported_path, Some(read_counter), None);
+                        }
+                    }
+                }
+
+                let mut route_pattern = None;
+
                 match self.modify_context(&mut filter_storage, &mut context) {
                     ContextAction::Next => {
                         *response.filter_storage_mut() = filter_storage;
 
+                        //Kept until the request has been handled, in case the
+                        //router is replaced in the meantime.
+                        let handlers = self.handlers.current();
                         let endpoint = context.uri_path.as_path().map_or_else(|| {
                             Endpoint {
                                 handler: None,
                                 variables: HashMap::new(),
                                 hyperlinks: vec![]
                             }
-                        }, |path| self.handlers.find(&context.method, &mut (&path[..]).into()));
+                        }, |path| {
+                            //The path is split before it's decoded if it has
+                            //encoded slashes, unless it has been changed.
+                            let mut route: RouteState = match segments {
+                                Some(ref segments) if reported_path.as_ref() == Some(&context.uri_path) => {
+                                    RouteState::from_segments(segments.iter().map(|segment| &segment[..]).collect())
+                                },
+                                _ => (&path[..]).into()
+                            };
+                            route.set_host(context.headers.get::<::header::Host>().map(|host| &*host.hostname));
+                            route.set_query(Some(&context.query));
+                            route.set_record_pattern(self.metrics.is_some());
+                            let endpoint = handlers.find(&context.method, &mut route);
+                            route_pattern = route.pattern().map(ToOwned::to_owned);
+                            endpoint
+                        });
 
                         let Endpoint {
                             handler,
@@ -235,7 +653,8 @@ impl<R: Router> HyperHandler for ServerInstance<R> {
                             context.variables = variables.into();
                             handler.handle_request(context, response);
                         } else {
-                            response.set_status(StatusCode::NotFound);
+                            debug!(target: "rustful::router", "no handler was found for {} {}", context.method, context.uri_path);
+                            send_not_found(&context, response);
                         }
                     },
                     ContextAction::Abort(status) => {
@@ -243,57 +662,453 @@ impl<R: Router> HyperHandler for ServerInstance<R> {
                         response.set_status(status);
                     }
                 }
+
+                (reported_path, Some(read_counter), route_pattern)
+            },
+            Err(error) => {
+                send_request_error(error, &raw_target, response);
+                (None, None, None)
+            }
+        }
+    }
+}
+
+struct ParsedUri {
+    host: Option<(String, Option<u16>)>,
+    uri_path: UriPath,
+    //Only set if the path has encoded slashes.
+    segments: Option<Vec<Vec<u8>>>,
+    query: Parameters,
+    fragment: Option<MaybeUtf8Owned>
+}
+
+impl<R: Router> HyperHandler for ServerInstance<R> {
+    fn handle(&self, request: hyper::server::request::Request, writer: hyper::server::response::Response) {
+        let method = request.method.clone();
+        let address = unmap_address(request.remote_addr);
+        let started = Instant::now();
+
+        self.lifecycle.request_started();
+        if let Some(ref metrics) = self.metrics {
+            metrics.request_started(&method);
+        }
+
+        //Hyper doesn't give access to the connections when they are
+        //accepted, so the options are set for each request instead.
+        if self.tcp_nodelay || self.linger.is_some() {
+            if let Some(stream) = tcp_stream(&request) {
+                if let Err(e) = set_socket_options(stream, self.tcp_nodelay, self.linger) {
+                    debug!(target: "rustful::server", "could not set the socket options: {}", e);
+                }
+            }
+        }
+
+        //A handle to the stream is kept, to be able to close the connection
+        //if the client stops reading the response.
+        let slow_client_stream = match (self.write_timeout, tcp_stream(&request)) {
+            (Some(timeout), Some(stream)) => match stream.set_write_timeout(Some(timeout)).and_then(|_| stream.try_clone()) {
+                Ok(stream) => Some(stream),
+                Err(e) => {
+                    debug!(target: "rustful::server", "could not set the write timeout: {}", e);
+                    None
+                }
             },
-            None => {
-                response.set_status(StatusCode::BadRequest);
+            _ => None
+        };
+
+        //The idle timeout shouldn't cut off slow request bodies, but they
+        //may have a timeout of their own. Hyper restores the idle timeout
+        //before the next request.
+        if self.keep_alive.is_some() || self.body_read_timeout.is_some() {
+            if let Err(e) = request.set_read_timeout(self.body_read_timeout) {
+                debug!(target: "rustful::server", "could not set the body read timeout: {}", e);
+            }
+        }
+
+        //Hyper closes the connection after the response if the client asked
+        //for it, or if it's an HTTP/1.0 client that didn't ask for
+        //`keep-alive`, so the response has to say so too, even if the
+        //handler sets another `Connection` header.
+        let force_close = if !hyper::http::should_keep_alive(request.version, &request.headers) {
+            true
+        } else if !self.lifecycle.is_accepting() {
+            true
+        } else if let Some(ref keep_alive) = self.keep_alive {
+            self.threads_in_use.load(Ordering::SeqCst) + keep_alive.free_threads > self.threads
+        } else {
+            false
+        };
+
+        //Hyper always writes the canonical reason phrase, so the status line
+        //is rewritten on its way out, in case the handler sets a custom one.
+        let (version, body, status, headers) = writer.deconstruct();
+        let mut status_line = StatusLine::new(body.into_inner());
+        let reason = status_line.reason_phrase();
+        let writer = hyper::server::response::Response::<hyper::net::Fresh>::construct(version, hyper::http::h1::HttpWriter::ThroughWriter(&mut status_line), status, headers);
+
+        let mut response = Response::new(writer, reason, &self.response_filters, &self.global, force_close);
+        response.set_stream_buffer_size(self.stream_buffer_size);
+        response.set_auto_content_length(self.auto_content_length);
+
+        //Files can only be sent directly to plain TCP connections, since TLS
+        //connections have to encrypt them first.
+        #[cfg(all(feature = "sendfile", target_os = "linux"))]
+        {
+            if let Some(stream) = request.downcast_ref::<HttpStream>() {
+                response.set_socket(stream.0.as_raw_fd());
+            }
+        }
+
+        response.headers_mut().set(Date(HttpDate(time::now_utc())));
+        response.headers_mut().set(ContentType(self.content_type.clone()));
+        response.headers_mut().set(hyper::header::Server(self.server.clone()));
+        for &(ref name, ref values) in &self.default_headers {
+            response.headers_mut().set_raw(name.clone(), values.clone());
+        }
+
+        //HTTP/1.0 connections are only kept alive if the response says so.
+        //Hyper has already added `Connection: close` if they shouldn't be.
+        if request.version == HttpVersion::Http10 && !response.headers().has::<Connection>() {
+            response.headers_mut().set(Connection(vec![ConnectionOption::KeepAlive]));
+        }
+
+        let progress = response.progress();
+        let request_uri = request.uri.clone();
+
+        let conditional = utils::is_conditional(&request.headers);
+        let request_headers = if self.completion_observers.is_empty() {
+            None
+        } else {
+            progress.record_headers();
+            Some(request.headers.clone())
+        };
+
+        if let Some(limit) = self.capture_response_body {
+            progress.capture_body(limit);
+        }
+
+        //The response will be sent as a 500 error while unwinding, if the
+        //handler panics, and the connection will be closed.
+        let result = panic::catch_unwind(AssertUnwindSafe(|| self.respond(request, response, started)));
+        let (uri_path, read_counter, route_pattern) = match result {
+            Ok(result) => result,
+            Err(payload) => {
+                let message = match payload.downcast_ref::<&str>() {
+                    Some(message) => message.to_string(),
+                    None => match payload.downcast_ref::<String>() {
+                        Some(message) => message.clone(),
+                        None => "Box<Any>".to_owned()
+                    }
+                };
+                error!(target: "rustful::server", "{} panicked while handling {} {}: {}", self.worker_name(), method, request_uri, message);
+                (None, None, None)
+            }
+        };
+
+        if let Some(threshold) = self.slow_request_threshold {
+            let duration = started.elapsed();
+            if duration > threshold {
+                let millis = duration.as_secs() * 1000 + (duration.subsec_nanos() / 1_000_000) as u64;
+                let status = progress.status().unwrap_or(StatusCode::Ok);
+                warn!(target: "rustful::server", "{} {} from {} took {} ms and got {}", method, request_uri, address, millis, status);
+            }
+        }
+
+        if progress.timed_out() {
+            warn!(target: "rustful::server", "{} {} from {} was aborted after {} bytes, since the client stopped reading the response", method, request_uri, address, progress.bytes_written());
+            if let Some(stream) = slow_client_stream {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        }
+
+        let bytes_read = read_counter.map_or(0, |counter| counter.load(Ordering::Relaxed) as u64);
+
+        if let Some(ref metrics) = self.metrics {
+            let labels = MetricLabels {
+                method: &method,
+                status: progress.status().unwrap_or(StatusCode::Ok),
+                route: route_pattern.as_ref().map(|pattern| &**pattern),
+            };
+            metrics.request_completed(&labels);
+            metrics.request_duration(&labels, started.elapsed());
+            metrics.bytes_read(&labels, bytes_read);
+            metrics.bytes_written(&labels, progress.bytes_written());
+        }
+
+        if !self.completion_observers.is_empty() || progress.has_callbacks() {
+            let status = progress.status().unwrap_or(StatusCode::Ok);
+            let (response_body, response_body_truncated) = match progress.take_captured_body() {
+                Some((body, truncated)) => (Some(body), truncated),
+                None => (None, false)
+            };
+            let info = CompletionInfo {
+                method: method.clone(),
+                uri_path: uri_path,
+                raw_target: request_uri.to_string(),
+                address: address,
+                status: status,
+                bytes_read: bytes_read,
+                bytes_written: progress.bytes_written(),
+                interrupted: progress.interrupted(),
+                conditional: conditional,
+                not_modified: conditional && status == StatusCode::NotModified,
+                request_headers: completion::redact(request_headers.unwrap_or_else(Headers::new), &self.redacted_headers),
+                response_headers: completion::redact(progress.take_headers().unwrap_or_else(Headers::new), &self.redacted_headers),
+                response_body: response_body,
+                response_body_truncated: response_body_truncated,
+            };
+
+            for callback in progress.take_callbacks() {
+                callback(&info);
+            }
+
+            for observer in &self.completion_observers {
+                observer.on_complete(&info);
             }
         }
+
+        if let Some(ref metrics) = self.metrics {
+            metrics.request_ended(&method);
+        }
+        self.lifecycle.request_finished();
+    }
+
+    fn check_continue(&self, (method, uri, headers): (&Method, &RequestUri, &Headers)) -> StatusCode {
+        if let Err(error) = check_framing(headers, self.max_body_length) {
+            info!(target: "rustful::server", "rejected a request before its body: {}", error);
+            return error.status().unwrap_or(StatusCode::BadRequest);
+        }
+
+        match self.continue_policy {
+            Some(ref policy) => {
+                let status = policy.check(method, &uri.to_string(), headers);
+                if status != StatusCode::Continue {
+                    info!(target: "rustful::server", "rejected a request before its body with {}", status);
+                }
+                status
+            },
+            None => StatusCode::Continue
+        }
     }
 
     fn on_connection_start(&self) {
-        self.threads_in_use.fetch_add(1, Ordering::SeqCst);
+        CONNECTION_STORAGE.with(|storage| *storage.borrow_mut() = Some(ConnectionStorage::new()));
+        self.lifecycle.connection_started();
+        let in_use = self.threads_in_use.fetch_add(1, Ordering::SeqCst) + 1;
+        debug!(target: "rustful::server", "accepted a connection, {} of {} threads are in use", in_use, self.threads);
     }
 
     fn on_connection_end(&self) {
+        CONNECTION_STORAGE.with(|storage| *storage.borrow_mut() = None);
+        self.lifecycle.connection_finished();
         self.threads_in_use.fetch_sub(1, Ordering::SeqCst);
     }
 }
 
-fn parse_path(path: &str) -> ParsedUri {
+fn log_listening(listening: Listening) -> Listening {
+    info!(target: "rustful::server", "listening on {}", listening.socket);
+    listening
+}
+
+fn check_target_length(target: &str, max_target_length: usize) -> Result<(), RequestError> {
+    if target.len() > max_target_length {
+        Err(RequestError::UriTooLong)
+    } else {
+        Ok(())
+    }
+}
+
+//Counts the pairs in the query, without decoding them.
+fn check_parameter_count(target: &str, max_parameters: usize) -> Result<(), RequestError> {
+    let query = match target.find('?') {
+        Some(index) => &target[index + 1..],
+        None => return Ok(())
+    };
+    let query = query.split('#').next().unwrap_or("");
+
+    if utils::count_pairs(query.as_bytes()) > max_parameters {
+        Err(RequestError::TooManyParameters)
+    } else {
+        Ok(())
+    }
+}
+
+//Counts every header line, including repeated names.
+fn check_header_count(headers: &Headers, max_headers: usize) -> Result<(), RequestError> {
+    let count = headers.iter().map(|header| headers.get_raw(header.name()).map_or(0, |values| values.len())).sum::<usize>();
+
+    if count > max_headers {
+        Err(RequestError::TooManyHeaders)
+    } else {
+        Ok(())
+    }
+}
+
+//Makes sure that the length of the body can be found and is within the
+//limit, so that nothing is read from the next request.
+//A reserved slot for a large body, which is released when it's dropped.
+struct LargeBodySlot<'a>(&'a AtomicUsize);
+
+impl<'a> Drop for LargeBodySlot<'a> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn check_framing(headers: &Headers, max_body_length: Option<u64>) -> Result<(), RequestError> {
+    if headers.has::<TransferEncoding>() {
+        if headers.has::<ContentLength>() {
+            return Err(RequestError::BadHeader(Some("Content-Length".into())));
+        }
+
+        //Only `chunked` affects the framing, and it has to be applied last,
+        //and only once. Any other coding would have to be decoded, which
+        //isn't supported, while `identity` doesn't change anything.
+        let codings: Vec<&Encoding> = headers.get::<TransferEncoding>().map_or(vec![], |codings| {
+            codings.iter().filter(|&coding| !is_coding(coding, "identity")).collect()
+        });
+
+        return match codings.split_last() {
+            Some((last, rest)) if is_coding(last, "chunked") => match rest.iter().find(|&&coding| !is_coding(coding, "chunked")) {
+                Some(coding) => Err(RequestError::UnsupportedTransferEncoding(coding.to_string())),
+                None if rest.is_empty() => Ok(()),
+                None => Err(RequestError::BadHeader(Some("Transfer-Encoding".into())))
+            },
+            Some((last, _)) => Err(RequestError::UnsupportedTransferEncoding(last.to_string())),
+            None => Err(RequestError::BadHeader(Some("Transfer-Encoding".into())))
+        };
+    }
+
+    match (headers.get::<ContentLength>(), max_body_length) {
+        (Some(&ContentLength(length)), Some(max)) if length > max => Err(RequestError::TooLarge),
+        _ => Ok(())
+    }
+}
+
+//Transfer codings are case insensitive.
+fn is_coding(coding: &Encoding, name: &str) -> bool {
+    coding.to_string().eq_ignore_ascii_case(name)
+}
+
+//The response when the request can't be handled. The connection is closed,
+//since the body may still be waiting to be read.
+//Collects the raw default headers, without those that are managed by the
+//server.
+fn raw_default_headers(headers: &Headers) -> Vec<(String, Vec<Vec<u8>>)> {
+    const MANAGED: &'static [&'static str] = &["Date", "Content-Length", "Transfer-Encoding", "Connection"];
+
+    headers.iter()
+        .filter(|header| !MANAGED.iter().any(|name| header.name().eq_ignore_ascii_case(name)))
+        .filter_map(|header| headers.get_raw(header.name()).map(|values| (header.name().to_owned(), values.to_vec())))
+        .collect()
+}
+
+fn send_request_error(error: RequestError, target: &str, mut response: Response) {
+    info!(target: "rustful::server", "rejected a request for {}: {}", target, error);
+    response.set_status(error.status().unwrap_or(StatusCode::InternalServerError));
+    response.headers_mut().set(Connection(vec![ConnectionOption::Close]));
+}
+
+//Collapses repeated slashes and resolves dot segments in the path part of
+//`path`. Returns `None` if it's already normalized.
+fn normalize_path(path: &str) -> Option<String> {
+    let (path, rest) = match path.find(|c| c == '?' || c == '#') {
+        Some(index) => path.split_at(index),
+        None => (path, "")
+    };
+
+    let mut segments = vec![];
+    let mut trailing_slash = false;
+
+    for segment in path.split('/').skip_while(|s| s.is_empty()) {
+        trailing_slash = true;
+        match segment {
+            "" | "." => {},
+            ".." => {
+                segments.pop();
+            },
+            segment => {
+                segments.push(segment);
+                trailing_slash = false;
+            }
+        }
+    }
+
+    let mut normalized = String::with_capacity(path.len() + rest.len());
+    for segment in &segments {
+        normalized.push('/');
+        normalized.push_str(segment);
+    }
+
+    if trailing_slash || segments.is_empty() {
+        normalized.push('/');
+    }
+
+    if normalized == path || (path.is_empty() && segments.is_empty()) {
+        None
+    } else {
+        normalized.push_str(rest);
+        Some(normalized)
+    }
+}
+
+fn parse_path(path: &str) -> Result<ParsedUri, PercentDecodeError> {
     match path.find('?') {
         Some(index) => {
             let (query, fragment) = parse_fragment(&path[index+1..]);
 
-            let mut path: Vec<_> = percent_decode(path[..index].as_bytes()).collect();
+            let raw_path = &path[..index];
+            let mut path = try!(percent_decode(raw_path.as_bytes())).into_bytes();
             if path.is_empty() {
                 path.push(b'/');
             }
 
-            ParsedUri {
+            Ok(ParsedUri {
                 host: None,
                 uri_path: UriPath::Path(path.into()),
-                query: utils::parse_parameters(query.as_bytes()),
-                fragment: fragment.map(|f| percent_decode(f.as_bytes()).collect::<Vec<_>>().into()),
-            }
+                segments: try!(decode_segments(raw_path)),
+                query: try!(utils::parse_parameters(query.as_bytes())),
+                fragment: try!(decode_fragment(fragment)),
+            })
         },
         None => {
-            let (path, fragment) = parse_fragment(&path);
+            let (raw_path, fragment) = parse_fragment(&path);
 
-            let mut path: Vec<_> = percent_decode(path.as_bytes()).collect();
+            let mut path = try!(percent_decode(raw_path.as_bytes())).into_bytes();
             if path.is_empty() {
                 path.push(b'/');
             }
 
-            ParsedUri {
+            Ok(ParsedUri {
                 host: None,
                 uri_path: UriPath::Path(path.into()),
+                segments: try!(decode_segments(raw_path)),
                 query: Parameters::new(),
-                fragment: fragment.map(|f| percent_decode(f.as_bytes()).collect::<Vec<_>>().into())
-            }
+                fragment: try!(decode_fragment(fragment))
+            })
         }
     }
 }
 
+//Splits `path` into route segments before they are decoded, so that encoded
+//slashes, like `%2F`, become a part of their segment instead of separating
+//it. Returns `None` if there are no encoded slashes, since the decoded path
+//can then be split as it is.
+fn decode_segments(path: &str) -> Result<Option<Vec<Vec<u8>>>, PercentDecodeError> {
+    let bytes = path.as_bytes();
+    let encoded_slash = bytes.windows(3).any(|w| w[0] == b'%' && w[1] == b'2' && (w[2] == b'f' || w[2] == b'F'));
+    if !encoded_slash {
+        return Ok(None);
+    }
+
+    let mut segments = vec![];
+    for segment in bytes.segments() {
+        segments.push(try!(percent_decode(segment)).into_bytes());
+    }
+
+    Ok(Some(segments))
+}
+
 fn parse_fragment(path: &str) -> (&str, Option<&str>) {
     match path.find('#') {
         Some(index) => (&path[..index], Some(&path[index+1..])),
@@ -301,54 +1116,100 @@ fn parse_fragment(path: &str) -> (&str, Option<&str>) {
     }
 }
 
-fn parse_url(url: &Url) -> ParsedUri {
-    let mut path = vec![];
-    path.extend(percent_decode(url.path().as_bytes()));
+fn decode_fragment(fragment: Option<&str>) -> Result<Option<MaybeUtf8Owned>, PercentDecodeError> {
+    match fragment {
+        Some(fragment) => percent_decode(fragment.as_bytes()).map(|f| Some(f.into_bytes().into())),
+        None => Ok(None)
+    }
+}
+
+fn parse_url(url: &Url) -> Result<ParsedUri, PercentDecodeError> {
+    let path = try!(percent_decode(url.path().as_bytes())).into_bytes();
 
-    let query = url.query_pairs()
-        .map(|(k, v)| (k.into_owned(), v.into_owned()))
-        .collect();
+    let query = match url.query() {
+        Some(query) => try!(utils::parse_parameters(query.as_bytes())),
+        None => Parameters::new()
+    };
 
     let host = url.host_str().map(|host| (host.into(), url.port()));
 
-    ParsedUri {
+    Ok(ParsedUri {
         host: host,
         uri_path: UriPath::Path(path.into()),
+        segments: try!(decode_segments(url.path())),
         query: query,
-        fragment: url.fragment().as_ref().map(|f| percent_decode(f.as_bytes()).collect::<Vec<_>>().into())
+        fragment: try!(decode_fragment(url.fragment()))
+    })
+}
+
+//Split an authority-form target into its host and port. The port is
+//required, and IPv6 addresses keep their brackets, as in the `Host` header.
+fn parse_authority(authority: &str) -> Option<(String, Option<u16>)> {
+    let separator = match authority.rfind(':') {
+        Some(index) => index,
+        None => return None
+    };
+    let (host, port) = (&authority[..separator], &authority[separator + 1..]);
+
+    let valid_host = if host.starts_with('[') {
+        host.len() > 2 && host.ends_with(']') && host[1..host.len() - 1].bytes().all(|byte| byte.is_ascii_hexdigit() || byte == b':' || byte == b'.')
+    } else {
+        !host.is_empty() && host.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'.' || byte == b'_')
+    };
+
+    if !valid_host || port.is_empty() || !port.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
     }
+
+    port.parse().ok().map(|port| (host.to_owned(), Some(port)))
 }
 
 //Helper to handle multiple protocols.
 enum HyperServer {
-    Http(hyper::server::Server<HttpListener>),
+    Http(hyper::server::Server<BackoffListener<HttpListener>>),
     #[cfg(feature = "ssl")]
-    Https(hyper::server::Server<HttpsListener<Openssl>>),
+    Https(hyper::server::Server<BackoffListener<HttpsListener<Openssl>>>),
 }
 
 impl HyperServer {
-    fn http(host: SocketAddr) -> HttpResult<HyperServer> {
-        hyper::server::Server::http(host).map(HyperServer::Http)
+    fn http(host: SocketAddr, ipv6_only: Option<bool>, backoff: AcceptBackoff, lifecycle: Lifecycle) -> HttpResult<HyperServer> {
+        let listener = try!(bind(host, ipv6_only));
+        Ok(HyperServer::Http(hyper::server::Server::new(BackoffListener::new(listener, backoff, lifecycle))))
     }
 
     #[cfg(feature = "ssl")]
-    fn https(host: SocketAddr, cert: PathBuf, key: PathBuf) -> HttpResult<HyperServer> {
+    fn https(host: SocketAddr, ipv6_only: Option<bool>, backoff: AcceptBackoff, lifecycle: Lifecycle, cert: PathBuf, key: PathBuf) -> HttpResult<HyperServer> {
         let ssl = try!(Openssl::with_cert_and_key(cert, key));
-        hyper::server::Server::https(host, ssl).map(HyperServer::Https)
+        let listener = try!(bind(host, ipv6_only));
+        let listener = HttpsListener::with_listener(listener, ssl);
+        Ok(HyperServer::Https(hyper::server::Server::new(BackoffListener::new(listener, backoff, lifecycle))))
     }
 
+    //The read timeout is the keep-alive timeout, to limit how long it may
+    //take before the first request, but it's lifted for the request bodies.
     #[cfg(feature = "ssl")]
     fn keep_alive(&mut self, timeout: Option<Duration>) {
         match *self {
-            HyperServer::Http(ref mut s) => s.keep_alive(timeout),
-            HyperServer::Https(ref mut s) => s.keep_alive(timeout),
+            HyperServer::Http(ref mut s) => {
+                s.keep_alive(timeout);
+                s.set_read_timeout(timeout);
+            },
+            HyperServer::Https(ref mut s) => {
+                s.keep_alive(timeout);
+                s.set_read_timeout(timeout);
+            },
         }
     }
 
+    //The read timeout is the keep-alive timeout, to limit how long it may
+    //take before the first request, but it's lifted for the request bodies.
     #[cfg(not(feature = "ssl"))]
     fn keep_alive(&mut self, timeout: Option<Duration>) {
         match *self {
-            HyperServer::Http(ref mut s) => s.keep_alive(timeout),
+            HyperServer::Http(ref mut s) => {
+                s.keep_alive(timeout);
+                s.set_read_timeout(timeout);
+            },
         }
     }
 
@@ -373,7 +1234,7 @@ impl HyperServer {
 fn parse_path_parts() {
     let with = "this".to_owned().into();
     let and = "that".to_owned().into();
-    let ParsedUri { uri_path, query, fragment, .. } = parse_path("/path/to/something?with=this&and=that#lol");
+    let ParsedUri { uri_path, query, fragment, .. } = parse_path("/path/to/something?with=this&and=that#lol").unwrap();
     assert_eq!(uri_path.as_path(), Some("/path/to/something".into()));
     assert_eq!(query.get_raw("with"), Some(&with));
     assert_eq!(query.get_raw("and"), Some(&and));
@@ -384,7 +1245,7 @@ fn parse_path_parts() {
 fn parse_strange_path() {
     let with = "this".to_owned().into();
     let and = "what?".to_owned().into();
-    let ParsedUri { uri_path, query, fragment, .. } = parse_path("/path/to/something?with=this&and=what?#");
+    let ParsedUri { uri_path, query, fragment, .. } = parse_path("/path/to/something?with=this&and=what?#").unwrap();
     assert_eq!(uri_path.as_path(), Some("/path/to/something".into()));
     assert_eq!(query.get_raw("with"), Some(&with));
     assert_eq!(query.get_raw("and"), Some(&and));
@@ -395,20 +1256,20 @@ fn parse_strange_path() {
 fn parse_missing_path_parts() {
     let with = "this".to_owned().into();
     let and = "that".to_owned().into();
-    let ParsedUri { uri_path, query, fragment, .. } = parse_path("/path/to/something?with=this&and=that");
+    let ParsedUri { uri_path, query, fragment, .. } = parse_path("/path/to/something?with=this&and=that").unwrap();
     assert_eq!(uri_path.as_path(), Some("/path/to/something".into()));
     assert_eq!(query.get_raw("with"), Some(&with));
     assert_eq!(query.get_raw("and"), Some(&and));
     assert_eq!(fragment, None);
 
 
-    let ParsedUri { uri_path, query, fragment, .. } = parse_path("/path/to/something#lol");
+    let ParsedUri { uri_path, query, fragment, .. } = parse_path("/path/to/something#lol").unwrap();
     assert_eq!(uri_path.as_path(), Some("/path/to/something".into()));
     assert_eq!(query.len(), 0);
     assert_eq!(fragment, Some("lol".to_owned().into()));
 
 
-    let ParsedUri { uri_path, query, fragment, .. } = parse_path("?with=this&and=that#lol");
+    let ParsedUri { uri_path, query, fragment, .. } = parse_path("?with=this&and=that#lol").unwrap();
     assert_eq!(uri_path.as_path(), Some("/".into()));
     assert_eq!(query.get_raw("with"), Some(&with));
     assert_eq!(query.get_raw("and"), Some(&and));
@@ -421,7 +1282,7 @@ fn parse_url_parts() {
     let with = "this".to_owned().into();
     let and = "that".to_owned().into();
     let url = Url::parse("http://example.com/path/to/something?with=this&and=that#lol").unwrap();
-    let ParsedUri { uri_path, query, fragment, .. } = parse_url(&url);
+    let ParsedUri { uri_path, query, fragment, .. } = parse_url(&url).unwrap();
     assert_eq!(uri_path.as_path(), Some("/path/to/something".into()));
     assert_eq!(query.get_raw("with"), Some(&with));
     assert_eq!(query.get_raw("and"), Some(&and));
@@ -433,7 +1294,7 @@ fn parse_strange_url() {
     let with = "this".to_owned().into();
     let and = "what?".to_owned().into();
     let url = Url::parse("http://example.com/path/to/something?with=this&and=what?#").unwrap();
-    let ParsedUri { uri_path, query, fragment, .. } = parse_url(&url);
+    let ParsedUri { uri_path, query, fragment, .. } = parse_url(&url).unwrap();
     assert_eq!(uri_path.as_path(), Some("/path/to/something".into()));
     assert_eq!(query.get_raw("with"), Some(&with));
     assert_eq!(query.get_raw("and"), Some(&and));
@@ -445,7 +1306,7 @@ fn parse_missing_url_parts() {
     let with = "this".to_owned().into();
     let and = "that".to_owned().into();
     let url = Url::parse("http://example.com/path/to/something?with=this&and=that").unwrap();
-    let ParsedUri { uri_path, query, fragment, .. } = parse_url(&url);
+    let ParsedUri { uri_path, query, fragment, .. } = parse_url(&url).unwrap();
     assert_eq!(uri_path.as_path(), Some("/path/to/something".into()));
     assert_eq!(query.get_raw("with"), Some(&with));
     assert_eq!(query.get_raw("and"), Some(&and));
@@ -453,16 +1314,3610 @@ fn parse_missing_url_parts() {
 
 
     let url = Url::parse("http://example.com/path/to/something#lol").unwrap();
-    let ParsedUri { uri_path, query, fragment, .. } = parse_url(&url);
+    let ParsedUri { uri_path, query, fragment, .. } = parse_url(&url).unwrap();
     assert_eq!(uri_path.as_path(), Some("/path/to/something".into()));
     assert_eq!(query.len(), 0);
     assert_eq!(fragment, Some("lol".to_owned().into()));
 
 
     let url = Url::parse("http://example.com?with=this&and=that#lol").unwrap();
-    let ParsedUri { uri_path, query, fragment, .. } = parse_url(&url);
+    let ParsedUri { uri_path, query, fragment, .. } = parse_url(&url).unwrap();
     assert_eq!(uri_path.as_path(), Some("/".into()));
     assert_eq!(query.get_raw("with"), Some(&with));
     assert_eq!(query.get_raw("and"), Some(&and));
     assert_eq!(fragment, Some("lol".to_owned().into()));
 }
+
+#[test]
+fn parse_malformed_path() {
+    assert_eq!(parse_path("/path/%G0").err(), Some(PercentDecodeError::InvalidEscape(6)));
+    assert_eq!(parse_path("/path?a=%A").err(), Some(PercentDecodeError::Truncated(0)));
+    assert_eq!(parse_path("/path#%").err(), Some(PercentDecodeError::Truncated(0)));
+}
+
+#[test]
+fn parse_double_encoded_path() {
+    let ParsedUri { uri_path, .. } = parse_path("/path/%252e%252e/file").unwrap();
+    assert_eq!(uri_path.as_path(), Some("/path/%2e%2e/file".into()));
+}
+
+#[test]
+fn normalize_paths() {
+    assert_eq!(normalize_path("/"), None);
+    assert_eq!(normalize_path("?a=b"), None);
+    assert_eq!(normalize_path("/a/b/"), None);
+    assert_eq!(normalize_path("/a/b?c=//d#/./e"), None);
+    assert_eq!(normalize_path("/a%2F%2E%2E/b"), None);
+    assert_eq!(normalize_path("//"), Some("/".into()));
+    assert_eq!(normalize_path("/users//42"), Some("/users/42".into()));
+    assert_eq!(normalize_path("/a/./b"), Some("/a/b".into()));
+    assert_eq!(normalize_path("/a/b/../c?d=e"), Some("/a/c?d=e".into()));
+    assert_eq!(normalize_path("/a/b/.."), Some("/a/".into()));
+    assert_eq!(normalize_path("/../../a#b"), Some("/a#b".into()));
+}
+
+//The response when no handler is found.
+fn send_not_found(context: &Context, mut response: Response) {
+    response.set_status(StatusCode::NotFound);
+
+    let available = [
+        content_type!(Text / Html; Charset = Utf8),
+        content_type!(Application / Json; Charset = Utf8)
+    ];
+
+    match context.preferred_content_type(&available) {
+        Some(media_type) if *media_type == available[0] => {
+            response.headers_mut().set(ContentType(media_type.clone()));
+            response.send("<!DOCTYPE html>\n<html><head><title>404 Not Found</title></head><body><h1>Not Found</h1></body></html>");
+        },
+        Some(media_type) => {
+            response.headers_mut().set(ContentType(media_type.clone()));
+            response.send("{\"error\":\"not found\"}");
+        },
+        None => {}
+    }
+}
+
+//Binds a listener to `host`, with `IPV6_V6ONLY` set to `ipv6_only` if it's
+//an IPv6 address.
+fn bind(host: SocketAddr, ipv6_only: Option<bool>) -> HttpResult<HttpListener> {
+    match (host, ipv6_only) {
+        (SocketAddr::V6(_), Some(ipv6_only)) => {
+            let builder = try!(TcpBuilder::new_v6());
+            try!(builder.only_v6(ipv6_only));
+
+            //Same as `TcpListener::bind`.
+            if cfg!(unix) {
+                try!(builder.reuse_address(true));
+            }
+
+            let listener = try!(try!(builder.bind(host)).listen(128));
+            Ok(listener.into())
+        },
+        (host, _) => HttpListener::new(host)
+    }
+}
+
+//Get the TCP stream of a request, if it's known.
+#[cfg(feature = "ssl")]
+fn tcp_stream<'a>(request: &'a hyper::server::request::Request) -> Option<&'a TcpStream> {
+    use openssl::ssl::SslStream;
+
+    request.downcast_ref::<HttpStream>()
+        .or_else(|| request.downcast_ref::<SslStream<HttpStream>>().map(|stream| stream.get_ref()))
+        .map(|stream| &stream.0)
+}
+
+//Get the TCP stream of a request, if it's known.
+#[cfg(not(feature = "ssl"))]
+fn tcp_stream<'a>(request: &'a hyper::server::request::Request) -> Option<&'a TcpStream> {
+    request.downcast_ref::<HttpStream>().map(|stream| &stream.0)
+}
+
+fn set_socket_options(stream: &TcpStream, nodelay: bool, linger: Option<Duration>) -> io::Result<()> {
+    try!(stream.set_nodelay(nodelay));
+    if linger.is_some() {
+        try!(TcpStreamExt::set_linger(stream, linger));
+    }
+    Ok(())
+}
+
+//Turns IPv4-mapped IPv6 addresses into IPv4 addresses.
+//Check if the last `X-Forwarded-Proto` value, which is the one that was
+//added by the closest proxy, is `https`.
+fn forwarded_https(headers: &Headers) -> bool {
+    let last = headers.get_raw("X-Forwarded-Proto")
+        .and_then(|values| values.last())
+        .and_then(|value| value.split(|&b| b == b',').last());
+
+    match last {
+        Some(proto) => String::from_utf8_lossy(proto).trim().eq_ignore_ascii_case("https"),
+        None => false
+    }
+}
+
+fn unmap_address(address: SocketAddr) -> SocketAddr {
+    if let SocketAddr::V6(address) = address {
+        let segments = address.ip().segments();
+        if segments[..6] == [0, 0, 0, 0, 0, 0xffff] {
+            let ip = Ipv4Addr::new(
+                (segments[6] >> 8) as u8,
+                segments[6] as u8,
+                (segments[7] >> 8) as u8,
+                segments[7] as u8
+            );
+            return SocketAddr::V4(SocketAddrV4::new(ip, address.port()));
+        }
+    }
+
+    address
+}
+
+//Lets `server` handle the raw `request` and returns the raw response.
+#[cfg(test)]
+fn send_request<R: Router>(server: &ServerInstance<R>, request: &str) -> String {
+    use hyper::buffer::BufReader;
+    use hyper::net::NetworkStream;
+
+    let mut stream = ::mock::MockStream::new(request);
+
+    let mut output = vec![];
+    {
+        let addr = stream.peer_addr().unwrap();
+        let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+        let request = hyper::server::request::Request::new(&mut reader, addr).unwrap();
+        let mut headers = hyper::header::Headers::new();
+        let mut response = hyper::server::response::Response::new(&mut output, &mut headers);
+        //Hyper does this too.
+        response.version = request.version;
+        server.handle(request, response);
+    }
+
+    String::from_utf8(output).unwrap()
+}
+
+#[cfg(test)]
+fn path_server(path_normalization: PathNormalization) -> ServerInstance<::TreeRouter<::router::MethodRouter<::router::Variables<fn(Context, Response)>>>> {
+    fn show_path(context: Context, response: Response) {
+        response.send(context.uri_path.to_string());
+    }
+
+    let mut router = ::TreeRouter::new();
+    router.insert(hyper::method::Method::Get, "/users/:id", show_path as fn(Context, Response));
+    router.insert(hyper::method::Method::Get, "/users/:id/*", show_path as fn(Context, Response));
+
+    Server {
+        path_normalization: path_normalization,
+        ..Server::new(router)
+    }.build().0
+}
+
+#[test]
+fn path_normalization_off() {
+    let server = path_server(PathNormalization::Off);
+    let response = send_request(&server, "GET /users//42 HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\n/users//42"), "{}", response);
+
+    let response = send_request(&server, "GET /users/42/./a HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\n/users/42/./a"), "{}", response);
+}
+
+#[test]
+fn path_normalization_normalize() {
+    let server = path_server(PathNormalization::Normalize);
+    let response = send_request(&server, "GET /users//42 HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\n/users/42"), "{}", response);
+
+    let response = send_request(&server, "GET /users/42/../42/./a%2F.. HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\n/users/42/a/.."), "{}", response);
+}
+
+#[test]
+fn path_normalization_redirect() {
+    let server = path_server(PathNormalization::Redirect);
+    let response = send_request(&server, "GET /users//42?a=b HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 301 "), "{}", response);
+    assert!(response.contains("\r\nLocation: /users/42?a=b\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /users/42 HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\n/users/42"), "{}", response);
+}
+
+#[test]
+fn completion_observer() {
+    use std::io::Read;
+    use std::sync::Mutex;
+
+    fn echo_half(mut context: Context, response: Response) {
+        let mut buffer = [0; 4];
+        context.body.read_exact(&mut buffer).unwrap();
+        let mut chunked = response.into_chunked();
+        chunked.send(&buffer[..]);
+        chunked.send("!");
+        assert_eq!(chunked.bytes_written(), 5);
+    }
+
+    let reports = Arc::new(Mutex::new(vec![]));
+    let observer_reports = reports.clone();
+    let server = Server {
+        completion_observers: vec![Box::new(move |info: &CompletionInfo| {
+            observer_reports.lock().unwrap().push(info.clone());
+        })],
+        ..Server::new(echo_half as fn(Context, Response))
+    }.build().0;
+
+    send_request(&server, "POST /echo HTTP/1.1\r\nContent-Length: 8\r\n\r\nabcdefgh");
+    send_request(&server, "GET /%zz HTTP/1.1\r\n\r\n");
+
+    let reports = reports.lock().unwrap();
+    assert_eq!(reports.len(), 2);
+
+    assert_eq!(reports[0].method, hyper::method::Method::Post);
+    assert_eq!(reports[0].uri_path, Some(UriPath::Path("/echo".to_owned().into())));
+    assert_eq!(reports[0].status, StatusCode::Ok);
+    assert_eq!(reports[0].bytes_read, 4);
+    assert_eq!(reports[0].bytes_written, 5);
+
+    assert_eq!(reports[0].raw_target, "/echo");
+
+    assert_eq!(reports[1].uri_path, None);
+    assert_eq!(reports[1].raw_target, "/%zz");
+    assert_eq!(reports[1].status, StatusCode::BadRequest);
+    assert_eq!(reports[1].bytes_read, 0);
+    assert_eq!(reports[1].bytes_written, 0);
+}
+
+#[test]
+fn redact_observed_headers() {
+    use std::sync::Mutex;
+
+    fn login(_context: Context, mut response: Response) {
+        response.headers_mut().set_raw("Set-Cookie", vec![b"session=secret".to_vec()]);
+        response.headers_mut().set_raw("X-Visible", vec![b"yes".to_vec()]);
+        response.send("welcome");
+    }
+
+    let reports = Arc::new(Mutex::new(vec![]));
+    let observer_reports = reports.clone();
+    let server = Server {
+        completion_observers: vec![Box::new(move |info: &CompletionInfo| {
+            observer_reports.lock().unwrap().push(info.clone());
+        })],
+        ..Server::new(login as fn(Context, Response))
+    }.build().0;
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\nAuthorization: Basic c2VjcmV0\r\nAccept: text/plain\r\n\r\n");
+    assert!(response.contains("\r\nSet-Cookie: session=secret\r\n"), "{}", response);
+
+    let reports = reports.lock().unwrap();
+    let redacted = &[b"[REDACTED]".to_vec()][..];
+    assert_eq!(reports[0].request_headers.get_raw("Authorization"), Some(redacted));
+    assert_eq!(reports[0].request_headers.get_raw("Accept"), Some(&[b"text/plain".to_vec()][..]));
+    assert_eq!(reports[0].response_headers.get_raw("Set-Cookie"), Some(redacted));
+    assert_eq!(reports[0].response_headers.get_raw("X-Visible"), Some(&[b"yes".to_vec()][..]));
+}
+
+#[test]
+fn capture_response_body() {
+    use std::sync::Mutex;
+
+    fn stream(context: Context, response: Response) {
+        let mut response = response.into_chunked();
+        for chunk in context.query.get("chunks").unwrap_or("".into()).split(',') {
+            response.send(chunk.as_bytes());
+        }
+    }
+
+    let reports = Arc::new(Mutex::new(vec![]));
+    let observer_reports = reports.clone();
+    let server = Server::builder(stream as fn(Context, Response))
+        .completion_observer(move |info: &CompletionInfo| {
+            observer_reports.lock().unwrap().push((info.response_body.clone(), info.response_body_truncated));
+        })
+        .capture_response_body(8)
+        .auto_content_length(0)
+        .build()
+        .unwrap()
+        .build()
+        .0;
+
+    let response = send_request(&server, "GET /?chunks=abc,def HTTP/1.1\r\n\r\n");
+    assert!(response.contains("\r\n3\r\nabc\r\n3\r\ndef\r\n"), "{}", response);
+    let response = send_request(&server, "GET /?chunks=abcde,fghij,klmno HTTP/1.1\r\n\r\n");
+    assert!(response.contains("\r\n5\r\nabcde\r\n5\r\nfghij\r\n5\r\nklmno\r\n"), "{}", response);
+
+    assert_eq!(*reports.lock().unwrap(), vec![
+        (Some(b"abcdef".to_vec()), false),
+        (Some(b"abcdefgh".to_vec()), true),
+    ]);
+}
+
+#[test]
+fn observe_conditional_requests() {
+    use std::sync::Mutex;
+
+    fn cached(context: Context, mut response: Response) {
+        assert_eq!(context.is_conditional(), context.has_header("If-None-Match"));
+        if context.header_raw("If-None-Match") == Some(&[b"\"a\"".to_vec()][..]) {
+            response.set_status(StatusCode::NotModified);
+        } else {
+            response.send("content");
+        }
+    }
+
+    let reports = Arc::new(Mutex::new(vec![]));
+    let observer_reports = reports.clone();
+    let server = Server {
+        completion_observers: vec![Box::new(move |info: &CompletionInfo| {
+            observer_reports.lock().unwrap().push((info.conditional, info.not_modified));
+        })],
+        ..Server::new(cached as fn(Context, Response))
+    }.build().0;
+
+    send_request(&server, "GET / HTTP/1.1\r\n\r\n");
+    send_request(&server, "GET / HTTP/1.1\r\nIf-None-Match: \"a\"\r\n\r\n");
+    send_request(&server, "GET / HTTP/1.1\r\nIf-None-Match: \"b\"\r\n\r\n");
+
+    assert_eq!(*reports.lock().unwrap(), vec![(false, false), (true, true), (true, false)]);
+}
+
+#[test]
+fn completion_callback() {
+    use std::io;
+    use std::sync::Mutex;
+
+    struct Report(Arc<Mutex<Vec<(StatusCode, u64, bool)>>>);
+
+    impl Handler for Report {
+        fn handle_request(&self, context: Context, mut response: Response) {
+            let reports = self.0.clone();
+            response.on_complete(move |info: &CompletionInfo| {
+                reports.lock().unwrap().push((info.status, info.bytes_written, info.interrupted));
+            });
+
+            response.set_status(StatusCode::Accepted);
+            if context.uri_path.as_path().map_or(false, |path| path.as_utf8() == Some("/fail")) {
+                let _ = response.send_with(|writer| {
+                    try!(writer.write_all(b"part"));
+                    Err(io::Error::new(io::ErrorKind::Other, "failed"))
+                });
+            } else {
+                response.send("done");
+            }
+        }
+    }
+
+    let reports = Arc::new(Mutex::new(vec![]));
+    let server = Server::new(Report(reports.clone())).build().0;
+
+    send_request(&server, "GET / HTTP/1.1\r\n\r\n");
+    send_request(&server, "GET /fail HTTP/1.1\r\n\r\n");
+
+    let reports = reports.lock().unwrap();
+    assert_eq!(*reports, vec![(StatusCode::Accepted, 4, false), (StatusCode::Accepted, 4, true)]);
+}
+
+#[test]
+fn forwarded_proto() {
+    fn scheme(context: Context, response: Response) {
+        response.send(if context.is_secure() { "https" } else { "http" });
+    }
+
+    let untrusted = Server::new(scheme as fn(Context, Response)).build().0;
+    let response = send_request(&untrusted, "GET / HTTP/1.1\r\nX-Forwarded-Proto: https\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nhttp"), "{}", response);
+
+    let trusted = Server {
+        //The address of every `MockStream`.
+        trusted_proxies: vec!["127.0.0.1".parse().unwrap()],
+        ..Server::new(scheme as fn(Context, Response))
+    }.build().0;
+    let response = send_request(&trusted, "GET / HTTP/1.1\r\nX-Forwarded-Proto: https\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nhttps"), "{}", response);
+    let response = send_request(&trusted, "GET / HTTP/1.1\r\nX-Forwarded-Proto: https, http\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nhttp"), "{}", response);
+    let response = send_request(&trusted, "GET / HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nhttp"), "{}", response);
+}
+
+#[test]
+fn check_origin() {
+    fn socket(context: Context, mut response: Response) {
+        if context.origin_allowed(&["https://example.com", "http://[::1]:8080", "not an origin"]) {
+            response.send(context.origin().unwrap_or("").to_owned());
+        } else {
+            response.set_status(StatusCode::Forbidden);
+        }
+    }
+
+    let server = Server::new(socket as fn(Context, Response)).build().0;
+
+    for origin in &["https://example.com", "HTTPS://Example.COM:443", "http://[::1]:8080"] {
+        let response = send_request(&server, &format!("GET / HTTP/1.1\r\nOrigin: {}\r\n\r\n", origin));
+        assert!(response.ends_with(&format!("\r\n\r\n{}", origin)), "{}", response);
+    }
+
+    let disallowed = [
+        "http://example.com",
+        "https://example.com:8443",
+        "https://sub.example.com",
+        "https://example.com.evil.net",
+        "https://evil.net/https://example.com",
+        "https://user@example.com",
+        "null",
+        "not an origin",
+    ];
+    for origin in &disallowed {
+        let response = send_request(&server, &format!("GET / HTTP/1.1\r\nOrigin: {}\r\n\r\n", origin));
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{}: {}", origin, response);
+    }
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\nOrigin: https://example.com\r\nOrigin: https://evil.net\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{}", response);
+}
+
+#[test]
+fn custom_reason_phrase() {
+    fn expired(_context: Context, mut response: Response) {
+        response.set_status_with_reason(419, "Session Expired").unwrap();
+        response.send("expired");
+    }
+
+    let server = Server::new(expired as fn(Context, Response)).build().0;
+    let response = send_request(&server, "GET / HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 419 Session Expired\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nexpired"), "{}", response);
+}
+
+#[test]
+fn abort_chunked() {
+    use hyper::buffer::BufReader;
+    use hyper::net::NetworkStream;
+
+    fn broken(_context: Context, response: Response) {
+        let mut chunked = response.into_chunked();
+        chunked.send("abc");
+        chunked.abort();
+    }
+
+    let server = Server::new(broken as fn(Context, Response)).build().0;
+    let mut stream = ::mock::MockStream::new("GET / HTTP/1.1\r\n\r\n");
+    let mut output = vec![];
+    let mut headers = hyper::header::Headers::new();
+    {
+        let addr = stream.peer_addr().unwrap();
+        let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+        let request = hyper::server::request::Request::new(&mut reader, addr).unwrap();
+        let response = hyper::server::response::Response::new(&mut output, &mut headers);
+        server.handle(request, response);
+    }
+
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.ends_with("\r\n\r\n3\r\nabc\r\n"), "{}", output);
+    assert!(!hyper::http::should_keep_alive(hyper::version::HttpVersion::Http11, &headers));
+}
+
+#[test]
+fn http_1_0_clients() {
+    use std::io::Write;
+    use hyper::buffer::BufReader;
+    use hyper::net::NetworkStream;
+
+    fn stream(_context: Context, response: Response) {
+        let mut chunked = response.into_chunked();
+        chunked.send("abc");
+        chunked.send("def");
+    }
+
+    fn sized(_context: Context, response: Response) {
+        response.send("abc");
+    }
+
+    //Sets up the connection headers like Hyper does.
+    fn send<R: Router>(server: &ServerInstance<R>, request: &str) -> (String, bool) {
+        let mut stream = ::mock::MockStream::new(request);
+        let mut output = vec![];
+        let mut headers = hyper::header::Headers::new();
+        let version = {
+            let addr = stream.peer_addr().unwrap();
+            let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+            let request = hyper::server::request::Request::new(&mut reader, addr).unwrap();
+            let version = request.version;
+            if !hyper::http::should_keep_alive(version, &request.headers) {
+                headers.set(Connection::close());
+            }
+            let mut response = hyper::server::response::Response::new(&mut output as &mut Write, &mut headers);
+            response.version = version;
+            server.handle(request, response);
+            version
+        };
+
+        (String::from_utf8(output).unwrap(), hyper::http::should_keep_alive(version, &headers))
+    }
+
+    let server = Server::new(sized as fn(Context, Response)).build().0;
+
+    let (response, keep_alive) = send(&server, "GET / HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.0 200 OK\r\n"), "{}", response);
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+    assert!(!keep_alive);
+
+    let (response, keep_alive) = send(&server, "GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n");
+    assert!(response.contains("Connection: keep-alive\r\n"), "{}", response);
+    assert!(response.contains("Content-Length: 3\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nabc"), "{}", response);
+    assert!(keep_alive);
+
+    //Small bodies would otherwise be sent with a `Content-Length`.
+    let server = Server {
+        auto_content_length: 0,
+        ..Server::new(stream as fn(Context, Response))
+    }.build().0;
+
+    let (response, keep_alive) = send(&server, "GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.0 200 OK\r\n"), "{}", response);
+    assert!(!response.contains("Transfer-Encoding"), "{}", response);
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nabcdef"), "{}", response);
+    assert!(!keep_alive);
+
+    let (response, keep_alive) = send(&server, "GET / HTTP/1.1\r\n\r\n");
+    assert!(response.contains("Transfer-Encoding: chunked\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\n3\r\nabc\r\n3\r\ndef\r\n0\r\n\r\n"), "{}", response);
+    assert!(keep_alive);
+}
+
+#[test]
+fn rewrite_paths() {
+    use anymap::AnyMap;
+    use TreeRouter;
+    use Method::Get;
+
+    struct Tenant(String);
+
+    fn rewrite(path: MaybeUtf8Owned, storage: &mut AnyMap) -> MaybeUtf8Owned {
+        let (tenant, rest) = {
+            let path = path.as_utf8_lossy();
+            let mut parts = path[1..].splitn(2, '/');
+            (parts.next().unwrap_or("").to_owned(), parts.next().map(|rest| rest.to_owned()))
+        };
+
+        match rest {
+            Some(rest) => {
+                storage.insert(Tenant(tenant));
+                format!("/{}", rest).into()
+            },
+            None => "".into()
+        }
+    }
+
+    fn show(context: Context, response: Response) {
+        let tenant = response.filter_storage().get::<Tenant>().map(|tenant| tenant.0.clone()).unwrap_or_default();
+        response.send(format!("{} {}", tenant, context.variables.get("id").unwrap_or_default()));
+    }
+
+    let mut router = TreeRouter::new();
+    router.insert(Get, "/items/:id", show as fn(Context, Response));
+    let server = Server {
+        path_rewrite: Some(Box::new(rewrite)),
+        ..Server::new(router)
+    }.build().0;
+
+    let response = send_request(&server, "GET /acme/items/%31 HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nacme 1"), "{}", response);
+
+    let response = send_request(&server, "GET /acme HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /items/1 HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", response);
+}
+
+#[test]
+fn send_early_hints() {
+    fn page(_context: Context, mut response: Response) {
+        assert!(response.send_early_hints(&["</bad.css>\r\nX-Injected: yes"]).is_err());
+        assert!(response.send_early_hints(&["</bad.css>; rel=preload\r\nX-Injected: yes"]).is_err());
+        assert!(response.send_early_hints(&["</a.css>; rel=preload", "bad.css"]).is_err());
+        assert!(response.send_early_hints(&["</bad file.css>"]).is_err());
+        assert!(response.send_early_hints(&["</bad.css> rel=preload"]).is_err());
+        response.send_early_hints(&["</a.css>; rel=preload", "</b.js>; rel=preload"]).unwrap();
+        response.send("page");
+    }
+
+    let server = Server::new(page as fn(Context, Response)).build().0;
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 103 Early Hints\r\nLink: </a.css>; rel=preload\r\nLink: </b.js>; rel=preload\r\n\r\nHTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\npage"), "{}", response);
+    assert!(!response.contains("X-Injected"), "{}", response);
+
+    let response = send_request(&server, "GET / HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.0 200 OK\r\n"), "{}", response);
+}
+
+#[test]
+fn unmap_addresses() {
+    let mapped: SocketAddr = "[::ffff:192.168.0.1]:8080".parse().unwrap();
+    assert_eq!(unmap_address(mapped), "192.168.0.1:8080".parse().unwrap());
+
+    let v6: SocketAddr = "[::1]:8080".parse().unwrap();
+    assert_eq!(unmap_address(v6), v6);
+
+    let v4: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+    assert_eq!(unmap_address(v4), v4);
+}
+
+#[test]
+fn dual_stack_listener() {
+    use std::net::TcpStream;
+    use hyper::net::{NetworkListener, NetworkStream};
+
+    let mut listener = match bind("[::]:0".parse().unwrap(), Some(false)) {
+        Ok(listener) => listener,
+        Err(_) => return //IPv6 is not available
+    };
+    let port = listener.local_addr().unwrap().port();
+
+    let _client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    let mut stream = listener.accept().unwrap();
+    let address = unmap_address(stream.peer_addr().unwrap());
+    assert_eq!(address.ip(), "127.0.0.1".parse::<::std::net::IpAddr>().unwrap());
+}
+
+#[test]
+fn negotiated_not_found() {
+    let server = path_server(PathNormalization::Off);
+
+    let response = send_request(&server, "GET /missing HTTP/1.1\r\nAccept: application/json\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", response);
+    assert!(response.contains("Content-Type: application/json; charset=utf-8\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\n{\"error\":\"not found\"}"), "{}", response);
+
+    let response = send_request(&server, "GET /missing HTTP/1.1\r\nAccept: text/html,*/*;q=0.8\r\n\r\n");
+    assert!(response.contains("Content-Type: text/html; charset=utf-8\r\n"), "{}", response);
+    assert!(response.ends_with("</html>"), "{}", response);
+
+    let response = send_request(&server, "GET /missing HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\n"), "{}", response);
+}
+
+#[test]
+fn close_connections_while_draining() {
+    let server = path_server(PathNormalization::Off);
+    let lifecycle = server.lifecycle.clone();
+
+    let response = send_request(&server, "GET /users/42 HTTP/1.1\r\n\r\n");
+    assert!(!response.contains("Connection: close\r\n"), "{}", response);
+
+    lifecycle.request_started();
+    lifecycle.begin_shutdown();
+    let response = send_request(&server, "GET /users/42 HTTP/1.1\r\n\r\n");
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+    assert_eq!(lifecycle.state(), ::server::LifecycleState::Draining);
+
+    lifecycle.request_finished();
+    assert_eq!(lifecycle.state(), ::server::LifecycleState::Stopped);
+}
+
+#[test]
+fn persist_sessions() {
+    fn count(context: Context, response: Response) {
+        let session = context.session().unwrap();
+        let visits = session.get("visits").and_then(|v| v.parse().ok()).unwrap_or(0u32) + 1;
+        session.insert("visits", visits.to_string());
+        response.send(visits.to_string());
+    }
+
+    let server = Server {
+        sessions: Some(::session::SessionConfig {
+            key: b"secret".to_vec(),
+            ..Default::default()
+        }),
+        ..Server::new(count as fn(Context, Response))
+    }.build().0;
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\n1"), "{}", response);
+    let start = response.find("Set-Cookie: ").unwrap() + 12;
+    let end = start + response[start..].find(';').unwrap();
+    let cookie = &response[start..end];
+
+    let response = send_request(&server, &format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", cookie));
+    assert!(response.ends_with("\r\n\r\n2"), "{}", response);
+}
+
+#[test]
+fn route_by_host() {
+    fn show_host(context: Context, response: Response) {
+        response.send(format!("{}{}", context.headers.get::<::header::Host>().unwrap().hostname, context.uri_path));
+    }
+
+    let mut example = ::TreeRouter::new();
+    example.insert(hyper::method::Method::Get, "/", show_host as fn(Context, Response));
+
+    let mut router = ::router::HostRouter::new();
+    router.insert_host("*.example.com", example);
+
+    let server = Server::new(router).build().0;
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\nHost: www.example.com:8080\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nwww.example.com/"), "{}", response);
+
+    let response = send_request(&server, "GET http://blog.example.com/ HTTP/1.1\r\nHost: example.org\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nblog.example.com/"), "{}", response);
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\nHost: example.org\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", response);
+}
+
+#[test]
+fn recover_from_panic() {
+    fn panic(context: Context, _response: Response) {
+        if context.uri_path.as_path().map_or(false, |path| path == "/panic") {
+            panic!("no!");
+        }
+    }
+
+    let server = Server::new(panic as fn(Context, Response)).build().0;
+
+    let response = send_request(&server, "GET /panic HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"), "{}", response);
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert_eq!(server.lifecycle.active_requests(), 0);
+    assert_eq!(server.worker_name(), "rustful-worker-1");
+}
+
+#[test]
+fn send_ranges() {
+    fn send_seekable(context: Context, response: Response) {
+        let content = ::std::io::Cursor::new(b"hello, world".to_vec());
+        response.send_seekable(content, 12, &context).unwrap();
+    }
+
+    let server = Server::new(send_seekable as fn(Context, Response)).build().0;
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains("Accept-Ranges: bytes\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nhello, world"), "{}", response);
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\nRange: bytes=7-100\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"), "{}", response);
+    assert!(response.contains("Content-Range: bytes 7-11/12\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nworld"), "{}", response);
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\nRange: bytes=-5\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nworld"), "{}", response);
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\nRange: bytes=12-\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"), "{}", response);
+    assert!(response.contains("Content-Range: bytes */12\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nRange: bytes=7-\r\nContent-Length: 0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nhello, world"), "{}", response);
+}
+
+#[test]
+#[cfg(feature = "rustc_json_body")]
+fn reuse_body_bytes() {
+    fn verify_and_decode(mut context: Context, response: Response) {
+        let length = match context.body_bytes() {
+            Ok(body) => body.len(),
+            Err(e) => return response.send(format!("error: {}", e))
+        };
+        let first = context.body.read_json_body().unwrap();
+        let second = context.body.read_json_body().unwrap();
+        assert_eq!(first, second);
+        response.send(format!("{} bytes: {}", length, first));
+    }
+
+    let server = Server {
+        max_body_length: Some(16),
+        ..Server::new(verify_and_decode as fn(Context, Response))
+    }.build().0;
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n4\r\n{\"a\"\r\n3\r\n:1}\r\n0\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\n7 bytes: {\"a\":1}"), "{}", response);
+
+    //Chunked bodies are limited too.
+    let response = send_request(&server, "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n12\r\n{\"a\":\"1234567890\"}\r\n0\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nerror: the body is too large"), "{}", response);
+}
+
+#[test]
+#[cfg(feature = "rustc_json_body")]
+fn custom_body_deserializer() {
+    use std::collections::BTreeMap;
+    use std::io::Read;
+    use rustc_serialize::json::Json;
+    use context::body::BodyReader;
+
+    //`key: value` lines.
+    fn pairs(body: &mut BodyReader) -> Result<Json, StatusCode> {
+        let mut text = String::new();
+        try!(body.read_to_string(&mut text).map_err(|_| StatusCode::BadRequest));
+
+        let mut object = BTreeMap::new();
+        for line in text.lines() {
+            let mut parts = line.splitn(2, ": ");
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => object.insert(key.to_owned(), Json::String(value.to_owned())),
+                _ => return Err(StatusCode::BadRequest)
+            };
+        }
+        Ok(Json::Object(object))
+    }
+
+    fn greet(mut context: Context, mut response: Response) {
+        match context.read_typed::<BTreeMap<String, String>>() {
+            Ok(fields) => response.send(format!("hello, {}", fields["name"])),
+            Err(status) => response.set_status(status)
+        }
+    }
+
+    let mut body_deserializers = BodyDeserializers::new();
+    body_deserializers.insert("text/x-pairs", pairs);
+    let server = Server {
+        body_deserializers: body_deserializers,
+        ..Server::new(greet as fn(Context, Response))
+    }.build().0;
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nContent-Type: text/x-pairs\r\nContent-Length: 18\r\n\r\nname: Ann\ntitle: Dr");
+    assert!(response.ends_with("\r\n\r\nhello, Ann"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nContent-Type: text/x-pairs\r\nContent-Length: 4\r\n\r\nname");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nContent-Type: application/json; charset=utf-8\r\nContent-Length: 13\r\n\r\n{\"name\":\"Bo\"}");
+    assert!(response.ends_with("\r\n\r\nhello, Bo"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: 7\r\n\r\nname=Cy");
+    assert!(response.ends_with("\r\n\r\nhello, Cy"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 3\r\n\r\n[1]");
+    assert!(response.starts_with("HTTP/1.1 422 Unprocessable Entity\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nContent-Type: text/csv\r\nContent-Length: 9\r\n\r\nname\nDee");
+    assert!(response.starts_with("HTTP/1.1 415 Unsupported Media Type\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\nname: Eve");
+    assert!(response.starts_with("HTTP/1.1 415 Unsupported Media Type\r\n"), "{}", response);
+}
+
+#[test]
+fn route_encoded_slashes() {
+    fn show_name(context: Context, response: Response) {
+        let name = context.variables.get("name").map(|name| name.into_owned());
+        response.send(format!("{:?} {}", name, context.uri_path));
+    }
+
+    let mut router = ::TreeRouter::new();
+    router.insert(Method::Get, "/files/:name", show_name as fn(Context, Response));
+    let server = Server::new(router).build().0;
+
+    let response = send_request(&server, "GET /files/a%2Fb HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.ends_with("Some(\"a/b\") /files/a/b"), "{}", response);
+
+    let response = send_request(&server, "GET /files/a%2fb/ HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("Some(\"a/b\") /files/a/b/"), "{}", response);
+
+    let response = send_request(&server, "GET /files/a/b HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /files%2Fa HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", response);
+}
+
+#[test]
+fn reject_bad_requests() {
+    fn echo(mut context: Context, response: Response) {
+        use std::io::Read;
+
+        let mut body = String::new();
+        context.body.read_to_string(&mut body).unwrap();
+        response.send(body);
+    }
+
+    let server = Server {
+        max_body_length: Some(5),
+        ..Server::new(echo as fn(Context, Response))
+    }.build().0;
+
+    let response = send_request(&server, "GET /%G0 HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /foo%ZZ HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /foo%A HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET example.com:443 HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\nhello");
+    assert!(response.starts_with("HTTP/1.1 501 Not Implemented\r\n"), "{}", response);
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nTransfer-Encoding: compress\r\n\r\nhello");
+    assert!(response.starts_with("HTTP/1.1 501 Not Implemented\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 501 Not Implemented\r\n"), "{}", response);
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n5\r\nhello\r\n0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 501 Not Implemented\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nTransfer-Encoding: identity, Chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nTransfer-Encoding: identity\r\n\r\nhello");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nContent-Length: 6\r\n\r\nhello!");
+    assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello");
+    assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+}
+
+#[test]
+fn reject_many_headers() {
+    fn hello(_context: Context, response: Response) {
+        response.send("hello");
+    }
+
+    let server = Server {
+        max_headers: 10,
+        ..Server::new(hello as fn(Context, Response))
+    }.build().0;
+
+    let headers: String = (0..10).map(|i| format!("X-Header-{}: {}\r\n", i, i)).collect();
+    let response = send_request(&server, &format!("GET / HTTP/1.1\r\n{}\r\n", headers));
+    assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+
+    let headers: String = (0..11).map(|i| format!("X-Header-{}: {}\r\n", i, i)).collect();
+    let response = send_request(&server, &format!("GET / HTTP/1.1\r\n{}\r\n", headers));
+    assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"), "{}", response);
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+
+    let headers: String = (0..11).map(|i| format!("X-Repeated: {}\r\n", i)).collect();
+    let response = send_request(&server, &format!("GET / HTTP/1.1\r\n{}\r\n", headers));
+    assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"), "{}", response);
+}
+
+#[test]
+fn reject_many_parameters() {
+    use context::body::BodyError;
+
+    fn count(mut context: Context, response: Response) {
+        if context.method == Method::Get {
+            return response.send(format!("{}", context.query.len()));
+        }
+
+        match context.body.read_form_data() {
+            Ok(form) => response.send(format!("{}", form.len())),
+            Err(e) => match e.get_ref().and_then(|e| e.downcast_ref::<BodyError>()) {
+                Some(&BodyError::TooManyFields) => response.send("too many fields"),
+                _ => response.send(e.to_string())
+            }
+        }
+    }
+
+    let server = Server {
+        max_parameters: 3,
+        ..Server::new(count as fn(Context, Response))
+    }.build().0;
+
+    let response = send_request(&server, "GET /?a=1&b=2&&c=3# HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\n3"), "{}", response);
+
+    let response = send_request(&server, "GET /?a=1&b=2&c=3#&d=4 HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\n3"), "{}", response);
+
+    let response = send_request(&server, "GET /?a&a&a&a HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nContent-Length: 11\r\n\r\na=1&b=2&c=3");
+    assert!(response.ends_with("\r\n\r\n3"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nContent-Length: 15\r\n\r\na=1&b=2&c=3&d=4");
+    assert!(response.ends_with("\r\n\r\ntoo many fields"), "{}", response);
+
+    let query: String = (0..1001).map(|i| format!("p{}=1&", i)).collect();
+    let server = Server::new(count as fn(Context, Response)).build().0;
+    let response = send_request(&server, &format!("GET /?{} HTTP/1.1\r\n\r\n", query));
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+}
+
+#[test]
+fn reject_long_targets() {
+    fn hello(_context: Context, response: Response) {
+        response.send("hello");
+    }
+
+    let server = Server {
+        max_target_length: 16,
+        path_normalization: PathNormalization::Redirect,
+        ..Server::new(hello as fn(Context, Response))
+    }.build().0;
+
+    let response = send_request(&server, "GET /a/b/c?d=efghijk HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+
+    let response = send_request(&server, "GET /a/b/c?d=efghijkl HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 414 URI Too Long\r\n"), "{}", response);
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /a//b/../../c/d/e/f/g HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 414 URI Too Long\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET http://example.com/a HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 414 URI Too Long\r\n"), "{}", response);
+}
+
+#[test]
+fn default_response_headers() {
+    use hyper::header::ContentLength;
+
+    fn hello(_context: Context, mut response: Response) {
+        response.headers_mut().set_raw("X-Override", vec![b"handler".to_vec()]);
+        response.send("hello");
+    }
+
+    let mut router = ::TreeRouter::new();
+    router.insert(hyper::method::Method::Get, "/", hello as fn(Context, Response));
+
+    let mut default_headers = Headers::new();
+    default_headers.set_raw("X-App-Version", vec![b"1.2.3".to_vec()]);
+    default_headers.set_raw("X-Override", vec![b"default".to_vec()]);
+    default_headers.set(ContentLength(100));
+
+    let server = Server {
+        default_headers: default_headers,
+        ..Server::new(router)
+    }.build().0;
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\n\r\n");
+    assert!(response.contains("X-App-Version: 1.2.3\r\n"), "{}", response);
+    assert!(response.contains("X-Override: handler\r\n"), "{}", response);
+    assert!(response.contains("Content-Length: 5\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+
+    let response = send_request(&server, "GET /missing HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", response);
+    assert!(response.contains("X-App-Version: 1.2.3\r\n"), "{}", response);
+    assert!(response.contains("X-Override: default\r\n"), "{}", response);
+
+    let response = send_request(&server, &format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(9000)));
+    assert!(response.starts_with("HTTP/1.1 414 URI Too Long\r\n"), "{}", response);
+    assert!(response.contains("X-App-Version: 1.2.3\r\n"), "{}", response);
+}
+
+#[test]
+fn plain_connection_info() {
+    fn show_encryption(context: Context, response: Response) {
+        response.send(format!("{}", context.connection_info().is_encrypted()));
+    }
+
+    let server = Server::new(show_encryption as fn(Context, Response)).build().0;
+    let response = send_request(&server, "GET / HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nfalse"), "{}", response);
+}
+
+#[test]
+fn close_idle_connections() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    fn hello(_context: Context, response: Response) {
+        response.send("hello");
+    }
+
+    let lifecycle = Lifecycle::new();
+    let mut listening = Server {
+        host: "127.0.0.1:0".parse::<SocketAddr>().unwrap().into(),
+        threads: Some(2),
+        keep_alive: Some(KeepAlive {
+            timeout: Duration::from_millis(200),
+            free_threads: 0
+        }),
+        lifecycle: lifecycle.clone(),
+        ..Server::new(hello as fn(Context, Response))
+    }.run().unwrap();
+
+    let mut client = TcpStream::connect(listening.socket).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+
+    let mut response = vec![];
+    client.read_to_end(&mut response).unwrap();
+    let response = String::from_utf8(response).unwrap();
+    assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+
+    let mut client = TcpStream::connect(listening.socket).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    assert_eq!(client.read(&mut [0; 16]).unwrap(), 0);
+    assert_eq!(lifecycle.idle_connections(), 0);
+
+    //Let the server threads run in the background.
+    listening.close().unwrap();
+}
+
+#[test]
+fn lifecycle_hooks() {
+    use std::thread;
+
+    let started = Arc::new(AtomicUsize::new(0));
+    let stopped = Arc::new(AtomicUsize::new(0));
+    let counts = (started.clone(), stopped.clone());
+    let handler = move |context: Context, response: Response| {
+        if context.uri_path.as_utf8_path() == Some("/stop") {
+            context.lifecycle.begin_shutdown();
+        }
+        response.send(format!("{} {}", counts.0.load(Ordering::SeqCst), counts.1.load(Ordering::SeqCst)));
+    };
+
+    let lifecycle = Lifecycle::new();
+    let (on_start, on_shutdown) = (started.clone(), stopped.clone());
+    let mut listening = Server::builder(handler)
+        .host("127.0.0.1:0".parse::<SocketAddr>().unwrap().into())
+        .threads(2)
+        .lifecycle(lifecycle.clone())
+        .on_start(move || { on_start.fetch_add(1, Ordering::SeqCst); })
+        .on_shutdown(move || { on_shutdown.fetch_add(1, Ordering::SeqCst); })
+        .build()
+        .unwrap()
+        .run()
+        .unwrap();
+    assert_eq!(started.load(Ordering::SeqCst), 1);
+
+    let request = |target: &str| {
+        let mut client = TcpStream::connect(listening.socket).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.write_all(format!("GET {} HTTP/1.1\r\nConnection: close\r\n\r\n", target).as_bytes()).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    let response = request("/");
+    assert!(response.ends_with("\r\n\r\n1 0"), "{}", response);
+
+    //The hook waits for the request that began the shutdown.
+    let response = request("/stop");
+    assert!(response.ends_with("\r\n\r\n1 0"), "{}", response);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while stopped.load(Ordering::SeqCst) == 0 && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(lifecycle.state(), ::server::LifecycleState::Stopped);
+
+    lifecycle.begin_shutdown();
+    let response = request("/");
+    assert!(response.ends_with("\r\n\r\n1 1"), "{}", response);
+    assert_eq!(started.load(Ordering::SeqCst), 1);
+    assert_eq!(stopped.load(Ordering::SeqCst), 1);
+
+    //Let the server threads run in the background.
+    listening.close().unwrap();
+}
+
+#[test]
+fn serve_in_memory_connection() {
+    fn echo(mut context: Context, response: Response) {
+        let body = context.body.read_query_body().ok().and_then(|body| body.get("say").map(|say| say.into_owned()));
+        response.send(format!("{} {}", context.method, body.unwrap_or_default()));
+    }
+
+    //Removes the date, since it changes.
+    fn without_date(output: Vec<u8>) -> String {
+        let output = String::from_utf8(output).unwrap();
+        output.split("\r\n").filter(|line| !line.starts_with("Date: ")).collect::<Vec<_>>().join("\r\n")
+    }
+
+    let server = Server {
+        keep_alive: Some(KeepAlive {
+            timeout: Duration::from_secs(5),
+            free_threads: 0
+        }),
+        ..Server::new(echo as fn(Context, Response))
+    }.build().0;
+
+    //The third request is never read, since the second one closes the
+    //connection.
+    let stream = ::mock::MockStream::new(
+        "POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\nsay=hello\
+        GET / HTTP/1.1\r\nConnection: close\r\n\r\n\
+        GET / HTTP/1.1\r\n\r\n"
+    );
+    let stream = server.serve_connection(stream, "127.0.0.1:1337".parse().unwrap());
+    assert_eq!(
+        without_date(stream.output),
+        "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nServer: rustful\r\nContent-Length: 10\r\n\r\nPOST hello\
+        HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Type: text/html; charset=utf-8\r\nServer: rustful\r\nContent-Length: 4\r\n\r\nGET "
+    );
+}
+
+#[test]
+fn reject_before_body() {
+    use std::sync::atomic::AtomicBool;
+
+    static HANDLED: AtomicBool = AtomicBool::new(false);
+
+    fn upload(mut context: Context, response: Response) {
+        HANDLED.store(true, Ordering::SeqCst);
+        let mut body = String::new();
+        context.body.read_to_string(&mut body).unwrap();
+        response.send(format!("got {}", body));
+    }
+
+    fn check(method: &Method, target: &str, headers: &Headers) -> StatusCode {
+        if *method == Method::Put && target == "/private" && !headers.has::<::header::Authorization<String>>() {
+            StatusCode::Unauthorized
+        } else {
+            StatusCode::Continue
+        }
+    }
+
+    let server = Server {
+        max_body_length: Some(10),
+        continue_policy: Some(Box::new(check)),
+        ..Server::new(upload as fn(Context, Response))
+    }.build().0;
+
+    let serve = |request: &str| {
+        let stream = server.serve_connection(::mock::MockStream::new(request), "127.0.0.1:1337".parse().unwrap());
+        String::from_utf8(stream.output).unwrap()
+    };
+
+    //The body would have been sent after the 100 response.
+    let output = serve("PUT /private HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\nhello");
+    assert_eq!(output, "HTTP/1.1 401 Unauthorized\r\n\r\n");
+
+    let output = serve("PUT /public HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 11\r\n\r\nhello world");
+    assert_eq!(output, "HTTP/1.1 413 Payload Too Large\r\n\r\n");
+    assert!(!HANDLED.load(Ordering::SeqCst));
+
+    let output = serve("PUT /public HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\nhello");
+    assert!(output.starts_with("HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n"), "{}", output);
+    assert!(output.ends_with("\r\n\r\ngot hello"), "{}", output);
+    assert!(HANDLED.load(Ordering::SeqCst));
+}
+
+#[test]
+fn reload_handlers() {
+    use std::sync::Mutex;
+    use std::sync::mpsc::{self, Sender, Receiver};
+    use std::thread;
+    use Handler;
+
+    //Waits for a signal before responding, if it has a gate.
+    struct Version {
+        name: &'static str,
+        gate: Option<(Mutex<Sender<()>>, Mutex<Receiver<()>>)>
+    }
+
+    impl Handler for Version {
+        fn handle_request(&self, _context: Context, response: Response) {
+            if let Some((ref started, ref release)) = self.gate {
+                started.lock().unwrap().send(()).unwrap();
+                release.lock().unwrap().recv().unwrap();
+            }
+            response.send(self.name);
+        }
+    }
+
+    let (started_tx, started) = mpsc::channel();
+    let (release, release_rx) = mpsc::channel();
+    let server = Arc::new(Server::new(Version {
+        name: "old",
+        gate: Some((Mutex::new(started_tx), Mutex::new(release_rx)))
+    }).build().0);
+    let handle = server.server_handle();
+
+    let in_flight = {
+        let server = server.clone();
+        thread::spawn(move || send_request(&server, "GET / HTTP/1.1\r\n\r\n"))
+    };
+    started.recv().unwrap();
+
+    handle.reload(Version {
+        name: "new",
+        gate: None
+    });
+    let response = send_request(&server, "GET / HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nnew"), "{}", response);
+
+    //The request that was already in progress finishes with the old one.
+    release.send(()).unwrap();
+    let response = in_flight.join().unwrap();
+    assert!(response.ends_with("\r\n\r\nold"), "{}", response);
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nnew"), "{}", response);
+}
+
+#[test]
+fn close_when_asked() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    fn hello(_context: Context, mut response: Response) {
+        response.headers_mut().set(Connection(vec![ConnectionOption::KeepAlive]));
+        response.send("hello");
+    }
+
+    fn send(address: SocketAddr, requests: &str) -> String {
+        let mut client = TcpStream::connect(address).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.write_all(requests.as_bytes()).unwrap();
+
+        let mut response = vec![];
+        client.read_to_end(&mut response).unwrap();
+        String::from_utf8(response).unwrap()
+    }
+
+    let mut listening = Server {
+        host: "127.0.0.1:0".parse::<SocketAddr>().unwrap().into(),
+        threads: Some(2),
+        keep_alive: Some(KeepAlive {
+            timeout: Duration::from_secs(5),
+            free_threads: 0
+        }),
+        ..Server::new(hello as fn(Context, Response))
+    }.run().unwrap();
+
+    //The second request is never read.
+    let response = send(listening.socket, "GET / HTTP/1.1\r\nConnection: close\r\n\r\nGET / HTTP/1.1\r\n\r\n");
+    assert_eq!(response.matches("HTTP/1.1 200 OK\r\n").count(), 1, "{}", response);
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+
+    let response = send(listening.socket, "GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert_eq!(response.matches("HTTP/1.1 200 OK\r\n").count(), 2, "{}", response);
+    assert_eq!(response.matches("Connection: close\r\n").count(), 1, "{}", response);
+
+    let response = send(listening.socket, "GET / HTTP/1.0\r\n\r\nGET / HTTP/1.0\r\n\r\n");
+    assert_eq!(response.matches("HTTP/1.0 200 OK\r\n").count(), 1, "{}", response);
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+
+    //Let the server threads run in the background.
+    listening.close().unwrap();
+}
+
+#[test]
+fn inspect_borrowed_context() {
+    use filter::{ContextFilter, ContextAction, FilterContext};
+
+    struct RequireToken;
+
+    impl ContextFilter for RequireToken {
+        fn modify(&self, _ctx: FilterContext, context: &mut Context) -> ContextAction {
+            let path = context.path().map(|path| path.as_bytes() == b"/secret");
+            let token = context.header_raw("X-Token").map(|values| values == &[b"abc".to_vec()][..]);
+            let query = context.query_raw("token").and_then(|token| token.as_utf8().map(|token| token == "abc"));
+
+            match (path, token, query) {
+                (Some(true), Some(true), _) | (Some(true), _, Some(true)) | (Some(false), _, _) => ContextAction::next(),
+                _ => ContextAction::abort(StatusCode::Forbidden)
+            }
+        }
+    }
+
+    fn hello(_context: Context, response: Response) {
+        response.send("hello");
+    }
+
+    let server = Server {
+        context_filters: vec![Box::new(RequireToken)],
+        ..Server::new(hello as fn(Context, Response))
+    }.build().0;
+
+    let response = send_request(&server, "GET /secret HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /secret HTTP/1.1\r\nX-Token: abc\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+
+    let response = send_request(&server, "GET /secret?token=abc HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+
+    let response = send_request(&server, "GET /public HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+}
+
+#[test]
+fn request_target_forms() {
+    fn describe(context: Context, response: Response) {
+        let path = context.path().map(|path| path.as_utf8_lossy().into_owned());
+        let fragment = context.fragment().map(|fragment| fragment.as_utf8_lossy().into_owned());
+        response.send(format!("{:?} {} {:?} {:?}", context.target_form(), context.raw_target(), path, fragment));
+    }
+
+    //Asterisk-form requests are only handled by the fallback handler.
+    let server = Server {
+        fallback_handler: Some(describe as fn(Context, Response)),
+        ..Server::new(describe as fn(Context, Response))
+    }.build().0;
+
+    let response = send_request(&server, "GET /a%20b?c=d#e%21 HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nOrigin /a%20b?c=d#e%21 Some(\"/a b\") Some(\"e!\")"), "{}", response);
+
+    let response = send_request(&server, "GET http://example.com/a%20b?c=d HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nAbsolute http://example.com/a%20b?c=d Some(\"/a b\") None"), "{}", response);
+
+    let response = send_request(&server, "OPTIONS * HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nAsterisk * None None"), "{}", response);
+
+    let response = send_request(&server, "CONNECT example.com:443 HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nAuthority example.com:443 None None"), "{}", response);
+
+    let response = send_request(&server, "GET example.com:443 HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+
+    let response = send_request(&server, "CONNECT example.com HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+}
+
+#[test]
+fn target_form_hosts() {
+    fn show_host(context: Context, response: Response) {
+        let host = context.headers.get::<::header::Host>().map(|host| format!("{}:{:?}", host.hostname, host.port));
+        response.send(format!("{:?} {:?} {:?}", context.target_form(), host, context.path().map(|path| path.as_utf8_lossy().into_owned())));
+    }
+
+    let mut router = ::TreeRouter::new();
+    router.insert(Method::Get, "/a", show_host as fn(Context, Response));
+    let server = Server {
+        fallback_handler: Some(show_host as fn(Context, Response)),
+        ..Server::new(router)
+    }.build().0;
+
+    let response = send_request(&server, "GET /a HTTP/1.1\r\nHost: origin.example\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nOrigin Some(\"origin.example:None\") Some(\"/a\")"), "{}", response);
+
+    //The path of an absolute-form target is routed as usual.
+    let response = send_request(&server, "GET http://absolute.example:8080/a HTTP/1.1\r\nHost: origin.example\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nAbsolute Some(\"absolute.example:Some(8080)\") Some(\"/a\")"), "{}", response);
+
+    let response = send_request(&server, "CONNECT [::1]:443 HTTP/1.1\r\nHost: origin.example\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nAuthority Some(\"[::1]:Some(443)\") None"), "{}", response);
+}
+
+#[test]
+fn collapse_identical_requests() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+    use handler::{Handler, CollapsingHandler};
+
+    const REQUESTS: usize = 4;
+
+    let arrived = Arc::new(AtomicUsize::new(0));
+    let computed = Arc::new(AtomicUsize::new(0));
+
+    let collapsing = {
+        let arrived = arrived.clone();
+        let computed = computed.clone();
+        CollapsingHandler::new(move |_context: Context, mut response: Response| {
+            computed.fetch_add(1, Ordering::SeqCst);
+
+            //Give the other requests a moment to start waiting.
+            while arrived.load(Ordering::SeqCst) < REQUESTS {
+                thread::sleep(Duration::from_millis(1));
+            }
+            thread::sleep(Duration::from_millis(50));
+
+            response.headers_mut().set_raw("X-Report", vec![b"yes".to_vec()]);
+            response.send("an expensive report");
+        })
+    };
+
+    let handler = {
+        let arrived = arrived.clone();
+        move |context: Context, response: Response| {
+            arrived.fetch_add(1, Ordering::SeqCst);
+            collapsing.handle_request(context, response);
+        }
+    };
+
+    let server = Arc::new(Server::new(handler).build().0);
+
+    let threads: Vec<_> = (0..REQUESTS).map(|_| {
+        let server = server.clone();
+        thread::spawn(move || send_request(&server, "GET /report?year=2016 HTTP/1.1\r\n\r\n"))
+    }).collect();
+
+    for thread in threads {
+        let response = thread.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains("\r\nX-Report: yes\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\nan expensive report"), "{}", response);
+    }
+
+    assert_eq!(computed.load(Ordering::SeqCst), 1);
+
+    //The flight has landed, so the next request is handled again.
+    arrived.store(0, Ordering::SeqCst);
+    arrived.fetch_add(REQUESTS - 1, Ordering::SeqCst);
+    send_request(&server, "GET /report?year=2016 HTTP/1.1\r\n\r\n");
+    assert_eq!(computed.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn cache_responses() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+    use handler::CacheHandler;
+
+    let computed = Arc::new(AtomicUsize::new(0));
+
+    let mut cache = {
+        let computed = computed.clone();
+        CacheHandler::new(move |context: Context, mut response: Response| {
+            let count = computed.fetch_add(1, Ordering::SeqCst) + 1;
+            if context.path().map_or(false, |path| path == "/private") {
+                response.headers_mut().set_raw("Cache-Control", vec![b"no-store".to_vec()]);
+            } else if context.path().map_or(false, |path| path == "/cookie") {
+                response.headers_mut().set_raw("Cache-Control", vec![b"max-age=60, Private=\"Set-Cookie\"".to_vec()]);
+            }
+            response.send(format!("response {}", count));
+        }, Duration::from_millis(200))
+    };
+    cache.max_entries(2);
+
+    let server = Server::new(cache).build().0;
+
+    let response = send_request(&server, "GET /a HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nresponse 1"), "{}", response);
+    assert!(!response.contains("\r\nAge: "), "{}", response);
+
+    let response = send_request(&server, "GET /a HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nresponse 1"), "{}", response);
+    assert!(response.contains("\r\nAge: 0\r\n"), "{}", response);
+
+    //Different query, different entry.
+    let response = send_request(&server, "GET /a?b HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nresponse 2"), "{}", response);
+
+    //Not stored, since it's not allowed.
+    send_request(&server, "GET /private HTTP/1.1\r\n\r\n");
+    let response = send_request(&server, "GET /private HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nresponse 4"), "{}", response);
+
+    //Not cached.
+    let response = send_request(&server, "POST /a HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nresponse 5"), "{}", response);
+
+    //Evicts `/a?b`, since `/a` is used more recently.
+    send_request(&server, "GET /a HTTP/1.1\r\n\r\n");
+    send_request(&server, "GET /c HTTP/1.1\r\n\r\n");
+    let response = send_request(&server, "GET /a HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nresponse 1"), "{}", response);
+    let response = send_request(&server, "GET /a?b HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nresponse 7"), "{}", response);
+
+    thread::sleep(Duration::from_millis(250));
+    let response = send_request(&server, "GET /a HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nresponse 8"), "{}", response);
+
+    //A qualified `private` prevents it too.
+    send_request(&server, "GET /cookie HTTP/1.1\r\n\r\n");
+    let response = send_request(&server, "GET /cookie HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nresponse 10"), "{}", response);
+}
+
+#[test]
+fn cache_private_responses() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use handler::CacheHandler;
+
+    let computed = Arc::new(AtomicUsize::new(0));
+
+    let cache = {
+        let computed = computed.clone();
+        CacheHandler::new(move |context: Context, mut response: Response| {
+            let count = computed.fetch_add(1, Ordering::SeqCst) + 1;
+            if context.path().map_or(false, |path| path == "/public") {
+                response.headers_mut().set_raw("Cache-Control", vec![b"public".to_vec()]);
+            } else if context.path().map_or(false, |path| path == "/language") {
+                response.headers_mut().set_raw("Vary", vec![b"Accept-Language".to_vec()]);
+            } else if context.path().map_or(false, |path| path == "/anything") {
+                response.headers_mut().set_raw("Vary", vec![b"*".to_vec()]);
+            }
+            response.send(format!("response {}", count));
+        }, Duration::from_secs(60))
+    };
+
+    let server = Server::new(cache).build().0;
+
+    //Neither stored nor looked up with cookies.
+    send_request(&server, "GET /a HTTP/1.1\r\nCookie: user=a\r\n\r\n");
+    let response = send_request(&server, "GET /a HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nresponse 2"), "{}", response);
+    let response = send_request(&server, "GET /a HTTP/1.1\r\nCookie: user=a\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nresponse 3"), "{}", response);
+
+    //Only stored with authorization if it's public.
+    send_request(&server, "GET /b HTTP/1.1\r\nAuthorization: Basic YTpi\r\n\r\n");
+    let response = send_request(&server, "GET /b HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nresponse 5"), "{}", response);
+    let response = send_request(&server, "GET /b HTTP/1.1\r\nAuthorization: Basic YTpi\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nresponse 6"), "{}", response);
+    send_request(&server, "GET /public HTTP/1.1\r\nAuthorization: Basic YTpi\r\n\r\n");
+    let response = send_request(&server, "GET /public HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nresponse 7"), "{}", response);
+
+    //The response's own `Vary` is respected.
+    send_request(&server, "GET /language HTTP/1.1\r\nAccept-Language: sv\r\n\r\n");
+    let response = send_request(&server, "GET /language HTTP/1.1\r\nAccept-Language: sv\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nresponse 8"), "{}", response);
+    let response = send_request(&server, "GET /language HTTP/1.1\r\nAccept-Language: en\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nresponse 9"), "{}", response);
+    let response = send_request(&server, "GET /language HTTP/1.1\r\nAccept-Language: en\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nresponse 9"), "{}", response);
+
+    send_request(&server, "GET /anything HTTP/1.1\r\n\r\n");
+    let response = send_request(&server, "GET /anything HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nresponse 11"), "{}", response);
+}
+
+#[test]
+fn split_traffic() {
+    use handler::{SplitHandler, Variant};
+
+    fn variant(_context: Context, response: Response) {
+        let Variant(index) = *response.filter_storage().get::<Variant>().expect("no variant");
+        response.send(index.to_string());
+    }
+
+    let server = Server::new(SplitHandler::new(vec![
+        (3, variant as fn(Context, Response)),
+        (1, variant as fn(Context, Response)),
+        (0, variant as fn(Context, Response)),
+    ])).build().0;
+
+    let mut counts = [0; 3];
+    for _ in 0..1000 {
+        let response = send_request(&server, "GET / HTTP/1.1\r\n\r\n");
+        let index: usize = response.rsplit("\r\n").next().unwrap().parse().unwrap();
+        counts[index] += 1;
+    }
+
+    assert!(counts[0] > 650 && counts[0] < 850, "{:?}", counts);
+    assert_eq!(counts[2], 0);
+
+    let mut split = SplitHandler::new(vec![
+        (1, variant as fn(Context, Response)),
+        (1, variant as fn(Context, Response)),
+    ]);
+    split.stick_to_header("X-User");
+    let server = Server::new(split).build().0;
+
+    for user in 0..20 {
+        let request = format!("GET / HTTP/1.1\r\nX-User: {}\r\n\r\n", user);
+        let first = send_request(&server, &request);
+        for _ in 0..5 {
+            assert_eq!(send_request(&server, &request), first);
+        }
+    }
+}
+
+#[test]
+fn request_deadline() {
+    fn remaining(context: Context, response: Response) {
+        match context.time_remaining() {
+            Some(remaining) => response.send(format!("{}", remaining <= Duration::from_secs(10) && remaining > Duration::from_secs(9))),
+            None => response.send("none")
+        }
+    }
+
+    let server = Server::new(remaining as fn(Context, Response)).build().0;
+    let response = send_request(&server, "GET / HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nnone"), "{}", response);
+
+    let server = Server {
+        request_timeout: Some(Duration::from_secs(10)),
+        ..Server::new(remaining as fn(Context, Response))
+    }.build().0;
+    let response = send_request(&server, "GET / HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\ntrue"), "{}", response);
+}
+
+#[test]
+fn serve_embedded_files() {
+    use std::time::{Duration, UNIX_EPOCH};
+    use file::{EmbeddedFiles, EmbeddedFile};
+    use TreeRouter;
+    use Method::Get;
+
+    let file = EmbeddedFile::new(&b"body {}"[..], content_type!(Text / Css)).last_modified(UNIX_EPOCH + Duration::from_secs(784111777));
+    let etag = file.etag().tag().to_owned();
+
+    let mut files = EmbeddedFiles::new();
+    files.insert("/css/style.css", file);
+    let mut router = TreeRouter::new();
+    router.insert(Get, "/assets/*path", files);
+    let server = Server::new(router).build().0;
+
+    let response = send_request(&server, "GET /assets/css/style.css HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains(&format!("ETag: \"{}\"\r\n", etag)), "{}", response);
+    assert!(response.contains("Last-Modified: Sun, 06 Nov 1994 08:49:37 GMT\r\n"), "{}", response);
+    assert!(response.contains("Content-Type: text/css\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nbody {}"), "{}", response);
+
+    let request = format!("GET /assets/css/style.css HTTP/1.1\r\nIf-None-Match: \"other\", W/\"{}\"\r\n\r\n", etag);
+    let response = send_request(&server, &request);
+    assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /assets/css/style.css HTTP/1.1\r\nIf-None-Match: \"other\"\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /assets/css/style.css HTTP/1.1\r\nIf-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /assets/css/style.css HTTP/1.1\r\nIf-Modified-Since: Sun, 06 Nov 1994 08:49:36 GMT\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /assets/missing.css HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", response);
+}
+
+#[test]
+fn serve_single_file() {
+    use std::fs;
+    use std::time::Duration;
+    use file::{SingleFile, EmbeddedFile};
+    use TreeRouter;
+    use Method::Get;
+
+    let robots = SingleFile::embedded(EmbeddedFile::new(&b"User-agent: *"[..], content_type!(Text / Plain)));
+    let manifest = SingleFile::new("Cargo.toml").content_type(content_type!(Text / Plain)).max_age(Duration::from_secs(60));
+
+    let mut router = TreeRouter::new();
+    router.insert(Get, "/robots.txt", robots);
+    router.insert(Get, "/manifest", manifest);
+    router.insert(Get, "/missing", SingleFile::new("missing.ico"));
+    let server = Server::new(router).build().0;
+
+    let response = send_request(&server, "GET /robots.txt HTTP/1.1\r\n\r\n");
+    assert!(response.contains("Content-Type: text/plain\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nUser-agent: *"), "{}", response);
+
+    let response = send_request(&server, "GET /manifest HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains("Cache-Control: public, max-age=60\r\n"), "{}", response);
+    assert!(response.ends_with(&fs::read_to_string("Cargo.toml").unwrap()), "{}", response);
+
+    let etag = response.lines().find(|line| line.starts_with("ETag: ")).unwrap()[6..].to_owned();
+    let request = format!("GET /manifest HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n", etag);
+    let response = send_request(&server, &request);
+    assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /missing HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", response);
+}
+
+#[test]
+fn serve_immutable_file() {
+    use std::time::Duration;
+    use file::{SingleFile, EmbeddedFile};
+    use TreeRouter;
+    use Method::Get;
+
+    let app = SingleFile::embedded(EmbeddedFile::new(&b"app()"[..], content_type!(Application / Javascript)))
+        .max_age(Duration::from_secs(31536000))
+        .immutable(true);
+    let plain = SingleFile::embedded(EmbeddedFile::new(&b"app()"[..], content_type!(Application / Javascript)))
+        .immutable(true);
+
+    let mut router = TreeRouter::new();
+    router.insert(Get, "/app.3f9a1c.js", app);
+    router.insert(Get, "/plain.js", plain);
+    let server = Server::new(router).build().0;
+
+    let response = send_request(&server, "GET /app.3f9a1c.js HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains("Cache-Control: public, max-age=31536000, immutable\r\n"), "{}", response);
+
+    let etag = response.lines().find(|line| line.starts_with("ETag: ")).unwrap()[6..].to_owned();
+    let request = format!("GET /app.3f9a1c.js HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n", etag);
+    let response = send_request(&server, &request);
+    assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"), "{}", response);
+    assert!(response.contains("Cache-Control: public, max-age=31536000, immutable\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /plain.js HTTP/1.1\r\n\r\n");
+    assert!(!response.contains("Cache-Control"), "{}", response);
+}
+
+#[test]
+fn list_static_directories() {
+    use std::{env, fs};
+    use file::StaticFiles;
+    use TreeRouter;
+    use Method::Get;
+
+    let root = env::temp_dir().join("rustful_list_static_directories");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("sub dir")).unwrap();
+    fs::create_dir_all(root.join("site")).unwrap();
+    fs::write(root.join("a <b>#1.txt"), "hello").unwrap();
+    fs::write(root.join("sub dir").join("inner.txt"), "inner").unwrap();
+    fs::write(root.join("site").join("index.html"), "index").unwrap();
+
+    let mut files = StaticFiles::new(root.clone());
+    let mut router = TreeRouter::new();
+    router.insert(Get, "/hidden", files.clone());
+    router.insert(Get, "/hidden/*path", files.clone());
+    files.listing = true;
+    router.insert(Get, "/files", files.clone());
+    router.insert(Get, "/files/*path", files);
+    let server = Server::new(router).build().0;
+
+    let response = send_request(&server, "GET /hidden/ HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /files/ HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains("Content-Type: text/html; charset=utf-8\r\n"), "{}", response);
+    assert!(response.contains("<a href=\"a%20%3Cb%3E%231.txt\">a &lt;b&gt;#1.txt</a></td><td>5</td>"), "{}", response);
+    assert!(response.contains("<a href=\"sub%20dir/\">sub dir/</a>"), "{}", response);
+    assert!(!response.contains("../"), "{}", response);
+
+    let response = send_request(&server, "GET /files/sub%20dir/ HTTP/1.1\r\nAccept: application/json\r\n\r\n");
+    assert!(response.contains("Content-Type: application/json; charset=utf-8\r\n"), "{}", response);
+    assert!(response.contains("[{\"name\":\"inner.txt\",\"type\":\"file\",\"size\":5,\"modified\":"), "{}", response);
+
+    let response = send_request(&server, "GET /files/sub%20dir?a=b HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 301 Moved Permanently\r\n"), "{}", response);
+    assert!(response.contains("Location: /files/sub%20dir/?a=b\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /files/site/ HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nindex"), "{}", response);
+
+    let response = send_request(&server, "GET /files/sub%20dir/inner.txt HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\ninner"), "{}", response);
+
+    let response = send_request(&server, "GET /files/sub%20dir/%2E%2E/%2E%2E/ HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{}", response);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn static_cache_policies() {
+    use std::{env, fs};
+    use std::path::Path;
+    use file::{StaticFiles, CachePolicy, CacheRules};
+    use TreeRouter;
+    use Method::Get;
+
+    let root = env::temp_dir().join("rustful_static_cache_policies");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("assets")).unwrap();
+    fs::create_dir_all(root.join("docs")).unwrap();
+    fs::write(root.join("assets").join("app.1234.js"), "app").unwrap();
+    fs::write(root.join("docs").join("index.html"), "docs").unwrap();
+    fs::write(root.join("robots.txt"), "robots").unwrap();
+
+    let mut files = StaticFiles::new(root.clone());
+    files.cache = CachePolicy::Rules(CacheRules::new()
+        .prefix("assets", "public, max-age=31536000, immutable")
+        .extension("HTML", "no-cache"));
+    let mut router = TreeRouter::new();
+    router.insert(Get, "/rules/*path", files.clone());
+    files.cache = CachePolicy::function(|path: &Path| if path == Path::new("robots.txt") {
+        Some("max-age=60".into())
+    } else {
+        None
+    });
+    router.insert(Get, "/function/*path", files);
+    let server = Server::new(router).build().0;
+
+    let response = send_request(&server, "GET /rules/assets/app.1234.js HTTP/1.1\r\n\r\n");
+    assert!(response.contains("Cache-Control: public, max-age=31536000, immutable\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /rules/docs/ HTTP/1.1\r\n\r\n");
+    assert!(response.contains("Cache-Control: no-cache\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\ndocs"), "{}", response);
+
+    let response = send_request(&server, "GET /rules/docs HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 301 Moved Permanently\r\n"), "{}", response);
+    assert!(!response.contains("Cache-Control"), "{}", response);
+
+    let response = send_request(&server, "GET /rules/robots.txt HTTP/1.1\r\n\r\n");
+    assert!(!response.contains("Cache-Control"), "{}", response);
+
+    let response = send_request(&server, "GET /function/robots.txt HTTP/1.1\r\n\r\n");
+    assert!(response.contains("Cache-Control: max-age=60\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /function/docs/index.html HTTP/1.1\r\n\r\n");
+    assert!(!response.contains("Cache-Control"), "{}", response);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn layered_static_files() {
+    use std::{env, fs};
+    use file::StaticFiles;
+    use TreeRouter;
+    use Method::Get;
+
+    let root = env::temp_dir().join("rustful_layered_static_files");
+    let _ = fs::remove_dir_all(&root);
+    let custom = root.join("custom");
+    let default = root.join("default");
+    fs::create_dir_all(custom.join("docs")).unwrap();
+    fs::create_dir_all(default.join("docs")).unwrap();
+    fs::write(custom.join("style.css"), "custom style").unwrap();
+    fs::write(default.join("style.css"), "default style").unwrap();
+    fs::write(default.join("logo.svg"), "default logo").unwrap();
+    fs::write(default.join("docs").join("index.html"), "default docs").unwrap();
+
+    let mut router = TreeRouter::new();
+    router.insert(Get, "/*path", StaticFiles::new(custom).fallback(default));
+    let server = Server::new(router).build().0;
+
+    let response = send_request(&server, "GET /style.css HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\ncustom style"), "{}", response);
+
+    let response = send_request(&server, "GET /logo.svg HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\ndefault logo"), "{}", response);
+
+    let response = send_request(&server, "GET /docs/ HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\ndefault docs"), "{}", response);
+
+    let response = send_request(&server, "GET /missing.txt HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /docs/%2E%2E/%2E%2E/ HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /docs/%252E%252E/%252E%252E/ HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{}", response);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn head_ranged_files() {
+    use std::io::Cursor;
+
+    fn seekable(context: Context, response: Response) {
+        response.send_seekable(Cursor::new(b"hello, world".to_vec()), 12, &context).unwrap();
+    }
+
+    fn file(_context: Context, response: Response) {
+        response.send_file("Cargo.toml").unwrap_or_else(|_| panic!("could not send Cargo.toml"));
+    }
+
+    let server = Server::new(seekable as fn(Context, Response)).build().0;
+
+    let response = send_request(&server, "HEAD / HTTP/1.1\r\nRange: bytes=0-4\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains("Accept-Ranges: bytes\r\n"), "{}", response);
+    assert!(response.contains("Content-Length: 12\r\n"), "{}", response);
+    assert!(!response.contains("Content-Range"), "{}", response);
+    assert!(response.ends_with("\r\n\r\n"), "{}", response);
+
+    let server = Server::new(file as fn(Context, Response)).build().0;
+    let length = ::std::fs::metadata("Cargo.toml").unwrap().len();
+
+    let response = send_request(&server, "HEAD /Cargo.toml HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains(&format!("Content-Length: {}\r\n", length)), "{}", response);
+    assert!(response.ends_with("\r\n\r\n"), "{}", response);
+
+    //The response filters finalize the head, just like for `GET`.
+    fn hooked(_status: StatusCode, headers: &mut ::header::Headers) {
+        headers.set_raw("X-Hooked", vec![b"yes".to_vec()]);
+    }
+
+    fn head(response: &str) -> Vec<&str> {
+        let head = response.split("\r\n\r\n").next().unwrap();
+        let mut lines: Vec<_> = head.lines().filter(|line| !line.starts_with("Date: ")).collect();
+        lines.sort();
+        lines
+    }
+
+    let server = Server {
+        response_filters: vec![Box::new(::filter::StatusHooks::new().on(::StatusClass::Success, hooked))],
+        ..Server::new(file as fn(Context, Response))
+    }.build().0;
+
+    let get = send_request(&server, "GET /Cargo.toml HTTP/1.1\r\n\r\n");
+    let response = send_request(&server, "HEAD /Cargo.toml HTTP/1.1\r\n\r\n");
+    assert!(response.contains("X-Hooked: yes\r\n"), "{}", response);
+    assert_eq!(head(&response), head(&get));
+}
+
+#[test]
+fn close_after_unread_body() {
+    fn ignore_body(_context: Context, response: Response) {
+        response.send("ignored");
+    }
+
+    fn read_body(mut context: Context, response: Response) {
+        use std::io::Read;
+
+        let mut body = String::new();
+        context.body.read_to_string(&mut body).unwrap();
+        response.send(body);
+    }
+
+    let server = Server::new(ignore_body as fn(Context, Response)).build().0;
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET / HTTP/1.1\r\n\r\n");
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n");
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n");
+    assert!(!response.contains("Connection: close\r\n"), "{}", response);
+
+    let server = Server::new(read_body as fn(Context, Response)).build().0;
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET / HTTP/1.1\r\n\r\n");
+    assert!(!response.contains("Connection: close\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\nGET / HTTP/1.1\r\n\r\n");
+    assert!(!response.contains("Connection: close\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+}
+
+#[test]
+fn drain_unread_body() {
+    use std::io::Read;
+    use hyper::buffer::BufReader;
+    use hyper::net::NetworkStream;
+
+    //Returns the response and what's left of the request stream.
+    fn send<R: Router>(server: &ServerInstance<R>, request: &str) -> (String, String) {
+        let mut stream = ::mock::MockStream::new(request);
+        let mut output = vec![];
+        let mut rest = String::new();
+        {
+            let addr = stream.peer_addr().unwrap();
+            let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+            {
+                let request = hyper::server::request::Request::new(&mut reader, addr).unwrap();
+                let mut headers = hyper::header::Headers::new();
+                let mut response = hyper::server::response::Response::new(&mut output, &mut headers);
+                response.version = request.version;
+                server.handle(request, response);
+            }
+            reader.read_to_string(&mut rest).unwrap();
+        }
+
+        (String::from_utf8(output).unwrap(), rest)
+    }
+
+    fn ignore_body(context: Context, mut response: Response) {
+        if context.header_raw("X-Close").is_some() {
+            response.headers_mut().set(Connection(vec![ConnectionOption::Close]));
+        }
+        response.send("ignored");
+    }
+
+    fn drain_body(mut context: Context, response: Response) {
+        context.drain_body().unwrap();
+        response.send(format!("drained {}", context.bytes_read()));
+    }
+
+    let server = Server {
+        auto_drain_limit: Some(5),
+        ..Server::new(ignore_body as fn(Context, Response))
+    }.build().0;
+
+    let (response, rest) = send(&server, "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET / HTTP/1.1\r\n\r\n");
+    assert!(!response.contains("Connection: close\r\n"), "{}", response);
+    assert_eq!(rest, "GET / HTTP/1.1\r\n\r\n");
+
+    let (response, rest) = send(&server, "POST / HTTP/1.1\r\nContent-Length: 6\r\n\r\nhello!GET / HTTP/1.1\r\n\r\n");
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+    assert_eq!(rest, "hello!GET / HTTP/1.1\r\n\r\n");
+
+    let (response, _) = send(&server, "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\na\r\n0\r\n\r\n");
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+
+    let (response, rest) = send(&server, "POST / HTTP/1.1\r\nContent-Length: 5\r\nX-Close: 1\r\n\r\nhelloGET / HTTP/1.1\r\n\r\n");
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+    assert_eq!(rest, "helloGET / HTTP/1.1\r\n\r\n");
+
+    let server = Server::new(drain_body as fn(Context, Response)).build().0;
+
+    let (response, rest) = send(&server, "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\nGET / HTTP/1.1\r\n\r\n");
+    assert!(!response.contains("Connection: close\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\ndrained 5"), "{}", response);
+    assert_eq!(rest, "GET / HTTP/1.1\r\n\r\n");
+}
+
+#[test]
+fn modified_route_variables() {
+    use filter::RouteFilters;
+    use TreeRouter;
+    use Method::Get;
+
+    struct LowercaseSlug;
+
+    impl ContextFilter for LowercaseSlug {
+        fn modify(&self, _ctx: FilterContext, context: &mut Context) -> ContextAction {
+            let slug = context.variables.get("slug").map(|slug| slug.to_lowercase()).unwrap_or_default();
+            let previous = context.set_variable("slug", slug);
+            assert!(previous.is_some());
+            ContextAction::next()
+        }
+    }
+
+    fn post(context: Context, response: Response) {
+        response.send(format!("post {}", context.variables.get("slug").unwrap_or_default()));
+    }
+
+    let mut router = TreeRouter::new();
+    router.insert(Get, "/posts/:slug", Box::new(
+        RouteFilters::new(post as fn(Context, Response)).context_filter(LowercaseSlug)
+    ) as Box<Handler>);
+    let server = Server::new(router).build().0;
+
+    let response = send_request(&server, "GET /posts/Hello-World HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\npost hello-world"), "{}", response);
+}
+
+#[test]
+#[cfg(feature = "rustc_json_body")]
+fn extracting_handlers() {
+    use extract::{Extracting, Path, Query, Form, Json};
+    use TreeRouter;
+    use Method::{Get, Post};
+
+    use rustc_serialize::{Decodable, Decoder};
+
+    struct NewUser {
+        name: String,
+        age: u8,
+    }
+
+    impl Decodable for NewUser {
+        fn decode<D: Decoder>(d: &mut D) -> Result<NewUser, D::Error> {
+            d.read_struct("NewUser", 2, |d| Ok(NewUser {
+                name: try!(d.read_struct_field("name", 0, Decodable::decode)),
+                age: try!(d.read_struct_field("age", 1, Decodable::decode)),
+            }))
+        }
+    }
+
+    struct Search {
+        q: String,
+        page: Option<u32>,
+        tags: Vec<String>,
+    }
+
+    impl Decodable for Search {
+        fn decode<D: Decoder>(d: &mut D) -> Result<Search, D::Error> {
+            d.read_struct("Search", 3, |d| Ok(Search {
+                q: try!(d.read_struct_field("q", 0, Decodable::decode)),
+                page: try!(d.read_struct_field("page", 1, Decodable::decode)),
+                tags: try!(d.read_struct_field("tags", 2, Decodable::decode)),
+            }))
+        }
+    }
+
+    fn create(Path(group): Path<u64>, Json(user): Json<NewUser>) -> (StatusCode, String) {
+        (StatusCode::Created, format!("{} ({}) in {}", user.name, user.age, group))
+    }
+
+    fn search(Query(search): Query<Search>) -> String {
+        format!("{} {:?} {:?}", search.q, search.page, search.tags)
+    }
+
+    fn rename(Path(id): Path<u64>, Form(user): Form<NewUser>) -> String {
+        format!("{} is {}", id, user.name)
+    }
+
+    let mut router = TreeRouter::new();
+    router.insert(Post, "/groups/:id/users", Box::new(Extracting::new(create)) as Box<Handler>);
+    router.insert(Get, "/search", Box::new(Extracting::new(search)) as Box<Handler>);
+    router.insert(Post, "/users/:id", Box::new(Extracting::new(rename)) as Box<Handler>);
+    let server = Server::new(router).build().0;
+
+    let json = "{\"name\": \"Alice\", \"age\": 30}";
+    let response = send_request(&server, &format!("POST /groups/7/users HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}", json.len(), json));
+    assert!(response.starts_with("HTTP/1.1 201 Created\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nAlice (30) in 7"), "{}", response);
+
+    //The path is checked before the body is read.
+    let response = send_request(&server, &format!("POST /groups/seven/users HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}", json.len(), json));
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", response);
+
+    let response = send_request(&server, &format!("POST /groups/7/users HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}", json.len(), json));
+    assert!(response.starts_with("HTTP/1.1 415 Unsupported Media Type\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST /groups/7/users HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 7\r\n\r\n{\"name\"");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST /groups/7/users HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 17\r\n\r\n{\"name\": \"Alice\"}");
+    assert!(response.starts_with("HTTP/1.1 422 Unprocessable Entity\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /search?q=rust&tags[]=web&page=2 HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nrust Some(2) [\"web\"]"), "{}", response);
+
+    let response = send_request(&server, "GET /search?q=rust&tag=a HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /search?q=rust&tags=a&tags=b HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nrust None [\"a\", \"b\"]"), "{}", response);
+
+    let response = send_request(&server, "POST /users/3 HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: 17\r\n\r\nname=Bob&age=abc");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST /users/3 HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: 15\r\n\r\nname=Bob&age=40");
+    assert!(response.ends_with("\r\n\r\n3 is Bob"), "{}", response);
+}
+
+#[test]
+fn route_filters() {
+    use filter::{RouteFilters, ResponseAction};
+    use response::Data;
+    use TreeRouter;
+    use Method::Get;
+
+    //Appends its name to the `X-Filters` header.
+    struct Trace(&'static str);
+
+    impl ContextFilter for Trace {
+        fn modify(&self, _ctx: FilterContext, context: &mut Context) -> ContextAction {
+            if context.header_raw("X-Deny").map_or(false, |deny| deny[0] == self.0.as_bytes()) {
+                return ContextAction::abort(StatusCode::Forbidden);
+            }
+            ContextAction::next()
+        }
+    }
+
+    impl ResponseFilter for Trace {
+        fn begin(&self, _ctx: FilterContext, status: StatusCode, headers: &mut Headers) -> (StatusCode, ResponseAction) {
+            let mut trace = headers.get_raw("X-Filters").map_or_else(Vec::new, |values| values[0].clone());
+            trace.extend_from_slice(self.0.as_bytes());
+            headers.set_raw("X-Filters", vec![trace]);
+            (status, ResponseAction::next::<Data>(None))
+        }
+
+        fn write<'a>(&'a self, _ctx: FilterContext, content: Option<Data<'a>>) -> ResponseAction {
+            ResponseAction::next(content)
+        }
+
+        fn end(&self, _ctx: FilterContext) -> ResponseAction {
+            ResponseAction::next::<Data>(None)
+        }
+    }
+
+    fn hello(_context: Context, response: Response) {
+        response.send("hello");
+    }
+
+    let mut router = TreeRouter::new();
+    router.insert(Get, "/plain", Box::new(hello as fn(Context, Response)) as Box<Handler>);
+    router.insert(Get, "/filtered", Box::new(
+        RouteFilters::new(hello as fn(Context, Response))
+            .context_filter(Trace("x"))
+            .response_filter(Trace("x"))
+            .response_filter(Trace("y"))
+            .skip_global_response_filter(1)
+    ) as Box<Handler>);
+
+    let server = Server {
+        context_filters: vec![Box::new(Trace("a"))],
+        response_filters: vec![Box::new(Trace("a")), Box::new(Trace("b")), Box::new(Trace("c"))],
+        ..Server::new(router)
+    }.build().0;
+
+    let response = send_request(&server, "GET /plain HTTP/1.1\r\n\r\n");
+    assert!(response.contains("X-Filters: abc\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /filtered HTTP/1.1\r\n\r\n");
+    assert!(response.contains("X-Filters: xyac\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+
+    let response = send_request(&server, "GET /filtered HTTP/1.1\r\nX-Deny: x\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{}", response);
+    assert!(response.contains("X-Filters: xyac\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /plain HTTP/1.1\r\nX-Deny: x\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+}
+
+#[test]
+fn slow_request_bodies() {
+    use std::io::{self, Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+
+    fn echo(mut context: Context, mut response: Response) {
+        let mut body = String::new();
+        match context.body.read_to_string(&mut body) {
+            Ok(_) => response.send(body),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => response.set_status(StatusCode::RequestTimeout),
+            Err(_) => response.set_status(StatusCode::BadRequest)
+        }
+    }
+
+    let mut listening = Server {
+        host: "127.0.0.1:0".parse::<SocketAddr>().unwrap().into(),
+        threads: Some(2),
+        body_read_timeout: Some(Duration::from_millis(200)),
+        ..Server::new(echo as fn(Context, Response))
+    }.run().unwrap();
+
+    let mut client = TcpStream::connect(listening.socket).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    client.write_all(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nab").unwrap();
+
+    let mut response = vec![];
+    client.read_to_end(&mut response).unwrap();
+    let response = String::from_utf8(response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"), "{}", response);
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+
+    let mut client = TcpStream::connect(listening.socket).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    client.write_all(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\n").unwrap();
+    for &byte in b"0123456789" {
+        thread::sleep(Duration::from_millis(50));
+        client.write_all(&[byte]).unwrap();
+    }
+
+    let mut response = vec![];
+    client.read_to_end(&mut response).unwrap();
+    let response = String::from_utf8(response).unwrap();
+    assert!(response.ends_with("\r\n\r\n0123456789"), "{}", response);
+
+    //Let the server threads run in the background.
+    listening.close().unwrap();
+}
+
+#[test]
+fn slow_response_readers() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+
+    static ABORTED: AtomicBool = AtomicBool::new(false);
+
+    fn flood(_context: Context, response: Response) {
+        let chunk = vec![b'x'; 64 * 1024];
+        let mut response = response.into_chunked();
+        for _ in 0..16 * 1024 {
+            if response.try_send(&chunk[..]).is_err() {
+                ABORTED.store(true, Ordering::SeqCst);
+                return;
+            }
+        }
+    }
+
+    let mut listening = Server {
+        host: "127.0.0.1:0".parse::<SocketAddr>().unwrap().into(),
+        threads: Some(2),
+        write_timeout: Some(Duration::from_millis(200)),
+        ..Server::new(flood as fn(Context, Response))
+    }.run().unwrap();
+
+    let mut client = TcpStream::connect(listening.socket).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+
+    //Stop reading until the server gives up.
+    for _ in 0..100 {
+        if ABORTED.load(Ordering::SeqCst) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert!(ABORTED.load(Ordering::SeqCst));
+
+    //The connection is closed after what was sent before the timeout.
+    let mut response = vec![];
+    client.read_to_end(&mut response).unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 200 OK\r\n"));
+    assert!(!response.ends_with(b"\r\n0\r\n\r\n"));
+
+    //Let the server threads run in the background.
+    listening.close().unwrap();
+}
+
+#[test]
+fn route_media_types() {
+    use filter::{RouteFilters, MediaTypes, Negotiated};
+    use mime::{TopLevel, SubLevel};
+    use TreeRouter;
+    use Method::Post;
+
+    fn create(_context: Context, response: Response) {
+        let negotiated = response.filter_storage().get::<Negotiated>().map(|n| n.0.to_string());
+        response.send(negotiated.unwrap_or_default());
+    }
+
+    let mut router = TreeRouter::new();
+    router.insert(Post, "/items", RouteFilters::new(create as fn(Context, Response))
+        .context_filter(MediaTypes::new()
+            .consumes(Mime(TopLevel::Application, SubLevel::Json, vec![]))
+            .produces(Mime(TopLevel::Application, SubLevel::Json, vec![]))
+            .produces(Mime(TopLevel::Text, SubLevel::Plain, vec![]))));
+
+    let server = Server::new(router).build().0;
+
+    let response = send_request(&server, "POST /items HTTP/1.1\r\nContent-Type: application/json; charset=utf-8\r\nContent-Length: 2\r\n\r\n{}");
+    assert!(response.starts_with("HTTP/1.1 200 "), "{}", response);
+    assert!(response.ends_with("\r\n\r\napplication/json"), "{}", response);
+
+    let response = send_request(&server, "POST /items HTTP/1.1\r\nContent-Type: text/xml\r\nContent-Length: 4\r\n\r\n<a/>");
+    assert!(response.starts_with("HTTP/1.1 415 "), "{}", response);
+
+    let response = send_request(&server, "POST /items HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}");
+    assert!(response.starts_with("HTTP/1.1 415 "), "{}", response);
+
+    let response = send_request(&server, "POST /items HTTP/1.1\r\nAccept: text/html\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 406 "), "{}", response);
+
+    let response = send_request(&server, "POST /items HTTP/1.1\r\nAccept: text/*\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\ntext/plain"), "{}", response);
+}
+
+#[test]
+#[cfg(feature = "encoding")]
+fn fallback_charset() {
+    use utils::Charset;
+
+    fn form(mut context: Context, response: Response) {
+        let query = context.query.get("q").map(|q| q.into_owned()).unwrap_or_default();
+        let body = context.body.read_query_body().unwrap();
+        response.send(format!("{}|{}", query, body.get("name").unwrap_or_default()));
+    }
+
+    let server = Server {
+        fallback_charset: Charset::from_label("latin1"),
+        ..Server::new(form as fn(Context, Response))
+    }.build().0;
+
+    let response = send_request(&server, "POST /?q=%E9 HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: 11\r\n\r\nname=Jos%E9");
+    assert!(response.ends_with("\r\n\r\né|José"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded; charset=windows-1252\r\nContent-Length: 8\r\n\r\nname=%80");
+    assert!(response.ends_with("\r\n\r\n|€"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded; charset=Shift_JIS\r\nContent-Length: 17\r\n\r\nname=%93%FA%96%7B");
+    assert!(response.ends_with("\r\n\r\n|日本"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded; charset=utf-8\r\nContent-Length: 8\r\n\r\nname=%E9");
+    assert!(response.ends_with("\r\n\r\n|\u{FFFD}"), "{}", response);
+
+    let server = Server::new(form as fn(Context, Response)).build().0;
+    let response = send_request(&server, "POST /?q=%E9 HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\n\u{FFFD}|"), "{}", response);
+}
+
+#[test]
+fn limit_large_bodies() {
+    use server::LargeBodyLimit;
+
+    fn echo(mut context: Context, response: Response) {
+        use std::io::Read;
+
+        let mut body = String::new();
+        context.body.read_to_string(&mut body).unwrap();
+        response.send(body);
+    }
+
+    let server = Server {
+        large_body_limit: Some(LargeBodyLimit {
+            threshold: 5,
+            max_concurrent: 1,
+            multipart: true,
+        }),
+        ..Server::new(echo as fn(Context, Response))
+    }.build().0;
+
+    let mut headers = Headers::new();
+    headers.set(ContentLength(6));
+    let slot = server.reserve_large_body(&headers).unwrap();
+    assert!(slot.is_some());
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nContent-Length: 6\r\n\r\nhello!");
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"), "{}", response);
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=x\r\nContent-Length: 0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello");
+    assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+
+    drop(slot);
+    let response = send_request(&server, "POST / HTTP/1.1\r\nContent-Length: 6\r\n\r\nhello!");
+    assert!(response.ends_with("\r\n\r\nhello!"), "{}", response);
+    assert_eq!(server.large_bodies.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn socket_options() {
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+
+    set_socket_options(&stream, true, Some(Duration::from_secs(1))).unwrap();
+    assert!(stream.nodelay().unwrap());
+    assert_eq!(TcpStreamExt::linger(&stream).unwrap(), Some(Duration::from_secs(1)));
+
+    set_socket_options(&stream, false, None).unwrap();
+    assert!(!stream.nodelay().unwrap());
+    assert_eq!(TcpStreamExt::linger(&stream).unwrap(), Some(Duration::from_secs(1)));
+}
+
+#[test]
+fn random_requests() {
+    use std::io::Read;
+    use hyper::buffer::BufReader;
+    use hyper::net::NetworkStream;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use mime::{TopLevel, SubLevel};
+
+    fn inspect(mut context: Context, response: Response) {
+        let _ = context.authorization();
+        let _ = context.content_type();
+        let _ = context.preferred_content_type(&[Mime(TopLevel::Text, SubLevel::Plain, vec![])]);
+        let _ = context.is_conditional();
+        let _ = ::utils::parse_range(&context.headers, 100);
+
+        let mut body = vec![];
+        let _ = (&mut context.body).take(64 * 1024).read_to_end(&mut body);
+        let _ = ::utils::parse_parameters(&body);
+        response.send(body);
+    }
+
+    const BASES: &'static [&'static [u8]] = &[
+        b"GET /a/b/../c?x=1&y=%20#f HTTP/1.1\r\nHost: example.com\r\n\r\n",
+        b"POST /form HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded; charset=utf-8\r\nContent-Length: 7\r\n\r\na=b&c=d",
+        b"POST /chunked HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\nX-Trailer: a\r\n\r\n",
+        b"GET http://example.com:8080/x HTTP/1.0\r\nRange: bytes=0-9,-5\r\nAuthorization: Basic dXNlcjpwYXNz\r\n\r\n",
+        b"OPTIONS * HTTP/1.1\r\nAccept: text/*;q=0.5, */*;q=0.1\r\nIf-None-Match: \"a\"\r\n\r\n",
+    ];
+    const INTERESTING: &'static [u8] = b"\r\n\t :;,=%?#/.\\\"*-+0123456789aAfFzZ\x00\x7f\x80\xc3\xa9\xff";
+
+    let server = Server {
+        max_body_length: Some(1024),
+        path_normalization: PathNormalization::Normalize,
+        ..Server::new(inspect as fn(Context, Response))
+    }.build().0;
+
+    let mut rng = XorShiftRng::from_seed([0x193a_6754, 0xa8a7_d469, 0x9783_0e05, 0x113b_a7bb]);
+
+    for _ in 0..10000 {
+        let mut input = BASES[rng.gen_range(0, BASES.len())].to_vec();
+        for _ in 0..rng.gen_range(1, 8) {
+            let position = rng.gen_range(0, input.len());
+            let byte = if rng.gen() { INTERESTING[rng.gen_range(0, INTERESTING.len())] } else { rng.gen() };
+            match rng.gen_range(0, 3) {
+                0 => input[position] = byte,
+                1 => input.insert(position, byte),
+                _ => {
+                    input.remove(position);
+                }
+            }
+        }
+
+        //Hyper panics if `Content-Length` is malformed, before the request
+        //reaches rustful, so those requests are skipped.
+        match hyper::http::h1::parse_request(&mut BufReader::new(&input[..])) {
+            Ok(head) => if head.headers.has::<ContentLength>() && head.headers.get::<ContentLength>().is_none() {
+                continue;
+            },
+            Err(_) => continue
+        }
+
+        let mut stream = ::mock::MockStream::new(input.clone());
+        let mut output = vec![];
+        {
+            let addr = stream.peer_addr().unwrap();
+            let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+            let request = match hyper::server::request::Request::new(&mut reader, addr) {
+                Ok(request) => request,
+                Err(_) => continue
+            };
+            let mut headers = hyper::header::Headers::new();
+            let mut response = hyper::server::response::Response::new(&mut output, &mut headers);
+            response.version = request.version;
+            server.handle(request, response);
+        }
+
+        let output = String::from_utf8_lossy(&output);
+        assert!(output.starts_with("HTTP/1."), "{:?} gave {:?}", String::from_utf8_lossy(&input), output);
+        assert!(!output.contains(" 500 "), "{:?} gave {:?}", String::from_utf8_lossy(&input), output);
+        assert!(output.len() < 64 * 1024, "{:?} gave {} bytes", String::from_utf8_lossy(&input), output.len());
+    }
+}
+
+#[test]
+fn require_https() {
+    use filter::RequireHttps;
+
+    fn hello(_context: Context, response: Response) {
+        response.send("hello");
+    }
+
+    let https = RequireHttps::new().exempt("/.well-known/acme-challenge/");
+    let server = Server {
+        trusted_proxies: vec!["127.0.0.1".parse().unwrap()],
+        context_filters: vec![Box::new(https.clone())],
+        response_filters: vec![Box::new(https)],
+        ..Server::new(hello as fn(Context, Response))
+    }.build().0;
+
+    let response = send_request(&server, "GET /a/b?c=d HTTP/1.1\r\nHost: example.com:8080\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 301 Moved Permanently\r\n"), "{}", response);
+    assert!(response.contains("Location: https://example.com/a/b?c=d\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET http://example.com/x HTTP/1.1\r\n\r\n");
+    assert!(response.contains("Location: https://example.com/x\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /.well-known/acme-challenge/token HTTP/1.1\r\nHost: example.com\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\nHost: example.com\r\nX-Forwarded-Proto: https\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+
+    let response = send_request(&server, "GET / HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.0 400 Bad Request\r\n"), "{}", response);
+
+    let https = RequireHttps {
+        status: StatusCode::PermanentRedirect,
+        port: Some(8443),
+        ..RequireHttps::new()
+    };
+    let server = Server {
+        context_filters: vec![Box::new(https.clone())],
+        response_filters: vec![Box::new(https)],
+        ..Server::new(hello as fn(Context, Response))
+    }.build().0;
+
+    let response = send_request(&server, "POST /form HTTP/1.1\r\nHost: example.com\r\nContent-Length: 0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 308 Permanent Redirect\r\n"), "{}", response);
+    assert!(response.contains("Location: https://example.com:8443/form\r\n"), "{}", response);
+}
+
+#[test]
+fn mount_handlers() {
+    use TreeRouter;
+    use Method::Get;
+
+    fn show_path(context: Context, response: Response) {
+        let path = context.path().map(|path| path.as_utf8_lossy().into_owned());
+        let original = context.original_path().map(|path| path.as_utf8_lossy().into_owned());
+        response.send(format!("{:?} {:?}", path, original));
+    }
+
+    let mut router = TreeRouter::new();
+    router.insert(Get, "/metricsx", Box::new(show_path as fn(Context, Response)) as Box<Handler>);
+    router.mount_handler("/metrics/", show_path as fn(Context, Response));
+
+    let server = Server::new(router).build().0;
+
+    let response = send_request(&server, "GET /metrics/cpu/load HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nSome(\"/cpu/load\") Some(\"/metrics/cpu/load\")"), "{}", response);
+
+    let response = send_request(&server, "POST /metrics/cpu/ HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nSome(\"/cpu/\") Some(\"/metrics/cpu/\")"), "{}", response);
+
+    let response = send_request(&server, "GET /metrics HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nSome(\"/\") Some(\"/metrics\")"), "{}", response);
+
+    let response = send_request(&server, "GET /metrics/ HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nSome(\"/\") Some(\"/metrics/\")"), "{}", response);
+
+    let response = send_request(&server, "GET /metricsx HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nSome(\"/metricsx\") Some(\"/metricsx\")"), "{}", response);
+
+    let response = send_request(&server, "GET /other HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", response);
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn compressed_ranges() {
+    use std::io::Cursor;
+    use filter::Compression;
+
+    fn send_data(context: Context, response: Response) {
+        response.send_seekable(Cursor::new(b"hello world".to_vec()), 11, &context).unwrap();
+    }
+
+    let compression = Compression::new();
+    let server = Server {
+        context_filters: vec![Box::new(compression.clone())],
+        response_filters: vec![Box::new(compression)],
+        ..Server::new(send_data as fn(Context, Response))
+    }.build().0;
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\nRange: bytes=6-\r\nAccept-Encoding: gzip\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"), "{}", response);
+    assert!(response.contains("Content-Range: bytes 6-10/11\r\n"), "{}", response);
+    assert!(response.contains("Content-Length: 5\r\n"), "{}", response);
+    assert!(!response.contains("Content-Encoding"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nworld"), "{}", response);
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\nRange: bytes=20-\r\nAccept-Encoding: gzip\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"), "{}", response);
+    assert!(!response.contains("Content-Encoding"), "{}", response);
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn compressed_head() {
+    use filter::Compression;
+
+    fn send_text(_context: Context, response: Response) {
+        response.send("hello hello hello hello");
+    }
+
+    let compression = Compression::new();
+    let server = Server {
+        context_filters: vec![Box::new(compression.clone())],
+        response_filters: vec![Box::new(compression)],
+        ..Server::new(send_text as fn(Context, Response))
+    }.build().0;
+
+    let response = send_request(&server, "HEAD / HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains("Content-Length: 23\r\n"), "{}", response);
+    assert!(response.contains("Vary: Accept-Encoding\r\n"), "{}", response);
+    assert!(!response.contains("Content-Encoding"), "{}", response);
+}
+
+#[test]
+fn verify_body_trailer() {
+    use handler::IntegrityHandler;
+
+    fn store(mut context: Context, response: Response) {
+        let body = context.body_bytes().unwrap();
+        response.send(format!("stored {:?}", String::from_utf8_lossy(&body)));
+    }
+
+    let handler = IntegrityHandler::new(store as fn(Context, Response), "X-Checksum", |body: &[u8], trailer: &[u8]| {
+        format!("{}", body.len()).as_bytes() == trailer
+    });
+    let server = Server::new(handler).build().0;
+
+    let response = send_request(&server, "PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\nX-Checksum: 11\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nstored \"hello world\""), "{}", response);
+
+    let response = send_request(&server, "PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\nX-Checksum: 11\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 422 Unprocessable Entity\r\n"), "{}", response);
+    assert!(!response.contains("stored"), "{}", response);
+
+    let response = send_request(&server, "PUT / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+}
+
+#[test]
+#[cfg(feature = "async_bridge")]
+fn block_on_future() {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::Mutex;
+    use std::task::{Context as TaskContext, Poll, Waker};
+    use std::thread;
+    use handler::block_on;
+
+    //Completed by an other thread, like a task on a shared runtime.
+    struct Delayed(Arc<Mutex<(Option<String>, Option<Waker>)>>);
+
+    impl Future for Delayed {
+        type Output = String;
+
+        fn poll(self: Pin<&mut Self>, task_context: &mut TaskContext) -> Poll<String> {
+            let mut state = self.0.lock().unwrap();
+            match state.0.take() {
+                Some(value) => Poll::Ready(value),
+                None => {
+                    state.1 = Some(task_context.waker().clone());
+                    Poll::Pending
+                }
+            }
+        }
+    }
+
+    fn fetch(context: Context, response: Response) {
+        let state = Arc::new(Mutex::new((None, None::<Waker>)));
+        let remote = state.clone();
+        let name = context.query.get("name").unwrap_or("".into()).into_owned();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            let waker = {
+                let mut state = remote.lock().unwrap();
+                state.0 = Some(format!("hello {}", name));
+                state.1.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        });
+
+        response.send(block_on(Delayed(state)));
+    }
+
+    let server = Server::new(fetch as fn(Context, Response)).build().0;
+    let response = send_request(&server, "GET /?name=world HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nhello world"), "{}", response);
+}
+
+#[test]
+fn reject_folded_headers() {
+    use hyper::buffer::BufReader;
+    use hyper::net::NetworkStream;
+
+    //Hyper rejects the head before rustful sees it, so this only makes sure
+    //that folding is still treated as an invalid header.
+    let mut stream = ::mock::MockStream::new("GET / HTTP/1.1\r\nX-Folded: a\r\n b\r\nHost: x\r\n\r\n");
+    let addr = stream.peer_addr().unwrap();
+    let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+    let error: RequestError = match hyper::server::request::Request::new(&mut reader, addr) {
+        Ok(_) => panic!("a folded header was accepted"),
+        Err(e) => e.into()
+    };
+    assert_eq!(error.status(), Some(StatusCode::BadRequest));
+}
+
+#[test]
+fn unexpected_bodies() {
+    use server::UnexpectedBody;
+    use Method::Get;
+
+    fn ignore_body(_context: Context, response: Response) {
+        response.send("ignored");
+    }
+
+    let server = Server::new(ignore_body as fn(Context, Response)).build().0;
+    let response = send_request(&server, "GET / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(!response.contains("Connection: close\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n");
+    assert!(!response.contains("Connection: close\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello");
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+
+    let server = Server::builder(ignore_body as fn(Context, Response))
+        .unexpected_body(Get, UnexpectedBody::Reject)
+        .build()
+        .unwrap()
+        .build().0;
+    let response = send_request(&server, "GET / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+
+    let server = Server::builder(ignore_body as fn(Context, Response))
+        .unexpected_body(Get, UnexpectedBody::Allow)
+        .build()
+        .unwrap()
+        .build().0;
+    let response = send_request(&server, "GET / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello");
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+}
+
+#[test]
+fn connection_storage() {
+    #[derive(Default)]
+    struct RequestCount(usize);
+
+    fn count(context: Context, response: Response) {
+        let count = context.connection_storage().with(|count: &mut RequestCount| {
+            count.0 += 1;
+            count.0
+        });
+        response.send(count.to_string());
+    }
+
+    let server = Server::new(count as fn(Context, Response)).build().0;
+
+    server.on_connection_start();
+    let response = send_request(&server, "GET / HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\n1"), "{}", response);
+    let response = send_request(&server, "GET / HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\n2"), "{}", response);
+    server.on_connection_end();
+
+    server.on_connection_start();
+    let response = send_request(&server, "GET / HTTP/1.1\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\n1"), "{}", response);
+    server.on_connection_end();
+}
+
+#[test]
+fn automatic_vary() {
+    fn negotiate(context: Context, mut response: Response) {
+        if context.header_raw("X-Origin").is_some() {
+            response.headers_mut().set_raw("Vary", vec![b"Origin, accept-encoding".to_vec()]);
+        }
+        if context.accepts_encoding("gzip") {
+            response.send("gzip");
+        } else {
+            response.send("identity");
+        }
+    }
+
+    let server = Server::new(negotiate as fn(Context, Response)).build().0;
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n");
+    assert!(response.contains("\r\nVary: Accept-Encoding\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\ngzip"), "{}", response);
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\nX-Origin: 1\r\n\r\n");
+    assert!(response.contains("\r\nVary: Origin, accept-encoding\r\n"), "{}", response);
+
+    let server = Server::builder(negotiate as fn(Context, Response)).auto_vary(false).build().unwrap().build().0;
+    let response = send_request(&server, "GET / HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n");
+    assert!(!response.contains("\r\nVary:"), "{}", response);
+}
+
+#[test]
+fn negotiate_content_type() {
+    fn page(context: Context, mut response: Response) {
+        let offers = [
+            Mime(TopLevel::Application, SubLevel::Json, vec![]),
+            Mime(TopLevel::Text, SubLevel::Html, vec![]),
+        ];
+
+        match context.negotiate(&offers).apply(&mut response) {
+            Some(0) => response.send("json"),
+            Some(_) => response.send("html"),
+            None => response.send("nothing")
+        }
+    }
+
+    let server = Server::new(page as fn(Context, Response)).build().0;
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\nAccept: text/html\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains("\r\nContent-Type: text/html\r\n"), "{}", response);
+    assert!(response.contains("\r\nVary: Accept\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nhtml"), "{}", response);
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\nAccept: text/*;q=0.5, application/json\r\n\r\n");
+    assert!(response.contains("\r\nContent-Type: application/json\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\njson"), "{}", response);
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\n\r\n");
+    assert!(response.contains("\r\nContent-Type: application/json\r\n"), "{}", response);
+    assert!(response.contains("\r\nVary: Accept\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\nAccept: image/png\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 406 Not Acceptable\r\n"), "{}", response);
+    assert!(response.contains("\r\nVary: Accept\r\n"), "{}", response);
+}
+
+#[test]
+fn chunk_limits() {
+    use std::io::Read;
+
+    fn read_body(mut context: Context, response: Response) {
+        let mut body = String::new();
+        match context.body.read_to_string(&mut body) {
+            Ok(_) => response.send(body),
+            Err(e) => response.send(e.to_string())
+        }
+    }
+
+    let server = Server::builder(read_body as fn(Context, Response))
+        .max_chunk_size(16)
+        .max_chunk_extension_length(8)
+        .build()
+        .unwrap()
+        .build().0;
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5;a=b\r\nhello\r\n0\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffffffff\r\nhello");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5;abcdefghijkl\r\nhello\r\n0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n11\r\n");
+    assert!(response.ends_with("\r\n\r\nthe chunk is too large"), "{}", response);
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+}
+
+#[test]
+fn send_download() {
+    fn download(context: Context, response: Response) {
+        let name = if context.header_raw("X-Ascii").is_some() {
+            "report.toml"
+        } else {
+            "rapport \"år\"\u{7}.toml"
+        };
+        response.send_download("Cargo.toml", name, &context).unwrap_or_else(|_| panic!("could not send Cargo.toml"));
+    }
+
+    let server = Server::new(download as fn(Context, Response)).build().0;
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\nX-Ascii: 1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains("\r\nContent-Disposition: attachment; filename=\"report.toml\"\r\n"), "{}", response);
+    assert!(response.contains("\r\nAccept-Ranges: bytes\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\nRange: bytes=0-8\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"), "{}", response);
+    assert!(
+        response.contains("\r\nContent-Disposition: attachment; filename=\"rapport __r__.toml\"; filename*=UTF-8''rapport%20%22%C3%A5r%22.toml\r\n"),
+        "{}", response
+    );
+    assert!(response.ends_with("\r\n\r\n[package]"), "{}", response);
+}
+
+#[test]
+fn close_after_short_raw_body() {
+    use hyper::buffer::BufReader;
+    use hyper::net::NetworkStream;
+
+    fn short(_context: Context, response: Response) {
+        let mut raw = unsafe { response.into_raw(10) };
+        raw.send("hello");
+    }
+
+    let server = Server::new(short as fn(Context, Response)).build().0;
+    let mut stream = ::mock::MockStream::new("GET / HTTP/1.1\r\n\r\n");
+    let mut output = vec![];
+    let mut headers = hyper::header::Headers::new();
+    {
+        let addr = stream.peer_addr().unwrap();
+        let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+        let request = hyper::server::request::Request::new(&mut reader, addr).unwrap();
+        let response = hyper::server::response::Response::new(&mut output, &mut headers);
+        server.handle(request, response);
+    }
+
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("Content-Length: 10\r\n"), "{}", output);
+    assert!(output.ends_with("\r\n\r\nhello"), "{}", output);
+
+    //Hyper checks these headers after the response, to see if the
+    //connection can be kept alive.
+    assert_eq!(headers.get(), Some(&Connection(vec![ConnectionOption::Close])));
+}
+
+#[test]
+fn metrics_events() {
+    use std::io::Read;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use TreeRouter;
+    use Method::{Get, Post};
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Started(Method),
+        Completed(Method, StatusCode, Option<String>),
+        Duration(Option<String>),
+        Read(Option<String>, u64),
+        Written(Option<String>, u64),
+        Ended(Method),
+    }
+
+    struct Recorder(Arc<Mutex<Vec<Event>>>);
+
+    impl Recorder {
+        fn push(&self, event: Event) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    impl Metrics for Recorder {
+        fn request_started(&self, method: &Method) {
+            self.push(Event::Started(method.clone()));
+        }
+
+        fn request_completed(&self, labels: &MetricLabels) {
+            self.push(Event::Completed(labels.method.clone(), labels.status, labels.route.map(ToOwned::to_owned)));
+        }
+
+        fn request_duration(&self, labels: &MetricLabels, duration: Duration) {
+            assert!(duration < Duration::from_secs(10));
+            self.push(Event::Duration(labels.route.map(ToOwned::to_owned)));
+        }
+
+        fn bytes_read(&self, labels: &MetricLabels, bytes: u64) {
+            self.push(Event::Read(labels.route.map(ToOwned::to_owned), bytes));
+        }
+
+        fn bytes_written(&self, labels: &MetricLabels, bytes: u64) {
+            self.push(Event::Written(labels.route.map(ToOwned::to_owned), bytes));
+        }
+
+        fn request_ended(&self, method: &Method) {
+            self.push(Event::Ended(method.clone()));
+        }
+    }
+
+    fn echo(mut context: Context, response: Response) {
+        let mut body = String::new();
+        context.body.read_to_string(&mut body).unwrap();
+        response.send(body);
+    }
+
+    fn fail(_context: Context, _response: Response) {
+        panic!("failed on purpose");
+    }
+
+    let mut router = TreeRouter::new();
+    router.insert(Post, "/users/:id/echo", echo as fn(Context, Response));
+    router.insert(Get, "/fail", fail as fn(Context, Response));
+
+    let events = Arc::new(Mutex::new(vec![]));
+    let server = Server {
+        metrics: Some(Box::new(Recorder(events.clone()))),
+        ..Server::new(router)
+    }.build().0;
+
+    let route = || Some("/users/:id/echo".to_owned());
+    send_request(&server, "POST /users/1/echo HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello");
+    assert_eq!(*events.lock().unwrap(), vec![
+        Event::Started(Post),
+        Event::Completed(Post, StatusCode::Ok, route()),
+        Event::Duration(route()),
+        Event::Read(route(), 5),
+        Event::Written(route(), 5),
+        Event::Ended(Post),
+    ]);
+
+    events.lock().unwrap().clear();
+    send_request(&server, "GET /missing HTTP/1.1\r\n\r\n");
+    assert_eq!(events.lock().unwrap()[1], Event::Completed(Get, StatusCode::NotFound, None));
+
+    events.lock().unwrap().clear();
+    send_request(&server, "GET /%zz HTTP/1.1\r\n\r\n");
+    assert_eq!(events.lock().unwrap()[1], Event::Completed(Get, StatusCode::BadRequest, None));
+
+    events.lock().unwrap().clear();
+    send_request(&server, "GET /fail HTTP/1.1\r\n\r\n");
+    let events = events.lock().unwrap();
+    assert_eq!(events[1], Event::Completed(Get, StatusCode::InternalServerError, None));
+    assert_eq!(events.last(), Some(&Event::Ended(Get)));
+}
+
+#[test]
+fn timeout_handler() {
+    use std::thread;
+    use std::time::Duration;
+    use handler::TimeoutHandler;
+
+    fn upstream(context: Context, response: Response) {
+        if context.path().map_or(false, |path| path == "/slow") {
+            assert!(context.time_remaining().unwrap() <= Duration::from_millis(50));
+            thread::sleep(Duration::from_millis(100));
+        }
+        response.send("from upstream");
+    }
+
+    let server = Server::new(TimeoutHandler::new(upstream as fn(Context, Response), Duration::from_millis(50))).build().0;
+
+    let response = send_request(&server, "GET /fast HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nfrom upstream"), "{}", response);
+
+    let response = send_request(&server, "GET /slow HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 504 Gateway Timeout\r\n"), "{}", response);
+    assert!(response.contains("\r\nContent-Length: 0\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\n"), "{}", response);
+}
+
+#[test]
+fn csrf_filter() {
+    use std::io::Read;
+    use filter::{Csrf, CsrfToken};
+    use session::CookieKeys;
+
+    fn echo(mut context: Context, response: Response) {
+        if context.method == Method::Get {
+            let token = response.filter_storage().get::<CsrfToken>().map(|token| token.0.clone());
+            response.send(token.unwrap_or_default());
+        } else {
+            let mut body = String::new();
+            context.body.read_to_string(&mut body).unwrap();
+            response.send(body);
+        }
+    }
+
+    fn post<R: Router>(server: &ServerInstance<R>, cookie: &str, fields: &str, body: &str) -> String {
+        send_request(server, &format!(
+            "POST /items HTTP/1.1\r\nCookie: {}\r\n{}Content-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\n\r\n{}",
+            cookie, fields, body.len(), body
+        ))
+    }
+
+    let csrf = Csrf::new().exempt("/hooks/");
+    let server = Server {
+        cookie_keys: Some(CookieKeys::new("a test key that is long enough")),
+        context_filters: vec![Box::new(csrf.clone())],
+        response_filters: vec![Box::new(csrf)],
+        ..Server::new(echo as fn(Context, Response))
+    }.build().0;
+
+    let response = send_request(&server, "GET /items HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    let token = response.split("\r\n\r\n").nth(1).unwrap().to_owned();
+    assert_eq!(token.len(), 64);
+    let set_cookie = response.lines().find(|line| line.starts_with("Set-Cookie: ")).expect(&response);
+    assert!(set_cookie.contains("; HttpOnly"), "{}", set_cookie);
+    let cookie = set_cookie["Set-Cookie: ".len()..].split(';').next().unwrap().to_owned();
+    assert!(cookie.starts_with(&format!("rustful_csrf={}.", token)), "{}", cookie);
+
+    //The cookie is only issued once.
+    let response = send_request(&server, &format!("GET /items HTTP/1.1\r\nCookie: {}\r\n\r\n", cookie));
+    assert!(!response.contains("Set-Cookie"), "{}", response);
+    assert!(response.ends_with(&format!("\r\n\r\n{}", token)), "{}", response);
+
+    let response = post(&server, &cookie, &format!("X-CSRF-Token: {}\r\n", token), "a=1");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\na=1"), "{}", response);
+
+    let body = format!("a=1&csrf_token={}", token);
+    let response = post(&server, &cookie, "", &body);
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.ends_with(&format!("\r\n\r\n{}", body)), "{}", response);
+
+    let response = post(&server, &cookie, "", "a=1");
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{}", response);
+
+    let forged = format!("{}{}", &token[..63], if token.ends_with('0') { '1' } else { '0' });
+    let response = post(&server, &cookie, &format!("X-CSRF-Token: {}\r\n", forged), "a=1");
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{}", response);
+
+    let forged_cookie = format!("rustful_csrf={}.0000", forged);
+    let response = post(&server, &forged_cookie, &format!("X-CSRF-Token: {}\r\n", forged), "a=1");
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST /hooks/push HTTP/1.1\r\nContent-Length: 3\r\n\r\na=1");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST /hooks HTTP/1.1\r\nContent-Length: 3\r\n\r\na=1");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST /hooks-admin HTTP/1.1\r\nContent-Length: 3\r\n\r\na=1");
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST /hooks%2Fpush HTTP/1.1\r\nContent-Length: 3\r\n\r\na=1");
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{}", response);
+
+    //The exemption follows the normalized path, which is what's routed.
+    let csrf = Csrf::new().exempt("/hooks/");
+    let server = Server {
+        cookie_keys: Some(CookieKeys::new("a test key that is long enough")),
+        path_normalization: PathNormalization::Normalize,
+        context_filters: vec![Box::new(csrf.clone())],
+        response_filters: vec![Box::new(csrf)],
+        ..Server::new(echo as fn(Context, Response))
+    }.build().0;
+
+    let response = send_request(&server, "POST /hooks/../items HTTP/1.1\r\nContent-Length: 3\r\n\r\na=1");
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{}", response);
+
+    let response = send_request(&server, "POST /items/../hooks/push HTTP/1.1\r\nContent-Length: 3\r\n\r\na=1");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+}
+
+//Serves `path` from `/file`, `/download` and, copied through a buffer, from
+//`/buffered`, over TCP.
+#[cfg(test)]
+fn file_server(path: ::std::path::PathBuf) -> Listening {
+    use std::fs::File;
+
+    let handler = move |context: Context, response: Response| {
+        match context.uri_path.as_utf8_path() {
+            Some("/file") => response.send_file(&path).unwrap_or_else(|_| panic!("could not send the file")),
+            Some("/download") => response.send_download(&path, "data.bin", &context).unwrap_or_else(|_| panic!("could not send the file")),
+            _ => {
+                let file = File::open(&path).unwrap();
+                let length = file.metadata().unwrap().len();
+                response.send_reader(file, Some(length)).unwrap();
+            }
+        }
+    };
+
+    Server {
+        host: "127.0.0.1:0".parse::<SocketAddr>().unwrap().into(),
+        threads: Some(2),
+        keep_alive: Some(KeepAlive {
+            timeout: Duration::from_secs(5),
+            free_threads: 0
+        }),
+        ..Server::new(handler)
+    }.run().unwrap()
+}
+
+//Reads one response from a kept alive connection, and returns the head and
+//the body.
+#[cfg(test)]
+fn read_response(client: &mut TcpStream) -> (String, Vec<u8>) {
+    let mut head = vec![];
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0];
+        client.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8(head).unwrap();
+
+    let length = head.split("\r\n")
+        .find(|line| line.starts_with("Content-Length: "))
+        .map(|line| line["Content-Length: ".len()..].parse().unwrap())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    client.read_exact(&mut body).unwrap();
+
+    (head, body)
+}
+
+#[test]
+fn send_files_over_tcp() {
+    use std::{env, fs};
+
+    let path = env::temp_dir().join("rustful_send_files_over_tcp.bin");
+    let content: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(&path, &content).unwrap();
+
+    let mut listening = file_server(path.clone());
+    let mut client = TcpStream::connect(listening.socket).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+    for target in &["/file", "/download", "/buffered"] {
+        client.write_all(format!("GET {} HTTP/1.1\r\n\r\n", target).as_bytes()).unwrap();
+        let (head, body) = read_response(&mut client);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+        assert!(body == content, "the body of {} differs", target);
+    }
+
+    client.write_all(b"GET /download HTTP/1.1\r\nRange: bytes=100000-199999\r\n\r\n").unwrap();
+    let (head, body) = read_response(&mut client);
+    assert!(head.starts_with("HTTP/1.1 206 Partial Content\r\n"), "{}", head);
+    assert!(head.contains("Content-Range: bytes 100000-199999/300000\r\n"), "{}", head);
+    assert!(body == &content[100000..200000], "the range differs");
+
+    //Let the server threads run in the background.
+    listening.close().unwrap();
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn full_duplex_echo() {
+    fn echo(mut context: Context, mut response: Response) {
+        if context.uri_path.as_utf8_path() == Some("/duplex") {
+            context.full_duplex(&mut response);
+        }
+
+        let mut chunked = response.into_chunked();
+        let mut buffer = [0; 1024];
+        loop {
+            match context.body.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(length) => {
+                    chunked.send(&buffer[..length]);
+                    if chunked.flush().is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    fn read_exact(client: &mut TcpStream, length: usize) -> String {
+        let mut buffer = vec![0; length];
+        client.read_exact(&mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    let mut listening = Server {
+        host: "127.0.0.1:0".parse::<SocketAddr>().unwrap().into(),
+        threads: Some(2),
+        keep_alive: Some(KeepAlive {
+            timeout: Duration::from_secs(5),
+            free_threads: 0
+        }),
+        ..Server::new(echo as fn(Context, Response))
+    }.run().unwrap();
+    let mut client = TcpStream::connect(listening.socket).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+    //Each part is echoed before the next one is sent.
+    client.write_all(b"POST /duplex HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n").unwrap();
+    let (head, _) = read_response(&mut client);
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+    assert!(head.contains("Transfer-Encoding: chunked\r\n"), "{}", head);
+    assert!(!head.contains("Connection: close"), "{}", head);
+    assert_eq!(read_exact(&mut client, 10), "5\r\nhello\r\n");
+
+    client.write_all(b"6\r\n world\r\n").unwrap();
+    assert_eq!(read_exact(&mut client, 11), "6\r\n world\r\n");
+
+    client.write_all(b"0\r\n\r\n").unwrap();
+    assert_eq!(read_exact(&mut client, 5), "0\r\n\r\n");
+
+    //The connection is still alive, and what the handler doesn't read is
+    //drained after it.
+    client.write_all(b"POST /duplex HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc").unwrap();
+    let (head, _) = read_response(&mut client);
+    assert!(!head.contains("Connection: close"), "{}", head);
+    assert_eq!(read_exact(&mut client, 13), "3\r\nabc\r\n0\r\n\r\n");
+
+    //A response that is started before the body has been read closes the
+    //connection by default.
+    client.write_all(b"POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhi\r\n").unwrap();
+    let (head, _) = read_response(&mut client);
+    assert!(head.contains("Connection: close\r\n"), "{}", head);
+    assert_eq!(read_exact(&mut client, 7), "2\r\nhi\r\n");
+
+    listening.close().unwrap();
+}
+
+#[test]
+fn auto_content_length() {
+    fn respond(context: Context, response: Response) {
+        let path = context.uri_path.as_path();
+        let mut chunked = response.into_chunked();
+        if path == Some("/large".into()) {
+            chunked.send(vec![b'x'; 32]);
+        } else if path == Some("/flush".into()) {
+            chunked.send("hello");
+            chunked.flush().unwrap();
+        } else {
+            chunked.send("hello");
+            chunked.send(" world");
+        }
+    }
+
+    let server = Server::builder(respond as fn(Context, Response)).auto_content_length(16).build().unwrap().build().0;
+
+    let response = send_request(&server, "GET /small HTTP/1.1\r\n\r\n");
+    assert!(response.contains("Content-Length: 11\r\n"), "{}", response);
+    assert!(!response.contains("Transfer-Encoding"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nhello world"), "{}", response);
+
+    let response = send_request(&server, "GET /small HTTP/1.0\r\n\r\n");
+    assert!(response.contains("Content-Length: 11\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nhello world"), "{}", response);
+
+    let response = send_request(&server, "GET /large HTTP/1.1\r\n\r\n");
+    assert!(response.contains("Transfer-Encoding: chunked\r\n"), "{}", response);
+    assert!(!response.contains("Content-Length"), "{}", response);
+
+    let response = send_request(&server, "GET /flush HTTP/1.1\r\n\r\n");
+    assert!(response.contains("Transfer-Encoding: chunked\r\n"), "{}", response);
+    assert!(response.ends_with("5\r\nhello\r\n0\r\n\r\n"), "{}", response);
+
+    let server = Server::new(respond as fn(Context, Response)).build().0;
+    let response = send_request(&server, "GET /small HTTP/1.1\r\n\r\n");
+    assert!(response.contains("Content-Length: 11\r\n"), "{}", response);
+    assert!(!response.contains("Transfer-Encoding"), "{}", response);
+
+    let server = Server::builder(respond as fn(Context, Response)).auto_content_length(0).build().unwrap().build().0;
+    let response = send_request(&server, "GET /small HTTP/1.1\r\n\r\n");
+    assert!(response.contains("Transfer-Encoding: chunked\r\n"), "{}", response);
+}
+
+#[test]
+fn status_hooks() {
+    use filter::StatusHooks;
+
+    struct Maintenance;
+
+    impl ContextFilter for Maintenance {
+        fn modify(&self, _ctx: FilterContext, context: &mut Context) -> ContextAction {
+            if context.path().map_or(false, |path| path.as_bytes() == b"/maintenance") {
+                ContextAction::abort(StatusCode::ServiceUnavailable)
+            } else {
+                ContextAction::next()
+            }
+        }
+    }
+
+    fn respond(context: Context, mut response: Response) {
+        if context.uri_path.as_path() == Some("/busy".into()) {
+            response.set_status(StatusCode::ServiceUnavailable);
+        }
+        response.send("hello");
+    }
+
+    fn retry_later(_status: StatusCode, headers: &mut Headers) {
+        headers.set_raw("Retry-After", vec![b"120".to_vec()]);
+    }
+
+    let server = Server {
+        context_filters: vec![Box::new(Maintenance)],
+        response_filters: vec![Box::new(StatusHooks::new().on(StatusCode::ServiceUnavailable, retry_later))],
+        ..Server::new(respond as fn(Context, Response))
+    }.build().0;
+
+    let response = send_request(&server, "GET /busy HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"), "{}", response);
+    assert!(response.contains("Retry-After: 120\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET /maintenance HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"), "{}", response);
+    assert!(response.contains("Retry-After: 120\r\n"), "{}", response);
+
+    let response = send_request(&server, "GET / HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(!response.contains("Retry-After"), "{}", response);
+}
+
+#[cfg(all(test, feature = "benchmark"))]
+fn bench_file_server(b: &mut ::test::Bencher, target: &str) {
+    use std::{env, fs};
+
+    let path = env::temp_dir().join(format!("rustful_bench{}.bin", target.replace('/', "_")));
+    let content = vec![7; 4 * 1024 * 1024];
+    fs::write(&path, &content).unwrap();
+
+    let mut listening = file_server(path.clone());
+    let mut client = TcpStream::connect(listening.socket).unwrap();
+    let request = format!("GET {} HTTP/1.1\r\n\r\n", target);
+
+    b.bytes = content.len() as u64;
+    b.iter(|| {
+        client.write_all(request.as_bytes()).unwrap();
+        read_response(&mut client)
+    });
+
+    listening.close().unwrap();
+    fs::remove_file(&path).unwrap();
+}
+
+//With the `sendfile` feature on Linux, this skips the copy through
+//userspace. Compare with `send_buffered_file_speed`.
+#[bench]
+#[cfg(feature = "benchmark")]
+fn send_file_speed(b: &mut ::test::Bencher) {
+    bench_file_server(b, "/file");
+}
+
+#[bench]
+#[cfg(feature = "benchmark")]
+fn send_buffered_file_speed(b: &mut ::test::Bencher) {
+    bench_file_server(b, "/buffered");
+}
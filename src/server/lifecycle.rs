@@ -0,0 +1,364 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "testing")]
+use std::sync::Condvar;
+#[cfg(feature = "testing")]
+use std::time::{Duration, Instant};
+
+const ACCEPTING: usize = 0;
+const DRAINING: usize = 1;
+const STOPPED: usize = 2;
+
+///The lifecycle state of a server.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LifecycleState {
+    ///The server is accepting and handling requests as usual.
+    Accepting,
+
+    ///Shutdown has begun and the remaining requests are being drained.
+    ///Responses will close their connections, instead of keeping them
+    ///alive.
+    Draining,
+
+    ///Shutdown has begun and there are no more requests in progress.
+    Stopped
+}
+
+///A shared handle to the lifecycle state of a server.
+///
+///The handle can be cloned and shared with handlers, for example to tell a
+///load balancer to stop sending traffic while the server is draining. The
+///same handle is available through `Context::lifecycle`.
+///
+///```
+///use rustful::{Context, Response, StatusCode};
+///use rustful::server::LifecycleState;
+///
+///fn ready(context: Context, mut response: Response) {
+///    if context.lifecycle.state() != LifecycleState::Accepting {
+///        response.set_status(StatusCode::ServiceUnavailable);
+///    }
+///}
+///```
+#[derive(Clone, Debug, Default)]
+pub struct Lifecycle {
+    inner: Arc<LifecycleInner>
+}
+
+#[derive(Debug, Default)]
+struct LifecycleInner {
+    state: AtomicUsize,
+    active: AtomicUsize,
+    connections: AtomicUsize,
+    failure: Mutex<Option<String>>,
+    stop_hooks: StopHooks,
+    #[cfg(feature = "testing")]
+    changes: Mutex<()>,
+    #[cfg(feature = "testing")]
+    changed: Condvar
+}
+
+impl Lifecycle {
+    ///Create a new handle in the `Accepting` state.
+    pub fn new() -> Lifecycle {
+        Lifecycle::default()
+    }
+
+    ///Get the current state.
+    pub fn state(&self) -> LifecycleState {
+        match self.inner.state.load(Ordering::SeqCst) {
+            ACCEPTING => LifecycleState::Accepting,
+            DRAINING => LifecycleState::Draining,
+            _ => LifecycleState::Stopped
+        }
+    }
+
+    ///Check if the server is in the `Accepting` state.
+    pub fn is_accepting(&self) -> bool {
+        self.state() == LifecycleState::Accepting
+    }
+
+    ///The number of requests that are currently being handled.
+    pub fn active_requests(&self) -> usize {
+        self.inner.active.load(Ordering::SeqCst)
+    }
+
+    ///The number of open connections, including those that are waiting
+    ///for another `keep-alive` request.
+    pub fn open_connections(&self) -> usize {
+        self.inner.connections.load(Ordering::SeqCst)
+    }
+
+    ///The number of open connections that are not currently handling a
+    ///request. They are waiting for another `keep-alive` request, or for the
+    ///first request to arrive.
+    pub fn idle_connections(&self) -> usize {
+        self.open_connections().saturating_sub(self.active_requests())
+    }
+
+    ///Begin shutting down. The state changes to `Draining` immediately, and
+    ///then to `Stopped` when the last request in progress has been handled,
+    ///or right away if there are none.
+    ///
+    ///This is also what a signal handler should call to shut the server
+    ///down gracefully, so `Server::on_shutdown` runs the same way no matter
+    ///how the shutdown was triggered.
+    pub fn begin_shutdown(&self) {
+        if self.inner.state.compare_exchange(ACCEPTING, DRAINING, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+            self.try_stop();
+            self.notify();
+        }
+    }
+
+    ///The error that made the server stop accepting connections, if any.
+    ///The listener is broken when this happens, so the server begins
+    ///shutting down, as if `begin_shutdown` was called, and the process
+    ///should exit after `Server::on_shutdown` has been called.
+    pub fn failure(&self) -> Option<String> {
+        self.inner.failure.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    ///
+    ///Record that the listener failed, and begin shutting down. Only the
+    ///first error is kept.
+    pub fn fail(&self, error: String) {
+        {
+            let mut failure = self.inner.failure.lock().unwrap_or_else(|e| e.into_inner());
+            if failure.is_none() {
+                *failure = Some(error);
+            }
+        }
+
+        self.begin_shutdown();
+    }
+
+    ///Block until the state is `state`, or until `timeout` has passed.
+    ///Returns `true` if the state was reached. This is meant for testing
+    ///graceful shutdown without guessing how long to sleep, and it requires
+    ///the `testing` feature.
+    ///
+    ///```
+    ///# #[cfg(feature = "testing")] {
+    ///use std::time::Duration;
+    ///use rustful::server::{Lifecycle, LifecycleState};
+    ///
+    ///let lifecycle = Lifecycle::new();
+    ///let shutdown = lifecycle.clone();
+    ///std::thread::spawn(move || shutdown.begin_shutdown());
+    ///
+    ///assert!(lifecycle.wait_for_state(LifecycleState::Stopped, Duration::from_secs(10)));
+    ///# }
+    ///```
+    #[cfg(feature = "testing")]
+    pub fn wait_for_state(&self, state: LifecycleState, timeout: Duration) -> bool {
+        self.wait_until(timeout, || self.state() == state)
+    }
+
+    ///Block until exactly `count` requests are being handled, or until
+    ///`timeout` has passed. Returns `true` if the count was reached. This
+    ///can be used to make sure that a slow request has started before
+    ///shutdown begins, and it requires the `testing` feature.
+    #[cfg(feature = "testing")]
+    pub fn wait_for_active_requests(&self, count: usize, timeout: Duration) -> bool {
+        self.wait_until(timeout, || self.active_requests() == count)
+    }
+
+    #[cfg(feature = "testing")]
+    fn wait_until<F: Fn() -> bool>(&self, timeout: Duration, condition: F) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut guard = self.inner.changes.lock().unwrap_or_else(|e| e.into_inner());
+
+        while !condition() {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+
+            guard = match self.inner.changed.wait_timeout(guard, deadline - now) {
+                Ok((guard, _)) => guard,
+                Err(e) => e.into_inner().0
+            };
+        }
+
+        true
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    ///
+    ///Run `hook` once, when the state changes to `Stopped`, or right away if
+    ///it already has.
+    pub fn on_stopped(&self, hook: Box<Fn() + Send + Sync>) {
+        {
+            //The state is checked while holding the lock, so the hook can't
+            //be added after the hooks have been taken by `try_stop`.
+            let mut hooks = self.inner.stop_hooks.0.lock().unwrap_or_else(|e| e.into_inner());
+            if self.state() != LifecycleState::Stopped {
+                hooks.push(hook);
+                return;
+            }
+        }
+
+        hook();
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn request_started(&self) {
+        self.inner.active.fetch_add(1, Ordering::SeqCst);
+        self.notify();
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn request_finished(&self) {
+        if self.inner.active.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.try_stop();
+        }
+        self.notify();
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn connection_started(&self) {
+        self.inner.connections.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn connection_finished(&self) {
+        self.inner.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    //Wake up anyone who waits for a change. The lock makes sure that the
+    //change isn't missed between checking the condition and waiting.
+    #[cfg(feature = "testing")]
+    fn notify(&self) {
+        let _guard = self.inner.changes.lock().unwrap_or_else(|e| e.into_inner());
+        self.inner.changed.notify_all();
+    }
+
+    #[cfg(not(feature = "testing"))]
+    fn notify(&self) {}
+
+    fn try_stop(&self) {
+        if self.inner.active.load(Ordering::SeqCst) == 0 && self.inner.state.compare_exchange(DRAINING, STOPPED, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+            //The hooks are called outside the lock, in case they take a
+            //while or add more hooks.
+            let hooks = ::std::mem::replace(&mut *self.inner.stop_hooks.0.lock().unwrap_or_else(|e| e.into_inner()), vec![]);
+            for hook in hooks {
+                hook();
+            }
+        }
+    }
+}
+
+//The hooks that are called when the state changes to `Stopped`.
+#[derive(Default)]
+struct StopHooks(Mutex<Vec<Box<Fn() + Send + Sync>>>);
+
+impl fmt::Debug for StopHooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("StopHooks(..)")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Lifecycle, LifecycleState};
+
+    #[test]
+    fn drain_requests() {
+        let lifecycle = Lifecycle::new();
+        assert_eq!(lifecycle.state(), LifecycleState::Accepting);
+
+        lifecycle.request_started();
+        lifecycle.request_started();
+        lifecycle.begin_shutdown();
+        assert_eq!(lifecycle.clone().state(), LifecycleState::Draining);
+
+        lifecycle.request_finished();
+        assert_eq!(lifecycle.state(), LifecycleState::Draining);
+        lifecycle.request_finished();
+        assert_eq!(lifecycle.state(), LifecycleState::Stopped);
+        assert_eq!(lifecycle.active_requests(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "testing")]
+    fn wait_for_changes() {
+        use std::thread;
+        use std::time::Duration;
+
+        let lifecycle = Lifecycle::new();
+        assert!(!lifecycle.wait_for_active_requests(1, Duration::from_millis(10)));
+
+        let server = lifecycle.clone();
+        let handle = thread::spawn(move || {
+            server.request_started();
+            assert!(server.wait_for_state(LifecycleState::Draining, Duration::from_secs(10)));
+            server.request_finished();
+        });
+
+        assert!(lifecycle.wait_for_active_requests(1, Duration::from_secs(10)));
+        lifecycle.begin_shutdown();
+        assert!(lifecycle.wait_for_state(LifecycleState::Stopped, Duration::from_secs(10)));
+        assert_eq!(lifecycle.active_requests(), 0);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn stop_when_idle() {
+        let lifecycle = Lifecycle::new();
+        lifecycle.request_started();
+        lifecycle.request_finished();
+        assert!(lifecycle.is_accepting());
+
+        lifecycle.begin_shutdown();
+        assert_eq!(lifecycle.state(), LifecycleState::Stopped);
+    }
+
+    #[test]
+    fn run_stop_hooks_once() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let lifecycle = Lifecycle::new();
+        let hook_calls = calls.clone();
+        lifecycle.on_stopped(Box::new(move || { hook_calls.fetch_add(1, Ordering::SeqCst); }));
+
+        lifecycle.request_started();
+        lifecycle.begin_shutdown();
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        lifecycle.request_finished();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        lifecycle.begin_shutdown();
+        lifecycle.request_started();
+        lifecycle.request_finished();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let hook_calls = calls.clone();
+        lifecycle.on_stopped(Box::new(move || { hook_calls.fetch_add(1, Ordering::SeqCst); }));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn count_idle_connections() {
+        let lifecycle = Lifecycle::new();
+        lifecycle.connection_started();
+        lifecycle.connection_started();
+        lifecycle.request_started();
+        assert_eq!(lifecycle.open_connections(), 2);
+        assert_eq!(lifecycle.idle_connections(), 1);
+
+        lifecycle.request_finished();
+        lifecycle.connection_finished();
+        assert_eq!(lifecycle.open_connections(), 1);
+        assert_eq!(lifecycle.idle_connections(), 1);
+    }
+}
@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use Method;
+use StatusCode;
+
+///The labels of a handled request.
+#[derive(Clone, Copy, Debug)]
+pub struct MetricLabels<'a> {
+    ///The HTTP method.
+    pub method: &'a Method,
+
+    ///The final status code.
+    pub status: StatusCode,
+
+    ///The pattern of the matched route, like `/users/:id`, or `None` if no
+    ///route was matched, or if the router doesn't record patterns. Variables
+    ///are written as `:name` and variable sequences as `*name`, so the
+    ///number of different values is limited, even if the number of paths
+    ///isn't.
+    pub route: Option<&'a str>,
+}
+
+///A sink for request metrics, like counters, histograms and gauges.
+///
+///The server calls these methods for every request, including those that
+///are rejected before they reach a handler, and those where the handler
+///panics. Every method does nothing by default, so only the interesting ones
+///have to be implemented. This is where they are called, in order:
+///
+/// 1. `request_started`, as soon as the request head has been parsed and
+///before anything else is done with the request.
+/// 2. `request_completed`, after the response has been sent, or failed to be
+///sent, and before the completion observers are notified.
+/// 3. `request_duration`, right after `request_completed`, with the time
+///since `request_started`.
+/// 4. `bytes_read`, right after `request_duration`, with the number of
+///request body bytes that were received.
+/// 5. `bytes_written`, right after `bytes_read`, with the number of response
+///body bytes that were sent, as in `CompletionInfo::bytes_written`.
+/// 6. `request_ended`, after the completion observers, as the very last
+///thing the server does with the request.
+///
+///Requests that are too broken to reach rustful, as described in
+///`RequestError`, are never reported.
+///
+///```
+///use std::sync::atomic::{AtomicUsize, Ordering};
+///use rustful::{Server, Context, Response, Method};
+///use rustful::server::{Metrics, MetricLabels};
+///
+///#[derive(Default)]
+///struct Counters {
+///    in_flight: AtomicUsize,
+///    handled: AtomicUsize,
+///}
+///
+///impl Metrics for Counters {
+///    fn request_started(&self, _method: &Method) {
+///        self.in_flight.fetch_add(1, Ordering::Relaxed);
+///    }
+///
+///    fn request_completed(&self, _labels: &MetricLabels) {
+///        self.handled.fetch_add(1, Ordering::Relaxed);
+///    }
+///
+///    fn request_ended(&self, _method: &Method) {
+///        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+///    }
+///}
+///
+///# fn handler(_context: Context, _response: Response) {}
+///let server = Server {
+///    metrics: Some(Box::new(Counters::default())),
+///    ..Server::new(handler as fn(Context, Response))
+///};
+///```
+pub trait Metrics: Send + Sync + 'static {
+    ///A request has been received. This is the place to increment an
+    ///in-flight gauge.
+    #[allow(unused_variables)]
+    fn request_started(&self, method: &Method) {}
+
+    ///A request has been handled. This is the place to increment a request
+    ///counter.
+    #[allow(unused_variables)]
+    fn request_completed(&self, labels: &MetricLabels) {}
+
+    ///The time it took to handle a request, including sending the response.
+    #[allow(unused_variables)]
+    fn request_duration(&self, labels: &MetricLabels, duration: Duration) {}
+
+    ///The number of request body bytes that were received, after removing
+    ///any chunked transfer encoding.
+    #[allow(unused_variables)]
+    fn bytes_read(&self, labels: &MetricLabels, bytes: u64) {}
+
+    ///The number of response body bytes that were written to the connection.
+    #[allow(unused_variables)]
+    fn bytes_written(&self, labels: &MetricLabels, bytes: u64) {}
+
+    ///The server is done with a request. This is the place to decrement an
+    ///in-flight gauge. It's called once for each call to `request_started`.
+    #[allow(unused_variables)]
+    fn request_ended(&self, method: &Method) {}
+}
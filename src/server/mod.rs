@@ -1,6 +1,110 @@
 //!Server configuration and instance.
+//!
+//!The server reports what it's doing through the [`log`][log] crate, so the
+//!messages can be collected by any logger. Everything concerning connections
+//!and requests, like rejected requests, slow requests and panicking handlers,
+//!is logged with the target `rustful::server`, while routing issues are
+//!logged with the target `rustful::router`.
+//!
+//!#Pipelining
+//!
+//!Requests that are pipelined on a `keep-alive` connection are handled one at
+//!a time, in order. The next request is not read until the previous one has
+//!been handled and its response has been sent, so the responses can't be
+//!mixed up. The next request can only be found if the body of the previous
+//!one has been read to the end, so a response will be sent with
+//!`Connection: close` if its request body has not been fully read when the
+//!headers are written. The connection is then closed after the response,
+//!instead of reading the leftover body as a new request. Handlers that want
+//!to keep the connection open should read, or discard, the body before
+//!responding.
+//!
+//!A connection is served by a single worker thread, so it can't have more
+//!than one request in progress at a time, and pipelined requests are not
+//!buffered by the server. They stay in the socket until the connection gets
+//!to them. One client can therefore only occupy as many threads as it has
+//!connections, and `KeepAlive::free_threads` can be used to make sure that
+//!idle `keep-alive` connections don't hold on to all of the threads in the
+//!pool. HTTP/2 is not supported, so there are no concurrent streams to
+//!limit.
+//!
+//!#Header parsing
+//!
+//!Request heads are parsed by Hyper, which is strict about the syntax.
+//!Obsolete line folding, where a header value continues on the next line
+//!after a space or a tab, is always rejected as a malformed header, and the
+//!connection is closed without a response. Handlers and filters never see
+//!folded headers, so there is no risk that rustful and a proxy in front of
+//!it interpret them differently, which could otherwise be used to smuggle
+//!requests past the proxy. Unfolding is not supported, since it would have
+//!to happen before Hyper parses the head, and legacy clients that fold their
+//!headers will have to be served through a proxy that unfolds them.
+//!
+//!#Transfer codings
+//!
+//!Request bodies may be sent with `Transfer-Encoding: chunked`, which is the
+//!only transfer coding that is supported. `identity` is accepted as well,
+//!but it's ignored, since it doesn't change anything. The rules are:
+//!
+//! * `chunked` has to be the last coding, since it's what tells where the
+//!body ends, and it can only be applied once. `chunked, chunked` is rejected
+//!with `400 Bad Request`.
+//! * Any other coding, like `gzip` or `compress`, is rejected with `501 Not
+//!Implemented`, whether it's alone or stacked, as in `gzip, chunked`. The
+//!body would otherwise reach the handler without being decoded.
+//! * A request with both `Transfer-Encoding` and `Content-Length` is
+//!rejected with `400 Bad Request`, since they could be interpreted
+//!differently by a proxy.
+//!
+//!The coding names are case insensitive, and the connection is closed after
+//!a rejection, since the body is never read. Compressed content should be
+//!sent with `Content-Encoding` instead, which leaves the decoding to the
+//!handler.
+//!
+//!#Startup and shutdown
+//!
+//!`Server::on_start` and `Server::on_shutdown` can be used to open and close
+//!resources that follow the lifetime of the server. They are called in this
+//!order by `run`:
+//!
+//! 1. The socket is bound. An error is returned if it fails, and neither of
+//!the hooks are called.
+//! 2. `on_start` is called, on the thread that called `run`.
+//! 3. The accept loop starts, and `run` returns.
+//! 4. Requests are handled until `Server::lifecycle` begins shutting down,
+//!from a handler, another thread or a signal handler. The state changes to
+//!`Draining`, and the connections are closed after their current requests.
+//! 5. `on_shutdown` is called when the last request in progress has been
+//!handled, on the thread that handled it, or right away if there was none.
+//!
+//!Each hook is called at most once. The accept loop is not stopped by the
+//!shutdown, so the process should exit, or stop sending traffic to the
+//!server, after `on_shutdown` has been called.
+//!
+//!#Protocol versions
+//!
+//!Only HTTP/1.0 and HTTP/1.1 are supported, since the underlying Hyper
+//!version has no HTTP/2 implementation. This is how HTTP/2 clients are
+//!handled:
+//!
+//! * TLS connections don't offer `h2` through ALPN, so the clients fall back
+//!to HTTP/1.1.
+//! * Cleartext requests with `Upgrade: h2c` are answered as ordinary
+//!HTTP/1.1 requests, without switching protocols, which is what the
+//!specification expects from servers that don't support the upgrade.
+//! * Connections that start with the HTTP/2 connection preface, from clients
+//!that assume support without asking, are closed, since the preface is not
+//!a valid HTTP/1.x request line.
+//!
+//!Handlers may therefore assume that `Context.http_version` is one of the
+//!HTTP/1.x versions. HTTP/2 can still be offered to the clients by running
+//!the server behind a reverse proxy that translates it to HTTP/1.1.
+//!
+//![log]: https://crates.io/crates/log
 
 use std::borrow::ToOwned;
+use std::time::Duration;
+use std::net::IpAddr;
 
 use hyper;
 use hyper::mime::Mime;
@@ -8,18 +112,46 @@ use hyper::mime::Mime;
 pub use hyper::server::Listening;
 
 use filter::{ContextFilter, ResponseFilter};
+use header::Headers;
 use router::Router;
+use session::{SessionConfig, CookieKeys};
+#[cfg(feature = "rustc_json_body")]
+use context::BodyDeserializers;
+#[cfg(feature = "encoding")]
+use utils::Charset;
 
 use HttpResult;
+use Method;
 
 pub use self::instance::ServerInstance;
-pub use self::config::{Host, Global, Scheme, KeepAlive};
+pub use self::handle::ServerHandle;
+pub use self::config::{Host, Global, Scheme, KeepAlive, AcceptBackoff, PathNormalization, LargeBodyLimit, UnexpectedBody};
+pub use self::completion::{CompletionInfo, CompletionObserver};
+pub use self::rewrite::PathRewrite;
+pub use self::expect::ContinuePolicy;
+pub use self::metrics::{Metrics, MetricLabels};
+pub use self::lifecycle::{Lifecycle, LifecycleState};
+pub use self::error::{RequestError, ConfigError};
+pub use self::builder::ServerBuilder;
 
 mod instance;
+mod handle;
+mod accept;
+mod stream;
 mod config;
+mod completion;
+mod rewrite;
+mod expect;
+mod metrics;
+mod lifecycle;
+mod error;
+mod builder;
 
 ///Used to set up and run a server.
 ///
+///The fields can be set directly, as below, or through the chainable setters
+///of a `ServerBuilder`, from `Server::builder`.
+///
 ///```no_run
 ///# use std::error::Error;
 ///# use rustful::{Server, Handler, Context, Response};
@@ -45,8 +177,11 @@ pub struct Server<R: Router> {
     pub handlers: R,
 
     ///A fallback handler for when none is found in `handlers`. Leaving this
-    ///unspecified will cause an empty `404` response to be automatically sent
-    ///instead.
+    ///unspecified will cause a `404` response to be automatically sent
+    ///instead. Its body will be a small HTML page or a JSON object
+    ///(`{"error":"not found"}`), depending on the `Accept` header, or empty
+    ///if neither is acceptable. Setting a fallback handler replaces it
+    ///entirely.
     pub fallback_handler: Option<R::Handler>,
 
     ///The host address and port where the server will listen for requests.
@@ -56,6 +191,41 @@ pub struct Server<R: Router> {
     ///Use good old HTTP or the more secure HTTPS. Default is HTTP.
     pub scheme: Scheme,
 
+    ///Set the `IPV6_V6ONLY` option when listening on an IPv6 address.
+    ///Setting this to `Some(false)` makes it possible to accept both IPv6
+    ///and IPv4 connections with a single `[::]` socket, while `Some(true)`
+    ///restricts it to IPv6. It has no effect on IPv4 addresses.
+    ///
+    ///The default (`None`) is to leave it up to the platform. Linux will
+    ///usually accept IPv4 connections (unless `net.ipv6.bindv6only` is set),
+    ///while Windows and the BSDs, including OS X, will only accept IPv6.
+    pub ipv6_only: Option<bool>,
+
+    ///Set `TCP_NODELAY` on the connections, to disable Nagle's algorithm.
+    ///The algorithm holds back small writes until the previous ones have
+    ///been acknowledged, which saves bandwidth when many small packets are
+    ///sent, but it can delay each response by up to a round trip, or more
+    ///when it's combined with delayed ACKs on the client. Responses are
+    ///usually written in a few large pieces, so there is little to gain from
+    ///the batching. Default is `true`.
+    pub tcp_nodelay: bool,
+
+    ///Set `SO_LINGER` on the connections. `Some(duration)` makes closing a
+    ///connection wait for up to `duration` until unsent data has been
+    ///delivered, while `Some(Duration::from_secs(0))` makes it reset the
+    ///connection right away, discarding unsent data and skipping the
+    ///`TIME_WAIT` state. Note that the closing thread is blocked while it's
+    ///waiting, which may keep it from serving other requests. Default is
+    ///`None`, to let the operating system close the connection in the
+    ///background.
+    pub linger: Option<Duration>,
+
+    ///The name of the worker threads, which will be followed by a number,
+    ///as in `rustful-worker-1`. It's used when reporting panics, since the
+    ///threads are not spawned by rustful itself. Default is
+    ///`"rustful-worker"`.
+    pub thread_name_prefix: String,
+
     ///The number of threads to be used in the server thread pool. The default
     ///(`None`) will cause the server to optimistically use the formula
     ///`(num_cores * 5) / 4`.
@@ -64,14 +234,193 @@ pub struct Server<R: Router> {
     ///The server's `keep-alive` policy. Setting this to `Some(...)` will
     ///allow `keep-alive` connections with a timeout, and keeping it as `None`
     ///will force connections to close after each request. Default is `None`.
+    ///
+    ///A connection is always closed after the current response if the
+    ///client sends `Connection: close`, or if it's an HTTP/1.0 client that
+    ///doesn't send `Connection: keep-alive`. The response has `Connection:
+    ///close` in those cases, even if the handler sets the header to
+    ///something else.
     pub keep_alive: Option<KeepAlive>,
 
+    ///How long to wait before trying again, when accepting a connection fails
+    ///because the server is out of resources. See `AcceptBackoff` for the
+    ///details and the defaults.
+    pub accept_backoff: AcceptBackoff,
+
+    ///How request paths should be normalized before they are routed.
+    ///Default is `PathNormalization::Off`.
+    pub path_normalization: PathNormalization,
+
+    ///Log a warning for requests that take longer than this to handle. The
+    ///time is counted from when the request head has been parsed, and it
+    ///includes the filters, the handler, reading the request body and
+    ///sending the response. The warning includes the method, the request
+    ///target, the client address, the duration and the response status.
+    ///Default is `None`, which disables the warning.
+    pub slow_request_threshold: Option<Duration>,
+
+    ///How long a request is expected to take, at most. This is advisory, so
+    ///nothing is cancelled when it runs out, but it's available as a deadline
+    ///through `Context::deadline` and `Context::time_remaining`, for handlers
+    ///that can skip optional work when they are short on time. Default is
+    ///`None`, for no deadline.
+    pub request_timeout: Option<Duration>,
+
+    ///The largest request body that will be accepted, in bytes. Requests
+    ///with a larger `Content-Length` are rejected with `413 Payload Too
+    ///Large`, before they reach any handler. Clients that send `Expect:
+    ///100-continue` are rejected before they send the body. Chunked bodies
+    ///have no announced length and are not checked. Default is `None`, for
+    ///no limit.
+    pub max_body_length: Option<u64>,
+
+    ///A limit on how many requests with large bodies can be handled at the
+    ///same time. See `LargeBodyLimit` for the details. Default is `None`, for
+    ///no limit.
+    pub large_body_limit: Option<LargeBodyLimit>,
+
+    ///Drain request bodies of at most this many bytes, if the handler
+    ///leaves them unread, instead of closing the connection. The connection
+    ///is otherwise closed after the response, since the next request can't
+    ///be found before the body has been read. Only bodies with a
+    ///`Content-Length` are drained, since the length of a chunked body is
+    ///unknown.
+    ///
+    ///The body is drained after the handler is done with the request, so
+    ///the connection is kept open if the body was expected to be drained,
+    ///and it's shut down if that fails. A handler can still make the
+    ///connection close, without draining the body, by setting the
+    ///`Connection: close` header in its response. Default is `None`, which
+    ///never drains bodies.
+    pub auto_drain_limit: Option<u64>,
+
+    ///What to do with request bodies on methods where they have no defined
+    ///meaning. A request is only affected if it has a `Transfer-Encoding`,
+    ///or a `Content-Length` above 0, and its method is in the list. See
+    ///`UnexpectedBody` for the alternatives. Default is to drain the bodies
+    ///of `GET`, `HEAD`, `DELETE` and `TRACE` requests, since that's the most
+    ///lenient option that still keeps the connection open.
+    pub unexpected_bodies: Vec<(Method, UnexpectedBody)>,
+
+    ///A hook that decides if requests with `Expect: 100-continue` may send
+    ///their bodies, or `None` to accept every request that is within
+    ///`max_body_length`. See `ContinuePolicy` for the details. Default is
+    ///`None`.
+    pub continue_policy: Option<Box<ContinuePolicy>>,
+
+    ///The largest chunk that a chunked request body may have, in bytes.
+    ///The size of each chunk is checked before any of it is read, and the
+    ///request is rejected with `400 Bad Request` if it's too large and the
+    ///handler hasn't been called yet. Reading the body fails with an
+    ///`InvalidData` IO error otherwise. This complements `max_body_length`,
+    ///which can't be checked for chunked bodies. Default is 8 MiB.
+    pub max_chunk_size: u64,
+
+    ///The longest chunk extension that a chunked request body may have, in
+    ///bytes, counting everything between the chunk size and the line break.
+    ///Extensions are ignored, so they are only a waste of time when they are
+    ///long. It's checked just like `max_chunk_size`. Default is 1 KiB.
+    pub max_chunk_extension_length: usize,
+
+    ///The longest time to wait for more of the request body, while it's
+    ///being read. Reading the body fails with a `TimedOut` IO error if
+    ///nothing arrives within this time, and the connection is closed after
+    ///the response, since the rest of the body can't be found. Bodies that
+    ///keep arriving, however slowly, are not affected. Default is `None`, for
+    ///no timeout.
+    pub body_read_timeout: Option<Duration>,
+
+    ///The longest time it may take to receive the whole request body,
+    ///counted from when the request was received. Reading the body fails
+    ///with a `TimedOut` IO error after this time, just like with
+    ///`body_read_timeout`, but it's only checked between reads, so it should
+    ///be combined with `body_read_timeout` to also stop clients that send
+    ///nothing. This limits large uploads too, so it should be generous.
+    ///Default is `None`, for no deadline.
+    pub body_read_deadline: Option<Duration>,
+
+    ///The longest time a write to the connection may be blocked, because the
+    ///client is not reading the response. The response is written through
+    ///buffers of a limited size, like `stream_buffer_size`, so a client that
+    ///reads slowly makes the handler wait instead of making the buffers
+    ///grow. Writing fails with a `TimedOut` or `WouldBlock` IO error when
+    ///this time runs out, which aborts the response, as with
+    ///`Response::abort`. The connection is then closed and the request is
+    ///logged as a warning, so a worker thread can't be held by a client that
+    ///stopped reading. Default is `None`, for no timeout.
+    pub write_timeout: Option<Duration>,
+
+    ///The largest number of header fields that will be accepted in a
+    ///request, where each line counts, even if the name is repeated. Requests
+    ///with more fields are rejected with `431 Request Header Fields Too
+    ///Large`, before they reach any handler. Hyper's parser has a fixed limit
+    ///of 100 fields, and it closes the connection without a response if
+    ///there are more, so this can only be used to lower the limit. Default is
+    ///100.
+    pub max_headers: usize,
+
+    ///The longest request target, meaning the path and the query, that will
+    ///be accepted, in bytes. Requests with longer targets are rejected with
+    ///`414 URI Too Long`, before the target is parsed or routed. The whole
+    ///request head is also limited by Hyper, but that limit is much higher,
+    ///so this is what puts a bound on the work that goes into matching
+    ///routes. Default is 8 KiB.
+    pub max_target_length: usize,
+
+    ///The largest number of parameters that will be parsed from a query, or
+    ///fields from a form body. Requests with more query parameters are
+    ///rejected with `400 Bad Request`, before the query is parsed, and
+    ///`BodyReader::read_query_body` and `BodyReader::read_form_data` fail
+    ///with `BodyError::TooManyFields` for larger forms. This keeps requests
+    ///with huge numbers of tiny parameters, like `a&a&a&...`, from using up
+    ///memory and time. Empty pairs, as in `a=1&&b=2`, are not counted.
+    ///Default is 1000.
+    pub max_parameters: usize,
+
+    ///The size of the buffer that is used when a response body is copied
+    ///from a reader or a file, in bytes. It can be changed for individual
+    ///responses, using `Response::set_stream_buffer_size`, which also
+    ///describes the tradeoffs. Default is 8 KiB.
+    pub stream_buffer_size: usize,
+
+    ///The largest body that a chunked response will hold back and send with
+    ///a `Content-Length` instead, in bytes. This lets small responses from
+    ///`Response::into_chunked` keep the connection alive without chunked
+    ///transfer encoding. Responses that grow larger, or that are flushed,
+    ///are streamed as usual. It can be changed for individual responses,
+    ///using `Response::set_auto_content_length`, which describes the details.
+    ///A limit of 0 turns it off. Default is 4 KiB.
+    pub auto_content_length: usize,
+
     ///The content of the server header. Default is `"rustful"`.
     pub server: String,
 
     ///The default media type. Default is `text/plain, charset: UTF-8`.
     pub content_type: Mime,
 
+    ///Headers that are added to every response, including error responses,
+    ///such as `X-App-Version` or `Content-Security-Policy`. They are set
+    ///before the handler is called, so the handler and the response filters
+    ///can replace or remove them. They take precedence over `server` and
+    ///`content_type`.
+    ///
+    ///`Date`, `Content-Length`, `Transfer-Encoding` and `Connection` are
+    ///managed by the server and are ignored if they are included. Default is
+    ///no headers.
+    pub default_headers: Headers,
+
+    ///Add the request headers that were consulted by the negotiation helpers
+    ///of `Context`, such as `preferred_content_type`, `preferred_language`
+    ///and `accepts_encoding`, to the `Vary` header of the response, so that
+    ///caches can tell the variants apart. They are merged with any `Vary`
+    ///header that is already in the response, without duplicates, and
+    ///nothing is added if it's `Vary: *`.
+    ///
+    ///Set this to `false` to manage `Vary` manually, for example if the
+    ///result of the negotiation is not used for the response. Default is
+    ///`true`.
+    pub auto_vary: bool,
+
     ///Globally accessible data.
     pub global: Global,
 
@@ -79,7 +428,103 @@ pub struct Server<R: Router> {
     pub context_filters: Vec<Box<ContextFilter>>,
 
     ///The response filter stack.
-    pub response_filters: Vec<Box<ResponseFilter>>
+    pub response_filters: Vec<Box<ResponseFilter>>,
+
+    ///The observers that will be notified when a request has been handled.
+    pub completion_observers: Vec<Box<CompletionObserver>>,
+
+    ///A hook that rewrites the request path before it's routed, or `None`
+    ///to leave it as it is. See `PathRewrite` for the details. Default is
+    ///`None`.
+    pub path_rewrite: Option<Box<PathRewrite>>,
+
+    ///A sink for request metrics, or `None` to not collect any. See
+    ///`Metrics` for when it's called. Default is `None`.
+    pub metrics: Option<Box<Metrics>>,
+
+    ///Copy up to this many bytes of each response body into
+    ///`CompletionInfo::response_body`, for audit logs and similar, or `None`
+    ///to not capture anything. The body is still sent as it is, and the copy
+    ///stops growing at the limit, so large and streamed bodies are not kept
+    ///in memory. Each captured response costs an allocation that grows up to
+    ///the limit, and an extra copy of the captured bytes, so the limit should
+    ///be kept small. Default is `None`.
+    pub capture_response_body: Option<usize>,
+
+    ///Headers that should be redacted in the copies that are given to the
+    ///completion observers, since they may end up in logs. Their values are
+    ///replaced with `[REDACTED]`, but the actual request and response are
+    ///not affected. The names are case insensitive. Rustful never logs any
+    ///header values by itself. Default is `Authorization`, `Cookie`,
+    ///`Set-Cookie` and `Proxy-Authorization`.
+    pub redacted_headers: Vec<String>,
+
+    ///A handle to the lifecycle state of the server. Keep a clone of it to
+    ///begin shutting down, or to check the state from outside the handlers.
+    ///Default is a new handle in the `Accepting` state.
+    pub lifecycle: Lifecycle,
+
+    ///A hook that is called once by `run`, after the server has bound its
+    ///socket, but before it starts accepting connections. This is a good
+    ///place to open resources that the handlers depend on, like database
+    ///pools. It's not called if the socket can't be bound, or if the server
+    ///is never started, like when only `serve_connection` is used. Default
+    ///is `None`.
+    pub on_start: Option<Box<Fn() + Send + Sync>>,
+
+    ///A hook that is called once when a server that was started by `run` has
+    ///drained its requests after `lifecycle.begin_shutdown()`, which is when
+    ///the state changes to `Stopped`. It's called on the thread that
+    ///finished the last request, or on the thread that began the shutdown if
+    ///there were no requests in progress, so it runs the same way if the
+    ///shutdown was triggered by a signal handler. The accept loop is still
+    ///running at that point, but any new requests are answered while
+    ///`Stopped`, with their connections closed. Default is `None`.
+    pub on_shutdown: Option<Box<Fn() + Send + Sync>>,
+
+    ///The addresses of the reverse proxies that are trusted to report the
+    ///scheme of the original request, using `X-Forwarded-Proto`. Default is
+    ///an empty list, so the header is always ignored.
+    ///
+    ///A request is treated as secure, through `Context::is_secure`, if it
+    ///was received over TLS, or if it comes directly from one of these
+    ///addresses and the last value of its `X-Forwarded-Proto` header is
+    ///`https`. The last value is the one that was added by the trusted
+    ///proxy, while anything before it may come from the client.
+    ///
+    ///The header is ignored for any other peer, since anyone can send it.
+    ///Only the direct peer is checked, so the proxies have to be the ones
+    ///that connect to the server, and they have to set or overwrite the
+    ///header for every request. Otherwise, a client that manages to connect
+    ///through a trusted address, without passing the proxy's own checks,
+    ///would be able to claim that it used HTTPS.
+    pub trusted_proxies: Vec<IpAddr>,
+
+    ///The encoding of query and form values that are not valid UTF-8, for
+    ///clients that send legacy forms. They are decoded into `Utf8` values,
+    ///instead of being left as `NotUtf8`, which is what happens if this is
+    ///`None`. A `charset` parameter in the `Content-Type` of a form body
+    ///takes precedence, and it can be changed for individual routes with
+    ///`filter::FallbackCharset`. See `Charset` for the supported encodings.
+    ///Default is `None`.
+    #[cfg(feature = "encoding")]
+    pub fallback_charset: Option<Charset>,
+
+    ///Session settings, or `None` to disable sessions. Default is `None`.
+    ///The server will panic when it's built if sessions are enabled without
+    ///a signing key.
+    pub sessions: Option<SessionConfig>,
+
+    ///The keys for signed and private cookies, or `None` to disable them.
+    ///See `CookieKeys` for the details. Default is `None`. The current key
+    ///can't be empty.
+    pub cookie_keys: Option<CookieKeys>,
+
+    ///The deserializers that are used by `Context::read_typed`, keyed by
+    ///content type. Default is `BodyDeserializers::new()`, which has
+    ///deserializers for JSON and `application/x-www-form-urlencoded`.
+    #[cfg(feature = "rustc_json_body")]
+    pub body_deserializers: BodyDeserializers
 }
 
 impl<R: Router> Server<R> {
@@ -104,20 +549,76 @@ impl<R: Router> Server<R> {
             fallback_handler: None,
             host: 80.into(),
             scheme: Scheme::Http,
+            ipv6_only: None,
+            tcp_nodelay: true,
+            linger: None,
+            thread_name_prefix: "rustful-worker".to_owned(),
             threads: None,
             keep_alive: None,
+            accept_backoff: AcceptBackoff::default(),
+            path_normalization: PathNormalization::Off,
+            slow_request_threshold: None,
+            request_timeout: None,
+            max_body_length: None,
+            large_body_limit: None,
+            auto_drain_limit: None,
+            max_chunk_size: 8 * 1024 * 1024,
+            max_chunk_extension_length: 1024,
+            unexpected_bodies: vec![
+                (Method::Get, UnexpectedBody::Drain),
+                (Method::Head, UnexpectedBody::Drain),
+                (Method::Delete, UnexpectedBody::Drain),
+                (Method::Trace, UnexpectedBody::Drain),
+            ],
+            continue_policy: None,
+            body_read_timeout: None,
+            body_read_deadline: None,
+            write_timeout: None,
+            max_headers: 100,
+            max_target_length: 8 * 1024,
+            max_parameters: 1000,
+            stream_buffer_size: 8 * 1024,
+            auto_content_length: 4 * 1024,
             server: "rustful".to_owned(),
             content_type: Mime(
                 hyper::mime::TopLevel::Text,
                 hyper::mime::SubLevel::Html,
                 vec![(hyper::mime::Attr::Charset, hyper::mime::Value::Utf8)]
             ),
+            default_headers: Headers::new(),
+            auto_vary: true,
             global: Global::default(),
             context_filters: Vec::new(),
             response_filters: Vec::new(),
+            completion_observers: Vec::new(),
+            path_rewrite: None,
+            metrics: None,
+            redacted_headers: vec![
+                "Authorization".to_owned(),
+                "Cookie".to_owned(),
+                "Set-Cookie".to_owned(),
+                "Proxy-Authorization".to_owned(),
+            ],
+            capture_response_body: None,
+            lifecycle: Lifecycle::new(),
+            on_start: None,
+            on_shutdown: None,
+            trusted_proxies: vec![],
+            #[cfg(feature = "encoding")]
+            fallback_charset: None,
+            sessions: None,
+            cookie_keys: None,
+            #[cfg(feature = "rustc_json_body")]
+            body_deserializers: BodyDeserializers::new(),
         }
     }
 
+    ///Start building a server with chainable setters, as an alternative to
+    ///the struct fields. See `ServerBuilder` for more details.
+    pub fn builder(handlers: R) -> ServerBuilder<R> {
+        ServerBuilder::new(handlers)
+    }
+
     ///Start the server.
     pub fn run(self) -> HttpResult<Listening> {
         let (server, scheme) = self.build();
@@ -0,0 +1,61 @@
+use anymap::AnyMap;
+
+use context::MaybeUtf8Owned;
+
+///A hook that can rewrite the request path before it's routed.
+///
+///The path is given after it has been percent decoded and normalized, but
+///before the context filters and the router see it. Whatever is returned is
+///used as the path from then on, so return it as it is to leave it
+///unchanged. The result has to start with `/`, and anything else, including
+///an empty path, results in `400 Bad Request`. The original path is still
+///reported in `CompletionInfo`.
+///
+///Extracted data can be put in the filter storage, where the context
+///filters, the response filters and the handler can find it, using
+///`FilterContext::storage` and `Response::filter_storage`.
+///
+///```
+///# extern crate rustful;
+///# extern crate anymap;
+///use rustful::{Server, Context, Response};
+///use rustful::context::MaybeUtf8Owned;
+///use anymap::AnyMap;
+///
+///struct Locale(&'static str);
+///
+///fn strip_locale(path: MaybeUtf8Owned, storage: &mut AnyMap) -> MaybeUtf8Owned {
+///    for &locale in &["en", "sv"] {
+///        let prefix = format!("/{}", locale);
+///        let bytes = path.as_bytes();
+///        if bytes.starts_with(prefix.as_bytes()) && (bytes.len() == prefix.len() || bytes[prefix.len()] == b'/') {
+///            storage.insert(Locale(locale));
+///            let rest = MaybeUtf8Owned::from_bytes(bytes[prefix.len()..].to_vec());
+///            return if rest.as_bytes().is_empty() { "/".into() } else { rest };
+///        }
+///    }
+///    path
+///}
+///
+///fn hello(_context: Context, response: Response) {
+///    let locale = response.filter_storage().get::<Locale>().map_or("en", |locale| locale.0);
+///    response.send(format!("hello in {}", locale));
+///}
+///
+///# fn main() {
+///let server = Server {
+///    path_rewrite: Some(Box::new(strip_locale)),
+///    ..Server::new(hello as fn(Context, Response))
+///};
+///# }
+///```
+pub trait PathRewrite: Send + Sync + 'static {
+    ///Rewrite the path of a request.
+    fn rewrite(&self, path: MaybeUtf8Owned, storage: &mut AnyMap) -> MaybeUtf8Owned;
+}
+
+impl<F: Fn(MaybeUtf8Owned, &mut AnyMap) -> MaybeUtf8Owned + Send + Sync + 'static> PathRewrite for F {
+    fn rewrite(&self, path: MaybeUtf8Owned, storage: &mut AnyMap) -> MaybeUtf8Owned {
+        self(path, storage)
+    }
+}
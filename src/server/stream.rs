@@ -0,0 +1,80 @@
+//A network stream that wraps any `Read + Write` stream, for serving
+//connections that don't come from one of Hyper's listeners.
+
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use hyper::net::NetworkStream;
+
+//The request is read from one handle while the response is written to
+//another, so the stream is shared between them.
+pub struct SharedStream<S> {
+    stream: Arc<Mutex<S>>,
+    peer_addr: SocketAddr,
+}
+
+impl<S> SharedStream<S> {
+    pub fn new(stream: S, peer_addr: SocketAddr) -> SharedStream<S> {
+        SharedStream {
+            stream: Arc::new(Mutex::new(stream)),
+            peer_addr: peer_addr,
+        }
+    }
+
+    //Get the stream back. `None` is returned if there are other handles.
+    pub fn into_inner(self) -> Option<S> {
+        Arc::try_unwrap(self.stream).ok().map(|stream| match stream.into_inner() {
+            Ok(stream) => stream,
+            Err(poisoned) => poisoned.into_inner()
+        })
+    }
+
+    fn lock<'s>(&'s self) -> MutexGuard<'s, S> {
+        match self.stream.lock() {
+            Ok(stream) => stream,
+            Err(poisoned) => poisoned.into_inner()
+        }
+    }
+}
+
+impl<S> Clone for SharedStream<S> {
+    fn clone(&self) -> SharedStream<S> {
+        SharedStream {
+            stream: self.stream.clone(),
+            peer_addr: self.peer_addr,
+        }
+    }
+}
+
+impl<S: Read> Read for SharedStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.lock().read(buf)
+    }
+}
+
+impl<S: Write> Write for SharedStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lock().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock().flush()
+    }
+}
+
+impl<S: Read + Write + Send + 'static> NetworkStream for SharedStream<S> {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        Ok(self.peer_addr)
+    }
+
+    //Timeouts are up to the stream itself.
+    fn set_read_timeout(&self, _dur: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _dur: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,389 @@
+use std::io;
+
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce, KeyInit};
+use chacha20poly1305::aead::{Aead, Payload};
+
+use header::{Headers, Cookie, SetCookie, CookiePair};
+
+use utils::{constant_time_eq, random_bytes};
+
+use super::{sign, to_hex};
+
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+
+//The keys that are derived from one secret key.
+#[derive(Clone)]
+struct KeySet {
+    signing: [u8; 32],
+    encryption: [u8; 32],
+}
+
+impl KeySet {
+    fn derive(key: &[u8]) -> KeySet {
+        KeySet {
+            signing: sign(key, b"rustful signed cookies"),
+            encryption: sign(key, b"rustful private cookies encryption"),
+        }
+    }
+
+    fn signature(&self, name: &str, value: &str) -> [u8; 32] {
+        let mut message = Vec::with_capacity(name.len() + value.len() + 1);
+        message.extend_from_slice(name.as_bytes());
+        message.push(b'=');
+        message.extend_from_slice(value.as_bytes());
+        sign(&self.signing, &message)
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&self.encryption))
+    }
+}
+
+///Secret keys for signed and private cookies.
+///
+///The keys for signing and encryption are derived from a secret key, which
+///should be long and random, preferably at least 32 bytes. New cookies are
+///always signed or encrypted with the current key, while cookies from the
+///client can also be verified with old keys. This makes it possible to
+///rotate the key without invalidating the cookies that were set with the
+///previous one. Old keys should be removed when the cookies that were set
+///with them have expired.
+///
+///```
+///use rustful::session::CookieKeys;
+///
+///let keys = CookieKeys::new(b"the new and very secret key".to_vec())
+///    .old_key(b"the previous secret key".to_vec());
+///```
+#[derive(Clone)]
+pub struct CookieKeys {
+    keys: Vec<KeySet>,
+    missing_key: bool,
+}
+
+impl CookieKeys {
+    ///Use `key` as the current secret key.
+    pub fn new<K: Into<Vec<u8>>>(key: K) -> CookieKeys {
+        let key = key.into();
+        CookieKeys {
+            keys: vec![KeySet::derive(&key)],
+            missing_key: key.is_empty(),
+        }
+    }
+
+    ///Accept cookies that were signed or encrypted with an old secret key.
+    ///The old keys are tried in the order they were added, after the
+    ///current key.
+    pub fn old_key<K: Into<Vec<u8>>>(mut self, key: K) -> CookieKeys {
+        self.keys.push(KeySet::derive(&key.into()));
+        self
+    }
+
+    ///Read and write signed cookies, using the request cookies in
+    ///`headers`.
+    pub fn signed<'a>(&'a self, headers: &'a Headers) -> SignedCookies<'a> {
+        SignedCookies {
+            keys: self,
+            headers: headers,
+        }
+    }
+
+    ///Read and write private cookies, using the request cookies in
+    ///`headers`.
+    pub fn private<'a>(&'a self, headers: &'a Headers) -> PrivateCookies<'a> {
+        PrivateCookies {
+            keys: self,
+            headers: headers,
+        }
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn is_missing_key(&self) -> bool {
+        self.missing_key
+    }
+
+    fn current(&self) -> &KeySet {
+        &self.keys[0]
+    }
+}
+
+///Signed cookies, which can be read by the client, but not changed.
+///
+///The value of each cookie is sent together with an HMAC-SHA256 signature
+///of its name and value, so a changed value, or a value that is moved to a
+///cookie with a different name, is detected and rejected.
+///
+///```
+///use rustful::{Context, Response};
+///use rustful::header::CookiePair;
+///
+///fn flash(context: Context, mut response: Response) {
+///    let cookies = context.signed_cookies().expect("the cookie keys are set");
+///    let message = cookies.get("flash").unwrap_or_else(|| "no message".to_owned());
+///    cookies.set(response.headers_mut(), CookiePair::new("flash".to_owned(), "saved".to_owned()));
+///    response.send(message);
+///}
+///```
+pub struct SignedCookies<'a> {
+    keys: &'a CookieKeys,
+    headers: &'a Headers,
+}
+
+impl<'a> SignedCookies<'a> {
+    ///Get the value of the cookie `name`, if it exists and its signature is
+    ///valid. The first valid cookie is used if there are more than one.
+    pub fn get(&self, name: &str) -> Option<String> {
+        request_cookies(self.headers, name).filter_map(|value| self.verify(name, value)).next()
+    }
+
+    ///Sign `cookie` and add it to the `Set-Cookie` header in `headers`. The
+    ///value should be valid as a cookie value, since it's sent as it is,
+    ///together with the signature.
+    pub fn set(&self, headers: &mut Headers, mut cookie: CookiePair) {
+        let signature = self.keys.current().signature(&cookie.name, &cookie.value);
+        cookie.value = format!("{}.{}", cookie.value, to_hex(&signature));
+        add_cookie(headers, cookie);
+    }
+
+    fn verify(&self, name: &str, signed: &str) -> Option<String> {
+        let mut parts = signed.rsplitn(2, '.');
+        let (signature, value) = match (parts.next().and_then(from_hex), parts.next()) {
+            (Some(signature), Some(value)) => (signature, value),
+            _ => return None
+        };
+
+        if self.keys.keys.iter().any(|keys| constant_time_eq(&keys.signature(name, value), &signature)) {
+            Some(value.to_owned())
+        } else {
+            None
+        }
+    }
+}
+
+///Private cookies, which can't be read or changed by the client.
+///
+///The value of each cookie is encrypted and authenticated with
+///ChaCha20-Poly1305, using a random nonce, and with the name of the cookie
+///as associated data. A changed value, or a value that is moved to a cookie
+///with a different name, is detected and rejected. The encrypted value is
+///sent as hexadecimal, so the original value may contain any characters.
+///
+///```
+///use rustful::{Context, Response, StatusCode};
+///use rustful::header::CookiePair;
+///
+///fn csrf(context: Context, mut response: Response) {
+///    let cookies = context.private_cookies().expect("the cookie keys are set");
+///    if cookies.get("csrf").is_none() {
+///        let cookie = CookiePair::new("csrf".to_owned(), "a random token".to_owned());
+///        if cookies.set(response.headers_mut(), cookie).is_err() {
+///            response.set_status(StatusCode::InternalServerError);
+///            return;
+///        }
+///    }
+///    response.send("a form");
+///}
+///```
+pub struct PrivateCookies<'a> {
+    keys: &'a CookieKeys,
+    headers: &'a Headers,
+}
+
+impl<'a> PrivateCookies<'a> {
+    ///Get the decrypted value of the cookie `name`, if it exists and it's
+    ///authentic. The first authentic cookie is used if there are more than
+    ///one.
+    pub fn get(&self, name: &str) -> Option<String> {
+        request_cookies(self.headers, name).filter_map(|value| self.decrypt(name, value)).next()
+    }
+
+    ///Encrypt `cookie` and add it to the `Set-Cookie` header in `headers`.
+    ///An error is returned if the operating system's random number
+    ///generator can't be accessed, since it's needed for the nonce.
+    pub fn set(&self, headers: &mut Headers, mut cookie: CookiePair) -> io::Result<()> {
+        let keys = self.keys.current();
+
+        let mut nonce = [0; NONCE_SIZE];
+        try!(random_bytes(&mut nonce));
+
+        let payload = Payload {
+            msg: cookie.value.as_bytes(),
+            aad: cookie.name.as_bytes(),
+        };
+        let ciphertext = keys.cipher().encrypt(Nonce::from_slice(&nonce), payload).expect("the cookie is too large to encrypt");
+
+        let mut data = nonce.to_vec();
+        data.extend_from_slice(&ciphertext);
+
+        cookie.value = to_hex(&data);
+        add_cookie(headers, cookie);
+        Ok(())
+    }
+
+    fn decrypt(&self, name: &str, value: &str) -> Option<String> {
+        let data = match from_hex(value) {
+            Some(data) => data,
+            None => return None
+        };
+
+        if data.len() < NONCE_SIZE + TAG_SIZE {
+            return None;
+        }
+
+        let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+        let plaintext = self.keys.keys.iter().filter_map(|keys| {
+            let payload = Payload {
+                msg: ciphertext,
+                aad: name.as_bytes(),
+            };
+            keys.cipher().decrypt(Nonce::from_slice(nonce), payload).ok()
+        }).next();
+
+        plaintext.and_then(|plaintext| String::from_utf8(plaintext).ok())
+    }
+}
+
+//The values of the request cookies with the name `name`.
+fn request_cookies<'a>(headers: &'a Headers, name: &'a str) -> Box<Iterator<Item=&'a str> + 'a> {
+    match headers.get::<Cookie>() {
+        Some(cookies) => Box::new(cookies.iter().filter(move |cookie| cookie.name == name).map(|cookie| &*cookie.value)),
+        None => Box::new(None.into_iter())
+    }
+}
+
+//Add `cookie` to `Set-Cookie`, and keep any other cookies that are set.
+fn add_cookie(headers: &mut Headers, cookie: CookiePair) {
+    if let Some(&mut SetCookie(ref mut cookies)) = headers.get_mut::<SetCookie>() {
+        cookies.retain(|existing| existing.name != cookie.name);
+        cookies.push(cookie);
+        return;
+    }
+
+    headers.set(SetCookie(vec![cookie]));
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    hex.as_bytes().chunks(2).map(|pair| {
+        ::std::str::from_utf8(pair).ok().and_then(|pair| u8::from_str_radix(pair, 16).ok())
+    }).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use header::{Headers, SetCookie, CookiePair};
+    use super::CookieKeys;
+
+    //Send the cookies from `response` back in a new request.
+    fn round_trip(response: &Headers) -> Headers {
+        let cookies = response.get::<SetCookie>().expect("no cookies were set");
+        let value = cookies.iter().map(|cookie| format!("{}={}", cookie.name, cookie.value)).collect::<Vec<_>>().join("; ");
+        request(&value)
+    }
+
+    fn request(cookies: &str) -> Headers {
+        let mut headers = Headers::new();
+        headers.set_raw("Cookie", vec![cookies.as_bytes().to_vec()]);
+        headers
+    }
+
+    fn set_cookie_value(headers: &Headers, name: &str) -> String {
+        let cookies = headers.get::<SetCookie>().expect("no cookies were set");
+        cookies.iter().find(|cookie| cookie.name == name).expect("the cookie was not set").value.clone()
+    }
+
+    #[test]
+    fn signed_cookies() {
+        let keys = CookieKeys::new(b"secret".to_vec());
+        let empty = Headers::new();
+        let mut response = Headers::new();
+        keys.signed(&empty).set(&mut response, CookiePair::new("flash".to_owned(), "saved.it".to_owned()));
+        keys.signed(&empty).set(&mut response, CookiePair::new("user".to_owned(), "alice".to_owned()));
+
+        let signed = set_cookie_value(&response, "flash");
+        assert!(signed.starts_with("saved.it."), "{}", signed);
+
+        let request = round_trip(&response);
+        assert_eq!(keys.signed(&request).get("flash"), Some("saved.it".to_owned()));
+        assert_eq!(keys.signed(&request).get("user"), Some("alice".to_owned()));
+        assert_eq!(keys.signed(&request).get("missing"), None);
+    }
+
+    #[test]
+    fn tampered_signed_cookies() {
+        let keys = CookieKeys::new(b"secret".to_vec());
+        let mut response = Headers::new();
+        keys.signed(&Headers::new()).set(&mut response, CookiePair::new("user".to_owned(), "alice".to_owned()));
+        let signature = set_cookie_value(&response, "user")["alice.".len()..].to_owned();
+
+        let changed = request(&format!("user=mallory.{}", signature));
+        assert_eq!(keys.signed(&changed).get("user"), None);
+
+        let moved = request(&format!("admin=alice.{}", signature));
+        assert_eq!(keys.signed(&moved).get("admin"), None);
+
+        let unsigned = request("user=alice");
+        assert_eq!(keys.signed(&unsigned).get("user"), None);
+
+        let other_key = CookieKeys::new(b"other secret".to_vec());
+        assert_eq!(other_key.signed(&round_trip(&response)).get("user"), None);
+    }
+
+    #[test]
+    fn private_cookies() {
+        let keys = CookieKeys::new(b"secret".to_vec());
+        let mut response = Headers::new();
+        keys.private(&Headers::new()).set(&mut response, CookiePair::new("csrf".to_owned(), "a token; with = symbols".to_owned())).unwrap();
+
+        let encrypted = set_cookie_value(&response, "csrf");
+        assert!(!encrypted.contains("token"), "{}", encrypted);
+
+        let request = round_trip(&response);
+        assert_eq!(keys.private(&request).get("csrf"), Some("a token; with = symbols".to_owned()));
+        assert_eq!(keys.signed(&request).get("csrf"), None);
+    }
+
+    #[test]
+    fn tampered_private_cookies() {
+        let keys = CookieKeys::new(b"secret".to_vec());
+        let mut response = Headers::new();
+        keys.private(&Headers::new()).set(&mut response, CookiePair::new("csrf".to_owned(), "token".to_owned())).unwrap();
+        let encrypted = set_cookie_value(&response, "csrf");
+
+        //Flip one bit in the encrypted value.
+        let mut bytes = encrypted.into_bytes();
+        bytes[30] = if bytes[30] == b'0' { b'1' } else { b'0' };
+        let changed = String::from_utf8(bytes).unwrap();
+        assert_eq!(keys.private(&request(&format!("csrf={}", changed))).get("csrf"), None);
+
+        let encrypted = set_cookie_value(&response, "csrf");
+        assert_eq!(keys.private(&request(&format!("other={}", encrypted))).get("other"), None);
+        assert_eq!(keys.private(&request("csrf=00ff")).get("csrf"), None);
+        assert_eq!(keys.private(&request("csrf=token")).get("csrf"), None);
+    }
+
+    #[test]
+    fn rotate_keys() {
+        let old = CookieKeys::new(b"old secret".to_vec());
+        let mut response = Headers::new();
+        old.signed(&Headers::new()).set(&mut response, CookiePair::new("user".to_owned(), "alice".to_owned()));
+        old.private(&Headers::new()).set(&mut response, CookiePair::new("csrf".to_owned(), "token".to_owned())).unwrap();
+        let request = round_trip(&response);
+
+        let new = CookieKeys::new(b"new secret".to_vec());
+        assert_eq!(new.signed(&request).get("user"), None);
+
+        let rotated = CookieKeys::new(b"new secret".to_vec()).old_key(b"old secret".to_vec());
+        assert_eq!(rotated.signed(&request).get("user"), Some("alice".to_owned()));
+        assert_eq!(rotated.private(&request).get("csrf"), Some("token".to_owned()));
+
+        let mut response = Headers::new();
+        rotated.signed(&Headers::new()).set(&mut response, CookiePair::new("user".to_owned(), "alice".to_owned()));
+        assert_eq!(new.signed(&round_trip(&response)).get("user"), Some("alice".to_owned()));
+    }
+}
@@ -0,0 +1,566 @@
+//!Server side sessions, identified by signed cookies, and signed or private
+//!cookies.
+//!
+//!Sessions are enabled by setting `Server::sessions` to a `SessionConfig`
+//!with a secret signing key. The session for each request is then available
+//!through `Context::session`, and any changes are persisted when the response
+//!headers are written.
+//!
+//!```no_run
+//!use rustful::{Server, Context, Response};
+//!use rustful::session::SessionConfig;
+//!
+//!fn count(context: Context, response: Response) {
+//!    let session = context.session().expect("sessions are enabled");
+//!    let visits = session.get("visits").and_then(|v| v.parse().ok()).unwrap_or(0u32) + 1;
+//!    session.insert("visits", visits.to_string());
+//!    response.send(format!("visit number {}", visits));
+//!}
+//!
+//!# fn main() {
+//!let server_result = Server {
+//!    sessions: Some(SessionConfig {
+//!        key: b"a long and very secret signing key".to_vec(),
+//!        ..SessionConfig::default()
+//!    }),
+//!    ..Server::new(count)
+//!}.run();
+//!# }
+//!```
+//!
+//!The session data is kept in a `SessionStore`. The default is a
+//!`MemoryStore`, which only lives as long as the server process, but any
+//!other backend can be plugged in by implementing `SessionStore` for it.
+//!
+//!#Signed and private cookies
+//!
+//!Small amounts of state, like flash messages and CSRF tokens, can be kept
+//!in the cookies themselves, if they are protected against changes. This is
+//!enabled by setting `Server::cookie_keys` to a set of `CookieKeys`, and the
+//!cookies are then available through `Context::signed_cookies` and
+//!`Context::private_cookies`:
+//!
+//! * `SignedCookies` can be read by the client, but any change to them is
+//!detected and they are then ignored.
+//! * `PrivateCookies` are also encrypted, so they can't be read by the
+//!client.
+//!
+//!The secret key can be replaced without invalidating the existing cookies,
+//!by keeping the previous key as an old key. See `CookieKeys` for more
+//!details.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use header::{Headers, Cookie, SetCookie, CookiePair};
+use utils::{constant_time_eq, random_bytes};
+
+pub use self::cookies::{CookieKeys, SignedCookies, PrivateCookies};
+
+mod cookies;
+
+///Session data, as it's kept in a `SessionStore`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SessionData {
+    ///The session values.
+    pub values: HashMap<String, String>,
+
+    ///When the session expires, or `None` if it doesn't.
+    pub expires: Option<SystemTime>
+}
+
+impl SessionData {
+    ///Check if the session has expired.
+    pub fn is_expired(&self) -> bool {
+        self.expires.map_or(false, |expires| expires <= SystemTime::now())
+    }
+}
+
+///A storage backend for session data.
+///
+///Expired sessions are ignored when they are loaded, so a store doesn't have
+///to check `SessionData::expires` itself, but it may use it to clean up old
+///sessions.
+pub trait SessionStore: Send + Sync + 'static {
+    ///Load the data for a session, if it exists.
+    fn load(&self, session_id: &str) -> Option<SessionData>;
+
+    ///Store the data for a session, replacing any previous data.
+    fn store(&self, session_id: &str, data: SessionData);
+
+    ///Remove a session.
+    fn remove(&self, session_id: &str);
+}
+
+///A session store that keeps the sessions in memory.
+///
+///The sessions are lost when the server stops, and they are not shared
+///between processes. Expired sessions are removed when new data is stored.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, SessionData>>
+}
+
+impl MemoryStore {
+    ///Create an empty store.
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    fn sessions<'a>(&'a self) -> MutexGuard<'a, HashMap<String, SessionData>> {
+        match self.sessions.lock() {
+            Ok(sessions) => sessions,
+            Err(poisoned) => poisoned.into_inner()
+        }
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, session_id: &str) -> Option<SessionData> {
+        self.sessions().get(session_id).cloned()
+    }
+
+    fn store(&self, session_id: &str, data: SessionData) {
+        let mut sessions = self.sessions();
+        sessions.retain(|_, data| !data.is_expired());
+        sessions.insert(session_id.to_owned(), data);
+    }
+
+    fn remove(&self, session_id: &str) {
+        self.sessions().remove(session_id);
+    }
+}
+
+///Session settings.
+pub struct SessionConfig {
+    ///The secret key that is used to sign the session cookies. It should be
+    ///long and random, preferably at least 32 bytes, and it has to be set
+    ///when sessions are enabled. The server will panic when it's built if
+    ///the key is empty.
+    pub key: Vec<u8>,
+
+    ///Where the session data is stored. Default is a `MemoryStore`.
+    pub store: Box<SessionStore>,
+
+    ///The name of the session cookie. Default is `"rustful_session"`.
+    pub cookie_name: String,
+
+    ///How long a session will last after it was last stored, or `None` to
+    ///make it last until the browser is closed. Default is 24 hours.
+    pub max_age: Option<Duration>,
+
+    ///Always mark the session cookie as `Secure`, so it's only sent over
+    ///HTTPS. The cookie is also marked as `Secure` if the request that sets
+    ///it is secure, according to `Context::is_secure`, regardless of this
+    ///setting. Default is `false`.
+    pub secure: bool
+}
+
+impl Default for SessionConfig {
+    fn default() -> SessionConfig {
+        SessionConfig {
+            key: vec![],
+            store: Box::new(MemoryStore::new()),
+            cookie_name: "rustful_session".to_owned(),
+            max_age: Some(Duration::from_secs(24 * 60 * 60)),
+            secure: false
+        }
+    }
+}
+
+#[doc(hidden)]
+///Internal and may change without warning.
+pub struct Sessions {
+    config: SessionConfig
+}
+
+impl Sessions {
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn new(config: SessionConfig) -> Arc<Sessions> {
+        if config.key.is_empty() {
+            panic!("sessions are enabled, but the session signing key is missing");
+        }
+
+        Arc::new(Sessions {
+            config: config
+        })
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn load(sessions: &Arc<Sessions>, headers: &Headers, secure: bool) -> Session {
+        let existing = headers.get::<Cookie>().and_then(|cookies| {
+            cookies.iter()
+                .filter(|cookie| cookie.name == sessions.config.cookie_name)
+                .filter_map(|cookie| sessions.verify(&cookie.value))
+                .filter_map(|id| sessions.config.store.load(id).map(|data| (id.to_owned(), data)))
+                .next()
+        });
+
+        let (id, values) = match existing {
+            Some((id, data)) => if data.is_expired() {
+                sessions.config.store.remove(&id);
+                (None, HashMap::new())
+            } else {
+                (Some(id), data.values)
+            },
+            None => (None, HashMap::new())
+        };
+
+        Session {
+            state: Arc::new(Mutex::new(SessionState {
+                id: id,
+                values: values,
+                modified: false,
+                rotate: false,
+                destroyed: false,
+                committed: false
+            })),
+            sessions: sessions.clone(),
+            secure: secure
+        }
+    }
+
+    fn sign(&self, id: &str) -> String {
+        format!("{}.{}", id, to_hex(&sign(&self.config.key, id.as_bytes())))
+    }
+
+    fn verify<'a>(&self, value: &'a str) -> Option<&'a str> {
+        let mut parts = value.splitn(2, '.');
+        match (parts.next(), parts.next()) {
+            (Some(id), Some(signature)) => {
+                let expected = to_hex(&sign(&self.config.key, id.as_bytes()));
+                if constant_time_eq(expected.as_bytes(), signature.as_bytes()) {
+                    Some(id)
+                } else {
+                    None
+                }
+            },
+            _ => None
+        }
+    }
+
+    fn cookie(&self, value: String, max_age: Option<u64>, secure: bool) -> CookiePair {
+        let mut cookie = CookiePair::new(self.config.cookie_name.clone(), value);
+        cookie.path = Some("/".to_owned());
+        cookie.httponly = true;
+        cookie.secure = self.config.secure || secure;
+        cookie.max_age = max_age;
+        cookie
+    }
+}
+
+struct SessionState {
+    id: Option<String>,
+    values: HashMap<String, String>,
+    modified: bool,
+    rotate: bool,
+    destroyed: bool,
+    committed: bool
+}
+
+///The session of a request.
+///
+///Changes are persisted when the response headers are written, so anything
+///that is changed after the response has started to be sent will be lost.
+///A new session is only stored, and its cookie only sent, if it contains
+///any values.
+#[derive(Clone)]
+pub struct Session {
+    state: Arc<Mutex<SessionState>>,
+    sessions: Arc<Sessions>,
+    secure: bool
+}
+
+impl Session {
+    fn state<'a>(&'a self) -> MutexGuard<'a, SessionState> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner()
+        }
+    }
+
+    ///Check if the session was created for this request, as opposed to
+    ///being loaded from the store.
+    pub fn is_new(&self) -> bool {
+        self.state().id.is_none()
+    }
+
+    ///Get a session value.
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.state().values.get(key).cloned()
+    }
+
+    ///Set a session value and return the previous value, if any.
+    pub fn insert<K: Into<String>, V: Into<String>>(&self, key: K, value: V) -> Option<String> {
+        let mut state = self.state();
+        state.modified = true;
+        state.values.insert(key.into(), value.into())
+    }
+
+    ///Remove a session value and return it, if it existed.
+    pub fn remove(&self, key: &str) -> Option<String> {
+        let mut state = self.state();
+        let value = state.values.remove(key);
+        state.modified |= value.is_some();
+        value
+    }
+
+    ///Get a copy of all the session values.
+    pub fn values(&self) -> HashMap<String, String> {
+        self.state().values.clone()
+    }
+
+    ///Give the session a new ID, while keeping its values. The old ID will
+    ///stop working. This should be done whenever the privileges of the
+    ///session changes, such as when a user logs in, to prevent session
+    ///fixation attacks.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn log_in(context: Context, response: Response) {
+    ///    //Check the credentials...
+    ///
+    ///    if let Some(session) = context.session() {
+    ///        session.rotate();
+    ///        session.insert("user", "alice");
+    ///    }
+    ///
+    ///    response.send("welcome!");
+    ///}
+    ///```
+    pub fn rotate(&self) {
+        let mut state = self.state();
+        state.rotate = true;
+        state.modified = true;
+    }
+
+    ///Remove all values and end the session. The session will be removed
+    ///from the store and its cookie will be cleared.
+    pub fn destroy(&self) {
+        let mut state = self.state();
+        state.values.clear();
+        state.destroyed = true;
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn commit(&self, headers: &mut Headers) {
+        let mut state = self.state();
+        if state.committed {
+            return;
+        }
+        state.committed = true;
+
+        let config = &self.sessions.config;
+
+        let cookie = if state.destroyed {
+            state.id.take().map(|id| {
+                config.store.remove(&id);
+                self.sessions.cookie(String::new(), Some(0), self.secure)
+            })
+        } else if state.modified && (state.id.is_some() || !state.values.is_empty()) {
+            if state.rotate {
+                if let Some(id) = state.id.take() {
+                    config.store.remove(&id);
+                }
+            }
+
+            let id = match state.id.clone() {
+                Some(id) => id,
+                None => match new_id() {
+                    Ok(id) => id,
+                    Err(e) => {
+                        error!("could not generate a session ID, so the session is not saved: {}", e);
+                        return;
+                    }
+                }
+            };
+
+            config.store.store(&id, SessionData {
+                values: state.values.clone(),
+                expires: config.max_age.map(|max_age| SystemTime::now() + max_age)
+            });
+
+            let cookie = self.sessions.cookie(self.sessions.sign(&id), config.max_age.map(|max_age| max_age.as_secs()), self.secure);
+            state.id = Some(id);
+            Some(cookie)
+        } else {
+            None
+        };
+
+        if let Some(cookie) = cookie {
+            if let Some(cookies) = headers.get_mut::<SetCookie>() {
+                cookies.push(cookie);
+                return;
+            }
+            headers.set(SetCookie(vec![cookie]));
+        }
+    }
+}
+
+fn new_id() -> io::Result<String> {
+    let mut id = [0; 16];
+    try!(random_bytes(&mut id));
+    Ok(to_hex(&id))
+}
+
+//The HMAC-SHA256 of `message`.
+fn sign(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().into()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    use header::{Headers, Cookie, SetCookie, CookiePair};
+    use super::{Sessions, SessionConfig, SessionStore, SessionData, MemoryStore, sign, to_hex};
+
+    fn sessions() -> Arc<Sessions> {
+        Sessions::new(SessionConfig {
+            key: b"secret".to_vec(),
+            ..SessionConfig::default()
+        })
+    }
+
+    fn request(cookie: Option<&str>) -> Headers {
+        let mut headers = Headers::new();
+        if let Some(cookie) = cookie {
+            headers.set(Cookie(vec![CookiePair::new("rustful_session".to_owned(), cookie.to_owned())]));
+        }
+        headers
+    }
+
+    fn response_cookie(headers: &Headers) -> Option<CookiePair> {
+        headers.get::<SetCookie>().and_then(|cookies| cookies.first().cloned())
+    }
+
+    #[test]
+    fn hmac_sha256() {
+        //Test case 2 from RFC 4231.
+        assert_eq!(
+            to_hex(&sign(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn new_session() {
+        let sessions = sessions();
+
+        let session = Sessions::load(&sessions, &request(None), false);
+        let mut headers = Headers::new();
+        session.commit(&mut headers);
+        assert!(response_cookie(&headers).is_none());
+
+        let session = Sessions::load(&sessions, &request(None), false);
+        assert!(session.is_new());
+        session.insert("user", "alice");
+        let mut headers = Headers::new();
+        session.commit(&mut headers);
+
+        let cookie = response_cookie(&headers).unwrap();
+        assert!(cookie.httponly);
+        assert!(!cookie.secure);
+        assert_eq!(cookie.max_age, Some(24 * 60 * 60));
+
+        let session = Sessions::load(&sessions, &request(Some(&cookie.value)), false);
+        assert!(!session.is_new());
+        assert_eq!(session.get("user"), Some("alice".to_owned()));
+    }
+
+    #[test]
+    fn secure_request() {
+        let session = Sessions::load(&sessions(), &request(None), true);
+        session.insert("user", "alice");
+        let mut headers = Headers::new();
+        session.commit(&mut headers);
+        assert!(response_cookie(&headers).unwrap().secure);
+    }
+
+    #[test]
+    fn tampered_cookie() {
+        let sessions = sessions();
+        let session = Sessions::load(&sessions, &request(None), false);
+        session.insert("user", "alice");
+        let mut headers = Headers::new();
+        session.commit(&mut headers);
+
+        let value = response_cookie(&headers).unwrap().value;
+        let (id, _) = value.split_at(value.find('.').unwrap());
+        let forged = format!("{}.{}", id, "00".repeat(32));
+
+        assert!(Sessions::load(&sessions, &request(Some(&forged)), false).is_new());
+        assert!(Sessions::load(&sessions, &request(Some(id)), false).is_new());
+
+        let other = Sessions::new(SessionConfig {
+            key: b"another secret".to_vec(),
+            ..SessionConfig::default()
+        });
+        assert!(Sessions::load(&other, &request(Some(&value)), false).is_new());
+    }
+
+    #[test]
+    fn rotate_and_destroy() {
+        let sessions = sessions();
+        let session = Sessions::load(&sessions, &request(None), false);
+        session.insert("user", "alice");
+        let mut headers = Headers::new();
+        session.commit(&mut headers);
+        let old = response_cookie(&headers).unwrap().value;
+
+        let session = Sessions::load(&sessions, &request(Some(&old)), false);
+        session.rotate();
+        let mut headers = Headers::new();
+        session.commit(&mut headers);
+        let new = response_cookie(&headers).unwrap().value;
+
+        assert!(old != new);
+        assert!(Sessions::load(&sessions, &request(Some(&old)), false).is_new());
+
+        let session = Sessions::load(&sessions, &request(Some(&new)), false);
+        assert_eq!(session.get("user"), Some("alice".to_owned()));
+        session.destroy();
+        let mut headers = Headers::new();
+        session.commit(&mut headers);
+        let cleared = response_cookie(&headers).unwrap();
+        assert_eq!(cleared.value, "");
+        assert_eq!(cleared.max_age, Some(0));
+
+        assert!(Sessions::load(&sessions, &request(Some(&new)), false).is_new());
+    }
+
+    #[test]
+    fn expired_session() {
+        let store = MemoryStore::new();
+        store.store("old", SessionData {
+            values: Default::default(),
+            expires: Some(SystemTime::now() - Duration::from_secs(1))
+        });
+        store.store("new", SessionData::default());
+
+        assert!(store.load("old").is_none());
+        assert!(store.load("new").is_some());
+    }
+
+    #[test]
+    #[should_panic]
+    fn missing_key() {
+        Sessions::new(SessionConfig::default());
+    }
+}
@@ -0,0 +1,172 @@
+//!Tools for testing handlers. This module requires the `testing` feature.
+//!
+//!`MultipartBuilder` builds `multipart/form-data` request bodies, with text
+//!fields and files, for testing upload handlers. Contexts can't be created
+//!outside of a running server, so the body is sent to one, through any HTTP
+//!client, together with the content type from the builder:
+//!
+//!```
+//!use std::io::{Read, Write};
+//!use std::net::TcpStream;
+//!use std::fs;
+//!use rustful::{Server, Context, Response};
+//!use rustful::context::upload::UploadSettings;
+//!use rustful::mime::{Mime, TopLevel, SubLevel};
+//!use rustful::testing::MultipartBuilder;
+//!
+//!fn upload(mut context: Context, response: Response) {
+//!    let upload = context.body.save_multipart(&UploadSettings::default()).unwrap();
+//!    let title = upload.fields.get("title").unwrap_or("".into()).into_owned();
+//!    let tags = upload.fields.get("tags").unwrap_or("".into()).into_owned();
+//!
+//!    let file = &upload.files[0];
+//!    let result = format!("{} [{}] {}: {} bytes", title, tags, file.filename.as_ref().unwrap(), file.size);
+//!    fs::remove_file(&file.path).unwrap();
+//!    response.send(result);
+//!}
+//!
+//!let (content_type, body) = MultipartBuilder::new()
+//!    .text("title", "Holiday")
+//!    .text("tags", "beach")
+//!    .file("photo", "beach.png", Mime(TopLevel::Image, SubLevel::Png, vec![]), &[0x89, b'P', b'N', b'G'])
+//!    .build();
+//!
+//!let mut listening = Server {
+//!    host: "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap().into(),
+//!    ..Server::new(upload as fn(Context, Response))
+//!}.run().unwrap();
+//!
+//!let mut client = TcpStream::connect(listening.socket).unwrap();
+//!write!(client, "POST /upload HTTP/1.1\r\nConnection: close\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n", content_type, body.len()).unwrap();
+//!client.write_all(&body).unwrap();
+//!
+//!let mut response = String::new();
+//!client.read_to_string(&mut response).unwrap();
+//!assert!(response.ends_with("\r\n\r\nHoliday [beach] beach.png: 4 bytes"), "{}", response);
+//!listening.close().unwrap();
+//!```
+
+use rand::{self, Rng};
+
+use mime::{Mime, TopLevel, SubLevel, Attr, Value};
+
+///A builder for `multipart/form-data` request bodies.
+///
+///The parts are added in order, and the body is finished with `build`.
+///Field names and filenames are quoted, with `"` and line breaks percent
+///encoded, the same way as browsers do it.
+///
+///See the [module level documentation](index.html) for an example.
+#[derive(Clone, Debug)]
+pub struct MultipartBuilder {
+    boundary: String,
+    body: Vec<u8>
+}
+
+impl MultipartBuilder {
+    ///Create an empty body with a random boundary.
+    pub fn new() -> MultipartBuilder {
+        let mut rng = rand::thread_rng();
+        MultipartBuilder::with_boundary(format!("{:016x}{:016x}", rng.gen::<u64>(), rng.gen::<u64>()))
+    }
+
+    ///Create an empty body with a fixed boundary. It's up to the caller to
+    ///make sure that it doesn't appear in any of the parts.
+    pub fn with_boundary<S: Into<String>>(boundary: S) -> MultipartBuilder {
+        MultipartBuilder {
+            boundary: boundary.into(),
+            body: vec![]
+        }
+    }
+
+    ///The boundary that separates the parts.
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    ///Add a text field.
+    pub fn text(mut self, name: &str, value: &str) -> MultipartBuilder {
+        self.begin_part(name, None, None);
+        self.body.extend_from_slice(value.as_bytes());
+        self.body.extend_from_slice(b"\r\n");
+        self
+    }
+
+    ///Add a file, with its original filename and its content type.
+    pub fn file(mut self, name: &str, filename: &str, content_type: Mime, content: &[u8]) -> MultipartBuilder {
+        self.begin_part(name, Some(filename), Some(content_type));
+        self.body.extend_from_slice(content);
+        self.body.extend_from_slice(b"\r\n");
+        self
+    }
+
+    ///The `multipart/form-data` content type, with the boundary.
+    pub fn content_type(&self) -> Mime {
+        Mime(
+            TopLevel::Multipart,
+            SubLevel::FormData,
+            vec![(Attr::Boundary, Value::Ext(self.boundary.clone()))]
+        )
+    }
+
+    ///Finish the body, and return it together with its content type.
+    pub fn build(mut self) -> (Mime, Vec<u8>) {
+        let content_type = self.content_type();
+        self.body.extend_from_slice(format!("--{}--\r\n", self.boundary).as_bytes());
+        (content_type, self.body)
+    }
+
+    fn begin_part(&mut self, name: &str, filename: Option<&str>, content_type: Option<Mime>) {
+        let mut head = format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"", self.boundary, escape(name));
+        if let Some(filename) = filename {
+            head.push_str(&format!("; filename=\"{}\"", escape(filename)));
+        }
+        head.push_str("\r\n");
+        if let Some(content_type) = content_type {
+            head.push_str(&format!("Content-Type: {}\r\n", content_type));
+        }
+        head.push_str("\r\n");
+        self.body.extend_from_slice(head.as_bytes());
+    }
+}
+
+impl Default for MultipartBuilder {
+    fn default() -> MultipartBuilder {
+        MultipartBuilder::new()
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('"', "%22").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+#[cfg(test)]
+mod test {
+    use mime::{Mime, TopLevel, SubLevel};
+    use super::MultipartBuilder;
+
+    #[test]
+    fn build_body() {
+        let (content_type, body) = MultipartBuilder::with_boundary("xyz")
+            .text("a", "1")
+            .text("say \"hi\"", "hello\r\nworld")
+            .file("f", "new\nfile.txt", Mime(TopLevel::Text, SubLevel::Plain, vec![]), b"content")
+            .build();
+
+        assert_eq!(content_type.to_string(), "multipart/form-data; boundary=xyz");
+        assert_eq!(String::from_utf8(body).unwrap(), concat!(
+            "--xyz\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n",
+            "--xyz\r\nContent-Disposition: form-data; name=\"say %22hi%22\"\r\n\r\nhello\r\nworld\r\n",
+            "--xyz\r\nContent-Disposition: form-data; name=\"f\"; filename=\"new%0Afile.txt\"\r\nContent-Type: text/plain\r\n\r\ncontent\r\n",
+            "--xyz--\r\n"
+        ));
+    }
+
+    #[test]
+    fn random_boundaries() {
+        let builder = MultipartBuilder::new();
+        assert_eq!(builder.boundary().len(), 32);
+        assert!(builder.boundary() != MultipartBuilder::new().boundary());
+        assert!(builder.content_type().to_string().ends_with(builder.boundary()));
+    }
+}
@@ -1,33 +1,890 @@
-use std::io::Write;
-use url::percent_encoding::percent_decode;
-use context::Parameters;
+//!General purpose utilities.
 
-pub fn parse_parameters(source: &[u8]) -> Parameters {
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::fmt;
+use std::error::Error;
+use std::cmp::{min, max};
+use std::time::{SystemTime, Duration, UNIX_EPOCH};
+use context::{Parameters, MaybeUtf8, MaybeUtf8Owned};
+#[cfg(feature = "encoding")]
+use context::FormData;
+#[cfg(feature = "encoding")]
+use encoding_rs::{self, Encoding};
+use header::{Headers, Range, ByteRangeSpec, SetCookie};
+use mime::{Mime, Attr};
+use subtle::ConstantTimeEq;
+use rand::{OsRng, Rng};
+
+#[doc(hidden)]
+///Internal and may change without warning.
+pub fn parse_parameters(source: &[u8]) -> Result<Parameters, PercentDecodeError> {
     let mut parameters = Parameters::new();
-    let source: Vec<u8> = source.iter()
-                                .map(|&e| if e == b'+' { b' ' } else { e })
-                                .collect();
 
-    for parameter in source.split(|&e| e == b'&') {
-        let mut parts = parameter.split(|&e| e == b'=');
+    for pair in parse_urlencoded(source) {
+        let (name, value) = try!(pair);
+        parameters.insert(name, value);
+    }
+
+    Ok(parameters)
+}
+
+#[doc(hidden)]
+///Internal and may change without warning.
+///
+///Count the non-empty pairs in an `application/x-www-form-urlencoded` byte
+///string, the same way as `parse_urlencoded` would find them.
+pub fn count_pairs(source: &[u8]) -> usize {
+    source.split(is_pair_separator).filter(|pair| !pair.is_empty()).count()
+}
+
+///Parse an `application/x-www-form-urlencoded` byte string, like a query
+///string or a form body, into name and value pairs.
+///
+///The pairs are separated by `&`, and the name and the value are separated
+///by `=`. A name without `=` gets an empty value, and anything after a
+///second `=` is ignored. Empty pairs are skipped, so an empty input results
+///in no pairs at all. Each `+` is decoded as a space, and percent escapes
+///are decoded as described for `percent_decode`. The names and values are
+///UTF-8 encoded if they are valid UTF-8, and they are otherwise left as raw
+///bytes.
+///
+///Malformed escape sequences are not decoded lossily. They result in an
+///error for that pair instead, where the position is counted from the start
+///of the name or the value, and the parsing can continue with the next
+///pair. This is how the query and form bodies are parsed, where the whole
+///input is rejected if any pair is malformed.
+///
+///```
+///use rustful::context::MaybeUtf8Owned;
+///use rustful::utils::{parse_urlencoded, PercentDecodeError};
+///
+///let mut pairs = parse_urlencoded(b"name=J%C3%B6rg+Smith&flag&&bad=%G0&raw=%FF");
+///assert_eq!(pairs.next(), Some(Ok(("name".into(), "Jörg Smith".into()))));
+///assert_eq!(pairs.next(), Some(Ok(("flag".into(), "".into()))));
+///assert_eq!(pairs.next(), Some(Err(PercentDecodeError::InvalidEscape(0))));
+///assert_eq!(pairs.next(), Some(Ok(("raw".into(), MaybeUtf8Owned::from(vec![255])))));
+///assert_eq!(pairs.next(), None);
+///
+///assert_eq!(parse_urlencoded(b"").next(), None);
+///```
+pub fn parse_urlencoded(source: &[u8]) -> UrlEncoded {
+    UrlEncoded {
+        pairs: source.split(is_pair_separator)
+    }
+}
+
+///An iterator over the pairs in an `application/x-www-form-urlencoded` byte
+///string. It's created by `parse_urlencoded`.
+pub struct UrlEncoded<'a> {
+    pairs: ::std::slice::Split<'a, u8, fn(&u8) -> bool>
+}
+
+impl<'a> Iterator for UrlEncoded<'a> {
+    type Item = Result<(MaybeUtf8Owned, MaybeUtf8Owned), PercentDecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pair = match self.pairs.by_ref().find(|pair| !pair.is_empty()) {
+            Some(pair) => pair,
+            None => return None
+        };
+
+        let mut parts = pair.split(|&b| b == b'=');
+        let name = parts.next().unwrap_or(&[]);
+        let value = parts.next().unwrap_or(&[]);
+
+        Some(decode_form_component(name).and_then(|name| {
+            decode_form_component(value).map(|value| (name, value))
+        }))
+    }
+}
+
+fn is_pair_separator(byte: &u8) -> bool {
+    *byte == b'&'
+}
+
+//Decoded in a single allocation, since there may be many of them.
+fn decode_form_component(source: &[u8]) -> Result<MaybeUtf8Owned, PercentDecodeError> {
+    decode_escapes(source, true).map(|bytes| bytes.into())
+}
+
+///Decode a percent encoded byte string.
+///
+///The decoding is done in a single pass, so only one layer of encoding is
+///removed. A double encoded sequence, like `%252e`, will therefore become
+///`%2e` and not `.`. Whether the result still contains something that looks
+///like an escape sequence can be checked with `still_encoded`, which is
+///useful for security sensitive consumers, such as file servers, that may
+///want to reject such paths.
+///
+///Malformed escape sequences, such as `%G0` or a truncated `%A`, are not
+///passed through. They will instead result in an error.
+///
+///```
+///use rustful::utils::{percent_decode, PercentDecodeError};
+///
+///let decoded = percent_decode(b"%2e%2e/file").unwrap();
+///assert_eq!(decoded.as_bytes(), b"../file");
+///assert!(!decoded.still_encoded());
+///
+///let double = percent_decode(b"%252e%252e/file").unwrap();
+///assert_eq!(double.as_bytes(), b"%2e%2e/file");
+///assert!(double.still_encoded());
+///
+///assert_eq!(percent_decode(b"a%G0").err(), Some(PercentDecodeError::InvalidEscape(1)));
+///assert_eq!(percent_decode(b"a%A").err(), Some(PercentDecodeError::Truncated(1)));
+///```
+pub fn percent_decode(source: &[u8]) -> Result<PercentDecoded, PercentDecodeError> {
+    let bytes = try!(decode_escapes(source, false));
+    let still_encoded = contains_escapes(&bytes);
+
+    Ok(PercentDecoded {
+        bytes: bytes,
+        still_encoded: still_encoded
+    })
+}
+
+//Decode percent escapes, and `+` as space if `plus_as_space` is set.
+fn decode_escapes(source: &[u8], plus_as_space: bool) -> Result<Vec<u8>, PercentDecodeError> {
+    let mut bytes = Vec::with_capacity(source.len());
+    let mut index = 0;
+
+    while index < source.len() {
+        let byte = source[index];
+        if byte == b'%' {
+            let high = source.get(index + 1).cloned();
+            let low = source.get(index + 2).cloned();
+            match (high.and_then(from_hex), low.and_then(from_hex)) {
+                (Some(high), Some(low)) => bytes.push(high * 0x10 + low),
+                _ => if low.is_none() && high.map_or(true, |h| from_hex(h).is_some()) {
+                    return Err(PercentDecodeError::Truncated(index));
+                } else {
+                    return Err(PercentDecodeError::InvalidEscape(index));
+                }
+            }
+            index += 3;
+        } else if plus_as_space && byte == b'+' {
+            bytes.push(b' ');
+            index += 1;
+        } else {
+            bytes.push(byte);
+            index += 1;
+        }
+    }
+
+    Ok(bytes)
+}
+
+///Check if a byte string contains any valid percent escape sequences.
+///
+///This can be used on already decoded data, like route variables, to detect
+///double encoding.
+///
+///```
+///use rustful::utils::contains_escapes;
+///
+///assert!(contains_escapes(b"%2e%2e"));
+///assert!(!contains_escapes(b"100%"));
+///```
+pub fn contains_escapes(source: &[u8]) -> bool {
+    source.windows(3).any(|w| w[0] == b'%' && from_hex(w[1]).is_some() && from_hex(w[2]).is_some())
+}
+
+///Escape `&`, `<`, `>`, `"` and `'` in `input`, to make it safe to embed as
+///text in an HTML element or in a quoted attribute value. The input is
+///borrowed if there is nothing to escape.
+///
+///This doesn't make the text safe in other contexts, like scripts, styles,
+///URLs or unquoted attributes, so it's not a substitute for a template
+///engine that knows where the text ends up.
+///
+///```
+///use rustful::utils::html_escape;
+///
+///assert_eq!(html_escape("<b>Tom & Jerry</b>"), "&lt;b&gt;Tom &amp; Jerry&lt;/b&gt;");
+///assert_eq!(html_escape("nothing to see"), "nothing to see");
+///```
+pub fn html_escape<'a>(input: &'a str) -> Cow<'a, str> {
+    let first = match input.find(|c| match c {
+        '&' | '<' | '>' | '"' | '\'' => true,
+        _ => false
+    }) {
+        Some(index) => index,
+        None => return Cow::Borrowed(input)
+    };
+
+    let mut escaped = String::with_capacity(input.len() + 16);
+    escaped.push_str(&input[..first]);
+    for c in input[first..].chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c)
+        }
+    }
+
+    Cow::Owned(escaped)
+}
+
+///Escape a `MaybeUtf8` value for HTML, the same way as `html_escape`. Any
+///invalid UTF-8 is replaced with `U+FFFD REPLACEMENT CHARACTER` first.
+///
+///```
+///use rustful::context::MaybeUtf8Owned;
+///use rustful::utils::html_escape_lossy;
+///
+///let value = MaybeUtf8Owned::from(b"\"a\" \xff".to_vec());
+///assert_eq!(html_escape_lossy(&value), "&quot;a&quot; \u{FFFD}");
+///```
+pub fn html_escape_lossy<'a, S: AsRef<str>, V: AsRef<[u8]>>(input: &'a MaybeUtf8<S, V>) -> Cow<'a, str> {
+    match input.as_utf8_lossy() {
+        Cow::Borrowed(text) => html_escape(text),
+        Cow::Owned(text) => Cow::Owned(html_escape(&text).into_owned())
+    }
+}
+
+fn from_hex(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None
+    }
+}
+
+///The result of a successful percent decoding.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PercentDecoded {
+    bytes: Vec<u8>,
+    still_encoded: bool
+}
+
+impl PercentDecoded {
+    ///Borrow the decoded bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    ///Take the decoded bytes.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    ///Check if the decoded bytes still contains percent escape sequences,
+    ///which is a sign of multiple layers of encoding.
+    pub fn still_encoded(&self) -> bool {
+        self.still_encoded
+    }
+}
+
+///An error from percent decoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PercentDecodeError {
+    ///A `%` was not followed by two hexadecimal digits. The position of the
+    ///`%` is included.
+    InvalidEscape(usize),
+
+    ///The input ended in the middle of an escape sequence. The position of
+    ///the `%` is included.
+    Truncated(usize)
+}
+
+impl fmt::Display for PercentDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PercentDecodeError::InvalidEscape(i) => write!(f, "invalid percent escape sequence at position {}", i),
+            PercentDecodeError::Truncated(i) => write!(f, "truncated percent escape sequence at position {}", i)
+        }
+    }
+}
+
+impl Error for PercentDecodeError {
+    fn description(&self) -> &str {
+        match *self {
+            PercentDecodeError::InvalidEscape(_) => "invalid percent escape sequence",
+            PercentDecodeError::Truncated(_) => "truncated percent escape sequence"
+        }
+    }
+}
+
+///How the `Range` header of a request applies to a resource.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ByteRange {
+    ///The whole resource should be sent, because the header is missing,
+    ///malformed, or asks for more than one range.
+    Full,
+
+    ///Only the bytes from the first position to the last position,
+    ///inclusive, should be sent.
+    Partial(u64, u64),
+
+    ///The range is outside of the resource.
+    Unsatisfiable
+}
+
+///Find the part of a resource, with the length `length` in bytes, that is
+///requested in the `Range` header.
+///
+///Multiple ranges are not supported, so the whole resource is selected
+///instead, which is allowed by the specification.
+///
+///```
+///use rustful::header::{Headers, Range};
+///use rustful::utils::{parse_range, ByteRange};
+///
+///let mut headers = Headers::new();
+///assert_eq!(parse_range(&headers, 100), ByteRange::Full);
+///
+///headers.set(Range::bytes(10, 1000));
+///assert_eq!(parse_range(&headers, 100), ByteRange::Partial(10, 99));
+///
+///headers.set(Range::bytes(100, 1000));
+///assert_eq!(parse_range(&headers, 100), ByteRange::Unsatisfiable);
+///```
+pub fn parse_range(headers: &Headers, length: u64) -> ByteRange {
+    let spec = match headers.get::<Range>() {
+        Some(&Range::Bytes(ref specs)) if specs.len() == 1 => specs[0].clone(),
+        _ => return ByteRange::Full
+    };
+
+    let last = match length.checked_sub(1) {
+        Some(last) => last,
+        None => return ByteRange::Unsatisfiable
+    };
+
+    match spec {
+        ByteRangeSpec::FromTo(from, to) if from > to => ByteRange::Full,
+        ByteRangeSpec::FromTo(from, _) | ByteRangeSpec::AllFrom(from) if from > last => ByteRange::Unsatisfiable,
+        ByteRangeSpec::FromTo(from, to) => ByteRange::Partial(from, ::std::cmp::min(to, last)),
+        ByteRangeSpec::AllFrom(from) => ByteRange::Partial(from, last),
+        ByteRangeSpec::Last(0) => ByteRange::Unsatisfiable,
+        ByteRangeSpec::Last(count) => ByteRange::Partial(length.saturating_sub(count), last)
+    }
+}
+
+///Parse a media type, like the value of a `Content-Type` header, including
+///its parameters.
+///
+///The type, subtype and parameter names are case insensitive, so they are
+///converted to lower case, as well as the value of a `charset` parameter.
+///Other parameter values are kept as they are. Quoted values may contain
+///separators and backslash escapes, which are removed. `None` is returned
+///if the media type is malformed.
+///
+///```
+///use rustful::mime::{TopLevel, SubLevel, Attr, Value};
+///use rustful::utils::parse_media_type;
+///
+///let mime = parse_media_type("Multipart/Form-Data; boundary=\"a; b\"").unwrap();
+///assert_eq!(mime.0, TopLevel::Multipart);
+///assert_eq!(mime.1, SubLevel::FormData);
+///assert_eq!(mime.get_param(Attr::Boundary), Some(&Value::Ext("a; b".into())));
+///
+///assert!(parse_media_type("text").is_none());
+///```
+pub fn parse_media_type(media_type: &str) -> Option<Mime> {
+    let (essence, mut rest) = match media_type.find(';') {
+        Some(index) => media_type.split_at(index),
+        None => (media_type, "")
+    };
+
+    let (top, sub) = match essence.find('/') {
+        Some(index) => (essence[..index].trim(), essence[index + 1..].trim()),
+        None => return None
+    };
+
+    if !is_token(top) || !is_token(sub) {
+        return None;
+    }
+
+    let top = match top.to_ascii_lowercase().parse() {
+        Ok(top) => top,
+        Err(_) => return None
+    };
+    let sub = match sub.to_ascii_lowercase().parse() {
+        Ok(sub) => sub,
+        Err(_) => return None
+    };
+
+    let mut params = vec![];
+
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        if !rest.starts_with(';') {
+            return None;
+        }
+        rest = rest[1..].trim_start();
+        if rest.is_empty() {
+            break;
+        }
+
+        let name_end = match rest.find('=') {
+            Some(index) => index,
+            None => return None
+        };
+        let name = rest[..name_end].trim().to_ascii_lowercase();
+        if !is_token(&name) {
+            return None;
+        }
+        rest = rest[name_end + 1..].trim_start();
+
+        let mut value = if rest.starts_with('"') {
+            let mut value = String::new();
+            let mut chars = rest[1..].char_indices();
+            let mut end = None;
+
+            while let Some((index, c)) = chars.next() {
+                match c {
+                    '"' => {
+                        end = Some(index + 2);
+                        break;
+                    },
+                    '\\' => match chars.next() {
+                        Some((_, c)) => value.push(c),
+                        None => break
+                    },
+                    c => value.push(c)
+                }
+            }
+
+            match end {
+                Some(end) => rest = &rest[end..],
+                None => return None
+            }
+            value
+        } else {
+            let end = rest.find(';').unwrap_or(rest.len());
+            let value = rest[..end].trim();
+            if !is_token(value) {
+                return None;
+            }
+            rest = &rest[end..];
+            value.to_owned()
+        };
+
+        let attr: Attr = match name.parse() {
+            Ok(attr) => attr,
+            Err(_) => return None
+        };
+        if attr == Attr::Charset {
+            value = value.to_ascii_lowercase();
+        }
+        let value = match value.parse() {
+            Ok(value) => value,
+            Err(_) => return None
+        };
+
+        params.push((attr, value));
+    }
+
+    Some(Mime(top, sub, params))
+}
+
+#[doc(hidden)]
+///Internal and may change without warning.
+pub fn parse_content_type(headers: &Headers) -> Option<Mime> {
+    headers.get_raw("Content-Type")
+        .and_then(|values| values.first())
+        .and_then(|value| ::std::str::from_utf8(value).ok())
+        .and_then(parse_media_type)
+}
+
+#[doc(hidden)]
+///Internal and may change without warning.
+pub fn is_conditional(headers: &Headers) -> bool {
+    headers.get_raw("If-None-Match").is_some() || headers.get_raw("If-Modified-Since").is_some()
+}
+
+//The characters of a token, from RFC 7230.
+fn is_token(token: &str) -> bool {
+    !token.is_empty() && token.bytes().all(|b| match b {
+        b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => true,
+        b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => true,
+        _ => false
+    })
+}
+
+//Like `try!`, but for `Option`.
+macro_rules! try_opt {
+    ($e: expr) => (match $e {
+        Some(value) => value,
+        None => return None
+    })
+}
+
+const DAY_NAMES: [&'static str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const LONG_DAY_NAMES: [&'static str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+const MONTH_NAMES: [&'static str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+//0001-01-01T00:00:00Z and 9999-12-31T23:59:59Z, which are the limits of
+//the four digit years in HTTP dates.
+const MIN_DATE_SECONDS: i64 = -62135596800;
+const MAX_DATE_SECONDS: i64 = 253402300799;
+
+///Format `time` as an HTTP date, in the IMF-fixdate format from RFC 7231,
+///like `Sun, 06 Nov 1994 08:49:37 GMT`.
+///
+///The time is rounded down to whole seconds. Times before the epoch are
+///formatted as they are, while times before year 1 and after year 9999 are
+///clamped to the first and last second of that range, since the year has
+///four digits.
+///
+///```
+///use std::time::{Duration, UNIX_EPOCH};
+///use rustful::utils::format_http_date;
+///
+///let time = UNIX_EPOCH + Duration::from_secs(784111777);
+///assert_eq!(format_http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+///```
+pub fn format_http_date(time: SystemTime) -> String {
+    let seconds = min(max(unix_seconds(time), MIN_DATE_SECONDS), MAX_DATE_SECONDS);
+    let days = floor_div(seconds, 86400);
+    let time_of_day = seconds - days * 86400;
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        DAY_NAMES[(days + 4).rem_euclid(7) as usize],
+        day,
+        MONTH_NAMES[month as usize - 1],
+        year,
+        time_of_day / 3600,
+        time_of_day / 60 % 60,
+        time_of_day % 60
+    )
+}
+
+///Parse an HTTP date in any of the three formats that RFC 7231 requires
+///recipients to accept:
+///
+/// * IMF-fixdate, like `Sun, 06 Nov 1994 08:49:37 GMT`,
+/// * the obsolete RFC 850 format, like `Sunday, 06-Nov-94 08:49:37 GMT`,
+/// * and ANSI C's `asctime()` format, like `Sun Nov  6 08:49:37 1994`.
+///
+///A two digit year is interpreted as the most recent year with the same
+///last two digits that isn't more than 50 years in the future. Names are
+///case insensitive, and the week day isn't checked against the date. `None`
+///is returned if the date is malformed, or if it can't be represented as a
+///`SystemTime` on this platform.
+///
+///```
+///use std::time::{Duration, UNIX_EPOCH};
+///use rustful::utils::parse_http_date;
+///
+///let time = UNIX_EPOCH + Duration::from_secs(784111777);
+///assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(time));
+///assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), Some(time));
+///assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), Some(time));
+///assert_eq!(parse_http_date("yesterday"), None);
+///```
+pub fn parse_http_date(date: &str) -> Option<SystemTime> {
+    parse_date_seconds(date).and_then(|seconds| if seconds >= 0 {
+        UNIX_EPOCH.checked_add(Duration::from_secs(seconds as u64))
+    } else {
+        UNIX_EPOCH.checked_sub(Duration::from_secs(seconds.unsigned_abs()))
+    })
+}
+
+#[doc(hidden)]
+///Internal and may change without warning.
+///
+///Parse a date header, if it appears exactly once.
+pub fn header_date(headers: &Headers, name: &str) -> Option<SystemTime> {
+    match headers.get_raw(name) {
+        Some(values) if values.len() == 1 => ::std::str::from_utf8(&values[0]).ok().and_then(parse_http_date),
+        _ => None
+    }
+}
+
+#[doc(hidden)]
+///Internal and may change without warning.
+///
+///Get the raw values of a header, like `Headers::get_raw`, but with each
+///cookie in a typed `SetCookie` header as a separate value. Hyper writes
+///them as separate lines, but joins them with commas in `get_raw`, which is
+///invalid for `Set-Cookie`.
+pub fn raw_header_values(headers: &Headers, name: &str) -> Option<Vec<Vec<u8>>> {
+    let values = match headers.get_raw(name) {
+        Some(values) => values,
+        None => return None
+    };
+
+    if values.len() == 1 && name.eq_ignore_ascii_case("set-cookie") {
+        if let Some(&SetCookie(ref cookies)) = headers.get::<SetCookie>() {
+            if cookies.len() > 1 {
+                return Some(cookies.iter().map(|cookie| cookie.to_string().into_bytes()).collect());
+            }
+        }
+    }
+
+    Some(values.to_vec())
+}
+
+#[doc(hidden)]
+///Internal and may change without warning.
+///
+///Compare two byte slices in constant time, with respect to their content.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+#[doc(hidden)]
+///Internal and may change without warning.
+///
+///Fill `buffer` with random bytes from the operating system's random number
+///generator, or fail if it can't be accessed.
+pub fn random_bytes(buffer: &mut [u8]) -> io::Result<()> {
+    let mut rng = try!(OsRng::new());
+    rng.fill_bytes(buffer);
+    Ok(())
+}
+
+///The number of whole seconds between the epoch and `time`, rounded down,
+///and saturated at the limits of `i64`. This is the precision of HTTP dates,
+///so it's useful for comparing a modification time with a parsed date.
+///
+///```
+///use std::time::{Duration, UNIX_EPOCH};
+///use rustful::utils::unix_seconds;
+///
+///assert_eq!(unix_seconds(UNIX_EPOCH + Duration::from_millis(1500)), 1);
+///assert_eq!(unix_seconds(UNIX_EPOCH - Duration::from_millis(1500)), -2);
+///```
+pub fn unix_seconds(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => min(duration.as_secs(), ::std::i64::MAX as u64) as i64,
+        Err(e) => {
+            let duration = e.duration();
+            let seconds = duration.as_secs() + if duration.subsec_nanos() > 0 { 1 } else { 0 };
+            -(min(seconds, ::std::i64::MAX as u64) as i64)
+        }
+    }
+}
+
+fn parse_date_seconds(date: &str) -> Option<i64> {
+    let parts: Vec<_> = date.split_whitespace().collect();
+
+    let (year, month, day, time) = match parts.len() {
+        //IMF-fixdate.
+        6 => {
+            if !is_day_name(parts[0], &DAY_NAMES, true) || parts[5] != "GMT" {
+                return None;
+            }
+            (try_opt!(parse_digits(parts[3], 4, 4)), try_opt!(parse_month(parts[2])), try_opt!(parse_digits(parts[1], 2, 2)), parts[4])
+        },
+        //RFC 850.
+        4 => {
+            if !is_day_name(parts[0], &LONG_DAY_NAMES, true) || parts[3] != "GMT" {
+                return None;
+            }
+            let date: Vec<_> = parts[1].split('-').collect();
+            if date.len() != 3 {
+                return None;
+            }
+            let year = full_year(try_opt!(parse_digits(date[2], 2, 2)), current_year());
+            (year, try_opt!(parse_month(date[1])), try_opt!(parse_digits(date[0], 2, 2)), parts[2])
+        },
+        //asctime.
+        5 => {
+            if !is_day_name(parts[0], &DAY_NAMES, false) {
+                return None;
+            }
+            (try_opt!(parse_digits(parts[4], 4, 4)), try_opt!(parse_month(parts[1])), try_opt!(parse_digits(parts[2], 1, 2)), parts[3])
+        },
+        _ => return None
+    };
+
+    let time: Vec<_> = time.split(':').collect();
+    if time.len() != 3 {
+        return None;
+    }
+    let hour = try_opt!(parse_digits(time[0], 2, 2));
+    let minute = try_opt!(parse_digits(time[1], 2, 2));
+    //60 is a leap second.
+    let second = try_opt!(parse_digits(time[2], 2, 2));
+
+    if year < 1 || day < 1 || day > days_in_month(year, month) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    Some(days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+fn is_day_name(name: &str, names: &[&str], comma: bool) -> bool {
+    let name = if comma {
+        if !name.ends_with(',') {
+            return false;
+        }
+        &name[..name.len() - 1]
+    } else {
+        name
+    };
+
+    names.iter().any(|day| day.eq_ignore_ascii_case(name))
+}
+
+fn parse_month(name: &str) -> Option<i64> {
+    MONTH_NAMES.iter().position(|month| month.eq_ignore_ascii_case(name)).map(|index| index as i64 + 1)
+}
+
+fn parse_digits(digits: &str, min_length: usize, max_length: usize) -> Option<i64> {
+    if digits.len() < min_length || digits.len() > max_length || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    digits.parse().ok()
+}
+
+fn current_year() -> i64 {
+    let days = floor_div(unix_seconds(SystemTime::now()), 86400);
+    civil_from_days(days).0
+}
+
+//The most recent year ending with `year`, that isn't more than 50 years
+//after `current_year`.
+fn full_year(year: i64, current_year: i64) -> i64 {
+    let year = current_year - current_year % 100 + year;
+    if year > current_year + 50 {
+        year - 100
+    } else {
+        year
+    }
+}
 
-        match (parts.next(), parts.next()) {
-            (Some(name), Some(value)) => {
-                let name: Vec<_> = percent_decode(name).collect();
-                let value: Vec<_> = percent_decode(value).collect();
-                parameters.insert(name, value);
-            },
-            (Some(name), None) => {
-                let name: Vec<_> = percent_decode(name).collect();
-                parameters.insert(name, String::new());
-            },
-            _ => {}
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31
+    }
+}
+
+fn floor_div(a: i64, b: i64) -> i64 {
+    let quotient = a / b;
+    if a % b < 0 { quotient - 1 } else { quotient }
+}
+
+//The number of days since 1970-01-01 in the proleptic Gregorian calendar,
+//using Howard Hinnant's algorithm.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = floor_div(year, 400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+//The inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = floor_div(days, 146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400;
+
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+///A character encoding for form and query values that are not UTF-8.
+///
+///Any encoding from the [WHATWG Encoding
+///Standard](https://encoding.spec.whatwg.org/) can be used, including
+///multibyte encodings like Shift JIS, EUC-KR and GBK. The labels are the
+///same as in browsers, so `ISO-8859-1` is treated as Windows-1252.
+///
+///```
+///use rustful::utils::Charset;
+///
+///let windows_1252 = Charset::from_label("ISO-8859-1").unwrap();
+///assert_eq!(windows_1252.encoding().name(), "windows-1252");
+///assert_eq!(windows_1252.decode(b"\x80 5"), "€ 5");
+///
+///let shift_jis = Charset::from_label("Shift_JIS").unwrap();
+///assert_eq!(shift_jis.decode(b"\x93\xfa\x96\x7b"), "日本");
+///
+///assert_eq!(Charset::from_label("utf-8"), None);
+///```
+#[cfg(feature = "encoding")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Charset(&'static Encoding);
+
+#[cfg(feature = "encoding")]
+impl Charset {
+    ///Find the encoding for a `charset` label, ignoring case. `None` is
+    ///returned for UTF-8 and for unknown encodings, as well as for the
+    ///labels that the standard maps to the replacement encoding.
+    pub fn from_label(label: &str) -> Option<Charset> {
+        Encoding::for_label(label.as_bytes()).and_then(|encoding| {
+            if encoding == encoding_rs::UTF_8 || encoding == encoding_rs::REPLACEMENT {
+                None
+            } else {
+                Some(Charset(encoding))
+            }
+        })
+    }
+
+    ///Get the underlying encoding.
+    pub fn encoding(&self) -> &'static Encoding {
+        self.0
+    }
+
+    ///Decode bytes from this encoding. Malformed sequences are replaced with
+    ///`U+FFFD REPLACEMENT CHARACTER`.
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        self.0.decode_without_bom_handling(bytes).0.into_owned()
+    }
+
+    ///Decode the keys and values that are not valid UTF-8. The others are
+    ///kept as they are.
+    pub fn decode_parameters(&self, parameters: Parameters) -> Parameters {
+        parameters.into_iter().map(|(key, value)| (self.decode_value(key), self.decode_value(value))).collect()
+    }
+
+    ///Decode the names and values in a `FormData` that are not valid UTF-8.
+    ///The others are kept as they are.
+    pub fn decode_form_data(&self, form: FormData) -> FormData {
+        form.into_iter().map(|(key, value)| (self.decode_value(key), self.decode_value(value))).collect()
+    }
+
+    fn decode_value(&self, value: MaybeUtf8Owned) -> MaybeUtf8Owned {
+        match value {
+            MaybeUtf8::NotUtf8(bytes) => self.decode(&bytes).into(),
+            value => value
         }
     }
+}
 
-    parameters
+#[cfg(feature = "encoding")]
+impl From<&'static Encoding> for Charset {
+    fn from(encoding: &'static Encoding) -> Charset {
+        Charset(encoding)
+    }
 }
 
+#[doc(hidden)]
+#[cfg(feature = "encoding")]
+///Internal and may change without warning.
+pub fn request_charset(headers: &Headers) -> Option<Option<Charset>> {
+    parse_content_type(headers).and_then(|Mime(_, _, params)| {
+        params.into_iter()
+            .find(|&(ref attr, _)| *attr == Attr::Charset)
+            .map(|(_, value)| Charset::from_label(&value.to_string()))
+    })
+}
+
+#[doc(hidden)]
 ///Extension trait for byte vectors.
 pub trait BytesExt {
     ///Copy a number of bytes to the vector.
@@ -43,11 +900,95 @@ impl BytesExt for Vec<u8> {
 #[cfg(test)]
 mod test {
     use std::borrow::ToOwned;
-    use super::parse_parameters;
+    use header::{Headers, Range, ByteRangeSpec};
+    use mime::{Mime, TopLevel, SubLevel, Attr, Value};
+    use std::time::{Duration, UNIX_EPOCH};
+    use super::{parse_parameters, percent_decode, PercentDecodeError, parse_range, ByteRange, parse_media_type, parse_urlencoded, html_escape, html_escape_lossy};
+    use super::{format_http_date, parse_http_date, unix_seconds, full_year};
+
+    fn range(specs: Vec<ByteRangeSpec>) -> Headers {
+        let mut headers = Headers::new();
+        headers.set(Range::Bytes(specs));
+        headers
+    }
+
+    #[test]
+    fn parsing_ranges() {
+        assert_eq!(parse_range(&range(vec![ByteRangeSpec::FromTo(0, 9)]), 100), ByteRange::Partial(0, 9));
+        assert_eq!(parse_range(&range(vec![ByteRangeSpec::AllFrom(90)]), 100), ByteRange::Partial(90, 99));
+        assert_eq!(parse_range(&range(vec![ByteRangeSpec::Last(10)]), 100), ByteRange::Partial(90, 99));
+        assert_eq!(parse_range(&range(vec![ByteRangeSpec::Last(1000)]), 100), ByteRange::Partial(0, 99));
+        assert_eq!(parse_range(&range(vec![ByteRangeSpec::Last(0)]), 100), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(&range(vec![ByteRangeSpec::AllFrom(100)]), 100), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(&range(vec![ByteRangeSpec::FromTo(0, 9)]), 0), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(&range(vec![ByteRangeSpec::FromTo(9, 0)]), 100), ByteRange::Full);
+        assert_eq!(parse_range(&range(vec![ByteRangeSpec::FromTo(0, 9), ByteRangeSpec::Last(10)]), 100), ByteRange::Full);
+    }
+
+    #[test]
+    fn escaping_html() {
+        use std::borrow::Cow;
+        use context::MaybeUtf8Owned;
+
+        assert_eq!(html_escape("&"), "&amp;");
+        assert_eq!(html_escape("<"), "&lt;");
+        assert_eq!(html_escape(">"), "&gt;");
+        assert_eq!(html_escape("\""), "&quot;");
+        assert_eq!(html_escape("'"), "&#39;");
+        assert_eq!(html_escape("a <'b'> & \"c\" ä"), "a &lt;&#39;b&#39;&gt; &amp; &quot;c&quot; ä");
+
+        match html_escape("plain text ä") {
+            Cow::Borrowed(text) => assert_eq!(text, "plain text ä"),
+            Cow::Owned(text) => panic!("unnecessary allocation of {:?}", text)
+        }
+
+        let value: MaybeUtf8Owned = "<a>".into();
+        assert_eq!(html_escape_lossy(&value), "&lt;a&gt;");
+        let value: MaybeUtf8Owned = b"a\xfe&".to_vec().into();
+        assert_eq!(html_escape_lossy(&value), "a\u{FFFD}&amp;");
+    }
+
+    #[test]
+    fn parsing_media_types() {
+        assert_eq!(
+            parse_media_type("Application/JSON; Charset=UTF-8"),
+            Some(Mime(TopLevel::Application, SubLevel::Json, vec![(Attr::Charset, Value::Utf8)]))
+        );
+        assert_eq!(
+            parse_media_type("multipart/form-data ; boundary=\"--a \\\"b\\\" c\" ;x=Y"),
+            Some(Mime(TopLevel::Multipart, SubLevel::FormData, vec![
+                (Attr::Boundary, Value::Ext("--a \"b\" c".into())),
+                (Attr::Ext("x".into()), Value::Ext("Y".into()))
+            ]))
+        );
+        assert_eq!(parse_media_type("text/plain;"), Some(Mime(TopLevel::Text, SubLevel::Plain, vec![])));
+        assert_eq!(parse_media_type("text"), None);
+        assert_eq!(parse_media_type("text/"), None);
+        assert_eq!(parse_media_type("text/plain; charset"), None);
+        assert_eq!(parse_media_type("text/plain; charset=\"utf-8"), None);
+        assert_eq!(parse_media_type("text/plain; a=b c"), None);
+    }
+
+    #[test]
+    fn parsing_urlencoded() {
+        let pairs: Vec<_> = parse_urlencoded(b"a+b=c%2Bd&&e%ff=%fe&=x&y=").collect();
+        assert_eq!(pairs, vec![
+            Ok(("a b".into(), "c+d".into())),
+            Ok((vec![b'e', 0xff].into(), vec![0xfe].into())),
+            Ok(("".into(), "x".into())),
+            Ok(("y".into(), "".into()))
+        ]);
+        assert!(!pairs[1].as_ref().unwrap().0.is_utf8());
+
+        let pairs: Vec<_> = parse_urlencoded(b"a=%A&b=1").collect();
+        assert_eq!(pairs, vec![Err(PercentDecodeError::Truncated(0)), Ok(("b".into(), "1".into()))]);
+
+        assert_eq!(parse_urlencoded(b"&&").count(), 0);
+    }
 
     #[test]
     fn parsing_parameters() {
-        let parameters = parse_parameters(b"a=1&aa=2&ab=202");
+        let parameters = parse_parameters(b"a=1&aa=2&ab=202").unwrap();
         let a = "1".to_owned().into();
         let aa = "2".to_owned().into();
         let ab = "202".to_owned().into();
@@ -58,7 +999,7 @@ mod test {
 
     #[test]
     fn parsing_parameters_with_plus() {
-        let parameters = parse_parameters(b"a=1&aa=2+%2B+extra+meat&ab=202+fifth+avenue");
+        let parameters = parse_parameters(b"a=1&aa=2+%2B+extra+meat&ab=202+fifth+avenue").unwrap();
         let a = "1".to_owned().into();
         let aa = "2 + extra meat".to_owned().into();
         let ab = "202 fifth avenue".to_owned().into();
@@ -69,7 +1010,7 @@ mod test {
 
     #[test]
     fn parsing_strange_parameters() {
-        let parameters = parse_parameters(b"a=1=2&=2&ab=");
+        let parameters = parse_parameters(b"a=1=2&=2&ab=").unwrap();
         let a = "1".to_owned().into();
         let aa = "2".to_owned().into();
         let ab = "".to_owned().into();
@@ -77,4 +1018,142 @@ mod test {
         assert_eq!(parameters.get_raw(""), Some(&aa));
         assert_eq!(parameters.get_raw("ab"), Some(&ab));
     }
+
+    #[test]
+    fn parsing_malformed_parameters() {
+        assert_eq!(parse_parameters(b"a=%G0").err(), Some(PercentDecodeError::InvalidEscape(0)));
+        assert_eq!(parse_parameters(b"a=1&b=%A").err(), Some(PercentDecodeError::Truncated(0)));
+    }
+
+    #[test]
+    fn decoding_one_layer() {
+        let decoded = percent_decode(b"%252e%252e").unwrap();
+        assert_eq!(decoded.as_bytes(), b"%2e%2e");
+        assert!(decoded.still_encoded());
+
+        let decoded = percent_decode(b"%2e%2e%2F").unwrap();
+        assert_eq!(decoded.as_bytes(), b"../");
+        assert!(!decoded.still_encoded());
+    }
+
+    #[test]
+    fn random_input() {
+        use rand::{Rng, SeedableRng, XorShiftRng};
+        use context::Authorization;
+
+        const INTERESTING: &'static [u8] = b"%+=&;,/\\\"* \t-0123456789aAfFgG\x00\x80\xc3\xa9\xff";
+
+        let mut rng = XorShiftRng::from_seed([0x8b2e_51d3, 0x0f6c_3a97, 0x5d41_402a, 0xc4ca_4238]);
+
+        for _ in 0..10000 {
+            let length = rng.gen_range(0, 64);
+            let input: Vec<u8> = (0..length).map(|_| if rng.gen() {
+                INTERESTING[rng.gen_range(0, INTERESTING.len())]
+            } else {
+                rng.gen()
+            }).collect();
+
+            if let Ok(decoded) = percent_decode(&input) {
+                assert!(decoded.as_bytes().len() <= input.len());
+            }
+            if let Ok(parameters) = parse_parameters(&input) {
+                assert!(parameters.len() <= input.len() + 1);
+            }
+            assert!(parse_urlencoded(&input).count() <= input.len() + 1);
+            let _ = Authorization::parse(&input);
+
+            let text = String::from_utf8_lossy(&input);
+            let _ = parse_media_type(&text);
+        }
+    }
+
+    #[bench]
+    #[cfg(feature = "benchmark")]
+    fn parse_query_speed(b: &mut ::test::Bencher) {
+        let query = b"page=2&per_page=50&sort=created_at&order=desc&q=caf%C3%A9+au+lait&tags=a%2Cb%2Cc&empty=&flag";
+
+        b.bytes = query.len() as u64;
+        b.iter(|| parse_parameters(query));
+    }
+
+    #[test]
+    fn decoding_malformed_escapes() {
+        assert_eq!(percent_decode(b"%G0"), Err(PercentDecodeError::InvalidEscape(0)));
+        assert_eq!(percent_decode(b"%0G"), Err(PercentDecodeError::InvalidEscape(0)));
+        assert_eq!(percent_decode(b"ab%"), Err(PercentDecodeError::Truncated(2)));
+        assert_eq!(percent_decode(b"ab%A"), Err(PercentDecodeError::Truncated(2)));
+    }
+
+    #[test]
+    fn http_date_formats() {
+        let time = UNIX_EPOCH + Duration::from_secs(784111777);
+        let formatted = format_http_date(time);
+        assert_eq!(formatted, "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(parse_http_date(&formatted), Some(time));
+
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), Some(time));
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), Some(time));
+        assert_eq!(parse_http_date("Sun Nov 06 08:49:37 1994"), Some(time));
+        assert_eq!(parse_http_date("sun, 06 nov 1994 08:49:37 GMT"), Some(time));
+
+        //Fractions of a second are rounded down.
+        assert_eq!(format_http_date(time + Duration::from_millis(999)), formatted);
+    }
+
+    #[test]
+    fn http_date_round_trip() {
+        for &seconds in &[0, 951782400, 1709164799, 2147483648, 4102444800] {
+            let time = UNIX_EPOCH + Duration::from_secs(seconds);
+            assert_eq!(parse_http_date(&format_http_date(time)), Some(time), "{}", seconds);
+        }
+
+        assert_eq!(format_http_date(UNIX_EPOCH + Duration::from_secs(951782400)), "Tue, 29 Feb 2000 00:00:00 GMT");
+        assert_eq!(format_http_date(UNIX_EPOCH + Duration::from_secs(1709164799)), "Wed, 28 Feb 2024 23:59:59 GMT");
+    }
+
+    #[test]
+    fn http_date_limits() {
+        let before = UNIX_EPOCH - Duration::from_secs(1);
+        assert_eq!(format_http_date(before), "Wed, 31 Dec 1969 23:59:59 GMT");
+        assert_eq!(parse_http_date("Wed, 31 Dec 1969 23:59:59 GMT"), Some(before));
+        assert_eq!(format_http_date(UNIX_EPOCH - Duration::from_millis(1)), "Wed, 31 Dec 1969 23:59:59 GMT");
+        assert_eq!(unix_seconds(UNIX_EPOCH - Duration::from_millis(1)), -1);
+
+        let far_future = UNIX_EPOCH + Duration::from_secs(1 << 40);
+        assert_eq!(format_http_date(far_future), "Fri, 31 Dec 9999 23:59:59 GMT");
+        assert_eq!(parse_http_date("Fri, 31 Dec 9999 23:59:59 GMT"), Some(UNIX_EPOCH + Duration::from_secs(253402300799)));
+
+        //The oldest date is only representable on some platforms.
+        if let Some(oldest) = UNIX_EPOCH.checked_sub(Duration::from_secs(62135596800)) {
+            assert_eq!(format_http_date(oldest), "Mon, 01 Jan 0001 00:00:00 GMT");
+            assert_eq!(parse_http_date("Mon, 01 Jan 0001 00:00:00 GMT"), Some(oldest));
+        }
+    }
+
+    #[test]
+    fn two_digit_years() {
+        assert_eq!(full_year(94, 2026), 1994);
+        assert_eq!(full_year(76, 2026), 2076);
+        assert_eq!(full_year(77, 2026), 1977);
+        assert_eq!(full_year(26, 2026), 2026);
+        assert_eq!(full_year(0, 2026), 2000);
+    }
+
+    #[test]
+    fn malformed_http_dates() {
+        assert_eq!(parse_http_date(""), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 UTC"), None);
+        assert_eq!(parse_http_date("Sun 06 Nov 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 6 Nov 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Foo 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 31 Nov 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Tue, 29 Feb 1900 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 24:00:00 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:60:00 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov +994 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06-Nov-94 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sunday, 06-Nov-1994 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 94"), None);
+    }
 }